@@ -0,0 +1,90 @@
+//! Compares band-limited resampling against naive decimation.
+//! `cargo run --release --example audio_bench`
+
+use std::time::Instant;
+
+use nes::apu::{Apu, BlipBuffer};
+
+const CLOCK_RATE: f64 = 1_789_773.0;
+const SAMPLE_RATE: f64 = 44100.0;
+const SECONDS: u32 = 60;
+
+/// Both pulses playing high notes with the triangle and noise underneath
+fn playing_apu() -> Apu {
+    let mut apu = Apu::new();
+    for (addr, val) in [
+        (0x4015, 0x0f),
+        (0x4000, 0xbf),
+        (0x4002, 0x20),
+        (0x4003, 0x00),
+        (0x4004, 0x7f),
+        (0x4006, 0x35),
+        (0x4007, 0x00),
+        (0x4008, 0xff),
+        (0x400a, 0x80),
+        (0x400b, 0x00),
+        (0x400c, 0x3f),
+        (0x400e, 0x04),
+        (0x400f, 0x00),
+    ] {
+        apu.write_register(addr, val);
+    }
+    apu
+}
+
+/// Step the APU and keep the level at every output sample
+fn naive(cycles: u32) -> Vec<f32> {
+    let mut apu = playing_apu();
+    let step = CLOCK_RATE / SAMPLE_RATE;
+    let mut next = 0.0;
+    let mut out = Vec::new();
+    for cycle in 0..cycles {
+        apu.tick();
+        if cycle as f64 >= next {
+            out.push(apu.output());
+            next += step;
+        }
+    }
+    out
+}
+
+/// Step the APU and feed every level change to the band-limited buffer
+fn band_limited(cycles: u32) -> Vec<f32> {
+    let mut apu = playing_apu();
+    let mut blip = BlipBuffer::new(CLOCK_RATE, SAMPLE_RATE);
+    let mut out = Vec::new();
+    let mut level = 0.0;
+    let mut buf = [0.0; 1024];
+    // Read out once per frame like the frontend does
+    for _ in 0..cycles / 29780 {
+        for clock in 0..29780 {
+            apu.tick();
+            let new = apu.output();
+            if new != level {
+                blip.add_delta(clock, new - level);
+                level = new;
+            }
+        }
+        blip.end_frame(29780);
+        let count = blip.read_samples(&mut buf);
+        out.extend_from_slice(&buf[..count]);
+    }
+    out
+}
+
+fn main() {
+    let cycles = CLOCK_RATE as u32 * SECONDS;
+    for (name, run) in [
+        ("naive decimation", naive as fn(u32) -> Vec<f32>),
+        ("band-limited", band_limited),
+    ] {
+        let start = Instant::now();
+        let samples = run(cycles);
+        let elapsed = start.elapsed();
+        println!(
+            "{name:>16}: {SECONDS}s of audio ({} samples) in {elapsed:.2?}, {:.0}x realtime",
+            samples.len(),
+            SECONDS as f64 / elapsed.as_secs_f64()
+        );
+    }
+}
@@ -0,0 +1,66 @@
+//! Snake on a sprite in a Bevy app, through `NesPlugin`.
+//! `cargo run --example bevy --features bevy`, WASD steers.
+
+use bevy::prelude::*;
+use nes::Cpu;
+use nes::bevy_plugin::{NesConsole, NesPlugin, NesScreen};
+use nes::console::Console;
+use nes::video::Frame;
+
+#[path = "common/snake.rs"]
+mod snake;
+
+fn main() {
+    App::new()
+        .add_plugins(DefaultPlugins.set(ImagePlugin::default_nearest()))
+        .add_plugins(
+            NesPlugin::new(|| snake::new(snake::time_seed()))
+                .with_run(run)
+                .with_render(render),
+        )
+        .add_systems(Startup, setup)
+        .add_systems(Update, steer)
+        .run();
+}
+
+/// Snake doesn't wait for vblank, so it runs at about the pace the main
+/// frontend gives it rather than a frame at a time
+fn run(console: &mut Console) {
+    for _ in 0..250 {
+        if console.cpu.status.contains(nes::Flags::BREAK) {
+            return;
+        }
+        console.step();
+    }
+}
+
+fn render(console: &Console) -> Frame {
+    let mut board = vec![0; 32 * 32];
+    snake::read_screen_state(&console.cpu, &mut board);
+    Frame::from_pixels(board, 32, 32).unwrap()
+}
+
+fn setup(mut commands: Commands, screen: Res<NesScreen>) {
+    commands.spawn(Camera2d);
+    commands.spawn((
+        Sprite {
+            custom_size: Some(Vec2::splat(320.0)),
+            ..Sprite::from_image(screen.image.clone())
+        },
+        Transform::default(),
+    ));
+}
+
+fn steer(keys: Res<ButtonInput<KeyCode>>, mut nes: NonSendMut<NesConsole>) {
+    let cpu: &mut Cpu = &mut nes.console.cpu;
+    for (key, c) in [
+        (KeyCode::KeyW, 'w'),
+        (KeyCode::KeyA, 'a'),
+        (KeyCode::KeyS, 's'),
+        (KeyCode::KeyD, 'd'),
+    ] {
+        if keys.just_pressed(key) {
+            snake::press(cpu, c);
+        }
+    }
+}
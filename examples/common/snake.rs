@@ -0,0 +1,64 @@
+//! The snake game the examples run, shared between them. It draws a 32 by
+//! 32 board from $0200 with one byte per cell, reads a random number from
+//! $FE and the last key pressed from $FF. The numbers come from a seeded
+//! [`RandomDevice`], so the same seed and keys play the same game.
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use nes::Cpu;
+use nes::console::Console;
+use nes::device::RandomDevice;
+use nes::rom::Rom;
+
+static GAME_CODE: &[u8] = include_bytes!("../snake.nes");
+
+/// A new game, the same one for the same seed
+pub fn new(seed: u64) -> Console {
+    let mut console = Console::new(Rom::new(GAME_CODE).unwrap());
+    let device = Box::new(RandomDevice::new(0xfe, seed));
+    console.bus_mut().devices.push(device);
+    console.cpu.reset();
+    console
+}
+
+/// Seeded from the host's clock, a different game each run
+pub fn time_seed() -> u64 {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH);
+    now.map_or(0, |now| now.as_nanos() as u64)
+}
+
+/// Steer with W, A, S or D, other keys are ignored
+pub fn press(cpu: &mut Cpu, key: char) {
+    if matches!(key, 'w' | 'a' | 's' | 'd') {
+        cpu.memory.write(0xff, key as u8);
+    }
+}
+
+fn color(byte: u8) -> u32 {
+    match byte {
+        0 => 0x000000,
+        1 => 0xFFFFFF,
+        2 | 9 => 0xAAAAAA,
+        3 | 10 => 0xFF0000,
+        4 | 11 => 0x00FF00,
+        5 | 12 => 0x0000FF,
+        6 | 13 => 0xFF00FF,
+        7 | 14 => 0xFFCC00,
+        _ => 0x00FFCC,
+    }
+}
+
+/// Copy the board into `frame`, returns whether anything changed
+pub fn read_screen_state(cpu: &Cpu, frame: &mut [u32]) -> bool {
+    let mut update = false;
+    for i in 0x0200..0x600 {
+        let color_idx = cpu.memory.peek(i as u16);
+        let c = color(color_idx);
+        let init = i - 0x200;
+        if frame[init] != c {
+            frame[init] = c;
+            update = true;
+        }
+    }
+    update
+}
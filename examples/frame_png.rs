@@ -0,0 +1,87 @@
+//! Runs snake without a window and writes its board as PNG images, one each
+//! time it changes. The same seed gives the same pictures every run.
+//! `cargo run --example frame_png -- out [FRAMES]`, 10 frames by default.
+
+use std::path::Path;
+
+use nes::Flags;
+use nes::video::Frame;
+
+#[path = "common/snake.rs"]
+#[allow(dead_code)]
+mod snake;
+
+/// PNG of a 0RGB frame, uncompressed so it needs nothing but a CRC
+fn to_png(frame: &Frame) -> Vec<u8> {
+    fn chunk(png: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+        png.extend((data.len() as u32).to_be_bytes());
+        let start = png.len();
+        png.extend(kind);
+        png.extend(data);
+        let crc = crc32fast::hash(&png[start..]);
+        png.extend(crc.to_be_bytes());
+    }
+
+    let mut header = Vec::new();
+    header.extend((frame.width as u32).to_be_bytes());
+    header.extend((frame.height as u32).to_be_bytes());
+    // 8 bits per channel, RGB, no interlacing
+    header.extend([8, 2, 0, 0, 0]);
+
+    // Each row starts with its filter, none
+    let mut raw = Vec::with_capacity((frame.width * 3 + 1) * frame.height);
+    for y in 0..frame.height {
+        raw.push(0);
+        for pixel in frame.row(y) {
+            raw.extend(&pixel.to_be_bytes()[1..]);
+        }
+    }
+    // A zlib stream of stored deflate blocks
+    let mut zlib = vec![0x78, 0x01];
+    let blocks = raw.chunks(0xffff);
+    let count = blocks.len();
+    for (i, block) in blocks.enumerate() {
+        zlib.push((i + 1 == count) as u8);
+        zlib.extend((block.len() as u16).to_le_bytes());
+        zlib.extend((!(block.len() as u16)).to_le_bytes());
+        zlib.extend(block);
+    }
+    let (a, b) = raw.iter().fold((1u32, 0u32), |(a, b), &byte| {
+        let a = (a + byte as u32) % 65521;
+        (a, (b + a) % 65521)
+    });
+    zlib.extend((b << 16 | a).to_be_bytes());
+
+    let mut png = b"\x89PNG\r\n\x1a\n".to_vec();
+    chunk(&mut png, b"IHDR", &header);
+    chunk(&mut png, b"IDAT", &zlib);
+    chunk(&mut png, b"IEND", &[]);
+    png
+}
+
+fn main() -> Result<(), String> {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let out = Path::new(args.first().ok_or("usage: frame_png out [FRAMES]")?);
+    let frames: usize = match args.get(1) {
+        Some(frames) => frames.parse().map_err(|_| "FRAMES is a number")?,
+        None => 10,
+    };
+    std::fs::create_dir_all(out).map_err(|e| format!("{}: {e}", out.display()))?;
+
+    let mut console = snake::new(1);
+    let mut board = [0; 32 * 32];
+    for number in 1..=frames {
+        while !snake::read_screen_state(&console.cpu, &mut board) {
+            if console.cpu.status.contains(Flags::BREAK) {
+                return Ok(());
+            }
+            console.step();
+        }
+        let frame = Frame::from_pixels(board.to_vec(), 32, 32).unwrap();
+        let path = out.join(format!("frame-{number}.png"));
+        std::fs::write(&path, to_png(&frame.scale(8)))
+            .map_err(|e| format!("{}: {e}", path.display()))?;
+        println!("{}", path.display());
+    }
+    Ok(())
+}
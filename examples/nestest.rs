@@ -0,0 +1,49 @@
+//! Runs nestest without a window and checks every instruction against the
+//! reference log, the usual first test of a 6502 core. Stops at the first
+//! line that differs, the log goes on to unofficial opcodes after the
+//! official ones.
+//! `cargo run --example nestest [nestest.nes] [nestest.txt]`, the copies in
+//! the repository by default.
+
+use std::path::Path;
+
+use nes::console::Console;
+use nes::rom::Rom;
+use nes::trace::trace;
+
+fn main() -> Result<(), String> {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let rom_path = args.first().map_or("nestest.nes", String::as_str);
+    let log_path = args.get(1).map_or("nestest.txt", String::as_str);
+    let rom = Rom::load(Path::new(rom_path), false)?;
+    let log = std::fs::read_to_string(log_path).map_err(|e| format!("{log_path}: {e}"))?;
+
+    let mut console = Console::new(rom);
+    console.cpu.reset();
+    // Automation mode, which runs every test without the menu
+    console.cpu.set_pc(0xc000);
+    let mut matched = 0;
+    for expected in log.lines() {
+        let line = trace(&console.cpu);
+        if line != expected {
+            println!("Line {} differs", matched + 1);
+            println!("expected: {expected}");
+            println!("     got: {line}");
+            break;
+        }
+        console.step();
+        matched += 1;
+    }
+    // The test keeps its error codes at $02 and $03, 0 while everything passes
+    let bus = console.bus();
+    println!(
+        "{matched} of {} instructions match, error codes ${:02X} ${:02X}",
+        log.lines().count(),
+        bus.peek(0x02),
+        bus.peek(0x03)
+    );
+    if matched < log.lines().count() {
+        std::process::exit(1);
+    }
+    Ok(())
+}
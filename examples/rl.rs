@@ -0,0 +1,91 @@
+//! The console as a reinforcement learning environment: pick an action, run
+//! the game a step, read the observation and reward out of RAM. A random
+//! policy plays a few episodes of snake here, an agent would go in its place.
+//! `cargo run --release --example rl`
+
+use nes::Flags;
+use nes::console::Console;
+use nes::joypad::Buttons;
+
+#[path = "common/snake.rs"]
+#[allow(dead_code)]
+mod snake;
+
+/// Where snake keeps its length, two bytes a segment
+const LENGTH: u16 = 0x03;
+/// Snake's board, a byte a cell
+const BOARD: std::ops::Range<u16> = 0x0200..0x0600;
+/// Steps before an episode is cut short
+const MAX_STEPS: usize = 1000;
+
+/// The action space, the d-pad. Games read the controller, snake reads the
+/// last key from $FF.
+const ACTIONS: [(Buttons, char); 4] = [
+    (Buttons::UP, 'w'),
+    (Buttons::LEFT, 'a'),
+    (Buttons::DOWN, 's'),
+    (Buttons::RIGHT, 'd'),
+];
+
+struct Env {
+    console: Console,
+    /// The board as last seen, a step runs until it changes
+    board: [u32; 32 * 32],
+}
+
+impl Env {
+    /// A new game, the same one for the same seed
+    fn reset(seed: u64) -> Self {
+        Env {
+            console: snake::new(seed),
+            board: [0; 32 * 32],
+        }
+    }
+
+    /// What the agent sees, the board straight from RAM
+    fn observe(&self) -> Vec<u8> {
+        BOARD.map(|addr| self.console.bus().peek(addr)).collect()
+    }
+
+    /// Take `action` and run until the snake moves. Returns the reward, 1
+    /// for each segment the snake grew, and whether the game is over.
+    fn step(&mut self, action: usize) -> (f32, bool) {
+        let (buttons, key) = ACTIONS[action];
+        self.console.bus_mut().joypads[0].buttons = buttons;
+        snake::press(&mut self.console.cpu, key);
+
+        let length = self.console.bus().peek(LENGTH);
+        while !snake::read_screen_state(&self.console.cpu, &mut self.board) {
+            // Snake runs into BRK when it crashes
+            if self.console.cpu.status.contains(Flags::BREAK) {
+                return (-1.0, true);
+            }
+            self.console.step();
+        }
+        let grown = self.console.bus().peek(LENGTH).wrapping_sub(length) / 2;
+        (grown as f32, false)
+    }
+}
+
+fn main() {
+    // A random policy, from a linear congruential generator
+    let mut policy = 42u64;
+    for episode in 0..5 {
+        let mut env = Env::reset(episode);
+        let (mut total, mut steps) = (0.0, 0);
+        let mut done = false;
+        while !done && steps < MAX_STEPS {
+            let observation = env.observe();
+            debug_assert_eq!(observation.len(), 32 * 32);
+            policy = policy
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            let action = (policy >> 33) as usize % ACTIONS.len();
+            let (reward, over) = env.step(action);
+            total += reward;
+            steps += 1;
+            done = over;
+        }
+        println!("episode {episode}: {steps} steps, return {total}");
+    }
+}
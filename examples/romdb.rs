@@ -0,0 +1,91 @@
+//! Makes `data/romdb.csv` from NesCartDB's XML export, the file its site
+//! offers as `NesCarts (date).xml`.
+//! `cargo run --example romdb -- NesCarts.xml > data/romdb.csv`
+//!
+//! Each cartridge becomes a line keyed by its `crc`, which NesCartDB takes
+//! of PRG ROM followed by CHR ROM like [`nes::romdb::rom_crc`]. The mapper
+//! comes from the board, the mirroring from its solder pads, `-` for boards
+//! without them, and PRG RAM is all its `wram` chips added up. NesCartDB
+//! has no submappers, so those stay `-`. When several releases share a
+//! CRC the first one is kept.
+
+use std::collections::BTreeMap;
+
+fn main() -> Result<(), String> {
+    let path = std::env::args()
+        .nth(1)
+        .ok_or("usage: romdb NesCarts.xml > data/romdb.csv")?;
+    let xml = std::fs::read_to_string(&path).map_err(|e| format!("{path}: {e}"))?;
+    let mut entries = BTreeMap::new();
+    let mut skipped = 0;
+    for game in xml.split("<game ").skip(1) {
+        let name = attr(game, "name").map_or(String::new(), unescape);
+        for cartridge in game.split("<cartridge ").skip(1) {
+            match entry(cartridge, &name) {
+                Some((crc, line)) => {
+                    entries.entry(crc).or_insert(line);
+                }
+                None => skipped += 1,
+            }
+        }
+    }
+    println!("# Header corrections derived from NesCartDB (https://nescartdb.com)");
+    println!("# Keyed by the CRC32 of PRG ROM followed by CHR ROM (the headerless image).");
+    println!("# Made by examples/romdb.rs from {path}, don't edit by hand.");
+    println!("# crc32,mapper,submapper,mirroring (H/V/4/-),prg_ram_kb,name");
+    for (crc, line) in &entries {
+        println!("{crc:08X},{line}");
+    }
+    eprintln!(
+        "{} cartridges, {skipped} skipped for missing a CRC or mapper",
+        entries.len()
+    );
+    Ok(())
+}
+
+/// The CRC and the rest of the line for a `<cartridge>` element, `None` if
+/// it lacks something the line needs
+fn entry(cartridge: &str, name: &str) -> Option<(u32, String)> {
+    let cartridge = &cartridge[..cartridge.find("</cartridge>").unwrap_or(cartridge.len())];
+    let crc = u32::from_str_radix(attr(cartridge, "crc")?, 16).ok()?;
+    let board = tag(cartridge, "board")?;
+    let mapper: u8 = attr(board, "mapper")?.parse().ok()?;
+    let mirroring = match tag(cartridge, "pad") {
+        Some(pad) if attr(pad, "h") == Some("1") => "H",
+        Some(pad) if attr(pad, "v") == Some("1") => "V",
+        _ => "-",
+    };
+    let prg_ram_kb: usize = cartridge
+        .split("<wram ")
+        .skip(1)
+        .filter_map(|wram| attr(wram, "size")?.strip_suffix('k')?.parse::<usize>().ok())
+        .sum();
+    Some((crc, format!("{mapper},-,{mirroring},{prg_ram_kb},{name}")))
+}
+
+/// The attributes of the first `<name ...>` tag
+fn tag<'a>(xml: &'a str, name: &str) -> Option<&'a str> {
+    let start = xml.find(&format!("<{name} "))?;
+    let tag = &xml[start..];
+    Some(&tag[..tag.find('>')?])
+}
+
+/// The value of attribute `name` in the first tag of `xml`
+fn attr<'a>(xml: &'a str, name: &str) -> Option<&'a str> {
+    let tag = &xml[..xml.find('>').unwrap_or(xml.len())];
+    let pattern = format!("{name}=\"");
+    let start = tag.match_indices(&pattern).find_map(|(i, _)| {
+        let after_space = tag[..i].ends_with(char::is_whitespace) || i == 0;
+        after_space.then_some(i + pattern.len())
+    })?;
+    let value = &tag[start..];
+    Some(&value[..value.find('"')?])
+}
+
+fn unescape(text: &str) -> String {
+    text.replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&amp;", "&")
+}
@@ -0,0 +1,86 @@
+//! A 6502 sandbox: a hand assembled program on a bare NROM cartridge, a
+//! homemade device on the expansion port, and a trace of every instruction.
+//! A starting point for trying out code or a device without a whole game.
+//! `cargo run --example sandbox`
+
+use nes::expansion::ExpansionDevice;
+use nes::rom::Rom;
+use nes::savestate::{StateStream, Stateful};
+use nes::trace::trace;
+use nes::{Bus, Cpu};
+
+/// Rolls a die each time $4016 is strobed, read from $4017 bits 1-4
+struct Die {
+    seed: u8,
+    roll: u8,
+}
+
+impl Stateful for Die {
+    fn stream(&mut self, s: &mut StateStream) {
+        self.seed.stream(s);
+        self.roll.stream(s);
+    }
+}
+
+impl ExpansionDevice for Die {
+    fn write(&mut self, val: u8) {
+        if val & 1 != 0 {
+            self.seed = self.seed.wrapping_mul(5).wrapping_add(3);
+            self.roll = self.seed % 6 + 1;
+        }
+    }
+
+    fn peek(&self, port: usize) -> u8 {
+        match port {
+            1 => self.roll << 1,
+            _ => 0,
+        }
+    }
+}
+
+/// An instruction a line
+const PROGRAM: &[&[u8]] = &[
+    // Roll the die into $00
+    &[0xa9, 0x01],       // LDA #$01
+    &[0x8d, 0x16, 0x40], // STA $4016
+    &[0xa9, 0x00],       // LDA #$00
+    &[0x8d, 0x16, 0x40], // STA $4016
+    &[0xad, 0x17, 0x40], // LDA $4017
+    &[0x4a],             // LSR A
+    &[0x29, 0x0f],       // AND #$0F
+    &[0x85, 0x00],       // STA $00
+    // Add up 1 to 10 into $10
+    &[0xa9, 0x00], // LDA #$00
+    &[0xa2, 0x0a], // LDX #$0A
+    &[0x86, 0x11], // loop: STX $11
+    &[0x18],       // CLC
+    &[0x65, 0x11], // ADC $11
+    &[0xca],       // DEX
+    &[0xd0, 0xf8], // BNE loop
+    &[0x85, 0x10], // STA $10
+    &[0x00],       // BRK
+];
+
+/// An iNES image of one 16 KiB PRG bank holding `program` at $8000, which
+/// the reset vector points at
+fn cartridge(program: &[u8]) -> Vec<u8> {
+    let mut ines = vec![0; 16 + 0x4000 + 0x2000];
+    ines[..6].copy_from_slice(b"NES\x1a\x01\x01");
+    ines[16..16 + program.len()].copy_from_slice(program);
+    ines[16 + 0x3ffc..16 + 0x3ffe].copy_from_slice(&[0x00, 0x80]);
+    ines
+}
+
+fn main() -> Result<(), String> {
+    let mut bus = Bus::new(Rom::new(&cartridge(&PROGRAM.concat()))?);
+    bus.expansion = Some(Box::new(Die { seed: 7, roll: 0 }));
+    let mut cpu = Cpu::new(bus);
+    // Runs up to the BRK
+    cpu.run_with_callback(|cpu| println!("{}", trace(cpu)));
+    println!(
+        "rolled {}, 1 + 2 + ... + 10 = {}",
+        cpu.memory.peek(0x00),
+        cpu.memory.peek(0x10)
+    );
+    Ok(())
+}
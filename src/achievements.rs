@@ -0,0 +1,102 @@
+//! Memory exposure for achievement runtimes like rcheevos.
+//!
+//! Achievement definitions address the console through a flat memory map,
+//! for the NES that is the CPU address space laid out in [`MEMORY_REGIONS`].
+//! A client reads it with [`read_memory`] from a frame callback registered
+//! through [`crate::console::Console::on_frame`], and identifies the game
+//! with [`rom_hash`].
+
+use crate::Bus;
+use crate::rom::Rom;
+
+/// What a [`MemoryRegion`] holds, as achievement runtimes classify it
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RegionKind {
+    SystemRam,
+    /// Mirrors of system RAM
+    VirtualRam,
+    HardwareController,
+    /// Battery backed or work RAM on the cartridge
+    SaveRam,
+    ReadOnly,
+}
+
+/// A range of the flat memory map, `start..=end`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MemoryRegion {
+    pub start: u32,
+    pub end: u32,
+    pub kind: RegionKind,
+    pub description: &'static str,
+}
+
+const fn region(start: u32, end: u32, kind: RegionKind, description: &'static str) -> MemoryRegion {
+    MemoryRegion {
+        start,
+        end,
+        kind,
+        description,
+    }
+}
+
+/// The NES memory map as RetroAchievements lays it out
+pub const MEMORY_REGIONS: [MemoryRegion; 8] = [
+    region(0x0000, 0x07FF, RegionKind::SystemRam, "System RAM"),
+    region(0x0800, 0x1FFF, RegionKind::VirtualRam, "Mirror RAM"),
+    region(
+        0x2000,
+        0x2007,
+        RegionKind::HardwareController,
+        "PPU Register",
+    ),
+    region(
+        0x2008,
+        0x3FFF,
+        RegionKind::VirtualRam,
+        "Mirrored PPU Register",
+    ),
+    region(
+        0x4000,
+        0x401F,
+        RegionKind::HardwareController,
+        "APU and I/O register",
+    ),
+    region(0x4020, 0x5FFF, RegionKind::ReadOnly, "Cartridge data"),
+    region(0x6000, 0x7FFF, RegionKind::SaveRam, "Cartridge RAM"),
+    region(0x8000, 0xFFFF, RegionKind::ReadOnly, "Cartridge ROM"),
+];
+
+/// Size of the flat memory map
+pub const MEMORY_SIZE: u32 = 0x10000;
+
+/// Copy memory starting at `address` into `buf` without side effects, the
+/// way a runtime's read callback expects. Returns how many bytes were
+/// read, fewer than asked for past the end of the map.
+pub fn read_memory(bus: &Bus, address: u32, buf: &mut [u8]) -> usize {
+    let available = MEMORY_SIZE.saturating_sub(address) as usize;
+    let len = buf.len().min(available);
+    for (i, byte) in buf[..len].iter_mut().enumerate() {
+        *byte = bus.peek((address as usize + i) as u16);
+    }
+    len
+}
+
+/// The 2KB of system RAM
+pub fn system_ram(bus: &Bus) -> &[u8] {
+    &bus.cpu_ram
+}
+
+/// Cartridge RAM at $6000-$7FFF, read through the mapper
+pub fn prg_ram(bus: &Bus) -> Vec<u8> {
+    (0x6000..=0x7FFF).map(|addr| bus.peek(addr)).collect()
+}
+
+/// RetroAchievements game hash, the MD5 of the file without its 16 byte
+/// iNES header as lowercase hex, or of the sides of a disk image. Patched
+/// games hash as patched.
+pub fn rom_hash(rom: &Rom) -> String {
+    if !rom.disk_sides.is_empty() {
+        return format!("{:x}", md5::compute(rom.disk_sides.concat()));
+    }
+    format!("{:x}", md5::compute(&rom.file()[16..]))
+}
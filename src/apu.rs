@@ -0,0 +1,529 @@
+mod blip;
+mod filter;
+mod mixer;
+mod noise;
+mod output;
+mod pulse;
+mod stats;
+mod triangle;
+mod units;
+
+pub use blip::BlipBuffer;
+pub use filter::{EqBand, FilterChain};
+pub use mixer::{AudioConfig, AudioRouting, ChannelPan, ExpansionChip};
+pub use noise::Noise;
+pub use output::{AudioOutput, STEM_COUNT, StemOutput};
+pub use pulse::Pulse;
+pub use stats::{AudioStats, FILL_HISTORY};
+pub use triangle::Triangle;
+pub use units::{Envelope, LengthCounter};
+
+use crate::rom::Region;
+use crate::savestate::{StateStream, Stateful, stream};
+use crate::scheduler::Scheduler;
+
+/// Output rate used until the frontend picks one
+pub const DEFAULT_SAMPLE_RATE: f64 = 44100.0;
+
+/// CPU cycles at which the frame counter clocks the channels. The 4-step
+/// sequence ends on the last entry, the 5-step one skips it and ends on the
+/// extra step. See https://www.nesdev.org/wiki/APU_Frame_Counter
+const FRAME_STEPS: [u32; 4] = [7457, 14913, 22371, 29829];
+const FIVE_STEP_END: u32 = 37281;
+const PAL_FRAME_STEPS: [u32; 4] = [8313, 16627, 24939, 33253];
+const PAL_FIVE_STEP_END: u32 = 41565;
+/// DMC timer periods in CPU cycles
+const DMC_RATES: [u16; 16] = [
+    428, 380, 340, 320, 286, 254, 226, 214, 190, 160, 142, 128, 106, 84, 72, 54,
+];
+const PAL_DMC_RATES: [u16; 16] = [
+    398, 354, 316, 298, 276, 236, 210, 198, 176, 148, 132, 118, 98, 78, 66, 50,
+];
+
+/// What the frame counter does when a step comes due
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+enum FrameStep {
+    /// A $4017 write taking effect, before anything else due on the same
+    /// cycle since it restarts the sequence
+    Write(u8),
+    #[default]
+    Quarter,
+    /// Quarter and half frame
+    Half,
+    Irq,
+    /// The sequence starts over on the next cycle
+    Restart,
+}
+
+impl Stateful for FrameStep {
+    fn stream(&mut self, s: &mut StateStream) {
+        let (mut tag, mut val) = match *self {
+            FrameStep::Write(val) => (0u8, val),
+            FrameStep::Quarter => (1, 0),
+            FrameStep::Half => (2, 0),
+            FrameStep::Irq => (3, 0),
+            FrameStep::Restart => (4, 0),
+        };
+        stream!(s, tag, val);
+        *self = match tag {
+            0 => FrameStep::Write(val),
+            1 => FrameStep::Quarter,
+            2 => FrameStep::Half,
+            3 => FrameStep::Irq,
+            4 => FrameStep::Restart,
+            _ => {
+                s.fail("invalid frame counter step");
+                FrameStep::Quarter
+            }
+        };
+    }
+}
+
+/// Delta modulation channel, plays 1-bit delta samples fetched from
+/// $C000-$FFFF through DMA.
+/// See https://www.nesdev.org/wiki/APU_DMC
+pub struct Dmc {
+    pub region: Region,
+    pub irq_enabled: bool,
+    pub loop_flag: bool,
+    /// DMC interrupt flag, readable through $4015 bit 7
+    pub irq_flag: bool,
+    /// 7-bit output level
+    pub output: u8,
+    pub bytes_remaining: u16,
+    period: u16,
+    timer: u16,
+    sample_addr: u16,
+    sample_len: u16,
+    current_addr: u16,
+    sample_buffer: Option<u8>,
+    shifter: u8,
+    bits_remaining: u8,
+    silence: bool,
+}
+
+impl Stateful for Dmc {
+    fn stream(&mut self, s: &mut StateStream) {
+        stream!(
+            s,
+            self.irq_enabled,
+            self.loop_flag,
+            self.irq_flag,
+            self.output,
+            self.bytes_remaining,
+            self.period,
+            self.timer,
+            self.sample_addr,
+            self.sample_len,
+            self.current_addr,
+            self.sample_buffer,
+            self.shifter,
+            self.bits_remaining,
+            self.silence,
+        );
+    }
+}
+
+impl Default for Dmc {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Dmc {
+    pub fn new() -> Self {
+        Dmc {
+            region: Region::Ntsc,
+            irq_enabled: false,
+            loop_flag: false,
+            irq_flag: false,
+            output: 0,
+            bytes_remaining: 0,
+            period: DMC_RATES[0],
+            timer: DMC_RATES[0],
+            sample_addr: 0xc000,
+            sample_len: 1,
+            current_addr: 0xc000,
+            sample_buffer: None,
+            shifter: 0,
+            bits_remaining: 8,
+            silence: true,
+        }
+    }
+
+    /// $4010-$4013
+    pub fn write_register(&mut self, addr: u16, val: u8) {
+        match addr & 0x3 {
+            0 => {
+                self.irq_enabled = val & 0x80 != 0;
+                self.loop_flag = val & 0x40 != 0;
+                let rates = match self.region {
+                    Region::Ntsc | Region::Dendy => DMC_RATES,
+                    Region::Pal => PAL_DMC_RATES,
+                };
+                self.period = rates[(val & 0xf) as usize];
+                if !self.irq_enabled {
+                    self.irq_flag = false;
+                }
+            }
+            1 => self.output = val & 0x7f,
+            2 => self.sample_addr = 0xc000 | ((val as u16) << 6),
+            _ => self.sample_len = ((val as u16) << 4) | 1,
+        }
+    }
+
+    /// $4015 bit 4
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.irq_flag = false;
+        if !enabled {
+            self.bytes_remaining = 0;
+        } else if self.bytes_remaining == 0 {
+            self.current_addr = self.sample_addr;
+            self.bytes_remaining = self.sample_len;
+        }
+    }
+
+    /// Address the memory reader wants fetched through DMA, if any
+    pub fn dma_request(&self) -> Option<u16> {
+        if self.sample_buffer.is_none() && self.bytes_remaining > 0 {
+            Some(self.current_addr)
+        } else {
+            None
+        }
+    }
+
+    /// Hand the byte fetched by DMA to the memory reader
+    pub fn dma_fill(&mut self, val: u8) {
+        self.sample_buffer = Some(val);
+        self.current_addr = self.current_addr.checked_add(1).unwrap_or(0x8000);
+        self.bytes_remaining -= 1;
+        if self.bytes_remaining == 0 {
+            if self.loop_flag {
+                self.current_addr = self.sample_addr;
+                self.bytes_remaining = self.sample_len;
+            } else if self.irq_enabled {
+                self.irq_flag = true;
+            }
+        }
+    }
+
+    /// Advance by a single CPU cycle
+    pub fn tick(&mut self) {
+        self.timer -= 1;
+        if self.timer > 0 {
+            return;
+        }
+        self.timer = self.period;
+        if !self.silence {
+            if self.shifter & 1 != 0 {
+                if self.output <= 125 {
+                    self.output += 2;
+                }
+            } else if self.output >= 2 {
+                self.output -= 2;
+            }
+        }
+        self.shifter >>= 1;
+        self.bits_remaining -= 1;
+        if self.bits_remaining == 0 {
+            self.bits_remaining = 8;
+            match self.sample_buffer.take() {
+                Some(sample) => {
+                    self.silence = false;
+                    self.shifter = sample;
+                }
+                None => self.silence = true,
+            }
+        }
+    }
+}
+
+pub struct Apu {
+    pub pulse1: Pulse,
+    pub pulse2: Pulse,
+    pub triangle: Triangle,
+    pub noise: Noise,
+    pub dmc: Dmc,
+    /// $4017 bit 7, 5-step sequence (no frame IRQ)
+    pub five_step: bool,
+    /// $4017 bit 6
+    pub irq_inhibit: bool,
+    /// Frame counter interrupt flag, readable through $4015 bit 6
+    pub frame_irq: bool,
+    region: Region,
+    /// Cycle the current frame counter sequence counts its first cycle on
+    sequence_start: u64,
+    /// The rest of the sequence and any pending $4017 write, by `cycles`
+    frame_steps: Scheduler<FrameStep>,
+    /// CPU cycles since power on, odd cycles are in the middle of an APU cycle
+    cycles: u64,
+}
+
+impl Stateful for Apu {
+    fn stream(&mut self, s: &mut StateStream) {
+        stream!(
+            s,
+            self.pulse1,
+            self.pulse2,
+            self.triangle,
+            self.noise,
+            self.dmc,
+            self.five_step,
+            self.irq_inhibit,
+            self.frame_irq,
+        );
+        if s.version() >= 4 {
+            stream!(s, self.sequence_start, self.frame_steps, self.cycles);
+            return;
+        }
+        // Before version 4 the frame counter counted cycles into its sequence
+        // and down to a pending $4017 write, instead of scheduling steps
+        let mut frame_cycle = 0u32;
+        let mut pending_write: Option<(u8, u8)> = None;
+        stream!(s, frame_cycle, pending_write, self.cycles);
+        if s.is_loading() {
+            self.frame_steps.clear();
+            self.schedule_sequence((self.cycles + 1).saturating_sub(frame_cycle as u64));
+            // Steps the old counter already ran
+            while self.frame_steps.pop_due(self.cycles).is_some() {}
+            if let Some((val, delay)) = pending_write {
+                self.frame_steps
+                    .schedule(self.cycles + delay as u64, FrameStep::Write(val));
+            }
+        }
+    }
+}
+
+impl Default for Apu {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Apu {
+    pub fn new() -> Self {
+        let mut apu = Apu {
+            pulse1: Pulse::new(true),
+            pulse2: Pulse::new(false),
+            triangle: Triangle::default(),
+            noise: Noise::default(),
+            dmc: Dmc::new(),
+            five_step: false,
+            irq_inhibit: false,
+            frame_irq: false,
+            region: Region::Ntsc,
+            sequence_start: 0,
+            frame_steps: Scheduler::new(),
+            cycles: 0,
+        };
+        apu.schedule_sequence(1);
+        apu
+    }
+
+    /// Switch the frame counter, noise and DMC rates to another TV system's timing
+    pub fn set_region(&mut self, region: Region) {
+        self.region = region;
+        self.noise.region = region;
+        self.dmc.region = region;
+        self.frame_steps
+            .cancel(|step| !matches!(step, FrameStep::Write(_)));
+        self.schedule_sequence(self.sequence_start);
+    }
+
+    /// Current output level, 0.0 to 1.0
+    /// See https://www.nesdev.org/wiki/APU_Mixer
+    pub fn output(&self) -> f32 {
+        self.channel_levels().iter().sum()
+    }
+
+    /// How much each of pulse 1, pulse 2, triangle, noise and DMC adds to
+    /// `output`. The mixer is non-linear, so channels sharing a DAC split its
+    /// output by their share of the input.
+    pub fn channel_levels(&self) -> [f32; 5] {
+        let pulse = [self.pulse1.output() as f32, self.pulse2.output() as f32];
+        let pulse_sum = pulse[0] + pulse[1];
+        let pulse_out = if pulse_sum == 0.0 {
+            0.0
+        } else {
+            95.88 / (8128.0 / pulse_sum + 100.0)
+        };
+        let tnd = [
+            self.triangle.output() as f32 / 8227.0,
+            self.noise.output() as f32 / 12241.0,
+            self.dmc.output as f32 / 22638.0,
+        ];
+        let tnd_sum = tnd[0] + tnd[1] + tnd[2];
+        let tnd_out = if tnd_sum == 0.0 {
+            0.0
+        } else {
+            159.79 / (1.0 / tnd_sum + 100.0)
+        };
+        let share = |level: f32, sum: f32, out: f32| {
+            if sum == 0.0 { 0.0 } else { out * level / sum }
+        };
+        [
+            share(pulse[0], pulse_sum, pulse_out),
+            share(pulse[1], pulse_sum, pulse_out),
+            share(tnd[0], tnd_sum, tnd_out),
+            share(tnd[1], tnd_sum, tnd_out),
+            share(tnd[2], tnd_sum, tnd_out),
+        ]
+    }
+
+    fn length_counters(&mut self) -> [&mut LengthCounter; 4] {
+        [
+            &mut self.pulse1.length,
+            &mut self.pulse2.length,
+            &mut self.triangle.length,
+            &mut self.noise.length,
+        ]
+    }
+
+    /// Read $4015 without side effects
+    pub fn peek_status(&self) -> u8 {
+        ((self.dmc.irq_flag as u8) << 7)
+            | ((self.frame_irq as u8) << 6)
+            | (((self.dmc.bytes_remaining > 0) as u8) << 4)
+            | ((self.noise.length.active() as u8) << 3)
+            | ((self.triangle.length.active() as u8) << 2)
+            | ((self.pulse2.length.active() as u8) << 1)
+            | self.pulse1.length.active() as u8
+    }
+
+    /// $4015 channel enables
+    pub fn write_status(&mut self, val: u8) {
+        for (i, length) in self.length_counters().into_iter().enumerate() {
+            length.set_enabled(val & (1 << i) != 0);
+        }
+        self.dmc.set_enabled(val & 0x10 != 0);
+    }
+
+    /// Reading clears the frame IRQ flag. The flag is set on three cycles in a
+    /// row, so a read on either of the first two doesn't keep it cleared.
+    pub fn read_status(&mut self) -> u8 {
+        let status = self.peek_status();
+        self.frame_irq = false;
+        status
+    }
+
+    pub fn write_register(&mut self, addr: u16, val: u8) {
+        match addr {
+            0x4000..=0x4003 => self.pulse1.write_register(addr, val),
+            0x4004..=0x4007 => self.pulse2.write_register(addr, val),
+            0x4008..=0x400b => self.triangle.write_register(addr, val),
+            0x400c..=0x400f => self.noise.write_register(addr, val),
+            0x4010..=0x4013 => self.dmc.write_register(addr, val),
+            0x4015 => self.write_status(val),
+            0x4017 => self.write_frame_counter(val),
+            _ => {}
+        }
+    }
+
+    /// The IRQ inhibit takes effect right away, the new mode and the sequence
+    /// reset happen 3 CPU cycles later if written during an APU cycle, 4 if
+    /// written between them.
+    pub fn write_frame_counter(&mut self, val: u8) {
+        self.irq_inhibit = val & 0x40 != 0;
+        if self.irq_inhibit {
+            self.frame_irq = false;
+        }
+        let delay = if self.cycles.is_multiple_of(2) { 4 } else { 3 };
+        self.frame_steps
+            .cancel(|step| matches!(step, FrameStep::Write(_)));
+        self.frame_steps
+            .schedule(self.cycles + delay, FrameStep::Write(val));
+    }
+
+    fn quarter_frame(&mut self) {
+        self.pulse1.envelope.quarter_frame();
+        self.pulse2.envelope.quarter_frame();
+        self.triangle.quarter_frame();
+        self.noise.envelope.quarter_frame();
+    }
+
+    fn half_frame(&mut self) {
+        self.pulse1.half_frame();
+        self.pulse2.half_frame();
+        self.triangle.length.half_frame();
+        self.noise.length.half_frame();
+    }
+
+    /// Schedule the steps of a sequence whose first cycle is `start`
+    fn schedule_sequence(&mut self, start: u64) {
+        self.sequence_start = start;
+        let (steps, five_step_end) = match self.region {
+            Region::Ntsc | Region::Dendy => (FRAME_STEPS, FIVE_STEP_END),
+            Region::Pal => (PAL_FRAME_STEPS, PAL_FIVE_STEP_END),
+        };
+        let last = steps[3];
+        let end = if self.five_step { five_step_end } else { last };
+        let at = |cycle: u32| start + cycle as u64 - 1;
+        for (cycle, step) in [
+            (steps[0], FrameStep::Quarter),
+            (steps[1], FrameStep::Half),
+            (steps[2], FrameStep::Quarter),
+            (end, FrameStep::Half),
+            (end + 1, FrameStep::Restart),
+        ] {
+            self.frame_steps.schedule(at(cycle), step);
+        }
+        if !self.five_step {
+            for cycle in last - 1..=last + 1 {
+                self.frame_steps.schedule(at(cycle), FrameStep::Irq);
+            }
+        }
+    }
+
+    /// Run the frame counter steps due this cycle, returns whether the length
+    /// counters were clocked
+    fn frame_counter_tick(&mut self) -> bool {
+        let mut clocked = false;
+        while let Some(step) = self.frame_steps.pop_due(self.cycles) {
+            match step {
+                FrameStep::Write(val) => {
+                    self.five_step = val & 0x80 != 0;
+                    self.frame_steps.clear();
+                    if self.five_step {
+                        // Clocks right away, the sequence counts from the next cycle
+                        self.quarter_frame();
+                        self.half_frame();
+                        self.schedule_sequence(self.cycles + 1);
+                        return true;
+                    }
+                    self.schedule_sequence(self.cycles);
+                }
+                FrameStep::Quarter => self.quarter_frame(),
+                FrameStep::Half => {
+                    self.quarter_frame();
+                    self.half_frame();
+                    clocked = true;
+                }
+                FrameStep::Irq => {
+                    if !self.irq_inhibit {
+                        self.frame_irq = true;
+                    }
+                }
+                FrameStep::Restart => self.schedule_sequence(self.cycles + 1),
+            }
+        }
+        clocked
+    }
+
+    /// Advance by a single CPU cycle
+    pub fn tick(&mut self) {
+        self.cycles += 1;
+        let before = self.length_counters().map(|length| length.counter);
+        let clocked = self.frame_counter_tick();
+        for (length, before) in self.length_counters().into_iter().zip(before) {
+            length.apply_writes(clocked, before);
+        }
+
+        self.triangle.tick();
+        self.noise.tick();
+        if self.cycles.is_multiple_of(2) {
+            self.pulse1.tick();
+            self.pulse2.tick();
+        }
+        self.dmc.tick();
+    }
+}
@@ -0,0 +1,141 @@
+/// Sub-sample positions a step can land on
+const PHASES: usize = 64;
+/// Taps on each side of a step
+const HALF_WIDTH: usize = 8;
+const WIDTH: usize = HALF_WIDTH * 2;
+/// Cutoff relative to the output Nyquist frequency, leaving room for the window to roll off
+const CUTOFF: f64 = 0.9;
+
+/// Band-limited resampler in the style of blip_buf. Instead of stepping every
+/// channel at the output rate, changes in the output level are added as deltas
+/// at the CPU cycle they happen on. Each delta is spread over the surrounding
+/// output samples with a windowed sinc, so the steps of the APU's square waves
+/// don't alias back into the audible range.
+/// See http://slack.net/~ant/bl-synth/
+pub struct BlipBuffer {
+    /// Output samples per input clock
+    ratio: f64,
+    /// Position of the frame start in output samples, only the fraction is kept
+    offset: f64,
+    /// Band-limited impulses, integrated when reading to get steps
+    deltas: Vec<f32>,
+    /// Complete samples ready to be read
+    avail: usize,
+    integrator: f32,
+    kernel: Box<[[f32; WIDTH]; PHASES]>,
+}
+
+impl BlipBuffer {
+    pub fn new(clock_rate: f64, sample_rate: f64) -> Self {
+        let mut kernel = Box::new([[0.0; WIDTH]; PHASES]);
+        for (phase, taps) in kernel.iter_mut().enumerate() {
+            let frac = phase as f64 / PHASES as f64;
+            let mut sum = 0.0;
+            let mut raw = [0.0; WIDTH];
+            for (k, tap) in raw.iter_mut().enumerate() {
+                let x = k as f64 - HALF_WIDTH as f64 + 1.0 - frac;
+                *tap = sinc(x * CUTOFF) * blackman(x / HALF_WIDTH as f64);
+                sum += *tap;
+            }
+            // Each impulse adds up to exactly 1 so steps end at the right level
+            for (tap, raw) in taps.iter_mut().zip(raw) {
+                *tap = (raw / sum) as f32;
+            }
+        }
+        BlipBuffer {
+            ratio: sample_rate / clock_rate,
+            offset: 0.0,
+            deltas: vec![0.0; WIDTH],
+            avail: 0,
+            integrator: 0.0,
+            kernel,
+        }
+    }
+
+    /// Change the rates without losing buffered samples
+    pub fn set_rates(&mut self, clock_rate: f64, sample_rate: f64) {
+        self.ratio = sample_rate / clock_rate;
+    }
+
+    /// Add a change in level at `clock` cycles into the current frame
+    pub fn add_delta(&mut self, clock: u32, delta: f32) {
+        let pos = self.offset + clock as f64 * self.ratio;
+        let sample = pos as usize;
+        let phase = ((pos - sample as f64) * PHASES as f64) as usize;
+        let start = self.avail + sample;
+        if self.deltas.len() < start + WIDTH {
+            self.deltas.resize(start + WIDTH, 0.0);
+        }
+        let taps = &self.kernel[phase.min(PHASES - 1)];
+        for (slot, tap) in self.deltas[start..start + WIDTH].iter_mut().zip(taps) {
+            *slot += delta * tap;
+        }
+    }
+
+    /// End the current frame after `clocks` cycles, making its samples readable.
+    /// Deltas for the next frame are timed from this point.
+    pub fn end_frame(&mut self, clocks: u32) {
+        let end = self.offset + clocks as f64 * self.ratio;
+        let samples = end as usize;
+        self.offset = end - samples as f64;
+        self.avail += samples;
+        if self.deltas.len() < self.avail + WIDTH {
+            self.deltas.resize(self.avail + WIDTH, 0.0);
+        }
+    }
+
+    pub fn samples_avail(&self) -> usize {
+        self.avail
+    }
+
+    /// Read up to `out.len()` samples, returns how many were written
+    pub fn read_samples(&mut self, out: &mut [f32]) -> usize {
+        let count = out.len().min(self.avail);
+        for (out, delta) in out.iter_mut().zip(&self.deltas[..count]) {
+            self.integrator += delta;
+            *out = self.integrator;
+        }
+        self.deltas.drain(..count);
+        self.deltas.resize(self.deltas.len().max(WIDTH), 0.0);
+        self.avail -= count;
+        count
+    }
+
+    /// Drop the oldest `count` readable samples, with the level carrying on
+    /// from where they left it
+    pub fn skip_samples(&mut self, count: usize) {
+        let count = count.min(self.avail);
+        self.integrator += self.deltas[..count].iter().sum::<f32>();
+        self.deltas.drain(..count);
+        self.deltas.resize(self.deltas.len().max(WIDTH), 0.0);
+        self.avail -= count;
+    }
+
+    /// Drop every buffered sample and delta
+    pub fn clear(&mut self) {
+        self.deltas.clear();
+        self.deltas.resize(WIDTH, 0.0);
+        self.avail = 0;
+        self.offset = 0.0;
+        self.integrator = 0.0;
+    }
+}
+
+fn sinc(x: f64) -> f64 {
+    if x == 0.0 {
+        1.0
+    } else {
+        let x = x * std::f64::consts::PI;
+        x.sin() / x
+    }
+}
+
+/// Blackman window over -1..1
+fn blackman(x: f64) -> f64 {
+    let x = (x + 1.0) / 2.0;
+    if !(0.0..=1.0).contains(&x) {
+        return 0.0;
+    }
+    let tau = std::f64::consts::TAU;
+    0.42 - 0.5 * (tau * x).cos() + 0.08 * (2.0 * tau * x).cos()
+}
@@ -0,0 +1,119 @@
+use std::f32::consts::TAU;
+
+/// Peaking EQ band
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct EqBand {
+    /// Center frequency in Hz
+    pub freq: f32,
+    /// Boost (or cut, when negative) in dB
+    pub gain_db: f32,
+    /// Bandwidth, higher is narrower
+    pub q: f32,
+}
+
+/// A single filter running at the output sample rate
+#[derive(Clone, Debug)]
+enum Filter {
+    /// First order high-pass, like the coupling capacitors on the APU output
+    HighPass { a: f32, prev_in: f32, prev_out: f32 },
+    /// First order low-pass
+    LowPass { a: f32, prev_out: f32 },
+    /// RBJ cookbook biquad, coefficients normalized by a0.
+    /// See https://www.w3.org/TR/audio-eq-cookbook/
+    Biquad {
+        b: [f32; 3],
+        a: [f32; 2],
+        x: [f32; 2],
+        y: [f32; 2],
+    },
+}
+
+impl Filter {
+    fn high_pass(freq: f32, sample_rate: f32) -> Self {
+        let rc = 1.0 / (TAU * freq);
+        let dt = 1.0 / sample_rate;
+        Filter::HighPass {
+            a: rc / (rc + dt),
+            prev_in: 0.0,
+            prev_out: 0.0,
+        }
+    }
+
+    fn low_pass(freq: f32, sample_rate: f32) -> Self {
+        let rc = 1.0 / (TAU * freq);
+        let dt = 1.0 / sample_rate;
+        Filter::LowPass {
+            a: dt / (rc + dt),
+            prev_out: 0.0,
+        }
+    }
+
+    fn peaking(band: EqBand, sample_rate: f32) -> Self {
+        let amp = 10f32.powf(band.gain_db / 40.0);
+        let w0 = TAU * band.freq / sample_rate;
+        let alpha = w0.sin() / (2.0 * band.q);
+        let a0 = 1.0 + alpha / amp;
+        Filter::Biquad {
+            b: [
+                (1.0 + alpha * amp) / a0,
+                -2.0 * w0.cos() / a0,
+                (1.0 - alpha * amp) / a0,
+            ],
+            a: [-2.0 * w0.cos() / a0, (1.0 - alpha / amp) / a0],
+            x: [0.0; 2],
+            y: [0.0; 2],
+        }
+    }
+
+    fn process(&mut self, input: f32) -> f32 {
+        match self {
+            Filter::HighPass {
+                a,
+                prev_in,
+                prev_out,
+            } => {
+                *prev_out = *a * (*prev_out + input - *prev_in);
+                *prev_in = input;
+                *prev_out
+            }
+            Filter::LowPass { a, prev_out } => {
+                *prev_out += *a * (input - *prev_out);
+                *prev_out
+            }
+            Filter::Biquad { b, a, x, y } => {
+                let out = b[0] * input + b[1] * x[0] + b[2] * x[1] - a[0] * y[0] - a[1] * y[1];
+                *x = [input, x[0]];
+                *y = [out, y[0]];
+                out
+            }
+        }
+    }
+}
+
+/// Filters applied in order to one output channel
+#[derive(Clone, Debug, Default)]
+pub struct FilterChain {
+    filters: Vec<Filter>,
+}
+
+impl FilterChain {
+    /// The NES output stage is two high-passes at 90Hz and 440Hz and a
+    /// low-pass at 14kHz, followed by the user's EQ bands.
+    /// See https://www.nesdev.org/wiki/APU_Mixer
+    pub fn new(hardware: bool, eq: &[EqBand], sample_rate: f32) -> Self {
+        let mut filters = vec![];
+        if hardware {
+            filters.push(Filter::high_pass(90.0, sample_rate));
+            filters.push(Filter::high_pass(440.0, sample_rate));
+            filters.push(Filter::low_pass(14000.0, sample_rate));
+        }
+        filters.extend(eq.iter().map(|&band| Filter::peaking(band, sample_rate)));
+        FilterChain { filters }
+    }
+
+    pub fn process(&mut self, sample: f32) -> f32 {
+        self.filters
+            .iter_mut()
+            .fold(sample, |sample, filter| filter.process(sample))
+    }
+}
@@ -0,0 +1,137 @@
+use super::filter::EqBand;
+
+/// Sound chips found on Famicom cartridges
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ExpansionChip {
+    Vrc6,
+    Vrc7,
+    Fds,
+    Mmc5,
+    N163,
+    S5b,
+}
+
+/// How cartridge audio reaches the output
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum AudioRouting {
+    /// The Famicom mixes cartridge audio with the APU on the cartridge connector
+    #[default]
+    Famicom,
+    /// The NES cartridge slot has no audio pins (they're on the expansion
+    /// port instead), so expansion chips are silent on an unmodified console
+    Nes,
+}
+
+/// Stereo position of each channel, from -1.0 (left) to 1.0 (right)
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct ChannelPan {
+    pub pulse1: f32,
+    pub pulse2: f32,
+    pub triangle: f32,
+    pub noise: f32,
+    pub dmc: f32,
+    pub expansion: f32,
+}
+
+/// Left and right gain for a pan position, the centre keeps full volume on both
+fn pan_gains(pan: f32) -> [f32; 2] {
+    [(1.0 - pan).min(1.0), (1.0 + pan).min(1.0)]
+}
+
+/// User volume controls applied when mixing the APU with cartridge audio.
+/// Volumes are linear, 1.0 keeps the hardware level.
+#[derive(Clone, Debug)]
+pub struct AudioConfig {
+    pub routing: AudioRouting,
+    /// Apply the high-pass and low-pass filters of the console's output stage
+    pub hardware_filters: bool,
+    /// Peaking EQ bands applied after the hardware filters
+    pub eq: Vec<EqBand>,
+    pub pan: ChannelPan,
+    pub master_volume: f32,
+    pub apu_volume: f32,
+    pub vrc6_volume: f32,
+    pub vrc7_volume: f32,
+    pub fds_volume: f32,
+    pub mmc5_volume: f32,
+    pub n163_volume: f32,
+    pub s5b_volume: f32,
+}
+
+impl Default for AudioConfig {
+    fn default() -> Self {
+        AudioConfig {
+            routing: AudioRouting::default(),
+            hardware_filters: true,
+            eq: Vec::new(),
+            pan: ChannelPan::default(),
+            master_volume: 1.0,
+            apu_volume: 1.0,
+            vrc6_volume: 1.0,
+            vrc7_volume: 1.0,
+            fds_volume: 1.0,
+            mmc5_volume: 1.0,
+            n163_volume: 1.0,
+            s5b_volume: 1.0,
+        }
+    }
+}
+
+impl AudioConfig {
+    pub fn expansion_volume(&self, chip: ExpansionChip) -> f32 {
+        match chip {
+            ExpansionChip::Vrc6 => self.vrc6_volume,
+            ExpansionChip::Vrc7 => self.vrc7_volume,
+            ExpansionChip::Fds => self.fds_volume,
+            ExpansionChip::Mmc5 => self.mmc5_volume,
+            ExpansionChip::N163 => self.n163_volume,
+            ExpansionChip::S5b => self.s5b_volume,
+        }
+    }
+
+    pub fn set_expansion_volume(&mut self, chip: ExpansionChip, volume: f32) {
+        let slot = match chip {
+            ExpansionChip::Vrc6 => &mut self.vrc6_volume,
+            ExpansionChip::Vrc7 => &mut self.vrc7_volume,
+            ExpansionChip::Fds => &mut self.fds_volume,
+            ExpansionChip::Mmc5 => &mut self.mmc5_volume,
+            ExpansionChip::N163 => &mut self.n163_volume,
+            ExpansionChip::S5b => &mut self.s5b_volume,
+        };
+        *slot = volume;
+    }
+
+    fn expansion_level(&self, expansion: Option<(ExpansionChip, f32)>) -> f32 {
+        match (self.routing, expansion) {
+            (AudioRouting::Famicom, Some((chip, level))) => level * self.expansion_volume(chip),
+            _ => 0.0,
+        }
+    }
+
+    /// Mix the APU output with the cartridge's sound chip output, both in
+    /// units where 1.0 is the loudest the APU can get
+    pub fn mix(&self, apu: f32, expansion: Option<(ExpansionChip, f32)>) -> f32 {
+        (apu * self.apu_volume + self.expansion_level(expansion)) * self.master_volume
+    }
+
+    /// Like `mix`, but with every channel panned to its place in the stereo field.
+    /// `channels` are the APU channel levels from `Apu::channel_levels`.
+    pub fn mix_stereo(
+        &self,
+        channels: [f32; 5],
+        expansion: Option<(ExpansionChip, f32)>,
+    ) -> [f32; 2] {
+        let pan = &self.pan;
+        let apu = [pan.pulse1, pan.pulse2, pan.triangle, pan.noise, pan.dmc]
+            .into_iter()
+            .zip(channels)
+            .map(|(pan, level)| (pan_gains(pan), level * self.apu_volume));
+        let expansion = (pan_gains(pan.expansion), self.expansion_level(expansion));
+        let mut out = [0.0; 2];
+        for ([left, right], level) in apu.chain([expansion]) {
+            out[0] += left * level;
+            out[1] += right * level;
+        }
+        out.map(|side| side * self.master_volume)
+    }
+}
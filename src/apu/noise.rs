@@ -0,0 +1,96 @@
+use super::units::{Envelope, LengthCounter};
+use crate::rom::Region;
+use crate::savestate::{StateStream, Stateful, stream};
+
+/// Timer periods in CPU cycles
+const NOISE_PERIODS: [u16; 16] = [
+    4, 8, 16, 32, 64, 96, 128, 160, 202, 254, 380, 508, 762, 1016, 2034, 4068,
+];
+const PAL_NOISE_PERIODS: [u16; 16] = [
+    4, 8, 14, 30, 60, 88, 118, 148, 188, 236, 354, 472, 708, 944, 1890, 3778,
+];
+
+/// Pseudo-random noise channel, $400C-$400F.
+/// See https://www.nesdev.org/wiki/APU_Noise
+pub struct Noise {
+    pub region: Region,
+    pub length: LengthCounter,
+    pub envelope: Envelope,
+    /// Short mode taps bit 6 instead of bit 1, giving a 93 step metallic loop
+    short_mode: bool,
+    shift: u16,
+    period: u16,
+    timer: u16,
+}
+
+impl Stateful for Noise {
+    fn stream(&mut self, s: &mut StateStream) {
+        stream!(
+            s,
+            self.length,
+            self.envelope,
+            self.short_mode,
+            self.shift,
+            self.period,
+            self.timer,
+        );
+    }
+}
+
+impl Default for Noise {
+    fn default() -> Self {
+        Noise {
+            region: Region::Ntsc,
+            length: LengthCounter::default(),
+            envelope: Envelope::default(),
+            short_mode: false,
+            shift: 1,
+            period: NOISE_PERIODS[0],
+            timer: 0,
+        }
+    }
+}
+
+impl Noise {
+    pub fn write_register(&mut self, addr: u16, val: u8) {
+        match addr & 0x3 {
+            0 => {
+                self.length.write_halt(val & 0x20 != 0);
+                self.envelope.write(val);
+            }
+            1 => {}
+            2 => {
+                let periods = match self.region {
+                    Region::Ntsc | Region::Dendy => NOISE_PERIODS,
+                    Region::Pal => PAL_NOISE_PERIODS,
+                };
+                self.short_mode = val & 0x80 != 0;
+                self.period = periods[(val & 0xf) as usize];
+            }
+            _ => {
+                self.length.write_load(val);
+                self.envelope.start = true;
+            }
+        }
+    }
+
+    /// Advance by a single CPU cycle
+    pub fn tick(&mut self) {
+        if self.timer > 0 {
+            self.timer -= 1;
+            return;
+        }
+        self.timer = self.period - 1;
+        let tap = if self.short_mode { 6 } else { 1 };
+        let feedback = (self.shift ^ (self.shift >> tap)) & 1;
+        self.shift = (self.shift >> 1) | (feedback << 14);
+    }
+
+    pub fn output(&self) -> u8 {
+        if self.shift & 1 != 0 || !self.length.active() {
+            0
+        } else {
+            self.envelope.output()
+        }
+    }
+}
@@ -0,0 +1,185 @@
+use super::blip::BlipBuffer;
+use super::filter::{EqBand, FilterChain};
+use super::mixer::AudioConfig;
+
+/// Cycles after which the samples made so far become readable even if
+/// nobody reads them, so the cycle count stays small
+const FRAME_CLOCKS: u32 = 1 << 16;
+/// Audio kept for frontends that don't read it, the oldest is dropped first
+const MAX_BUFFERED_SECONDS: f64 = 1.0;
+
+/// End the frame of every buffer after `clock` cycles and drop whatever is
+/// past [`MAX_BUFFERED_SECONDS`]
+fn end_frames(buffers: &mut [BlipBuffer], clock: u32, sample_rate: f64) {
+    let max = (sample_rate * MAX_BUFFERED_SECONDS) as usize;
+    for buffer in buffers {
+        buffer.end_frame(clock);
+        buffer.skip_samples(buffer.samples_avail().saturating_sub(max));
+    }
+}
+
+/// Turns the mixed level on every APU cycle into filtered stereo samples
+pub struct AudioOutput {
+    buffers: [BlipBuffer; 2],
+    /// APU cycles since the samples were last made readable
+    clock: u32,
+    level: [f32; 2],
+    sample_rate: f64,
+    filters: [FilterChain; 2],
+    /// Settings the filters were built from, rebuilt when the config changes
+    filter_settings: Option<(bool, Vec<EqBand>)>,
+}
+
+impl AudioOutput {
+    pub fn new(clock_rate: f64, sample_rate: f64) -> Self {
+        AudioOutput {
+            buffers: [
+                BlipBuffer::new(clock_rate, sample_rate),
+                BlipBuffer::new(clock_rate, sample_rate),
+            ],
+            clock: 0,
+            level: [0.0; 2],
+            sample_rate,
+            filters: Default::default(),
+            filter_settings: None,
+        }
+    }
+
+    /// Advance by a single APU cycle with the output at `level`
+    pub fn tick(&mut self, level: [f32; 2]) {
+        self.clock += 1;
+        for (side, buffer) in self.buffers.iter_mut().enumerate() {
+            let delta = level[side] - self.level[side];
+            if delta != 0.0 {
+                buffer.add_delta(self.clock, delta);
+            }
+        }
+        self.level = level;
+        if self.clock == FRAME_CLOCKS {
+            self.end_frame();
+        }
+    }
+
+    fn end_frame(&mut self) {
+        let clock = std::mem::take(&mut self.clock);
+        end_frames(&mut self.buffers, clock, self.sample_rate);
+    }
+
+    pub fn sample_rate(&self) -> f64 {
+        self.sample_rate
+    }
+
+    pub fn set_rates(&mut self, clock_rate: f64, sample_rate: f64) {
+        // Deltas already added this frame were timed with the old rate
+        self.end_frame();
+        for buffer in &mut self.buffers {
+            buffer.set_rates(clock_rate, sample_rate);
+        }
+        self.sample_rate = sample_rate;
+        self.filter_settings = None;
+    }
+
+    /// Read filtered stereo samples, returns how many were written
+    pub fn read(&mut self, out: &mut [[f32; 2]], config: &AudioConfig) -> usize {
+        let settings = (config.hardware_filters, config.eq.clone());
+        if self.filter_settings.as_ref() != Some(&settings) {
+            let chain = FilterChain::new(settings.0, &settings.1, self.sample_rate as f32);
+            self.filters = [chain.clone(), chain];
+            self.filter_settings = Some(settings);
+        }
+
+        self.end_frame();
+        let mut side = [0.0; 1024];
+        let mut written = 0;
+        while written < out.len() && self.buffers[0].samples_avail() > 0 {
+            let chunk = (out.len() - written).min(side.len());
+            let mut count = 0;
+            for (i, buffer) in self.buffers.iter_mut().enumerate() {
+                count = buffer.read_samples(&mut side[..chunk]);
+                for (out, &sample) in out[written..written + count].iter_mut().zip(&side) {
+                    out[i] = self.filters[i].process(sample);
+                }
+            }
+            written += count;
+        }
+        written
+    }
+}
+
+/// Stems are pulse 1, pulse 2, triangle, noise, DMC and the cartridge's sound chip
+pub const STEM_COUNT: usize = 6;
+
+/// Like [`AudioOutput`], but with every channel kept apart as mono samples,
+/// for exporting stems. Only the hardware filters apply, volumes, panning
+/// and EQ are left to whoever mixes them.
+pub struct StemOutput {
+    buffers: [BlipBuffer; STEM_COUNT],
+    clock: u32,
+    level: [f32; STEM_COUNT],
+    sample_rate: f64,
+    filters: [FilterChain; STEM_COUNT],
+}
+
+impl StemOutput {
+    pub fn new(clock_rate: f64, sample_rate: f64, hardware_filters: bool) -> Self {
+        let chain = FilterChain::new(hardware_filters, &[], sample_rate as f32);
+        StemOutput {
+            buffers: std::array::from_fn(|_| BlipBuffer::new(clock_rate, sample_rate)),
+            clock: 0,
+            level: [0.0; STEM_COUNT],
+            sample_rate,
+            filters: std::array::from_fn(|_| chain.clone()),
+        }
+    }
+
+    /// Advance by a single APU cycle with each channel at `level`
+    pub fn tick(&mut self, level: [f32; STEM_COUNT]) {
+        self.clock += 1;
+        for (stem, buffer) in self.buffers.iter_mut().enumerate() {
+            let delta = level[stem] - self.level[stem];
+            if delta != 0.0 {
+                buffer.add_delta(self.clock, delta);
+            }
+        }
+        self.level = level;
+        if self.clock == FRAME_CLOCKS {
+            self.end_frame();
+        }
+    }
+
+    fn end_frame(&mut self) {
+        let clock = std::mem::take(&mut self.clock);
+        end_frames(&mut self.buffers, clock, self.sample_rate);
+    }
+
+    pub fn sample_rate(&self) -> f64 {
+        self.sample_rate
+    }
+
+    pub fn set_rates(&mut self, clock_rate: f64, sample_rate: f64) {
+        self.end_frame();
+        for buffer in &mut self.buffers {
+            buffer.set_rates(clock_rate, sample_rate);
+        }
+        self.sample_rate = sample_rate;
+    }
+
+    /// Read filtered samples of every stem, returns how many were written
+    pub fn read(&mut self, out: &mut [[f32; STEM_COUNT]]) -> usize {
+        self.end_frame();
+        let mut stem = [0.0; 1024];
+        let mut written = 0;
+        while written < out.len() && self.buffers[0].samples_avail() > 0 {
+            let chunk = (out.len() - written).min(stem.len());
+            let mut count = 0;
+            for (i, buffer) in self.buffers.iter_mut().enumerate() {
+                count = buffer.read_samples(&mut stem[..chunk]);
+                for (out, &sample) in out[written..written + count].iter_mut().zip(&stem) {
+                    out[i] = self.filters[i].process(sample);
+                }
+            }
+            written += count;
+        }
+        written
+    }
+}
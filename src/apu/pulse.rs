@@ -0,0 +1,125 @@
+use super::units::{Envelope, LengthCounter};
+use crate::savestate::{StateStream, Stateful, stream};
+
+const DUTY_TABLE: [u8; 4] = [0b0000_0001, 0b0000_0011, 0b0000_1111, 0b1111_1100];
+
+/// Square wave channel, $4000-$4003 and $4004-$4007.
+/// See https://www.nesdev.org/wiki/APU_Pulse
+#[derive(Default)]
+pub struct Pulse {
+    pub length: LengthCounter,
+    pub envelope: Envelope,
+    /// Pulse 1 negates with ones' complement, so sweeping down is one step lower
+    ones_complement: bool,
+    duty: u8,
+    step: u8,
+    period: u16,
+    timer: u16,
+    sweep_enabled: bool,
+    sweep_period: u8,
+    sweep_negate: bool,
+    sweep_shift: u8,
+    sweep_reload: bool,
+    sweep_divider: u8,
+}
+
+/// Which pulse it is never changes, so `ones_complement` isn't saved
+impl Stateful for Pulse {
+    fn stream(&mut self, s: &mut StateStream) {
+        stream!(
+            s,
+            self.length,
+            self.envelope,
+            self.duty,
+            self.step,
+            self.period,
+            self.timer,
+            self.sweep_enabled,
+            self.sweep_period,
+            self.sweep_negate,
+            self.sweep_shift,
+            self.sweep_reload,
+            self.sweep_divider,
+        );
+    }
+}
+
+impl Pulse {
+    pub fn new(ones_complement: bool) -> Self {
+        Pulse {
+            ones_complement,
+            ..Default::default()
+        }
+    }
+
+    pub fn write_register(&mut self, addr: u16, val: u8) {
+        match addr & 0x3 {
+            0 => {
+                self.duty = val >> 6;
+                self.length.write_halt(val & 0x20 != 0);
+                self.envelope.write(val);
+            }
+            1 => {
+                self.sweep_enabled = val & 0x80 != 0;
+                self.sweep_period = (val >> 4) & 0x7;
+                self.sweep_negate = val & 0x08 != 0;
+                self.sweep_shift = val & 0x7;
+                self.sweep_reload = true;
+            }
+            2 => self.period = (self.period & 0x700) | val as u16,
+            _ => {
+                self.period = (self.period & 0xff) | ((val as u16 & 0x7) << 8);
+                self.length.write_load(val);
+                self.step = 0;
+                self.envelope.start = true;
+            }
+        }
+    }
+
+    /// Clocked every other CPU cycle
+    pub fn tick(&mut self) {
+        if self.timer == 0 {
+            self.timer = self.period;
+            self.step = (self.step + 1) % 8;
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    fn sweep_target(&self) -> u16 {
+        let change = self.period >> self.sweep_shift;
+        if !self.sweep_negate {
+            self.period + change
+        } else if self.ones_complement {
+            self.period.saturating_sub(change + 1)
+        } else {
+            self.period.saturating_sub(change)
+        }
+    }
+
+    fn muted(&self) -> bool {
+        self.period < 8 || self.sweep_target() > 0x7ff
+    }
+
+    pub fn half_frame(&mut self) {
+        self.length.half_frame();
+        if self.sweep_divider == 0 && self.sweep_enabled && self.sweep_shift > 0 && !self.muted() {
+            self.period = self.sweep_target();
+        }
+        if self.sweep_divider == 0 || self.sweep_reload {
+            self.sweep_divider = self.sweep_period;
+            self.sweep_reload = false;
+        } else {
+            self.sweep_divider -= 1;
+        }
+    }
+
+    pub fn output(&self) -> u8 {
+        let high = DUTY_TABLE[self.duty as usize] & (0x80 >> self.step) != 0;
+        if !high || !self.length.active() || self.muted() {
+            0
+        } else {
+            self.envelope.output()
+        }
+    }
+}
@@ -0,0 +1,104 @@
+use std::collections::VecDeque;
+use std::time::Duration;
+
+/// Reports kept by [`AudioStats`], 10 seconds at one a frame
+pub const FILL_HISTORY: usize = 600;
+
+/// A hand-off of samples to the audio device, see [`AudioStats::record`]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct Report {
+    /// Samples waiting on the device afterwards
+    fill: usize,
+    /// Samples added
+    added: usize,
+    /// Samples the device played since the report before
+    played: usize,
+}
+
+/// How the frontend's audio buffer holds up, for tuning its latency. The
+/// console doesn't see the device, so frontends report every time they hand
+/// it samples. Samples are counted a frame at a time, a stereo pair is one.
+#[derive(Clone, Debug)]
+pub struct AudioStats {
+    sample_rate: f64,
+    /// Oldest first, at most `FILL_HISTORY`
+    reports: VecDeque<Report>,
+    underruns: u64,
+    overruns: u64,
+}
+
+impl AudioStats {
+    /// For a device playing `sample_rate` samples a second
+    pub fn new(sample_rate: f64) -> Self {
+        AudioStats {
+            sample_rate,
+            reports: VecDeque::new(),
+            underruns: 0,
+            overruns: 0,
+        }
+    }
+
+    /// `added` samples were handed to the device while it still had `queued`
+    /// waiting to be played, after throwing `dropped` of those away to catch
+    /// up. Nothing waiting means the device ran dry, an underrun, and
+    /// dropping anything is an overrun.
+    pub fn record(&mut self, queued: usize, dropped: usize, added: usize) {
+        let played = match self.reports.back() {
+            Some(last) => {
+                if queued == 0 {
+                    self.underruns += 1;
+                }
+                last.fill.saturating_sub(queued)
+            }
+            None => 0,
+        };
+        if dropped > 0 {
+            self.overruns += 1;
+        }
+        if self.reports.len() == FILL_HISTORY {
+            self.reports.pop_front();
+        }
+        self.reports.push_back(Report {
+            fill: queued.saturating_sub(dropped) + added,
+            added,
+            played,
+        });
+    }
+
+    /// Times the device ran out of samples, and went quiet
+    pub fn underruns(&self) -> u64 {
+        self.underruns
+    }
+
+    /// Times samples were dropped for being too far ahead
+    pub fn overruns(&self) -> u64 {
+        self.overruns
+    }
+
+    /// How long the last samples handed over wait before they're heard
+    pub fn latency(&self) -> Duration {
+        let fill = self.reports.back().map_or(0, |report| report.fill);
+        Duration::from_secs_f64(fill as f64 / self.sample_rate)
+    }
+
+    /// Samples waiting on the device after each report, oldest first
+    pub fn fill_history(&self) -> impl ExactSizeIterator<Item = usize> + '_ {
+        self.reports.iter().map(|report| report.fill)
+    }
+
+    /// How much faster the emulator makes samples than the device plays them
+    /// over the reports kept, in parts per million. Positive fills the buffer
+    /// up until samples are dropped, negative drains it until it underruns.
+    /// `None` until the device has played anything.
+    pub fn drift_ppm(&self) -> Option<f64> {
+        // Samples added in a report are played by the next ones
+        let added: usize = self.reports.iter().rev().skip(1).map(|r| r.added).sum();
+        let played: usize = self.reports.iter().skip(1).map(|r| r.played).sum();
+        (played > 0).then(|| (added as f64 / played as f64 - 1.0) * 1e6)
+    }
+
+    /// Forget everything, like after the device was reopened
+    pub fn reset(&mut self) {
+        *self = AudioStats::new(self.sample_rate);
+    }
+}
@@ -0,0 +1,100 @@
+use super::units::LengthCounter;
+use crate::savestate::{StateStream, Stateful, stream};
+
+/// Triangle channel, $4008-$400B.
+/// See https://www.nesdev.org/wiki/APU_Triangle
+pub struct Triangle {
+    pub length: LengthCounter,
+    /// Length counter halt and linear counter control share bit 7 of $4008
+    control: bool,
+    linear_load: u8,
+    linear_counter: u8,
+    linear_reload: bool,
+    step: u8,
+    period: u16,
+    timer: u16,
+}
+
+impl Stateful for Triangle {
+    fn stream(&mut self, s: &mut StateStream) {
+        stream!(
+            s,
+            self.length,
+            self.control,
+            self.linear_load,
+            self.linear_counter,
+            self.linear_reload,
+            self.step,
+            self.period,
+            self.timer,
+        );
+    }
+}
+
+impl Default for Triangle {
+    fn default() -> Self {
+        Triangle {
+            length: LengthCounter::default(),
+            control: false,
+            linear_load: 0,
+            linear_counter: 0,
+            linear_reload: false,
+            // Start at the bottom of the ramp so nothing is output before the first note
+            step: 16,
+            period: 0,
+            timer: 0,
+        }
+    }
+}
+
+impl Triangle {
+    pub fn write_register(&mut self, addr: u16, val: u8) {
+        match addr & 0x3 {
+            0 => {
+                self.control = val & 0x80 != 0;
+                self.length.write_halt(self.control);
+                self.linear_load = val & 0x7f;
+            }
+            1 => {}
+            2 => self.period = (self.period & 0x700) | val as u16,
+            _ => {
+                self.period = (self.period & 0xff) | ((val as u16 & 0x7) << 8);
+                self.length.write_load(val);
+                self.linear_reload = true;
+            }
+        }
+    }
+
+    /// Clocked every CPU cycle, the sequencer only moves while both counters are non-zero
+    pub fn tick(&mut self) {
+        if self.timer == 0 {
+            self.timer = self.period;
+            if self.linear_counter > 0 && self.length.active() {
+                self.step = (self.step + 1) % 32;
+            }
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    pub fn quarter_frame(&mut self) {
+        if self.linear_reload {
+            self.linear_counter = self.linear_load;
+        } else if self.linear_counter > 0 {
+            self.linear_counter -= 1;
+        }
+        if !self.control {
+            self.linear_reload = false;
+        }
+    }
+
+    /// The sequencer keeps its position when halted, so this holds the last level.
+    /// Step 0 is the top of the ramp down.
+    pub fn output(&self) -> u8 {
+        if self.step < 16 {
+            15 - self.step
+        } else {
+            self.step - 16
+        }
+    }
+}
@@ -0,0 +1,139 @@
+use crate::savestate::{StateStream, Stateful, stream};
+
+/// Length counter load values, indexed by bits 3-7 of the 4th channel register
+const LENGTH_TABLE: [u8; 32] = [
+    10, 254, 20, 2, 40, 4, 80, 6, 160, 8, 60, 10, 14, 12, 26, 14, 12, 16, 24, 18, 48, 20, 96, 22,
+    192, 24, 72, 26, 16, 28, 32, 30,
+];
+
+/// Silences a channel after a number of half frames.
+/// Writes to the halt flag and reloads land after the frame counter has had
+/// its chance to clock the counter on the same cycle, which is what the
+/// apu_test length timing tests check.
+/// See https://www.nesdev.org/wiki/APU_Length_Counter
+#[derive(Default)]
+pub struct LengthCounter {
+    pub counter: u8,
+    pub halt: bool,
+    enabled: bool,
+    pending_halt: Option<bool>,
+    pending_reload: Option<u8>,
+}
+
+impl Stateful for LengthCounter {
+    fn stream(&mut self, s: &mut StateStream) {
+        stream!(
+            s,
+            self.counter,
+            self.halt,
+            self.enabled,
+            self.pending_halt,
+            self.pending_reload,
+        );
+    }
+}
+
+impl LengthCounter {
+    pub fn active(&self) -> bool {
+        self.counter > 0
+    }
+
+    /// $4015 enable bit, disabling clears the counter right away
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+        if !enabled {
+            self.counter = 0;
+        }
+    }
+
+    pub fn write_halt(&mut self, halt: bool) {
+        self.pending_halt = Some(halt);
+    }
+
+    /// Load from bits 3-7 of `val`, ignored while the channel is disabled
+    pub fn write_load(&mut self, val: u8) {
+        if self.enabled {
+            self.pending_reload = Some(LENGTH_TABLE[(val >> 3) as usize]);
+        }
+    }
+
+    pub fn half_frame(&mut self) {
+        if self.counter > 0 && !self.halt {
+            self.counter -= 1;
+        }
+    }
+
+    /// Apply register writes from this cycle. A reload at the same time the
+    /// counter was clocked only happens if the counter was already 0.
+    pub fn apply_writes(&mut self, clocked: bool, before_clock: u8) {
+        if let Some(halt) = self.pending_halt.take() {
+            self.halt = halt;
+        }
+        if let Some(load) = self.pending_reload.take()
+            && !(clocked && before_clock > 0)
+        {
+            self.counter = load;
+        }
+    }
+}
+
+/// Volume envelope shared by the pulse and noise channels.
+/// See https://www.nesdev.org/wiki/APU_Envelope
+#[derive(Default)]
+pub struct Envelope {
+    pub start: bool,
+    pub looping: bool,
+    pub constant: bool,
+    /// Constant volume, or the envelope's divider period
+    pub volume: u8,
+    divider: u8,
+    decay: u8,
+}
+
+impl Stateful for Envelope {
+    fn stream(&mut self, s: &mut StateStream) {
+        stream!(
+            s,
+            self.start,
+            self.looping,
+            self.constant,
+            self.volume,
+            self.divider,
+            self.decay,
+        );
+    }
+}
+
+impl Envelope {
+    /// Bits 0-5 of the first channel register
+    pub fn write(&mut self, val: u8) {
+        self.looping = val & 0x20 != 0;
+        self.constant = val & 0x10 != 0;
+        self.volume = val & 0xf;
+    }
+
+    pub fn quarter_frame(&mut self) {
+        if self.start {
+            self.start = false;
+            self.decay = 15;
+            self.divider = self.volume;
+        } else if self.divider == 0 {
+            self.divider = self.volume;
+            if self.decay > 0 {
+                self.decay -= 1;
+            } else if self.looping {
+                self.decay = 15;
+            }
+        } else {
+            self.divider -= 1;
+        }
+    }
+
+    pub fn output(&self) -> u8 {
+        if self.constant {
+            self.volume
+        } else {
+            self.decay
+        }
+    }
+}
@@ -0,0 +1,211 @@
+//! A Bevy plugin that runs a console inside a Bevy app, so a game can show
+//! a playable NES screen as part of its own world.
+//!
+//! The picture is a Bevy [`Image`] in [`NesScreen`], updated every frame,
+//! which can go on a sprite, a UI node or a material like any other texture.
+//! Controller 1 follows the keyboard through [`NesKeymap`]. The console is a
+//! non-send resource, [`NesConsole`], since it isn't `Sync`.
+//!
+//! The screen shows the PPU's whole 256x240 picture, overscan included,
+//! drawn a line at a time unless the console comes with another
+//! [`RenderMode`]. [`NesPlugin::with_render`] draws something else instead,
+//! a cropped picture or a program's own board.
+//!
+//! Bevy updates as often as the display refreshes, so by default a PAL game
+//! runs 20% too fast on a 60 Hz display. [`NesPlugin::with_pacing`] runs
+//! the console at its own rate instead.
+
+use bevy::app::{App, Plugin, Update};
+use bevy::asset::{Assets, Handle, RenderAssetUsages};
+use bevy::ecs::prelude::{IntoScheduleConfigs, NonSendMut, Res, ResMut, Resource};
+use bevy::image::Image;
+use bevy::input::ButtonInput;
+use bevy::input::keyboard::KeyCode;
+use bevy::render::render_resource::{Extent3d, TextureDimension, TextureFormat};
+use bevy::time::Time;
+
+use crate::console::Console;
+use crate::joypad::Buttons;
+use crate::pacing::FramePacer;
+use crate::ppu::RenderMode;
+use crate::video::Frame;
+
+/// Adds the console, its screen and the systems running them. Goes after
+/// Bevy's `DefaultPlugins`, which the screen's image needs.
+pub struct NesPlugin {
+    make_console: Box<dyn Fn() -> Console + Send + Sync>,
+    run: fn(&mut Console),
+    render: Option<fn(&Console) -> Frame>,
+    paced: bool,
+}
+
+impl NesPlugin {
+    /// `make_console` builds the console when the app starts
+    pub fn new(make_console: impl Fn() -> Console + Send + Sync + 'static) -> Self {
+        NesPlugin {
+            make_console: Box::new(make_console),
+            run: Console::run_frame,
+            render: None,
+            paced: false,
+        }
+    }
+
+    /// Advance the console with `run` every update instead of a whole frame
+    /// at a time, e.g. for programs that don't wait for vblank
+    pub fn with_run(mut self, run: fn(&mut Console)) -> Self {
+        self.run = run;
+        self
+    }
+
+    /// Draw the screen with `render` after every update instead of showing
+    /// the PPU's picture
+    pub fn with_render(mut self, render: fn(&Console) -> Frame) -> Self {
+        self.render = Some(render);
+        self
+    }
+
+    /// Run as many frames on each update as are due at the console's
+    /// [`Console::refresh_rate`], by the time since the last update, instead
+    /// of one. Follows the console when its region changes.
+    pub fn with_pacing(mut self) -> Self {
+        self.paced = true;
+        self
+    }
+}
+
+impl Plugin for NesPlugin {
+    fn build(&self, app: &mut App) {
+        let mut console = (self.make_console)();
+        let ppu = &mut console.bus_mut().ppu;
+        if self.render.is_none() && ppu.render_mode == RenderMode::Off {
+            ppu.render_mode = RenderMode::Scanline;
+        }
+        let pacer = self.paced.then(|| FramePacer::new(console.refresh_rate()));
+        app.insert_non_send_resource(NesConsole {
+            console,
+            run: self.run,
+            render: self.render.unwrap_or(picture),
+            pacer,
+            paused: false,
+        })
+        .init_resource::<NesKeymap>()
+        .add_systems(Update, (read_input, run_console).chain());
+    }
+
+    /// Runs once every plugin is built, so the image assets exist by now
+    fn finish(&self, app: &mut App) {
+        let console = app.world().non_send_resource::<NesConsole>();
+        let image = new_image(&(console.render)(&console.console));
+        let image = app.world_mut().resource_mut::<Assets<Image>>().add(image);
+        app.insert_resource(NesScreen { image });
+    }
+}
+
+/// The running console, get it with `NonSend<NesConsole>`
+pub struct NesConsole {
+    pub console: Console,
+    run: fn(&mut Console),
+    render: fn(&Console) -> Frame,
+    pacer: Option<FramePacer>,
+    /// Stops the console and leaves the last picture up
+    pub paused: bool,
+}
+
+/// The console's picture, 256x240 or as big as the frames
+/// [`NesPlugin::with_render`]'s function returns
+#[derive(Resource)]
+pub struct NesScreen {
+    pub image: Handle<Image>,
+}
+
+/// Keys held for each of controller 1's buttons
+#[derive(Resource)]
+pub struct NesKeymap {
+    pub keys: Vec<(KeyCode, Buttons)>,
+}
+
+impl Default for NesKeymap {
+    /// The arrows, Z for B, X for A, right Shift for Select and Enter for Start
+    fn default() -> Self {
+        NesKeymap {
+            keys: vec![
+                (KeyCode::ArrowUp, Buttons::UP),
+                (KeyCode::ArrowDown, Buttons::DOWN),
+                (KeyCode::ArrowLeft, Buttons::LEFT),
+                (KeyCode::ArrowRight, Buttons::RIGHT),
+                (KeyCode::KeyZ, Buttons::B),
+                (KeyCode::KeyX, Buttons::A),
+                (KeyCode::ShiftRight, Buttons::SELECT),
+                (KeyCode::Enter, Buttons::START),
+            ],
+        }
+    }
+}
+
+fn read_input(
+    keys: Res<ButtonInput<KeyCode>>,
+    keymap: Res<NesKeymap>,
+    mut nes: NonSendMut<NesConsole>,
+) {
+    let buttons = keymap
+        .keys
+        .iter()
+        .filter(|(key, _)| keys.pressed(*key))
+        .fold(Buttons::empty(), |buttons, &(_, button)| buttons | button);
+    nes.console.bus_mut().joypads[0].buttons = buttons;
+}
+
+fn run_console(
+    mut nes: NonSendMut<NesConsole>,
+    screen: Res<NesScreen>,
+    time: Res<Time>,
+    mut images: ResMut<Assets<Image>>,
+) {
+    if nes.paused {
+        return;
+    }
+    let nes = &mut *nes;
+    let frames = match &mut nes.pacer {
+        Some(pacer) => {
+            pacer.set_rate(nes.console.refresh_rate());
+            pacer.frames_due(time.delta())
+        }
+        None => 1,
+    };
+    if frames == 0 {
+        return;
+    }
+    for _ in 0..frames {
+        (nes.run)(&mut nes.console);
+    }
+    let frame = (nes.render)(&nes.console);
+    if let Some(image) = images.get_mut(&screen.image) {
+        *image = new_image(&frame);
+    }
+}
+
+fn picture(console: &Console) -> Frame {
+    Frame::from_picture(&console.bus().ppu.picture)
+}
+
+fn new_image(frame: &Frame) -> Image {
+    let data = frame
+        .pixels
+        .iter()
+        .flat_map(|pixel| {
+            let [_, r, g, b] = pixel.to_be_bytes();
+            [r, g, b, 0xff]
+        })
+        .collect();
+    Image::new(
+        Extent3d {
+            width: frame.width as u32,
+            height: frame.height as u32,
+            depth_or_array_layers: 1,
+        },
+        TextureDimension::D2,
+        data,
+        TextureFormat::Rgba8UnormSrgb,
+        RenderAssetUsages::default(),
+    )
+}
@@ -0,0 +1,60 @@
+//! Editor for `input.cfg`: the [`input_bindings`] panel in a window of its
+//! own, so bindings can be changed without writing the file by hand.
+//!
+//! `nes-input`, built with the `input-editor` feature. Changes are saved as
+//! they're made and the frontends pick them up when they start. Gamepad
+//! buttons can only be bound when it's also built with `sdl`, which is where
+//! gamepads are read from, egui doesn't see them.
+
+use std::time::Duration;
+
+use log::{Level, warn};
+use nes::config::{Dirs, InputBindings};
+use nes::debug_ui::{BindingCapture, input_bindings};
+
+#[cfg(feature = "sdl")]
+#[path = "../gamepad.rs"]
+mod gamepad;
+
+fn main() -> Result<(), String> {
+    simple_logger::init_with_level(Level::Info).unwrap();
+    let dirs = Dirs::detect(false);
+    if let Err(err) = dirs.create() {
+        warn!("Couldn't create the emulator's directories: {err}");
+    }
+    let mut bindings = InputBindings::load(&dirs.config)?;
+    let mut capture = BindingCapture::default();
+    #[cfg(feature = "sdl")]
+    let mut pads = gamepads();
+
+    let options = eframe::NativeOptions::default();
+    eframe::run_simple_native("NES input", options, move |ctx, _frame| {
+        #[cfg(feature = "sdl")]
+        if let Some((events, gamepads)) = &mut pads {
+            for event in events.poll_iter() {
+                if let Some((button, true)) = gamepads.button(&event) {
+                    capture.gamepad_pressed(button);
+                }
+            }
+        }
+        egui::CentralPanel::default().show(ctx, |ui| {
+            ui.label(format!("Saved to {}", bindings.path().display()));
+            egui::ScrollArea::vertical().show(ui, |ui| {
+                input_bindings(ui, &mut bindings, &mut capture);
+            });
+        });
+        if capture.waiting() {
+            // A gamepad press has to show up without moving the mouse
+            ctx.request_repaint_after(Duration::from_millis(16));
+        }
+    })
+    .map_err(|err| err.to_string())
+}
+
+/// SDL's events and the gamepads they report, `None` if SDL couldn't start
+#[cfg(feature = "sdl")]
+fn gamepads() -> Option<(sdl2::EventPump, gamepad::Gamepads)> {
+    let pads = sdl2::init().and_then(|sdl| Ok((sdl.event_pump()?, gamepad::Gamepads::new(&sdl)?)));
+    pads.inspect_err(|err| warn!("No gamepads, couldn't start SDL: {err}"))
+        .ok()
+}
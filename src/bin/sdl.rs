@@ -0,0 +1,206 @@
+//! A second, minimal frontend on SDL2 for setups where winit or softbuffer
+//! don't work. It only goes through the core's public API, like any other
+//! program using the crate would.
+//!
+//! `nes-sdl game.nes`, built with the `sdl` feature. Keys and gamepad
+//! buttons press controller 1's buttons following `input.cfg`, like in the
+//! main frontend, see [`InputBindings`]. The quit hotkey quits. Battery saves are kept like the main
+//! frontend keeps them.
+//! Audio latency, underruns and drift are logged on exit.
+
+use std::path::Path;
+use std::time::Instant;
+
+use gamepad::Gamepads;
+use log::{Level, info, warn};
+use nes::Bus;
+use nes::apu::AudioStats;
+use nes::config::{Action, Dirs, HotkeyMap, InputBindings, InputDevice};
+use nes::console::Console;
+use nes::pacing::FramePacer;
+use nes::ppu::RenderMode;
+use nes::rom::Rom;
+use nes::savestate::BatterySave;
+use nes::video::{FRAME_HEIGHT, FRAME_WIDTH, Frame};
+use sdl2::audio::{AudioQueue, AudioSpecDesired};
+use sdl2::event::Event;
+use sdl2::keyboard::Keycode;
+use sdl2::pixels::PixelFormatEnum;
+
+#[path = "../gamepad.rs"]
+mod gamepad;
+
+const SAMPLE_RATE: i32 = 44100;
+
+fn main() -> Result<(), String> {
+    simple_logger::init_with_level(Level::Info).unwrap();
+    let rom_path = std::env::args().nth(1).ok_or("usage: nes-sdl game.nes")?;
+    let rom_path = Path::new(&rom_path);
+    let rom = Rom::load(rom_path, true)?;
+    let dirs = Dirs::detect(false);
+    if let Err(err) = dirs.create() {
+        warn!("Couldn't create the emulator's directories: {err}");
+    }
+    let bindings = InputBindings::load(&dirs.config)?;
+    let hotkeys = HotkeyMap::new(&bindings);
+    let battery = BatterySave::new(&Dirs::file(&dirs.saves, rom_path));
+    let mut console = Console::new(rom);
+    console.bus_mut().ppu.render_mode = RenderMode::Scanline;
+    if battery.load(&mut console)? {
+        info!("Loaded {}", battery.path().display());
+    }
+    console.cpu.reset();
+
+    let sdl = sdl2::init()?;
+    let title = rom_path
+        .file_stem()
+        .map_or("nes".into(), |stem| stem.to_string_lossy().into_owned());
+    let window = sdl
+        .video()?
+        .window(&title, FRAME_WIDTH as u32 * 2, FRAME_HEIGHT as u32 * 2)
+        .position_centered()
+        .resizable()
+        .build()
+        .map_err(|e| e.to_string())?;
+    let mut canvas = window
+        .into_canvas()
+        .present_vsync()
+        .build()
+        .map_err(|e| e.to_string())?;
+    let texture_creator = canvas.texture_creator();
+    // 0RGB like the rest of the crate
+    let mut texture = texture_creator
+        .create_texture_streaming(
+            PixelFormatEnum::RGB888,
+            FRAME_WIDTH as u32,
+            FRAME_HEIGHT as u32,
+        )
+        .map_err(|e| e.to_string())?;
+    let audio: AudioQueue<f32> = sdl.audio()?.open_queue(
+        None,
+        &AudioSpecDesired {
+            freq: Some(SAMPLE_RATE),
+            channels: Some(1),
+            samples: None,
+        },
+    )?;
+    console.bus_mut().set_sample_rate(audio.spec().freq as f64);
+    let mut audio_stats = AudioStats::new(audio.spec().freq as f64);
+    audio.resume();
+    let mut gamepads = Gamepads::new(&sdl)?;
+    let mut events = sdl.event_pump()?;
+
+    // Vsync paces the loop, the pacer runs the game at its own rate on it
+    let mut pacer = FramePacer::new(console.refresh_rate());
+    let mut last = Instant::now();
+    let mut frame = Frame::new(FRAME_WIDTH, FRAME_HEIGHT);
+    'running: loop {
+        for event in events.poll_iter() {
+            let (device, name, pressed) = match event {
+                Event::Quit { .. } => break 'running,
+                Event::KeyDown {
+                    keycode: Some(key), ..
+                } => (InputDevice::Keyboard, key_name(key), true),
+                Event::KeyUp {
+                    keycode: Some(key), ..
+                } => (InputDevice::Keyboard, key_name(key), false),
+                _ => match gamepads.button(&event) {
+                    Some((name, pressed)) => (InputDevice::Gamepad, name.to_string(), pressed),
+                    None => continue,
+                },
+            };
+            if pressed && hotkeys.get(device, &name) == Some(Action::Quit) {
+                break 'running;
+            }
+            let button = bindings.action(device, &name).and_then(Action::button);
+            if let Some(button) = button {
+                console.bus_mut().joypads[0].buttons.set(button, pressed);
+            }
+        }
+
+        let now = Instant::now();
+        for _ in 0..pacer.frames_due(now - last) {
+            console.run_frame();
+        }
+        last = now;
+
+        frame.draw_picture(&console.bus().ppu.picture);
+        let pixels: Vec<u8> = frame
+            .pixels
+            .iter()
+            .flat_map(|pixel| pixel.to_ne_bytes())
+            .collect();
+        texture
+            .update(None, &pixels, FRAME_WIDTH * 4)
+            .map_err(|e| e.to_string())?;
+        canvas.clear();
+        canvas.copy(&texture, None, None)?;
+        canvas.present();
+        queue_audio(console.bus_mut(), &audio, &mut audio_stats)?;
+    }
+
+    let drift = audio_stats.drift_ppm().unwrap_or(0.0);
+    info!(
+        "Audio latency {} ms, {} underruns, {} overruns, {drift:+.0} ppm drift",
+        audio_stats.latency().as_millis(),
+        audio_stats.underruns(),
+        audio_stats.overruns()
+    );
+
+    if battery.save(&console)? {
+        info!("Saved {}", battery.path().display());
+    }
+    Ok(())
+}
+
+/// `key` named like egui's `Key::name`, which [`InputBindings`] uses
+fn key_name(key: Keycode) -> String {
+    let name = match key {
+        Keycode::Up => "ArrowUp",
+        Keycode::Down => "ArrowDown",
+        Keycode::Left => "ArrowLeft",
+        Keycode::Right => "ArrowRight",
+        Keycode::Return | Keycode::KpEnter => "Enter",
+        Keycode::Backquote => "Backtick",
+        Keycode::Backslash => "Backslash",
+        Keycode::Equals => "Equals",
+        Keycode::Minus => "Minus",
+        Keycode::Comma => "Comma",
+        Keycode::Period => "Period",
+        Keycode::Semicolon => "Semicolon",
+        Keycode::Slash => "Slash",
+        Keycode::LeftBracket => "OpenBracket",
+        Keycode::RightBracket => "CloseBracket",
+        Keycode::Quote => "Quote",
+        Keycode::PageUp => "PageUp",
+        Keycode::PageDown => "PageDown",
+        _ => return key.name(),
+    };
+    name.to_string()
+}
+
+/// Hand SDL the audio made since the last call. More than a quarter second
+/// queued means we fell behind, so that is dropped to catch up.
+fn queue_audio(
+    bus: &mut Bus,
+    audio: &AudioQueue<f32>,
+    stats: &mut AudioStats,
+) -> Result<(), String> {
+    let queued = audio.size() as usize / size_of::<f32>();
+    let mut dropped = 0;
+    if queued > SAMPLE_RATE as usize / 4 {
+        audio.clear();
+        dropped = queued;
+    }
+    let mut samples = [0.0; 1024];
+    let mut added = 0;
+    loop {
+        let count = bus.read_audio(&mut samples);
+        audio.queue_audio(&samples[..count])?;
+        added += count;
+        if count < samples.len() {
+            stats.record(queued, dropped, added);
+            return Ok(());
+        }
+    }
+}
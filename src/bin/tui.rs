@@ -0,0 +1,157 @@
+//! A terminal frontend, for a quick look at a game over SSH or without a
+//! display. Each character cell shows two pixels stacked with `▀`, the
+//! frame shrunk to fit the terminal. It is also the smallest example of
+//! running a [`Console`] from outside the crate.
+//!
+//! `nes-tui game.nes`, built with the `tui` feature. The arrows, Z, X,
+//! Space and Enter press controller 1's buttons.
+//! Escape, Q or Ctrl+C quits.
+
+use std::io::{self, Stdout, Write};
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use crossterm::style::{Color, Print, ResetColor, SetBackgroundColor, SetForegroundColor};
+use crossterm::{cursor, queue, terminal};
+use nes::config::Dirs;
+use nes::console::Console;
+use nes::joypad::Buttons;
+use nes::pacing::FramePacer;
+use nes::ppu::RenderMode;
+use nes::rom::Rom;
+use nes::savestate::BatterySave;
+use nes::video::Frame;
+
+/// Terminals only say when a key is pressed, so buttons are held this long
+const HOLD: Duration = Duration::from_millis(150);
+
+fn main() -> Result<(), String> {
+    let rom_path = std::env::args().nth(1).ok_or("usage: nes-tui game.nes")?;
+    let rom_path = Path::new(&rom_path);
+    let rom = Rom::load(rom_path, true)?;
+    let dirs = Dirs::detect(false);
+    dirs.create()?;
+    let battery = BatterySave::new(&Dirs::file(&dirs.saves, rom_path));
+    let mut console = Console::new(rom);
+    console.bus_mut().ppu.render_mode = RenderMode::Scanline;
+    battery.load(&mut console)?;
+    console.cpu.reset();
+
+    let ran = Terminal::new()
+        .and_then(|mut terminal| terminal.run(&mut console))
+        .map_err(|e| e.to_string());
+    battery.save(&console)?;
+    ran
+}
+
+/// The terminal in raw mode on the alternate screen, put back when dropped
+struct Terminal {
+    out: Stdout,
+    /// Controller 1 buttons and when they were last pressed
+    held: Vec<(Buttons, Instant)>,
+}
+
+impl Terminal {
+    fn new() -> io::Result<Self> {
+        terminal::enable_raw_mode()?;
+        let mut out = io::stdout();
+        queue!(out, terminal::EnterAlternateScreen, cursor::Hide)?;
+        Ok(Terminal {
+            out,
+            held: Vec::new(),
+        })
+    }
+
+    fn run(&mut self, console: &mut Console) -> io::Result<()> {
+        let mut pacer = FramePacer::new(console.refresh_rate());
+        let mut last = Instant::now();
+        let mut frame = Frame::default();
+        loop {
+            while event::poll(Duration::ZERO)? {
+                if let Event::Key(key) = event::read()?
+                    && key.kind != KeyEventKind::Release
+                    && !self.key(key)
+                {
+                    return Ok(());
+                }
+            }
+            self.held.retain(|(_, pressed)| pressed.elapsed() < HOLD);
+            console.bus_mut().joypads[0].buttons = self
+                .held
+                .iter()
+                .fold(Buttons::empty(), |buttons, &(button, _)| buttons | button);
+
+            let now = Instant::now();
+            for _ in 0..pacer.frames_due(now - last) {
+                console.run_frame();
+            }
+            last = now;
+            frame.draw_picture(&console.bus().ppu.picture);
+            self.draw(&frame)?;
+            std::thread::sleep(pacer.until_next());
+        }
+    }
+
+    /// Handle a key press, false to quit
+    fn key(&mut self, key: KeyEvent) -> bool {
+        let button = match key.code {
+            KeyCode::Esc | KeyCode::Char('q') => return false,
+            KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => return false,
+            KeyCode::Up => Buttons::UP,
+            KeyCode::Down => Buttons::DOWN,
+            KeyCode::Left => Buttons::LEFT,
+            KeyCode::Right => Buttons::RIGHT,
+            KeyCode::Char('z') => Buttons::B,
+            KeyCode::Char('x') => Buttons::A,
+            KeyCode::Char(' ') => Buttons::SELECT,
+            KeyCode::Enter => Buttons::START,
+            _ => return true,
+        };
+        self.held.push((button, Instant::now()));
+        true
+    }
+
+    /// `frame` as big as fits, keeping its shape
+    fn draw(&mut self, frame: &Frame) -> io::Result<()> {
+        let (columns, rows) = terminal::size()?;
+        let fit = (columns as usize)
+            .min(frame.width)
+            .min(rows as usize * 2 * frame.width / frame.height.max(1));
+        let frame = frame.downscale(fit, fit * frame.height / frame.width.max(1));
+        queue!(self.out, cursor::MoveTo(0, 0))?;
+        for (top, y) in (0..frame.height).step_by(2).zip(0..) {
+            queue!(self.out, cursor::MoveTo(0, y))?;
+            for x in 0..frame.width {
+                let upper = frame.row(top)[x];
+                let lower = frame.row((top + 1).min(frame.height - 1))[x];
+                queue!(
+                    self.out,
+                    SetForegroundColor(rgb(upper)),
+                    SetBackgroundColor(rgb(lower)),
+                    Print('▀')
+                )?;
+            }
+        }
+        queue!(self.out, ResetColor)?;
+        self.out.flush()
+    }
+}
+
+impl Drop for Terminal {
+    fn drop(&mut self) {
+        let _ = queue!(
+            self.out,
+            ResetColor,
+            cursor::Show,
+            terminal::LeaveAlternateScreen
+        );
+        let _ = self.out.flush();
+        let _ = terminal::disable_raw_mode();
+    }
+}
+
+fn rgb(pixel: u32) -> Color {
+    let [_, r, g, b] = pixel.to_be_bytes();
+    Color::Rgb { r, g, b }
+}
@@ -0,0 +1,243 @@
+//! Stopping the CPU for the debugger. Besides stopping at an address, the
+//! CPU can stop on the events ROM hackers usually need to catch: entering
+//! the NMI or IRQ handler, a BRK, a write that switches PRG ROM banks and
+//! code in a given bank starting to run. PPU memory is covered too, for
+//! accesses the CPU makes through PPUDATA ($2007): stopping after the
+//! instruction, with [`Breakpoints::ppu_access`] saying what it was.
+//! Rendering fetches don't count, they would stop every frame.
+//!
+//! [`crate::Cpu::try_step`] reports a stop as
+//! [`crate::error::CpuError::DebugBreak`], and [`Breakpoints::hit`] says
+//! which breakpoint it was. Stops before an instruction don't happen again
+//! when stepping on, so stepping again runs it.
+
+/// Something to stop the CPU on
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Breakpoint {
+    /// Before the instruction at `addr` runs
+    Pc(u16),
+    /// Once the NMI has jumped to its handler, before the handler runs
+    Nmi,
+    /// Once an IRQ has jumped to its handler, before the handler runs
+    Irq,
+    /// Before a BRK runs
+    Brk,
+    /// After an instruction that wrote to the board and changed which PRG
+    /// ROM is at $6000-$FFFF
+    BankSwitch,
+    /// Before running code from bank `bank` of PRG ROM, counted in banks of
+    /// `size` bytes like 0x4000 for UxROM, after running code anywhere else
+    PrgBank { bank: usize, size: usize },
+    /// After an instruction that wrote PPU memory at `start..=end`
+    PpuWrite { start: u16, end: u16 },
+    /// After an instruction that read PPU memory at `start..=end`
+    PpuRead { start: u16, end: u16 },
+    /// After an instruction that accessed cartridge space the board has
+    /// nothing at. Set with [`crate::UnmappedAccess::Break`] rather than
+    /// added here.
+    Unmapped,
+}
+
+impl Breakpoint {
+    /// Writes to the nametables and their mirrors
+    pub fn nametable_writes() -> Self {
+        Breakpoint::PpuWrite {
+            start: 0x2000,
+            end: 0x3eff,
+        }
+    }
+
+    /// Writes to the palettes and their mirrors
+    pub fn palette_writes() -> Self {
+        Breakpoint::PpuWrite {
+            start: 0x3f00,
+            end: 0x3fff,
+        }
+    }
+
+    /// Reads of `tile` in the pattern tables, 0-255 on the left and 256-511
+    /// on the right
+    pub fn tile_reads(tile: u16) -> Self {
+        let start = (tile & 0x1ff) * 16;
+        Breakpoint::PpuRead {
+            start,
+            end: start + 15,
+        }
+    }
+}
+
+/// An access to PPU memory through PPUDATA that hit a breakpoint
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PpuAccess {
+    /// In the PPU's address space, $0000-$3FFF
+    pub addr: u16,
+    /// What was written, or what was read from `addr`
+    pub val: u8,
+    pub write: bool,
+    /// The instruction that accessed PPUDATA
+    pub pc: u16,
+}
+
+/// The breakpoints to stop on. Checks cost nothing while there are none.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Breakpoints {
+    list: Vec<Breakpoint>,
+    /// What the CPU last stopped on
+    hit: Option<Breakpoint>,
+    /// The last stop on a PPU breakpoint
+    ppu_access: Option<PpuAccess>,
+    /// The instruction being run
+    pc: u16,
+    /// PRG ROM offset of the last instruction checked
+    last_offset: Option<usize>,
+    /// Hit by the instruction being run, to stop after it
+    pending: Option<Breakpoint>,
+}
+
+impl Breakpoints {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Start stopping on `breakpoint`, if it isn't set already
+    pub fn add(&mut self, breakpoint: Breakpoint) {
+        if !self.list.contains(&breakpoint) {
+            self.list.push(breakpoint);
+        }
+    }
+
+    /// Returns whether `breakpoint` was set
+    pub fn remove(&mut self, breakpoint: Breakpoint) -> bool {
+        let len = self.list.len();
+        self.list.retain(|&set| set != breakpoint);
+        self.list.len() != len
+    }
+
+    pub fn clear(&mut self) {
+        self.list.clear();
+    }
+
+    /// Breakpoints in the order they were added
+    pub fn list(&self) -> &[Breakpoint] {
+        &self.list
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.list.is_empty()
+    }
+
+    /// The breakpoint behind the last [`crate::error::CpuError::DebugBreak`],
+    /// `None` if it was some other stop, see
+    /// [`crate::diagnostics::Diagnostics::break_on_return_mismatch`]
+    pub fn hit(&self) -> Option<Breakpoint> {
+        self.hit
+    }
+
+    /// The access behind the last stop on a [`Breakpoint::PpuWrite`] or
+    /// [`Breakpoint::PpuRead`]
+    pub fn ppu_access(&self) -> Option<PpuAccess> {
+        self.ppu_access
+    }
+
+    /// Whether writes to the board need checking for bank switches
+    pub(crate) fn watches_banks(&self) -> bool {
+        self.list.contains(&Breakpoint::BankSwitch)
+    }
+
+    /// The instruction at `pc` with `opcode` is about to run, from `offset`
+    /// into PRG ROM if it is in ROM. `resuming` from a stop there, it only
+    /// keeps track of where code runs. Returns whether to stop before it.
+    pub(crate) fn instruction(
+        &mut self,
+        pc: u16,
+        opcode: u8,
+        offset: Option<usize>,
+        resuming: bool,
+    ) -> bool {
+        self.pc = pc;
+        let last_offset = std::mem::replace(&mut self.last_offset, offset);
+        if resuming {
+            return false;
+        }
+        let in_bank = |offset: Option<usize>, bank, size| {
+            offset.is_some_and(|offset| size > 0 && offset / size == bank)
+        };
+        let hit = self
+            .list
+            .iter()
+            .copied()
+            .find(|&breakpoint| match breakpoint {
+                Breakpoint::Pc(addr) => addr == pc,
+                Breakpoint::Brk => opcode == 0x00,
+                Breakpoint::PrgBank { bank, size } => {
+                    in_bank(offset, bank, size) && !in_bank(last_offset, bank, size)
+                }
+                _ => false,
+            });
+        self.check(hit)
+    }
+
+    /// The CPU jumped to an interrupt handler. Returns whether to stop.
+    pub(crate) fn interrupt(&mut self, nmi: bool) -> bool {
+        let kind = if nmi {
+            Breakpoint::Nmi
+        } else {
+            Breakpoint::Irq
+        };
+        let hit = self.list.contains(&kind).then_some(kind);
+        self.check(hit)
+    }
+
+    /// A write changed which PRG ROM is mapped
+    pub(crate) fn bank_switched(&mut self) {
+        self.pending.get_or_insert(Breakpoint::BankSwitch);
+    }
+
+    /// The CPU read or wrote `val` at `addr` in PPU memory through PPUDATA
+    pub(crate) fn ppu(&mut self, addr: u16, val: u8, write: bool) {
+        let addr = addr & 0x3fff;
+        let hit = self
+            .list
+            .iter()
+            .copied()
+            .find(|&breakpoint| match breakpoint {
+                Breakpoint::PpuWrite { start, end } => write && (start..=end).contains(&addr),
+                Breakpoint::PpuRead { start, end } => !write && (start..=end).contains(&addr),
+                _ => false,
+            });
+        if self.pending.is_none()
+            && let Some(hit) = hit
+        {
+            self.pending = Some(hit);
+            self.ppu_access = Some(PpuAccess {
+                addr,
+                val,
+                write,
+                pc: self.pc,
+            });
+        }
+    }
+
+    /// The CPU accessed an address nothing is mapped at
+    pub(crate) fn unmapped(&mut self) {
+        self.pending.get_or_insert(Breakpoint::Unmapped);
+    }
+
+    /// An instruction finished. Returns whether to stop after it.
+    pub(crate) fn instruction_done(&mut self) -> bool {
+        let pending = self.pending.take();
+        self.check(pending)
+    }
+
+    /// The CPU stopped for something other than a breakpoint
+    pub(crate) fn other_stop(&mut self) {
+        self.hit = None;
+    }
+
+    fn check(&mut self, hit: Option<Breakpoint>) -> bool {
+        if hit.is_some() {
+            self.hit = hit;
+        }
+        hit.is_some()
+    }
+}
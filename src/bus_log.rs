@@ -0,0 +1,87 @@
+//! Every read and write on the CPU bus with the cycle it happened on, to
+//! diff against a logic analyzer capture or another emulator's log when
+//! chasing an accuracy bug. DMA reads are included.
+//!
+//! Compare with the CPU [`crate::mos6502::Cpu::cycle_stepped`]. Otherwise
+//! instructions only take their cycles once they ran, so all of an
+//! instruction's accesses share a cycle, and operand fetches and dummy
+//! reads are left out.
+
+use std::collections::VecDeque;
+
+/// A read or write on the CPU bus
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BusAccess {
+    /// CPU cycles since the log started
+    pub cycle: u64,
+    pub addr: u16,
+    pub val: u8,
+    pub write: bool,
+}
+
+/// The latest accesses, up to a limit. Off until started, since it costs a
+/// check on every access.
+#[derive(Default)]
+pub struct BusLog {
+    enabled: bool,
+    /// CPU cycle the log started on
+    start: u64,
+    /// Accesses kept, the oldest are dropped past this
+    limit: usize,
+    accesses: VecDeque<BusAccess>,
+}
+
+impl BusLog {
+    /// Clear the log and record from CPU cycle `cycle` on, keeping the
+    /// last `limit` accesses
+    pub fn start(&mut self, cycle: u64, limit: usize) {
+        self.enabled = true;
+        self.start = cycle;
+        self.limit = limit;
+        self.accesses.clear();
+    }
+
+    /// Stop recording, keeping what was logged
+    pub fn stop(&mut self) {
+        self.enabled = false;
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    pub fn record(&mut self, cycle: u64, addr: u16, val: u8, write: bool) {
+        if !self.enabled || self.limit == 0 {
+            return;
+        }
+        if self.accesses.len() == self.limit {
+            self.accesses.pop_front();
+        }
+        self.accesses.push_back(BusAccess {
+            // Loading a state or rolling back can go back past the start
+            cycle: cycle.saturating_sub(self.start),
+            addr,
+            val,
+            write,
+        });
+    }
+
+    /// Oldest first
+    pub fn accesses(&self) -> impl Iterator<Item = &BusAccess> {
+        self.accesses.iter()
+    }
+
+    /// A header then an access per line, the cycle in decimal and the rest
+    /// in hex, like `1234,C000,A9,R`
+    pub fn to_csv(&self) -> String {
+        let mut csv = String::from("cycle,address,value,access\n");
+        for access in &self.accesses {
+            let kind = if access.write { 'W' } else { 'R' };
+            csv += &format!(
+                "{},{:04X},{:02X},{kind}\n",
+                access.cycle, access.addr, access.val
+            );
+        }
+        csv
+    }
+}
@@ -0,0 +1,48 @@
+//! Wall-clock time as the emulated console sees it, for cartridges with a
+//! real-time clock and for timestamps like those on savestates.
+//!
+//! The time runs with the console's CPU cycles from a start the
+//! [`TimeSource`] picks, see [`crate::console::Console::set_time_source`].
+//! Boards with a clock, the Datach's DS1307, are set from it at power on and
+//! when the source changes, then count CPU cycles themselves and keep the
+//! time in savestates. Headless runs and [`crate::testing`] scripts use an
+//! [`EmulatedClock`] so their output is the same on every run.
+
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+pub trait TimeSource: Send {
+    /// The date and time `emulated` into the console's run, the time that
+    /// has passed for the console since power on
+    fn now(&self, emulated: Duration) -> SystemTime;
+}
+
+/// The host's clock, the default
+pub struct HostClock;
+
+impl TimeSource for HostClock {
+    fn now(&self, _emulated: Duration) -> SystemTime {
+        SystemTime::now()
+    }
+}
+
+/// A clock that starts at `start` on power on and runs with the console,
+/// so the same run always sees the same times
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct EmulatedClock {
+    pub start: SystemTime,
+}
+
+impl Default for EmulatedClock {
+    /// Starts at midnight UTC on January 1st 2000
+    fn default() -> Self {
+        EmulatedClock {
+            start: UNIX_EPOCH + Duration::from_secs(946_684_800),
+        }
+    }
+}
+
+impl TimeSource for EmulatedClock {
+    fn now(&self, emulated: Duration) -> SystemTime {
+        self.start + emulated
+    }
+}
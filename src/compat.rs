@@ -0,0 +1,168 @@
+//! What a game needs that the emulator doesn't do, found from its header
+//! when it is loaded. Frontends can say "this game may not work" up front
+//! rather than the game going wrong partway through.
+//!
+//! Games still load and run with every issue here: unknown boards run as
+//! NROM, ignored header fields are left out and missing sound chips are
+//! silent. [`CompatIssue::may_break`] tells the issues that are likely to
+//! stop a game working from ones that only change how it looks or sounds.
+
+use std::fmt;
+
+use crate::apu::ExpansionChip;
+use crate::mapper;
+use crate::rom::{Region, Rom};
+
+/// Every issue found with a game, see [`Rom::compat`]
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct CompatReport {
+    pub issues: Vec<CompatIssue>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CompatIssue {
+    /// A board that isn't emulated, the game runs as NROM
+    UnsupportedMapper(u8),
+    /// A 512 byte trainer, which is skipped instead of loaded at $7000
+    Trainer,
+    /// A NES 2.0 header field that isn't used
+    Nes2Field(Nes2Field),
+    /// Made for PAL consoles, it runs at 50 Hz with PAL timing
+    Pal,
+    /// Made for the Dendy, it runs at 50 Hz with Dendy timing
+    Dendy,
+    /// A sound chip on the cartridge that isn't emulated, it stays silent
+    ExpansionAudio(ExpansionChip),
+}
+
+/// NES 2.0 header fields the emulator ignores
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Nes2Field {
+    /// Mapper numbers above 255
+    MapperHighBits,
+    /// PRG or CHR ROM sizes past what iNES 1.0 can describe
+    RomSizeHighBits,
+    /// CHR RAM other than 8K, or battery backed
+    ChrRam,
+    /// Vs. System, PlayChoice-10 and other consoles
+    ConsoleType,
+    /// Extra ROM chips after CHR ROM
+    MiscRoms,
+    /// The device the game expects plugged in, which has to be picked by hand
+    DefaultExpansionDevice(u8),
+}
+
+impl CompatReport {
+    pub fn new(rom: &Rom) -> Self {
+        let mut issues = Vec::new();
+        if mapper::try_new(rom.clone()).is_err() {
+            issues.push(CompatIssue::UnsupportedMapper(rom.mapper));
+        } else if let Some(chip) = silent_chip(rom.mapper) {
+            issues.push(CompatIssue::ExpansionAudio(chip));
+        }
+        let header = &rom.file()[..16];
+        // Disk images have no iNES header
+        if rom.disk_sides.is_empty() && header[6] & 0x04 != 0 {
+            issues.push(CompatIssue::Trainer);
+        }
+        if rom.is_nes2() {
+            issues.extend(nes2_fields(header).into_iter().map(CompatIssue::Nes2Field));
+        }
+        match rom.region {
+            Region::Ntsc => {}
+            Region::Pal => issues.push(CompatIssue::Pal),
+            Region::Dendy => issues.push(CompatIssue::Dendy),
+        }
+        CompatReport { issues }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.issues.is_empty()
+    }
+
+    /// Whether any issue is likely to stop the game working
+    pub fn may_break(&self) -> bool {
+        self.issues.iter().any(CompatIssue::may_break)
+    }
+}
+
+impl CompatIssue {
+    /// Whether the game is likely not to work, rather than look or sound off
+    pub fn may_break(&self) -> bool {
+        match self {
+            CompatIssue::UnsupportedMapper(_) | CompatIssue::Trainer => true,
+            CompatIssue::Nes2Field(field) => !matches!(field, Nes2Field::DefaultExpansionDevice(_)),
+            CompatIssue::Pal | CompatIssue::Dendy | CompatIssue::ExpansionAudio(_) => false,
+        }
+    }
+}
+
+impl fmt::Display for CompatIssue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CompatIssue::UnsupportedMapper(mapper) => {
+                write!(f, "mapper {mapper} isn't supported, running as NROM")
+            }
+            CompatIssue::Trainer => write!(f, "the trainer isn't loaded"),
+            CompatIssue::Nes2Field(field) => write!(f, "NES 2.0 {field} isn't supported"),
+            CompatIssue::Pal => write!(f, "PAL game, running at 50 Hz"),
+            CompatIssue::Dendy => write!(f, "Dendy game, running at 50 Hz"),
+            CompatIssue::ExpansionAudio(chip) => {
+                write!(f, "{chip:?} audio isn't emulated")
+            }
+        }
+    }
+}
+
+impl fmt::Display for Nes2Field {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Nes2Field::MapperHighBits => write!(f, "mapper number above 255"),
+            Nes2Field::RomSizeHighBits => write!(f, "ROM size"),
+            Nes2Field::ChrRam => write!(f, "CHR RAM size"),
+            Nes2Field::ConsoleType => write!(f, "console type"),
+            Nes2Field::MiscRoms => write!(f, "miscellaneous ROM"),
+            Nes2Field::DefaultExpansionDevice(device) => {
+                write!(f, "default expansion device {device}")
+            }
+        }
+    }
+}
+
+/// The sound chip on boards that have one which isn't emulated
+fn silent_chip(mapper: u8) -> Option<ExpansionChip> {
+    match mapper {
+        5 => Some(ExpansionChip::Mmc5),
+        20 => Some(ExpansionChip::Fds),
+        24 | 26 => Some(ExpansionChip::Vrc6),
+        69 => Some(ExpansionChip::S5b),
+        _ => None,
+    }
+}
+
+/// See https://www.nesdev.org/wiki/NES_2.0
+fn nes2_fields(header: &[u8]) -> Vec<Nes2Field> {
+    let mut fields = Vec::new();
+    if header[8] & 0x0f != 0 {
+        fields.push(Nes2Field::MapperHighBits);
+    }
+    if header[9] != 0 {
+        fields.push(Nes2Field::RomSizeHighBits);
+    }
+    // 8K of CHR RAM is what's emulated for games without CHR ROM
+    let chr_ram = header[11] & 0x0f;
+    if (chr_ram != 0 && chr_ram != 7) || header[11] & 0xf0 != 0 {
+        fields.push(Nes2Field::ChrRam);
+    }
+    if header[7] & 0x03 != 0 {
+        fields.push(Nes2Field::ConsoleType);
+    }
+    if header[14] & 0x03 != 0 {
+        fields.push(Nes2Field::MiscRoms);
+    }
+    // 1 is the standard controllers
+    if header[15] & 0x3f > 1 {
+        fields.push(Nes2Field::DefaultExpansionDevice(header[15] & 0x3f));
+    }
+    fields
+}
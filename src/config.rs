@@ -0,0 +1,621 @@
+//! Files kept between runs: where they go, the ROMs opened recently,
+//! settings for each game and input bindings.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+
+use log::warn;
+
+use crate::console::Console;
+use crate::joypad::Buttons;
+use crate::ppu::Overclock;
+use crate::rom::{Region, Rom};
+
+/// How many ROMs [`RecentRoms`] remembers
+pub const RECENT_COUNT: usize = 10;
+
+/// Where the emulator keeps its files. Files for a game are named after its
+/// ROM file, e.g. `saves/game.sav` for `game.nes`, see [`Dirs::file`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Dirs {
+    /// Recent ROMs and per-game settings
+    pub config: PathBuf,
+    /// Battery backed cartridge RAM
+    pub saves: PathBuf,
+    /// Savestates and autosaves
+    pub states: PathBuf,
+    pub screenshots: PathBuf,
+}
+
+impl Dirs {
+    /// The user's directories: settings in `$XDG_CONFIG_HOME/nes`
+    /// (`~/.config/nes`), the rest in `$XDG_DATA_HOME/nes` (`~/.local/share/nes`).
+    /// Both are `%APPDATA%\nes` on Windows. `None` if the environment has
+    /// none of these.
+    pub fn user() -> Option<Self> {
+        let env = |name| {
+            std::env::var_os(name)
+                .filter(|value| !value.is_empty())
+                .map(PathBuf::from)
+        };
+        let home = env("HOME");
+        let config = env("XDG_CONFIG_HOME")
+            .or_else(|| Some(home.as_ref()?.join(".config")))
+            .or_else(|| env("APPDATA"))?
+            .join("nes");
+        let data = env("XDG_DATA_HOME")
+            .or_else(|| Some(home.as_ref()?.join(".local/share")))
+            .or_else(|| env("APPDATA"))?
+            .join("nes");
+        Some(Dirs {
+            config,
+            ..Dirs::in_dir(&data)
+        })
+    }
+
+    /// Everything in `dir`, each kind of file in its own folder
+    pub fn in_dir(dir: &Path) -> Self {
+        Dirs {
+            config: dir.to_path_buf(),
+            saves: dir.join("saves"),
+            states: dir.join("states"),
+            screenshots: dir.join("screenshots"),
+        }
+    }
+
+    /// The user's directories, or everything next to the executable when
+    /// `portable` is set or a `portable.txt` is there
+    pub fn detect(portable: bool) -> Self {
+        let exe_dir = std::env::current_exe()
+            .ok()
+            .and_then(|exe| Some(exe.parent()?.to_path_buf()));
+        let portable_dir = exe_dir
+            .as_ref()
+            .filter(|dir| portable || dir.join("portable.txt").exists());
+        match portable_dir {
+            Some(dir) => Dirs::in_dir(dir),
+            None => Dirs::user()
+                .unwrap_or_else(|| Dirs::in_dir(exe_dir.as_deref().unwrap_or(Path::new(".")))),
+        }
+    }
+
+    /// Make every directory that doesn't exist yet
+    pub fn create(&self) -> Result<(), String> {
+        for dir in [&self.config, &self.saves, &self.states, &self.screenshots] {
+            fs::create_dir_all(dir).map_err(|e| format!("{}: {e}", dir.display()))?;
+        }
+        Ok(())
+    }
+
+    /// `rom_path`'s file name in `dir`. Save files are named after the ROM,
+    /// so this gives e.g. [`crate::savestate::SaveSlots`] the right folder.
+    pub fn file(dir: &Path, rom_path: &Path) -> PathBuf {
+        dir.join(rom_path.file_name().unwrap_or(rom_path.as_os_str()))
+    }
+}
+
+/// `path`'s contents, or nothing if it doesn't exist yet
+fn read_optional(path: &Path) -> Result<String, String> {
+    match fs::read_to_string(path) {
+        Ok(text) => Ok(text),
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(String::new()),
+        Err(e) => Err(format!("{}: {e}", path.display())),
+    }
+}
+
+fn write_creating_dirs(path: &Path, text: &str) -> Result<(), String> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir).map_err(|e| format!("{}: {e}", dir.display()))?;
+    }
+    fs::write(path, text).map_err(|e| format!("{}: {e}", path.display()))
+}
+
+/// ROMs opened recently, newest first, in `recent.txt`
+pub struct RecentRoms {
+    path: PathBuf,
+    pub roms: Vec<PathBuf>,
+}
+
+impl RecentRoms {
+    /// The list in `dir`, empty if there is none yet
+    pub fn load(dir: &Path) -> Result<Self, String> {
+        let path = dir.join("recent.txt");
+        let roms = read_optional(&path)?
+            .lines()
+            .filter(|line| !line.is_empty())
+            .map(PathBuf::from)
+            .collect();
+        Ok(RecentRoms { path, roms })
+    }
+
+    /// Move `rom` to the top, forgetting the oldest past [`RECENT_COUNT`]
+    pub fn add(&mut self, rom: &Path) {
+        let rom = rom.canonicalize().unwrap_or_else(|_| rom.to_path_buf());
+        self.roms.retain(|recent| *recent != rom);
+        self.roms.insert(0, rom);
+        self.roms.truncate(RECENT_COUNT);
+    }
+
+    pub fn save(&self) -> Result<(), String> {
+        let text: String = self
+            .roms
+            .iter()
+            .map(|rom| format!("{}\n", rom.display()))
+            .collect();
+        write_creating_dirs(&self.path, &text)
+    }
+}
+
+/// Settings for one game, `games/<hash>.cfg` with a `key=value` per line.
+/// Keys are up to the frontend, [`GameSettings::apply`] handles the ones
+/// the core knows:
+/// - `overclock`: extra scanlines per frame, see [`Overclock`]
+/// - `sprite-limit`: `on` or `off`
+/// - `open-bus-decay`: milliseconds, or `off` to never decay
+/// - `region`: `ntsc`, `pal` or `dendy` in place of what the header says
+/// - `dip-switches`: a number with a bit per switch, for boards that have
+///   them like NES-EVENT
+/// - `fast-load`: `on` to skip the Famicom Disk System drive's delays
+///
+/// `key.ACTION` and `pad.ACTION` lines change the game's controls like
+/// lines in `input.cfg` do, see [`InputBindings::override_with`].
+pub struct GameSettings {
+    path: PathBuf,
+    values: BTreeMap<String, String>,
+}
+
+impl GameSettings {
+    /// Settings for `rom` in `dir`, keyed by [`Rom::hash`] so they follow the
+    /// game when the file is renamed. Empty if the game has none yet, invalid
+    /// lines are logged and skipped.
+    pub fn load(dir: &Path, rom: &Rom) -> Result<Self, String> {
+        let mut settings = GameSettings::empty(dir, rom);
+        let path = &settings.path;
+        for (i, line) in read_optional(path)?.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let Some((key, value)) = line.split_once('=') else {
+                warn!("{}: skipping invalid line {}", path.display(), i + 1);
+                continue;
+            };
+            let (key, value) = (key.trim().to_string(), value.trim().to_string());
+            settings.values.insert(key, value);
+        }
+        Ok(settings)
+    }
+
+    /// No settings for `rom`, saved where [`GameSettings::load`] reads them
+    pub fn empty(dir: &Path, rom: &Rom) -> Self {
+        GameSettings {
+            path: dir.join("games").join(format!("{:08x}.cfg", rom.hash())),
+            values: BTreeMap::new(),
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.values.get(key).map(String::as_str)
+    }
+
+    pub fn set(&mut self, key: &str, value: &str) {
+        self.values.insert(key.to_string(), value.to_string());
+    }
+
+    pub fn remove(&mut self, key: &str) {
+        self.values.remove(key);
+    }
+
+    pub fn clear(&mut self) {
+        self.values.clear();
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.values
+            .iter()
+            .map(|(key, value)| (key.as_str(), value.as_str()))
+    }
+
+    pub fn save(&self) -> Result<(), String> {
+        let text: String = self
+            .values
+            .iter()
+            .map(|(key, value)| format!("{key}={value}\n"))
+            .collect();
+        write_creating_dirs(&self.path, &text)
+    }
+
+    /// Set up `console` with the core's settings, leaving the rest alone
+    pub fn apply(&self, console: &mut Console) -> Result<(), String> {
+        let invalid =
+            |key: &str, value: &str| format!("{}: invalid {key} {value:?}", self.path.display());
+        if let Some(value) = self.get("region") {
+            let region = match value {
+                "ntsc" => Region::Ntsc,
+                "pal" => Region::Pal,
+                "dendy" => Region::Dendy,
+                _ => return Err(invalid("region", value)),
+            };
+            console.bus_mut().set_region(region);
+        }
+        if let Some(value) = self.get("dip-switches") {
+            let switches = value.parse().map_err(|_| invalid("dip-switches", value))?;
+            console.bus_mut().mapper.set_dip_switches(switches);
+        }
+        if let Some(value) = self.get("fast-load") {
+            let fast = match value {
+                "on" => true,
+                "off" => false,
+                _ => return Err(invalid("fast-load", value)),
+            };
+            console.bus_mut().mapper.set_fast_load(fast);
+        }
+        let ppu = &mut console.bus_mut().ppu;
+        if let Some(value) = self.get("overclock") {
+            let scanlines = value.parse().map_err(|_| invalid("overclock", value))?;
+            ppu.overclock = Overclock {
+                ntsc: scanlines,
+                pal: scanlines,
+            };
+        }
+        if let Some(value) = self.get("sprite-limit") {
+            ppu.sprite_limit = match value {
+                "on" => true,
+                "off" => false,
+                _ => return Err(invalid("sprite-limit", value)),
+            };
+        }
+        if let Some(value) = self.get("open-bus-decay") {
+            ppu.open_bus_decay = match value {
+                "off" => None,
+                ms => Some(ms.parse().map_err(|_| invalid("open-bus-decay", value))?),
+            };
+        }
+        Ok(())
+    }
+}
+
+/// What a key or gamepad button is bound to in [`InputBindings`]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Action {
+    Up,
+    Down,
+    Left,
+    Right,
+    Select,
+    Start,
+    B,
+    A,
+    SaveState,
+    LoadState,
+    Screenshot,
+    ToggleFps,
+    FlipDisk,
+    EjectDisk,
+    Quit,
+    Pause,
+    /// Run a single frame, pausing first
+    FrameAdvance,
+    /// Go back in time while held
+    Rewind,
+    /// Run as fast as possible while held
+    FastForward,
+    Fullscreen,
+    /// Jump back a few seconds and pause, to take back a mistake
+    Undo,
+    /// Start recording controller 1's buttons, or stop and keep them
+    RecordMacro,
+    /// Press the recorded buttons again, a frame at a time
+    PlayMacro,
+}
+
+impl Action {
+    pub const ALL: [Action; 23] = [
+        Action::Up,
+        Action::Down,
+        Action::Left,
+        Action::Right,
+        Action::Select,
+        Action::Start,
+        Action::B,
+        Action::A,
+        Action::SaveState,
+        Action::LoadState,
+        Action::Screenshot,
+        Action::ToggleFps,
+        Action::FlipDisk,
+        Action::EjectDisk,
+        Action::Quit,
+        Action::Pause,
+        Action::FrameAdvance,
+        Action::Rewind,
+        Action::FastForward,
+        Action::Fullscreen,
+        Action::Undo,
+        Action::RecordMacro,
+        Action::PlayMacro,
+    ];
+
+    /// Its name in `input.cfg`
+    pub fn name(self) -> &'static str {
+        match self {
+            Action::Up => "up",
+            Action::Down => "down",
+            Action::Left => "left",
+            Action::Right => "right",
+            Action::Select => "select",
+            Action::Start => "start",
+            Action::B => "b",
+            Action::A => "a",
+            Action::SaveState => "save-state",
+            Action::LoadState => "load-state",
+            Action::Screenshot => "screenshot",
+            Action::ToggleFps => "toggle-fps",
+            Action::FlipDisk => "flip-disk",
+            Action::EjectDisk => "eject-disk",
+            Action::Quit => "quit",
+            Action::Pause => "pause",
+            Action::FrameAdvance => "frame-advance",
+            Action::Rewind => "rewind",
+            Action::FastForward => "fast-forward",
+            Action::Fullscreen => "fullscreen",
+            Action::Undo => "undo",
+            Action::RecordMacro => "record-macro",
+            Action::PlayMacro => "play-macro",
+        }
+    }
+
+    /// Controller 1's button, `None` for the emulator's hotkeys
+    pub fn button(self) -> Option<Buttons> {
+        Some(match self {
+            Action::Up => Buttons::UP,
+            Action::Down => Buttons::DOWN,
+            Action::Left => Buttons::LEFT,
+            Action::Right => Buttons::RIGHT,
+            Action::Select => Buttons::SELECT,
+            Action::Start => Buttons::START,
+            Action::B => Buttons::B,
+            Action::A => Buttons::A,
+            _ => return None,
+        })
+    }
+}
+
+/// Where a bound input comes from
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum InputDevice {
+    /// Keys, named like egui's `Key::name`, e.g. `ArrowUp`, `Z` or `F5`
+    Keyboard,
+    /// Gamepad buttons, named like gilrs' `Button` variants, e.g. `South`
+    /// or `DPadUp`. Only the frontends on SDL read gamepads.
+    Gamepad,
+}
+
+impl InputDevice {
+    /// The prefix of its lines in `input.cfg`
+    fn prefix(self) -> &'static str {
+        match self {
+            InputDevice::Keyboard => "key",
+            InputDevice::Gamepad => "pad",
+        }
+    }
+}
+
+/// The binding a line of `input.cfg` sets, from its key like `key.a`
+fn binding_key(key: &str) -> Option<(InputDevice, Action)> {
+    let (prefix, name) = key.split_once('.')?;
+    let device = [InputDevice::Keyboard, InputDevice::Gamepad]
+        .into_iter()
+        .find(|device| device.prefix() == prefix)?;
+    let action = Action::ALL
+        .into_iter()
+        .find(|action| action.name() == name)?;
+    Some((device, action))
+}
+
+/// A key and a gamepad button for each [`Action`], in `input.cfg` with a
+/// line per binding like `key.a=X` or `pad.start=Start`, empty for none.
+/// Actions missing from the file keep their default.
+pub struct InputBindings {
+    path: PathBuf,
+    bindings: BTreeMap<(InputDevice, Action), String>,
+}
+
+impl InputBindings {
+    /// The bindings in `dir`, the defaults if there are none yet. Invalid
+    /// lines are logged and skipped.
+    pub fn load(dir: &Path) -> Result<Self, String> {
+        let mut bindings = InputBindings::defaults(dir);
+        let text = read_optional(&bindings.path)?;
+        for (i, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let binding = line.split_once('=').and_then(|(key, input)| {
+                let (device, action) = binding_key(key.trim())?;
+                Some((device, action, input.trim()))
+            });
+            match binding {
+                Some((device, action, input)) => bindings.set(device, action, input),
+                None => warn!(
+                    "{}: skipping invalid line {}",
+                    bindings.path.display(),
+                    i + 1
+                ),
+            }
+        }
+        Ok(bindings)
+    }
+
+    /// The arrows, Z for B, X for A, Space for Select, Enter for Start, F5
+    /// to save, F9 to load, F12 for screenshots, F10 for the frame rate, F6
+    /// to flip the disk, F7 to eject it, Escape to quit, P to pause,
+    /// Backslash to advance a frame, Backspace to rewind, the backtick to
+    /// fast forward, F11 for fullscreen and U to undo. On gamepads the d-pad, the
+    /// bottom face button for A, the left one for B, Select and Start.
+    /// Saved to `input.cfg` in `dir`.
+    pub fn defaults(dir: &Path) -> Self {
+        InputBindings::defaults_at(dir.join("input.cfg"))
+    }
+
+    fn defaults_at(path: PathBuf) -> Self {
+        let keys = [
+            (Action::Up, "ArrowUp"),
+            (Action::Down, "ArrowDown"),
+            (Action::Left, "ArrowLeft"),
+            (Action::Right, "ArrowRight"),
+            (Action::Select, "Space"),
+            (Action::Start, "Enter"),
+            (Action::B, "Z"),
+            (Action::A, "X"),
+            (Action::SaveState, "F5"),
+            (Action::LoadState, "F9"),
+            (Action::Screenshot, "F12"),
+            (Action::ToggleFps, "F10"),
+            (Action::FlipDisk, "F6"),
+            (Action::EjectDisk, "F7"),
+            (Action::Quit, "Escape"),
+            (Action::Pause, "P"),
+            (Action::FrameAdvance, "Backslash"),
+            (Action::Rewind, "Backspace"),
+            (Action::FastForward, "Backtick"),
+            (Action::Fullscreen, "F11"),
+            (Action::Undo, "U"),
+            (Action::RecordMacro, "R"),
+            (Action::PlayMacro, "T"),
+        ];
+        let pads = [
+            (Action::Up, "DPadUp"),
+            (Action::Down, "DPadDown"),
+            (Action::Left, "DPadLeft"),
+            (Action::Right, "DPadRight"),
+            (Action::Select, "Select"),
+            (Action::Start, "Start"),
+            (Action::B, "West"),
+            (Action::A, "South"),
+        ];
+        let bindings = keys
+            .map(|(action, key)| ((InputDevice::Keyboard, action), key.to_string()))
+            .into_iter()
+            .chain(pads.map(|(action, pad)| ((InputDevice::Gamepad, action), pad.to_string())))
+            .collect();
+        InputBindings { path, bindings }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Bind `input` to `action` like a line of `input.cfg`, empty unbinds
+    fn set(&mut self, device: InputDevice, action: Action, input: &str) {
+        match input {
+            "" => self.unbind(device, action),
+            input => {
+                self.bindings.insert((device, action), input.to_string());
+            }
+        }
+    }
+
+    /// Take the bindings a game's settings change, its `key.` and `pad.`
+    /// lines, over the ones from `input.cfg`. Saving would write them to
+    /// `input.cfg`.
+    pub fn override_with(&mut self, settings: &GameSettings) {
+        for (key, input) in settings.iter() {
+            if let Some((device, action)) = binding_key(key) {
+                self.set(device, action, input);
+            }
+        }
+    }
+
+    /// The input bound to `action`
+    pub fn get(&self, device: InputDevice, action: Action) -> Option<&str> {
+        self.bindings.get(&(device, action)).map(String::as_str)
+    }
+
+    /// The action `input` is bound to
+    pub fn action(&self, device: InputDevice, input: &str) -> Option<Action> {
+        self.bindings
+            .iter()
+            .find(|((bound_device, _), bound)| *bound_device == device && *bound == input)
+            .map(|((_, action), _)| *action)
+    }
+
+    /// Controller 1's buttons held, from the inputs held on `device`
+    pub fn buttons<'a>(
+        &self,
+        device: InputDevice,
+        held: impl IntoIterator<Item = &'a str>,
+    ) -> Buttons {
+        held.into_iter()
+            .filter_map(|input| self.action(device, input)?.button())
+            .fold(Buttons::empty(), |buttons, button| buttons | button)
+    }
+
+    /// Bind `input` to `action`, taking it from any other action so an
+    /// input does one thing
+    pub fn bind(&mut self, device: InputDevice, action: Action, input: &str) {
+        if let Some(other) = self.action(device, input) {
+            self.unbind(device, other);
+        }
+        self.bindings.insert((device, action), input.to_string());
+    }
+
+    pub fn unbind(&mut self, device: InputDevice, action: Action) {
+        self.bindings.remove(&(device, action));
+    }
+
+    /// Go back to the default bindings
+    pub fn reset(&mut self) {
+        *self = InputBindings::defaults_at(self.path.clone());
+    }
+
+    /// Write every binding, unbound actions as empty lines so they stay
+    /// unbound rather than going back to their default
+    pub fn save(&self) -> Result<(), String> {
+        let text: String = [InputDevice::Keyboard, InputDevice::Gamepad]
+            .into_iter()
+            .flat_map(|device| Action::ALL.map(|action| (device, action)))
+            .map(|(device, action)| {
+                let input = self.get(device, action).unwrap_or("");
+                format!("{}.{}={input}\n", device.prefix(), action.name())
+            })
+            .collect();
+        write_creating_dirs(&self.path, &text)
+    }
+}
+
+/// The emulator's hotkeys from [`InputBindings`], for frontends to look up
+/// what a key or button press does. Controller buttons aren't hotkeys, see
+/// [`InputBindings::buttons`].
+pub struct HotkeyMap {
+    hotkeys: BTreeMap<(InputDevice, String), Action>,
+}
+
+impl HotkeyMap {
+    pub fn new(bindings: &InputBindings) -> Self {
+        let hotkeys = bindings
+            .bindings
+            .iter()
+            .filter(|((_, action), _)| action.button().is_none())
+            .map(|(&(device, action), input)| ((device, input.clone()), action))
+            .collect();
+        HotkeyMap { hotkeys }
+    }
+
+    /// The hotkeys in `input.cfg` in `dir`, see [`InputBindings::load`]
+    pub fn load(dir: &Path) -> Result<Self, String> {
+        Ok(HotkeyMap::new(&InputBindings::load(dir)?))
+    }
+
+    /// The hotkey `input` is bound to
+    pub fn get(&self, device: InputDevice, input: &str) -> Option<Action> {
+        self.hotkeys.get(&(device, input.to_string())).copied()
+    }
+}
@@ -0,0 +1,540 @@
+use std::collections::VecDeque;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::clock::{HostClock, TimeSource};
+use crate::error::{CpuError, MapperError, StateError};
+use crate::expr::Expr;
+use crate::heatmap::{HeatmapMemory, MemoryHeatmap};
+use crate::mapper;
+use crate::ppu::DOTS_PER_SCANLINE;
+use crate::rom::Rom;
+use crate::savestate::{
+    OLDEST_VERSION, SaveState, StateStream, StateVersionError, Stateful, Thumbnail, VERSION,
+};
+use crate::{Bus, Cpu};
+
+/// Frames of history kept for each watch
+pub const WATCH_HISTORY: usize = 600;
+
+/// An expression sampled at the end of every frame
+pub struct Watch {
+    pub source: String,
+    expr: Expr,
+    /// Oldest first, at most `WATCH_HISTORY` values
+    pub history: VecDeque<i32>,
+}
+
+impl Watch {
+    pub fn value(&self) -> Option<i32> {
+        self.history.back().copied()
+    }
+}
+
+/// An expression checked at the end of every frame, met when it turns
+/// nonzero, like `[$075A] == 0` for a game over. See [`Console::add_condition`].
+pub struct Condition {
+    pub source: String,
+    expr: Expr,
+    /// Whether it was nonzero at the last check
+    pub holds: bool,
+}
+
+/// Called with the bus at the end of every frame
+pub type FrameCallback = Box<dyn FnMut(&Bus) + Send>;
+
+/// A whole console, with helpers for running to a point in the frame.
+/// Consoles share no state, so any number can run at once, each on its own
+/// thread or moved between them.
+pub struct Console {
+    pub cpu: Cpu,
+    watches: Vec<Watch>,
+    conditions: Vec<Condition>,
+    /// Conditions met since [`Console::take_met_conditions`], by index
+    met: Vec<usize>,
+    frame_callbacks: Vec<FrameCallback>,
+    rom_hash: u32,
+    /// `Rom::mapper` of the loaded game, a reload only keeps the board's
+    /// state on the same board
+    board: u8,
+    clock: Box<dyn TimeSource>,
+}
+
+impl Console {
+    /// Games on boards that aren't emulated run as NROM, see [`Console::try_new`]
+    pub fn new(rom: Rom) -> Self {
+        let (rom_hash, board) = (rom.hash(), rom.mapper);
+        let mut console = Console {
+            cpu: Cpu::new(Bus::new(rom)),
+            watches: Vec::new(),
+            conditions: Vec::new(),
+            met: Vec::new(),
+            frame_callbacks: Vec::new(),
+            rom_hash,
+            board,
+            clock: Box::new(HostClock),
+        };
+        console.set_mapper_time();
+        console
+    }
+
+    /// Like [`Console::new`], but fails for boards that aren't emulated
+    pub fn try_new(rom: Rom) -> Result<Self, MapperError> {
+        let (rom_hash, board) = (rom.hash(), rom.mapper);
+        let mut console = Console {
+            cpu: Cpu::new(Bus::try_new(rom)?),
+            watches: Vec::new(),
+            conditions: Vec::new(),
+            met: Vec::new(),
+            frame_callbacks: Vec::new(),
+            rom_hash,
+            board,
+            clock: Box::new(HostClock),
+        };
+        console.set_mapper_time();
+        Ok(console)
+    }
+
+    /// Where the console gets the date and time, the host's clock unless
+    /// set. Cartridges with a real-time clock are set to the new time.
+    pub fn set_time_source(&mut self, clock: impl TimeSource + 'static) {
+        self.clock = Box::new(clock);
+        self.set_mapper_time();
+    }
+
+    /// The date and time for the console, from its [`TimeSource`]
+    pub fn now(&self) -> SystemTime {
+        let bus = self.bus();
+        let seconds = bus.cycles as f64 / bus.ppu.region.cpu_clock_rate();
+        self.clock.now(Duration::from_secs_f64(seconds))
+    }
+
+    fn set_mapper_time(&mut self) {
+        let now = self.now();
+        self.bus_mut().mapper.set_time(now);
+    }
+
+    /// `Rom::hash` of the loaded game
+    pub fn rom_hash(&self) -> u32 {
+        self.rom_hash
+    }
+
+    /// Swap in a rebuilt `rom` without restarting the game, so homebrew
+    /// developers see their changes right away. RAM is kept, and so are the
+    /// board's registers, PRG RAM and CHR RAM when `rom` is on the same board
+    /// with the same amount of memory. Otherwise the board starts over with
+    /// only its battery backed RAM. With `keep_cpu` the CPU carries on from
+    /// where it was, which only works if the code it is in didn't move,
+    /// otherwise it is reset. Returns whether the board's state was kept.
+    ///
+    /// Savestates of the old ROM no longer load, and labels found by
+    /// [`crate::labels::AutoLabels`] are forgotten since the code moved.
+    pub fn reload_rom(&mut self, rom: Rom, keep_cpu: bool) -> bool {
+        let (rom_hash, board, prg_rom_len) = (rom.hash(), rom.mapper, rom.prg_rom.len());
+        let now = self.now();
+        let bus = &mut self.cpu.memory;
+        let mut old = StateStream::saving();
+        bus.mapper.stream(&mut old);
+        let old = old.finish().expect("saving can't fail");
+        let save_data = bus.mapper.save_data();
+        let mut mapper = mapper::new(rom.clone());
+        let kept = board == self.board && {
+            let mut s = StateStream::loading(old);
+            mapper.stream(&mut s);
+            s.finish().is_ok()
+        };
+        if !kept {
+            // Loading may have got partway
+            mapper = mapper::new(rom);
+            if let Some(data) = save_data {
+                mapper.load_save_data(&data);
+            }
+            mapper.set_time(now);
+        }
+        bus.mapper = mapper;
+        bus.ppu.tile_cache.clear();
+        if bus.heatmap.counts(HeatmapMemory::PrgRom).len() != prg_rom_len {
+            let mut heatmap = MemoryHeatmap::new(prg_rom_len);
+            heatmap.enabled = bus.heatmap.enabled;
+            heatmap.interval = bus.heatmap.interval;
+            bus.heatmap = heatmap;
+        }
+        bus.auto_labels.clear();
+        self.rom_hash = rom_hash;
+        self.board = board;
+        if !keep_cpu {
+            self.cpu.memory.diagnostics.clear_call_stack();
+            self.cpu.reset();
+        }
+        kept
+    }
+
+    /// Snapshot the console, with an optional screenshot to show in slot lists
+    pub fn save_state(&mut self, thumbnail: Option<Thumbnail>) -> SaveState {
+        let mut s = StateStream::saving();
+        self.cpu.stream(&mut s);
+        SaveState {
+            timestamp: self
+                .now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |time| time.as_secs()),
+            rom_hash: self.rom_hash,
+            frame: self.bus().ppu.frame,
+            thumbnail,
+            version: VERSION,
+            data: s.finish().expect("saving can't fail"),
+        }
+    }
+
+    /// Restore a snapshot from `save_state`, migrating states saved by older
+    /// versions. States of other games or unsupported versions are refused,
+    /// and the console is left untouched if the state is corrupt.
+    pub fn load_state(&mut self, state: &SaveState) -> Result<(), StateError> {
+        self.load(state, false)
+    }
+
+    /// Like [`Console::load_state`], but also takes states of other ROMs, like
+    /// an earlier build of a homebrew game. The board and its memory sizes
+    /// have to match, and the game has to cope with its RAM as the old build
+    /// left it.
+    pub fn load_state_any_rom(&mut self, state: &SaveState) -> Result<(), StateError> {
+        self.load(state, true)
+    }
+
+    fn load(&mut self, state: &SaveState, any_rom: bool) -> Result<(), StateError> {
+        if !any_rom && state.rom_hash != self.rom_hash {
+            return Err(StateError::WrongRom {
+                state: state.rom_hash,
+                rom: self.rom_hash,
+            });
+        }
+        if !(OLDEST_VERSION..=VERSION).contains(&state.version) {
+            return Err(StateVersionError {
+                found: state.version,
+            }
+            .into());
+        }
+        let mut backup = StateStream::saving();
+        self.cpu.stream(&mut backup);
+        let mut s = StateStream::loading_version(state.data.clone(), state.version);
+        self.cpu.stream(&mut s);
+        if let Err(err) = s.finish() {
+            let backup = backup.finish().expect("saving can't fail");
+            let mut s = StateStream::loading(backup);
+            self.cpu.stream(&mut s);
+            return Err(err);
+        }
+        // Savestates don't say which RAM the game has written or its calls
+        let diagnostics = &mut self.cpu.memory.diagnostics;
+        diagnostics.assume_ram_initialized();
+        diagnostics.clear_call_stack();
+        // Loading a state where a condition holds doesn't meet it
+        self.recheck_conditions();
+        Ok(())
+    }
+
+    /// Start sampling `source` (see `expr`) every frame, returns its index
+    pub fn add_watch(&mut self, source: &str) -> Result<usize, String> {
+        let expr = Expr::parse(source)?;
+        self.watches.push(Watch {
+            source: source.to_string(),
+            expr,
+            history: VecDeque::new(),
+        });
+        Ok(self.watches.len() - 1)
+    }
+
+    pub fn remove_watch(&mut self, index: usize) -> Watch {
+        self.watches.remove(index)
+    }
+
+    pub fn watches(&self) -> &[Watch] {
+        &self.watches
+    }
+
+    fn sample_watches(&mut self) {
+        for watch in &mut self.watches {
+            if watch.history.len() == WATCH_HISTORY {
+                watch.history.pop_front();
+            }
+            watch.history.push_back(watch.expr.eval(&self.cpu));
+        }
+    }
+
+    /// Check `source` (see `expr`) at the end of every frame, and report it
+    /// through [`Console::take_met_conditions`] when it becomes true. It has
+    /// to be false first, one already true when added is only met once it
+    /// turns false and back. Returns its index.
+    pub fn add_condition(&mut self, source: &str) -> Result<usize, String> {
+        let expr = Expr::parse(source)?;
+        let holds = expr.eval(&self.cpu) != 0;
+        self.conditions.push(Condition {
+            source: source.to_string(),
+            expr,
+            holds,
+        });
+        Ok(self.conditions.len() - 1)
+    }
+
+    /// The conditions after it move down an index
+    pub fn remove_condition(&mut self, index: usize) -> Condition {
+        self.met.retain(|&met| met != index);
+        for met in &mut self.met {
+            if *met > index {
+                *met -= 1;
+            }
+        }
+        self.conditions.remove(index)
+    }
+
+    pub fn conditions(&self) -> &[Condition] {
+        &self.conditions
+    }
+
+    /// Indices of the conditions that became true since the last call, in
+    /// the order they did. A condition met again is listed again.
+    pub fn take_met_conditions(&mut self) -> Vec<usize> {
+        std::mem::take(&mut self.met)
+    }
+
+    fn check_conditions(&mut self) {
+        for (index, condition) in self.conditions.iter_mut().enumerate() {
+            let holds = condition.expr.eval(&self.cpu) != 0;
+            if holds && !condition.holds {
+                self.met.push(index);
+            }
+            condition.holds = holds;
+        }
+    }
+
+    fn recheck_conditions(&mut self) {
+        for condition in &mut self.conditions {
+            condition.holds = condition.expr.eval(&self.cpu) != 0;
+        }
+    }
+
+    /// Call `callback` at the end of every frame, after watches are sampled.
+    /// Achievement runtimes evaluate their conditions from here.
+    pub fn on_frame(&mut self, callback: impl FnMut(&Bus) + Send + 'static) {
+        self.frame_callbacks.push(Box::new(callback));
+    }
+
+    pub fn bus(&self) -> &Bus {
+        &self.cpu.memory
+    }
+
+    pub fn bus_mut(&mut self) -> &mut Bus {
+        &mut self.cpu.memory
+    }
+
+    /// Run a single instruction (or interrupt)
+    pub fn step(&mut self) {
+        let _ = self.try_step();
+    }
+
+    /// Like [`Console::step`], but reports an opcode the CPU can't run.
+    /// The console still advances, see [`CpuError::InvalidOpcode`].
+    pub fn try_step(&mut self) -> Result<(), CpuError> {
+        let frame = self.bus().ppu.frame;
+        let stepped = self.cpu.try_step();
+        if self.bus().ppu.frame != frame {
+            self.sample_watches();
+            self.check_conditions();
+            for callback in &mut self.frame_callbacks {
+                callback(&self.cpu.memory);
+            }
+        }
+        stepped
+    }
+
+    /// CRC-32 of the last picture the PPU drew and everything it was drawn
+    /// from that the PPU holds: palette, nametables, OAM, scroll and the
+    /// control and mask registers. The picture is only drawn with a
+    /// [`RenderMode`](crate::ppu::RenderMode) other than `Off`, so CHR only
+    /// counts then. Stable across runs, for comparing against a known good frame.
+    pub fn frame_hash(&self) -> u32 {
+        let ppu = &self.bus().ppu;
+        let mut hasher = crc32fast::Hasher::new();
+        hasher.update(&ppu.picture);
+        hasher.update(&ppu.palette);
+        hasher.update(&ppu.vram);
+        hasher.update(&ppu.oam);
+        hasher.update(&ppu.temp_addr.to_le_bytes());
+        hasher.update(&[ppu.fine_x, ppu.ctrl.bits(), ppu.mask.bits()]);
+        hasher.finalize()
+    }
+
+    /// CRC-32 of everything a savestate holds, cheap enough to take every frame.
+    /// Two consoles with the same hash will run the same from here on, which is
+    /// what netplay desync checks, movie verification and regression tests compare.
+    /// Needs `&mut` because it goes through [`Stateful::stream`], nothing changes.
+    pub fn state_hash(&mut self) -> u32 {
+        let mut s = StateStream::hashing();
+        self.cpu.stream(&mut s);
+        s.finish_hash()
+    }
+
+    /// Whether the game skipped reading the controllers during the last frame
+    pub fn is_lag_frame(&self) -> bool {
+        self.bus().lag_frame
+    }
+
+    /// Lag frames since power on
+    pub fn lag_count(&self) -> u64 {
+        self.bus().lag_frames
+    }
+
+    /// Frames per second the console runs at for its region. Frontends that
+    /// wait for vsync pace frames with it, see [`crate::pacing::FramePacer`].
+    pub fn refresh_rate(&self) -> f64 {
+        self.bus().ppu.region.refresh_rate()
+    }
+
+    /// Sides of the Famicom Disk System image, 0 for cartridges
+    pub fn disk_sides(&self) -> usize {
+        self.bus().mapper.disk_sides()
+    }
+
+    /// Side in the disk drive, `None` when it's empty
+    pub fn disk_side(&self) -> Option<usize> {
+        self.bus().mapper.disk_side()
+    }
+
+    /// Take the disk out of the drive
+    pub fn eject_disk(&mut self) {
+        self.bus_mut().mapper.insert_disk(None);
+    }
+
+    /// Put `side` in the drive. Games only notice a swap if they see the
+    /// drive empty, so a disk that's in comes out first and `side` goes in
+    /// about a second later.
+    pub fn insert_disk(&mut self, side: usize) {
+        self.bus_mut().mapper.insert_disk(Some(side));
+    }
+
+    /// Swap in the next side, going back to the first after the last one
+    /// and starting there when the drive is empty. Returns the side going
+    /// in, `None` for cartridges.
+    pub fn flip_disk(&mut self) -> Option<usize> {
+        let sides = self.disk_sides();
+        if sides == 0 {
+            return None;
+        }
+        let side = self.disk_side().map_or(0, |side| (side + 1) % sides);
+        self.insert_disk(side);
+        Some(side)
+    }
+
+    /// Swipe a barcode through the Datach's reader, see
+    /// [`crate::mapper::BarcodeReader::scan`]
+    pub fn scan_barcode(&mut self, code: &str) -> Result<(), String> {
+        match self.bus_mut().mapper.barcode_reader() {
+            Some(reader) => reader.scan(code),
+            None => Err(String::from("the game has no barcode reader")),
+        }
+    }
+
+    /// Run until the next frame starts
+    pub fn run_frame(&mut self) {
+        let frame = self.bus().ppu.frame;
+        while self.bus().ppu.frame == frame {
+            self.step();
+        }
+    }
+
+    /// Like [`Console::run_frame`], but stops at the first opcode the CPU
+    /// can't run, leaving the CPU on it
+    pub fn try_run_frame(&mut self) -> Result<(), CpuError> {
+        let frame = self.bus().ppu.frame;
+        while self.bus().ppu.frame == frame {
+            self.try_step()?;
+        }
+        Ok(())
+    }
+
+    /// Run until the start of the next occurrence of `scanline`
+    pub fn run_to_scanline(&mut self, scanline: u16) {
+        self.run_to_dot(scanline, 0);
+    }
+
+    /// Run until the PPU reaches the next occurrence of `dot` on `scanline`.
+    /// The CPU runs whole instructions, so this stops at the first instruction
+    /// boundary at or past the target, which can overshoot by up to an
+    /// instruction (or DMA) worth of dots. Returns immediately when already there.
+    /// Positions past the end of the region's frame, like a PAL scanline on
+    /// NTSC, stop at its last scanline or dot instead.
+    pub fn run_to_dot(&mut self, scanline: u16, dot: u16) {
+        let scanline = scanline.min(self.bus().ppu.scanlines_per_frame() - 1);
+        let dot = dot.min(DOTS_PER_SCANLINE - 1);
+        let target = scanline as u32 * DOTS_PER_SCANLINE as u32 + dot as u32;
+        let mut pos = self.position();
+        while pos != target {
+            self.step();
+            let next = self.position();
+            let crossed = if next >= pos {
+                pos < target && target <= next
+            } else {
+                // Wrapped into the next frame
+                pos < target || target <= next
+            };
+            if crossed {
+                return;
+            }
+            pos = next;
+        }
+    }
+
+    fn position(&self) -> u32 {
+        let ppu = &self.bus().ppu;
+        ppu.scanline as u32 * DOTS_PER_SCANLINE as u32 + ppu.dot as u32
+    }
+}
+
+#[cfg(feature = "async")]
+impl Console {
+    /// Run frame after frame as a [`futures_core::Stream`], for async
+    /// runtimes. Each item is what `each_frame` takes from the console when a
+    /// frame ends, e.g. a screenshot or [`Console::state_hash`]. The stream
+    /// yields back to the runtime after every scanline, so a worker thread is
+    /// never held for more than a fraction of a frame. It never ends.
+    pub fn run_frames_stream<T, F: FnMut(&mut Console) -> T>(
+        &mut self,
+        each_frame: F,
+    ) -> FrameStream<'_, F> {
+        FrameStream {
+            console: self,
+            each_frame,
+        }
+    }
+}
+
+/// See [`Console::run_frames_stream`]
+#[cfg(feature = "async")]
+pub struct FrameStream<'a, F> {
+    console: &'a mut Console,
+    each_frame: F,
+}
+
+// Nothing is pinned in place, the stream only borrows the console
+#[cfg(feature = "async")]
+impl<F> Unpin for FrameStream<'_, F> {}
+
+#[cfg(feature = "async")]
+impl<T, F: FnMut(&mut Console) -> T> futures_core::Stream for FrameStream<'_, F> {
+    type Item = T;
+
+    fn poll_next(
+        self: std::pin::Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<Option<T>> {
+        let this = self.get_mut();
+        let timing = this.console.bus().ppu.timing();
+        while this.console.bus().ppu.scanline == timing.scanline {
+            this.console.step();
+        }
+        if this.console.bus().ppu.frame != timing.frame {
+            return std::task::Poll::Ready(Some((this.each_frame)(this.console)));
+        }
+        cx.waker().wake_by_ref();
+        std::task::Poll::Pending
+    }
+}
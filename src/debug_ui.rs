@@ -0,0 +1,401 @@
+use egui::{Color32, Event, Key, Pos2, Rect, Sense, Stroke, Ui, Vec2, vec2};
+use log::warn;
+
+use crate::Cpu;
+use crate::apu::{AudioStats, FILL_HISTORY};
+use crate::config::{Action, InputBindings, InputDevice};
+use crate::console::{WATCH_HISTORY, Watch};
+use crate::disasm::instruction_at;
+use crate::events::{BusEvent, BusEventKind};
+use crate::heatmap::{HeatmapMemory, MemoryHeatmap};
+use crate::ppu::DOTS_PER_SCANLINE;
+
+fn event_color(kind: &BusEventKind) -> Color32 {
+    match kind {
+        BusEventKind::PpuRegisterWrite { .. } => Color32::from_rgb(0x40, 0xa0, 0xff),
+        BusEventKind::MapperWrite { .. } => Color32::from_rgb(0xff, 0xa0, 0x20),
+        BusEventKind::Nmi => Color32::from_rgb(0xff, 0x40, 0x40),
+        BusEventKind::Irq => Color32::from_rgb(0xff, 0x40, 0xff),
+        BusEventKind::Sprite0Hit => Color32::from_rgb(0x40, 0xff, 0x40),
+    }
+}
+
+fn describe(event: &BusEvent) -> String {
+    let what = match event.kind {
+        BusEventKind::PpuRegisterWrite { addr, val } => format!("${addr:04X} = ${val:02X}"),
+        BusEventKind::MapperWrite { addr, val } => format!("Mapper ${addr:04X} = ${val:02X}"),
+        BusEventKind::Nmi => String::from("NMI"),
+        BusEventKind::Irq => String::from("IRQ"),
+        BusEventKind::Sprite0Hit => String::from("Sprite 0 hit"),
+    };
+    format!("{what}\nScanline {}, dot {}", event.scanline, event.dot)
+}
+
+/// Plot a frame's events on a dot by scanline grid, one pixel per dot.
+/// Hovering an event shows what it was.
+pub fn event_viewer(ui: &mut Ui, events: &[BusEvent], scanlines: u16) {
+    let size = vec2(DOTS_PER_SCANLINE as f32, scanlines as f32);
+    let (response, painter) = ui.allocate_painter(size, Sense::hover());
+    let origin = response.rect.min;
+    painter.rect_filled(response.rect, 0.0, Color32::from_gray(0x10));
+    // Visible area, vblank below it
+    painter.rect_filled(
+        Rect::from_min_size(origin + vec2(1.0, 0.0), vec2(256.0, 240.0)),
+        0.0,
+        Color32::from_gray(0x30),
+    );
+    for scanline in (0..scanlines).step_by(20) {
+        let y = origin.y + scanline as f32;
+        painter.hline(
+            response.rect.x_range(),
+            y,
+            Stroke::new(1.0, Color32::from_gray(0x20)),
+        );
+    }
+
+    let point = |event: &BusEvent| -> Pos2 {
+        origin + vec2(event.dot as f32 + 0.5, event.scanline as f32 + 0.5)
+    };
+    for event in events {
+        painter.rect_filled(
+            Rect::from_center_size(point(event), Vec2::splat(3.0)),
+            0.0,
+            event_color(&event.kind),
+        );
+    }
+
+    if let Some(hover) = response.hover_pos() {
+        let nearest = events
+            .iter()
+            .min_by_key(|event| (point(event) - hover).length_sq() as u32)
+            .filter(|event| (point(event) - hover).length() < 4.0);
+        if let Some(event) = nearest {
+            response.on_hover_text_at_pointer(describe(event));
+        }
+    }
+    ui.horizontal(|ui| {
+        for (kind, name) in [
+            (
+                BusEventKind::PpuRegisterWrite { addr: 0, val: 0 },
+                "PPU write",
+            ),
+            (
+                BusEventKind::MapperWrite { addr: 0, val: 0 },
+                "Mapper write",
+            ),
+            (BusEventKind::Nmi, "NMI"),
+            (BusEventKind::Irq, "IRQ"),
+            (BusEventKind::Sprite0Hit, "Sprite 0 hit"),
+        ] {
+            let (rect, _) = ui.allocate_exact_size(Vec2::splat(8.0), Sense::hover());
+            ui.painter().rect_filled(rect, 0.0, event_color(&kind));
+            ui.label(name);
+        }
+    });
+}
+
+/// Columns of the memory heatmap, 64 bytes or blocks per row
+const HEATMAP_COLUMNS: usize = 64;
+/// Most cells the memory heatmap shows, larger memories get bigger blocks
+const HEATMAP_CELLS: usize = 64 * 64;
+
+/// Reads of `memory` in green and writes in red, brighter the more often,
+/// on a grid of cells each covering a block of bytes. Hovering a cell shows
+/// its addresses and counts.
+pub fn memory_heatmap(ui: &mut Ui, heatmap: &MemoryHeatmap, memory: HeatmapMemory) {
+    let counts = heatmap.counts(memory);
+    let block = counts.len().div_ceil(HEATMAP_CELLS).next_power_of_two();
+    let sums: Vec<_> = (0..counts.len())
+        .step_by(block)
+        .map(|start| counts.sum(start..(start + block).min(counts.len())))
+        .collect();
+    let rows = sums.len().div_ceil(HEATMAP_COLUMNS).max(1);
+    let cell = 4.0;
+    let size = vec2(HEATMAP_COLUMNS as f32, rows as f32) * cell;
+    let (response, painter) = ui.allocate_painter(size, Sense::hover());
+    let origin = response.rect.min;
+    painter.rect_filled(response.rect, 0.0, Color32::from_gray(0x10));
+
+    // Log scale so rarely touched bytes still show next to hot loops
+    let max = sums.iter().map(|&(reads, writes)| reads.max(writes)).max();
+    let scale = (max.unwrap_or(0) as f32).ln_1p().max(1.0);
+    let level = |count: u64| ((count as f32).ln_1p() / scale * 255.0) as u8;
+    for (i, &(reads, writes)) in sums.iter().enumerate() {
+        if reads == 0 && writes == 0 {
+            continue;
+        }
+        let pos = origin + vec2((i % HEATMAP_COLUMNS) as f32, (i / HEATMAP_COLUMNS) as f32) * cell;
+        painter.rect_filled(
+            Rect::from_min_size(pos, Vec2::splat(cell)),
+            0.0,
+            Color32::from_rgb(level(writes), level(reads), 0x20),
+        );
+    }
+
+    if let Some(hover) = response.hover_pos() {
+        let column = ((hover.x - origin.x) / cell) as usize;
+        let row = ((hover.y - origin.y) / cell) as usize;
+        let i = row * HEATMAP_COLUMNS + column.min(HEATMAP_COLUMNS - 1);
+        if let Some(&(reads, writes)) = sums.get(i) {
+            let start = i * block;
+            let end = (start + block).min(counts.len()) - 1;
+            let (name, width) = match memory {
+                HeatmapMemory::Ram => ("RAM", 4),
+                HeatmapMemory::PrgRom => ("PRG ROM", 5),
+            };
+            let range = if start == end {
+                format!("${start:0width$X}")
+            } else {
+                format!("${start:0width$X}-${end:0width$X}")
+            };
+            response.on_hover_text_at_pointer(format!(
+                "{name} {range}\n{reads} reads, {writes} writes"
+            ));
+        }
+    }
+}
+
+/// `rows` instructions from the PC on, with their labels (see
+/// [`crate::Bus::label`]) on lines of their own. The instruction at the PC
+/// is highlighted.
+pub fn disassembly(ui: &mut Ui, cpu: &Cpu, rows: usize) {
+    let mut addr = cpu.pc;
+    for _ in 0..rows {
+        if let Some(name) = cpu.memory.label(addr) {
+            ui.monospace(format!("{name}:"));
+        }
+        let (text, len) = instruction_at(&cpu.memory, addr)
+            .unwrap_or_else(|| (format!(".byte ${:02X}", cpu.memory.peek(addr)), 1));
+        let line = egui::RichText::new(format!("  {addr:04X}  {text}")).monospace();
+        if addr == cpu.pc {
+            ui.label(line.background_color(Color32::from_rgb(0x40, 0x40, 0x80)));
+        } else {
+            ui.label(line);
+        }
+        addr = addr.wrapping_add(len);
+    }
+}
+
+/// Current value of every watch with a graph of its history
+pub fn watch_panel(ui: &mut Ui, watches: &[Watch]) {
+    egui::Grid::new("watches").striped(true).show(ui, |ui| {
+        for watch in watches {
+            ui.monospace(&watch.source);
+            match watch.value() {
+                Some(val) => ui.monospace(format!("${val:04X} ({val})")),
+                None => ui.monospace("-"),
+            };
+            history_graph(ui, watch);
+            ui.end_row();
+        }
+    });
+}
+
+fn history_graph(ui: &mut Ui, watch: &Watch) {
+    let size = vec2(WATCH_HISTORY as f32 / 4.0, 24.0);
+    let (rect, _) = ui.allocate_exact_size(size, Sense::hover());
+    let painter = ui.painter_at(rect);
+    painter.rect_filled(rect, 0.0, Color32::from_gray(0x10));
+    let (Some(&min), Some(&max)) = (watch.history.iter().min(), watch.history.iter().max()) else {
+        return;
+    };
+    let range = (max - min).max(1) as f32;
+    // Newest value on the right edge
+    let start = WATCH_HISTORY - watch.history.len();
+    let points = watch
+        .history
+        .iter()
+        .enumerate()
+        .map(|(i, &val)| {
+            let x = rect.left() + (start + i) as f32 / WATCH_HISTORY as f32 * rect.width();
+            let y = rect.bottom() - (val - min) as f32 / range * (rect.height() - 2.0) - 1.0;
+            Pos2::new(x, y)
+        })
+        .collect();
+    painter.add(egui::Shape::line(
+        points,
+        Stroke::new(1.0, Color32::from_rgb(0x40, 0xff, 0x40)),
+    ));
+}
+
+/// Audio latency, underruns, overruns and drift, with a graph of how full
+/// the device's buffer was. Underruns are likely when the graph touches the
+/// bottom, so a bigger buffer is needed.
+pub fn audio_stats_panel(ui: &mut Ui, stats: &AudioStats) {
+    egui::Grid::new("audio_stats").show(ui, |ui| {
+        ui.label("Latency");
+        ui.monospace(format!("{:.1} ms", stats.latency().as_secs_f64() * 1000.0));
+        ui.end_row();
+        ui.label("Underruns");
+        ui.monospace(stats.underruns().to_string());
+        ui.end_row();
+        ui.label("Overruns");
+        ui.monospace(stats.overruns().to_string());
+        ui.end_row();
+        ui.label("Drift");
+        match stats.drift_ppm() {
+            Some(drift) => ui.monospace(format!("{drift:+.0} ppm")),
+            None => ui.monospace("-"),
+        };
+        ui.end_row();
+    });
+
+    let size = vec2(FILL_HISTORY as f32 / 2.0, 48.0);
+    let (rect, _) = ui.allocate_exact_size(size, Sense::hover());
+    let painter = ui.painter_at(rect);
+    painter.rect_filled(rect, 0.0, Color32::from_gray(0x10));
+    let max = stats.fill_history().max().unwrap_or(0).max(1) as f32;
+    // Newest on the right edge, the bottom is empty
+    let start = FILL_HISTORY - stats.fill_history().len();
+    let points = stats
+        .fill_history()
+        .enumerate()
+        .map(|(i, fill)| {
+            let x = rect.left() + (start + i) as f32 / FILL_HISTORY as f32 * rect.width();
+            let y = rect.bottom() - fill as f32 / max * (rect.height() - 2.0) - 1.0;
+            Pos2::new(x, y)
+        })
+        .collect();
+    painter.add(egui::Shape::line(
+        points,
+        Stroke::new(1.0, Color32::from_rgb(0x40, 0xa0, 0xff)),
+    ));
+}
+
+/// The binding [`input_bindings`] is waiting to get a key or button for
+#[derive(Default)]
+pub struct BindingCapture {
+    waiting: Option<(InputDevice, Action)>,
+    /// Gamepad button pressed since the last frame
+    pressed: Option<String>,
+}
+
+impl BindingCapture {
+    /// Whether a binding is waiting for input, which the frontend shouldn't
+    /// act on meanwhile
+    pub fn waiting(&self) -> bool {
+        self.waiting.is_some()
+    }
+
+    /// Wait for the input to bind to `action`, like clicking its binding
+    pub fn start(&mut self, device: InputDevice, action: Action) {
+        self.waiting = Some((device, action));
+        self.pressed = None;
+    }
+
+    /// Pass on a gamepad button press, egui doesn't see gamepads. Buttons
+    /// are named like gilrs' `Button` variants, `format!("{button:?}")`.
+    pub fn gamepad_pressed(&mut self, button: &str) {
+        if let Some((InputDevice::Gamepad, _)) = self.waiting {
+            self.pressed = Some(button.to_string());
+        }
+    }
+
+    /// The input for the binding being captured, if it came this frame
+    fn take_input(&mut self, ui: &Ui, device: InputDevice) -> Option<String> {
+        match device {
+            InputDevice::Gamepad => self.pressed.take(),
+            // Games care where keys are, not what the layout calls them
+            InputDevice::Keyboard => ui.input(|input| {
+                input.events.iter().find_map(|event| match event {
+                    Event::Key {
+                        key,
+                        physical_key,
+                        pressed: true,
+                        repeat: false,
+                        ..
+                    } if *key != Key::Escape => {
+                        Some(physical_key.unwrap_or(*key).name().to_string())
+                    }
+                    _ => None,
+                })
+            }),
+        }
+    }
+}
+
+fn action_label(action: Action) -> &'static str {
+    match action {
+        Action::Up => "Up",
+        Action::Down => "Down",
+        Action::Left => "Left",
+        Action::Right => "Right",
+        Action::Select => "Select",
+        Action::Start => "Start",
+        Action::B => "B",
+        Action::A => "A",
+        Action::SaveState => "Save state",
+        Action::LoadState => "Load state",
+        Action::Screenshot => "Screenshot",
+        Action::ToggleFps => "Show frame rate",
+        Action::FlipDisk => "Flip disk",
+        Action::EjectDisk => "Eject disk",
+        Action::Quit => "Quit",
+        Action::Pause => "Pause",
+        Action::FrameAdvance => "Frame advance",
+        Action::Rewind => "Rewind",
+        Action::FastForward => "Fast forward",
+        Action::Fullscreen => "Fullscreen",
+        Action::Undo => "Undo",
+        Action::RecordMacro => "Record macro",
+        Action::PlayMacro => "Play macro",
+    }
+}
+
+/// Controller 1's buttons and the hotkeys with their key and gamepad
+/// button. Clicking a binding waits for the next key or button to bind to
+/// it, Escape cancels, right clicking clears it. Changes take effect right
+/// away and are saved to the config. Returns whether anything changed.
+pub fn input_bindings(
+    ui: &mut Ui,
+    bindings: &mut InputBindings,
+    capture: &mut BindingCapture,
+) -> bool {
+    let mut changed = false;
+    if let Some((device, action)) = capture.waiting {
+        if ui.input(|input| input.key_pressed(Key::Escape)) {
+            capture.waiting = None;
+        } else if let Some(input) = capture.take_input(ui, device) {
+            bindings.bind(device, action, &input);
+            capture.waiting = None;
+            changed = true;
+        }
+    }
+    egui::Grid::new("input bindings")
+        .striped(true)
+        .show(ui, |ui| {
+            ui.strong("");
+            ui.strong("Key");
+            ui.strong("Gamepad");
+            ui.end_row();
+            for action in Action::ALL {
+                ui.label(action_label(action));
+                for device in [InputDevice::Keyboard, InputDevice::Gamepad] {
+                    let text = match (capture.waiting, device) {
+                        (Some(waiting), InputDevice::Keyboard) if waiting == (device, action) => {
+                            "Press a key..."
+                        }
+                        (Some(waiting), _) if waiting == (device, action) => "Press a button...",
+                        _ => bindings.get(device, action).unwrap_or("-"),
+                    };
+                    let button = ui.button(text);
+                    if button.clicked() {
+                        capture.start(device, action);
+                    } else if button.secondary_clicked() {
+                        bindings.unbind(device, action);
+                        changed = true;
+                    }
+                }
+                ui.end_row();
+            }
+        });
+    if ui.button("Reset to defaults").clicked() {
+        bindings.reset();
+        capture.waiting = None;
+        changed = true;
+    }
+    if changed && let Err(err) = bindings.save() {
+        warn!("Couldn't save the input bindings: {err}");
+    }
+    changed
+}
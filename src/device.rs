@@ -0,0 +1,69 @@
+//! Homemade devices mapped into the CPU's address space, for programs that
+//! want hardware no console or cartridge has, like the random numbers the
+//! snake example reads from $FE.
+//!
+//! A device answers at its addresses in front of whatever is normally
+//! there, RAM included, see [`crate::Bus::devices`].
+
+use std::any::Any;
+
+use crate::savestate::{StateStream, Stateful, stream};
+
+/// Something mapped over part of the CPU's address space
+pub trait BusDevice: Stateful + Any + Send {
+    /// Whether the device answers at `addr`
+    fn maps(&self, addr: u16) -> bool;
+    /// The value at `addr`, without side effects
+    fn peek(&self, addr: u16) -> u8;
+    fn read(&mut self, addr: u16) -> u8 {
+        self.peek(addr)
+    }
+    fn write(&mut self, _addr: u16, _val: u8) {}
+}
+
+/// A new random number each time its address is read, from an xorshift
+/// generator. The same seed gives the same numbers.
+pub struct RandomDevice {
+    addr: u16,
+    state: u64,
+}
+
+impl Stateful for RandomDevice {
+    fn stream(&mut self, s: &mut StateStream) {
+        stream!(s, self.state);
+    }
+}
+
+impl RandomDevice {
+    pub fn new(addr: u16, seed: u64) -> Self {
+        RandomDevice {
+            addr,
+            // Xorshift never leaves 0
+            state: seed.max(1),
+        }
+    }
+
+    fn next(&self) -> u64 {
+        let mut state = self.state;
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+        state
+    }
+}
+
+impl BusDevice for RandomDevice {
+    fn maps(&self, addr: u16) -> bool {
+        addr == self.addr
+    }
+
+    /// The number the next read returns
+    fn peek(&self, _addr: u16) -> u8 {
+        self.next() as u8
+    }
+
+    fn read(&mut self, _addr: u16) -> u8 {
+        self.state = self.next();
+        self.state as u8
+    }
+}
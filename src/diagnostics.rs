@@ -0,0 +1,265 @@
+//! Warnings about a game doing things that are almost always bugs, for
+//! homebrew developers and ROM hackers: the stack wrapping around, running
+//! bytes a code/data log says are data, reading RAM nothing has written and
+//! writing where the board has nothing to write to. JSRs and interrupts are
+//! tracked as a call stack, so an RTS or RTI going somewhere other than where
+//! its call came from (usually after the stack got out of balance) is caught
+//! before it runs.
+//!
+//! The emulator carries on like hardware would, each problem is only
+//! reported with the PC and the instructions leading up to it. Every problem
+//! is reported once per PC so a loop doesn't flood the reports. Returns can
+//! also stop the CPU, see [`Diagnostics::break_on_return_mismatch`].
+
+use std::collections::{HashSet, VecDeque};
+use std::fmt;
+
+/// Instructions kept for each report
+pub const TRACE_LEN: usize = 16;
+/// Calls kept on the call stack, the oldest are dropped past this for games
+/// that never return from some of their calls
+pub const CALL_STACK_LEN: usize = 64;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum DiagnosticKind {
+    /// A push with the stack pointer at $00, which wraps to $01FF
+    StackOverflow,
+    /// A pull with the stack pointer at $FF, which wraps to $0100
+    StackUnderflow,
+    /// Ran the byte at `offset` into PRG ROM, which the code/data log only
+    /// saw read as data
+    DataExecuted { offset: usize },
+    /// A read of RAM ($0000-$07FF) nothing has written since power on
+    UninitializedRead { addr: u16 },
+    /// A write to cartridge space the board ignores
+    RomWrite { addr: u16, val: u8 },
+    /// An RTS or RTI about to go to `found` rather than back to the `expected`
+    /// instruction after the innermost call. Jump tables that push an
+    /// address and RTS to it are caught too, there's no telling them apart
+    /// from a stray push.
+    ReturnMismatch { expected: u16, found: u16 },
+}
+
+/// A JSR or interrupt that hasn't returned yet
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Call {
+    /// The JSR, or the instruction the interrupt came before
+    pub from: u16,
+    /// The subroutine or interrupt handler
+    pub to: u16,
+    /// Where the return goes
+    pub return_addr: u16,
+    /// The stack pointer once the call pushed its return address, and status
+    /// for interrupts
+    pub sp: u8,
+    /// Returned from with RTI rather than RTS
+    pub interrupt: bool,
+}
+
+/// A problem and where it happened
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub kind: DiagnosticKind,
+    /// The instruction that caused it
+    pub pc: u16,
+    /// The instructions before it, oldest first and ending with it, as
+    /// [`crate::trace::trace`] prints them
+    pub trace: Vec<String>,
+}
+
+/// Checks made on every instruction and memory access. Off by default since
+/// it traces every instruction, like [`crate::events::EventLog`] it is
+/// enabled by setting `enabled`.
+pub struct Diagnostics {
+    pub enabled: bool,
+    /// Make [`crate::Cpu::try_step`] stop before a mismatched return with
+    /// [`crate::error::CpuError::DebugBreak`]. Stepping again runs it.
+    pub break_on_return_mismatch: bool,
+    /// Code/data log flags for each byte of PRG ROM, see [`Diagnostics::set_cdl`]
+    cdl: Vec<u8>,
+    /// Which bytes of RAM have been written
+    ram_written: Vec<bool>,
+    /// The instruction being run
+    pc: u16,
+    trace: VecDeque<String>,
+    /// Innermost last
+    calls: VecDeque<Call>,
+    /// Problems already reported with the PC they happened at
+    seen: HashSet<(DiagnosticKind, u16)>,
+    reports: Vec<Diagnostic>,
+}
+
+/// Code/data log flag for bytes run as opcodes or operands
+pub(crate) const CDL_CODE: u8 = 0x01;
+/// Code/data log flag for bytes read as data
+pub(crate) const CDL_DATA: u8 = 0x02;
+
+impl Default for Diagnostics {
+    fn default() -> Self {
+        Diagnostics {
+            enabled: false,
+            break_on_return_mismatch: false,
+            cdl: Vec::new(),
+            ram_written: vec![false; 0x800],
+            pc: 0,
+            trace: VecDeque::with_capacity(TRACE_LEN),
+            calls: VecDeque::with_capacity(CALL_STACK_LEN),
+            seen: HashSet::new(),
+            reports: Vec::new(),
+        }
+    }
+}
+
+impl Diagnostics {
+    /// Check execution against a code/data log in the format of FCEUX's .cdl
+    /// files: a byte of flags for each byte of PRG ROM, bit 0 for code and
+    /// bit 1 for data. CHR ROM flags after them are ignored. Bytes the log
+    /// never saw aren't checked, the game may just not have run them yet.
+    pub fn set_cdl(&mut self, cdl: Vec<u8>) {
+        self.cdl = cdl;
+    }
+
+    /// Treat all of RAM as written, for when the game didn't start from
+    /// power on with diagnostics enabled, like after loading a savestate
+    pub fn assume_ram_initialized(&mut self) {
+        self.ram_written.fill(true);
+    }
+
+    /// Calls that haven't returned, innermost last. Only calls made while
+    /// enabled are known.
+    pub fn call_stack(&self) -> impl Iterator<Item = &Call> {
+        self.calls.iter()
+    }
+
+    /// Forget every call, for when the game didn't get where it is with
+    /// diagnostics enabled, like after loading a savestate
+    pub fn clear_call_stack(&mut self) {
+        self.calls.clear();
+    }
+
+    /// Problems found so far
+    pub fn reports(&self) -> &[Diagnostic] {
+        &self.reports
+    }
+
+    /// Take the problems found since the last call
+    pub fn take_reports(&mut self) -> Vec<Diagnostic> {
+        std::mem::take(&mut self.reports)
+    }
+
+    /// The CPU is about to run the instruction at `pc`, traced as `line`
+    pub(crate) fn begin_instruction(&mut self, pc: u16, line: String) {
+        self.pc = pc;
+        if self.trace.len() == TRACE_LEN {
+            self.trace.pop_front();
+        }
+        self.trace.push_back(line);
+    }
+
+    pub(crate) fn call(&mut self, call: Call) {
+        if !self.enabled {
+            return;
+        }
+        if self.calls.len() == CALL_STACK_LEN {
+            self.calls.pop_front();
+        }
+        self.calls.push_back(call);
+    }
+
+    /// A return is about to go to `addr`, with the stack pointer at `sp`
+    /// before it pulls anything. Returns whether the CPU should stop.
+    pub(crate) fn return_to(&mut self, addr: u16, sp: u8, interrupt: bool) -> bool {
+        // Calls the stack was pulled past returned some other way, like
+        // through a reset of the stack pointer
+        while self.calls.back().is_some_and(|call| call.sp < sp) {
+            self.calls.pop_back();
+        }
+        // Returns from calls made before tracking started can't be checked
+        let Some(&call) = self.calls.back() else {
+            return false;
+        };
+        if call.sp == sp {
+            self.calls.pop_back();
+        }
+        if call.sp == sp && call.return_addr == addr && call.interrupt == interrupt {
+            return false;
+        }
+        self.report(DiagnosticKind::ReturnMismatch {
+            expected: call.return_addr,
+            found: addr,
+        });
+        self.break_on_return_mismatch
+    }
+
+    /// The opcode at `offset` into PRG ROM is about to run
+    pub(crate) fn execute(&mut self, offset: usize) {
+        let flags = self.cdl.get(offset).copied().unwrap_or(0);
+        if flags & (CDL_CODE | CDL_DATA) == CDL_DATA {
+            self.report(DiagnosticKind::DataExecuted { offset });
+        }
+    }
+
+    /// A read or write of RAM at `addr` with the mirrors folded in
+    pub(crate) fn ram_access(&mut self, addr: u16, write: bool) {
+        if !self.enabled {
+            return;
+        }
+        let written = &mut self.ram_written[addr as usize];
+        if write {
+            *written = true;
+        } else if !*written {
+            self.report(DiagnosticKind::UninitializedRead { addr });
+        }
+    }
+
+    pub(crate) fn report(&mut self, kind: DiagnosticKind) {
+        if self.enabled && self.seen.insert((kind, self.pc)) {
+            self.reports.push(Diagnostic {
+                kind,
+                pc: self.pc,
+                trace: self.trace.iter().cloned().collect(),
+            });
+        }
+    }
+}
+
+impl fmt::Display for DiagnosticKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DiagnosticKind::StackOverflow => {
+                write!(f, "stack overflow, wrapped from $0100 to $01FF")
+            }
+            DiagnosticKind::StackUnderflow => {
+                write!(f, "stack underflow, wrapped from $01FF to $0100")
+            }
+            DiagnosticKind::DataExecuted { offset } => {
+                write!(
+                    f,
+                    "ran PRG ROM ${offset:05X}, which the code/data log has as data"
+                )
+            }
+            DiagnosticKind::UninitializedRead { addr } => {
+                write!(f, "read ${addr:04X} before anything wrote it")
+            }
+            DiagnosticKind::RomWrite { addr, val } => {
+                write!(
+                    f,
+                    "wrote ${val:02X} to ${addr:04X}, which the board ignores"
+                )
+            }
+            DiagnosticKind::ReturnMismatch { expected, found } => {
+                write!(f, "returning to ${found:04X} instead of ${expected:04X}")
+            }
+        }
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} at ${:04X}", self.kind, self.pc)?;
+        for line in &self.trace {
+            write!(f, "\n    {line}")?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,428 @@
+//! A ca65 source file of a game's PRG ROM, for reading and changing it.
+//!
+//! Bytes a code/data log (see [`crate::diagnostics::Diagnostics::set_cdl`])
+//! has as code are disassembled and everything else is written as `.byte`.
+//! Without a log every byte that decodes is taken for code. Jump, branch and
+//! table targets are labelled `Lxxxx` unless the [`Labels`] name them, and
+//! so are the NMI, reset and IRQ vectors at the end of the last bank.
+//!
+//! Each bank goes in its own `PRGn` segment at the address it most likely
+//! runs from: the last bank at the top of memory and the rest at $8000.
+//! Operands that don't land on a label are written as numbers, so the file
+//! assembles back to the same bytes even where the guess is wrong.
+//!
+//! [`instruction_at`] disassembles single instructions from memory the same
+//! way, for debuggers.
+
+use std::collections::{BTreeSet, HashMap};
+use std::fmt::Write;
+
+use crate::Bus;
+use crate::diagnostics::CDL_CODE;
+use crate::fetch_decode::{AddrMode, Opcode, decode};
+use crate::labels::Labels;
+use crate::rom::Rom;
+
+/// Bytes on each `.byte` line
+const BYTES_PER_LINE: usize = 8;
+
+/// What starts at a byte of a bank
+#[derive(Clone, Copy)]
+enum Item {
+    Code(Opcode, AddrMode, usize),
+    Data(DataKind),
+    /// The NMI, reset and IRQ vectors at $FFFA
+    Vectors,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum DataKind {
+    /// Read as data, or not an instruction
+    Data,
+    /// Never accessed while the code/data log was recorded
+    Unlogged,
+}
+
+struct Bank<'a> {
+    /// Offset into PRG ROM
+    start: usize,
+    base: u16,
+    bytes: &'a [u8],
+    /// One for each byte, `None` inside instructions
+    items: Vec<Option<Item>>,
+    /// No other bank guessed at the same address, so other banks can jump here
+    unique: bool,
+    /// Labels by offset into the bank
+    names: HashMap<usize, String>,
+}
+
+impl Bank<'_> {
+    fn offset(&self, addr: u16) -> Option<usize> {
+        let offset = addr.checked_sub(self.base)? as usize;
+        (offset < self.bytes.len()).then_some(offset)
+    }
+}
+
+/// ca65 source for `rom`'s header and PRG ROM. `cdl` is in the format of
+/// FCEUX's .cdl files.
+pub fn disassemble(rom: &Rom, cdl: Option<&[u8]>, labels: &Labels) -> String {
+    let size = bank_size(rom);
+    let count = rom.prg_rom.len().div_ceil(size);
+    let mut banks: Vec<Bank> = rom
+        .prg_rom
+        .chunks(size)
+        .enumerate()
+        .map(|(i, bytes)| Bank {
+            start: i * size,
+            base: if i + 1 == count {
+                (0x10000 - bytes.len()) as u16
+            } else {
+                0x8000
+            },
+            bytes,
+            items: items(
+                bytes,
+                cdl.map(|cdl| &cdl[(i * size).min(cdl.len())..]),
+                i + 1 == count,
+            ),
+            unique: false,
+            names: HashMap::new(),
+        })
+        .collect();
+    for i in 0..banks.len() {
+        let base = banks[i].base;
+        banks[i].unique = banks.iter().filter(|bank| bank.base == base).count() == 1;
+    }
+
+    // Label everything that's jumped to or read from, if a line starts there
+    let mut targets = BTreeSet::new();
+    for (i, bank) in banks.iter().enumerate() {
+        for (offset, item) in bank.items.iter().enumerate() {
+            let addrs = match item {
+                Some(Item::Code(_, mode, _)) => target(bank, offset, *mode).into_iter().collect(),
+                Some(Item::Vectors) => vectors(&bank.bytes[offset..]).to_vec(),
+                _ => Vec::new(),
+            };
+            for addr in addrs {
+                if let Some((j, target)) = find(&banks, i, addr)
+                    && banks[j].items[target].is_some()
+                {
+                    targets.insert((j, target));
+                }
+            }
+        }
+    }
+    for (i, bank) in banks.iter_mut().enumerate() {
+        for (offset, item) in bank.items.iter().enumerate() {
+            if item.is_none() {
+                continue;
+            }
+            let addr = bank.base as usize + offset;
+            let name = match labels.prg_rom(bank.start + offset) {
+                Some(label) if !label.name.is_empty() => label.name.clone(),
+                _ if !targets.contains(&(i, offset)) => continue,
+                _ if bank.unique => format!("L{addr:04X}"),
+                _ => format!("L{i}_{addr:04X}"),
+            };
+            bank.names.insert(offset, name);
+        }
+    }
+
+    let mut out = String::new();
+    writeln!(out, "; iNES header").unwrap();
+    writeln!(out, ".segment \"HEADER\"").unwrap();
+    writeln!(out, "    .byte {}", byte_list(&rom.file()[..16])).unwrap();
+    if !rom.chr_rom.is_empty() {
+        writeln!(
+            out,
+            "; {}K of CHR ROM isn't included",
+            rom.chr_rom.len() / 1024
+        )
+        .unwrap();
+    }
+    let mut constants = labels
+        .cpu()
+        .filter(|(_, label)| !label.name.is_empty())
+        .peekable();
+    if constants.peek().is_some() {
+        writeln!(out).unwrap();
+    }
+    for (addr, label) in constants {
+        let definition = format!("{} = ${addr:04X}", label.name);
+        write_line(&mut out, &definition, &label.comment);
+    }
+    for (i, bank) in banks.iter().enumerate() {
+        let end = bank.base as usize + bank.bytes.len() - 1;
+        writeln!(out).unwrap();
+        writeln!(out, "; PRG ROM bank {i}, ${:04X}-${end:04X}", bank.base).unwrap();
+        writeln!(out, ".segment \"PRG{i}\"").unwrap();
+        writeln!(out, ".org ${:04X}", bank.base).unwrap();
+        write_bank(&mut out, &banks, i, labels);
+    }
+    out
+}
+
+/// How big the board's PRG banks are
+fn bank_size(rom: &Rom) -> usize {
+    let size = match rom.mapper {
+        // MMC3, MMC5, N163 and VRC7 switch 8K at a time
+        4 | 5 | 19 | 85 => 0x2000,
+        // AxROM
+        7 => 0x8000,
+        _ => 0x4000,
+    };
+    size.min(rom.prg_rom.len()).max(1)
+}
+
+/// Where instructions and data start in `bytes`, with `cdl` flags from
+/// the start of the bank. The `last` bank ends with the vectors.
+fn items(bytes: &[u8], cdl: Option<&[u8]>, last: bool) -> Vec<Option<Item>> {
+    let vectors = (last && bytes.len() >= 6).then_some(bytes.len() - 6);
+    let bytes = &bytes[..vectors.unwrap_or(bytes.len())];
+    let flags = |offset: usize| match cdl {
+        Some(cdl) => cdl.get(offset).copied().unwrap_or(0),
+        None => CDL_CODE,
+    };
+    let mut items = vec![None; bytes.len()];
+    let mut offset = 0;
+    while offset < bytes.len() {
+        let instruction = decode(bytes[offset]).filter(|(_, _, info)| {
+            let end = offset + info.size as usize;
+            end <= bytes.len() && (offset..end).all(|i| flags(i) & CDL_CODE != 0)
+        });
+        if let Some((opcode, mode, info)) = instruction {
+            items[offset] = Some(Item::Code(opcode, mode, info.size as usize));
+            offset += info.size as usize;
+        } else {
+            let kind = match flags(offset) {
+                0 => DataKind::Unlogged,
+                _ => DataKind::Data,
+            };
+            items[offset] = Some(Item::Data(kind));
+            offset += 1;
+        }
+    }
+    if vectors.is_some() {
+        items.push(Some(Item::Vectors));
+        items.extend([None; 5]);
+    }
+    items
+}
+
+fn vectors(bytes: &[u8]) -> [u16; 3] {
+    [0, 2, 4].map(|i| u16::from_le_bytes([bytes[i], bytes[i + 1]]))
+}
+
+/// The address an instruction's operand refers to, if it is one
+fn target(bank: &Bank, offset: usize, mode: AddrMode) -> Option<u16> {
+    let operand = &bank.bytes[offset + 1..];
+    let pc = bank.base.wrapping_add(offset as u16);
+    match mode {
+        AddrMode::Relative => Some(pc.wrapping_add(2).wrapping_add(operand[0] as i8 as u16)),
+        AddrMode::Absolute | AddrMode::AbsoluteX | AddrMode::AbsoluteY | AddrMode::Indirect => {
+            Some(u16::from_le_bytes([operand[0], operand[1]]))
+        }
+        _ => None,
+    }
+}
+
+/// The bank and offset `addr` is at, seen from bank `from`
+fn find(banks: &[Bank], from: usize, addr: u16) -> Option<(usize, usize)> {
+    if let Some(offset) = banks[from].offset(addr) {
+        return Some((from, offset));
+    }
+    banks
+        .iter()
+        .enumerate()
+        .filter(|(_, bank)| bank.unique)
+        .find_map(|(i, bank)| Some((i, bank.offset(addr)?)))
+}
+
+/// `addr` by name if it has one, as a number otherwise
+fn name_or_number(banks: &[Bank], from: usize, addr: u16, labels: &Labels) -> String {
+    if let Some((i, offset)) = find(banks, from, addr) {
+        let bank = &banks[i];
+        if let Some(name) = bank.names.get(&offset) {
+            return name.clone();
+        }
+        // Inside a named range that starts in the same bank
+        if let Some((start, label)) = labels.prg_rom_at(bank.start + offset)
+            && let Some(name) = start
+                .checked_sub(bank.start)
+                .and_then(|start| bank.names.get(&start))
+            && *name == label.name
+        {
+            return format!("{name}+{}", bank.start + offset - start);
+        }
+    } else if let Some(name) = labels.cpu_name(addr) {
+        return name;
+    }
+    number(addr)
+}
+
+fn number(addr: u16) -> String {
+    match addr {
+        0x00..=0xff => format!("${addr:02X}"),
+        _ => format!("${addr:04X}"),
+    }
+}
+
+fn write_bank(out: &mut String, banks: &[Bank], i: usize, labels: &Labels) {
+    let bank = &banks[i];
+    let mut offset = 0;
+    while offset < bank.bytes.len() {
+        if let Some(label) = labels.prg_rom(bank.start + offset) {
+            for line in label.comment.lines() {
+                writeln!(out, "; {line}").unwrap();
+            }
+        }
+        if let Some(name) = bank.names.get(&offset) {
+            writeln!(out, "{name}:").unwrap();
+        }
+        let addr = bank.base.wrapping_add(offset as u16);
+        match bank.items[offset] {
+            Some(Item::Code(opcode, mode, len)) => {
+                let bytes = &bank.bytes[offset..offset + len];
+                let text = instruction(banks, i, offset, opcode, mode, labels);
+                write_line(
+                    out,
+                    &format!("    {text}"),
+                    &format!("{addr:04X}  {}", hex(bytes)),
+                );
+                offset += len;
+            }
+            Some(Item::Data(kind)) => {
+                // Up to the next label, or different kind of byte
+                let mut end = offset + 1;
+                while end < bank.bytes.len()
+                    && end - offset < BYTES_PER_LINE
+                    && matches!(bank.items[end], Some(Item::Data(next)) if next == kind)
+                    && !bank.names.contains_key(&end)
+                    && labels.prg_rom(bank.start + end).is_none()
+                {
+                    end += 1;
+                }
+                let what = match kind {
+                    DataKind::Data => "data",
+                    DataKind::Unlogged => "not logged",
+                };
+                let text = format!("    .byte {}", byte_list(&bank.bytes[offset..end]));
+                write_line(out, &text, &format!("{addr:04X}  {what}"));
+                offset = end;
+            }
+            Some(Item::Vectors) => {
+                let names = vectors(&bank.bytes[offset..])
+                    .map(|addr| name_or_number(banks, i, addr, labels));
+                let text = format!("    .word {}", names.join(", "));
+                write_line(out, &text, &format!("{addr:04X}  NMI, reset, IRQ"));
+                offset += 6;
+            }
+            None => unreachable!("lines start on items"),
+        }
+    }
+}
+
+fn instruction(
+    banks: &[Bank],
+    i: usize,
+    offset: usize,
+    opcode: Opcode,
+    mode: AddrMode,
+    labels: &Labels,
+) -> String {
+    let bank = &banks[i];
+    format_instruction(
+        opcode,
+        mode,
+        &bank.bytes[offset + 1..],
+        target(bank, offset, mode),
+        |addr| name_or_number(banks, i, addr, labels),
+    )
+}
+
+/// The instruction at `addr` as the CPU sees it now, named with
+/// [`Bus::label`], and how many bytes it takes. `None` for opcodes the CPU
+/// can't run.
+pub fn instruction_at(bus: &Bus, addr: u16) -> Option<(String, u16)> {
+    let (opcode, mode, info) = decode(bus.peek(addr))?;
+    let operand = [1, 2].map(|i| bus.peek(addr.wrapping_add(i)));
+    let target = operand_addr(bus, addr);
+    let text = format_instruction(opcode, mode, &operand, target, |addr| {
+        bus.label(addr).unwrap_or_else(|| number(addr))
+    });
+    Some((text, info.size))
+}
+
+/// The address in the operand of the instruction at `addr`, before any
+/// indexing or indirection
+pub(crate) fn operand_addr(bus: &Bus, addr: u16) -> Option<u16> {
+    let (_, mode, _) = decode(bus.peek(addr))?;
+    let operand = [1, 2].map(|i| bus.peek(addr.wrapping_add(i)));
+    match mode {
+        AddrMode::Implicit | AddrMode::Accumulator | AddrMode::Immediate => None,
+        AddrMode::Relative => Some(addr.wrapping_add(2).wrapping_add(operand[0] as i8 as u16)),
+        AddrMode::Absolute | AddrMode::AbsoluteX | AddrMode::AbsoluteY | AddrMode::Indirect => {
+            Some(u16::from_le_bytes(operand))
+        }
+        _ => Some(operand[0] as u16),
+    }
+}
+
+/// `opcode` with its `operand` bytes, where `name` gives the name or number
+/// for an address and `target` is the address absolute and branch operands
+/// refer to
+fn format_instruction(
+    opcode: Opcode,
+    mode: AddrMode,
+    operand: &[u8],
+    target: Option<u16>,
+    name: impl Fn(u16) -> String,
+) -> String {
+    let mnemonic = format!("{opcode:?}").to_lowercase();
+    let zero_page = || name(operand[0] as u16);
+    let absolute = || {
+        let addr = target.expect("absolute instructions have a target");
+        // ca65 would pick zero page addressing for these
+        if addr < 0x100 {
+            format!("a:{}", name(addr))
+        } else {
+            name(addr)
+        }
+    };
+    let operand = match mode {
+        AddrMode::Implicit => return mnemonic,
+        AddrMode::Accumulator => String::from("a"),
+        AddrMode::Immediate => format!("#${:02X}", operand[0]),
+        AddrMode::ZeroPage => zero_page(),
+        AddrMode::ZeroPageX => format!("{},x", zero_page()),
+        AddrMode::ZeroPageY => format!("{},y", zero_page()),
+        AddrMode::Relative => name(target.expect("branches have a target")),
+        AddrMode::Absolute => absolute(),
+        AddrMode::AbsoluteX => format!("{},x", absolute()),
+        AddrMode::AbsoluteY => format!("{},y", absolute()),
+        AddrMode::Indirect => format!("({})", absolute()),
+        AddrMode::IndexedIndirect => format!("({},x)", zero_page()),
+        AddrMode::IndirectIndexed => format!("({}),y", zero_page()),
+    };
+    format!("{mnemonic} {operand}")
+}
+
+/// `text` with `comment` lined up after it
+fn write_line(out: &mut String, text: &str, comment: &str) {
+    let comment = comment.replace('\n', " ");
+    if comment.is_empty() {
+        writeln!(out, "{text}").unwrap();
+    } else {
+        writeln!(out, "{text:<32}; {comment}").unwrap();
+    }
+}
+
+fn byte_list(bytes: &[u8]) -> String {
+    let bytes: Vec<_> = bytes.iter().map(|byte| format!("${byte:02X}")).collect();
+    bytes.join(", ")
+}
+
+fn hex(bytes: &[u8]) -> String {
+    let bytes: Vec<_> = bytes.iter().map(|byte| format!("{byte:02X}")).collect();
+    bytes.join(" ")
+}
@@ -0,0 +1,301 @@
+//! Frame-accurate audio and video dumps, for encoding TAS movies.
+//!
+//! Every emulated frame becomes a frame of a Y4M video, and the audio goes to
+//! a WAV file with exactly as many samples as the frames last at the
+//! console's frame rate. The two line up in a video editor no matter how fast
+//! the emulator ran while dumping.
+//!
+//! [`StemDump`] does the same for the sound channels, each in its own WAV.
+
+use std::fs::File;
+use std::io::{BufWriter, Seek, SeekFrom, Write};
+use std::path::Path;
+
+use crate::apu::STEM_COUNT;
+use crate::console::Console;
+use crate::rom::Region;
+
+/// Audio rate of dumps, the usual rate for video
+pub const DUMP_SAMPLE_RATE: u32 = 48000;
+
+/// Frames per second as a fraction, from the PPU clock and the average
+/// frame length of 89341.5 dots (NTSC) or 106392 dots (PAL and Dendy, which
+/// share a PPU clock)
+pub fn frame_rate(region: Region) -> (u64, u64) {
+    match region {
+        Region::Ntsc => (39_375_000, 655_171),
+        Region::Pal | Region::Dendy => (10_640_685, 212_784),
+    }
+}
+
+/// Samples the audio needs to have by the end of `frames` frames
+fn samples_by((num, den): (u64, u64), frames: u64) -> u64 {
+    frames * DUMP_SAMPLE_RATE as u64 * den / num
+}
+
+/// Writes `name.y4m` and `name.wav`, see the module docs
+pub struct AvDump {
+    video: BufWriter<File>,
+    audio: BufWriter<File>,
+    width: usize,
+    height: usize,
+    frame_rate: (u64, u64),
+    frames: u64,
+    /// Stereo samples written so far
+    samples: u64,
+    /// Repeated when a frame comes up short
+    last_sample: [f32; 2],
+}
+
+impl AvDump {
+    /// Start dumping `console` with frames of `width` by `height`. Switches
+    /// the console to [`DUMP_SAMPLE_RATE`] and drops audio it already made.
+    pub fn create(
+        path: &Path,
+        console: &mut Console,
+        width: usize,
+        height: usize,
+    ) -> Result<Self, String> {
+        let create = |ext: &str| {
+            let path = path.with_extension(ext);
+            File::create(&path)
+                .map(BufWriter::new)
+                .map_err(|e| format!("{}: {e}", path.display()))
+        };
+        let frame_rate = frame_rate(console.bus().ppu.region);
+        let mut dump = AvDump {
+            video: create("y4m")?,
+            audio: create("wav")?,
+            width,
+            height,
+            frame_rate,
+            frames: 0,
+            samples: 0,
+            last_sample: [0.0; 2],
+        };
+        let (num, den) = frame_rate;
+        let header = format!("YUV4MPEG2 W{width} H{height} F{num}:{den} Ip A1:1 C444\n");
+        dump.video.write_all(header.as_bytes()).map_err(io_error)?;
+        dump.write_wav_header().map_err(io_error)?;
+
+        let bus = console.bus_mut();
+        bus.set_sample_rate(DUMP_SAMPLE_RATE as f64);
+        while bus.read_audio_stereo(&mut [[0.0; 2]; 1024]) > 0 {}
+        Ok(dump)
+    }
+
+    pub fn frames(&self) -> u64 {
+        self.frames
+    }
+
+    pub fn samples(&self) -> u64 {
+        self.samples
+    }
+
+    /// Add a frame the console just ran: `pixels` (0RGB, row by row) and the
+    /// audio made since the last frame. A sample is dropped or repeated now
+    /// and then to keep the audio exactly as long as the video.
+    pub fn frame(&mut self, console: &mut Console, pixels: &[u32]) -> Result<(), String> {
+        if pixels.len() != self.width * self.height {
+            return Err(format!(
+                "frame has {} pixels, the dump is {}x{}",
+                pixels.len(),
+                self.width,
+                self.height
+            ));
+        }
+        self.write_video(pixels).map_err(io_error)?;
+        self.frames += 1;
+
+        let mut audio = Vec::new();
+        let mut chunk = [[0.0; 2]; 1024];
+        loop {
+            let count = console.bus_mut().read_audio_stereo(&mut chunk);
+            if count == 0 {
+                break;
+            }
+            audio.extend_from_slice(&chunk[..count]);
+        }
+        let due = samples_by(self.frame_rate, self.frames) - self.samples;
+        audio.resize(
+            due as usize,
+            audio.last().copied().unwrap_or(self.last_sample),
+        );
+        self.write_audio(&audio).map_err(io_error)
+    }
+
+    /// Flush both files and fill in the WAV sizes
+    pub fn finish(mut self) -> Result<(), String> {
+        self.video.flush().map_err(io_error)?;
+        self.audio.seek(SeekFrom::Start(0)).map_err(io_error)?;
+        self.write_wav_header().map_err(io_error)?;
+        self.audio.flush().map_err(io_error)
+    }
+
+    /// BT.601 studio range, one full resolution plane each for Y, Cb and Cr
+    fn write_video(&mut self, pixels: &[u32]) -> std::io::Result<()> {
+        let rgb = |pixel: u32| {
+            let [_, r, g, b] = pixel.to_be_bytes().map(|c| c as i32);
+            (r, g, b)
+        };
+        let planes: [fn(i32, i32, i32) -> i32; 3] = [
+            |r, g, b| ((66 * r + 129 * g + 25 * b + 128) >> 8) + 16,
+            |r, g, b| ((-38 * r - 74 * g + 112 * b + 128) >> 8) + 128,
+            |r, g, b| ((112 * r - 94 * g - 18 * b + 128) >> 8) + 128,
+        ];
+        self.video.write_all(b"FRAME\n")?;
+        for plane in planes {
+            let bytes: Vec<u8> = pixels
+                .iter()
+                .map(|&pixel| {
+                    let (r, g, b) = rgb(pixel);
+                    plane(r, g, b) as u8
+                })
+                .collect();
+            self.video.write_all(&bytes)?;
+        }
+        Ok(())
+    }
+
+    fn write_audio(&mut self, samples: &[[f32; 2]]) -> std::io::Result<()> {
+        for sample in samples {
+            for &side in sample {
+                self.audio.write_all(&pcm(side))?;
+            }
+        }
+        if let Some(&last) = samples.last() {
+            self.last_sample = last;
+        }
+        self.samples += samples.len() as u64;
+        Ok(())
+    }
+
+    fn write_wav_header(&mut self) -> std::io::Result<()> {
+        write_wav_header(&mut self.audio, 2, self.samples)
+    }
+}
+
+fn io_error(e: std::io::Error) -> String {
+    e.to_string()
+}
+
+/// Writes each channel to its own WAV, `name.pulse1.wav`, `name.pulse2.wav`,
+/// `name.triangle.wav`, `name.noise.wav`, `name.dmc.wav` and one named after
+/// the cartridge's sound chip if it has one, like `name.vrc6.wav`. For
+/// musicians ripping a soundtrack: the stems are unmixed and line up with
+/// each other like the audio of an [`AvDump`] lines up with its video.
+pub struct StemDump {
+    stems: Vec<BufWriter<File>>,
+    frame_rate: (u64, u64),
+    frames: u64,
+    /// Samples written to each stem so far
+    samples: u64,
+    last_sample: [f32; STEM_COUNT],
+}
+
+impl StemDump {
+    /// Start dumping `console`'s channels, see [`crate::Bus::enable_stems`]
+    pub fn create(path: &Path, console: &mut Console) -> Result<Self, String> {
+        let mut dump = StemDump {
+            stems: Vec::new(),
+            frame_rate: frame_rate(console.bus().ppu.region),
+            frames: 0,
+            samples: 0,
+            last_sample: [0.0; STEM_COUNT],
+        };
+        for name in stem_names(console) {
+            let path = path.with_extension(format!("{name}.wav"));
+            let mut file = File::create(&path)
+                .map(BufWriter::new)
+                .map_err(|e| format!("{}: {e}", path.display()))?;
+            write_wav_header(&mut file, 1, 0).map_err(io_error)?;
+            dump.stems.push(file);
+        }
+        console.bus_mut().enable_stems(DUMP_SAMPLE_RATE as f64);
+        Ok(dump)
+    }
+
+    pub fn frames(&self) -> u64 {
+        self.frames
+    }
+
+    pub fn samples(&self) -> u64 {
+        self.samples
+    }
+
+    /// Add the audio of a frame the console just ran, made as long as the
+    /// frame like [`AvDump::frame`] does
+    pub fn frame(&mut self, console: &mut Console) -> Result<(), String> {
+        self.frames += 1;
+        let mut audio = Vec::new();
+        let mut chunk = [[0.0; STEM_COUNT]; 1024];
+        loop {
+            let count = console.bus_mut().read_stems(&mut chunk);
+            if count == 0 {
+                break;
+            }
+            audio.extend_from_slice(&chunk[..count]);
+        }
+        let due = samples_by(self.frame_rate, self.frames) - self.samples;
+        audio.resize(
+            due as usize,
+            audio.last().copied().unwrap_or(self.last_sample),
+        );
+        for (i, stem) in self.stems.iter_mut().enumerate() {
+            for sample in &audio {
+                stem.write_all(&pcm(sample[i])).map_err(io_error)?;
+            }
+        }
+        if let Some(&last) = audio.last() {
+            self.last_sample = last;
+        }
+        self.samples += audio.len() as u64;
+        Ok(())
+    }
+
+    /// Fill in the WAV sizes and stop keeping the channels apart
+    pub fn finish(self, console: &mut Console) -> Result<(), String> {
+        console.bus_mut().disable_stems();
+        for mut stem in self.stems {
+            stem.seek(SeekFrom::Start(0)).map_err(io_error)?;
+            write_wav_header(&mut stem, 1, self.samples).map_err(io_error)?;
+            stem.flush().map_err(io_error)?;
+        }
+        Ok(())
+    }
+}
+
+/// What the stems of `console` are called, in the order of
+/// [`crate::Bus::read_stems`]. The last is only there for games with a sound chip.
+pub fn stem_names(console: &Console) -> Vec<String> {
+    let mut names: Vec<String> = ["pulse1", "pulse2", "triangle", "noise", "dmc"]
+        .map(String::from)
+        .into();
+    if let Some((chip, _)) = console.bus().mapper.expansion_audio() {
+        names.push(format!("{chip:?}").to_lowercase());
+    }
+    names
+}
+
+/// 16 bit PCM, the sizes are right once `samples` is final
+fn write_wav_header(w: &mut impl Write, channels: u16, samples: u64) -> std::io::Result<()> {
+    let block_align = channels * 2;
+    let data_len = (samples * block_align as u64) as u32;
+    w.write_all(b"RIFF")?;
+    w.write_all(&(36 + data_len).to_le_bytes())?;
+    w.write_all(b"WAVEfmt ")?;
+    w.write_all(&16u32.to_le_bytes())?;
+    // PCM
+    w.write_all(&1u16.to_le_bytes())?;
+    w.write_all(&channels.to_le_bytes())?;
+    w.write_all(&DUMP_SAMPLE_RATE.to_le_bytes())?;
+    w.write_all(&(DUMP_SAMPLE_RATE * block_align as u32).to_le_bytes())?;
+    w.write_all(&block_align.to_le_bytes())?;
+    w.write_all(&16u16.to_le_bytes())?;
+    w.write_all(b"data")?;
+    w.write_all(&data_len.to_le_bytes())
+}
+
+fn pcm(sample: f32) -> [u8; 2] {
+    ((sample.clamp(-1.0, 1.0) * i16::MAX as f32) as i16).to_le_bytes()
+}
@@ -0,0 +1,268 @@
+//! Errors an embedder can handle instead of the emulator aborting.
+//!
+//! Each part of the crate fails with its own error type: [`RomError`] for
+//! loading games, [`CpuError`] for code the CPU can't run, [`StateError`] for
+//! savestates, [`MapperError`] for cartridge boards and [`NetplayError`] for
+//! playing over the network. Functions return the
+//! narrowest one that fits, and all of them convert into [`NesError`] with
+//! `?` for code that handles them alike. They also convert into `String` for
+//! the parts of the crate that report errors as messages.
+
+use std::fmt;
+use std::io;
+use std::path::PathBuf;
+
+use crate::savestate::StateVersionError;
+
+/// Any error from the emulator
+#[derive(Debug)]
+pub enum NesError {
+    Rom(RomError),
+    Cpu(CpuError),
+    State(StateError),
+    Mapper(MapperError),
+    Netplay(NetplayError),
+}
+
+/// A game that couldn't be loaded
+#[derive(Debug)]
+pub enum RomError {
+    /// The file couldn't be read
+    Io { path: PathBuf, error: io::Error },
+    /// No iNES header
+    NotInes,
+    /// iNES header bits that are neither iNES 1.0 nor NES 2.0
+    UnsupportedFormat,
+    /// The header promises more data than the file has
+    Truncated { expected: usize, found: usize },
+    /// An IPS or BPS patch that doesn't apply to the game
+    Patch(String),
+    /// A Famicom Disk System image or BIOS that can't be used
+    Disk(String),
+}
+
+/// Code the CPU can't run
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CpuError {
+    /// An unofficial opcode, which the CPU stays on until an interrupt or
+    /// reset moves it
+    InvalidOpcode { opcode: u8, pc: u16 },
+    /// Stopped for the debugger with the instruction at `pc` next, see
+    /// [`crate::breakpoints`] and
+    /// [`crate::diagnostics::Diagnostics::break_on_return_mismatch`].
+    /// Stepping again runs it.
+    DebugBreak { pc: u16 },
+}
+
+/// A savestate that couldn't be loaded
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum StateError {
+    /// No savestate header
+    NotAState,
+    /// Saved by a version this build can't load
+    Version(StateVersionError),
+    /// Saved from another game, by [`crate::rom::Rom::hash`]
+    WrongRom { state: u32, rom: u32 },
+    /// The data ends before everything was loaded
+    Truncated,
+    /// Data left over after everything was loaded
+    TrailingData,
+    /// A component loaded a value it can't have
+    Invalid(String),
+}
+
+/// A cartridge board that can't be emulated
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MapperError {
+    /// The iNES mapper number of a board that isn't emulated
+    Unsupported(u8),
+}
+
+/// A netplay session that can't go on
+#[derive(Debug)]
+pub enum NetplayError {
+    /// Connecting to or talking with the other side failed
+    Io(io::Error),
+    /// The other side closed the connection
+    Closed,
+    /// A message with a tag this build doesn't know
+    UnknownMessage(u8),
+    /// The two consoles' states differ at the start of `frame`
+    Desync { frame: u64 },
+    /// The state to roll back to from `frame` is already gone
+    NoSnapshot { frame: u64 },
+    /// The state to roll back to couldn't be loaded
+    State(StateError),
+}
+
+impl fmt::Display for NesError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            NesError::Rom(err) => err.fmt(f),
+            NesError::Cpu(err) => err.fmt(f),
+            NesError::State(err) => err.fmt(f),
+            NesError::Mapper(err) => err.fmt(f),
+            NesError::Netplay(err) => err.fmt(f),
+        }
+    }
+}
+
+impl fmt::Display for RomError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RomError::Io { path, error } => write!(f, "{}: {error}", path.display()),
+            RomError::NotInes => write!(f, "Expected NES magic number"),
+            RomError::UnsupportedFormat => write!(f, "Only NES1.0 and NES2.0 supported"),
+            RomError::Truncated { expected, found } => {
+                write!(
+                    f,
+                    "ROM is truncated, expected {expected} bytes but found {found}"
+                )
+            }
+            RomError::Patch(err) => write!(f, "patch: {err}"),
+            RomError::Disk(err) => write!(f, "disk image: {err}"),
+        }
+    }
+}
+
+impl fmt::Display for CpuError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CpuError::InvalidOpcode { opcode, pc } => {
+                write!(f, "Invalid Opcode: `{opcode:02X}` at ${pc:04X}")
+            }
+            CpuError::DebugBreak { pc } => write!(f, "Stopped at ${pc:04X}"),
+        }
+    }
+}
+
+impl fmt::Display for StateError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            StateError::NotAState => write!(f, "not a savestate"),
+            StateError::Version(err) => err.fmt(f),
+            StateError::WrongRom { state, rom } => {
+                write!(f, "state is for ROM {state:08X}, not {rom:08X}")
+            }
+            StateError::Truncated => write!(f, "state is truncated"),
+            StateError::TrailingData => write!(f, "state has trailing data"),
+            StateError::Invalid(err) => f.write_str(err),
+        }
+    }
+}
+
+impl fmt::Display for MapperError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MapperError::Unsupported(mapper) => write!(f, "Mapper {mapper} is not supported"),
+        }
+    }
+}
+
+impl fmt::Display for NetplayError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            NetplayError::Io(err) => err.fmt(f),
+            NetplayError::Closed => write!(f, "connection closed"),
+            NetplayError::UnknownMessage(tag) => write!(f, "unknown netplay message {tag}"),
+            NetplayError::Desync { frame } => write!(f, "desynced at frame {frame}"),
+            NetplayError::NoSnapshot { frame } => {
+                write!(f, "no state to roll back to frame {frame}")
+            }
+            NetplayError::State(err) => write!(f, "couldn't roll back: {err}"),
+        }
+    }
+}
+
+impl std::error::Error for NesError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            NesError::Rom(err) => Some(err),
+            NesError::Cpu(err) => Some(err),
+            NesError::State(err) => Some(err),
+            NesError::Mapper(err) => Some(err),
+            NesError::Netplay(err) => Some(err),
+        }
+    }
+}
+
+impl std::error::Error for RomError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            RomError::Io { error, .. } => Some(error),
+            _ => None,
+        }
+    }
+}
+
+impl std::error::Error for CpuError {}
+
+impl std::error::Error for StateError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            StateError::Version(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl std::error::Error for MapperError {}
+
+impl std::error::Error for NetplayError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            NetplayError::Io(err) => Some(err),
+            NetplayError::State(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<StateVersionError> for StateError {
+    fn from(err: StateVersionError) -> Self {
+        StateError::Version(err)
+    }
+}
+
+impl From<io::Error> for NetplayError {
+    fn from(err: io::Error) -> Self {
+        NetplayError::Io(err)
+    }
+}
+
+impl From<StateError> for NetplayError {
+    fn from(err: StateError) -> Self {
+        NetplayError::State(err)
+    }
+}
+
+/// `From` each error into `NesError` and each one, `NesError` included, into `String`
+macro_rules! conversions {
+    ($($variant:ident($ty:ty)),+) => {
+        $(impl From<$ty> for NesError {
+            fn from(err: $ty) -> Self {
+                NesError::$variant(err)
+            }
+        }
+
+        impl From<$ty> for String {
+            fn from(err: $ty) -> Self {
+                err.to_string()
+            }
+        })+
+    };
+}
+
+conversions!(
+    Rom(RomError),
+    Cpu(CpuError),
+    State(StateError),
+    Mapper(MapperError),
+    Netplay(NetplayError)
+);
+
+impl From<NesError> for String {
+    fn from(err: NesError) -> Self {
+        err.to_string()
+    }
+}
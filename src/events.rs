@@ -0,0 +1,62 @@
+/// Something that happened on the bus, for the event viewer
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BusEventKind {
+    /// CPU write to $2000-$3FFF, `addr` is the mirrored register address
+    PpuRegisterWrite {
+        addr: u16,
+        val: u8,
+    },
+    /// CPU write to cartridge space ($4020-$FFFF)
+    MapperWrite {
+        addr: u16,
+        val: u8,
+    },
+    Nmi,
+    Irq,
+    Sprite0Hit,
+}
+
+/// An event and the PPU position it happened at
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BusEvent {
+    pub scanline: u16,
+    pub dot: u16,
+    pub kind: BusEventKind,
+}
+
+/// Per-frame record of bus events, like Mesen's event viewer.
+/// Recording is off by default since it costs a check on every cycle.
+#[derive(Default)]
+pub struct EventLog {
+    pub enabled: bool,
+    current: Vec<BusEvent>,
+    last_frame: Vec<BusEvent>,
+}
+
+impl EventLog {
+    pub fn record(&mut self, scanline: u16, dot: u16, kind: BusEventKind) {
+        if self.enabled {
+            self.current.push(BusEvent {
+                scanline,
+                dot,
+                kind,
+            });
+        }
+    }
+
+    /// Called when the PPU wraps around to a new frame
+    pub fn end_frame(&mut self) {
+        std::mem::swap(&mut self.current, &mut self.last_frame);
+        self.current.clear();
+    }
+
+    /// Events of the last complete frame
+    pub fn frame_events(&self) -> &[BusEvent] {
+        &self.last_frame
+    }
+
+    /// Events recorded so far in the frame being run
+    pub fn current_events(&self) -> &[BusEvent] {
+        &self.current
+    }
+}
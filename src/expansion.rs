@@ -0,0 +1,191 @@
+//! Devices in the Famicom expansion port.
+//!
+//! The port sees the three output bits of every $4016 write and can drive
+//! bits 1-4 of $4016 and $4017 reads, next to the two controllers.
+//! See https://www.nesdev.org/wiki/Expansion_port
+
+use std::any::Any;
+
+use crate::joypad::{Joypad, Vaus};
+use crate::savestate::{StateStream, Stateful, stream};
+
+/// Something plugged into the expansion port, see [`crate::Bus::expansion`]
+pub trait ExpansionDevice: Stateful + Any + Send {
+    /// $4016 write, bits 0-2 reach the port
+    fn write(&mut self, val: u8);
+    /// Bits driven on `port` (0 for $4016, 1 for $4017), without side effects
+    fn peek(&self, port: usize) -> u8;
+    fn read(&mut self, port: usize) -> u8 {
+        self.peek(port)
+    }
+}
+
+impl ExpansionDevice for Vaus {
+    fn write(&mut self, val: u8) {
+        Vaus::write(self, val);
+    }
+
+    fn peek(&self, port: usize) -> u8 {
+        Vaus::peek(self, port)
+    }
+
+    fn read(&mut self, port: usize) -> u8 {
+        Vaus::read(self, port)
+    }
+}
+
+/// Key names of the Family BASIC keyboard by row, column and bit.
+/// `¥` is the yen key, `KANA` and `GRPH` switch character sets.
+pub const KEYBOARD_MATRIX: [[[&str; 4]; 2]; 9] = [
+    [["]", "[", "RETURN", "F8"], ["STOP", "¥", "RSHIFT", "KANA"]],
+    [[";", ":", "@", "F7"], ["^", "-", "/", "_"]],
+    [["K", "L", "O", "F6"], ["0", "P", ",", "."]],
+    [["J", "U", "I", "F5"], ["8", "9", "N", "M"]],
+    [["H", "G", "Y", "F4"], ["6", "7", "V", "B"]],
+    [["D", "R", "T", "F3"], ["4", "5", "C", "F"]],
+    [["A", "S", "W", "F2"], ["3", "E", "Z", "X"]],
+    [["CTR", "Q", "ESC", "F1"], ["2", "1", "GRPH", "LSHIFT"]],
+    [
+        ["LEFT", "RIGHT", "UP", "CLR"],
+        ["INS", "DEL", "SPACE", "DOWN"],
+    ],
+];
+
+/// Family BASIC keyboard, scanned a row at a time. Each row has two columns
+/// of four keys read from $4017 bits 1-4, low while pressed.
+/// See https://www.nesdev.org/wiki/Family_BASIC_Keyboard
+#[derive(Default)]
+pub struct FamilyKeyboard {
+    /// Pressed keys, column 0 in the low nibble of each row
+    pub rows: [u8; 9],
+    enabled: bool,
+    row: u8,
+    column: u8,
+}
+
+impl Stateful for FamilyKeyboard {
+    fn stream(&mut self, s: &mut StateStream) {
+        stream!(s, self.rows, self.enabled, self.row, self.column);
+    }
+}
+
+impl FamilyKeyboard {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Press or release a key named in [`KEYBOARD_MATRIX`], `false` if there is no such key
+    pub fn set_key(&mut self, name: &str, pressed: bool) -> bool {
+        for (row, columns) in KEYBOARD_MATRIX.iter().enumerate() {
+            for (column, keys) in columns.iter().enumerate() {
+                if let Some(bit) = keys.iter().position(|&key| key == name) {
+                    let mask = 1 << (column * 4 + bit);
+                    if pressed {
+                        self.rows[row] |= mask;
+                    } else {
+                        self.rows[row] &= !mask;
+                    }
+                    return true;
+                }
+            }
+        }
+        false
+    }
+}
+
+impl ExpansionDevice for FamilyKeyboard {
+    /// Bit 0 goes back to the first row, bit 1 picks the column and moves
+    /// to the next row going from 1 to 0, bit 2 enables the keyboard
+    fn write(&mut self, val: u8) {
+        self.enabled = val & 0b100 != 0;
+        if !self.enabled {
+            return;
+        }
+        let column = (val >> 1) & 1;
+        if self.column == 1 && column == 0 {
+            self.row = self.row.saturating_add(1);
+        }
+        self.column = column;
+        if val & 1 != 0 {
+            self.row = 0;
+        }
+    }
+
+    fn peek(&self, port: usize) -> u8 {
+        if port != 1 || !self.enabled {
+            return 0;
+        }
+        // Past the last row nothing is pressed
+        let pressed = match self.rows.get(self.row as usize) {
+            Some(keys) => (keys >> (self.column * 4)) & 0xf,
+            None => 0,
+        };
+        (!pressed & 0xf) << 1
+    }
+}
+
+/// The microphone on the Famicom's second controller, read from $4016 bit 2.
+/// Games only check whether it picks anything up.
+#[derive(Default)]
+pub struct Microphone {
+    /// Whether the player is making noise
+    pub loud: bool,
+}
+
+impl Stateful for Microphone {
+    fn stream(&mut self, s: &mut StateStream) {
+        self.loud.stream(s);
+    }
+}
+
+impl Microphone {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl ExpansionDevice for Microphone {
+    fn write(&mut self, _val: u8) {}
+
+    fn peek(&self, port: usize) -> u8 {
+        ((port == 0 && self.loud) as u8) << 2
+    }
+}
+
+/// A four player adapter on the Famicom expansion port, like the Hyper Tap,
+/// in the mode Famicom games read: controllers 3 and 4 shift out on bit 1
+/// of $4016 and $4017 like the console's own two do on bit 0.
+/// See https://www.nesdev.org/wiki/Four_player_adapters
+#[derive(Default)]
+pub struct Multitap {
+    /// Controllers 3 and 4
+    pub joypads: [Joypad; 2],
+}
+
+impl Stateful for Multitap {
+    fn stream(&mut self, s: &mut StateStream) {
+        self.joypads.stream(s);
+    }
+}
+
+impl Multitap {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl ExpansionDevice for Multitap {
+    fn write(&mut self, val: u8) {
+        for joypad in &mut self.joypads {
+            joypad.write(val);
+        }
+    }
+
+    fn peek(&self, port: usize) -> u8 {
+        self.joypads[port].peek() << 1
+    }
+
+    fn read(&mut self, port: usize) -> u8 {
+        self.joypads[port].read() << 1
+    }
+}
@@ -0,0 +1,311 @@
+//! Debugger expressions, used by watches and breakpoint conditions.
+//!
+//! Values are 32-bit signed integers. Numbers are decimal, `$` hex or `%`
+//! binary. `a`, `x`, `y`, `sp`, `pc`, `p`, `scanline`, `dot` and `frame` read
+//! CPU and PPU state, `[addr]` reads a byte and `{addr}` a little-endian word.
+//! Memory reads use `Bus::peek`, so evaluating never has side effects.
+//! The operators and their precedence are the same as in C.
+
+use crate::Cpu;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Var {
+    A,
+    X,
+    Y,
+    Sp,
+    Pc,
+    P,
+    Scanline,
+    Dot,
+    Frame,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum UnaryOp {
+    Neg,
+    Not,
+    BitNot,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum BinaryOp {
+    Mul,
+    Div,
+    Rem,
+    Add,
+    Sub,
+    Shl,
+    Shr,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Eq,
+    Ne,
+    BitAnd,
+    BitXor,
+    BitOr,
+    And,
+    Or,
+}
+
+impl BinaryOp {
+    /// Higher binds tighter
+    fn precedence(self) -> u8 {
+        match self {
+            BinaryOp::Mul | BinaryOp::Div | BinaryOp::Rem => 10,
+            BinaryOp::Add | BinaryOp::Sub => 9,
+            BinaryOp::Shl | BinaryOp::Shr => 8,
+            BinaryOp::Lt | BinaryOp::Le | BinaryOp::Gt | BinaryOp::Ge => 7,
+            BinaryOp::Eq | BinaryOp::Ne => 6,
+            BinaryOp::BitAnd => 5,
+            BinaryOp::BitXor => 4,
+            BinaryOp::BitOr => 3,
+            BinaryOp::And => 2,
+            BinaryOp::Or => 1,
+        }
+    }
+
+    fn apply(self, lhs: i32, rhs: i32) -> i32 {
+        match self {
+            BinaryOp::Mul => lhs.wrapping_mul(rhs),
+            // Dividing by zero gives 0 rather than stopping the emulator
+            BinaryOp::Div => lhs.checked_div(rhs).unwrap_or(0),
+            BinaryOp::Rem => lhs.checked_rem(rhs).unwrap_or(0),
+            BinaryOp::Add => lhs.wrapping_add(rhs),
+            BinaryOp::Sub => lhs.wrapping_sub(rhs),
+            BinaryOp::Shl => lhs.wrapping_shl(rhs as u32),
+            BinaryOp::Shr => lhs.wrapping_shr(rhs as u32),
+            BinaryOp::Lt => (lhs < rhs) as i32,
+            BinaryOp::Le => (lhs <= rhs) as i32,
+            BinaryOp::Gt => (lhs > rhs) as i32,
+            BinaryOp::Ge => (lhs >= rhs) as i32,
+            BinaryOp::Eq => (lhs == rhs) as i32,
+            BinaryOp::Ne => (lhs != rhs) as i32,
+            BinaryOp::BitAnd => lhs & rhs,
+            BinaryOp::BitXor => lhs ^ rhs,
+            BinaryOp::BitOr => lhs | rhs,
+            BinaryOp::And => (lhs != 0 && rhs != 0) as i32,
+            BinaryOp::Or => (lhs != 0 || rhs != 0) as i32,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Node {
+    Num(i32),
+    Var(Var),
+    Byte(Box<Node>),
+    Word(Box<Node>),
+    Unary(UnaryOp, Box<Node>),
+    Binary(BinaryOp, Box<Node>, Box<Node>),
+}
+
+/// A parsed expression
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Expr {
+    root: Node,
+}
+
+impl Expr {
+    pub fn parse(src: &str) -> Result<Self, String> {
+        let tokens = tokenize(src)?;
+        let mut parser = Parser { tokens, pos: 0 };
+        let root = parser.binary(0)?;
+        match parser.tokens.get(parser.pos) {
+            None => Ok(Expr { root }),
+            Some(token) => Err(format!("unexpected {token:?}")),
+        }
+    }
+
+    pub fn eval(&self, cpu: &Cpu) -> i32 {
+        eval(&self.root, cpu)
+    }
+}
+
+fn eval(node: &Node, cpu: &Cpu) -> i32 {
+    let bus = &cpu.memory;
+    match node {
+        Node::Num(val) => *val,
+        Node::Var(var) => match var {
+            Var::A => cpu.reg_a as i32,
+            Var::X => cpu.reg_x as i32,
+            Var::Y => cpu.reg_y as i32,
+            Var::Sp => cpu.stack_ptr as i32,
+            Var::Pc => cpu.pc as i32,
+            Var::P => cpu.status.bits() as i32,
+            Var::Scanline => bus.ppu.scanline as i32,
+            Var::Dot => bus.ppu.dot as i32,
+            Var::Frame => bus.ppu.frame as i32,
+        },
+        Node::Byte(addr) => bus.peek(eval(addr, cpu) as u16) as i32,
+        Node::Word(addr) => {
+            let addr = eval(addr, cpu) as u16;
+            u16::from_le_bytes([bus.peek(addr), bus.peek(addr.wrapping_add(1))]) as i32
+        }
+        Node::Unary(op, val) => {
+            let val = eval(val, cpu);
+            match op {
+                UnaryOp::Neg => val.wrapping_neg(),
+                UnaryOp::Not => (val == 0) as i32,
+                UnaryOp::BitNot => !val,
+            }
+        }
+        Node::Binary(op, lhs, rhs) => op.apply(eval(lhs, cpu), eval(rhs, cpu)),
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Token {
+    Num(i32),
+    Ident(String),
+    Op(&'static str),
+}
+
+/// Longest operators first so `<=` isn't read as `<`
+const OPERATORS: [&str; 26] = [
+    "<<", ">>", "<=", ">=", "==", "!=", "&&", "||", "*", "/", "%", "+", "-", "<", ">", "&", "^",
+    "|", "!", "~", "(", ")", "[", "]", "{", "}",
+];
+
+fn tokenize(src: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = vec![];
+    let mut rest = src.trim_start();
+    while !rest.is_empty() {
+        // `%` is a binary number where an operand is expected, modulo otherwise
+        let operand_next = !matches!(
+            tokens.last(),
+            Some(Token::Num(_) | Token::Ident(_) | Token::Op(")" | "]" | "}"))
+        );
+        let binary_number = operand_next && rest.starts_with('%');
+        let operator = OPERATORS.iter().find(|op| rest.starts_with(**op));
+        let (token, len) = if let Some(op) = operator.filter(|_| !binary_number) {
+            (Token::Op(op), op.len())
+        } else {
+            let (radix, start) = match rest.as_bytes()[0] {
+                b'$' => (16, 1),
+                b'%' => (2, 1),
+                _ => (10, 0),
+            };
+            let len = rest[start..]
+                .find(|c: char| !c.is_ascii_alphanumeric() && c != '_')
+                .unwrap_or(rest.len() - start);
+            let word = &rest[start..start + len];
+            if word.is_empty() {
+                return Err(format!("unexpected character in {rest:?}"));
+            }
+            let token = if radix != 10 || word.as_bytes()[0].is_ascii_digit() {
+                let val = u32::from_str_radix(word, radix)
+                    .map_err(|_| format!("invalid number {:?}", &rest[..start + len]))?;
+                Token::Num(val as i32)
+            } else {
+                Token::Ident(word.to_ascii_lowercase())
+            };
+            (token, start + len)
+        };
+        tokens.push(token);
+        rest = rest[len..].trim_start();
+    }
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser {
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        token
+    }
+
+    fn expect(&mut self, op: &str) -> Result<(), String> {
+        match self.next() {
+            Some(Token::Op(found)) if found == op => Ok(()),
+            Some(token) => Err(format!("expected {op:?}, found {token:?}")),
+            None => Err(format!("expected {op:?}")),
+        }
+    }
+
+    fn binary_op(&self) -> Option<BinaryOp> {
+        let Some(Token::Op(op)) = self.tokens.get(self.pos) else {
+            return None;
+        };
+        Some(match *op {
+            "*" => BinaryOp::Mul,
+            "/" => BinaryOp::Div,
+            "%" => BinaryOp::Rem,
+            "+" => BinaryOp::Add,
+            "-" => BinaryOp::Sub,
+            "<<" => BinaryOp::Shl,
+            ">>" => BinaryOp::Shr,
+            "<" => BinaryOp::Lt,
+            "<=" => BinaryOp::Le,
+            ">" => BinaryOp::Gt,
+            ">=" => BinaryOp::Ge,
+            "==" => BinaryOp::Eq,
+            "!=" => BinaryOp::Ne,
+            "&" => BinaryOp::BitAnd,
+            "^" => BinaryOp::BitXor,
+            "|" => BinaryOp::BitOr,
+            "&&" => BinaryOp::And,
+            "||" => BinaryOp::Or,
+            _ => return None,
+        })
+    }
+
+    /// Precedence climbing, only operators binding tighter than `min` are taken
+    fn binary(&mut self, min: u8) -> Result<Node, String> {
+        let mut lhs = self.unary()?;
+        while let Some(op) = self.binary_op() {
+            if op.precedence() <= min {
+                break;
+            }
+            self.pos += 1;
+            let rhs = self.binary(op.precedence())?;
+            lhs = Node::Binary(op, Box::new(lhs), Box::new(rhs));
+        }
+        Ok(lhs)
+    }
+
+    fn unary(&mut self) -> Result<Node, String> {
+        let token = self.next().ok_or("unexpected end of expression")?;
+        Ok(match token {
+            Token::Num(val) => Node::Num(val),
+            Token::Ident(name) => Node::Var(match name.as_str() {
+                "a" => Var::A,
+                "x" => Var::X,
+                "y" => Var::Y,
+                "sp" => Var::Sp,
+                "pc" => Var::Pc,
+                "p" => Var::P,
+                "scanline" => Var::Scanline,
+                "dot" => Var::Dot,
+                "frame" => Var::Frame,
+                _ => return Err(format!("unknown name {name:?}")),
+            }),
+            Token::Op("-") => Node::Unary(UnaryOp::Neg, Box::new(self.unary()?)),
+            Token::Op("!") => Node::Unary(UnaryOp::Not, Box::new(self.unary()?)),
+            Token::Op("~") => Node::Unary(UnaryOp::BitNot, Box::new(self.unary()?)),
+            Token::Op("(") => {
+                let inner = self.binary(0)?;
+                self.expect(")")?;
+                inner
+            }
+            Token::Op("[") => {
+                let addr = self.binary(0)?;
+                self.expect("]")?;
+                Node::Byte(Box::new(addr))
+            }
+            Token::Op("{") => {
+                let addr = self.binary(0)?;
+                self.expect("}")?;
+                Node::Word(Box::new(addr))
+            }
+            Token::Op(op) => return Err(format!("unexpected {op:?}")),
+        })
+    }
+}
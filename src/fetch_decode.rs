@@ -164,8 +164,9 @@ fn info_extra2(size: u16, cycles: u16, cycles_extra: u16, cycles_extra2: u16) ->
     }
 }
 
-pub fn decode(val: u8) -> (Opcode, AddrMode, InstructionInfo) {
-    match val {
+/// The instruction for an opcode, `None` for unofficial opcodes
+pub fn decode(val: u8) -> Option<(Opcode, AddrMode, InstructionInfo)> {
+    Some(match val {
         0x69 => (Opcode::ADC, AddrMode::Immediate, info(2, 2)),
         0x65 => (Opcode::ADC, AddrMode::ZeroPage, info(2, 3)),
         0x75 => (Opcode::ADC, AddrMode::ZeroPageX, info(2, 4)),
@@ -350,6 +351,6 @@ pub fn decode(val: u8) -> (Opcode, AddrMode, InstructionInfo) {
         0x8A => (Opcode::TXA, AddrMode::Implicit, info(1, 2)),
         0x9A => (Opcode::TXS, AddrMode::Implicit, info(1, 2)),
         0x98 => (Opcode::TYA, AddrMode::Implicit, info(1, 2)),
-        other => panic!("Invalid Opcode: `{other:X}`"),
-    }
+        _ => return None,
+    })
 }
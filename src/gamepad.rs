@@ -0,0 +1,71 @@
+//! Gamepads through SDL's game controllers, for the programs built with the
+//! `sdl` feature. Buttons are named like gilrs' `Button` variants, which
+//! [`nes::config::InputDevice::Gamepad`] bindings use, so `South` is the
+//! bottom face button whatever the pad prints on it.
+
+use log::{info, warn};
+use sdl2::controller::{Button, GameController};
+use sdl2::event::Event;
+use sdl2::{GameControllerSubsystem, Sdl};
+
+/// The game controllers plugged in
+pub struct Gamepads {
+    subsystem: GameControllerSubsystem,
+    open: Vec<GameController>,
+}
+
+impl Gamepads {
+    pub fn new(sdl: &Sdl) -> Result<Self, String> {
+        Ok(Gamepads {
+            subsystem: sdl.game_controller()?,
+            open: Vec::new(),
+        })
+    }
+
+    /// The button `event` presses or lets go and whether it's pressed.
+    /// Controllers are opened as SDL finds them, including those plugged in
+    /// at start.
+    pub fn button(&mut self, event: &Event) -> Option<(&'static str, bool)> {
+        match *event {
+            Event::ControllerDeviceAdded { which, .. } => {
+                match self.subsystem.open(which) {
+                    Ok(pad) => {
+                        info!("Gamepad connected: {}", pad.name());
+                        self.open.push(pad);
+                    }
+                    Err(err) => warn!("Couldn't open gamepad {which}: {err}"),
+                }
+                None
+            }
+            Event::ControllerDeviceRemoved { which, .. } => {
+                self.open.retain(|pad| pad.instance_id() != which);
+                None
+            }
+            Event::ControllerButtonDown { button, .. } => Some((button_name(button)?, true)),
+            Event::ControllerButtonUp { button, .. } => Some((button_name(button)?, false)),
+            _ => None,
+        }
+    }
+}
+
+/// `button` named like gilrs does, `None` for buttons it has no name for
+fn button_name(button: Button) -> Option<&'static str> {
+    Some(match button {
+        Button::A => "South",
+        Button::B => "East",
+        Button::X => "West",
+        Button::Y => "North",
+        Button::Back => "Select",
+        Button::Guide => "Mode",
+        Button::Start => "Start",
+        Button::LeftStick => "LeftThumb",
+        Button::RightStick => "RightThumb",
+        Button::LeftShoulder => "LeftTrigger",
+        Button::RightShoulder => "RightTrigger",
+        Button::DPadUp => "DPadUp",
+        Button::DPadDown => "DPadDown",
+        Button::DPadLeft => "DPadLeft",
+        Button::DPadRight => "DPadRight",
+        _ => return None,
+    })
+}
@@ -0,0 +1,117 @@
+//! How often the CPU reads and writes each byte of RAM and PRG ROM, for
+//! finding the code and data a game actually touches.
+//!
+//! PRG ROM is counted by offset into the ROM, whichever bank it was read
+//! through, so the counts line up with the ROM file (after the 16 byte
+//! header) rather than with CPU addresses. Only bus accesses count, not
+//! [`crate::Bus::peek`], so debuggers looking at memory don't show up.
+
+/// Access counts for RAM and PRG ROM. Counting is off by default since it
+/// costs a check on every access, like [`crate::events::EventLog`].
+pub struct MemoryHeatmap {
+    pub enabled: bool,
+    /// Count only every `interval`th access, as `interval` accesses, so
+    /// long sessions cost less while the counts keep about the same size.
+    /// 1 counts every access.
+    pub interval: u32,
+    /// Accesses since the last one counted
+    skipped: u32,
+    ram: AccessCounts,
+    prg_rom: AccessCounts,
+}
+
+/// Reads and writes of each byte of a memory
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct AccessCounts {
+    pub reads: Vec<u32>,
+    pub writes: Vec<u32>,
+}
+
+/// The memories a [`MemoryHeatmap`] counts
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HeatmapMemory {
+    /// The 2K of CPU RAM, $0000-$1FFF with its mirrors folded in
+    Ram,
+    /// Offsets into PRG ROM. Only reads count, writes there go to mapper
+    /// registers.
+    PrgRom,
+}
+
+impl MemoryHeatmap {
+    pub fn new(prg_rom_len: usize) -> Self {
+        MemoryHeatmap {
+            enabled: false,
+            interval: 1,
+            skipped: 0,
+            ram: AccessCounts::new(0x800),
+            prg_rom: AccessCounts::new(prg_rom_len),
+        }
+    }
+
+    pub fn record(&mut self, memory: HeatmapMemory, offset: usize, write: bool) {
+        if !self.enabled {
+            return;
+        }
+        let interval = self.interval.max(1);
+        self.skipped += 1;
+        if self.skipped < interval {
+            return;
+        }
+        self.skipped = 0;
+        let counts = match memory {
+            HeatmapMemory::Ram => &mut self.ram,
+            HeatmapMemory::PrgRom => &mut self.prg_rom,
+        };
+        let count = if write {
+            &mut counts.writes[offset]
+        } else {
+            &mut counts.reads[offset]
+        };
+        *count = count.saturating_add(interval);
+    }
+
+    pub fn counts(&self, memory: HeatmapMemory) -> &AccessCounts {
+        match memory {
+            HeatmapMemory::Ram => &self.ram,
+            HeatmapMemory::PrgRom => &self.prg_rom,
+        }
+    }
+
+    /// Start counting again from zero
+    pub fn clear(&mut self) {
+        self.skipped = 0;
+        for counts in [&mut self.ram, &mut self.prg_rom] {
+            counts.reads.fill(0);
+            counts.writes.fill(0);
+        }
+    }
+}
+
+impl AccessCounts {
+    pub fn new(len: usize) -> Self {
+        AccessCounts {
+            reads: vec![0; len],
+            writes: vec![0; len],
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.reads.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.reads.is_empty()
+    }
+
+    /// Total reads and writes of the bytes in `range`, for showing a block
+    /// of memory at a time
+    pub fn sum(&self, range: std::ops::Range<usize>) -> (u64, u64) {
+        let total = |counts: &[u32]| counts[range.clone()].iter().map(|&n| n as u64).sum();
+        (total(&self.reads), total(&self.writes))
+    }
+
+    /// Offsets read or written at least once
+    pub fn touched(&self) -> impl Iterator<Item = usize> + '_ {
+        (0..self.len()).filter(|&i| self.reads[i] != 0 || self.writes[i] != 0)
+    }
+}
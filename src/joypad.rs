@@ -0,0 +1,402 @@
+use std::any::Any;
+
+use crate::savestate::{StateStream, Stateful, stateful_bitflags, stream};
+
+bitflags::bitflags! {
+    /// Standard controller buttons, in the order they are shifted out
+    #[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+    pub struct Buttons: u8 {
+        const A = 0b_0000_0001;
+        const B = 0b_0000_0010;
+        const SELECT = 0b_0000_0100;
+        const START = 0b_0000_1000;
+        const UP = 0b_0001_0000;
+        const DOWN = 0b_0010_0000;
+        const LEFT = 0b_0100_0000;
+        const RIGHT = 0b_1000_0000;
+    }
+}
+
+stateful_bitflags!(Buttons);
+
+/// Standard controller, read serially through $4016/$4017
+/// See https://www.nesdev.org/wiki/Standard_controller
+#[derive(Default)]
+pub struct Joypad {
+    pub buttons: Buttons,
+    strobe: bool,
+    shift: u8,
+}
+
+impl Stateful for Joypad {
+    fn stream(&mut self, s: &mut StateStream) {
+        stream!(s, self.buttons, self.strobe, self.shift);
+    }
+}
+
+impl Joypad {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// $4016 bit 0, reloads the shift register while high
+    pub fn write(&mut self, val: u8) {
+        self.strobe = val & 1 != 0;
+        if self.strobe {
+            self.shift = self.buttons.bits();
+        }
+    }
+
+    pub fn peek(&self) -> u8 {
+        if self.strobe {
+            self.buttons.contains(Buttons::A) as u8
+        } else {
+            self.shift & 1
+        }
+    }
+
+    pub fn read(&mut self) -> u8 {
+        let bit = self.peek();
+        if !self.strobe {
+            // Official controllers return 1 after all 8 buttons are read
+            self.shift = (self.shift >> 1) | 0x80;
+        }
+        bit
+    }
+}
+
+/// Something plugged into a controller port instead of a standard
+/// controller, see [`crate::Bus::ports`]
+pub trait PortDevice: Stateful + Any + Send {
+    /// $4016 bit 0, the strobe shared by both ports
+    fn write(&mut self, val: u8);
+    /// Bits driven on the port's register, without side effects
+    fn peek(&self) -> u8;
+    fn read(&mut self) -> u8;
+}
+
+/// Arkanoid's Vaus paddle, a dial and a fire button.
+/// The NES version takes controller port 2, see [`crate::Bus::ports`], the
+/// Famicom version plugs into the expansion port next to both controllers.
+/// See https://www.nesdev.org/wiki/Arkanoid_controller
+#[derive(Default)]
+pub struct Vaus {
+    /// Dial position, Arkanoid uses `Vaus::MIN` to `Vaus::MAX`
+    pub position: u8,
+    pub fire: bool,
+    /// Famicom version, set when plugging it in
+    pub famicom: bool,
+    strobe: bool,
+    shift: u8,
+}
+
+impl Stateful for Vaus {
+    fn stream(&mut self, s: &mut StateStream) {
+        stream!(s, self.position, self.fire, self.strobe, self.shift);
+    }
+}
+
+impl Vaus {
+    /// Dial range Arkanoid moves the paddle across
+    pub const MIN: u8 = 98;
+    pub const MAX: u8 = 242;
+
+    pub fn new(famicom: bool) -> Self {
+        Vaus {
+            position: Self::MIN,
+            famicom,
+            ..Self::default()
+        }
+    }
+
+    /// $4016 bit 0, latches the dial position while high
+    pub fn write(&mut self, val: u8) {
+        self.strobe = val & 1 != 0;
+        if self.strobe {
+            self.shift = self.position;
+        }
+    }
+
+    /// Bits the paddle drives on `port` (0 for $4016, 1 for $4017).
+    /// The dial is shifted out most significant bit first, inverted.
+    pub fn peek(&self, port: usize) -> u8 {
+        let latched = if self.strobe {
+            self.position
+        } else {
+            self.shift
+        };
+        let data = !latched >> 7;
+        match (self.famicom, port) {
+            (false, 1) => (data << 4) | ((self.fire as u8) << 3),
+            (false, _) => 0,
+            (true, 0) => (self.fire as u8) << 1,
+            (true, _) => data << 1,
+        }
+    }
+
+    pub fn read(&mut self, port: usize) -> u8 {
+        let bits = self.peek(port);
+        if port == 1 && !self.strobe {
+            self.shift <<= 1;
+        }
+        bits
+    }
+}
+
+impl PortDevice for Vaus {
+    fn write(&mut self, val: u8) {
+        Vaus::write(self, val);
+    }
+
+    fn peek(&self) -> u8 {
+        Vaus::peek(self, 1)
+    }
+
+    fn read(&mut self) -> u8 {
+        Vaus::read(self, 1)
+    }
+}
+
+/// One side of the NES Four Score, which takes four controllers on the two
+/// ports. Each port reports 24 bits on bit 0: its first controller, its
+/// second one, then a signature telling games the adapter is there.
+/// Plug one into each port, see [`crate::Bus::ports`]. Players 1 and 3 are
+/// on port 1, players 2 and 4 on port 2.
+/// See https://www.nesdev.org/wiki/Four_Score
+pub struct FourScore {
+    /// The port's two controllers, players 1 and 3 or players 2 and 4
+    pub buttons: [Buttons; 2],
+    signature: u8,
+    strobe: bool,
+    shift: u32,
+}
+
+impl Stateful for FourScore {
+    fn stream(&mut self, s: &mut StateStream) {
+        stream!(s, self.buttons, self.strobe, self.shift);
+    }
+}
+
+impl FourScore {
+    /// The side for `port`, 0 or 1
+    pub fn new(port: usize) -> Self {
+        FourScore {
+            buttons: [Buttons::empty(); 2],
+            // Read as 00010000 on port 1 and 00100000 on port 2
+            signature: if port == 0 { 0x08 } else { 0x04 },
+            strobe: false,
+            shift: 0,
+        }
+    }
+
+    fn report(&self) -> u32 {
+        let [first, second] = self.buttons.map(|buttons| buttons.bits() as u32);
+        (self.signature as u32) << 16 | second << 8 | first
+    }
+}
+
+impl PortDevice for FourScore {
+    fn write(&mut self, val: u8) {
+        self.strobe = val & 1 != 0;
+        if self.strobe {
+            self.shift = self.report();
+        }
+    }
+
+    fn peek(&self) -> u8 {
+        if self.strobe {
+            self.buttons[0].contains(Buttons::A) as u8
+        } else {
+            (self.shift & 1) as u8
+        }
+    }
+
+    fn read(&mut self) -> u8 {
+        let bit = self.peek();
+        if !self.strobe {
+            // 1s once all 24 bits have been read
+            self.shift = (self.shift >> 1) | 0x80_0000;
+        }
+        bit
+    }
+}
+
+/// SNES mouse through an adapter, as some homebrew supports. Reports 32 bits
+/// serially on bit 0: a zero byte, the buttons, sensitivity and a 0001
+/// signature, then vertical and horizontal movement since the last strobe.
+/// See https://www.nesdev.org/wiki/Super_NES_Mouse
+#[derive(Default)]
+pub struct SnesMouse {
+    /// Movement since the last strobe, positive is right and down
+    pub dx: i32,
+    pub dy: i32,
+    pub left: bool,
+    pub right: bool,
+    /// 0 to 2, cycled by the game reading while strobing
+    sensitivity: u8,
+    strobe: bool,
+    shift: u32,
+}
+
+impl Stateful for SnesMouse {
+    fn stream(&mut self, s: &mut StateStream) {
+        stream!(
+            s,
+            self.dx,
+            self.dy,
+            self.left,
+            self.right,
+            self.sensitivity,
+            self.strobe,
+            self.shift
+        );
+    }
+}
+
+impl SnesMouse {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn sensitivity(&self) -> u8 {
+        self.sensitivity
+    }
+
+    /// Direction bit and up to 7 bits of distance
+    fn motion(delta: i32) -> u32 {
+        ((delta < 0) as u32) << 7 | delta.unsigned_abs().min(0x7f)
+    }
+
+    fn report(&self) -> u32 {
+        let buttons = (self.right as u32) << 7 | (self.left as u32) << 6;
+        let status = buttons | (self.sensitivity as u32) << 4 | 0b0001;
+        status << 16 | Self::motion(self.dy) << 8 | Self::motion(self.dx)
+    }
+}
+
+impl PortDevice for SnesMouse {
+    /// Latches the report and starts counting movement again on the falling edge
+    fn write(&mut self, val: u8) {
+        let strobe = val & 1 != 0;
+        if self.strobe && !strobe {
+            self.shift = self.report();
+            self.dx = 0;
+            self.dy = 0;
+        }
+        self.strobe = strobe;
+    }
+
+    fn peek(&self) -> u8 {
+        if self.strobe {
+            return 0;
+        }
+        (self.shift >> 31) as u8
+    }
+
+    fn read(&mut self) -> u8 {
+        if self.strobe {
+            self.sensitivity = (self.sensitivity + 1) % 3;
+            return 0;
+        }
+        let bit = self.peek();
+        // Like controllers, 1s once the report has been read
+        self.shift = self.shift << 1 | 1;
+        bit
+    }
+}
+
+/// Zapper light gun, aimed at a pixel of the output frame. It sees light
+/// when the frame is bright around where it points, the frontend calls
+/// [`Zapper::sense`] with each frame it shows.
+/// See https://www.nesdev.org/wiki/Zapper
+#[derive(Default)]
+pub struct Zapper {
+    /// Where the player points in the output frame, `None` off screen
+    pub aim: Option<(i32, i32)>,
+    pub trigger: bool,
+    /// Added to `aim`, for scaling or cropping that puts the shot off target
+    pub offset: (i32, i32),
+    /// Whether `draw_crosshair` draws anything
+    pub crosshair: bool,
+    light: bool,
+}
+
+impl Stateful for Zapper {
+    fn stream(&mut self, s: &mut StateStream) {
+        stream!(s, self.aim, self.trigger, self.light);
+    }
+}
+
+impl Zapper {
+    /// How far around the target the sensor sees, in pixels
+    const SENSOR_RADIUS: i32 = 2;
+    /// Lowest luma that counts as light
+    const LIGHT_LEVEL: u32 = 0xa0;
+
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The pixel a shot lands on, `aim` corrected by `offset`
+    pub fn target(&self) -> Option<(i32, i32)> {
+        let (x, y) = self.aim?;
+        Some((x + self.offset.0, y + self.offset.1))
+    }
+
+    /// Set `offset` so that where the player points now hits `target`,
+    /// after asking them to aim at it
+    pub fn calibrate(&mut self, target: (i32, i32)) {
+        if let Some((x, y)) = self.aim {
+            self.offset = (target.0 - x, target.1 - y);
+        }
+    }
+
+    /// Look at a frame of `width` pixels per row (0RGB) before it is shown
+    pub fn sense(&mut self, frame: &[u32], width: usize) {
+        let Some((x, y)) = self.target() else {
+            self.light = false;
+            return;
+        };
+        let height = (frame.len() / width.max(1)) as i32;
+        let r = Self::SENSOR_RADIUS;
+        self.light = (y - r..=y + r)
+            .flat_map(|py| (x - r..=x + r).map(move |px| (px, py)))
+            .filter(|&(px, py)| (0..width as i32).contains(&px) && (0..height).contains(&py))
+            .any(|(px, py)| luma(frame[py as usize * width + px as usize]) >= Self::LIGHT_LEVEL);
+    }
+
+    /// Draw a crosshair on the target, inverting what's under it so it shows
+    /// on any background. Call after [`Zapper::sense`].
+    pub fn draw_crosshair(&self, frame: &mut [u32], width: usize) {
+        let Some((x, y)) = self.target().filter(|_| self.crosshair) else {
+            return;
+        };
+        let height = (frame.len() / width.max(1)) as i32;
+        let horizontal = (-4..=4).map(|d| (x + d, y));
+        let vertical = (-4..=4).filter(|&d| d != 0).map(|d| (x, y + d));
+        for (px, py) in horizontal.chain(vertical) {
+            if (0..width as i32).contains(&px) && (0..height).contains(&py) {
+                frame[py as usize * width + px as usize] ^= 0xffffff;
+            }
+        }
+    }
+}
+
+/// Brightness of a 0RGB pixel, 0 to 255
+fn luma(pixel: u32) -> u32 {
+    let [_, r, g, b] = pixel.to_be_bytes().map(u32::from);
+    (r * 299 + g * 587 + b * 114) / 1000
+}
+
+impl PortDevice for Zapper {
+    fn write(&mut self, _val: u8) {}
+
+    /// Bit 3 low while light is seen, bit 4 high while the trigger is pulled
+    fn peek(&self) -> u8 {
+        (!self.light as u8) << 3 | (self.trigger as u8) << 4
+    }
+
+    fn read(&mut self) -> u8 {
+        self.peek()
+    }
+}
@@ -0,0 +1,307 @@
+//! Names for addresses, for disassembly. Labels come from Mesen's `.mlb`
+//! label files, one `TYPE:ADDRESS[-END]:NAME[:COMMENT]` per line, where the
+//! type is `P` for an offset into PRG ROM, `R` for RAM, `W` or `S` for an
+//! offset into PRG RAM at $6000 and `G` for a register. A name can cover a
+//! range, the bytes after the first are then `NAME+1` and so on.
+//!
+//! [`AutoLabels`] names code as the game runs it, for where there are no
+//! labels yet: `sub_XXXX` for JSR targets, `loc_XXXX` for branch
+//! destinations and `nmi_XXXX`, `irq_XXXX` and `reset_XXXX` for where the
+//! vectors go.
+
+use std::collections::{BTreeMap, HashSet};
+
+/// A name for one or more bytes, and a note about them
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Label {
+    pub name: String,
+    /// Can be empty for a comment on its own
+    pub comment: String,
+    /// Bytes the name covers
+    pub len: usize,
+}
+
+/// Labels for PRG ROM, by offset into it, and for everything else the CPU
+/// sees, by address
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Labels {
+    prg_rom: BTreeMap<usize, Label>,
+    cpu: BTreeMap<u16, Label>,
+}
+
+/// The PPU and APU registers, named as on the NESdev wiki
+const REGISTERS: [(u16, &str); 30] = [
+    (0x2000, "PPUCTRL"),
+    (0x2001, "PPUMASK"),
+    (0x2002, "PPUSTATUS"),
+    (0x2003, "OAMADDR"),
+    (0x2004, "OAMDATA"),
+    (0x2005, "PPUSCROLL"),
+    (0x2006, "PPUADDR"),
+    (0x2007, "PPUDATA"),
+    (0x4000, "SQ1_VOL"),
+    (0x4001, "SQ1_SWEEP"),
+    (0x4002, "SQ1_LO"),
+    (0x4003, "SQ1_HI"),
+    (0x4004, "SQ2_VOL"),
+    (0x4005, "SQ2_SWEEP"),
+    (0x4006, "SQ2_LO"),
+    (0x4007, "SQ2_HI"),
+    (0x4008, "TRI_LINEAR"),
+    (0x400a, "TRI_LO"),
+    (0x400b, "TRI_HI"),
+    (0x400c, "NOISE_VOL"),
+    (0x400e, "NOISE_LO"),
+    (0x400f, "NOISE_HI"),
+    (0x4010, "DMC_FREQ"),
+    (0x4011, "DMC_RAW"),
+    (0x4012, "DMC_START"),
+    (0x4013, "DMC_LEN"),
+    (0x4014, "OAMDMA"),
+    (0x4015, "SND_CHN"),
+    (0x4016, "JOY1"),
+    (0x4017, "JOY2"),
+];
+
+impl Labels {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The PPU, APU and controller registers
+    pub fn registers() -> Self {
+        let mut labels = Self::new();
+        for (addr, name) in REGISTERS {
+            labels.insert_cpu(addr, name, "", 1);
+        }
+        labels
+    }
+
+    /// Add the labels in a Mesen `.mlb` file, replacing any at the same
+    /// addresses. Labels for memory other than the CPU's are skipped.
+    pub fn load_mlb(&mut self, text: &str) -> Result<(), String> {
+        for (number, line) in text.lines().enumerate() {
+            let line = line.trim_end_matches('\r');
+            if line.is_empty() {
+                continue;
+            }
+            let err = |msg: &str| format!("line {}: {msg}", number + 1);
+            let mut fields = line.splitn(4, ':');
+            let (Some(kind), Some(range), Some(name)) =
+                (fields.next(), fields.next(), fields.next())
+            else {
+                return Err(err("expected TYPE:ADDRESS:NAME"));
+            };
+            // Mesen writes newlines in comments as \n
+            let comment = fields.next().unwrap_or("").replace("\\n", "\n");
+            let (start, end) = match range.split_once('-') {
+                Some((start, end)) => (start, end),
+                None => (range, range),
+            };
+            let parse = |hex: &str| usize::from_str_radix(hex, 16).map_err(|_| err("bad address"));
+            let (start, end) = (parse(start)?, parse(end)?);
+            if end < start {
+                return Err(err("range ends before it starts"));
+            }
+            if !name.is_empty() && !is_identifier(name) {
+                return Err(err(&format!("`{name}` isn't a valid label")));
+            }
+            let len = end - start + 1;
+            match kind {
+                "P" => self.insert_prg_rom(start, name, &comment, len),
+                "R" | "G" if end <= 0xffff => self.insert_cpu(start as u16, name, &comment, len),
+                "W" | "S" if end < 0x2000 => {
+                    self.insert_cpu(0x6000 + start as u16, name, &comment, len)
+                }
+                "R" | "G" | "W" | "S" => return Err(err("address out of range")),
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    pub fn insert_prg_rom(&mut self, offset: usize, name: &str, comment: &str, len: usize) {
+        self.prg_rom.insert(offset, label(name, comment, len));
+    }
+
+    pub fn insert_cpu(&mut self, addr: u16, name: &str, comment: &str, len: usize) {
+        self.cpu.insert(addr, label(name, comment, len));
+    }
+
+    /// The label starting at `offset` into PRG ROM
+    pub fn prg_rom(&self, offset: usize) -> Option<&Label> {
+        self.prg_rom.get(&offset)
+    }
+
+    /// The label covering `offset` into PRG ROM and where it starts
+    pub fn prg_rom_at(&self, offset: usize) -> Option<(usize, &Label)> {
+        covering(&self.prg_rom, offset)
+    }
+
+    /// The name for `offset` into PRG ROM, `NAME+n` inside a range
+    pub fn prg_rom_name(&self, offset: usize) -> Option<String> {
+        let (start, label) = self.prg_rom_at(offset)?;
+        name(label, offset - start)
+    }
+
+    /// The name for `addr` outside PRG ROM, `NAME+n` inside a range
+    pub fn cpu_name(&self, addr: u16) -> Option<String> {
+        let (start, label) = covering(&self.cpu, addr)?;
+        name(label, (addr - start) as usize)
+    }
+
+    /// Add the labels in `auto` for addresses without a label, numbering
+    /// names that are already taken, like `sub_8000_2` for a subroutine at
+    /// $8000 in a second bank
+    pub fn merge(&mut self, auto: &AutoLabels) {
+        let mut names: HashSet<String> = self
+            .prg_rom
+            .values()
+            .chain(self.cpu.values())
+            .map(|label| label.name.clone())
+            .collect();
+        let mut unique = |name: String| {
+            let name = (1..)
+                .map(|n| match n {
+                    1 => name.clone(),
+                    n => format!("{name}_{n}"),
+                })
+                .find(|name| !names.contains(name))
+                .unwrap();
+            names.insert(name.clone());
+            name
+        };
+        for (&offset, &(addr, kind)) in &auto.prg_rom {
+            if self.prg_rom_at(offset).is_none() {
+                let name = unique(kind.name(addr));
+                self.insert_prg_rom(offset, &name, "", 1);
+            }
+        }
+        for (&addr, &kind) in &auto.cpu {
+            if covering(&self.cpu, addr).is_none() {
+                let name = unique(kind.name(addr));
+                self.insert_cpu(addr, &name, "", 1);
+            }
+        }
+    }
+
+    /// Labels outside PRG ROM in address order
+    pub fn cpu(&self) -> impl Iterator<Item = (u16, &Label)> {
+        self.cpu.iter().map(|(&addr, label)| (addr, label))
+    }
+}
+
+fn name(label: &Label, offset: usize) -> Option<String> {
+    Some(match offset {
+        _ if label.name.is_empty() => return None,
+        0 => label.name.clone(),
+        offset => format!("{}+{offset}", label.name),
+    })
+}
+
+fn label(name: &str, comment: &str, len: usize) -> Label {
+    Label {
+        name: name.to_string(),
+        comment: comment.to_string(),
+        len: len.max(1),
+    }
+}
+
+fn covering<K>(labels: &BTreeMap<K, Label>, addr: K) -> Option<(K, &Label)>
+where
+    K: Copy + Ord + Into<usize>,
+{
+    let (&start, label) = labels.range(..=addr).next_back()?;
+    (addr.into() - start.into() < label.len).then_some((start, label))
+}
+
+/// Names assemblers accept, like ca65's
+fn is_identifier(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_' || c == '@')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '@')
+}
+
+/// Why [`AutoLabels`] named an address. Later kinds take precedence when
+/// code is reached more than one way.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum AutoLabelKind {
+    Branch,
+    Subroutine,
+    Irq,
+    Nmi,
+    Reset,
+}
+
+impl AutoLabelKind {
+    /// The label for code of this kind at `addr`
+    pub fn name(self, addr: u16) -> String {
+        let prefix = match self {
+            AutoLabelKind::Branch => "loc",
+            AutoLabelKind::Subroutine => "sub",
+            AutoLabelKind::Irq => "irq",
+            AutoLabelKind::Nmi => "nmi",
+            AutoLabelKind::Reset => "reset",
+        };
+        format!("{prefix}_{addr:04X}")
+    }
+}
+
+/// Labels for code the CPU went to. Off by default, like
+/// [`crate::events::EventLog`] it is enabled by setting `enabled`. The reset
+/// vector is only followed on a reset, so enable it before
+/// [`crate::Cpu::reset`] to get a label there.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct AutoLabels {
+    pub enabled: bool,
+    /// By offset into PRG ROM, with the address it ran at
+    prg_rom: BTreeMap<usize, (u16, AutoLabelKind)>,
+    /// Code outside PRG ROM, like routines copied to RAM
+    cpu: BTreeMap<u16, AutoLabelKind>,
+}
+
+impl AutoLabels {
+    /// The CPU went to `addr`, which is `offset` into PRG ROM if it is in ROM
+    pub(crate) fn record(&mut self, addr: u16, offset: Option<usize>, kind: AutoLabelKind) {
+        if !self.enabled {
+            return;
+        }
+        match offset {
+            Some(offset) => {
+                let entry = self.prg_rom.entry(offset).or_insert((addr, kind));
+                entry.1 = entry.1.max(kind);
+            }
+            None => {
+                let entry = self.cpu.entry(addr).or_insert(kind);
+                *entry = (*entry).max(kind);
+            }
+        }
+    }
+
+    /// The name for `offset` into PRG ROM
+    pub fn prg_rom_name(&self, offset: usize) -> Option<String> {
+        let &(addr, kind) = self.prg_rom.get(&offset)?;
+        Some(kind.name(addr))
+    }
+
+    /// The name for `addr` outside PRG ROM
+    pub fn cpu_name(&self, addr: u16) -> Option<String> {
+        self.cpu.get(&addr).map(|kind| kind.name(addr))
+    }
+
+    pub fn len(&self) -> usize {
+        self.prg_rom.len() + self.cpu.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Forget every label found so far
+    pub fn clear(&mut self) {
+        self.prg_rom.clear();
+        self.cpu.clear();
+    }
+}
@@ -1,81 +1,654 @@
-use crate::fetch_decode::Opcode;
-use fetch_decode::{AddrMode, decode};
-use log::warn;
+use mos6502::{AddrMode, Config, Opcode, STACK_START};
 
+pub mod achievements;
+pub mod apu;
+#[cfg(feature = "bevy")]
+pub mod bevy_plugin;
+pub mod breakpoints;
+pub mod bus_log;
+pub mod clock;
+pub mod compat;
+pub mod config;
+pub mod console;
+#[cfg(feature = "egui")]
+pub mod debug_ui;
+pub mod device;
+pub mod diagnostics;
+pub mod disasm;
+pub mod dump;
+pub mod error;
+pub mod events;
+pub mod expansion;
+pub mod expr;
 mod fetch_decode;
+pub mod heatmap;
+pub mod joypad;
+pub mod labels;
+pub mod mapper;
+pub mod mos6502;
+pub mod movie;
+pub mod netplay;
+pub mod pacing;
+pub mod patch;
+pub mod ppu;
+pub mod register_log;
 pub mod rom;
+#[cfg(feature = "romdb")]
+pub mod romdb;
+pub mod savestate;
+pub mod scheduler;
+pub mod testing;
+pub mod triggers;
+pub mod video;
+use apu::{Apu, AudioConfig, AudioOutput, STEM_COUNT, StemOutput};
+use breakpoints::Breakpoints;
+use bus_log::BusLog;
+use device::BusDevice;
+use diagnostics::{Call, DiagnosticKind, Diagnostics};
+use error::MapperError;
+use events::{BusEventKind, EventLog};
+use expansion::ExpansionDevice;
+use heatmap::{HeatmapMemory, MemoryHeatmap};
+use joypad::{Joypad, PortDevice};
+use labels::{AutoLabelKind, AutoLabels, Labels};
+use log::warn;
+use mapper::Mapper;
+use ppu::{Ppu, PpuStatus};
+use register_log::RegisterLog;
 use rom::*;
+use savestate::{StateStream, Stateful, stream};
+use std::any::Any;
+use std::collections::HashSet;
 
 pub mod trace;
 
+pub use mos6502::Flags;
+
 bitflags::bitflags! {
-    #[derive(Clone)]
-    pub struct Flags: u8 {
-        const NEGATIVE = 0b_1000_0000;
-        const OVERFLOW = 0b_0100_0000;
-        const BREAK2 = 0b_0010_0000;
-        const BREAK = 0b_0001_0000;
-        const DECIMAL = 0b_0000_1000;
-        const INTERRUPTDISABLE = 0b_0000_0100;
-        const ZERO = 0b_0000_0010;
-        const CARRY = 0b_0000_0001;
+    /// Devices that can pull the shared CPU IRQ line low
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+    pub struct IrqSource: u8 {
+        const FRAME_COUNTER = 0b_0000_0001;
+        const DMC = 0b_0000_0010;
+        const MAPPER = 0b_0000_0100;
     }
 }
 
-impl Default for Flags {
-    fn default() -> Self {
-        Self::from_bits_retain(0)
-    }
+/// What to do when the CPU reads or writes cartridge space the board has
+/// nothing at, see [`mapper::Mapper::unmapped`]. Games probing $4020-$7FFF
+/// are common, so the default only warns about each address once.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum UnmappedAccess {
+    Ignore,
+    /// Log a warning the first time each address is accessed
+    #[default]
+    WarnOnce,
+    /// Log a warning for every access
+    Warn,
+    /// Stop the CPU after the instruction, with
+    /// [`breakpoints::Breakpoint::Unmapped`] as the breakpoint hit
+    Break,
 }
 
 pub struct Bus {
     pub cpu_ram: [u8; 0x800],
-    pub rom: Rom,
+    pub mapper: Box<dyn Mapper>,
+    pub ppu: Ppu,
+    pub apu: Apu,
+    /// Mixing of the APU with cartridge audio
+    pub audio: AudioConfig,
+    /// Resampled and filtered output, fed every APU cycle
+    audio_output: AudioOutput,
+    /// Every channel on its own, see [`Bus::enable_stems`]
+    stems: Option<StemOutput>,
+    /// Event viewer data, see `EventLog::enabled`
+    pub events: EventLog,
+    /// Sound register writes, see `RegisterLog::start`
+    pub register_log: RegisterLog,
+    /// Every CPU bus access, see `BusLog::start`
+    pub bus_log: BusLog,
+    /// RAM and PRG ROM access counts, see `MemoryHeatmap::enabled`
+    pub heatmap: MemoryHeatmap,
+    /// Likely bugs in the game, see `Diagnostics::enabled`
+    pub diagnostics: Diagnostics,
+    /// Names for addresses in traces and the disassembly view
+    pub labels: Labels,
+    /// Labels for code the CPU went to, see `AutoLabels::enabled`
+    pub auto_labels: AutoLabels,
+    /// What to stop the CPU on for the debugger
+    pub breakpoints: Breakpoints,
+    pub unmapped_access: UnmappedAccess,
+    /// Addresses already warned about with [`UnmappedAccess::WarnOnce`]
+    warned_unmapped: HashSet<u16>,
+    pub joypads: [Joypad; 2],
+    /// Devices plugged in instead of the standard controllers, like the NES
+    /// Arkanoid paddle in port 2. Their joypads are disconnected.
+    pub ports: [Option<Box<dyn PortDevice>>; 2],
+    /// Famicom expansion port
+    pub expansion: Option<Box<dyn ExpansionDevice>>,
+    /// Homemade devices, each answering at its addresses in front of what
+    /// is there, the first one that maps an address if several do
+    pub devices: Vec<Box<dyn BusDevice>>,
+    /// Frames so far where the game never read a controller
+    pub lag_frames: u64,
+    /// Whether the last complete frame was a lag frame
+    pub lag_frame: bool,
+    /// A controller was read during the current frame
+    controller_polled: bool,
+    /// Repeat halted $4016/$4017 reads during DMC DMA like hardware does,
+    /// which makes controllers randomly drop a button bit while samples play
+    pub dmc_read_glitch: bool,
+    /// CPU cycles elapsed since power on
+    pub cycles: u64,
+    /// Page written to $4014, copied to OAM once the current instruction is done
+    pub oam_dma_page: Option<u8>,
+    /// Address of the last read, if the last CPU bus access was a read
+    last_read: Option<u16>,
+    /// Last value on the CPU data bus, which reads of addresses nothing
+    /// drives return. See https://www.nesdev.org/wiki/Open_bus_behavior
+    /// Not saved in states: they are taken between instructions, and the
+    /// next opcode fetch replaces it before anything can see it.
+    data_bus: u8,
+    /// CPU cycles since the last extra PAL dot, PAL runs 3.2 dots per cycle
+    pal_phase: u8,
 }
 
 impl Bus {
+    /// Games on boards that aren't emulated run as NROM, see [`Bus::try_new`]
     pub fn new(rom: Rom) -> Self {
+        let (region, prg_rom_len) = (rom.region, rom.prg_rom.len());
+        Self::with_mapper(region, prg_rom_len, mapper::new(rom))
+    }
+
+    /// Like [`Bus::new`], but fails for boards that aren't emulated
+    pub fn try_new(rom: Rom) -> Result<Self, MapperError> {
+        let (region, prg_rom_len) = (rom.region, rom.prg_rom.len());
+        Ok(Self::with_mapper(
+            region,
+            prg_rom_len,
+            mapper::try_new(rom)?,
+        ))
+    }
+
+    fn with_mapper(region: Region, prg_rom_len: usize, mapper: Box<dyn Mapper>) -> Self {
+        let mut ppu = Ppu::new();
+        let mut apu = Apu::new();
+        let audio_output = AudioOutput::new(region.cpu_clock_rate(), apu::DEFAULT_SAMPLE_RATE);
+        ppu.region = region;
+        apu.set_region(region);
         Bus {
             cpu_ram: [0; 0x800],
-            rom,
+            mapper,
+            ppu,
+            apu,
+            audio: AudioConfig::default(),
+            audio_output,
+            stems: None,
+            events: EventLog::default(),
+            register_log: RegisterLog::default(),
+            bus_log: BusLog::default(),
+            heatmap: MemoryHeatmap::new(prg_rom_len),
+            diagnostics: Diagnostics::default(),
+            labels: Labels::new(),
+            auto_labels: AutoLabels::default(),
+            breakpoints: Breakpoints::new(),
+            unmapped_access: UnmappedAccess::default(),
+            warned_unmapped: HashSet::new(),
+            joypads: [Joypad::new(), Joypad::new()],
+            ports: [None, None],
+            expansion: None,
+            devices: Vec::new(),
+            lag_frames: 0,
+            lag_frame: false,
+            controller_polled: false,
+            dmc_read_glitch: true,
+            cycles: 0,
+            oam_dma_page: None,
+            last_read: None,
+            data_bus: 0,
+            pal_phase: 0,
+        }
+    }
+}
+
+impl Stateful for Bus {
+    fn stream(&mut self, s: &mut StateStream) {
+        stream!(
+            s,
+            self.cpu_ram,
+            *self.mapper,
+            self.ppu,
+            self.apu,
+            self.joypads,
+            self.lag_frames,
+            self.lag_frame,
+            self.controller_polled,
+            self.cycles,
+            self.oam_dma_page,
+            self.last_read,
+            self.pal_phase,
+        );
+        // Which devices are plugged in is a setting, only their state is saved
+        for device in self.ports.iter_mut().flatten() {
+            device.stream(s);
+        }
+        if let Some(device) = &mut self.expansion {
+            device.stream(s);
+        }
+        for device in &mut self.devices {
+            device.stream(s);
         }
     }
 }
 
 impl Bus {
-    pub fn read(&self, pos: u16) -> u8 {
+    /// Advance the rest of the system by the `cycles` CPU cycles of an instruction,
+    /// then run any DMA it triggered while the CPU is halted
+    pub fn tick(&mut self, cycles: u16) {
+        let mut halted_read = None;
+        for i in 0..cycles {
+            let requested = self.apu.dmc.dma_request().is_some();
+            self.clock();
+            // The DMC halts the CPU on its next read cycle. If that is the last cycle
+            // of the instruction, the halted read is repeated during the DMA.
+            if !requested && self.apu.dmc.dma_request().is_some() && i + 2 == cycles {
+                halted_read = self.last_read;
+            }
+        }
+        if let Some(page) = self.oam_dma_page.take() {
+            self.oam_dma(page);
+        }
+        if self.apu.dmc.dma_request().is_some() {
+            self.dmc_dma(halted_read);
+        }
+    }
+    /// A single CPU cycle
+    fn clock(&mut self) {
+        self.cycles += 1;
+        // The APU is frozen during overclock scanlines so audio and the DMC
+        // (including its DMA) stay in step with the original frame timing
+        if !self.ppu.overclocking() {
+            self.apu.tick();
+            let channels = self.apu.channel_levels();
+            let expansion = self.mapper.expansion_audio();
+            let level = self.audio.mix_stereo(channels, expansion);
+            self.audio_output.tick(level);
+            if let Some(stems) = &mut self.stems {
+                let [pulse1, pulse2, triangle, noise, dmc] = channels;
+                let expansion = expansion.map_or(0.0, |(_, level)| level);
+                stems.tick([pulse1, pulse2, triangle, noise, dmc, expansion]);
+            }
+        }
+        self.mapper.cpu_tick();
+        let mut dots = 3;
+        if self.ppu.region == Region::Pal {
+            self.pal_phase += 1;
+            if self.pal_phase == 5 {
+                self.pal_phase = 0;
+                dots += 1;
+            }
+        }
+        let frame = self.ppu.frame;
+        let sprite0_hit = self.ppu.status.contains(PpuStatus::SPRITE_ZERO_HIT);
+        for _ in 0..dots {
+            self.ppu.tick(self.mapper.as_mut());
+        }
+        if self.ppu.frame != frame {
+            self.end_frame();
+        }
+        if self.events.enabled
+            && !sprite0_hit
+            && self.ppu.status.contains(PpuStatus::SPRITE_ZERO_HIT)
+        {
+            self.record_event(BusEventKind::Sprite0Hit);
+        }
+    }
+
+    fn end_frame(&mut self) {
+        self.lag_frame = !std::mem::take(&mut self.controller_polled);
+        if self.lag_frame {
+            self.lag_frames += 1;
+        }
+        if self.events.enabled {
+            self.events.end_frame();
+        }
+    }
+
+    fn record_event(&mut self, kind: BusEventKind) {
+        self.events.record(self.ppu.scanline, self.ppu.dot, kind);
+    }
+
+    /// For the heatmap and diagnostics
+    fn record_access(&mut self, pos: u16, write: bool) {
         match pos {
+            0x0000..=0x1FFF => {
+                let masked = pos & 0x07ff;
+                self.heatmap
+                    .record(HeatmapMemory::Ram, masked as usize, write);
+                self.diagnostics.ram_access(masked, write);
+            }
+            // Writes to ROM go to mapper registers instead
+            _ if !write => {
+                if let Some(offset) = self.mapper.prg_rom_offset(pos) {
+                    self.heatmap.record(HeatmapMemory::PrgRom, offset, false);
+                }
+            }
+            _ => {}
+        }
+    }
+    /// An access to `addr`, which nothing on the board answers at
+    fn unmapped(&mut self, addr: u16, write: bool) {
+        let warn = match self.unmapped_access {
+            UnmappedAccess::Ignore => false,
+            UnmappedAccess::WarnOnce => self.warned_unmapped.insert(addr),
+            UnmappedAccess::Warn => true,
+            UnmappedAccess::Break => {
+                self.breakpoints.unmapped();
+                false
+            }
+        };
+        if warn {
+            let access = if write { "Write to" } else { "Read of" };
+            warn!("{access} unmapped address ${addr:04X} ignored");
+        }
+    }
+    fn record_label(&mut self, addr: u16, kind: AutoLabelKind) {
+        if self.auto_labels.enabled {
+            let offset = self.mapper.prg_rom_offset(addr);
+            self.auto_labels.record(addr, offset, kind);
+        }
+    }
+
+    /// Which PRG ROM is at $6000-$FFFF, in the 8K steps the finest boards
+    /// switch in
+    fn prg_banks(&self) -> [Option<usize>; 5] {
+        [0x6000, 0x8000, 0xa000, 0xc000, 0xe000].map(|addr| self.mapper.prg_rom_offset(addr))
+    }
+
+    /// The name for `addr`, from `labels` or else `auto_labels`. PRG ROM
+    /// is named by the bank mapped there now.
+    pub fn label(&self, addr: u16) -> Option<String> {
+        match self.mapper.prg_rom_offset(addr) {
+            Some(offset) => self
+                .labels
+                .prg_rom_name(offset)
+                .or_else(|| self.auto_labels.prg_rom_name(offset)),
+            None => self
+                .labels
+                .cpu_name(addr)
+                .or_else(|| self.auto_labels.cpu_name(addr)),
+        }
+    }
+
+    /// DMA reads happen on "get" cycles and writes on "put" cycles,
+    /// which alternate with the APU clock
+    fn is_get_cycle(&self) -> bool {
+        self.cycles.is_multiple_of(2)
+    }
+    /// 256 reads from `page` written to $2004, one read/write pair every 2 cycles.
+    /// DMC DMA takes priority over the reads, delaying OAM DMA by 2 cycles.
+    /// See https://www.nesdev.org/wiki/DMA
+    fn oam_dma(&mut self, page: u8) {
+        // Halt cycle
+        self.clock();
+        let mut latch = None;
+        let mut index = 0;
+        while index < 0x100 {
+            if self.is_get_cycle() {
+                if let Some(addr) = self.apu.dmc.dma_request() {
+                    let val = self.read(addr);
+                    self.apu.dmc.dma_fill(val);
+                } else {
+                    latch = Some(self.read(((page as u16) << 8) | index));
+                }
+            } else if let Some(val) = latch.take() {
+                self.write(0x2004, val);
+                index += 1;
+            }
+            // Put cycles without a pending write are alignment cycles
+            self.clock();
+        }
+    }
+    /// Halt, dummy and alignment cycles, then fetch the sample byte.
+    /// The halted CPU keeps reading its address during the DMA, which matters for
+    /// registers with read side effects. Back-to-back reads count as one access.
+    fn dmc_dma(&mut self, halted_read: Option<u16>) {
+        match halted_read {
+            Some(0x4016 | 0x4017) if !self.dmc_read_glitch => {}
+            Some(addr) => {
+                self.read(addr);
+            }
+            None => {}
+        }
+        self.clock();
+        self.clock();
+        if !self.is_get_cycle() {
+            self.clock();
+        }
+        if let Some(addr) = self.apu.dmc.dma_request() {
+            let val = self.read(addr);
+            self.apu.dmc.dma_fill(val);
+        }
+        self.clock();
+    }
+
+    /// Current mixed audio output
+    pub fn audio_sample(&self) -> f32 {
+        self.audio
+            .mix(self.apu.output(), self.mapper.expansion_audio())
+    }
+
+    /// Output rate of `read_audio`, in Hz
+    pub fn set_sample_rate(&mut self, rate: f64) {
+        let clock_rate = self.ppu.region.cpu_clock_rate();
+        self.audio_output.set_rates(clock_rate, rate);
+    }
+
+    /// Switch to the timing of `region`, for games whose header doesn't say
+    /// where they were made for, like most Dendy dumps
+    pub fn set_region(&mut self, region: Region) {
+        self.ppu.region = region;
+        self.apu.set_region(region);
+        self.pal_phase = 0;
+        let sample_rate = self.audio_output.sample_rate();
+        self.audio_output
+            .set_rates(region.cpu_clock_rate(), sample_rate);
+        if let Some(stems) = &mut self.stems {
+            let sample_rate = stems.sample_rate();
+            stems.set_rates(region.cpu_clock_rate(), sample_rate);
+        }
+    }
+
+    /// Also keep every APU channel and the cartridge's sound chip apart, at
+    /// `rate`, for [`Bus::read_stems`]. Starts from silence.
+    pub fn enable_stems(&mut self, rate: f64) {
+        let clock_rate = self.ppu.region.cpu_clock_rate();
+        self.stems = Some(StemOutput::new(
+            clock_rate,
+            rate,
+            self.audio.hardware_filters,
+        ));
+    }
+
+    pub fn disable_stems(&mut self) {
+        self.stems = None;
+    }
+
+    /// Take the samples of pulse 1, pulse 2, triangle, noise, DMC and the
+    /// cartridge's sound chip made since the last call, like
+    /// [`Bus::read_audio_stereo`]. Unmixed, in units where 1.0 is the loudest
+    /// the APU can get. Nothing unless [`Bus::enable_stems`] was called.
+    pub fn read_stems(&mut self, out: &mut [[f32; STEM_COUNT]]) -> usize {
+        self.stems.as_mut().map_or(0, |stems| stems.read(out))
+    }
+
+    /// Take the audio produced since the last call, returns how many samples
+    /// were written. Anything that doesn't fit in `out` is kept for next time,
+    /// up to a second of it, so frontends that never read don't pile it up.
+    pub fn read_audio_stereo(&mut self, out: &mut [[f32; 2]]) -> usize {
+        self.audio_output.read(out, &self.audio)
+    }
+
+    /// `read_audio_stereo` downmixed to mono
+    pub fn read_audio(&mut self, out: &mut [f32]) -> usize {
+        let mut stereo = [[0.0; 2]; 1024];
+        let mut written = 0;
+        while written < out.len() {
+            let chunk = (out.len() - written).min(stereo.len());
+            let count = self.read_audio_stereo(&mut stereo[..chunk]);
+            for (out, [left, right]) in out[written..].iter_mut().zip(&stereo[..count]) {
+                *out = (left + right) / 2.0;
+            }
+            written += count;
+            if count < chunk {
+                break;
+            }
+        }
+        written
+    }
+
+    /// Every device currently asserting IRQ, the CPU sees the combined level
+    pub fn irq_sources(&self) -> IrqSource {
+        let mut sources = IrqSource::empty();
+        sources.set(IrqSource::FRAME_COUNTER, self.apu.frame_irq);
+        sources.set(IrqSource::DMC, self.apu.dmc.irq_flag);
+        sources.set(IrqSource::MAPPER, self.mapper.irq());
+        sources
+    }
+    /// Read memory without triggering any side effects of the read
+    pub fn peek(&self, pos: u16) -> u8 {
+        if let Some(device) = self.devices.iter().find(|device| device.maps(pos)) {
+            return device.peek(pos);
+        }
+        match pos {
+            0x0000..=0x1FFF => self.cpu_ram[(pos & 0x07ff) as usize],
+            0x2000..=0x3FFF => self.ppu.peek_register(pos),
+            0x4000..=0x4014 | 0x4018..=0x401F => self.data_bus,
+            0x4015 => self.apu.peek_status() | (self.data_bus & 0x20),
+            0x4016 => self.peek_controller(0),
+            0x4017 => self.peek_controller(1),
+            0x4020..=0xFFFF => self.mapper.cpu_peek(pos),
+        }
+    }
+    /// $4016 or $4017 without shifting, see `read_controller`
+    fn peek_controller(&self, port: usize) -> u8 {
+        let controller = match &self.ports[port] {
+            Some(device) => device.peek(),
+            None => self.joypads[port].peek(),
+        };
+        let expansion = self
+            .expansion
+            .as_ref()
+            .map_or(0, |device| device.peek(port));
+        (self.data_bus & 0xe0) | controller | expansion
+    }
+    /// Controller `port`, or the device in its place, plus anything on the
+    /// Famicom expansion port
+    fn read_controller(&mut self, port: usize) -> u8 {
+        let controller = match &mut self.ports[port] {
+            Some(device) => device.read(),
+            None => self.joypads[port].read(),
+        };
+        let expansion = self
+            .expansion
+            .as_mut()
+            .map_or(0, |device| device.read(port));
+        // Only the low 5 bits are driven
+        (self.data_bus & 0xe0) | controller | expansion
+    }
+    /// The device in controller `port` if it is a `T`, for frontends feeding it input
+    pub fn port_mut<T: PortDevice>(&mut self, port: usize) -> Option<&mut T> {
+        let device: &mut dyn Any = self.ports[port].as_deref_mut()?;
+        device.downcast_mut()
+    }
+    /// The expansion port device if it is a `T`, for frontends feeding it input
+    pub fn expansion_mut<T: ExpansionDevice>(&mut self) -> Option<&mut T> {
+        let device: &mut dyn Any = self.expansion.as_deref_mut()?;
+        device.downcast_mut()
+    }
+    /// The first of [`Bus::devices`] that is a `T`
+    pub fn device_mut<T: BusDevice>(&mut self) -> Option<&mut T> {
+        self.devices.iter_mut().find_map(|device| {
+            let device: &mut dyn Any = device.as_mut();
+            device.downcast_mut()
+        })
+    }
+    pub fn peek_u16(&self, pos: u16) -> u16 {
+        let low = self.peek(pos) as u16;
+        let high = self.peek(pos.wrapping_add(1)) as u16;
+        (high << 8) | low
+    }
+    pub fn read(&mut self, pos: u16) -> u8 {
+        self.last_read = Some(pos);
+        if self.heatmap.enabled || self.diagnostics.enabled {
+            self.record_access(pos, false);
+        }
+        let device = self.devices.iter_mut().find(|device| device.maps(pos));
+        let val = match pos {
+            _ if let Some(device) = device => device.read(pos),
             // CPU
             0x0000..=0x1FFF => {
                 let masked = pos & 0x07ff;
                 self.cpu_ram[masked as usize]
             }
             // PPU
-            0x2000..=0x3FFF => {
-                let _masked = pos & 0x2007;
-                todo!("PPU")
-            }
-            0x8000..=0xFFFF => {
-                let mut pos = pos - 0x8000;
-                // if self.rom.prg_rom.len() == 0x4000 && pos >= 0x4000 {
-                //     pos %= 0x4000;
-                // }
-                if self.rom.prg_rom.len() == 0x4000 {
-                    pos %= 0x4000;
+            0x2000..=0x3FFF if pos & 7 == 7 && !self.breakpoints.is_empty() => {
+                let addr = self.ppu.vram_addr & 0x3fff;
+                let val = self.ppu.read_register(pos, self.mapper.as_mut());
+                // Reads below the palettes return the buffer, and fill it
+                // from `addr`
+                let read = if addr < 0x3f00 {
+                    self.ppu.read_buffer
+                } else {
+                    val
+                };
+                self.breakpoints.ppu(addr, read, false);
+                val
+            }
+            0x2000..=0x3FFF => self.ppu.read_register(pos, self.mapper.as_mut()),
+            // Write-only APU registers and the disabled test mode registers
+            0x4000..=0x4014 | 0x4018..=0x401F => self.data_bus,
+            // $4015 is read inside the CPU, so it doesn't drive the data bus
+            // and bit 5 is whatever was on it
+            0x4015 => self.apu.read_status() | (self.data_bus & 0x20),
+            // Controllers
+            0x4016 => {
+                self.controller_polled = true;
+                self.read_controller(0)
+            }
+            0x4017 => {
+                self.controller_polled = true;
+                self.read_controller(1)
+            }
+            // Cartridge
+            0x4020..=0xFFFF => {
+                if self.unmapped_access != UnmappedAccess::Ignore && self.mapper.unmapped(pos) {
+                    self.unmapped(pos, false);
                 }
-                self.rom.prg_rom[pos as usize]
-            }
-            // 0xfffc..=0xfffd => {
-            //     let masked = pos & 0x1;
-            //     self.pc_start_mem[masked as usize]
-            // }
-            _ => {
-                warn!("Unknown memory address 0x{pos:04X} accessed, ignoring...");
-                0
+                self.mapper.cpu_read(pos)
             }
+        };
+        if pos != 0x4015 {
+            self.data_bus = val;
         }
+        if self.bus_log.enabled() {
+            self.bus_log.record(self.cycles, pos, val, false);
+        }
+        val
     }
     pub fn write(&mut self, pos: u16, val: u8) {
+        self.last_read = None;
+        self.data_bus = val;
+        if self.bus_log.enabled() {
+            self.bus_log.record(self.cycles, pos, val, true);
+        }
+        if self.heatmap.enabled || self.diagnostics.enabled {
+            self.record_access(pos, true);
+        }
+        if let Some(device) = self.devices.iter_mut().find(|device| device.maps(pos)) {
+            device.write(pos, val);
+            return;
+        }
         match pos {
             // CPU
             0x0000..=0x1FFF => {
@@ -84,22 +657,63 @@ impl Bus {
             }
             // PPU
             0x2000..=0x3FFF => {
-                let _masked = pos & 0x2007;
-                todo!("PPU")
+                let addr = pos & 0x2007;
+                self.record_event(BusEventKind::PpuRegisterWrite { addr, val });
+                self.mapper.ppu_register_write(pos, val);
+                if addr == 0x2007 && !self.breakpoints.is_empty() {
+                    self.breakpoints.ppu(self.ppu.vram_addr, val, true);
+                }
+                self.ppu.write_register(pos, val, self.mapper.as_mut());
+            }
+            // APU
+            0x4000..=0x4013 | 0x4015 | 0x4017 => {
+                self.register_log.record(self.cycles, pos, val);
+                self.apu.write_register(pos, val);
             }
-            0x8000..=0xFFFF => {
-                panic!("Attempted to write into PRG rom")
+            0x4014 => self.oam_dma_page = Some(val),
+            0x4016 => {
+                for joypad in &mut self.joypads {
+                    joypad.write(val);
+                }
+                for device in self.ports.iter_mut().flatten() {
+                    device.write(val);
+                }
+                if let Some(device) = &mut self.expansion {
+                    device.write(val);
+                }
             }
-            _ => {
-                warn!("Unknown memory address 0x{pos:04X} accessed, ignoring...");
+            // Cartridge
+            0x4020..=0xFFFF => {
+                self.record_event(BusEventKind::MapperWrite { addr: pos, val });
+                if self.unmapped_access != UnmappedAccess::Ignore && self.mapper.unmapped(pos) {
+                    self.unmapped(pos, true);
+                }
+                if self.register_log.enabled() && self.mapper.audio_register(pos) {
+                    self.register_log.record(self.cycles, pos, val);
+                }
+                if self.diagnostics.enabled && self.mapper.ignores_write(pos) {
+                    self.diagnostics
+                        .report(DiagnosticKind::RomWrite { addr: pos, val });
+                }
+                if self.breakpoints.watches_banks() {
+                    let before = self.prg_banks();
+                    self.mapper.cpu_write(pos, val);
+                    if self.prg_banks() != before {
+                        self.breakpoints.bank_switched();
+                    }
+                } else {
+                    self.mapper.cpu_write(pos, val);
+                }
             }
+            // CPU test mode, disabled on retail consoles
+            0x4018..=0x401F => {}
         }
     }
-    pub fn read_u16(&self, pos: u16) -> u16 {
+    pub fn read_u16(&mut self, pos: u16) -> u16 {
         // dbg!(pos);
         let low = self.read(pos) as u16;
         // dbg!(low);
-        let high = self.read(pos + 1) as u16;
+        let high = self.read(pos.wrapping_add(1)) as u16;
         // dbg!(high);
         // println!("read u16 from 0x{pos:04x}: {:x}", (high << 8) | low);
         (high << 8) | low
@@ -108,7 +722,7 @@ impl Bus {
         let low = (val & 0xff) as u8;
         let high = (val >> 8) as u8;
         self.write(pos, low);
-        self.write(pos + 1, high);
+        self.write(pos.wrapping_add(1), high);
     }
     pub fn load_to(&mut self, pos: u16, slice: &[u8]) {
         let pos = pos as usize;
@@ -116,486 +730,160 @@ impl Bus {
     }
 }
 
-pub struct Cpu {
-    pub reg_a: u8,
-    pub reg_x: u8,
-    pub reg_y: u8,
-    pub stack_ptr: u8,
-    pub pc: u16,
-    pub status: Flags,
-    pub memory: Bus,
-    pub brk: bool,
-}
-
-const STACK_RESET: u8 = 0xfd;
-const STACK_START: u16 = 0x100;
+impl mos6502::Bus for Bus {
+    const CONFIG: Config = Config::RICOH_2A03;
 
-impl Cpu {
-    pub fn new(bus: Bus) -> Self {
-        let mut me = Self {
-            reg_a: 0,
-            reg_x: 0,
-            reg_y: 0,
-            stack_ptr: 0,
-            pc: 0,
-            status: Flags::empty(),
-            memory: bus,
-            brk: false,
-        };
-        me.reset();
-        me
-    }
-    pub fn reset(&mut self) {
-        self.status = Flags::INTERRUPTDISABLE | Flags::BREAK2;
-        self.reg_a = 0;
-        self.reg_x = 0;
-        self.reg_y = 0;
-        self.stack_ptr = STACK_RESET;
-        let pc = self.memory.read_u16(0xFFFC);
-        self.pc = pc;
+    fn read(&mut self, addr: u16) -> u8 {
+        Bus::read(self, addr)
     }
-    pub fn load_to(&mut self, start: u16, program: &[u8]) {
-        self.memory.load_to(start, program);
-        self.memory.write_u16(0xFFFC, 0x8000);
-    }
-    pub fn run_with_callback<F: FnMut(&mut Cpu)>(&mut self, mut f: F) {
-        while !self.brk {
-            f(self);
-            self.step();
-        }
+    fn write(&mut self, addr: u16, val: u8) {
+        Bus::write(self, addr, val)
     }
-    pub fn run(&mut self) {
-        self.run_with_callback(|_| {})
+    fn peek(&self, addr: u16) -> u8 {
+        Bus::peek(self, addr)
     }
-    fn update_zero_negative(&mut self, value: u8) {
-        self.status.set(Flags::ZERO, value == 0);
-        self.status.set(Flags::NEGATIVE, value & 0x80 != 0);
+    fn tick(&mut self, cycles: u16) {
+        Bus::tick(self, cycles)
     }
-}
-
-impl Cpu {
-    /// Get the value at the correct addressing mode
-    /// Assumes the `pc` is still set at the instruction beginning
-    fn get_addr_mode_dest(&self, addr_mode: AddrMode) -> u16 {
-        self.get_addr_mode_dest_ext(addr_mode, self.pc)
+    fn take_nmi(&mut self) -> bool {
+        let nmi = self.ppu.take_nmi();
+        if nmi {
+            self.record_event(BusEventKind::Nmi);
+        }
+        nmi
     }
-    fn get_addr_mode_dest_ext(&self, addr_mode: AddrMode, base: u16) -> u16 {
-        match addr_mode {
-            AddrMode::Implicit => panic!("Implicit should not need a memory load"),
-            AddrMode::Accumulator => panic!("Accumulator should not need a memory load"),
-            AddrMode::Immediate => base + 1,
-            AddrMode::ZeroPage => self.memory.read(base + 1) as u16,
-            AddrMode::ZeroPageX => self.memory.read(base + 1).wrapping_add(self.reg_x) as u16,
-            AddrMode::ZeroPageY => self.memory.read(base + 1).wrapping_add(self.reg_y) as u16,
-            AddrMode::Relative => base + 1,
-            AddrMode::Absolute => self.memory.read_u16(base + 1),
-            AddrMode::AbsoluteX => {
-                self.memory
-                    .read_u16(base + 1)
-                    .wrapping_add(self.reg_x as u16)
-                // + self.status.contains(Flags::CARRY) as u16
-            }
-            AddrMode::AbsoluteY => {
-                self.memory
-                    .read_u16(base + 1)
-                    .wrapping_add(self.reg_y as u16)
-                // + self.status.contains(Flags::CARRY) as u16
-            }
-            AddrMode::Indirect => {
-                let indirect_addr = self.memory.read_u16(self.pc + 1);
-                if indirect_addr as u8 == 0xff {
-                    let low = self.memory.read(indirect_addr) as u16;
-                    let high = self.memory.read(indirect_addr & 0xff00) as u16;
-                    (high << 8) | low
-                } else {
-                    self.memory.read_u16(indirect_addr)
-                }
-                // panic!("Should be implemented outside")
-                // let imm = self.memory.read_u16(base + 1);
-                // self.memory.read_u16(imm)
-            }
-            AddrMode::IndexedIndirect => {
-                let addr = self.memory.read(base + 1).wrapping_add(self.reg_x);
-                let low = self.memory.read(addr as u16);
-                let high = self.memory.read(addr.wrapping_add(1) as u16);
-                ((high as u16) << 8) | (low as u16)
-            }
-            AddrMode::IndirectIndexed => {
-                let base_loc = self.memory.read(base + 1);
-                let low = self.memory.read(base_loc as u16);
-                let high = self.memory.read(base_loc.wrapping_add(1) as u16);
-                let base = ((high as u16) << 8) | (low as u16);
-                base.wrapping_add(self.reg_y as u16)
-            }
+    fn irq(&mut self) -> bool {
+        let irq = !self.irq_sources().is_empty();
+        if irq {
+            self.record_event(BusEventKind::Irq);
         }
+        irq
     }
-    pub fn step(&mut self) {
-        // trace!(
-        //     "PC: {:02X}, values {:02X}, {:02X}, {:02X}",
-        //     self.pc,
-        //     self.memory.read(self.pc),
-        //     self.memory.read(self.pc + 1),
-        //     self.memory.read(self.pc + 2)
-        // );
-        let instruction_byte = self.memory.read(self.pc);
-        let (opcode, addr_mode, inst_info) = decode(instruction_byte);
-        // trace!("{opcode:?}, {addr_mode:?}, from {instruction_byte:02X}");
-        match (opcode, addr_mode) {
-            (Opcode::ADC, addr_mode) => {
-                let addr = self.get_addr_mode_dest(addr_mode);
-                let val = self.memory.read(addr);
-                self.add_a(val);
-            }
-            (Opcode::AND, addr_mode) => {
-                let addr = self.get_addr_mode_dest(addr_mode);
-                let val = self.memory.read(addr);
-                self.reg_a &= val;
-                self.update_zero_negative(self.reg_a);
-            }
-            (Opcode::ASL, addr_mode) => {
-                if let AddrMode::Accumulator = addr_mode {
-                    let carry = self.reg_a & 0x80 != 0;
-                    self.reg_a <<= 1;
-                    self.status.set(Flags::CARRY, carry);
-                    self.update_zero_negative(self.reg_a);
-                } else {
-                    let addr = self.get_addr_mode_dest(addr_mode);
-                    let val = self.memory.read(addr);
-                    let carry = val & 0x80 != 0;
-                    let new_val = val << 1;
-                    self.memory.write(addr, new_val);
-                    self.status.set(Flags::CARRY, carry);
-                    self.update_zero_negative(new_val);
-                }
-            }
-            (Opcode::BCC, addr_mode) => {
-                self.branch_impl(addr_mode, Flags::CARRY, false);
-            }
-            (Opcode::BCS, addr_mode) => {
-                self.branch_impl(addr_mode, Flags::CARRY, true);
-            }
-            (Opcode::BEQ, addr_mode) => {
-                self.branch_impl(addr_mode, Flags::ZERO, true);
-            }
-            (Opcode::BIT, addr_mode) => {
-                let addr = self.get_addr_mode_dest(addr_mode);
-                let val = self.memory.read(addr);
-                self.status.set(Flags::OVERFLOW, val & 0b0100_0000 != 0);
-                self.status.set(Flags::NEGATIVE, val & 0b1000_0000 != 0);
-                self.status.set(Flags::ZERO, self.reg_a & val == 0);
-            }
-            (Opcode::BMI, addr_mode) => {
-                self.branch_impl(addr_mode, Flags::NEGATIVE, true);
-            }
-            (Opcode::BNE, addr_mode) => {
-                self.branch_impl(addr_mode, Flags::ZERO, false);
-            }
-            (Opcode::BPL, addr_mode) => {
-                self.branch_impl(addr_mode, Flags::NEGATIVE, false);
-            }
-            (Opcode::BRK, _addr_mode) => {
-                self.status.insert(Flags::BREAK);
-                self.brk = true;
-                return;
-                // self.push_stack_u16(self.pc);
-                // self.push_stack(self.status.bits());
-                // self.pc = self.memory.read_u16(0xfffe);
-            }
-            (Opcode::BVC, addr_mode) => {
-                self.branch_impl(addr_mode, Flags::OVERFLOW, false);
-            }
-            (Opcode::BVS, addr_mode) => {
-                self.branch_impl(addr_mode, Flags::OVERFLOW, true);
-            }
-            (Opcode::CLC, _addr_mode) => {
-                self.status.remove(Flags::CARRY);
-            }
-            (Opcode::CLD, _addr_mode) => {
-                self.status.remove(Flags::DECIMAL);
-            }
-            (Opcode::CLI, _addr_mode) => {
-                self.status.remove(Flags::INTERRUPTDISABLE);
-            }
-            (Opcode::CLV, _addr_mode) => {
-                self.status.remove(Flags::OVERFLOW);
-            }
-            (Opcode::CMP, addr_mode) => {
-                let addr = self.get_addr_mode_dest(addr_mode);
-                let m = self.memory.read(addr);
-                self.status.set(Flags::CARRY, self.reg_a >= m);
-                self.update_zero_negative(self.reg_a.wrapping_sub(m));
-            }
-            (Opcode::CPX, addr_mode) => {
-                let addr = self.get_addr_mode_dest(addr_mode);
-                let m = self.memory.read(addr);
-                self.status.set(Flags::CARRY, self.reg_x >= m);
-                self.update_zero_negative(self.reg_x.wrapping_sub(m));
-            }
-            (Opcode::CPY, addr_mode) => {
-                let addr = self.get_addr_mode_dest(addr_mode);
-                let m = self.memory.read(addr);
-                self.status.set(Flags::CARRY, self.reg_y >= m);
-                self.update_zero_negative(self.reg_y.wrapping_sub(m));
-            }
-            (Opcode::DEC, addr_mode) => {
-                let addr = self.get_addr_mode_dest(addr_mode);
-                let val = self.memory.read(addr);
-                let val_m = val.wrapping_sub(1);
-                self.memory.write(addr, val_m);
-                self.update_zero_negative(val_m);
-            }
-            (Opcode::DEX, _addr_mode) => {
-                self.reg_x = self.reg_x.wrapping_sub(1);
-                self.update_zero_negative(self.reg_x);
-            }
-            (Opcode::DEY, _addr_mode) => {
-                self.reg_y = self.reg_y.wrapping_sub(1);
-                self.update_zero_negative(self.reg_y);
-            }
-            (Opcode::EOR, addr_mode) => {
-                let addr = self.get_addr_mode_dest(addr_mode);
-                self.reg_a ^= self.memory.read(addr);
-                self.update_zero_negative(self.reg_a);
-            }
-            (Opcode::INC, addr_mode) => {
-                let addr = self.get_addr_mode_dest(addr_mode);
-                let val = self.memory.read(addr);
-                let val_m = val.wrapping_add(1);
-                self.memory.write(addr, val_m);
-                self.update_zero_negative(val_m);
-            }
-            (Opcode::INX, _addr_mode) => {
-                self.reg_x = self.reg_x.wrapping_add(1);
-                self.update_zero_negative(self.reg_x);
-            }
-            (Opcode::INY, _addr_mode) => {
-                self.reg_y = self.reg_y.wrapping_add(1);
-                self.update_zero_negative(self.reg_y);
-            }
-            (Opcode::JMP, addr_mode) => match addr_mode {
-                AddrMode::Absolute => {
-                    let addr = self.get_addr_mode_dest(addr_mode);
-                    self.pc = addr;
-                    return;
-                }
-                AddrMode::Indirect => {
-                    // let indirect_addr = self.memory.read_u16(self.pc + 1);
-                    // let new_pc = if indirect_addr as u8 == 0xff {
-                    //     let low = self.memory.read(indirect_addr) as u16;
-                    //     let high = self.memory.read(indirect_addr & 0xff00) as u16;
-                    //     (high << 8) | low
-                    // } else {
-                    //     self.memory.read_u16(indirect_addr)
-                    // };
-                    let addr = self.get_addr_mode_dest(AddrMode::Indirect);
-                    self.pc = addr;
-                    return;
-                }
-                _ => unreachable!(),
-            },
-            (Opcode::JSR, addr_mode) => {
-                match addr_mode {
-                    AddrMode::Absolute => (),
-                    _ => panic!("Invalid addr mode for JSR: {addr_mode:?}"),
-                }
-                let return_loc = self.pc + 3; // jsr is 3 bytes
-                let fn_addr = self.memory.read_u16(self.pc + 1); // absolute
-                self.push_stack_u16(return_loc - 1); // rti is 1 byte so it'll be incremented
-                self.pc = fn_addr;
-                return;
-            }
-            (Opcode::LDA, addr_mode) => {
-                let addr = self.get_addr_mode_dest(addr_mode);
-                let val = self.memory.read(addr);
-                self.reg_a = val;
-                self.update_zero_negative(val);
-            }
-            (Opcode::LDX, addr_mode) => {
-                let addr = self.get_addr_mode_dest(addr_mode);
-                let val = self.memory.read(addr);
-                self.reg_x = val;
-                self.update_zero_negative(val);
-            }
-            (Opcode::LDY, addr_mode) => {
-                let addr = self.get_addr_mode_dest(addr_mode);
-                let val = self.memory.read(addr);
-                self.reg_y = val;
-                self.update_zero_negative(val);
-            }
-            (Opcode::LSR, addr_mode) => {
-                if let AddrMode::Accumulator = addr_mode {
-                    let carry = self.reg_a & 0x1 != 0;
-                    self.reg_a >>= 1;
-                    self.status.set(Flags::CARRY, carry);
-                    self.update_zero_negative(self.reg_a);
-                } else {
-                    let addr = self.get_addr_mode_dest(addr_mode);
-                    let val = self.memory.read(addr);
-                    let carry = val & 0x1 != 0;
-                    let new_val = val >> 1;
-                    self.memory.write(addr, new_val);
-                    self.status.set(Flags::CARRY, carry);
-                    self.update_zero_negative(new_val);
-                }
-            }
-            (Opcode::NOP, _addr_mode) => {}
-            (Opcode::ORA, addr_mode) => {
-                let addr = self.get_addr_mode_dest(addr_mode);
-                self.reg_a |= self.memory.read(addr);
-                self.update_zero_negative(self.reg_a);
-            }
-            (Opcode::PHA, _addr_mode) => {
-                self.push_stack(self.reg_a);
-            }
-            (Opcode::PHP, _addr_mode) => {
-                let mut status = self.status.clone();
-                status.insert(Flags::BREAK);
-                status.insert(Flags::BREAK2);
-                self.push_stack(status.bits());
-            }
-            (Opcode::PLA, _addr_mode) => {
-                self.reg_a = self.pop_stack();
-                self.update_zero_negative(self.reg_a);
-            }
-            (Opcode::PLP, _addr_mode) => {
-                self.status = Flags::from_bits_retain(self.pop_stack());
-                self.status.remove(Flags::BREAK);
-                self.status.insert(Flags::BREAK2);
-            }
-            (Opcode::ROL, addr_mode) => {
-                if let AddrMode::Accumulator = addr_mode {
-                    let carry = self.reg_a & 0x80 != 0;
-                    self.reg_a <<= 1;
-                    self.reg_a += self.status.contains(Flags::CARRY) as u8;
-                    self.status.set(Flags::CARRY, carry);
-                    self.update_zero_negative(self.reg_a);
-                } else {
-                    let addr = self.get_addr_mode_dest(addr_mode);
-                    let val = self.memory.read(addr);
-                    let carry = val & 0x80 != 0;
-                    let mut new_val = val << 1;
-                    new_val += self.status.contains(Flags::CARRY) as u8;
-                    self.memory.write(addr, new_val);
-                    self.status.set(Flags::CARRY, carry);
-                    self.update_zero_negative(new_val);
-                }
-            }
-            (Opcode::ROR, addr_mode) => {
-                if let AddrMode::Accumulator = addr_mode {
-                    let carry = self.reg_a & 0x1 != 0;
-                    self.reg_a >>= 1;
-                    self.reg_a += (self.status.contains(Flags::CARRY) as u8) << 7;
-                    self.status.set(Flags::CARRY, carry);
-                    self.update_zero_negative(self.reg_a);
-                } else {
-                    let addr = self.get_addr_mode_dest(addr_mode);
-                    let val = self.memory.read(addr);
-                    let carry = val & 0x1 != 0;
-                    let mut new_val = val >> 1;
-                    new_val += (self.status.contains(Flags::CARRY) as u8) << 7;
-                    self.memory.write(addr, new_val);
-                    self.status.set(Flags::CARRY, carry);
-                    self.update_zero_negative(new_val);
-                }
-            }
-            (Opcode::RTI, _addr_mode) => {
-                self.status = Flags::from_bits_retain(self.pop_stack());
-                self.status.remove(Flags::BREAK);
-                self.status.insert(Flags::BREAK2);
-                self.pc = self.pop_stack_u16() - 1;
-            }
-            (Opcode::RTS, _addr_mode) => {
-                self.pc = self.pop_stack_u16();
-            }
-            (Opcode::SBC, addr_mode) => {
-                let addr = self.get_addr_mode_dest(addr_mode);
-                let val = self.memory.read(addr);
-                self.add_a(!val);
-            }
-            (Opcode::SEC, _addr_mode) => self.status.insert(Flags::CARRY),
-            (Opcode::SED, _addr_mode) => self.status.insert(Flags::DECIMAL),
-            (Opcode::SEI, _addr_mode) => self.status.insert(Flags::INTERRUPTDISABLE),
-            (Opcode::STA, _addr_mode) => {
-                let addr = self.get_addr_mode_dest(addr_mode);
-                self.memory.write(addr, self.reg_a);
-            }
-            (Opcode::STX, addr_mode) => {
-                let addr = self.get_addr_mode_dest(addr_mode);
-                self.memory.write(addr, self.reg_x);
-            }
-            (Opcode::STY, addr_mode) => {
-                let addr = self.get_addr_mode_dest(addr_mode);
-                self.memory.write(addr, self.reg_y);
-            }
-            (Opcode::TAX, _addr_mode) => {
-                self.reg_x = self.reg_a;
-                self.update_zero_negative(self.reg_x);
-            }
-            (Opcode::TAY, _addr_mode) => {
-                self.reg_y = self.reg_a;
-                self.update_zero_negative(self.reg_y);
-            }
-            (Opcode::TSX, _addr_mode) => {
-                self.reg_x = self.stack_ptr;
-                self.update_zero_negative(self.reg_x);
-            }
-            (Opcode::TXA, _addr_mode) => {
-                self.reg_a = self.reg_x;
-                self.update_zero_negative(self.reg_a);
+
+    fn before_instruction(cpu: &mut Cpu, resuming: bool) -> bool {
+        if cpu.memory.diagnostics.enabled && !resuming {
+            let line = trace::trace_labeled(cpu);
+            cpu.memory.diagnostics.begin_instruction(cpu.pc, line);
+            if let Some(offset) = cpu.memory.mapper.prg_rom_offset(cpu.pc) {
+                cpu.memory.diagnostics.execute(offset);
             }
-            (Opcode::TXS, _addr_mode) => {
-                self.stack_ptr = self.reg_x;
-                // self.update_zero_negative(self.stack_ptr);
+            if cpu.check_return() {
+                cpu.memory.breakpoints.other_stop();
+                return true;
             }
-            (Opcode::TYA, _addr_mode) => {
-                self.reg_a = self.reg_y;
-                self.update_zero_negative(self.reg_a);
-            } // _ => todo!(),
         }
-        self.pc += inst_info.size;
+        if !cpu.memory.breakpoints.is_empty() {
+            let opcode = cpu.memory.peek(cpu.pc);
+            let offset = cpu.memory.mapper.prg_rom_offset(cpu.pc);
+            return cpu
+                .memory
+                .breakpoints
+                .instruction(cpu.pc, opcode, offset, resuming);
+        }
+        false
     }
-
-    fn branch_impl(&mut self, addr_mode: AddrMode, flag: Flags, set: bool) {
-        let addr = self.get_addr_mode_dest(addr_mode);
-        let val = self.memory.read(addr) as i8 as i16;
-        // contains, set => true
-        // !contains, set => false
-        // contains, !set => false
-        // !contains, !set => true
-        // xor truth table
-        if !self.status.contains(flag) ^ set {
-            self.pc = self.pc.wrapping_add_signed(val);
+    fn decoded(cpu: &mut Cpu, opcode: Opcode, addr_mode: AddrMode, size: u16) {
+        if cpu.memory.heatmap.enabled || cpu.memory.diagnostics.enabled {
+            cpu.record_operands(opcode, addr_mode, size);
+        }
+        if let Some(val) = cpu.last_fetch(addr_mode) {
+            cpu.memory.data_bus = val;
         }
     }
-
-    fn add_a(&mut self, val: u8) {
-        let sum = self.reg_a as u16 + val as u16 + self.status.contains(Flags::CARRY) as u16;
-        self.status.set(Flags::CARRY, sum > 0xFF);
-        let res = sum as u8;
-        // https://www.righto.com/2012/12/the-6502-overflow-flag-explained.html
-        let overflow = (val ^ res) & (res ^ self.reg_a) & 0x80 != 0;
-        self.status.set(Flags::OVERFLOW, overflow);
-        self.reg_a = res;
-        self.update_zero_negative(res);
+    fn after_instruction(&mut self) -> bool {
+        self.breakpoints.instruction_done()
+    }
+    fn after_interrupt(&mut self, nmi: bool) -> bool {
+        self.breakpoints.interrupt(nmi)
     }
+    fn call(&mut self, call: Call) {
+        self.diagnostics.call(call);
+    }
+    fn code_at(&mut self, addr: u16, kind: AutoLabelKind) {
+        self.record_label(addr, kind);
+    }
+    fn stack_wrapped(&mut self, overflow: bool) {
+        self.diagnostics.report(match overflow {
+            true => DiagnosticKind::StackOverflow,
+            false => DiagnosticKind::StackUnderflow,
+        });
+    }
+}
 
-    fn push_stack(&mut self, val: u8) {
-        self.memory.write(STACK_START + self.stack_ptr as u16, val);
-        self.stack_ptr = self.stack_ptr.wrapping_sub(1);
+/// The CPU on the NES's bus, see [`mos6502`]
+pub type Cpu = mos6502::Cpu<Bus>;
+
+impl Cpu {
+    pub fn load_to(&mut self, start: u16, program: &[u8]) {
+        self.memory.load_to(start, program);
+        self.memory.write_u16(0xFFFC, 0x8000);
     }
-    fn pop_stack(&mut self) -> u8 {
-        self.stack_ptr = self.stack_ptr.wrapping_add(1);
-        self.memory.read(STACK_START + self.stack_ptr as u16)
+    /// Operands and pointers are peeked rather than read, so the heatmap and
+    /// diagnostics see them here. Immediate operands, branch offsets and JSR targets
+    /// are read already.
+    fn record_operands(&mut self, opcode: Opcode, addr_mode: AddrMode, size: u16) {
+        let operands = match (opcode, addr_mode) {
+            (_, AddrMode::Immediate | AddrMode::Relative) | (Opcode::JSR, _) => 0,
+            _ => size - 1,
+        };
+        for offset in 1..=operands {
+            self.memory
+                .record_access(self.pc.wrapping_add(offset), false);
+        }
+        let pointer = match addr_mode {
+            // The high byte doesn't carry into the next page
+            AddrMode::Indirect => self.memory.peek_u16(self.pc.wrapping_add(1)),
+            AddrMode::IndexedIndirect => self
+                .memory
+                .peek(self.pc.wrapping_add(1))
+                .wrapping_add(self.reg_x) as u16,
+            AddrMode::IndirectIndexed => self.memory.peek(self.pc.wrapping_add(1)) as u16,
+            _ => return,
+        };
+        let high = (pointer & 0xff00) | (pointer as u8).wrapping_add(1) as u16;
+        self.memory.record_access(pointer, false);
+        self.memory.record_access(high, false);
     }
-    fn push_stack_u16(&mut self, val: u16) {
-        let low = val as u8;
-        let high = (val >> 8) as u8;
-        self.push_stack(high);
-        self.push_stack(low);
+    /// The last operand or pointer byte fetched before the instruction goes
+    /// to its address. Those are peeked, so this puts it on the data bus for
+    /// open bus reads, which mostly see the high byte of the address.
+    fn last_fetch(&self, addr_mode: AddrMode) -> Option<u8> {
+        match addr_mode {
+            AddrMode::ZeroPage | AddrMode::ZeroPageX | AddrMode::ZeroPageY => {
+                Some(self.memory.peek(self.pc.wrapping_add(1)))
+            }
+            AddrMode::Absolute | AddrMode::AbsoluteX | AddrMode::AbsoluteY | AddrMode::Indirect => {
+                Some(self.memory.peek(self.pc.wrapping_add(2)))
+            }
+            AddrMode::IndexedIndirect | AddrMode::IndirectIndexed => {
+                let base = self.get_addr_mode_dest(addr_mode);
+                let y = match addr_mode {
+                    AddrMode::IndirectIndexed => self.reg_y as u16,
+                    _ => 0,
+                };
+                Some((base.wrapping_sub(y) >> 8) as u8)
+            }
+            _ => None,
+        }
     }
-    fn pop_stack_u16(&mut self) -> u16 {
-        let low = self.pop_stack();
-        let high = self.pop_stack();
-        ((high as u16) << 8) | (low as u16)
+    /// Check where an RTS or RTI at PC would go against the call stack,
+    /// returns whether to stop before it
+    fn check_return(&mut self) -> bool {
+        let interrupt = match self.memory.peek(self.pc) {
+            0x40 => true,
+            0x60 => false,
+            _ => return false,
+        };
+        let stack = |offset: u8| STACK_START + self.stack_ptr.wrapping_add(offset) as u16;
+        // RTI pulls status first, RTS adds 1 to the address
+        let (low, high, after) = if interrupt {
+            (stack(2), stack(3), 0)
+        } else {
+            (stack(1), stack(2), 1)
+        };
+        let addr = u16::from_le_bytes([self.memory.peek(low), self.memory.peek(high)]);
+        self.memory
+            .diagnostics
+            .return_to(addr.wrapping_add(after), self.stack_ptr, interrupt)
     }
 }
@@ -1,139 +1,684 @@
+use std::collections::VecDeque;
 use std::num::NonZeroU32;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime};
 
-use log::Level;
+use clock::EmulatedClock;
+use config::{Action, Dirs, GameSettings, HotkeyMap, InputBindings, InputDevice, RecentRoms};
+use console::Console;
+use error::{NetplayError, StateError};
+use expansion::{ExpansionDevice, FamilyKeyboard, Microphone, Multitap};
+use joypad::{Buttons, FourScore, PortDevice, SnesMouse, Vaus, Zapper};
+use labels::Labels;
+use log::{Level, error, info, warn};
+use movie::Movie;
 use nes::*;
+use netplay::{Mode, Session, TcpTransport};
+use osd::Osd;
+use pacing::FramePacer;
+use ppu::RenderMode;
 use rom::Rom;
+use savestate::{Autosave, BatterySave, SLOT_COUNT, SaveSlots, SaveState, Thumbnail};
+use triggers::{Macro, Triggers};
+use video::{
+    BlendMode, FRAME_HEIGHT, FRAME_WIDTH, FilterChain, Frame, FrameBlend, FramePool, Overscan,
+};
 use winit::{
-    dpi::{LogicalSize, PhysicalSize, Size},
-    event::{Event, KeyEvent, WindowEvent},
+    dpi::{LogicalSize, Size},
+    event::{DeviceEvent, ElementState, Event, KeyEvent, MouseButton, WindowEvent},
     event_loop::{ControlFlow, EventLoop},
-    keyboard::{Key, KeyCode, NamedKey, PhysicalKey},
+    keyboard::{KeyCode, PhysicalKey},
+    window::{CursorGrabMode, Fullscreen, Window},
 };
 
+mod osd;
 mod winit_app;
 fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if args.first().map(String::as_str) == Some("verify") {
+        let code = match verify(&args[1..]) {
+            Ok(true) => 0,
+            Ok(false) => 1,
+            Err(err) => {
+                eprintln!("{err}");
+                2
+            }
+        };
+        std::process::exit(code);
+    }
+    if args.first().map(String::as_str) == Some("disasm") {
+        if let Err(err) = disasm(&args[1..]) {
+            eprintln!("{err}");
+            std::process::exit(2);
+        }
+        return;
+    }
+    if args.first().map(String::as_str) == Some("stems") {
+        if let Err(err) = stems(&args[1..]) {
+            eprintln!("{err}");
+            std::process::exit(2);
+        }
+        return;
+    }
+    if args.first().map(String::as_str) == Some("registers") {
+        if let Err(err) = registers(&args[1..]) {
+            eprintln!("{err}");
+            std::process::exit(2);
+        }
+        return;
+    }
     simple_logger::init_with_level(Level::Debug).unwrap();
+    let mut args = match parse_args() {
+        Ok(args) => Some(args),
+        Err(err) => {
+            eprintln!("{err}");
+            std::process::exit(2);
+        }
+    };
+    // Before the window opens, which would sit there blank while the host
+    // waits for the other player
+    let mut netplay = args
+        .as_mut()
+        .and_then(|args| args.netplay.take())
+        .map(|netplay| {
+            netplay.connect().unwrap_or_else(|err| {
+                eprintln!("netplay: {err}");
+                std::process::exit(2);
+            })
+        });
     let event_loop = EventLoop::new().unwrap();
 
     let app = winit_app::WinitAppBuilder::with_init(
-        |elwt| {
+        move |elwt| {
             let window = winit_app::make_window(elwt, |w| {
-                w.with_inner_size(Size::Physical(PhysicalSize::new(320, 320)))
+                w.with_inner_size(Size::Logical(LogicalSize::new(
+                    WINDOW_SIZE.0,
+                    WINDOW_SIZE.1,
+                )))
             });
             let context = softbuffer::Context::new(window.clone()).unwrap();
-            let rom = Rom::new(GAME_CODE).unwrap();
-            let bus = Bus::new(rom);
-            let mut cpu = Cpu::new(bus);
-            cpu.reset();
-            let doublebuffer = [0u32; 1024];
-            (window, context, cpu, doublebuffer)
-        },
-        |_elwt, (window, context, _cpu, _doublebuffer)| {
-            softbuffer::Surface::new(context, window.clone()).unwrap()
+            let args = args.take().expect("the window is only made once");
+            info!(
+                "RetroAchievements hash: {}",
+                achievements::rom_hash(&args.rom)
+            );
+            let game = game_name(&args.rom, &args.rom_path);
+            let compat = args.rom.compat();
+            for issue in &compat.issues {
+                warn!("{issue}");
+            }
+            let mut console = Console::new(args.rom);
+            console.bus_mut().ppu.render_mode = args.render_mode;
+            console.bus_mut().ports = args.ports;
+            console.bus_mut().expansion = args.expansion;
+            if let Some(cdl) = args.diagnostics {
+                let diagnostics = &mut console.bus_mut().diagnostics;
+                diagnostics.enabled = true;
+                diagnostics.set_cdl(cdl);
+                let cpu = &mut console.cpu;
+                cpu.memory.auto_labels.enabled = true;
+                cpu.reset();
+            }
+            if let Err(err) = args.settings.apply(&mut console) {
+                warn!("{err}");
+            }
+            let mut files = GameFiles::new(&args.dirs, &args.rom_path, args.autosave_seconds * 60);
+            files.watch = args.watch;
+            if netplay.is_some() {
+                files.battery = None;
+            }
+            if let Some(battery) = &files.battery {
+                match battery.load(&mut console) {
+                    Ok(true) => info!("Loaded {}", battery.path().display()),
+                    Ok(false) => {}
+                    Err(err) => warn!("Couldn't load the battery save: {err}"),
+                }
+            }
+            capture_mouse(&window, console.bus_mut(), true);
+            console.cpu.reset();
+            let screen = Screen::new(console.refresh_rate());
+            let slots = Slots::new(&Dirs::file(&args.dirs.states, &args.rom_path));
+            let mut osd = Osd::new();
+            let mut bindings = InputBindings::load(&args.dirs.config).unwrap_or_else(|err| {
+                warn!("Couldn't load the input bindings, using the defaults: {err}");
+                InputBindings::defaults(&args.dirs.config)
+            });
+            bindings.override_with(&args.settings);
+            let controls = Controls {
+                hotkeys: HotkeyMap::new(&bindings),
+                bindings,
+                held: Buttons::empty(),
+            };
+            // The details are logged, they don't fit on screen
+            if compat.may_break() {
+                osd.show("This game may not work");
+            }
+            if args.resume {
+                match files.autosave.load() {
+                    Ok(Some(state)) => match console.load_state(&state) {
+                        Ok(()) => {
+                            info!("Resumed from {}", files.autosave.path().display());
+                            osd.show("Resumed");
+                        }
+                        Err(err) => warn!("Couldn't resume: {err}"),
+                    },
+                    Ok(None) => warn!("No autosave to resume from"),
+                    Err(err) => warn!("Couldn't resume: {err}"),
+                }
+            }
+            let when_met = WhenMet {
+                save: args
+                    .save_when
+                    .map(|source| console.add_condition(&source).unwrap()),
+                pause: args
+                    .pause_when
+                    .map(|source| console.add_condition(&source).unwrap()),
+            };
+            let macros = Macros::new(args.settings);
+            let mut filters = FilterChain::new();
+            // The region is known once the game's settings are applied
+            let region = console.bus().ppu.region;
+            filters.push(args.overscan.unwrap_or(Overscan::for_region(region)));
+            if let Some(mode) = args.blend {
+                filters.push(FrameBlend::new(mode));
+            }
+            // Scaling up the rest of the way to the window is left to drawing,
+            // which knows the window's size
+            #[cfg(feature = "upscale")]
+            match args.upscaler {
+                Some(Upscaler::Hq2x) => filters.push(video::upscale::Hq2x),
+                Some(Upscaler::Xbr(factor)) => filters.push(video::upscale::Xbr::new(factor)),
+                None => {}
+            }
+            // Text currently in the window title
+            let title = String::new();
+            let dpi_scale = window.scale_factor();
+            let mut playback = Playback::new(args.undo_seconds * console.refresh_rate());
+            playback.netplay = netplay.take();
+            (
+                window,
+                context,
+                console,
+                screen,
+                slots,
+                files,
+                title,
+                filters,
+                game,
+                osd,
+                args.triggers,
+                controls,
+                playback,
+                when_met,
+                macros,
+                args.background,
+                dpi_scale,
+            )
         },
+        |_elwt,
+         (
+            window,
+            context,
+            _console,
+            _screen,
+            _slots,
+            _files,
+            _title,
+            _filters,
+            _game,
+            _osd,
+            _triggers,
+            _controls,
+            _playback,
+            _when_met,
+            _macros,
+            _background,
+            _dpi_scale,
+        )| { softbuffer::Surface::new(context, window.clone()).unwrap() },
     )
     .with_event_handler(
-        |(window, _context, cpu, doublebuffer), surface, event, elwt| {
-            elwt.set_control_flow(ControlFlow::Poll);
-            window.request_redraw();
-
+        |(
+            window,
+            _context,
+            console,
+            screen,
+            slots,
+            files,
+            title,
+            filters,
+            game,
+            osd,
+            triggers,
+            controls,
+            playback,
+            when_met,
+            macros,
+            background,
+            dpi_scale,
+        ),
+         surface,
+         event,
+         elwt| {
             let surface = surface.unwrap();
 
+            if let Event::WindowEvent {
+                event:
+                    WindowEvent::KeyboardInput {
+                        event:
+                            KeyEvent {
+                                physical_key: PhysicalKey::Code(key),
+                                state,
+                                ..
+                            },
+                        ..
+                    },
+                ..
+            } = &event
+            {
+                let pressed = *state == ElementState::Pressed;
+                let name = key_name(*key);
+                let button = controls.bindings.action(InputDevice::Keyboard, &name);
+                if let Some(button) = button.and_then(Action::button) {
+                    controls.held.set(button, pressed);
+                    let bus = console.bus_mut();
+                    match bus.port_mut::<FourScore>(0) {
+                        Some(four_score) => four_score.buttons[0].set(button, pressed),
+                        None => bus.joypads[0].buttons.set(button, pressed),
+                    }
+                }
+                match controls.hotkeys.get(InputDevice::Keyboard, &name) {
+                    Some(Action::Rewind) if playback.netplay.is_none() => {
+                        playback.rewinding = pressed
+                    }
+                    Some(Action::FastForward) => playback.fast_forward = pressed,
+                    _ => {}
+                }
+                let bus = console.bus_mut();
+                if let Some(keyboard) = bus.expansion_mut::<FamilyKeyboard>() {
+                    if let Some(name) = family_key(*key) {
+                        keyboard.set_key(name, pressed);
+                    }
+                } else if let Some(mic) = bus.expansion_mut::<Microphone>()
+                    && *key == KeyCode::KeyM
+                {
+                    mic.loud = pressed;
+                }
+            }
+
             match event {
                 Event::WindowEvent {
                     window_id: _winid,
                     event: WindowEvent::RedrawRequested,
                 } => {
-                    cpu.memory.write(0xfe, fastrand::u8(1..16));
+                    let run = !background.paused()
+                        && !background.throttled()
+                        && playback.next_frame(console);
+                    if run {
+                        macros.record(console, controls.held);
+                    }
+                    triggers.apply(console.bus_mut());
 
+                    // Drawn in physical pixels, so the picture is as sharp on
+                    // high DPI displays
                     let size = window.inner_size();
-                    if let (Some(_width), Some(_height)) =
+                    if let (Some(width), Some(height)) =
                         (NonZeroU32::new(size.width), NonZeroU32::new(size.height))
                     {
+                        surface.resize(width, height).unwrap();
+                        let stride = size.width as usize;
                         let mut buffer = surface.buffer_mut().unwrap();
 
-                        if buffer.len() < 1024 * 100 {
+                        if size.width < Slots::WIDTH as u32 || size.height < Slots::HEIGHT as u32 {
+                            return;
+                        }
+                        if slots.browsing {
+                            slots.draw(&mut buffer, stride);
+                            osd.draw(&mut buffer, stride);
+                            buffer.present().unwrap();
+                            return;
+                        }
+                        if !run {
+                            // The last picture stays up
+                            draw_frame(&mut buffer, stride, filters.filter(screen.present()));
+                            osd.draw(&mut buffer, stride);
+                            buffer.present().unwrap();
                             return;
                         }
 
-                        loop {
-                            if cpu.status.contains(Flags::BREAK) {
-                                std::process::exit(0);
+                        // Keep the player's progress if the emulator panics
+                        let ran = panic::catch_unwind(AssertUnwindSafe(|| {
+                            let fast = playback.fast_forward;
+                            run_until_frame(console, screen, playback, controls.held, fast);
+                            let cpu = &mut console.cpu;
+                            draw_frame(&mut buffer, stride, filters.filter(screen.present()));
+                            if let Some(zapper) = cpu.memory.port_mut::<Zapper>(1) {
+                                zapper.sense(&buffer, stride);
+                                zapper.draw_crosshair(&mut buffer, stride);
                             }
-                            cpu.step();
-
-                            if read_screen_state(cpu, doublebuffer) {
-                                for (i, dblbfr) in doublebuffer.iter().take(0x400).enumerate() {
-                                    let y = i / 32;
-                                    let x = i % 32;
-                                    for dx in 0..10 {
-                                        for dy in 0..10 {
-                                            let coord = (y * 10 + dy) * (32 * 10) + (x * 10 + dx);
-
-                                            buffer[coord] = *dblbfr;
+                            osd.draw(&mut buffer, stride);
+                            buffer.present().unwrap();
+                            osd.frame();
+                            for report in cpu.memory.diagnostics.take_reports() {
+                                warn!("{report}");
+                            }
+                            let new_title = format!(
+                                "{game} - lag frames: {} - {}",
+                                cpu.memory.lag_frames,
+                                slots.describe()
+                            );
+                            if *title != new_title {
+                                window.set_title(&new_title);
+                                *title = new_title;
+                            }
+                        }));
+                        if let Err(panic) = ran {
+                            files.save_on_exit(console);
+                            panic::resume_unwind(panic);
+                        }
+                        when_met.apply(console, screen, slots, playback, osd);
+                        if let Err(err) = files.autosave.tick(console) {
+                            warn!("Couldn't autosave: {err}");
+                        }
+                        if let Some(watch) = &mut files.watch
+                            && let Some(rom) = watch.poll()
+                        {
+                            match rom {
+                                Ok(rom) => {
+                                    if !console.reload_rom(rom, watch.keep_cpu) {
+                                        warn!("The board changed, its state started over");
+                                    }
+                                    if let Some(slot) = watch.state_slot {
+                                        match &slots.states[slot] {
+                                            Some(state) => {
+                                                if let Err(err) = console.load_state_any_rom(state)
+                                                {
+                                                    warn!("Couldn't load slot {slot}: {err}");
+                                                }
+                                            }
+                                            None => warn!("Slot {slot} is empty"),
                                         }
                                     }
+                                    let latency = watch.latency().as_millis();
+                                    info!(
+                                        "Reloaded {}, {latency} ms after it was built",
+                                        watch.path.display()
+                                    );
+                                    osd.show(format!("Reloaded in {latency} ms"));
                                 }
-                                // dbg!(doublebuffer);
-                                buffer.present().unwrap();
-                                break;
+                                Err(err) => warn!("Couldn't reload: {err}"),
                             }
-                            ::std::thread::sleep(std::time::Duration::new(0, 70_000));
                         }
                     }
                 }
                 Event::WindowEvent {
                     window_id,
-                    event: WindowEvent::Resized(size),
+                    event: WindowEvent::Resized(_),
                 } if window_id == window.id() => {
-                    if let (Some(width), Some(height)) =
-                        (NonZeroU32::new(size.width), NonZeroU32::new(size.height))
-                    {
-                        surface.resize(width, height).unwrap();
-                    }
+                    // The surface follows the window when it's next drawn, even
+                    // while paused in the background
+                    window.request_redraw();
                 }
                 Event::WindowEvent {
                     event:
-                        WindowEvent::CloseRequested
-                        | WindowEvent::KeyboardInput {
-                            event:
-                                KeyEvent {
-                                    logical_key: Key::Named(NamedKey::Escape),
-                                    ..
-                                },
-                            ..
+                        WindowEvent::ScaleFactorChanged {
+                            scale_factor,
+                            mut inner_size_writer,
                         },
+                    ..
+                } => {
+                    // Keep the window the same size on the new display. Drawing
+                    // picks a whole scale for it, so the picture stays sharp
+                    // whatever the scale factor.
+                    let size = window.inner_size().to_logical::<f64>(*dpi_scale);
+                    *dpi_scale = scale_factor;
+                    if let Err(err) =
+                        inner_size_writer.request_inner_size(size.to_physical(scale_factor))
+                    {
+                        warn!("Couldn't resize the window: {err}");
+                    }
+                    window.request_redraw();
+                }
+                Event::WindowEvent {
+                    event: WindowEvent::CursorMoved { position, .. },
+                    ..
+                } => {
+                    // The paddle dial follows the mouse across the window
+                    if let Some(vaus) = vaus_mut(console.bus_mut()) {
+                        let width = window.inner_size().width.max(1) as f64;
+                        let x = (position.x / width).clamp(0.0, 1.0);
+                        let range = (Vaus::MAX - Vaus::MIN) as f64;
+                        vaus.position = Vaus::MIN + (x * range) as u8;
+                    }
+                    if let Some(zapper) = console.bus_mut().port_mut::<Zapper>(1) {
+                        zapper.aim = Some((position.x as i32, position.y as i32));
+                    }
+                }
+                Event::WindowEvent {
+                    event: WindowEvent::CursorLeft { .. },
+                    ..
+                } => {
+                    if let Some(zapper) = console.bus_mut().port_mut::<Zapper>(1) {
+                        zapper.aim = None;
+                    }
+                }
+                Event::WindowEvent {
+                    event: WindowEvent::MouseInput { state, button, .. },
+                    ..
+                } => {
+                    let pressed = state == ElementState::Pressed;
+                    let bus = console.bus_mut();
+                    if let Some(vaus) = vaus_mut(bus)
+                        && button == MouseButton::Left
+                    {
+                        vaus.fire = pressed;
+                    }
+                    if let Some(zapper) = bus.port_mut::<Zapper>(1)
+                        && button == MouseButton::Left
+                    {
+                        zapper.trigger = pressed;
+                    }
+                    if let Some(mouse) = snes_mouse_mut(bus) {
+                        match button {
+                            MouseButton::Left => mouse.left = pressed,
+                            MouseButton::Right => mouse.right = pressed,
+                            _ => {}
+                        }
+                    }
+                }
+                Event::DeviceEvent {
+                    event: DeviceEvent::MouseMotion { delta: (dx, dy) },
+                    ..
+                } => {
+                    if let Some(mouse) = snes_mouse_mut(console.bus_mut()) {
+                        mouse.dx += dx as i32;
+                        mouse.dy += dy as i32;
+                    }
+                }
+                Event::WindowEvent {
+                    event: WindowEvent::Focused(focused),
+                    ..
+                } => {
+                    capture_mouse(window, console.bus_mut(), focused);
+                    background.set_focused(focused, console);
+                }
+                Event::WindowEvent {
+                    event: WindowEvent::Occluded(occluded),
+                    ..
+                } => background.set_minimized(occluded),
+                Event::AboutToWait => {
+                    if background.paused() {
+                        // Nothing to do until the window gets focus back
+                        elwt.set_control_flow(ControlFlow::Wait);
+                    } else if let Some((due, next)) = background.frames_due() {
+                        // Hidden windows may not be redrawn, so the game runs
+                        // here without drawing
+                        elwt.set_control_flow(ControlFlow::WaitUntil(next));
+                        for _ in 0..due {
+                            if !playback.next_frame(console) {
+                                break;
+                            }
+                            macros.record(console, controls.held);
+                            triggers.apply(console.bus_mut());
+                            let ran = panic::catch_unwind(AssertUnwindSafe(|| {
+                                run_until_frame(console, screen, playback, controls.held, true)
+                            }));
+                            if let Err(panic) = ran {
+                                files.save_on_exit(console);
+                                panic::resume_unwind(panic);
+                            }
+                            when_met.apply(console, screen, slots, playback, osd);
+                            if let Err(err) = files.autosave.tick(console) {
+                                warn!("Couldn't autosave: {err}");
+                            }
+                        }
+                    } else {
+                        elwt.set_control_flow(ControlFlow::Poll);
+                        window.request_redraw();
+                    }
+                }
+                Event::WindowEvent {
+                    event: WindowEvent::CloseRequested,
                     window_id,
                 } if window_id == window.id() => {
+                    files.save_on_exit(console);
                     elwt.exit();
                 }
                 Event::WindowEvent {
                     event:
                         WindowEvent::KeyboardInput {
-                            device_id: _devid,
                             event:
                                 KeyEvent {
-                                    physical_key:
-                                        PhysicalKey::Code(
-                                            key @ (KeyCode::KeyW
-                                            | KeyCode::KeyA
-                                            | KeyCode::KeyS
-                                            | KeyCode::KeyD),
-                                        ),
+                                    physical_key: PhysicalKey::Code(key),
+                                    state: ElementState::Pressed,
+                                    repeat: false,
                                     ..
                                 },
-                            is_synthetic: _issynth,
+                            ..
                         },
                     ..
                 } => {
-                    let val = match key {
-                        KeyCode::KeyW => 0x77,
-                        KeyCode::KeyS => 0x73,
-                        KeyCode::KeyA => 0x61,
-                        KeyCode::KeyD => 0x64,
-                        _ => unreachable!(),
-                    };
-                    cpu.memory.write(0xff, val);
+                    let name = key_name(key);
+                    let action = controls.hotkeys.get(InputDevice::Keyboard, &name);
+                    if playback.netplay.is_some() && action.is_some_and(desyncs) {
+                        osd.show("Not during netplay");
+                        return;
+                    }
+                    match action {
+                        Some(Action::SaveState) => {
+                            let state = console.save_state(Some(screen.thumbnail()));
+                            match slots.save(state) {
+                                Ok(()) => osd.show(format!("State {} saved", slots.selected)),
+                                Err(err) => {
+                                    warn!("Couldn't save slot {}: {err}", slots.selected);
+                                    osd.show(format!("Couldn't save state {}", slots.selected));
+                                }
+                            }
+                        }
+                        Some(Action::LoadState) => {
+                            if let Some(state) = &slots.states[slots.selected] {
+                                match console.load_state(state) {
+                                    Ok(()) => osd.show(format!("State {} loaded", slots.selected)),
+                                    Err(err) => {
+                                        warn!("Couldn't load slot {}: {err}", slots.selected);
+                                        osd.show(format!("Couldn't load state {}", slots.selected));
+                                    }
+                                }
+                                filters.reset();
+                            } else {
+                                osd.show(format!("State {} is empty", slots.selected));
+                            }
+                        }
+                        Some(Action::FlipDisk) => {
+                            if let Some(side) = console.flip_disk() {
+                                osd.show(disk_side_name(side));
+                            }
+                        }
+                        Some(Action::EjectDisk) if console.disk_sides() > 0 => {
+                            if console.disk_side().is_some() {
+                                console.eject_disk();
+                                osd.show("Disk ejected");
+                            } else {
+                                console.insert_disk(0);
+                                osd.show(disk_side_name(0));
+                            }
+                        }
+                        Some(Action::ToggleFps) => osd.show_fps = !osd.show_fps,
+                        Some(Action::Screenshot) => match files.screenshot(screen.present()) {
+                            Ok(path) => {
+                                info!("Saved {}", path.display());
+                                osd.show("Screenshot saved");
+                            }
+                            Err(err) => {
+                                warn!("Couldn't save a screenshot: {err}");
+                                osd.show("Couldn't save a screenshot");
+                            }
+                        },
+                        Some(Action::Quit) => {
+                            files.save_on_exit(console);
+                            elwt.exit();
+                        }
+                        Some(Action::Pause) => {
+                            playback.paused = !playback.paused;
+                            osd.show(if playback.paused {
+                                "Paused"
+                            } else {
+                                "Unpaused"
+                            });
+                        }
+                        Some(Action::FrameAdvance) => {
+                            playback.paused = true;
+                            playback.advance = true;
+                        }
+                        Some(Action::Undo) => match playback.undo(console) {
+                            Ok(0) => osd.show("Nothing to undo"),
+                            Ok(frames) => {
+                                let seconds = frames as f64 / console.refresh_rate();
+                                osd.show(format!("Went back {seconds:.1} seconds, paused"));
+                                filters.reset();
+                            }
+                            Err(err) => {
+                                warn!("Couldn't undo: {err}");
+                                osd.show("Couldn't undo");
+                            }
+                        },
+                        Some(Action::RecordMacro) => osd.show(macros.toggle_recording(console)),
+                        Some(Action::PlayMacro) => {
+                            if macros.recorded.frames.is_empty() {
+                                osd.show("No macro recorded");
+                            } else {
+                                let frame = console.bus().ppu.frame;
+                                macros.recorded.schedule(triggers, 0, frame);
+                                osd.show("Playing macro");
+                            }
+                        }
+                        Some(Action::Fullscreen) => {
+                            let fullscreen = match window.fullscreen() {
+                                Some(_) => None,
+                                None => Some(Fullscreen::Borderless(None)),
+                            };
+                            window.set_fullscreen(fullscreen);
+                        }
+                        _ => match key {
+                            KeyCode::Tab => slots.browsing = !slots.browsing,
+                            KeyCode::KeyC => {
+                                // Aim at the middle of the window and press C
+                                if let Some(zapper) = console.bus_mut().port_mut::<Zapper>(1) {
+                                    let size = window.inner_size();
+                                    let middle = (size.width as i32 / 2, size.height as i32 / 2);
+                                    zapper.calibrate(middle);
+                                    info!("Zapper offset {:?}", zapper.offset);
+                                    osd.show("Zapper calibrated");
+                                }
+                            }
+                            _ => {
+                                if let Some(slot) = slot_key(key) {
+                                    slots.selected = slot;
+                                    osd.show(format!("Slot {slot}"));
+                                }
+                            }
+                        },
+                    }
                 }
                 _ => {}
             }
@@ -143,32 +688,1376 @@ fn main() {
     winit_app::run_app(event_loop, app);
 }
 
-fn color(byte: u8) -> u32 {
-    match byte {
-        0 => 0x000000,
-        1 => 0xFFFFFF,
-        2 | 9 => 0xAAAAAA,
-        3 | 10 => 0xFF0000,
-        4 | 11 => 0x00FF00,
-        5 | 12 => 0x0000FF,
-        6 | 13 => 0xFF00FF,
-        7 | 14 => 0xFFCC00,
-        _ => 0x00FFCC,
+struct Args {
+    rom: Rom,
+    /// Save files are named after it
+    rom_path: PathBuf,
+    dirs: Dirs,
+    /// Continue from the autosave
+    resume: bool,
+    /// 0 only autosaves on exit
+    autosave_seconds: u64,
+    /// Report likely bugs, with the code/data log to check execution against
+    /// (empty without one)
+    diagnostics: Option<Vec<u8>>,
+    /// Reloads the game when it is rebuilt
+    watch: Option<RomWatcher>,
+    /// Input pressed at set frames
+    triggers: Triggers,
+    /// Devices in place of the controllers
+    ports: [Option<Box<dyn PortDevice>>; 2],
+    expansion: Option<Box<dyn ExpansionDevice>>,
+    /// Cropped off the picture before scaling it up, `None` for what TVs of
+    /// the game's region hid
+    overscan: Option<Overscan>,
+    blend: Option<BlendMode>,
+    #[cfg(feature = "upscale")]
+    upscaler: Option<Upscaler>,
+    /// Everything above comes from these, which the core applies its part of
+    settings: GameSettings,
+    /// What happens while the window is in the background
+    background: Background,
+    render_mode: RenderMode,
+    /// How far back the undo hotkey goes
+    undo_seconds: f64,
+    /// Expressions that save a state or pause when they become true
+    save_when: Option<String>,
+    pause_when: Option<String>,
+    netplay: Option<NetplayArgs>,
+}
+
+/// Playing with someone over the network, see [`netplay`]
+struct NetplayArgs {
+    /// Wait for the other player at `addr` rather than connect to them there
+    host: bool,
+    addr: String,
+    mode: Mode,
+}
+
+impl NetplayArgs {
+    /// The session, once the other player is there. The host plays
+    /// controller 1 and the other player controller 2.
+    fn connect(&self) -> Result<Session<TcpTransport>, NetplayError> {
+        let (transport, player) = if self.host {
+            // A port alone listens on every interface
+            let addr = match self.addr.parse::<u16>() {
+                Ok(port) => format!("0.0.0.0:{port}"),
+                Err(_) => self.addr.clone(),
+            };
+            info!("Waiting for the other player on {addr}");
+            (TcpTransport::host(addr)?, 0)
+        } else {
+            (TcpTransport::connect(self.addr.as_str())?, 1)
+        };
+        info!("Connected, playing controller {}", player + 1);
+        Ok(Session::new(transport, self.mode, player))
+    }
+}
+
+#[cfg(feature = "upscale")]
+enum Upscaler {
+    Hq2x,
+    Xbr(usize),
+}
+
+/// `nes [--no-auto-patch] [--portable] [--resume] [--autosave=SECONDS]
+/// [--diagnostics[=game.cdl]] [--watch[=keep-cpu]] [--watch-state=SLOT]
+/// [--triggers=FILE] [--vaus] [--mouse[=PORT]] [--zapper[=crosshair]]
+/// [--four-score] [--famicom=vaus|keyboard|mic|multitap]
+/// [--overscan=EDGES|none] [--blend[=phosphor]] [--upscale=hq2x|xbr2|xbr3]
+/// [--overclock=SCANLINES] [--sprite-limit=on|off] [--open-bus-decay=MS|off]
+/// [--region=ntsc|pal|dendy] [--dip-switches=N] [--fast-load=on|off]
+/// [--defaults] [--unfocused=pause|mute] [--minimized-fps=FPS]
+/// [--render=scanline|dot] [--undo=SECONDS] [--save-when=EXPR]
+/// [--pause-when=EXPR] [--host=[ADDR:]PORT] [--join=ADDR:PORT]
+/// [--netplay-delay=FRAMES] [--rollback=FRAMES] game.nes` runs the game.
+/// A `game.ips` or `game.bps` next to the ROM is applied unless disabled.
+/// Saves, states and screenshots go in the user's data directory, settings in
+/// their config directory. `--portable` (or a `portable.txt` next to the
+/// executable) keeps everything next to the executable instead.
+/// The game is autosaved every minute by default, and battery saves are
+/// written on exit.
+/// Hotkeys and controller 1's keys come from
+/// `input.cfg` in the config directory, see [`InputBindings`]. By default
+/// the arrows are the d-pad, Z is B, X is A, F12 takes a
+/// screenshot, P pauses, Backslash advances a frame, holding Backspace
+/// rewinds, holding the backtick fast forwards, F11 toggles fullscreen, U
+/// undoes and Escape quits.
+/// R starts recording controller 1's buttons and stops again, T presses them
+/// again from the next frame, for a frame-perfect move on demand. The macro
+/// is kept with the game's settings.
+/// Undo jumps back `--undo` seconds (10 by default) and pauses, for taking
+/// back a mistake, where rewind goes back for as long as it's held.
+/// `--save-when` saves a state to the selected slot and `--pause-when` pauses
+/// as soon as a condition (see [`expr`]) becomes true, like
+/// `--save-when='[$075A] == 0'` for the moment the lives run out. Each only
+/// acts again once its condition has been false.
+/// `--diagnostics` logs stack wraparound, returns that don't go back to their
+/// JSR, reads of RAM nothing wrote and writes the board ignores, with a trace
+/// of the instructions before them, named with labels for the subroutines
+/// and branches the game ran.
+/// Given an FCEUX code/data log it also logs running bytes the log has as data.
+/// `--watch` reloads the game whenever its file changes, keeping RAM and the
+/// board's state, and resets it or with `keep-cpu` carries on where it was.
+/// With `--watch-state` it then loads the state in `SLOT`, so a test setup
+/// saved there comes back with every build. The time from the build writing
+/// the file to the game running it is logged and shown.
+/// `--triggers` presses buttons at the frames a file says, see [`triggers`].
+/// `--vaus` plugs the Arkanoid paddle into port 2, moved with the mouse and
+/// fired with the left button. `--mouse` plugs a SNES mouse into port 2 (or
+/// `PORT`), the window captures the host mouse while focused. `--zapper` plugs
+/// the light gun into port 2, aimed with the mouse, optionally showing where
+/// it points. Pointing at the middle of the window and pressing C calibrates it.
+/// `--four-score` plugs the NES Four Score into both ports, the keyboard
+/// plays player 1.
+/// `--famicom` picks a Famicom expansion port device: the Famicom paddle,
+/// the Family BASIC keyboard typed on the host keyboard, the microphone
+/// which hears noise while M is held, or a four player adapter.
+/// `--overscan` crops `top,bottom,left,right` pixels (or the same on every
+/// edge) off the picture. By default it crops what TVs of the game's region
+/// hid, 8 lines top and bottom on NTSC and nothing on PAL, and `none` shows
+/// the whole picture.
+/// `--blend` mixes each frame with the last to reduce flicker, or lets them
+/// fade out slowly like on a CRT.
+/// `--upscale` smooths the picture with HQ2x or xBR, with the `upscale` feature.
+/// The picture is scaled up to the window by whole pixels, which keeps it
+/// sharp on high DPI displays, and centered. It is drawn a line at a time,
+/// switching to a dot at a time for the rest of a line the game changes the
+/// scroll or PPU settings in the middle of. `--render=dot` draws every
+/// line a dot at a time, for boards that switch CHR banks mid-line.
+/// `--region` runs the game with the timing of another console, like the
+/// Dendy for dumps from its clones whose header says NTSC.
+/// `--dip-switches` sets the switches on boards that have them, a bit each,
+/// like the contest time on Nintendo World Championships.
+/// Famicom Disk System images (`game.fds`) need the BIOS as `disksys.rom` in
+/// the config directory, and keep what the game writes in `game.fds.sav`. The
+/// flip disk hotkey (F6) puts the next disk side in the drive and eject disk
+/// (F7) takes the disk out or puts side A of the first disk back in.
+/// `--fast-load=on` skips the drive's delays, which loads faster but doesn't
+/// always work.
+/// The options from `--vaus` to `--fast-load` are remembered for the game and
+/// used again next time, each until it's given again. `--defaults` forgets
+/// them. The game's settings file can also change its controls, see
+/// [`GameSettings`].
+/// `--host=[ADDR:]PORT` waits for a second player to `--join=ADDR:PORT` and
+/// plays the game with them over the network, the host on controller 1.
+/// Only inputs are sent, so both need the same game and settings, which a
+/// check of both sides' states every second catches if they don't have.
+/// Inputs take effect `--netplay-delay` frames (2 by default) after they're
+/// made, to give them time to arrive. Otherwise the game waits for them, or
+/// with `--rollback=FRAMES` runs up to `FRAMES` ahead on a guess and goes
+/// back to correct it. Both sides have to give the same delay and rollback.
+/// Netplay starts without the battery save and keeps none, and the hotkeys
+/// that change only one side's game, like loading a state, are off.
+/// `--unfocused` pauses the game or mutes it while the window doesn't have
+/// focus. `--minimized-fps` runs the game at most `FPS` frames a second while
+/// the window is minimized or hidden, without drawing them.
+/// `nes verify` checks a movie instead, see [`verify`], and `nes disasm`
+/// disassembles the game, see [`disasm`].
+/// Options it can't make sense of are all listed with the usage, before
+/// exiting with status 2.
+fn parse_args() -> Result<Args, String> {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let auto_patch = !args.iter().any(|arg| arg == "--no-auto-patch");
+    let Some(rom_arg) = args.iter().find(|arg| !arg.starts_with("--")) else {
+        return Err(String::from(USAGE));
+    };
+    let rom = load_rom(Path::new(rom_arg), auto_patch).map_err(|err| err.to_string())?;
+    let rom_path = PathBuf::from(rom_arg);
+    let dirs = Dirs::detect(args.iter().any(|arg| arg == "--portable"));
+    if let Err(err) = dirs.create() {
+        warn!("Couldn't create the emulator's directories: {err}");
+    }
+    let (settings, changed) = game_settings(&args, &dirs, &rom, &rom_path);
+    // Devices and video options come from the game's settings
+    let options: Vec<String> = settings
+        .iter()
+        .map(|(key, value)| match value {
+            "" => format!("--{key}"),
+            value => format!("--{key}={value}"),
+        })
+        .collect();
+    // Every bad option is reported at once, each parsed as its default
+    let mut errors = Vec::new();
+    let option = |prefix: &str| args.iter().find_map(|arg| arg.strip_prefix(prefix));
+    let remembered = |prefix: &str| options.iter().find_map(|arg| arg.strip_prefix(prefix));
+    let autosave_seconds = option("--autosave=").map_or(60, |seconds| {
+        checked(
+            seconds.parse().map_err(|_| "--autosave takes seconds"),
+            60,
+            &mut errors,
+        )
+    });
+    let diagnostics = args.iter().find_map(|arg| match arg.as_str() {
+        "--diagnostics" => Some(Vec::new()),
+        arg => arg.strip_prefix("--diagnostics=").map(|path| {
+            let cdl = std::fs::read(path).map_err(|e| format!("--diagnostics: {path}: {e}"));
+            checked(cdl, Vec::new(), &mut errors)
+        }),
+    });
+    let watch = args.iter().find_map(|arg| match arg.as_str() {
+        "--watch" => Some(false),
+        "--watch=keep-cpu" => Some(true),
+        arg if arg.starts_with("--watch=") => {
+            errors.push(String::from("--watch takes keep-cpu"));
+            None
+        }
+        _ => None,
+    });
+    let state_slot = option("--watch-state=").and_then(|slot| match slot.parse() {
+        Ok(slot) if slot < SLOT_COUNT => Some(slot),
+        _ => {
+            errors.push(format!(
+                "--watch-state takes a slot from 0 to {}",
+                SLOT_COUNT - 1
+            ));
+            None
+        }
+    });
+    if state_slot.is_some() && watch.is_none() {
+        warn!("--watch-state does nothing without --watch");
+    }
+    let watch = watch.map(|keep_cpu| {
+        let mut watcher = RomWatcher::new(rom_path.clone(), auto_patch, keep_cpu);
+        watcher.state_slot = state_slot;
+        watcher
+    });
+    let triggers = option("--triggers=").map_or(Triggers::new(), |path| {
+        let triggers = std::fs::read_to_string(path)
+            .map_err(|err| err.to_string())
+            .and_then(|text| Triggers::parse(&text))
+            .map_err(|err| format!("--triggers: {path}: {err}"));
+        checked(triggers, Triggers::new(), &mut errors)
+    });
+    let mut ports: [Option<Box<dyn PortDevice>>; 2] = [None, None];
+    for arg in &options {
+        match arg.as_str() {
+            "--vaus" => ports[1] = Some(Box::new(Vaus::new(false))),
+            "--mouse" | "--mouse=2" => ports[1] = Some(Box::new(SnesMouse::new())),
+            "--mouse=1" => ports[0] = Some(Box::new(SnesMouse::new())),
+            "--four-score" => ports = [0, 1].map(|port| Some(Box::new(FourScore::new(port)) as _)),
+            "--zapper" | "--zapper=crosshair" => {
+                let mut zapper = Zapper::new();
+                zapper.crosshair = arg.ends_with("crosshair");
+                ports[1] = Some(Box::new(zapper));
+            }
+            _ => {}
+        }
+    }
+    let expansion =
+        remembered("--famicom=").and_then(|device| -> Option<Box<dyn ExpansionDevice>> {
+            Some(match device {
+                "vaus" => Box::new(Vaus::new(true)),
+                "keyboard" => Box::new(FamilyKeyboard::new()),
+                "mic" => Box::new(Microphone::new()),
+                "multitap" => Box::new(Multitap::new()),
+                _ => {
+                    errors.push(String::from(
+                        "--famicom takes vaus, keyboard, mic or multitap",
+                    ));
+                    return None;
+                }
+            })
+        });
+    let overscan = remembered("--overscan=").map(|edges| match edges {
+        "none" => Overscan::default(),
+        edges => {
+            let overscan = Overscan::parse(edges).map_err(|err| format!("--overscan: {err}"));
+            checked(overscan, Overscan::default(), &mut errors)
+        }
+    });
+    let blend = options.iter().find_map(|arg| match arg.as_str() {
+        "--blend" => Some(BlendMode::Mix),
+        "--blend=phosphor" => Some(BlendMode::Phosphor { persistence: 0.6 }),
+        _ => None,
+    });
+    #[cfg(feature = "upscale")]
+    let upscaler = remembered("--upscale=").and_then(|upscaler| match upscaler {
+        "hq2x" => Some(Upscaler::Hq2x),
+        "xbr2" => Some(Upscaler::Xbr(2)),
+        "xbr3" => Some(Upscaler::Xbr(3)),
+        _ => {
+            errors.push(String::from("--upscale takes hq2x, xbr2 or xbr3"));
+            None
+        }
+    });
+    let unfocused = match option("--unfocused=") {
+        None => Unfocused::Run,
+        Some("pause") => Unfocused::Pause,
+        Some("mute") => Unfocused::Mute,
+        Some(_) => {
+            errors.push(String::from("--unfocused takes pause or mute"));
+            Unfocused::Run
+        }
+    };
+    // Checked here, the console only gets them once it's made
+    let mut condition = |name: &str| {
+        let source = option(&format!("--{name}="))?;
+        match expr::Expr::parse(source) {
+            Ok(_) => Some(source.to_string()),
+            Err(err) => {
+                errors.push(format!("--{name}: {err}"));
+                None
+            }
+        }
+    };
+    let save_when = condition("save-when");
+    let pause_when = condition("pause-when");
+    let minimized_fps = option("--minimized-fps=").and_then(|fps| match fps.parse() {
+        Ok(fps) if fps > 0.0 => Some(fps),
+        _ => {
+            errors.push(String::from(
+                "--minimized-fps takes frames a second above 0",
+            ));
+            None
+        }
+    });
+    let render_mode = match option("--render=") {
+        None | Some("scanline") => RenderMode::Scanline,
+        Some("dot") => RenderMode::Dot,
+        Some(_) => {
+            errors.push(String::from("--render takes scanline or dot"));
+            RenderMode::Scanline
+        }
+    };
+    let undo_seconds = option("--undo=").map_or(10.0, |seconds| match seconds.parse() {
+        Ok(seconds) if seconds > 0.0 => seconds,
+        _ => {
+            errors.push(String::from("--undo takes seconds above 0"));
+            10.0
+        }
+    });
+    let delay = option("--netplay-delay=").map_or(2, |frames| {
+        checked(
+            frames.parse().map_err(|_| "--netplay-delay takes frames"),
+            2,
+            &mut errors,
+        )
+    });
+    let mode = match option("--rollback=").map(str::parse) {
+        None => Mode::Lockstep { delay },
+        Some(Ok(max_frames)) if max_frames > 0 => Mode::Rollback { delay, max_frames },
+        Some(_) => {
+            errors.push(String::from("--rollback takes frames above 0"));
+            Mode::Lockstep { delay }
+        }
+    };
+    let netplay = match (option("--host="), option("--join=")) {
+        (None, None) => {
+            if option("--netplay-delay=").is_some() || option("--rollback=").is_some() {
+                warn!("--netplay-delay and --rollback do nothing without --host or --join");
+            }
+            None
+        }
+        (Some(_), Some(_)) => {
+            errors.push(String::from("--host and --join don't go together"));
+            None
+        }
+        (host, join) => Some(NetplayArgs {
+            host: host.is_some(),
+            addr: host.or(join).unwrap_or_default().to_string(),
+            mode,
+        }),
+    };
+    if netplay.is_some() && (args.iter().any(|arg| arg == "--resume") || watch.is_some()) {
+        errors.push(String::from(
+            "--resume and --watch don't work with netplay, both sides start alike",
+        ));
+    }
+    if !errors.is_empty() {
+        errors.push(String::from(USAGE));
+        return Err(errors.join("\n"));
+    }
+    // Only once they're known to be good
+    if changed {
+        match settings.save() {
+            Ok(()) => info!("Saved settings to {}", settings.path().display()),
+            Err(err) => warn!("Couldn't save the game's settings: {err}"),
+        }
+    }
+    Ok(Args {
+        rom,
+        rom_path,
+        dirs,
+        resume: args.iter().any(|arg| arg == "--resume"),
+        autosave_seconds,
+        diagnostics,
+        watch,
+        triggers,
+        ports,
+        expansion,
+        overscan,
+        blend,
+        #[cfg(feature = "upscale")]
+        upscaler,
+        settings,
+        background: Background {
+            unfocused,
+            minimized_pacer: minimized_fps.map(FramePacer::new),
+            ..Background::default()
+        },
+        render_mode,
+        undo_seconds,
+        save_when,
+        pause_when,
+        netplay,
+    })
+}
+
+const USAGE: &str = "usage: nes [OPTIONS] game.nes";
+
+/// `result`'s value, or `fallback` with the error added to `errors`
+fn checked<T>(result: Result<T, impl Into<String>>, fallback: T, errors: &mut Vec<String>) -> T {
+    result.unwrap_or_else(|err| {
+        errors.push(err.into());
+        fallback
+    })
+}
+
+/// Options remembered for each game
+const GAME_OPTIONS: [&str; 14] = [
+    "vaus",
+    "mouse",
+    "zapper",
+    "four-score",
+    "famicom",
+    "overscan",
+    "blend",
+    "upscale",
+    "overclock",
+    "sprite-limit",
+    "open-bus-decay",
+    "region",
+    "dip-switches",
+    "fast-load",
+];
+
+/// Options for what's plugged into the controller ports, only one of which
+/// is remembered
+const PORT_DEVICES: [&str; 4] = ["vaus", "mouse", "zapper", "four-score"];
+
+/// `nes verify [--no-auto-patch] game.nes movie.nesm` replays the movie as
+/// fast as possible without a window, with the default settings, and prints
+/// the frames played, the final state hash and the first frame that desynced
+/// from the recorded state hashes. Returns whether the movie synced.
+fn verify(args: &[String]) -> Result<bool, String> {
+    let auto_patch = !args.iter().any(|arg| arg == "--no-auto-patch");
+    let paths: Vec<&String> = args.iter().filter(|arg| !arg.starts_with("--")).collect();
+    let [rom_path, movie_path] = paths[..] else {
+        return Err(String::from(
+            "usage: nes verify [--no-auto-patch] game.nes movie.nesm",
+        ));
+    };
+    let rom = load_rom(Path::new(rom_path), auto_patch)?;
+    let bytes = std::fs::read(movie_path).map_err(|e| format!("{movie_path}: {e}"))?;
+    let movie = Movie::from_bytes(&bytes).map_err(|e| format!("{movie_path}: {e}"))?;
+    let mut console = Console::new(rom);
+    let verification = movie.verify(&mut console)?;
+    println!("frames: {}", verification.frames);
+    println!("state hash: {:08X}", verification.hash);
+    let unchecked = (0..movie.len()).find(|&frame| movie.state_hash(frame).is_none());
+    match (verification.desync, unchecked) {
+        (Some(frame), _) => println!("desynced at frame {frame}"),
+        (None, Some(frame)) => {
+            println!("in sync for the {frame} frames with recorded state hashes")
+        }
+        (None, None) => println!("in sync"),
+    }
+    Ok(verification.desync.is_none())
+}
+
+/// `nes disasm [--no-auto-patch] [--cdl=game.cdl] [--labels=game.mlb] game.nes game.s`
+/// writes the game's PRG ROM as ca65 source. An FCEUX code/data log separates
+/// code from data and a Mesen label file names addresses, the PPU and APU
+/// registers are always named.
+fn disasm(args: &[String]) -> Result<(), String> {
+    let auto_patch = !args.iter().any(|arg| arg == "--no-auto-patch");
+    let paths: Vec<&String> = args.iter().filter(|arg| !arg.starts_with("--")).collect();
+    let [rom_path, out_path] = paths[..] else {
+        return Err(String::from(
+            "usage: nes disasm [--no-auto-patch] [--cdl=game.cdl] [--labels=game.mlb] game.nes game.s",
+        ));
+    };
+    let option = |name: &str| args.iter().find_map(|arg| arg.strip_prefix(name));
+    let rom = Rom::load(Path::new(rom_path), auto_patch)?;
+    let cdl = match option("--cdl=") {
+        Some(path) => Some(std::fs::read(path).map_err(|e| format!("{path}: {e}"))?),
+        None => None,
+    };
+    let mut labels = Labels::registers();
+    if let Some(path) = option("--labels=") {
+        let text = std::fs::read_to_string(path).map_err(|e| format!("{path}: {e}"))?;
+        labels.load_mlb(&text).map_err(|e| format!("{path}: {e}"))?;
+    }
+    let source = disasm::disassemble(&rom, cdl.as_deref(), &labels);
+    std::fs::write(out_path, source).map_err(|e| format!("{out_path}: {e}"))
+}
+
+/// `nes stems [--no-auto-patch] [--movie=movie.nesm] game.nes out FIRST-END`
+/// writes each sound channel from frame `FIRST` until frame `END` to its own
+/// WAV, `out.pulse1.wav` and so on (see [`dump::StemDump`]), running as fast
+/// as possible without a window. Frames count from power on, or from the
+/// start of the movie, whose input is played until it runs out.
+fn stems(args: &[String]) -> Result<(), String> {
+    let mut run = HeadlessRun::parse(args, "stems")?;
+    let mut dump = None;
+    for frame in 0..run.frames.end {
+        if frame == run.frames.start {
+            dump = Some(dump::StemDump::create(&run.out, &mut run.console)?);
+        }
+        run.run_frame();
+        if let Some(dump) = &mut dump {
+            dump.frame(&mut run.console)?;
+        }
+    }
+    if let Some(dump) = dump {
+        dump.finish(&mut run.console)?;
+    }
+    println!("stems: {}", dump::stem_names(&run.console).join(", "));
+    Ok(())
+}
+
+/// `nes registers [--no-auto-patch] [--movie=movie.nesm] game.nes out FIRST-END`
+/// logs the writes to sound registers from frame `FIRST` until frame `END`,
+/// like `nes stems`. `out.txt` has every write and its cycle, `out.vgm` the
+/// APU writes for VGM players. See [`register_log::RegisterLog`].
+fn registers(args: &[String]) -> Result<(), String> {
+    let mut run = HeadlessRun::parse(args, "registers")?;
+    for frame in 0..run.frames.end {
+        if frame == run.frames.start {
+            let bus = run.console.bus_mut();
+            bus.register_log.start(bus.cycles);
+        }
+        run.run_frame();
+    }
+    let bus = run.console.bus_mut();
+    bus.register_log.stop(bus.cycles);
+    let log = &bus.register_log;
+    for (path, bytes) in [
+        (run.out.with_extension("txt"), log.to_text().into_bytes()),
+        (run.out.with_extension("vgm"), log.to_vgm(bus.ppu.region)),
+    ] {
+        std::fs::write(&path, bytes).map_err(|e| format!("{}: {e}", path.display()))?;
+    }
+    println!("writes: {}", log.writes().len());
+    Ok(())
+}
+
+/// A game run without a window for the `nes stems` and `nes registers`
+/// commands, from `[--no-auto-patch] [--movie=movie.nesm] game.nes out FIRST-END`
+struct HeadlessRun {
+    console: Console,
+    movie: Option<Movie>,
+    out: PathBuf,
+    frames: std::ops::Range<u64>,
+}
+
+impl HeadlessRun {
+    fn parse(args: &[String], command: &str) -> Result<Self, String> {
+        let usage = format!(
+            "usage: nes {command} [--no-auto-patch] [--movie=movie.nesm] game.nes out FIRST-END"
+        );
+        let auto_patch = !args.iter().any(|arg| arg == "--no-auto-patch");
+        let paths: Vec<&String> = args.iter().filter(|arg| !arg.starts_with("--")).collect();
+        let [rom_path, out_path, range] = paths[..] else {
+            return Err(usage);
+        };
+        let (first, end): (u64, u64) = range
+            .split_once('-')
+            .and_then(|(first, end)| Some((first.parse().ok()?, end.parse().ok()?)))
+            .filter(|(first, end)| first < end)
+            .ok_or(usage)?;
+        let rom = load_rom(Path::new(rom_path), auto_patch)?;
+        let mut console = Console::new(rom);
+        // The same output every time without a movie too
+        console.set_time_source(EmulatedClock::default());
+        let movie = match args.iter().find_map(|arg| arg.strip_prefix("--movie=")) {
+            Some(path) => {
+                let bytes = std::fs::read(path).map_err(|e| format!("{path}: {e}"))?;
+                let mut movie = Movie::from_bytes(&bytes).map_err(|e| format!("{path}: {e}"))?;
+                movie.seek(&mut console, 0)?;
+                Some(movie)
+            }
+            None => None,
+        };
+        Ok(HeadlessRun {
+            console,
+            movie,
+            out: PathBuf::from(out_path),
+            frames: first..end,
+        })
+    }
+
+    /// A frame with the movie's input, or none once it has run out
+    fn run_frame(&mut self) {
+        let played = self
+            .movie
+            .as_mut()
+            .is_some_and(|movie| movie.play(&mut self.console));
+        if !played {
+            self.console.run_frame();
+        }
+    }
+}
+
+/// The game's settings with the options on the command line in place of the
+/// remembered ones, and whether that changed them. Also puts the ROM at the
+/// top of the recent list.
+fn game_settings(args: &[String], dirs: &Dirs, rom: &Rom, rom_path: &Path) -> (GameSettings, bool) {
+    let recent = RecentRoms::load(&dirs.config).and_then(|mut recent| {
+        recent.add(rom_path);
+        recent.save()
+    });
+    if let Err(err) = recent {
+        warn!("Couldn't update the recent ROMs: {err}");
+    }
+    let mut settings = GameSettings::load(&dirs.config, rom).unwrap_or_else(|err| {
+        warn!("Couldn't load the game's settings, using the defaults: {err}");
+        GameSettings::empty(&dirs.config, rom)
+    });
+    let given: Vec<(&str, &str)> = args
+        .iter()
+        .filter_map(|arg| arg.strip_prefix("--"))
+        .map(|option| option.split_once('=').unwrap_or((option, "")))
+        .filter(|(key, _)| GAME_OPTIONS.contains(key))
+        .collect();
+    let defaults = args.iter().any(|arg| arg == "--defaults");
+    if defaults {
+        for key in GAME_OPTIONS {
+            settings.remove(key);
+        }
+    }
+    let changed = !given.is_empty() || defaults;
+    for (key, value) in given {
+        // A device replaces the one remembered for its port
+        if PORT_DEVICES.contains(&key) {
+            for device in PORT_DEVICES {
+                settings.remove(device);
+            }
+        }
+        settings.set(key, value);
+    }
+    (settings, changed)
+}
+
+/// Name for the window title, from the ROM database or else the file name
+fn game_name(rom: &Rom, path: &Path) -> String {
+    #[cfg(feature = "romdb")]
+    if let Some(entry) = romdb::RomDb::embedded().get(romdb::rom_crc(rom)) {
+        return entry.name.clone();
+    }
+    #[cfg(not(feature = "romdb"))]
+    let _ = rom;
+    path.file_stem()
+        .map_or("nes".into(), |stem| stem.to_string_lossy().into_owned())
+}
+
+/// Either paddle, whichever port it is in
+fn vaus_mut(bus: &mut Bus) -> Option<&mut Vaus> {
+    if bus.port_mut::<Vaus>(1).is_some() {
+        return bus.port_mut(1);
+    }
+    bus.expansion_mut()
+}
+
+fn snes_mouse_mut(bus: &mut Bus) -> Option<&mut SnesMouse> {
+    let port = (0..2).find(|&port| bus.port_mut::<SnesMouse>(port).is_some())?;
+    bus.port_mut(port)
+}
+
+/// Hide and lock the host mouse while a SNES mouse is plugged in and the
+/// window is focused, so it moves the SNES mouse instead of the cursor
+fn capture_mouse(window: &Window, bus: &mut Bus, focused: bool) {
+    if snes_mouse_mut(bus).is_none() {
+        return;
+    }
+    let grab = if focused {
+        window
+            .set_cursor_grab(CursorGrabMode::Locked)
+            .or_else(|_| window.set_cursor_grab(CursorGrabMode::Confined))
+    } else {
+        window.set_cursor_grab(CursorGrabMode::None)
+    };
+    if let Err(err) = grab {
+        warn!("Couldn't capture the mouse: {err}");
+    }
+    window.set_cursor_visible(!focused);
+}
+
+/// The Family BASIC key at the same place as a host key
+fn family_key(key: KeyCode) -> Option<&'static str> {
+    let name = match key {
+        KeyCode::KeyA => "A",
+        KeyCode::KeyB => "B",
+        KeyCode::KeyC => "C",
+        KeyCode::KeyD => "D",
+        KeyCode::KeyE => "E",
+        KeyCode::KeyF => "F",
+        KeyCode::KeyG => "G",
+        KeyCode::KeyH => "H",
+        KeyCode::KeyI => "I",
+        KeyCode::KeyJ => "J",
+        KeyCode::KeyK => "K",
+        KeyCode::KeyL => "L",
+        KeyCode::KeyM => "M",
+        KeyCode::KeyN => "N",
+        KeyCode::KeyO => "O",
+        KeyCode::KeyP => "P",
+        KeyCode::KeyQ => "Q",
+        KeyCode::KeyR => "R",
+        KeyCode::KeyS => "S",
+        KeyCode::KeyT => "T",
+        KeyCode::KeyU => "U",
+        KeyCode::KeyV => "V",
+        KeyCode::KeyW => "W",
+        KeyCode::KeyX => "X",
+        KeyCode::KeyY => "Y",
+        KeyCode::KeyZ => "Z",
+        KeyCode::Digit0 => "0",
+        KeyCode::Digit1 => "1",
+        KeyCode::Digit2 => "2",
+        KeyCode::Digit3 => "3",
+        KeyCode::Digit4 => "4",
+        KeyCode::Digit5 => "5",
+        KeyCode::Digit6 => "6",
+        KeyCode::Digit7 => "7",
+        KeyCode::Digit8 => "8",
+        KeyCode::Digit9 => "9",
+        KeyCode::Enter => "RETURN",
+        KeyCode::Space => "SPACE",
+        KeyCode::Backspace => "DEL",
+        KeyCode::Insert => "INS",
+        KeyCode::Home => "CLR",
+        KeyCode::ShiftLeft => "LSHIFT",
+        KeyCode::ShiftRight => "RSHIFT",
+        KeyCode::ControlLeft => "CTR",
+        KeyCode::AltLeft => "GRPH",
+        KeyCode::AltRight => "KANA",
+        KeyCode::Pause => "STOP",
+        KeyCode::ArrowUp => "UP",
+        KeyCode::ArrowDown => "DOWN",
+        KeyCode::ArrowLeft => "LEFT",
+        KeyCode::ArrowRight => "RIGHT",
+        KeyCode::Minus => "-",
+        KeyCode::Equal => "^",
+        KeyCode::BracketLeft => "@",
+        KeyCode::BracketRight => "[",
+        KeyCode::Backslash => "]",
+        KeyCode::Semicolon => ";",
+        KeyCode::Quote => ":",
+        KeyCode::Comma => ",",
+        KeyCode::Period => ".",
+        KeyCode::Slash => "/",
+        KeyCode::IntlRo => "_",
+        KeyCode::IntlYen => "¥",
+        KeyCode::F1 => "F1",
+        KeyCode::F2 => "F2",
+        KeyCode::F3 => "F3",
+        KeyCode::F4 => "F4",
+        KeyCode::F6 => "F6",
+        KeyCode::F7 => "F7",
+        KeyCode::F8 => "F8",
+        _ => return None,
+    };
+    Some(name)
+}
+
+/// The running game's files, in the directories from [`Dirs`]
+/// Keyboard bindings from the config, see [`InputBindings`]
+struct Controls {
+    bindings: InputBindings,
+    hotkeys: HotkeyMap,
+    /// Controller 1's buttons held on the keyboard
+    held: Buttons,
+}
+
+/// `key` named like egui's `Key::name`, which [`InputBindings`] uses
+fn key_name(key: KeyCode) -> String {
+    let name = match key {
+        KeyCode::Backquote => "Backtick",
+        KeyCode::Equal => "Equals",
+        KeyCode::NumpadEnter => "Enter",
+        _ => {
+            let name = format!("{key:?}");
+            let short = name.strip_prefix("Key").or(name.strip_prefix("Digit"));
+            return short.unwrap_or(&name).to_string();
+        }
+    };
+    name.to_string()
+}
+
+/// Frames [`Playback`] can rewind, unless undo goes further back
+const REWIND_FRAMES: usize = 600;
+
+/// Pausing, rewinding, undoing and fast forwarding
+struct Playback {
+    paused: bool,
+    /// Run one frame while paused
+    advance: bool,
+    /// The rewind hotkey is held
+    rewinding: bool,
+    /// The fast forward hotkey is held
+    fast_forward: bool,
+    /// The state at the start of each frame run, oldest first
+    history: VecDeque<SaveState>,
+    /// Frames kept in `history`
+    history_len: usize,
+    /// Frames the undo hotkey goes back
+    undo_frames: usize,
+    /// Frames only run once the other player's input allows it
+    netplay: Option<Session<TcpTransport>>,
+}
+
+/// Hotkeys that change the game on one side of a netplay session only
+fn desyncs(action: Action) -> bool {
+    matches!(
+        action,
+        Action::LoadState | Action::Rewind | Action::Undo | Action::FlipDisk | Action::EjectDisk
+    )
+}
+
+impl Playback {
+    /// With undo going back `undo_frames` frames
+    fn new(undo_frames: f64) -> Self {
+        let undo_frames = undo_frames.round().max(1.0) as usize;
+        Playback {
+            paused: false,
+            advance: false,
+            rewinding: false,
+            fast_forward: false,
+            history: VecDeque::new(),
+            history_len: REWIND_FRAMES.max(undo_frames),
+            undo_frames,
+            netplay: None,
+        }
+    }
+
+    /// Go back to the start of the frame `undo_frames` ago, or the oldest
+    /// one kept, and pause there. The frames after it are forgotten.
+    /// Returns how many frames it went back.
+    fn undo(&mut self, console: &mut Console) -> Result<usize, StateError> {
+        let back = self.undo_frames.min(self.history.len());
+        let Some(state) = self.history.get(self.history.len() - back) else {
+            return Ok(0);
+        };
+        console.load_state(state)?;
+        self.history.truncate(self.history.len() - back);
+        self.paused = true;
+        Ok(back)
+    }
+
+    /// Whether to run a frame now. While rewinding it goes back to the start
+    /// of the frame before the last one run, so running it leaves the game a
+    /// frame further back each time.
+    fn next_frame(&mut self, console: &mut Console) -> bool {
+        if self.rewinding {
+            if self.history.len() < 2 {
+                return false;
+            }
+            self.history.pop_back();
+            let state = self.history.pop_back().expect("checked above");
+            if let Err(err) = console.load_state(&state) {
+                warn!("Couldn't rewind: {err}");
+                return false;
+            }
+        } else if self.paused && !self.advance {
+            return false;
+        }
+        self.advance = false;
+        if self.history.len() == self.history_len {
+            self.history.pop_front();
+        }
+        self.history.push_back(console.save_state(None));
+        true
+    }
+
+    /// Run a frame with `held` on this side's controller, returns whether
+    /// one ran. During netplay it's the next frame the other side's input is
+    /// in for, and when the session fails the game carries on alone.
+    fn run_frame(&mut self, console: &mut Console, held: Buttons) -> bool {
+        let Some(session) = &mut self.netplay else {
+            console.run_frame();
+            return true;
+        };
+        match session.advance(console, held) {
+            Ok(ran) => ran,
+            Err(err) => {
+                error!("Netplay stopped: {err}");
+                self.netplay = None;
+                false
+            }
+        }
+    }
+}
+
+/// Run the game for a frame and hand its picture to `screen`, then wait
+/// until the frame is due to keep the game's pace unless `fast`
+fn run_until_frame(
+    console: &mut Console,
+    screen: &mut Screen,
+    playback: &mut Playback,
+    held: Buttons,
+    fast: bool,
+) {
+    if !playback.run_frame(console, held) {
+        // Waiting for the other player, who is due to send something soon
+        std::thread::sleep(Duration::from_millis(1));
+        return;
+    }
+    screen.submit(&console.bus().ppu.picture);
+    let now = Instant::now();
+    if fast {
+        screen.due = now;
+        return;
     }
+    if screen.due > now {
+        std::thread::sleep(screen.due - now);
+    }
+    screen.due = screen.due.max(now) + screen.frame_time;
+}
+
+/// The PPU's picture on its way to the window, through a [`FramePool`] so
+/// frames run while nothing is drawn, like when minimized, don't pile up
+struct Screen {
+    frames: FramePool,
+    /// The picture in the window, drawn again until a new one comes
+    shown: Frame,
+    /// The newest picture, for savestate thumbnails
+    latest: Frame,
+    /// When the next frame should be done, to run at the game's frame rate
+    due: Instant,
+    frame_time: Duration,
 }
 
-fn read_screen_state(cpu: &Cpu, frame: &mut [u32]) -> bool {
-    let mut update = false;
-    for i in 0x0200..0x600 {
-        let color_idx = cpu.memory.read(i as u16);
-        let c = color(color_idx);
-        let init = i - 0x200;
-        if frame[init] != c {
-            frame[init] = c;
-            update = true;
+impl Screen {
+    fn new(refresh_rate: f64) -> Self {
+        Screen {
+            frames: FramePool::new(FRAME_WIDTH, FRAME_HEIGHT, 3),
+            shown: Frame::new(FRAME_WIDTH, FRAME_HEIGHT),
+            latest: Frame::new(FRAME_WIDTH, FRAME_HEIGHT),
+            due: Instant::now(),
+            frame_time: Duration::from_secs_f64(1.0 / refresh_rate),
+        }
+    }
+
+    fn submit(&mut self, picture: &[u8]) {
+        self.latest.draw_picture(picture);
+        let mut frame = self.frames.acquire();
+        frame.copy_from(&self.latest);
+        self.frames.submit(frame);
+    }
+
+    /// The picture as a savestate's screenshot, a quarter of its size
+    fn thumbnail(&self) -> Thumbnail {
+        let small = self.latest.downscale(FRAME_WIDTH / 4, FRAME_HEIGHT / 4);
+        Thumbnail {
+            width: small.width as u16,
+            height: small.height as u16,
+            pixels: small.pixels,
+        }
+    }
+
+    /// The newest picture, which is then the one shown
+    fn present(&mut self) -> &Frame {
+        if let Some(frame) = self.frames.take() {
+            let shown = std::mem::replace(&mut self.shown, frame);
+            self.frames.recycle(shown);
         }
+        &self.shown
     }
-    update
 }
 
-static GAME_CODE: &[u8] = include_bytes!("../snake.nes");
+/// The game's input macro, kept in its settings as `macro`
+struct Macros {
+    settings: GameSettings,
+    recorded: Macro,
+    /// The frame recording started on and what it has so far
+    recording: Option<(u64, Macro)>,
+}
+
+impl Macros {
+    fn new(settings: GameSettings) -> Self {
+        let recorded = settings.get("macro").map_or(Ok(Macro::new()), Macro::parse);
+        let recorded = recorded.unwrap_or_else(|err| {
+            warn!("{}: invalid macro: {err}", settings.path().display());
+            Macro::new()
+        });
+        Macros {
+            settings,
+            recorded,
+            recording: None,
+        }
+    }
+
+    /// Record the buttons held for the frame about to run
+    fn record(&mut self, console: &Console, held: Buttons) {
+        if let Some((start, recording)) = &mut self.recording {
+            let frame = console.bus().ppu.frame.saturating_sub(*start);
+            recording.record(frame as usize, held);
+        }
+    }
+
+    /// Start recording, or stop and keep what was recorded. Returns what
+    /// to show.
+    fn toggle_recording(&mut self, console: &Console) -> String {
+        let Some((_, mut recording)) = self.recording.take() else {
+            self.recording = Some((console.bus().ppu.frame, Macro::new()));
+            return "Recording macro".to_string();
+        };
+        recording.trim();
+        if recording.frames.is_empty() {
+            return "Nothing recorded, kept the last macro".to_string();
+        }
+        let frames = recording.frames.len();
+        self.settings.set("macro", &recording.to_text());
+        self.recorded = recording;
+        if let Err(err) = self.settings.save() {
+            warn!("Couldn't save the macro: {err}");
+        }
+        format!("Macro recorded, {frames} frames")
+    }
+}
+
+/// What `--save-when` and `--pause-when` do, with the index of the
+/// condition each added to the console
+struct WhenMet {
+    save: Option<usize>,
+    pause: Option<usize>,
+}
+
+impl WhenMet {
+    /// Save to the selected slot or pause for the conditions met in the
+    /// frames just run
+    fn apply(
+        &self,
+        console: &mut Console,
+        screen: &Screen,
+        slots: &mut Slots,
+        playback: &mut Playback,
+        osd: &mut Osd,
+    ) {
+        for index in console.take_met_conditions() {
+            let source = console.conditions()[index].source.clone();
+            if self.save == Some(index) {
+                let state = console.save_state(Some(screen.thumbnail()));
+                match slots.save(state) {
+                    Ok(()) => {
+                        info!("{source}, saved slot {}", slots.selected);
+                        osd.show(format!("State {} saved", slots.selected));
+                    }
+                    Err(err) => warn!("Couldn't save slot {}: {err}", slots.selected),
+                }
+            }
+            if self.pause == Some(index) {
+                info!("{source}, paused");
+                playback.paused = true;
+                osd.show("Paused");
+            }
+        }
+    }
+}
+
+/// What `--unfocused` does while the window doesn't have focus
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Unfocused {
+    /// Carry on as usual
+    Run,
+    Pause,
+    Mute,
+}
+
+/// The window's focus and whether it's minimized, and what the game does
+/// about them
+struct Background {
+    unfocused: Unfocused,
+    /// Paces frames while minimized from `--minimized-fps`, none to run as usual
+    minimized_pacer: Option<FramePacer>,
+    focused: bool,
+    /// Minimized or hidden behind other windows
+    minimized: bool,
+    /// The master volume to go back to once muted for losing focus
+    volume: Option<f32>,
+    /// When frames were last run while minimized
+    last_run: Instant,
+}
+
+impl Default for Background {
+    fn default() -> Self {
+        Background {
+            unfocused: Unfocused::Run,
+            minimized_pacer: None,
+            focused: true,
+            minimized: false,
+            volume: None,
+            last_run: Instant::now(),
+        }
+    }
+}
+
+impl Background {
+    fn set_focused(&mut self, focused: bool, console: &mut Console) {
+        self.focused = focused;
+        if self.unfocused != Unfocused::Mute {
+            return;
+        }
+        let audio = &mut console.bus_mut().audio;
+        if focused {
+            if let Some(volume) = self.volume.take() {
+                audio.master_volume = volume;
+            }
+        } else if self.volume.is_none() {
+            self.volume = Some(audio.master_volume);
+            audio.master_volume = 0.0;
+        }
+    }
+
+    fn set_minimized(&mut self, minimized: bool) {
+        if minimized && !self.minimized {
+            self.last_run = Instant::now();
+        }
+        self.minimized = minimized;
+    }
+
+    /// Whether the game waits for the window to get focus back
+    fn paused(&self) -> bool {
+        self.unfocused == Unfocused::Pause && !self.focused
+    }
+
+    /// Whether frames only run in [`Background::frames_due`]
+    fn throttled(&self) -> bool {
+        self.minimized && self.minimized_pacer.is_some()
+    }
+
+    /// While minimized with a cap, how many frames to run now and when to
+    /// wake up for the next. None when the game runs as usual.
+    fn frames_due(&mut self) -> Option<(u32, Instant)> {
+        if !self.throttled() {
+            return None;
+        }
+        let pacer = self.minimized_pacer.as_mut()?;
+        let now = Instant::now();
+        let due = pacer.frames_due(now - self.last_run);
+        self.last_run = now;
+        Some((due, now + pacer.until_next()))
+    }
+}
+
+/// The window's starting size in logical pixels, the picture at 2x
+const WINDOW_SIZE: (f64, f64) = (FRAME_WIDTH as f64 * 2.0, FRAME_HEIGHT as f64 * 2.0);
+
+/// `frame` scaled up by as many whole pixels as fit, in the middle of
+/// `buffer` with rows of `stride` pixels
+fn draw_frame(buffer: &mut [u32], stride: usize, frame: &Frame) {
+    let height = buffer.len() / stride;
+    buffer.fill(0);
+    let scale = frame.fit_scale(stride, height);
+    frame.blit_scaled_centered(buffer, stride, scale);
+}
+
+struct GameFiles {
+    autosave: Autosave,
+    /// `None` during netplay, whose game isn't the player's own
+    battery: Option<BatterySave>,
+    /// Screenshots are numbered after this, `game-1.ppm` for `game.nes`
+    screenshot_base: PathBuf,
+    watch: Option<RomWatcher>,
+}
+
+/// Frames between checks of the ROM file for `--watch`, checking is cheap
+/// and every frame waited adds to the time until a build runs
+const WATCH_INTERVAL: u32 = 2;
+
+/// Reloads the ROM when an assembler rewrites it, see [`Console::reload_rom`]
+struct RomWatcher {
+    path: PathBuf,
+    auto_patch: bool,
+    keep_cpu: bool,
+    /// Loaded after every reload
+    state_slot: Option<usize>,
+    /// When the file was last changed as of the last check
+    modified: Option<SystemTime>,
+    /// Frames until the next check
+    countdown: u32,
+}
+
+impl RomWatcher {
+    fn new(path: PathBuf, auto_patch: bool, keep_cpu: bool) -> Self {
+        let modified = modified(&path);
+        RomWatcher {
+            path,
+            auto_patch,
+            keep_cpu,
+            state_slot: None,
+            modified,
+            countdown: WATCH_INTERVAL,
+        }
+    }
+
+    /// Call every frame, gives the ROM again once its file has changed. A
+    /// file caught halfway through being written fails to load and is tried
+    /// again when the write changes it again.
+    fn poll(&mut self) -> Option<Result<Rom, error::RomError>> {
+        self.countdown = self.countdown.saturating_sub(1);
+        if self.countdown > 0 {
+            return None;
+        }
+        self.countdown = WATCH_INTERVAL;
+        let modified = modified(&self.path);
+        if modified == self.modified {
+            return None;
+        }
+        self.modified = modified;
+        Some(load_rom(&self.path, self.auto_patch))
+    }
+
+    /// Time since the build wrote the ROM last given by [`RomWatcher::poll`]
+    fn latency(&self) -> Duration {
+        self.modified
+            .and_then(|modified| modified.elapsed().ok())
+            .unwrap_or_default()
+    }
+}
+
+/// The game at `path`, running Famicom Disk System images on the BIOS in the
+/// config directory
+fn load_rom(path: &Path, auto_patch: bool) -> Result<Rom, error::RomError> {
+    if !path
+        .extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("fds"))
+    {
+        return Rom::load(path, auto_patch);
+    }
+    let portable = std::env::args().any(|arg| arg == "--portable");
+    let bios = Dirs::detect(portable).config.join("disksys.rom");
+    Rom::load_fds(path, &bios, auto_patch)
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path)
+        .and_then(|meta| meta.modified())
+        .ok()
+}
+
+impl GameFiles {
+    fn new(dirs: &Dirs, rom_path: &Path, autosave_interval: u64) -> Self {
+        GameFiles {
+            autosave: Autosave::new(&Dirs::file(&dirs.states, rom_path), autosave_interval),
+            battery: Some(BatterySave::new(&Dirs::file(&dirs.saves, rom_path))),
+            screenshot_base: Dirs::file(&dirs.screenshots, rom_path),
+            watch: None,
+        }
+    }
+
+    /// Keep the player's progress when the emulator exits or panics
+    fn save_on_exit(&mut self, console: &mut Console) {
+        match self.autosave.save_now(console) {
+            Ok(()) => info!("Saved {}", self.autosave.path().display()),
+            Err(err) => error!("Couldn't autosave: {err}"),
+        }
+        if let Some(battery) = &self.battery {
+            match battery.save(console) {
+                Ok(true) => info!("Saved {}", battery.path().display()),
+                Ok(false) => {}
+                Err(err) => error!("Couldn't write the battery save: {err}"),
+            }
+        }
+    }
+
+    /// Write `frame` to the first free `game-N.ppm`
+    fn screenshot(&self, frame: &Frame) -> Result<PathBuf, String> {
+        let stem = self.screenshot_base.with_extension("");
+        let path = (1..)
+            .map(|n| PathBuf::from(format!("{}-{n}.ppm", stem.display())))
+            .find(|path| !path.exists())
+            .unwrap();
+        std::fs::write(&path, frame.to_ppm()).map_err(|e| format!("{}: {e}", path.display()))?;
+        Ok(path)
+    }
+}
+
+/// Savestate slots. 0-9 picks a slot, the save and load state hotkeys (F5
+/// and F9) save to it and load it.
+/// Tab pauses and shows every slot's screenshot.
+struct Slots {
+    slots: SaveSlots,
+    /// Loaded once, then kept in sync with what we save
+    states: Vec<Option<SaveState>>,
+    selected: usize,
+    browsing: bool,
+}
+
+impl Slots {
+    const CELL: usize = 64;
+    /// Room [`Slots::draw`] needs
+    const WIDTH: usize = 5 * Self::CELL;
+    const HEIGHT: usize = 2 * Self::CELL;
+
+    fn new(rom_path: &Path) -> Self {
+        let slots = SaveSlots::new(rom_path);
+        Slots {
+            states: slots.list(),
+            slots,
+            selected: 0,
+            browsing: false,
+        }
+    }
+
+    fn save(&mut self, state: SaveState) -> Result<(), String> {
+        self.slots.save(self.selected, &state)?;
+        self.states[self.selected] = Some(state);
+        Ok(())
+    }
+
+    fn describe(&self) -> String {
+        match &self.states[self.selected] {
+            Some(state) => format!("slot {} (frame {})", self.selected, state.frame),
+            None => format!("slot {} (empty)", self.selected),
+        }
+    }
+
+    /// Thumbnails in a 5 by 2 grid, the selected slot outlined
+    /// Every slot's screenshot in a `buffer` with rows of `stride` pixels
+    fn draw(&self, buffer: &mut [u32], stride: usize) {
+        const CELL: usize = Slots::CELL;
+        buffer.fill(0);
+        for (slot, state) in self.states.iter().enumerate() {
+            let (left, top) = ((slot % 5) * CELL, (slot / 5) * CELL);
+            for y in 0..CELL {
+                for x in 0..CELL {
+                    let edge = x < 2 || y < 2 || x >= CELL - 2 || y >= CELL - 2;
+                    let pixel = match state.as_ref().and_then(|state| state.thumbnail.as_ref()) {
+                        _ if edge && slot == self.selected => 0xffffff,
+                        _ if edge => 0x404040,
+                        Some(thumb) if x < thumb.width as usize && y < thumb.height as usize => {
+                            thumb.pixels[y * thumb.width as usize + x]
+                        }
+                        _ => 0x202020,
+                    };
+                    buffer[(top + y) * stride + left + x] = pixel;
+                }
+            }
+        }
+    }
+}
+
+/// Disk sides go A then B, like the labels on them
+fn disk_side_name(side: usize) -> String {
+    let face = if side.is_multiple_of(2) { 'A' } else { 'B' };
+    format!("Disk {} side {face}", side / 2 + 1)
+}
+
+fn slot_key(key: KeyCode) -> Option<usize> {
+    let keys = [
+        KeyCode::Digit0,
+        KeyCode::Digit1,
+        KeyCode::Digit2,
+        KeyCode::Digit3,
+        KeyCode::Digit4,
+        KeyCode::Digit5,
+        KeyCode::Digit6,
+        KeyCode::Digit7,
+        KeyCode::Digit8,
+        KeyCode::Digit9,
+    ];
+    keys[..SLOT_COUNT].iter().position(|&slot| slot == key)
+}
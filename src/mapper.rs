@@ -0,0 +1,269 @@
+use std::time::SystemTime;
+
+use log::warn;
+
+use crate::apu::ExpansionChip;
+use crate::error::MapperError;
+use crate::ppu::nametable_index;
+use crate::rom::{Mirroring, Rom};
+use crate::savestate::{StateStream, Stateful};
+
+mod action53;
+mod axrom;
+mod bandai;
+mod barcode;
+mod camerica;
+mod cart_vram;
+mod cnrom;
+mod color_dreams;
+mod eeprom;
+mod fds;
+mod gtrom;
+mod mmc1;
+mod mmc3;
+mod mmc5;
+mod n163;
+mod nrom;
+mod opll;
+mod prg_ram;
+mod rtc;
+mod uxrom;
+mod vrc7;
+mod vrc_irq;
+
+pub use action53::Action53;
+pub use axrom::Axrom;
+pub use bandai::{Bandai, BandaiBoard};
+pub use barcode::BarcodeReader;
+pub use camerica::{Camerica, CamericaBoard};
+pub use cart_vram::CartVram;
+pub use cnrom::Cnrom;
+pub use color_dreams::ColorDreams;
+pub use fds::Fds;
+pub use gtrom::Gtrom;
+pub use mmc1::{Mmc1, Mmc1Board};
+pub use mmc3::{Mmc3, Mmc3Board, Mmc3Revision};
+pub use mmc5::Mmc5;
+pub use n163::N163;
+pub use nrom::Nrom;
+pub use prg_ram::PrgRam;
+pub use uxrom::Uxrom;
+pub use vrc7::Vrc7;
+
+/// Memory a PPU access ends up in
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PpuTarget {
+    /// Handled by [`Mapper::ppu_read`] and [`Mapper::ppu_write`]
+    Cartridge,
+    /// Offset into the console's 2K of nametable RAM
+    Ciram(usize),
+}
+
+/// Rendering fetches, in the order the PPU makes them
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PpuFetch {
+    Nametable,
+    Attribute,
+    BgPattern,
+    SpritePattern,
+    /// Garbage nametable fetches during sprite fetches and at the end of the line
+    Unused,
+}
+
+impl Stateful for PpuFetch {
+    fn stream(&mut self, s: &mut StateStream) {
+        let mut tag = *self as u8;
+        tag.stream(s);
+        *self = match tag {
+            0 => PpuFetch::Nametable,
+            1 => PpuFetch::Attribute,
+            2 => PpuFetch::BgPattern,
+            3 => PpuFetch::SpritePattern,
+            4 => PpuFetch::Unused,
+            _ => {
+                s.fail("invalid PPU fetch");
+                PpuFetch::Unused
+            }
+        };
+    }
+}
+
+/// Cartridge hardware. Handles everything the CPU sees at $4020-$FFFF
+/// and everything the PPU sees at $0000-$1FFF (pattern tables).
+/// Savestates stream the registers and RAM, never the ROM.
+/// `Send` so consoles can move between threads.
+pub trait Mapper: Stateful + Send {
+    /// Read without side effects
+    fn cpu_peek(&self, addr: u16) -> u8;
+    fn cpu_read(&mut self, addr: u16) -> u8 {
+        self.cpu_peek(addr)
+    }
+    fn cpu_write(&mut self, addr: u16, val: u8);
+    /// Where in PRG ROM a read of `addr` comes from with the current banks,
+    /// `None` for anything else like registers and PRG RAM
+    fn prg_rom_offset(&self, _addr: u16) -> Option<usize> {
+        None
+    }
+    /// Where in CHR memory a read of pattern table address `addr` comes from
+    /// with the current banks, for [`crate::ppu::TileCache`]. `None` keeps
+    /// tiles out of the cache, which boards that pick banks by what the PPU
+    /// is fetching need.
+    fn chr_offset(&self, _addr: u16) -> Option<usize> {
+        None
+    }
+    /// Whether a CPU write to `addr` does nothing on this board, which is
+    /// likely a bug in the game
+    fn ignores_write(&self, _addr: u16) -> bool {
+        false
+    }
+    /// Whether nothing on the board answers at `addr`, for
+    /// [`crate::UnmappedAccess`]
+    fn unmapped(&self, _addr: u16) -> bool {
+        false
+    }
+    /// Pattern tables go to the cartridge and nametables to CIRAM
+    /// following [`Mapper::mirroring`], unless the mapper says otherwise.
+    /// Four-screen boards keep the nametables at $2800 and $2C00 in their
+    /// [`CartVram`].
+    fn ppu_target(&self, addr: u16) -> PpuTarget {
+        fixed_mirroring(addr, self.mirroring())
+    }
+    /// The PPU is about to make a rendering fetch from `addr`.
+    /// Called before [`Mapper::ppu_target`], so the mapper can redirect it.
+    fn ppu_fetch(&mut self, _addr: u16, _kind: PpuFetch) {}
+    fn ppu_read(&mut self, addr: u16) -> u8;
+    fn ppu_write(&mut self, addr: u16, val: u8);
+    fn mirroring(&self) -> Mirroring;
+    /// PPU A12 went high after being low for long enough,
+    /// see [`crate::ppu::A12_FILTER_DOTS`]
+    fn a12_rise(&mut self) {}
+    /// A CPU write to $2000-$3FFF, which some mappers watch
+    fn ppu_register_write(&mut self, _addr: u16, _val: u8) {}
+    /// Advance by a single CPU cycle
+    fn cpu_tick(&mut self) {}
+    /// Whether the cartridge is asserting the CPU IRQ line
+    fn irq(&self) -> bool {
+        false
+    }
+    /// Battery backed memory to persist between sessions
+    fn save_data(&self) -> Option<Vec<u8>> {
+        None
+    }
+    /// Restore memory previously returned by [`Mapper::save_data`]
+    fn load_save_data(&mut self, _data: &[u8]) {}
+    /// Set the DIP switches on boards that have them, one bit per switch
+    fn set_dip_switches(&mut self, _switches: u8) {}
+    /// Output of the cartridge's sound chip, if it has one, in units where
+    /// 1.0 is the loudest the APU can get
+    fn expansion_audio(&self) -> Option<(ExpansionChip, f32)> {
+        None
+    }
+    /// Whether a CPU write to `addr` goes to the cartridge's sound chip,
+    /// for [`crate::register_log::RegisterLog`]
+    fn audio_register(&self, _addr: u16) -> bool {
+        false
+    }
+    /// Disk sides the drive can take, 0 for cartridges
+    fn disk_sides(&self) -> usize {
+        0
+    }
+    /// Side in the drive, or going in after a swap
+    fn disk_side(&self) -> Option<usize> {
+        None
+    }
+    /// Put `side` in the drive, or leave it empty. A disk that's already in
+    /// comes out first, and the new one goes in a moment later.
+    fn insert_disk(&mut self, _side: Option<usize>) {}
+    /// Skip mechanical delays like the disk drive rewinding
+    fn set_fast_load(&mut self, _fast: bool) {}
+    /// Set the real-time clock on boards that have one. Called at power on
+    /// and when [`crate::console::Console::set_time_source`] changes the
+    /// source, the clock then runs on CPU cycles and is kept in savestates.
+    fn set_time(&mut self, _now: SystemTime) {}
+    /// The barcode reader on boards that have one
+    fn barcode_reader(&mut self) -> Option<&mut BarcodeReader> {
+        None
+    }
+}
+
+/// The board for `rom`, NROM for boards that aren't emulated
+pub fn new(rom: Rom) -> Box<dyn Mapper> {
+    board(rom).unwrap_or_else(|(err, rom)| {
+        warn!("{err}, falling back to NROM");
+        Box::new(Nrom::new(*rom))
+    })
+}
+
+/// The board for `rom`
+pub fn try_new(rom: Rom) -> Result<Box<dyn Mapper>, MapperError> {
+    board(rom).map_err(|(err, _)| err)
+}
+
+/// The board for `rom`, or the ROM back if it isn't emulated
+fn board(rom: Rom) -> Result<Box<dyn Mapper>, (MapperError, Box<Rom>)> {
+    Ok(match rom.mapper {
+        0 => Box::new(Nrom::new(rom)),
+        1 | 105 => Box::new(Mmc1::new(rom)),
+        2 => Box::new(Uxrom::new(rom)),
+        3 => Box::new(Cnrom::new(rom)),
+        4 | 88 | 154 | 206 => Box::new(Mmc3::new(rom)),
+        5 => Box::new(Mmc5::new(rom)),
+        7 => Box::new(Axrom::new(rom)),
+        11 | 144 => Box::new(ColorDreams::new(rom)),
+        16 | 153 | 157 | 159 => Box::new(Bandai::new(rom)),
+        19 => Box::new(N163::new(rom)),
+        20 => Box::new(Fds::new(rom)),
+        28 => Box::new(Action53::new(rom)),
+        71 | 232 => Box::new(Camerica::new(rom)),
+        85 => Box::new(Vrc7::new(rom)),
+        111 => Box::new(Gtrom::new(rom)),
+        other => return Err((MapperError::Unsupported(other), Box::new(rom))),
+    })
+}
+
+/// Where [`Mapper::ppu_target`] goes for boards that don't redirect PPU
+/// accesses, with nametables following `mirroring`
+fn fixed_mirroring(addr: u16, mirroring: Mirroring) -> PpuTarget {
+    let four_screen = mirroring == Mirroring::FourScreen;
+    match addr & 0x3fff {
+        0x0000..=0x1fff => PpuTarget::Cartridge,
+        _ if four_screen && addr & 0x800 != 0 => PpuTarget::Cartridge,
+        _ => PpuTarget::Ciram(nametable_index(addr, mirroring)),
+    }
+}
+
+/// Nametables for the common 2 bit mirroring register:
+/// vertical, horizontal, single screen A, single screen B
+fn switchable_mirroring(addr: u16, mode: u8) -> PpuTarget {
+    let addr = addr as usize & 0x3fff;
+    if addr < 0x2000 {
+        return PpuTarget::Cartridge;
+    }
+    let table = match mode & 3 {
+        0 => (addr >> 10) & 1,
+        1 => (addr >> 11) & 1,
+        n => n as usize & 1,
+    };
+    PpuTarget::Ciram(table * 0x400 + (addr & 0x3ff))
+}
+
+/// Whether a discrete board ANDs written values with the ROM byte at the
+/// written address. NES 2.0 submapper 1 says no and 2 says yes, otherwise
+/// we go with what most boards for the mapper do.
+/// See https://www.nesdev.org/wiki/Bus_conflict
+fn bus_conflicts(rom: &Rom, default: bool) -> bool {
+    match rom.submapper {
+        1 => false,
+        2 => true,
+        _ => default,
+    }
+}
+
+/// CHR ROM, or 8K of CHR RAM if the cartridge has none
+fn chr_mem(rom: &Rom) -> (Vec<u8>, bool) {
+    if rom.chr_rom.is_empty() {
+        (vec![0; 0x2000], true)
+    } else {
+        (rom.chr_rom.clone(), false)
+    }
+}
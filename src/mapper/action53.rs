@@ -0,0 +1,135 @@
+use super::{Mapper, PpuTarget, switchable_mirroring};
+use crate::rom::{Mirroring, Rom};
+use crate::savestate::{StateStream, Stateful, stream};
+
+/// Mapper 28, Action 53: homebrew multicarts of NROM, CNROM, UxROM and
+/// AxROM games. The game size bits keep a game's own banking within its
+/// part of the ROM, picked by the outer bank.
+/// See https://www.nesdev.org/wiki/Action_53_mapper
+pub struct Action53 {
+    prg_rom: Vec<u8>,
+    chr: Vec<u8>,
+    chr_ram: bool,
+    /// Picked by writing $5000-$5FFF: CHR bank, inner bank, mode, outer bank
+    select: u8,
+    chr_bank: u8,
+    inner_bank: u8,
+    /// Bits 0-1 mirroring, 2-3 PRG banking mode and 4-5 game size
+    mode: u8,
+    /// 32K bank
+    outer_bank: u8,
+}
+
+impl Action53 {
+    pub fn new(rom: Rom) -> Self {
+        let chr_ram = rom.chr_rom.is_empty();
+        let chr = if chr_ram {
+            vec![0; 0x8000]
+        } else {
+            rom.chr_rom.clone()
+        };
+        Action53 {
+            prg_rom: rom.prg_rom,
+            chr,
+            chr_ram,
+            select: 0,
+            chr_bank: 0,
+            inner_bank: 0,
+            mode: match rom.mirroring {
+                Mirroring::Horizontal => 3,
+                _ => 2,
+            },
+            // The menu is in the last bank
+            outer_bank: 0xff,
+        }
+    }
+
+    fn prg_addr(&self, addr: u16) -> usize {
+        let upper = (addr as usize >> 14) & 1;
+        let outer = (self.outer_bank as usize) << 1;
+        let inner = self.inner_bank as usize & 0x0f;
+        let mask = (2 << ((self.mode >> 4) & 3)) - 1;
+        let bank = match ((self.mode >> 2) & 3, upper) {
+            (2, 0) | (3, 1) => outer | upper,
+            (0 | 1, _) => (outer & !mask) | (((inner << 1) | upper) & mask),
+            _ => (outer & !mask) | (inner & mask),
+        };
+        let bank_count = self.prg_rom.len() / 0x4000;
+        (bank % bank_count) * 0x4000 + (addr as usize & 0x3fff)
+    }
+
+    fn chr_addr(&self, addr: u16) -> usize {
+        let bank_count = self.chr.len() / 0x2000;
+        ((self.chr_bank & 3) as usize % bank_count) * 0x2000 + (addr as usize & 0x1fff)
+    }
+}
+
+impl Stateful for Action53 {
+    fn stream(&mut self, s: &mut StateStream) {
+        stream!(
+            s,
+            self.select,
+            self.chr_bank,
+            self.inner_bank,
+            self.mode,
+            self.outer_bank,
+        );
+        if self.chr_ram {
+            self.chr.stream(s);
+        }
+    }
+}
+
+impl Mapper for Action53 {
+    fn cpu_peek(&self, addr: u16) -> u8 {
+        self.prg_rom_offset(addr)
+            .map_or(0, |offset| self.prg_rom[offset])
+    }
+    fn prg_rom_offset(&self, addr: u16) -> Option<usize> {
+        (addr >= 0x8000).then(|| self.prg_addr(addr))
+    }
+    fn unmapped(&self, addr: u16) -> bool {
+        addr < 0x8000 && !(0x5000..=0x5FFF).contains(&addr)
+    }
+    fn cpu_write(&mut self, addr: u16, val: u8) {
+        match addr {
+            0x5000..=0x5FFF => self.select = ((val >> 6) & 2) | (val & 1),
+            0x8000..=0xFFFF => {
+                // The CHR and inner bank registers set one screen mirroring
+                // for AxROM games
+                if self.select < 2 && self.mode & 2 == 0 {
+                    self.mode = (self.mode & !1) | ((val >> 4) & 1);
+                }
+                match self.select {
+                    0 => self.chr_bank = val,
+                    1 => self.inner_bank = val,
+                    2 => self.mode = val,
+                    _ => self.outer_bank = val,
+                }
+            }
+            _ => {}
+        }
+    }
+    fn ppu_target(&self, addr: u16) -> PpuTarget {
+        // One screen A, one screen B, vertical, horizontal
+        switchable_mirroring(addr, (self.mode & 3) ^ 2)
+    }
+    fn chr_offset(&self, addr: u16) -> Option<usize> {
+        Some(self.chr_addr(addr))
+    }
+    fn ppu_read(&mut self, addr: u16) -> u8 {
+        self.chr[self.chr_addr(addr)]
+    }
+    fn ppu_write(&mut self, addr: u16, val: u8) {
+        if self.chr_ram {
+            let addr = self.chr_addr(addr);
+            self.chr[addr] = val;
+        }
+    }
+    fn mirroring(&self) -> Mirroring {
+        match self.mode & 3 {
+            3 => Mirroring::Horizontal,
+            _ => Mirroring::Vertical,
+        }
+    }
+}
@@ -0,0 +1,83 @@
+use super::{Mapper, PpuTarget, bus_conflicts, chr_mem, switchable_mirroring};
+use crate::rom::{Mirroring, Rom};
+use crate::savestate::{StateStream, Stateful, stream};
+
+/// Mapper 7, 32K PRG switching and single screen mirroring
+/// See https://www.nesdev.org/wiki/AxROM
+pub struct Axrom {
+    prg_rom: Vec<u8>,
+    chr: Vec<u8>,
+    chr_ram: bool,
+    bus_conflicts: bool,
+    bank: u8,
+}
+
+impl Axrom {
+    pub fn new(rom: Rom) -> Self {
+        let (chr, chr_ram) = chr_mem(&rom);
+        Axrom {
+            // Only AMROM has them, ANROM and AOROM were designed to avoid them
+            bus_conflicts: bus_conflicts(&rom, false),
+            prg_rom: rom.prg_rom,
+            chr,
+            chr_ram,
+            bank: 0,
+        }
+    }
+}
+
+impl Stateful for Axrom {
+    fn stream(&mut self, s: &mut StateStream) {
+        stream!(s, self.bank);
+        if self.chr_ram {
+            self.chr.stream(s);
+        }
+    }
+}
+
+impl Mapper for Axrom {
+    fn cpu_peek(&self, addr: u16) -> u8 {
+        self.prg_rom_offset(addr)
+            .map_or(0, |offset| self.prg_rom[offset])
+    }
+    fn prg_rom_offset(&self, addr: u16) -> Option<usize> {
+        match addr {
+            0x8000..=0xFFFF => {
+                let bank_count = self.prg_rom.len() / 0x8000;
+                let bank = (self.bank & 0x7) as usize % bank_count;
+                Some(bank * 0x8000 + (addr as usize & 0x7fff))
+            }
+            _ => None,
+        }
+    }
+    fn unmapped(&self, addr: u16) -> bool {
+        addr < 0x8000
+    }
+    fn cpu_write(&mut self, addr: u16, val: u8) {
+        if addr >= 0x8000 {
+            self.bank = if self.bus_conflicts {
+                val & self.cpu_peek(addr)
+            } else {
+                val
+            };
+        }
+    }
+    fn ppu_target(&self, addr: u16) -> PpuTarget {
+        // Bit 4 selects the nametable
+        switchable_mirroring(addr, 2 | ((self.bank >> 4) & 1))
+    }
+    fn chr_offset(&self, addr: u16) -> Option<usize> {
+        Some(addr as usize & 0x1fff)
+    }
+    fn ppu_read(&mut self, addr: u16) -> u8 {
+        self.chr[addr as usize & 0x1fff]
+    }
+    fn ppu_write(&mut self, addr: u16, val: u8) {
+        if self.chr_ram {
+            self.chr[addr as usize & 0x1fff] = val;
+        }
+    }
+    fn mirroring(&self) -> Mirroring {
+        Mirroring::Vertical
+    }
+}
@@ -0,0 +1,287 @@
+use std::time::SystemTime;
+
+use super::barcode::BarcodeReader;
+use super::eeprom::{Eeprom, EepromKind};
+use super::{Mapper, PpuTarget, chr_mem, switchable_mirroring};
+use crate::rom::{Mirroring, Rom};
+use crate::savestate::{StateStream, Stateful, stream};
+
+/// Bandai FCG family boards
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BandaiBoard {
+    /// Mapper 16, FCG-1/2 registers at $6000 and/or LZ93D50 registers at $8000
+    Fcg,
+    /// Mapper 153, LZ93D50 with 8K of PRG RAM and a 512K PRG outer bank
+    Lz93d50Sram,
+    /// Mapper 157, Datach Joint ROM System
+    Datach,
+    /// Mapper 159, LZ93D50 with a 24C01
+    Lz93d50X24c01,
+}
+
+/// Mappers 16, 153, 157 and 159
+/// See https://www.nesdev.org/wiki/Bandai_FCG_board
+pub struct Bandai {
+    board: BandaiBoard,
+    prg_rom: Vec<u8>,
+    prg_ram: Option<[u8; 0x2000]>,
+    prg_ram_enabled: bool,
+    chr: Vec<u8>,
+    chr_ram: bool,
+    /// Respond to writes at $6000-$7FFF and $8000-$FFFF
+    fcg_regs: bool,
+    lz93d50_regs: bool,
+    /// $x0-$x7, 1K CHR pages (the low bit of the first 4 is the PRG outer bank on mapper 153)
+    chr_banks: [u8; 8],
+    /// $x8, 16K page for $8000
+    prg_bank: u8,
+    /// $x9, vertical, horizontal, single screen A, single screen B
+    mirroring: u8,
+    irq_enabled: bool,
+    irq_counter: u16,
+    /// The LZ93D50 loads the counter from a latch, the FCG writes it directly
+    irq_latch: u16,
+    irq_pending: bool,
+    eeprom: Option<Eeprom>,
+    /// On the Datach
+    barcode: Option<BarcodeReader>,
+    /// A DS1307 sharing the EEPROM's bus on the Datach
+    clock: Option<Eeprom>,
+}
+
+impl Bandai {
+    pub fn new(rom: Rom) -> Self {
+        let (chr, chr_ram) = chr_mem(&rom);
+        let board = match rom.mapper {
+            153 => BandaiBoard::Lz93d50Sram,
+            157 => BandaiBoard::Datach,
+            159 => BandaiBoard::Lz93d50X24c01,
+            _ => BandaiBoard::Fcg,
+        };
+        // Mapper 16 submappers 4 and 5 pick a chip, without one we respond to both
+        let (fcg_regs, lz93d50_regs) = match (board, rom.submapper) {
+            (BandaiBoard::Fcg, 4) => (true, false),
+            (BandaiBoard::Fcg, 5) => (false, true),
+            (BandaiBoard::Fcg, _) => (true, true),
+            _ => (false, true),
+        };
+        let eeprom = match (board, rom.submapper) {
+            (BandaiBoard::Fcg, 4) | (BandaiBoard::Lz93d50Sram, _) => None,
+            (BandaiBoard::Lz93d50X24c01, _) => Some(Eeprom::new(EepromKind::X24C01)),
+            _ => Some(Eeprom::new(EepromKind::X24C02)),
+        };
+        Bandai {
+            board,
+            prg_rom: rom.prg_rom,
+            prg_ram: (board == BandaiBoard::Lz93d50Sram).then_some([0; 0x2000]),
+            prg_ram_enabled: false,
+            chr,
+            chr_ram,
+            fcg_regs,
+            lz93d50_regs,
+            chr_banks: [0; 8],
+            prg_bank: 0,
+            mirroring: 0,
+            irq_enabled: false,
+            irq_counter: 0,
+            irq_latch: 0,
+            irq_pending: false,
+            eeprom,
+            barcode: (board == BandaiBoard::Datach).then(BarcodeReader::default),
+            clock: (board == BandaiBoard::Datach).then(|| Eeprom::ds1307(rom.region)),
+        }
+    }
+
+    pub fn board(&self) -> BandaiBoard {
+        self.board
+    }
+
+    fn prg_addr(&self, addr: u16) -> usize {
+        let bank_count = self.prg_rom.len() / 0x4000;
+        let (outer, banks) = match self.board {
+            BandaiBoard::Lz93d50Sram => {
+                let outer = self.chr_banks[..4].iter().fold(0, |acc, b| acc | (b & 1));
+                (outer as usize * 16, 16)
+            }
+            _ => (0, bank_count),
+        };
+        let bank = match addr {
+            0x8000..=0xBFFF => outer + (self.prg_bank & 0xf) as usize,
+            _ => outer + banks - 1,
+        };
+        (bank % bank_count) * 0x4000 + (addr as usize & 0x3fff)
+    }
+
+    fn chr_addr(&self, addr: u16) -> usize {
+        if self.chr_ram {
+            return addr as usize & 0x1fff;
+        }
+        let bank = self.chr_banks[(addr as usize & 0x1fff) / 0x400] as usize;
+        let bank_count = self.chr.len() / 0x400;
+        (bank % bank_count) * 0x400 + (addr as usize & 0x3ff)
+    }
+
+    fn write_register(&mut self, reg: u16, val: u8, lz93d50: bool) {
+        match reg & 0xf {
+            n @ 0x0..=0x7 => self.chr_banks[n as usize] = val,
+            0x8 => self.prg_bank = val,
+            0x9 => self.mirroring = val & 3,
+            0xA => {
+                self.irq_enabled = val & 1 != 0;
+                self.irq_pending = false;
+                if lz93d50 {
+                    self.irq_counter = self.irq_latch;
+                }
+            }
+            0xB if lz93d50 => self.irq_latch = (self.irq_latch & 0xff00) | val as u16,
+            0xB => self.irq_counter = (self.irq_counter & 0xff00) | val as u16,
+            0xC if lz93d50 => self.irq_latch = (self.irq_latch & 0x00ff) | ((val as u16) << 8),
+            0xC => self.irq_counter = (self.irq_counter & 0x00ff) | ((val as u16) << 8),
+            0xD => {
+                self.prg_ram_enabled = val & 0x20 != 0;
+                for chip in self.eeprom.iter_mut().chain(&mut self.clock) {
+                    chip.write(val & 0x20 != 0, val & 0x40 != 0);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Which RAM and EEPROM the board has comes from the header, only their contents are saved
+impl Stateful for Bandai {
+    fn stream(&mut self, s: &mut StateStream) {
+        if let Some(prg_ram) = &mut self.prg_ram {
+            prg_ram.stream(s);
+        }
+        stream!(
+            s,
+            self.prg_ram_enabled,
+            self.chr_banks,
+            self.prg_bank,
+            self.mirroring,
+            self.irq_enabled,
+            self.irq_counter,
+            self.irq_latch,
+            self.irq_pending,
+        );
+        if let Some(eeprom) = &mut self.eeprom {
+            eeprom.stream(s);
+        }
+        if self.chr_ram {
+            self.chr.stream(s);
+        }
+        if let Some(barcode) = &mut self.barcode
+            && s.version() >= 7
+        {
+            barcode.stream(s);
+        }
+        if let Some(clock) = &mut self.clock
+            && s.version() >= 9
+        {
+            clock.stream(s);
+        }
+    }
+}
+
+impl Mapper for Bandai {
+    fn cpu_peek(&self, addr: u16) -> u8 {
+        match addr {
+            0x6000..=0x7FFF => match (&self.prg_ram, &self.eeprom) {
+                (Some(ram), _) if self.prg_ram_enabled => ram[addr as usize - 0x6000],
+                (_, Some(eeprom)) => {
+                    let barcode = self.barcode.as_ref().map_or(0, BarcodeReader::output);
+                    let sda = eeprom.sda() && self.clock.as_ref().is_none_or(Eeprom::sda);
+                    ((sda as u8) << 4) | barcode
+                }
+                _ => 0,
+            },
+            0x8000..=0xFFFF => self.prg_rom[self.prg_addr(addr)],
+            _ => 0,
+        }
+    }
+    fn prg_rom_offset(&self, addr: u16) -> Option<usize> {
+        (addr >= 0x8000).then(|| self.prg_addr(addr))
+    }
+    fn unmapped(&self, addr: u16) -> bool {
+        match addr {
+            0x6000..=0x7FFF => self.prg_ram.is_none() && self.eeprom.is_none() && !self.fcg_regs,
+            _ => addr < 0x6000,
+        }
+    }
+    fn cpu_write(&mut self, addr: u16, val: u8) {
+        match addr {
+            0x6000..=0x7FFF if self.prg_ram_enabled && self.prg_ram.is_some() => {
+                if let Some(ram) = &mut self.prg_ram {
+                    ram[addr as usize - 0x6000] = val;
+                }
+            }
+            0x6000..=0x7FFF if self.fcg_regs => self.write_register(addr, val, false),
+            0x8000..=0xFFFF if self.lz93d50_regs => self.write_register(addr, val, true),
+            _ => {}
+        }
+    }
+    fn ppu_target(&self, addr: u16) -> PpuTarget {
+        switchable_mirroring(addr, self.mirroring)
+    }
+    fn chr_offset(&self, addr: u16) -> Option<usize> {
+        Some(self.chr_addr(addr))
+    }
+    fn ppu_read(&mut self, addr: u16) -> u8 {
+        self.chr[self.chr_addr(addr)]
+    }
+    fn ppu_write(&mut self, addr: u16, val: u8) {
+        if self.chr_ram {
+            let addr = self.chr_addr(addr);
+            self.chr[addr] = val;
+        }
+    }
+    fn mirroring(&self) -> Mirroring {
+        match self.mirroring {
+            1 => Mirroring::Horizontal,
+            _ => Mirroring::Vertical,
+        }
+    }
+    fn cpu_tick(&mut self) {
+        if let Some(barcode) = &mut self.barcode {
+            barcode.tick();
+        }
+        if let Some(clock) = &mut self.clock {
+            clock.tick();
+        }
+        if self.irq_enabled {
+            self.irq_counter = self.irq_counter.wrapping_sub(1);
+            if self.irq_counter == 0 {
+                self.irq_pending = true;
+            }
+        }
+    }
+    fn irq(&self) -> bool {
+        self.irq_pending
+    }
+    fn save_data(&self) -> Option<Vec<u8>> {
+        match (&self.prg_ram, &self.eeprom) {
+            (Some(ram), _) => Some(ram.to_vec()),
+            (_, Some(eeprom)) => Some(eeprom.data().to_vec()),
+            _ => None,
+        }
+    }
+    fn load_save_data(&mut self, data: &[u8]) {
+        match (&mut self.prg_ram, &mut self.eeprom) {
+            (Some(ram), _) => {
+                let len = data.len().min(ram.len());
+                ram[..len].copy_from_slice(&data[..len]);
+            }
+            (_, Some(eeprom)) => eeprom.load(data),
+            _ => {}
+        }
+    }
+    fn set_time(&mut self, now: SystemTime) {
+        if let Some(clock) = &mut self.clock {
+            clock.set_time(now);
+        }
+    }
+    fn barcode_reader(&mut self) -> Option<&mut BarcodeReader> {
+        self.barcode.as_mut()
+    }
+}
@@ -0,0 +1,104 @@
+use crate::savestate::{StateStream, Stateful, stream};
+
+/// CPU cycles the reader spends on each module (thinnest bar) of a code
+const MODULE_CYCLES: u32 = 1000;
+/// Blank paper read before and after the code
+const QUIET_ZONE: usize = 33;
+
+/// EAN left-hand digits with odd parity (L codes), 1 for a bar. Even
+/// parity (G codes) are the right-hand codes backwards, and the right-hand
+/// codes (R) are these inverted.
+const L_CODES: [u8; 10] = [
+    0b0001101, 0b0011001, 0b0010011, 0b0111101, 0b0100011, 0b0110001, 0b0101111, 0b0111011,
+    0b0110111, 0b0001011,
+];
+/// Which left-hand digits of an EAN-13 code have even parity, from the
+/// first digit, which isn't printed as bars itself. Bit 5 is the second digit.
+const EAN13_PARITY: [u8; 10] = [
+    0b000000, 0b001011, 0b001101, 0b001110, 0b010011, 0b011001, 0b011100, 0b010101, 0b010110,
+    0b011010,
+];
+
+/// The Datach Joint ROM System's barcode reader. A scanned code comes out
+/// of $6000-$7FFF bit 3 a module at a time, low for bars.
+/// See https://www.nesdev.org/wiki/Datach_Joint_ROM_System
+#[derive(Default)]
+pub struct BarcodeReader {
+    /// Modules of the code being read, true for bars
+    modules: Vec<bool>,
+    /// CPU cycles since the scan started
+    cycle: u32,
+}
+
+impl Stateful for BarcodeReader {
+    fn stream(&mut self, s: &mut StateStream) {
+        stream!(s, self.modules, self.cycle);
+    }
+}
+
+impl BarcodeReader {
+    /// Swipe an EAN-13 or EAN-8 barcode, given as its 13 or 8 digits
+    /// including the check digit, which games check.
+    pub fn scan(&mut self, code: &str) -> Result<(), String> {
+        let digits: Vec<u8> = code
+            .bytes()
+            .map(|c| c.is_ascii_digit().then(|| c - b'0'))
+            .collect::<Option<_>>()
+            .ok_or_else(|| format!("barcode {code:?} isn't all digits"))?;
+        let (parity, left, right) = match digits.len() {
+            13 => (
+                EAN13_PARITY[digits[0] as usize],
+                &digits[1..7],
+                &digits[7..],
+            ),
+            8 => (0, &digits[..4], &digits[4..]),
+            n => return Err(format!("barcode {code:?} has {n} digits, not 13 or 8")),
+        };
+        let mut modules = vec![false; QUIET_ZONE];
+        let mut push = |bits: u8, len: u8| {
+            modules.extend((0..len).rev().map(|i| bits & (1 << i) != 0));
+        };
+        push(0b101, 3);
+        for (i, &digit) in left.iter().enumerate() {
+            let l_code = L_CODES[digit as usize];
+            if parity & (0b100000 >> i) != 0 {
+                push(reverse7(!l_code & 0x7f), 7);
+            } else {
+                push(l_code, 7);
+            }
+        }
+        push(0b01010, 5);
+        for &digit in right {
+            push(!L_CODES[digit as usize] & 0x7f, 7);
+        }
+        push(0b101, 3);
+        modules.resize(modules.len() + QUIET_ZONE, false);
+        self.modules = modules;
+        self.cycle = 0;
+        Ok(())
+    }
+
+    /// Whether a code is still being read
+    pub fn scanning(&self) -> bool {
+        self.cycle < self.modules.len() as u32 * MODULE_CYCLES
+    }
+
+    /// $6000-$7FFF bit 3, 0 while nothing is being read
+    pub fn output(&self) -> u8 {
+        match self.modules.get((self.cycle / MODULE_CYCLES) as usize) {
+            Some(false) => 0x08,
+            _ => 0,
+        }
+    }
+
+    pub fn tick(&mut self) {
+        if self.scanning() {
+            self.cycle += 1;
+        }
+    }
+}
+
+/// The low 7 bits of `bits` in the opposite order
+fn reverse7(bits: u8) -> u8 {
+    bits.reverse_bits() >> 1
+}
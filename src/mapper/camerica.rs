@@ -0,0 +1,118 @@
+use super::{Mapper, PpuTarget, chr_mem, fixed_mirroring, switchable_mirroring};
+use crate::rom::{Mirroring, Rom};
+use crate::savestate::{StateStream, Stateful, stream};
+
+/// Camerica and Codemasters boards
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CamericaBoard {
+    /// Mapper 71, BF9093 and BF9097: UxROM-like 16K switching at $8000 with
+    /// the last bank fixed. The BF9097 (submapper 1, Fire Hawk) adds single
+    /// screen mirroring at $8000-$9FFF.
+    Bf909x,
+    /// Mapper 232, BF9096 (Quattro multicarts): a 64K outer bank at
+    /// $8000-$BFFF, and the 16K bank within it at $C000-$FFFF
+    Bf9096,
+}
+
+/// Mappers 71 and 232, with 8K of CHR RAM and no bus conflicts
+/// See https://www.nesdev.org/wiki/INES_Mapper_071 and
+/// https://www.nesdev.org/wiki/INES_Mapper_232
+pub struct Camerica {
+    pub board: CamericaBoard,
+    prg_rom: Vec<u8>,
+    chr: Vec<u8>,
+    chr_ram: bool,
+    mirroring: Mirroring,
+    bank: u8,
+    /// The BF9096's 64K block
+    outer_bank: u8,
+    /// The BF9097's nametable, once the game has written the register.
+    /// Other boards ignore those writes and games made for them don't make
+    /// any, so a write is how a BF9097 without a submapper shows itself.
+    single_screen: Option<u8>,
+}
+
+impl Camerica {
+    pub fn new(rom: Rom) -> Self {
+        let (chr, chr_ram) = chr_mem(&rom);
+        let board = match rom.mapper {
+            232 => CamericaBoard::Bf9096,
+            _ => CamericaBoard::Bf909x,
+        };
+        Camerica {
+            board,
+            single_screen: (board == CamericaBoard::Bf909x && rom.submapper == 1).then_some(0),
+            prg_rom: rom.prg_rom,
+            chr,
+            chr_ram,
+            mirroring: rom.mirroring,
+            bank: 0,
+            outer_bank: 0,
+        }
+    }
+}
+
+impl Stateful for Camerica {
+    fn stream(&mut self, s: &mut StateStream) {
+        stream!(s, self.bank, self.outer_bank, self.single_screen);
+        if self.chr_ram {
+            self.chr.stream(s);
+        }
+    }
+}
+
+impl Mapper for Camerica {
+    fn cpu_peek(&self, addr: u16) -> u8 {
+        self.prg_rom_offset(addr)
+            .map_or(0, |offset| self.prg_rom[offset])
+    }
+    fn prg_rom_offset(&self, addr: u16) -> Option<usize> {
+        let bank_count = self.prg_rom.len() / 0x4000;
+        let bank = match (self.board, addr) {
+            (CamericaBoard::Bf909x, 0x8000..=0xBFFF) => self.bank as usize,
+            (CamericaBoard::Bf909x, 0xC000..=0xFFFF) => bank_count - 1,
+            (CamericaBoard::Bf9096, 0x8000..=0xBFFF) => {
+                (self.outer_bank as usize * 4) | (self.bank & 3) as usize
+            }
+            (CamericaBoard::Bf9096, 0xC000..=0xFFFF) => self.outer_bank as usize * 4 + 3,
+            _ => return None,
+        };
+        Some((bank % bank_count) * 0x4000 + (addr as usize & 0x3fff))
+    }
+    fn unmapped(&self, addr: u16) -> bool {
+        addr < 0x8000
+    }
+    fn cpu_write(&mut self, addr: u16, val: u8) {
+        match (self.board, addr) {
+            (CamericaBoard::Bf909x, 0x8000..=0x9FFF)
+                if addr >= 0x9000 || self.single_screen.is_some() =>
+            {
+                self.single_screen = Some((val >> 4) & 1);
+            }
+            (CamericaBoard::Bf909x, 0xC000..=0xFFFF) => self.bank = val,
+            (CamericaBoard::Bf9096, 0x8000..=0xBFFF) => self.outer_bank = (val >> 3) & 3,
+            (CamericaBoard::Bf9096, 0xC000..=0xFFFF) => self.bank = val,
+            _ => {}
+        }
+    }
+    fn ppu_target(&self, addr: u16) -> PpuTarget {
+        match self.single_screen {
+            Some(screen) => switchable_mirroring(addr, 2 | screen),
+            None => fixed_mirroring(addr, self.mirroring),
+        }
+    }
+    fn chr_offset(&self, addr: u16) -> Option<usize> {
+        Some(addr as usize & 0x1fff)
+    }
+    fn ppu_read(&mut self, addr: u16) -> u8 {
+        self.chr[addr as usize & 0x1fff]
+    }
+    fn ppu_write(&mut self, addr: u16, val: u8) {
+        if self.chr_ram {
+            self.chr[addr as usize & 0x1fff] = val;
+        }
+    }
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+}
@@ -0,0 +1,45 @@
+use crate::rom::{Mirroring, Rom};
+use crate::savestate::{StateStream, Stateful};
+
+/// The extra 2K of nametable RAM on four-screen boards, like the ones
+/// Gauntlet and Rad Racer II come on. It holds the nametables at $2800 and
+/// $2C00 while CIRAM keeps the ones at $2000 and $2400. The default
+/// [`super::Mapper::ppu_target`] sends those two here through
+/// [`super::Mapper::ppu_read`] and [`super::Mapper::ppu_write`].
+/// Empty on every other board.
+pub struct CartVram(Vec<u8>);
+
+impl CartVram {
+    pub fn new(rom: &Rom) -> Self {
+        match rom.mirroring {
+            Mirroring::FourScreen => CartVram(vec![0; 0x800]),
+            _ => CartVram(Vec::new()),
+        }
+    }
+
+    /// Whether `addr` is a nametable, rather than a pattern table
+    pub fn contains(addr: u16) -> bool {
+        addr & 0x3fff >= 0x2000
+    }
+
+    pub fn read(&self, addr: u16) -> u8 {
+        self.0.get(addr as usize & 0x7ff).copied().unwrap_or(0)
+    }
+
+    pub fn write(&mut self, addr: u16, val: u8) {
+        if let Some(byte) = self.0.get_mut(addr as usize & 0x7ff) {
+            *byte = val;
+        }
+    }
+}
+
+impl Stateful for CartVram {
+    fn stream(&mut self, s: &mut StateStream) {
+        // Four-screen games had only CIRAM before version 6
+        if s.version() >= 6 {
+            for byte in &mut self.0 {
+                byte.stream(s);
+            }
+        }
+    }
+}
@@ -0,0 +1,82 @@
+use super::{CartVram, Mapper, bus_conflicts, chr_mem};
+use crate::rom::{Mirroring, Rom};
+use crate::savestate::{StateStream, Stateful, stream};
+
+/// Mapper 3, 8K CHR switching
+/// See https://www.nesdev.org/wiki/INES_Mapper_003
+pub struct Cnrom {
+    prg_rom: Vec<u8>,
+    chr: Vec<u8>,
+    vram: CartVram,
+    mirroring: Mirroring,
+    bus_conflicts: bool,
+    bank: u8,
+}
+
+impl Cnrom {
+    pub fn new(rom: Rom) -> Self {
+        let (chr, _) = chr_mem(&rom);
+        let vram = CartVram::new(&rom);
+        Cnrom {
+            bus_conflicts: bus_conflicts(&rom, true),
+            prg_rom: rom.prg_rom,
+            chr,
+            vram,
+            mirroring: rom.mirroring,
+            bank: 0,
+        }
+    }
+
+    fn chr_addr(&self, addr: u16) -> usize {
+        let bank_count = self.chr.len() / 0x2000;
+        (self.bank as usize % bank_count) * 0x2000 + (addr as usize & 0x1fff)
+    }
+}
+
+impl Stateful for Cnrom {
+    fn stream(&mut self, s: &mut StateStream) {
+        stream!(s, self.bank, self.vram);
+    }
+}
+
+impl Mapper for Cnrom {
+    fn cpu_peek(&self, addr: u16) -> u8 {
+        self.prg_rom_offset(addr)
+            .map_or(0, |offset| self.prg_rom[offset])
+    }
+    fn prg_rom_offset(&self, addr: u16) -> Option<usize> {
+        match addr {
+            0x8000..=0xFFFF => Some((addr - 0x8000) as usize % self.prg_rom.len()),
+            _ => None,
+        }
+    }
+    fn unmapped(&self, addr: u16) -> bool {
+        addr < 0x8000
+    }
+    fn cpu_write(&mut self, addr: u16, val: u8) {
+        if addr >= 0x8000 {
+            self.bank = if self.bus_conflicts {
+                val & self.cpu_peek(addr)
+            } else {
+                val
+            };
+        }
+    }
+    fn chr_offset(&self, addr: u16) -> Option<usize> {
+        Some(self.chr_addr(addr))
+    }
+    fn ppu_read(&mut self, addr: u16) -> u8 {
+        if CartVram::contains(addr) {
+            return self.vram.read(addr);
+        }
+        self.chr[self.chr_addr(addr)]
+    }
+    fn ppu_write(&mut self, addr: u16, val: u8) {
+        if CartVram::contains(addr) {
+            self.vram.write(addr, val);
+        }
+    }
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+}
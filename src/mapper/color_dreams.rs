@@ -0,0 +1,98 @@
+use super::{CartVram, Mapper, bus_conflicts, chr_mem};
+use crate::rom::{Mirroring, Rom};
+use crate::savestate::{StateStream, Stateful, stream};
+
+/// Mapper 11 (Color Dreams) and 144 (AGCI 50282, Death Race): 32K PRG
+/// switching with bits 0-1 and 8K CHR switching with bits 4-7.
+/// On mapper 144 only the ROM drives D0 of the latch, so it always sees a
+/// bus conflict on bit 0.
+/// See https://www.nesdev.org/wiki/Color_Dreams and
+/// https://www.nesdev.org/wiki/INES_Mapper_144
+pub struct ColorDreams {
+    prg_rom: Vec<u8>,
+    chr: Vec<u8>,
+    chr_ram: bool,
+    vram: CartVram,
+    mirroring: Mirroring,
+    bus_conflicts: bool,
+    /// Mapper 144
+    rom_drives_d0: bool,
+    bank: u8,
+}
+
+impl ColorDreams {
+    pub fn new(rom: Rom) -> Self {
+        let (chr, chr_ram) = chr_mem(&rom);
+        let vram = CartVram::new(&rom);
+        ColorDreams {
+            bus_conflicts: bus_conflicts(&rom, false),
+            rom_drives_d0: rom.mapper == 144,
+            prg_rom: rom.prg_rom,
+            chr,
+            chr_ram,
+            vram,
+            mirroring: rom.mirroring,
+            bank: 0,
+        }
+    }
+
+    fn chr_addr(&self, addr: u16) -> usize {
+        let bank_count = self.chr.len() / 0x2000;
+        ((self.bank >> 4) as usize % bank_count) * 0x2000 + (addr as usize & 0x1fff)
+    }
+}
+
+impl Stateful for ColorDreams {
+    fn stream(&mut self, s: &mut StateStream) {
+        stream!(s, self.bank);
+        if self.chr_ram {
+            self.chr.stream(s);
+        }
+        self.vram.stream(s);
+    }
+}
+
+impl Mapper for ColorDreams {
+    fn cpu_peek(&self, addr: u16) -> u8 {
+        self.prg_rom_offset(addr)
+            .map_or(0, |offset| self.prg_rom[offset])
+    }
+    fn prg_rom_offset(&self, addr: u16) -> Option<usize> {
+        let bank_count = self.prg_rom.len() / 0x8000;
+        let bank = (self.bank & 3) as usize % bank_count;
+        (addr >= 0x8000).then(|| bank * 0x8000 + (addr as usize & 0x7fff))
+    }
+    fn unmapped(&self, addr: u16) -> bool {
+        addr < 0x8000
+    }
+    fn cpu_write(&mut self, addr: u16, val: u8) {
+        if addr >= 0x8000 {
+            let rom = self.cpu_peek(addr);
+            self.bank = match (self.rom_drives_d0, self.bus_conflicts) {
+                (true, _) => (val | 1) & rom,
+                (false, true) => val & rom,
+                (false, false) => val,
+            };
+        }
+    }
+    fn chr_offset(&self, addr: u16) -> Option<usize> {
+        Some(self.chr_addr(addr))
+    }
+    fn ppu_read(&mut self, addr: u16) -> u8 {
+        if CartVram::contains(addr) {
+            return self.vram.read(addr);
+        }
+        self.chr[self.chr_addr(addr)]
+    }
+    fn ppu_write(&mut self, addr: u16, val: u8) {
+        if CartVram::contains(addr) {
+            self.vram.write(addr, val);
+        } else if self.chr_ram {
+            let addr = self.chr_addr(addr);
+            self.chr[addr] = val;
+        }
+    }
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+}
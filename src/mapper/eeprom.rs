@@ -0,0 +1,272 @@
+use std::time::SystemTime;
+
+use super::rtc::Rtc;
+use crate::rom::Region;
+use crate::savestate::{StateStream, Stateful, stream};
+
+/// Serial EEPROMs found on Bandai boards
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EepromKind {
+    /// 128 bytes, the first byte holds the word address and R/W bit
+    X24C01,
+    /// 256 bytes, standard I2C with a device address byte
+    X24C02,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Phase {
+    Idle,
+    Device,
+    Address,
+    Write,
+    Read,
+}
+
+/// I2C EEPROM driven by bit-banging SCL and SDA, or a DS1307 clock, which
+/// talks the same protocol
+/// See https://www.nesdev.org/wiki/Bandai_FCG_board#Serial_EEPROM
+pub struct Eeprom {
+    kind: EepromKind,
+    data: Vec<u8>,
+    /// On a DS1307, behind the first 7 bytes
+    clock: Option<Rtc>,
+    scl: bool,
+    sda: bool,
+    /// SDA as driven by the EEPROM, high when released
+    out: bool,
+    phase: Phase,
+    /// Phase once the ongoing byte is acknowledged
+    next: Phase,
+    /// Clock within the byte, 8 is the acknowledge clock
+    bit: u8,
+    shift: u8,
+    addr: u8,
+    master_ack: bool,
+}
+
+impl Stateful for Phase {
+    fn stream(&mut self, s: &mut StateStream) {
+        let mut tag = *self as u8;
+        tag.stream(s);
+        *self = match tag {
+            0 => Phase::Idle,
+            1 => Phase::Device,
+            2 => Phase::Address,
+            3 => Phase::Write,
+            4 => Phase::Read,
+            _ => {
+                s.fail("invalid EEPROM phase");
+                Phase::Idle
+            }
+        };
+    }
+}
+
+impl Stateful for Eeprom {
+    fn stream(&mut self, s: &mut StateStream) {
+        stream!(
+            s,
+            self.data,
+            self.scl,
+            self.sda,
+            self.out,
+            self.phase,
+            self.next,
+            self.bit,
+            self.shift,
+            self.addr,
+            self.master_ack,
+        );
+        if let Some(clock) = &mut self.clock {
+            clock.stream(s);
+        }
+    }
+}
+
+impl Eeprom {
+    pub fn new(kind: EepromKind) -> Self {
+        let size = match kind {
+            EepromKind::X24C01 => 0x80,
+            EepromKind::X24C02 => 0x100,
+        };
+        Eeprom {
+            kind,
+            data: vec![0xff; size],
+            clock: None,
+            scl: false,
+            sda: false,
+            out: true,
+            phase: Phase::Idle,
+            next: Phase::Idle,
+            bit: 0,
+            shift: 0,
+            addr: 0,
+            master_ack: false,
+        }
+    }
+
+    /// A DS1307 at device address $D0: a clock in registers $00-$06,
+    /// control at $07 and 56 bytes of RAM at $08-$3F
+    pub fn ds1307(region: Region) -> Self {
+        Eeprom {
+            data: vec![0; 0x40],
+            clock: Some(Rtc::new(region)),
+            ..Eeprom::new(EepromKind::X24C02)
+        }
+    }
+
+    /// Set and start the clock on a DS1307
+    pub fn set_time(&mut self, now: SystemTime) {
+        if let Some(clock) = &mut self.clock {
+            clock.set(now);
+        }
+    }
+
+    /// Run the clock on a DS1307 for a CPU cycle
+    pub fn tick(&mut self) {
+        if let Some(clock) = &mut self.clock {
+            clock.tick();
+        }
+    }
+
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    pub fn load(&mut self, data: &[u8]) {
+        let len = data.len().min(self.data.len());
+        self.data[..len].copy_from_slice(&data[..len]);
+    }
+
+    /// SDA as seen by the CPU
+    pub fn sda(&self) -> bool {
+        self.out && self.sda
+    }
+
+    pub fn write(&mut self, scl: bool, sda: bool) {
+        let (old_scl, old_sda) = (self.scl, self.sda);
+        self.scl = scl;
+        self.sda = sda;
+        match (old_scl, scl) {
+            (true, true) if old_sda && !sda => self.start(),
+            (true, true) if !old_sda && sda => {
+                self.phase = Phase::Idle;
+                self.out = true;
+            }
+            (false, true) => self.rise(),
+            (true, false) => self.fall(),
+            _ => {}
+        }
+    }
+
+    fn start(&mut self) {
+        self.phase = match self.kind {
+            EepromKind::X24C01 => Phase::Address,
+            EepromKind::X24C02 => Phase::Device,
+        };
+        self.bit = 0;
+        self.shift = 0;
+        self.out = true;
+    }
+
+    fn rise(&mut self) {
+        match self.phase {
+            Phase::Idle => {}
+            Phase::Device | Phase::Address | Phase::Write if self.bit < 8 => {
+                self.shift = (self.shift << 1) | self.sda as u8;
+                self.bit += 1;
+            }
+            Phase::Read if self.bit == 8 => {
+                self.master_ack = !self.sda;
+                self.bit += 1;
+            }
+            _ => self.bit += 1,
+        }
+    }
+
+    fn fall(&mut self) {
+        match (self.phase, self.bit) {
+            (Phase::Idle, _) => {}
+            (Phase::Read, 0..=7) => self.out = (self.shift >> (7 - self.bit)) & 1 != 0,
+            // Released for the master's acknowledge
+            (Phase::Read, 8) => self.out = true,
+            (Phase::Read, _) => {
+                self.addr = self.addr.wrapping_add(1) & (self.data.len() - 1) as u8;
+                if self.master_ack {
+                    self.load_read_byte();
+                } else {
+                    self.out = true;
+                    self.phase = Phase::Idle;
+                }
+            }
+            (_, 8) => self.receive(),
+            (_, 9) => {
+                self.out = true;
+                self.phase = self.next;
+                self.bit = 0;
+                self.shift = 0;
+                if self.phase == Phase::Read {
+                    self.load_read_byte();
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Handle a complete byte from the master and acknowledge it
+    fn receive(&mut self) {
+        let byte = self.shift;
+        self.next = match (self.phase, self.kind) {
+            (Phase::Device, _) if !self.addressed(byte) => {
+                // Not addressed to us, no acknowledge
+                self.phase = Phase::Idle;
+                return;
+            }
+            (Phase::Device, _) if byte & 1 != 0 => Phase::Read,
+            (Phase::Device, _) => Phase::Address,
+            (Phase::Address, EepromKind::X24C01) => {
+                self.addr = byte >> 1;
+                if byte & 1 != 0 {
+                    Phase::Read
+                } else {
+                    Phase::Write
+                }
+            }
+            (Phase::Address, EepromKind::X24C02) => {
+                self.addr = byte;
+                Phase::Write
+            }
+            _ => {
+                match &mut self.clock {
+                    Some(clock) if self.addr < 7 => clock.write(self.addr, byte),
+                    _ => self.data[self.addr as usize] = byte,
+                }
+                // Writes wrap around within a page, the DS1307's is all of it
+                let page = match (self.kind, &self.clock) {
+                    (_, Some(_)) => 0x40,
+                    (EepromKind::X24C01, _) => 4,
+                    (EepromKind::X24C02, _) => 8,
+                };
+                self.addr = (self.addr & !(page - 1)) | (self.addr.wrapping_add(1) & (page - 1));
+                Phase::Write
+            }
+        };
+        self.out = false;
+    }
+
+    fn addressed(&self, device: u8) -> bool {
+        match self.clock {
+            Some(_) => device & 0xfe == 0xd0,
+            None => device & 0xf0 == 0xa0,
+        }
+    }
+
+    fn load_read_byte(&mut self) {
+        self.shift = match &self.clock {
+            Some(clock) if self.addr < 7 => clock.read(self.addr),
+            _ => self.data[self.addr as usize],
+        };
+        self.bit = 0;
+        self.out = self.shift & 0x80 != 0;
+    }
+}
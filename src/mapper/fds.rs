@@ -0,0 +1,475 @@
+use super::Mapper;
+use crate::rom::{FDS_SIDE_LEN, Mirroring, Rom};
+use crate::savestate::{StateStream, Stateful, stream};
+
+/// CPU cycles per byte passing under the head, about 96.4 kbit/s
+const BYTE_CYCLES: u32 = 150;
+/// CPU cycles for the head to go back to the start of the disk
+const REWIND_CYCLES: u32 = 50000;
+/// CPU cycles between taking a disk out and the next one going in, about a
+/// second, so games see the drive empty in between
+const SWAP_CYCLES: u32 = 1_800_000;
+/// Gap before the first block, 28300 bits
+const LEAD_IN: usize = 28300 / 8;
+/// Gap after each block, 976 bits
+const BLOCK_GAP: usize = 976 / 8;
+
+/// Mapper 20, the Famicom Disk System: the RAM adapter's 32K of PRG RAM,
+/// 8K of CHR RAM, timer IRQ and disk drive, running the BIOS from PRG ROM.
+/// Its sound channel isn't emulated.
+/// See https://www.nesdev.org/wiki/Family_Computer_Disk_System
+pub struct Fds {
+    bios: Vec<u8>,
+    prg_ram: Vec<u8>,
+    chr_ram: Vec<u8>,
+    /// Disk sides as the head sees them, blocks between gaps, each starting
+    /// with a $80 mark and ending with a CRC
+    sides: Vec<Vec<u8>>,
+    /// `sides` as they came in the image, what the game wrote is the difference
+    original: Vec<Vec<u8>>,
+    /// The game has written the disk, so savestates carry it
+    written: bool,
+    /// Skip the drive's mechanical delays, see [`Mapper::set_fast_load`]
+    fast_load: bool,
+    /// Side in the drive
+    side: Option<u8>,
+    /// Side going in once `swap_delay` runs out
+    next_side: Option<u8>,
+    swap_delay: u32,
+    irq_reload: u16,
+    irq_counter: u16,
+    irq_enabled: bool,
+    irq_repeat: bool,
+    timer_irq: bool,
+    /// $4023 bit 0, the drive and timer registers respond
+    disk_io: bool,
+    /// $4025: motor on, transfer reset, read mode, horizontal mirroring, CRC,
+    /// unused, start of a block expected and byte IRQs enabled, from bit 0
+    control: u8,
+    write_data: u8,
+    read_data: u8,
+    /// A byte was read or is due to be written, cleared by $4030 and $4031
+    transfer_done: bool,
+    disk_irq: bool,
+    /// Byte of the side under the head
+    position: usize,
+    /// CPU cycles until the next byte
+    delay: u32,
+    /// The head is at the end of the disk, or the drive is stopped
+    end_of_head: bool,
+    /// The head is moving over the disk
+    scanning: bool,
+    /// Reading has found the mark starting a block
+    gap_ended: bool,
+    crc: u16,
+    /// `control` bit 4 on the last byte, for appending the CRC
+    was_crc: bool,
+}
+
+impl Fds {
+    pub fn new(rom: Rom) -> Self {
+        let sides: Vec<Vec<u8>> = rom.disk_sides.iter().map(|side| add_gaps(side)).collect();
+        Fds {
+            bios: rom.prg_rom,
+            prg_ram: vec![0; 0x8000],
+            chr_ram: vec![0; 0x2000],
+            original: sides.clone(),
+            sides,
+            written: false,
+            fast_load: false,
+            side: Some(0),
+            next_side: None,
+            swap_delay: 0,
+            irq_reload: 0,
+            irq_counter: 0,
+            irq_enabled: false,
+            irq_repeat: false,
+            timer_irq: false,
+            disk_io: false,
+            control: 0,
+            write_data: 0,
+            read_data: 0,
+            transfer_done: false,
+            disk_irq: false,
+            position: 0,
+            delay: 0,
+            end_of_head: true,
+            scanning: false,
+            gap_ended: false,
+            crc: 0,
+            was_crc: false,
+        }
+    }
+
+    fn control(&self, bit: u8) -> bool {
+        self.control & (1 << bit) != 0
+    }
+
+    fn status(&self) -> u8 {
+        (self.timer_irq as u8) | ((self.transfer_done as u8) << 1) | ((self.end_of_head as u8) << 6)
+    }
+
+    fn drive_status(&self) -> u8 {
+        let empty = self.side.is_none();
+        (empty as u8) | (((empty || !self.scanning) as u8) << 1) | ((empty as u8) << 2)
+    }
+
+    fn update_crc(&mut self, val: u8) {
+        for bit in 0..8 {
+            let carry = self.crc & 1 != 0;
+            self.crc >>= 1;
+            if carry {
+                self.crc ^= 0x8408;
+            }
+            if val & (1 << bit) != 0 {
+                self.crc ^= 0x8000;
+            }
+        }
+    }
+
+    fn clock_timer(&mut self) {
+        if !self.irq_enabled {
+            return;
+        }
+        if self.irq_counter == 0 {
+            self.timer_irq = true;
+            self.irq_counter = self.irq_reload;
+            self.irq_enabled = self.irq_repeat;
+        } else {
+            self.irq_counter -= 1;
+        }
+    }
+
+    /// The byte under the head goes past
+    fn transfer(&mut self, side: usize) {
+        let irq = self.control(7);
+        if self.control(2) {
+            let val = self.sides[side][self.position];
+            if !self.was_crc {
+                self.update_crc(val);
+            }
+            if !self.control(6) {
+                self.gap_ended = false;
+                self.crc = 0;
+            } else if val != 0 && !self.gap_ended {
+                // The mark starting the block
+                self.gap_ended = true;
+                self.read_data = val;
+                self.transfer_done = true;
+                return;
+            }
+            if self.gap_ended {
+                self.read_data = val;
+                self.transfer_done = true;
+                self.disk_irq |= irq;
+            }
+        } else {
+            let mut val = 0;
+            if !self.control(4) {
+                self.transfer_done = true;
+                self.disk_irq |= irq;
+                val = self.write_data;
+            }
+            if !self.control(6) {
+                val = 0;
+            }
+            if !self.control(4) {
+                self.update_crc(val);
+            } else {
+                if !self.was_crc {
+                    self.update_crc(0);
+                    self.update_crc(0);
+                }
+                val = self.crc as u8;
+                self.crc >>= 8;
+            }
+            self.sides[side][self.position] = val;
+            self.written = true;
+            self.gap_ended = false;
+        }
+    }
+}
+
+/// An `.fds` side as the drive sees it, see [`Fds::sides`]. The CRCs are
+/// made up, the BIOS doesn't check them.
+fn add_gaps(side: &[u8]) -> Vec<u8> {
+    let mut disk = vec![0; LEAD_IN];
+    let mut pos = 0;
+    while pos < side.len() {
+        let len = match side[pos] {
+            1 => 56,
+            2 => 2,
+            3 => 16,
+            // The file header before it has the size
+            4 if pos >= 16 => 1 + u16::from_le_bytes([side[pos - 3], side[pos - 2]]) as usize,
+            _ => break,
+        };
+        let Some(block) = side.get(pos..pos + len) else {
+            break;
+        };
+        disk.push(0x80);
+        disk.extend_from_slice(block);
+        disk.extend_from_slice(&[0x4d, 0x62]);
+        disk.extend_from_slice(&[0; BLOCK_GAP]);
+        pos += len;
+    }
+    // Room for files the game adds
+    disk.resize(disk.len().max(LEAD_IN + FDS_SIDE_LEN), 0);
+    disk
+}
+
+impl Stateful for Fds {
+    fn stream(&mut self, s: &mut StateStream) {
+        stream!(
+            s,
+            self.prg_ram,
+            self.chr_ram,
+            self.side,
+            self.next_side,
+            self.swap_delay,
+            self.irq_reload,
+            self.irq_counter,
+            self.irq_enabled,
+            self.irq_repeat,
+            self.timer_irq,
+            self.disk_io,
+            self.control,
+            self.write_data,
+            self.read_data,
+            self.transfer_done,
+            self.disk_irq,
+            self.delay,
+            self.end_of_head,
+            self.scanning,
+            self.gap_ended,
+            self.crc,
+            self.was_crc,
+        );
+        let mut position = self.position as u32;
+        position.stream(s);
+        self.position = position as usize;
+        self.written.stream(s);
+        if self.written && s.is_loading() {
+            let mut sides = Vec::new();
+            sides.stream(s);
+            let lengths = |sides: &[Vec<u8>]| sides.iter().map(Vec::len).collect::<Vec<_>>();
+            if lengths(&sides) == lengths(&self.original) {
+                self.sides = sides;
+            } else {
+                s.fail("disk sides don't match the image");
+            }
+        } else if self.written {
+            self.sides.stream(s);
+        } else if s.is_loading() {
+            self.sides = self.original.clone();
+        }
+    }
+}
+
+impl Mapper for Fds {
+    fn cpu_peek(&self, addr: u16) -> u8 {
+        match addr {
+            0x4030 => self.status(),
+            0x4031 => self.read_data,
+            0x4032 => self.drive_status(),
+            // The battery is good
+            0x4033 => 0x80,
+            0x6000..=0xDFFF => self.prg_ram[addr as usize - 0x6000],
+            0xE000..=0xFFFF => self.bios[addr as usize - 0xE000],
+            _ => 0,
+        }
+    }
+    fn cpu_read(&mut self, addr: u16) -> u8 {
+        let val = self.cpu_peek(addr);
+        match addr {
+            0x4030 => {
+                self.transfer_done = false;
+                self.timer_irq = false;
+                self.disk_irq = false;
+            }
+            0x4031 => {
+                self.transfer_done = false;
+                self.disk_irq = false;
+            }
+            _ => {}
+        }
+        val
+    }
+    fn prg_rom_offset(&self, addr: u16) -> Option<usize> {
+        (addr >= 0xE000).then(|| addr as usize - 0xE000)
+    }
+    fn ignores_write(&self, addr: u16) -> bool {
+        addr >= 0xE000
+    }
+    fn unmapped(&self, addr: u16) -> bool {
+        // The sound registers at $4040-$4097 are on the RAM adapter too
+        addr < 0x6000 && !matches!(addr, 0x4020..=0x4026 | 0x4030..=0x4033 | 0x4040..=0x4097)
+    }
+    fn cpu_write(&mut self, addr: u16, val: u8) {
+        match addr {
+            0x4023 => {
+                self.disk_io = val & 1 != 0;
+                if !self.disk_io {
+                    self.irq_enabled = false;
+                    self.timer_irq = false;
+                    self.disk_irq = false;
+                }
+            }
+            0x4020..=0x4026 if !self.disk_io => {}
+            0x4020 => self.irq_reload = (self.irq_reload & 0xff00) | val as u16,
+            0x4021 => self.irq_reload = (self.irq_reload & 0x00ff) | ((val as u16) << 8),
+            0x4022 => {
+                self.irq_repeat = val & 1 != 0;
+                self.irq_enabled = val & 2 != 0;
+                if self.irq_enabled {
+                    self.irq_counter = self.irq_reload;
+                } else {
+                    self.timer_irq = false;
+                }
+            }
+            0x4024 => {
+                self.write_data = val;
+                self.transfer_done = false;
+                self.disk_irq = false;
+            }
+            0x4025 => {
+                self.control = val;
+                self.disk_irq = false;
+            }
+            0x6000..=0xDFFF => self.prg_ram[addr as usize - 0x6000] = val,
+            _ => {}
+        }
+    }
+    fn chr_offset(&self, addr: u16) -> Option<usize> {
+        Some(addr as usize & 0x1fff)
+    }
+    fn ppu_read(&mut self, addr: u16) -> u8 {
+        self.chr_ram[addr as usize & 0x1fff]
+    }
+    fn ppu_write(&mut self, addr: u16, val: u8) {
+        self.chr_ram[addr as usize & 0x1fff] = val;
+    }
+    fn mirroring(&self) -> Mirroring {
+        if self.control(3) {
+            Mirroring::Horizontal
+        } else {
+            Mirroring::Vertical
+        }
+    }
+    fn cpu_tick(&mut self) {
+        self.clock_timer();
+        if self.swap_delay > 0 {
+            self.swap_delay -= 1;
+            if self.swap_delay == 0 {
+                self.side = self.next_side.take();
+            }
+        }
+        let Some(side) = self.side.filter(|_| self.control(0)) else {
+            self.end_of_head = true;
+            self.scanning = false;
+            return;
+        };
+        let side = side as usize;
+        if self.control(1) && !self.scanning {
+            return;
+        }
+        if self.end_of_head {
+            self.delay = if self.fast_load { 0 } else { REWIND_CYCLES };
+            self.end_of_head = false;
+            self.position = 0;
+            self.gap_ended = false;
+            return;
+        }
+        if self.delay > 0 {
+            self.delay -= 1;
+            return;
+        }
+        self.scanning = true;
+        let disk = &self.sides[side];
+        if self.fast_load && self.control(2) && self.control(6) && !self.gap_ended {
+            // Straight to the next block
+            while self.position + 1 < disk.len() && disk[self.position] == 0 {
+                self.position += 1;
+            }
+        }
+        self.transfer(side);
+        self.was_crc = self.control(4);
+        self.position += 1;
+        if self.position >= self.sides[side].len() {
+            // The motor stops at the end of the disk
+            self.control &= !1;
+        } else {
+            self.delay = BYTE_CYCLES;
+        }
+    }
+    fn irq(&self) -> bool {
+        self.timer_irq || self.disk_irq
+    }
+    /// Sectors the game wrote, as records of a side number, an offset into
+    /// the side with its gaps (32 bit little endian), a length (16 bit) and
+    /// that many bytes
+    fn save_data(&self) -> Option<Vec<u8>> {
+        if !self.written {
+            return None;
+        }
+        let mut overlay = Vec::new();
+        for (i, (side, original)) in self.sides.iter().zip(&self.original).enumerate() {
+            let mut pos = 0;
+            while pos < side.len() {
+                if side[pos] == original[pos] {
+                    pos += 1;
+                    continue;
+                }
+                let start = pos;
+                while pos < side.len() && side[pos] != original[pos] && pos - start < 0xffff {
+                    pos += 1;
+                }
+                overlay.push(i as u8);
+                overlay.extend_from_slice(&(start as u32).to_le_bytes());
+                overlay.extend_from_slice(&((pos - start) as u16).to_le_bytes());
+                overlay.extend_from_slice(&side[start..pos]);
+            }
+        }
+        Some(overlay)
+    }
+    fn load_save_data(&mut self, data: &[u8]) {
+        let mut rest = data;
+        while let [side, a, b, c, d, e, f, tail @ ..] = rest {
+            let offset = u32::from_le_bytes([*a, *b, *c, *d]) as usize;
+            let len = u16::from_le_bytes([*e, *f]) as usize;
+            let (Some(bytes), Some(disk)) = (tail.get(..len), self.sides.get_mut(*side as usize))
+            else {
+                break;
+            };
+            if let Some(sector) = disk.get_mut(offset..offset + len) {
+                sector.copy_from_slice(bytes);
+                self.written = true;
+            }
+            rest = &tail[len..];
+        }
+    }
+    fn set_fast_load(&mut self, fast: bool) {
+        self.fast_load = fast;
+    }
+    fn disk_sides(&self) -> usize {
+        self.sides.len()
+    }
+    fn disk_side(&self) -> Option<usize> {
+        self.side.or(self.next_side).map(usize::from)
+    }
+    fn insert_disk(&mut self, side: Option<usize>) {
+        let side = side
+            .filter(|&side| side < self.sides.len())
+            .map(|side| side as u8);
+        if self.disk_side().is_some() && side.is_some() {
+            // Out with the old disk first
+            self.side = None;
+            self.next_side = side;
+            self.swap_delay = SWAP_CYCLES;
+        } else {
+            self.side = side;
+            self.next_side = None;
+            self.swap_delay = 0;
+        }
+    }
+}
@@ -0,0 +1,158 @@
+use super::{Mapper, PpuTarget};
+use crate::rom::{Mirroring, Rom};
+use crate::savestate::{StateStream, Stateful, stream};
+
+/// Mapper 111, GTROM (Cheapocabra), a homebrew board with 512K of flash
+/// for PRG and 32K of RAM holding two 8K CHR banks and two 8K pages of
+/// four-screen nametables. Games can rewrite the flash, for saves or
+/// updates, which we model as RAM: programming a byte stores it outright.
+/// See https://www.nesdev.org/wiki/GTROM
+pub struct Gtrom {
+    prg_rom: Vec<u8>,
+    /// CHR banks, then nametable pages
+    vram: Vec<u8>,
+    /// $5000-$5FFF and $7000-$7FFF: PRG bank in bits 0-3, CHR bank in bit
+    /// 4, nametable page in bit 5, red and green LEDs in bits 6 and 7
+    /// (lit when clear)
+    reg: u8,
+    /// Writes so far of the flash command in progress, for the board's
+    /// SST39SF040
+    command: Vec<u8>,
+    /// The game has written the flash, so savestates carry it
+    flashed: bool,
+    /// The flash is kept, like a battery save, when the header says so
+    battery: bool,
+}
+
+impl Gtrom {
+    pub fn new(rom: Rom) -> Self {
+        Gtrom {
+            prg_rom: rom.prg_rom,
+            vram: vec![0; 0x8000],
+            reg: 0,
+            command: Vec::new(),
+            flashed: false,
+            battery: rom.battery,
+        }
+    }
+
+    /// Whether the red and green LEDs are lit
+    pub fn leds(&self) -> (bool, bool) {
+        (self.reg & 0x40 == 0, self.reg & 0x80 == 0)
+    }
+
+    fn prg_addr(&self, addr: u16) -> usize {
+        let bank_count = self.prg_rom.len() / 0x8000;
+        ((self.reg & 0x0f) as usize % bank_count) * 0x8000 + (addr as usize & 0x7fff)
+    }
+
+    fn vram_addr(&self, addr: u16) -> usize {
+        let addr = addr as usize & 0x3fff;
+        if addr < 0x2000 {
+            ((self.reg as usize >> 4) & 1) * 0x2000 + addr
+        } else {
+            0x4000 + ((self.reg as usize >> 5) & 1) * 0x2000 + (addr & 0xfff)
+        }
+    }
+
+    /// A write to the flash, at `offset` into PRG ROM. Commands start with
+    /// unlock cycles at chip addresses $5555 and $2AAA, of which only the
+    /// low 15 bits are decoded.
+    fn flash_write(&mut self, offset: usize, val: u8) {
+        let chip_addr = offset & 0x7fff;
+        let step = self.command.len();
+        let unlock = match step {
+            0 | 3 => (0x5555, 0xaa),
+            _ => (0x2aaa, 0x55),
+        };
+        let next = match step {
+            3 if self.command[2] == 0xa0 => {
+                self.prg_rom[offset] = val;
+                self.flashed = true;
+                false
+            }
+            0 | 1 | 3 | 4 => (chip_addr, val) == unlock,
+            // Program a byte, or erase
+            2 => chip_addr == 0x5555 && matches!(val, 0xa0 | 0x80),
+            _ if val == 0x30 => {
+                let sector = offset & !0xfff;
+                self.prg_rom[sector..sector + 0x1000].fill(0xff);
+                self.flashed = true;
+                false
+            }
+            _ if (chip_addr, val) == (0x5555, 0x10) => {
+                self.prg_rom.fill(0xff);
+                self.flashed = true;
+                false
+            }
+            _ => false,
+        };
+        if next {
+            self.command.push(val);
+        } else {
+            self.command.clear();
+        }
+    }
+}
+
+impl Stateful for Gtrom {
+    fn stream(&mut self, s: &mut StateStream) {
+        stream!(s, self.reg, self.vram, self.command, self.flashed);
+        if self.flashed && s.is_loading() {
+            let mut flash = Vec::new();
+            flash.stream(s);
+            if flash.len() == self.prg_rom.len() {
+                self.prg_rom = flash;
+            } else {
+                s.fail("flash size doesn't match the ROM");
+            }
+        } else if self.flashed {
+            self.prg_rom.stream(s);
+        }
+    }
+}
+
+impl Mapper for Gtrom {
+    fn cpu_peek(&self, addr: u16) -> u8 {
+        self.prg_rom_offset(addr)
+            .map_or(0, |offset| self.prg_rom[offset])
+    }
+    fn prg_rom_offset(&self, addr: u16) -> Option<usize> {
+        (addr >= 0x8000).then(|| self.prg_addr(addr))
+    }
+    fn unmapped(&self, addr: u16) -> bool {
+        matches!(addr, 0x4020..=0x4FFF | 0x6000..=0x6FFF)
+    }
+    fn cpu_write(&mut self, addr: u16, val: u8) {
+        match addr {
+            0x5000..=0x5FFF | 0x7000..=0x7FFF => self.reg = val,
+            0x8000..=0xFFFF => self.flash_write(self.prg_addr(addr), val),
+            _ => {}
+        }
+    }
+    fn ppu_target(&self, _addr: u16) -> PpuTarget {
+        PpuTarget::Cartridge
+    }
+    fn chr_offset(&self, addr: u16) -> Option<usize> {
+        Some(self.vram_addr(addr))
+    }
+    fn ppu_read(&mut self, addr: u16) -> u8 {
+        self.vram[self.vram_addr(addr)]
+    }
+    fn ppu_write(&mut self, addr: u16, val: u8) {
+        let addr = self.vram_addr(addr);
+        self.vram[addr] = val;
+    }
+    fn mirroring(&self) -> Mirroring {
+        Mirroring::FourScreen
+    }
+    fn save_data(&self) -> Option<Vec<u8>> {
+        self.battery.then(|| self.prg_rom.clone())
+    }
+    fn load_save_data(&mut self, data: &[u8]) {
+        if data.len() == self.prg_rom.len() {
+            self.prg_rom.copy_from_slice(data);
+            self.flashed = true;
+        }
+    }
+}
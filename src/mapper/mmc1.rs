@@ -0,0 +1,286 @@
+use super::{Mapper, PpuTarget, PrgRam, chr_mem, switchable_mirroring};
+use crate::rom::{Mirroring, Rom};
+use crate::savestate::{StateStream, Stateful, stream};
+
+/// What an SxROM board does with the CHR bank lines it doesn't need for
+/// CHR, which boards with 8K of CHR RAM are free to use elsewhere.
+/// In 4K CHR mode games write the same to both CHR registers, and we go by
+/// the first one.
+/// See https://www.nesdev.org/wiki/MMC1#SxROM_connection_variants
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Mmc1Board {
+    /// Only CHR banking, like SNROM and SKROM
+    Standard,
+    /// SUROM: bit 4 picks the 256K half of 512K PRG ROM (Dragon Warrior
+    /// III and IV, Final Fantasy I+II)
+    Surom,
+    /// SOROM: bit 3 picks the 8K bank of 16K PRG RAM. Only the second bank
+    /// is battery backed.
+    Sorom,
+    /// SXROM: bit 4 picks the 256K half of PRG ROM like SUROM, and bits 2-3
+    /// the 8K bank of 32K PRG RAM
+    Sxrom,
+    /// Mapper 105, NES-EVENT (Nintendo World Championships 1990): bits 1-2
+    /// pick 32K of the first 128K PRG ROM chip, bit 3 switches to MMC1
+    /// banking in the second and bit 4 holds the contest timer at 0.
+    /// See https://www.nesdev.org/wiki/INES_Mapper_105
+    Event,
+}
+
+impl Mmc1Board {
+    /// From the NES 2.0 submapper, or the ROM and RAM sizes for headers
+    /// without one
+    pub fn detect(rom: &Rom) -> Self {
+        if rom.mapper == 105 {
+            return Mmc1Board::Event;
+        }
+        match rom.submapper {
+            1 => Mmc1Board::Surom,
+            2 => Mmc1Board::Sorom,
+            4 => Mmc1Board::Sxrom,
+            _ => match (rom.prg_rom.len(), rom.prg_ram_size) {
+                (_, 0x8000..) => Mmc1Board::Sxrom,
+                (0x80000.., _) => Mmc1Board::Surom,
+                (_, 0x4000..) => Mmc1Board::Sorom,
+                _ => Mmc1Board::Standard,
+            },
+        }
+    }
+}
+
+/// Mappers 1 (SxROM) and 105, banks set through a serial port, one bit per
+/// write
+/// See https://www.nesdev.org/wiki/MMC1
+pub struct Mmc1 {
+    prg_rom: Vec<u8>,
+    prg_ram: PrgRam,
+    chr: Vec<u8>,
+    chr_ram: bool,
+    pub board: Mmc1Board,
+    /// Bits written so far, the marker bit reaching bit 0 means it's full
+    shift: u8,
+    /// $8000, mirroring and the PRG and CHR banking modes
+    control: u8,
+    chr_banks: [u8; 2],
+    /// $E000, bit 4 disables PRG RAM
+    prg_bank: u8,
+    /// NES-EVENT: counts up every CPU cycle while bit 4 of the first CHR
+    /// register is clear, asserting the IRQ when it reaches the time the
+    /// DIP switches set
+    timer: u32,
+    timer_irq: bool,
+    /// NES-EVENT: PRG ROM stays on the first 32K until bit 4 of the first
+    /// CHR register has been cleared and then set, which counts to 2
+    event_init: u8,
+    /// NES-EVENT: 4 switches adding to the contest time
+    dip_switches: u8,
+}
+
+/// The shift register when empty
+const SHIFT_RESET: u8 = 0x10;
+
+impl Mmc1 {
+    pub fn new(rom: Rom) -> Self {
+        let (chr, chr_ram) = chr_mem(&rom);
+        let prg_ram = PrgRam::new(&rom);
+        Mmc1 {
+            board: Mmc1Board::detect(&rom),
+            prg_ram,
+            chr,
+            chr_ram,
+            prg_rom: rom.prg_rom,
+            shift: SHIFT_RESET,
+            // The last bank fixed at $C000, so the reset vector is there
+            control: 0x0c,
+            chr_banks: [0; 2],
+            prg_bank: 0,
+            timer: 0,
+            timer_irq: false,
+            event_init: 0,
+            dip_switches: 0,
+        }
+    }
+
+    /// CPU cycles the NES-EVENT timer runs for, from 5:00 with all the DIP
+    /// switches off to 9:41 with all of them on
+    pub fn event_time(&self) -> u32 {
+        0x2000_0000 | ((self.dip_switches as u32) << 25)
+    }
+
+    fn prg_addr(&self, addr: u16) -> usize {
+        let chr = self.chr_banks[0] as usize;
+        // SUROM and SXROM bank within the 256K half the CHR lines pick, and
+        // NES-EVENT within its second 128K chip
+        let (outer, last) = match self.board {
+            Mmc1Board::Surom | Mmc1Board::Sxrom => (((chr >> 4) & 1) * 16, 15),
+            Mmc1Board::Event if self.event_init < 2 => return addr as usize & 0x7fff,
+            Mmc1Board::Event if chr & 0x08 == 0 => {
+                return ((chr >> 1) & 3) * 0x8000 + (addr as usize & 0x7fff);
+            }
+            Mmc1Board::Event => (8, 7),
+            Mmc1Board::Standard | Mmc1Board::Sorom => (0, 15),
+        };
+        let bank = (self.prg_bank & 0x0f) as usize & last;
+        let bank = match ((self.control >> 2) & 3, addr) {
+            (0 | 1, _) => (bank & !1) | ((addr as usize >> 14) & 1),
+            (2, 0x8000..=0xBFFF) => 0,
+            (2, _) => bank,
+            (_, 0x8000..=0xBFFF) => bank,
+            (_, _) => last,
+        };
+        let bank_count = self.prg_rom.len() / 0x4000;
+        ((outer + bank) % bank_count) * 0x4000 + (addr as usize & 0x3fff)
+    }
+
+    fn chr_addr(&self, addr: u16) -> usize {
+        let addr = addr as usize & 0x1fff;
+        if self.board == Mmc1Board::Event {
+            return addr;
+        }
+        let bank = if self.control & 0x10 == 0 {
+            (self.chr_banks[0] & 0x1e) as usize + addr / 0x1000
+        } else {
+            self.chr_banks[addr / 0x1000] as usize
+        };
+        let bank_count = self.chr.len() / 0x1000;
+        (bank % bank_count) * 0x1000 + (addr & 0xfff)
+    }
+
+    fn prg_ram_offset(&self, addr: u16) -> usize {
+        let bank = match self.board {
+            // The battery backed bank comes first in PrgRam
+            Mmc1Board::Sorom => ((self.chr_banks[0] as usize >> 3) & 1) ^ 1,
+            Mmc1Board::Sxrom => (self.chr_banks[0] as usize >> 2) & 3,
+            Mmc1Board::Standard | Mmc1Board::Surom | Mmc1Board::Event => 0,
+        };
+        bank * 0x2000 + (addr as usize - 0x6000)
+    }
+
+    fn prg_ram_enabled(&self) -> bool {
+        self.prg_bank & 0x10 == 0
+    }
+
+    fn event_chr_written(&mut self) {
+        let hold = self.chr_banks[0] & 0x10 != 0;
+        self.event_init = match (self.event_init, hold) {
+            (0, false) => 1,
+            (1, true) => 2,
+            (init, _) => init,
+        };
+        if hold {
+            self.timer = 0;
+            self.timer_irq = false;
+        }
+    }
+}
+
+impl Stateful for Mmc1 {
+    fn stream(&mut self, s: &mut StateStream) {
+        stream!(
+            s,
+            self.prg_ram,
+            self.shift,
+            self.control,
+            self.chr_banks,
+            self.prg_bank,
+        );
+        if self.chr_ram {
+            self.chr.stream(s);
+        }
+        if self.board == Mmc1Board::Event {
+            stream!(s, self.timer, self.timer_irq, self.event_init);
+        }
+    }
+}
+
+impl Mapper for Mmc1 {
+    fn cpu_peek(&self, addr: u16) -> u8 {
+        match addr {
+            0x6000..=0x7FFF if self.prg_ram_enabled() => {
+                self.prg_ram.read(self.prg_ram_offset(addr)).unwrap_or(0)
+            }
+            0x8000..=0xFFFF => self.prg_rom[self.prg_addr(addr)],
+            _ => 0,
+        }
+    }
+    fn prg_rom_offset(&self, addr: u16) -> Option<usize> {
+        (addr >= 0x8000).then(|| self.prg_addr(addr))
+    }
+    fn unmapped(&self, addr: u16) -> bool {
+        addr < 0x6000 || (addr < 0x8000 && self.prg_ram.is_empty())
+    }
+    fn cpu_write(&mut self, addr: u16, val: u8) {
+        match addr {
+            0x6000..=0x7FFF if self.prg_ram_enabled() => {
+                let offset = self.prg_ram_offset(addr);
+                self.prg_ram.write(offset, val);
+            }
+            0x8000..=0xFFFF if val & 0x80 != 0 => {
+                self.shift = SHIFT_RESET;
+                self.control |= 0x0c;
+            }
+            0x8000..=0xFFFF => {
+                let full = self.shift & 1 != 0;
+                self.shift = (self.shift >> 1) | ((val & 1) << 4);
+                if !full {
+                    return;
+                }
+                let val = std::mem::replace(&mut self.shift, SHIFT_RESET);
+                match addr {
+                    0x8000..=0x9FFF => self.control = val,
+                    0xA000..=0xBFFF => {
+                        self.chr_banks[0] = val;
+                        if self.board == Mmc1Board::Event {
+                            self.event_chr_written();
+                        }
+                    }
+                    0xC000..=0xDFFF => self.chr_banks[1] = val,
+                    _ => self.prg_bank = val,
+                }
+            }
+            _ => {}
+        }
+    }
+    fn ppu_target(&self, addr: u16) -> PpuTarget {
+        // One screen A, one screen B, vertical, horizontal
+        switchable_mirroring(addr, (self.control + 2) & 3)
+    }
+    fn chr_offset(&self, addr: u16) -> Option<usize> {
+        Some(self.chr_addr(addr))
+    }
+    fn ppu_read(&mut self, addr: u16) -> u8 {
+        self.chr[self.chr_addr(addr)]
+    }
+    fn ppu_write(&mut self, addr: u16, val: u8) {
+        if self.chr_ram {
+            let addr = self.chr_addr(addr);
+            self.chr[addr] = val;
+        }
+    }
+    fn mirroring(&self) -> Mirroring {
+        match self.control & 3 {
+            3 => Mirroring::Horizontal,
+            _ => Mirroring::Vertical,
+        }
+    }
+    fn cpu_tick(&mut self) {
+        if self.board == Mmc1Board::Event && self.event_init == 2 && self.chr_banks[0] & 0x10 == 0 {
+            self.timer = (self.timer + 1) & 0x3fff_ffff;
+            if self.timer == self.event_time() {
+                self.timer_irq = true;
+            }
+        }
+    }
+    fn irq(&self) -> bool {
+        self.timer_irq
+    }
+    fn set_dip_switches(&mut self, switches: u8) {
+        self.dip_switches = switches & 0x0f;
+    }
+    fn save_data(&self) -> Option<Vec<u8>> {
+        self.prg_ram.save_data()
+    }
+    fn load_save_data(&mut self, data: &[u8]) {
+        self.prg_ram.load_save_data(data);
+    }
+}
@@ -0,0 +1,273 @@
+use super::{CartVram, Mapper, PpuTarget, PrgRam, chr_mem, fixed_mirroring, switchable_mirroring};
+use crate::rom::{Mirroring, Rom};
+use crate::savestate::{StateStream, Stateful, stream};
+
+/// The MMC3 revisions differ in when the IRQ counter fires
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Mmc3Revision {
+    /// MMC3A ("old" behavior, NES 2.0 submapper 4).
+    /// A counter that is 0 after clocking only fires if it was decremented
+    /// to 0, or reloaded because of a $C001 write.
+    A,
+    /// MMC3B and MMC3C ("new" behavior).
+    /// Fires whenever the counter is 0 after clocking, so a latch of 0
+    /// fires on every scanline.
+    BC,
+}
+
+/// Boards built on the MMC3, or on the Namco 108 it grew out of. The Namco
+/// 108 (also sold as the Namcot 118 and copied as Tengen's MIMIC-1) has the
+/// MMC3's bank registers but not its PRG and CHR modes, mirroring control,
+/// PRG RAM or IRQ.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Mmc3Board {
+    /// Mapper 4, TxROM and the other MMC3 boards
+    Txrom,
+    /// Mapper 206, the Namco 108 on DxROM and Namco's own boards
+    Namco108,
+    /// Mapper 88, NAMCOT-3443: CHR A16 follows PPU A12, so the pattern
+    /// table at $1000 comes from the upper 64K of CHR ROM
+    Namco3443,
+    /// Mapper 154, NAMCOT-3453: mapper 88 with bit 6 of any write to
+    /// $8000-$FFFF picking the single screen nametable
+    Namco3453,
+}
+
+/// Mappers 4, 88, 154 and 206
+/// See https://www.nesdev.org/wiki/MMC3 and
+/// https://www.nesdev.org/wiki/Namco_163_family#Namco_108
+pub struct Mmc3 {
+    prg_rom: Vec<u8>,
+    prg_ram: PrgRam,
+    chr: Vec<u8>,
+    chr_ram: bool,
+    vram: CartVram,
+    four_screen: bool,
+    mirroring: Mirroring,
+    pub revision: Mmc3Revision,
+    pub board: Mmc3Board,
+    bank_select: u8,
+    /// $A001, bit 7 enables PRG RAM and bit 6 protects it from writes
+    prg_ram_control: u8,
+    /// R0-R7
+    banks: [u8; 8],
+    irq_latch: u8,
+    irq_counter: u8,
+    irq_reload: bool,
+    irq_enabled: bool,
+    irq_pending: bool,
+    /// Nametable for [`Mmc3Board::Namco3453`]
+    single_screen: u8,
+}
+
+impl Mmc3 {
+    pub fn new(rom: Rom) -> Self {
+        let (chr, chr_ram) = chr_mem(&rom);
+        let vram = CartVram::new(&rom);
+        let prg_ram = PrgRam::new(&rom);
+        let revision = match rom.submapper {
+            4 => Mmc3Revision::A,
+            _ => Mmc3Revision::BC,
+        };
+        let board = match rom.mapper {
+            88 => Mmc3Board::Namco3443,
+            154 => Mmc3Board::Namco3453,
+            206 => Mmc3Board::Namco108,
+            _ => Mmc3Board::Txrom,
+        };
+        Mmc3 {
+            prg_rom: rom.prg_rom,
+            prg_ram,
+            chr,
+            chr_ram,
+            vram,
+            four_screen: rom.mirroring == Mirroring::FourScreen,
+            mirroring: rom.mirroring,
+            revision,
+            board,
+            bank_select: 0,
+            // Enabled, for games that never write $A001. Namco 108 boards
+            // have no RAM and can't write $A001.
+            prg_ram_control: if board == Mmc3Board::Txrom { 0x80 } else { 0 },
+            banks: [0, 2, 4, 5, 6, 7, 0, 1],
+            irq_latch: 0,
+            irq_counter: 0,
+            irq_reload: false,
+            irq_enabled: false,
+            irq_pending: false,
+            single_screen: 0,
+        }
+    }
+
+    fn prg_addr(&self, addr: u16) -> usize {
+        let bank_count = self.prg_rom.len() / 0x2000;
+        let prg_mode = self.bank_select & 0x40 != 0;
+        let bank = match ((addr - 0x8000) / 0x2000, prg_mode) {
+            (0, false) | (2, true) => self.banks[6] as usize,
+            (0, true) | (2, false) => bank_count - 2,
+            (1, _) => self.banks[7] as usize,
+            _ => bank_count - 1,
+        };
+        (bank % bank_count) * 0x2000 + (addr as usize & 0x1fff)
+    }
+
+    fn chr_addr(&self, addr: u16) -> usize {
+        let addr = addr as usize & 0x1fff;
+        // CHR A12 inversion swaps the 2K and 1K bank halves
+        let inverted = if self.bank_select & 0x80 != 0 {
+            addr ^ 0x1000
+        } else {
+            addr
+        };
+        let bank = match inverted / 0x400 {
+            0 => self.banks[0] & 0xfe,
+            1 => self.banks[0] | 0x01,
+            2 => self.banks[1] & 0xfe,
+            3 => self.banks[1] | 0x01,
+            n => self.banks[n - 2],
+        } as usize;
+        let bank = match self.board {
+            Mmc3Board::Namco3443 | Mmc3Board::Namco3453 => (bank & 0x3f) | ((addr & 0x1000) >> 6),
+            Mmc3Board::Txrom | Mmc3Board::Namco108 => bank,
+        };
+        let bank_count = self.chr.len() / 0x400;
+        (bank % bank_count) * 0x400 + (addr & 0x3ff)
+    }
+}
+
+impl Stateful for Mmc3 {
+    fn stream(&mut self, s: &mut StateStream) {
+        stream!(
+            s,
+            self.prg_ram,
+            self.mirroring,
+            self.bank_select,
+            self.banks,
+            self.irq_latch,
+            self.irq_counter,
+            self.irq_reload,
+            self.irq_enabled,
+            self.irq_pending,
+        );
+        if s.version() >= 5 {
+            self.prg_ram_control.stream(s);
+        }
+        if self.chr_ram {
+            self.chr.stream(s);
+        }
+        self.vram.stream(s);
+        if self.board == Mmc3Board::Namco3453 {
+            self.single_screen.stream(s);
+        }
+    }
+}
+
+impl Mapper for Mmc3 {
+    fn cpu_peek(&self, addr: u16) -> u8 {
+        match addr {
+            0x6000..=0x7FFF if self.prg_ram_control & 0x80 != 0 => {
+                self.prg_ram.read(addr as usize - 0x6000).unwrap_or(0)
+            }
+            0x8000..=0xFFFF => self.prg_rom[self.prg_addr(addr)],
+            _ => 0,
+        }
+    }
+    fn prg_rom_offset(&self, addr: u16) -> Option<usize> {
+        (addr >= 0x8000).then(|| self.prg_addr(addr))
+    }
+    fn unmapped(&self, addr: u16) -> bool {
+        addr < 0x6000 || (addr < 0x8000 && self.prg_ram.is_empty())
+    }
+    fn cpu_write(&mut self, addr: u16, val: u8) {
+        let even = addr & 1 == 0;
+        if self.board != Mmc3Board::Txrom {
+            if self.board == Mmc3Board::Namco3453 && addr >= 0x8000 {
+                self.single_screen = (val >> 6) & 1;
+            }
+            match addr {
+                0x8000..=0x9FFF if even => self.bank_select = val & 0x07,
+                0x8000..=0x9FFF => self.banks[(self.bank_select & 0x7) as usize] = val,
+                _ => {}
+            }
+            return;
+        }
+        match addr {
+            0x6000..=0x7FFF if self.prg_ram_control & 0xc0 == 0x80 => {
+                self.prg_ram.write(addr as usize - 0x6000, val)
+            }
+            0x8000..=0x9FFF if even => self.bank_select = val,
+            0x8000..=0x9FFF => self.banks[(self.bank_select & 0x7) as usize] = val,
+            // Four screen boards wire nametables themselves and ignore it
+            0xA000..=0xBFFF if even && self.four_screen => {}
+            0xA000..=0xBFFF if even => {
+                self.mirroring = match val & 1 {
+                    0 => Mirroring::Vertical,
+                    _ => Mirroring::Horizontal,
+                };
+            }
+            0xA000..=0xBFFF => self.prg_ram_control = val,
+            0xC000..=0xDFFF if even => self.irq_latch = val,
+            0xC000..=0xDFFF => {
+                self.irq_counter = 0;
+                self.irq_reload = true;
+            }
+            0xE000..=0xFFFF if even => {
+                self.irq_enabled = false;
+                self.irq_pending = false;
+            }
+            0xE000..=0xFFFF => self.irq_enabled = true,
+            _ => {}
+        }
+    }
+    fn ppu_target(&self, addr: u16) -> PpuTarget {
+        match self.board {
+            Mmc3Board::Namco3453 => switchable_mirroring(addr, 2 | self.single_screen),
+            _ => fixed_mirroring(addr, self.mirroring),
+        }
+    }
+    fn chr_offset(&self, addr: u16) -> Option<usize> {
+        Some(self.chr_addr(addr))
+    }
+    fn ppu_read(&mut self, addr: u16) -> u8 {
+        if CartVram::contains(addr) {
+            return self.vram.read(addr);
+        }
+        self.chr[self.chr_addr(addr)]
+    }
+    fn ppu_write(&mut self, addr: u16, val: u8) {
+        if CartVram::contains(addr) {
+            self.vram.write(addr, val);
+        } else if self.chr_ram {
+            let addr = self.chr_addr(addr);
+            self.chr[addr] = val;
+        }
+    }
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+    fn a12_rise(&mut self) {
+        let count = self.irq_counter;
+        if count == 0 || self.irq_reload {
+            self.irq_counter = self.irq_latch;
+        } else {
+            self.irq_counter -= 1;
+        }
+        let fire = match self.revision {
+            Mmc3Revision::A => (count > 0 || self.irq_reload) && self.irq_counter == 0,
+            Mmc3Revision::BC => self.irq_counter == 0,
+        };
+        if fire && self.irq_enabled {
+            self.irq_pending = true;
+        }
+        self.irq_reload = false;
+    }
+    fn irq(&self) -> bool {
+        self.irq_pending
+    }
+    fn save_data(&self) -> Option<Vec<u8>> {
+        self.prg_ram.save_data()
+    }
+    fn load_save_data(&mut self, data: &[u8]) {
+        self.prg_ram.load_save_data(data);
+    }
+}
@@ -0,0 +1,457 @@
+use super::{Mapper, PpuFetch, PpuTarget, PrgRam, chr_mem};
+use crate::rom::{Mirroring, Rom};
+use crate::savestate::{StateStream, Stateful, stream};
+
+/// CPU cycles without a PPU read after which the MMC5 considers rendering stopped
+const IDLE_CYCLES: u8 = 3;
+/// Visible scanlines, where the vertical split scroll wraps
+const SPLIT_HEIGHT: u8 = 240;
+
+/// Mapper 5 (ExROM)
+/// See https://www.nesdev.org/wiki/MMC5
+pub struct Mmc5 {
+    prg_rom: Vec<u8>,
+    /// Up to 64K in 8K pages, pages past the end repeat
+    prg_ram: PrgRam,
+    chr: Vec<u8>,
+    chr_ram: bool,
+    /// $5100
+    prg_mode: u8,
+    /// $5101
+    chr_mode: u8,
+    /// $5102 and $5103, writes are allowed with 2 and 1
+    prg_ram_protect: [u8; 2],
+    /// $5104
+    exram_mode: u8,
+    /// $5105, two bits per nametable: CIRAM A, CIRAM B, ExRAM, fill
+    nametables: u8,
+    /// $5106
+    fill_tile: u8,
+    /// $5107
+    fill_attr: u8,
+    /// $5113-$5117
+    prg_banks: [u8; 5],
+    /// $5120-$512B, 1K-8K CHR pages, sprites then background
+    chr_banks: [u16; 12],
+    /// $5130
+    chr_upper: u8,
+    /// Whether $5128-$512B were written after $5120-$5127
+    last_chr_bg: bool,
+    /// $5200
+    split_control: u8,
+    /// $5201
+    split_scroll: u8,
+    /// $5202
+    split_bank: u8,
+    /// $5203
+    irq_scanline: u8,
+    irq_enabled: bool,
+    irq_pending: bool,
+    /// $5205 and $5206
+    multiplier: [u8; 2],
+    exram: [u8; 0x400],
+    /// $2000 bit 5, background and sprites use separate CHR banks with 8x16 sprites
+    sprites_8x16: bool,
+
+    in_frame: bool,
+    scanline: u8,
+    last_nt_addr: u16,
+    nt_matches: u8,
+    idle_cycles: u8,
+    /// Background tile being fetched, 0 and 1 are the prefetched tiles
+    /// at the end of the previous scanline
+    tile: u8,
+    fetch: PpuFetch,
+    /// The background tile being fetched is in the split region
+    in_split: bool,
+    /// ExRAM byte for the background tile being fetched in extended attribute mode
+    ext_attr: u8,
+}
+
+impl Mmc5 {
+    pub fn new(rom: Rom) -> Self {
+        let (chr, chr_ram) = chr_mem(&rom);
+        // iNES 1.0 headers can't be trusted to say, so give the most the
+        // board can bank in
+        let prg_ram = match rom.is_nes2() {
+            true => PrgRam::new(&rom),
+            false if rom.battery => PrgRam::with_len(0x10000, 0x10000),
+            false => PrgRam::with_len(0x10000, 0),
+        };
+        Mmc5 {
+            prg_rom: rom.prg_rom,
+            prg_ram,
+            chr,
+            chr_ram,
+            prg_mode: 3,
+            chr_mode: 0,
+            prg_ram_protect: [0; 2],
+            exram_mode: 0,
+            nametables: 0,
+            fill_tile: 0,
+            fill_attr: 0,
+            prg_banks: [0, 0, 0, 0, 0xff],
+            chr_banks: [0; 12],
+            chr_upper: 0,
+            last_chr_bg: false,
+            split_control: 0,
+            split_scroll: 0,
+            split_bank: 0,
+            irq_scanline: 0,
+            irq_enabled: false,
+            irq_pending: false,
+            multiplier: [0xff; 2],
+            exram: [0; 0x400],
+            sprites_8x16: false,
+            in_frame: false,
+            scanline: 0,
+            last_nt_addr: 0,
+            nt_matches: 0,
+            idle_cycles: 0,
+            tile: 0,
+            fetch: PpuFetch::Unused,
+            in_split: false,
+            ext_attr: 0,
+        }
+    }
+
+    /// Whether the 8K window at `addr` has ROM, and which 8K page
+    fn prg_page(&self, addr: u16) -> (bool, usize) {
+        let slot = (addr as usize - 0x8000) / 0x2000;
+        // Register ($5114-$5117) and page size in 8K units
+        let (reg, size) = match (self.prg_mode & 3, slot) {
+            (0, _) => (3, 4),
+            (1, 0 | 1) | (2, 0 | 1) => (1, 2),
+            (1, _) => (3, 2),
+            (_, n) => (n, 1),
+        };
+        let val = self.prg_banks[reg + 1];
+        let rom = reg == 3 || val & 0x80 != 0;
+        let page = (val as usize & 0x7f & !(size - 1)) | (slot & (size - 1));
+        (rom, page)
+    }
+
+    fn prg_ram_writable(&self) -> bool {
+        self.prg_ram_protect == [2, 1]
+    }
+
+    fn chr_addr(&self, addr: u16) -> usize {
+        let addr = addr as usize & 0x1fff;
+        let (bank, size) = if self.in_split && self.fetch == PpuFetch::BgPattern {
+            (self.split_bank as usize, 0x1000)
+        } else if self.exram_mode == 1 && self.fetch == PpuFetch::BgPattern {
+            let bank = ((self.chr_upper as usize & 3) << 6) | (self.ext_attr as usize & 0x3f);
+            (bank, 0x1000)
+        } else {
+            let size = 0x2000 >> (self.chr_mode & 3);
+            let slot = addr / size;
+            let bg = if self.sprites_8x16 && self.in_frame {
+                self.fetch == PpuFetch::BgPattern
+            } else {
+                self.last_chr_bg
+            };
+            let reg = match (self.chr_mode & 3, bg) {
+                (mode, false) => (slot + 1) * (8 >> mode) - 1,
+                // The background set only covers 4K, mirrored across both pattern tables
+                (0 | 1, true) => 11,
+                (2, true) => 9 + (slot & 1) * 2,
+                (_, true) => 8 + (slot & 3),
+            };
+            (self.chr_banks[reg] as usize, size)
+        };
+        let bank_count = (self.chr.len() / size).max(1);
+        (bank % bank_count) * size + (addr & (size - 1))
+    }
+
+    fn split_region(&self) -> bool {
+        if self.split_control & 0x80 == 0 || self.exram_mode >= 2 {
+            return false;
+        }
+        let threshold = self.split_control & 0x1f;
+        if self.split_control & 0x40 != 0 {
+            self.tile >= threshold
+        } else {
+            self.tile < threshold
+        }
+    }
+
+    /// Vertical scroll of the split region for the tile being fetched
+    fn split_y(&self) -> u8 {
+        // The prefetched tiles belong to the next scanline
+        let line = match (self.tile, self.in_frame) {
+            (0 | 1, true) => self.scanline.wrapping_add(1),
+            (0 | 1, false) => 0,
+            _ => self.scanline,
+        };
+        ((self.split_scroll as u16 + line as u16) % SPLIT_HEIGHT as u16) as u8
+    }
+
+    /// Watch for the three identical nametable reads at the start of each scanline
+    fn detect_scanline(&mut self, addr: u16) {
+        if addr & 0x3000 == 0x2000 && addr == self.last_nt_addr {
+            self.nt_matches += 1;
+            if self.nt_matches == 2 {
+                if self.in_frame {
+                    self.scanline = self.scanline.wrapping_add(1);
+                    if self.scanline == self.irq_scanline && self.irq_scanline != 0 {
+                        self.irq_pending = true;
+                    }
+                } else {
+                    self.in_frame = true;
+                    self.scanline = 0;
+                }
+            }
+        } else {
+            self.nt_matches = 0;
+        }
+        self.last_nt_addr = addr;
+    }
+
+    fn nametable_mode(&self, addr: u16) -> u8 {
+        (self.nametables >> (((addr >> 10) & 3) * 2)) & 3
+    }
+}
+
+impl Stateful for Mmc5 {
+    fn stream(&mut self, s: &mut StateStream) {
+        if s.version() >= 5 {
+            self.prg_ram.stream(s);
+        } else {
+            // A length and 64K before PRG RAM was sized from the header
+            let mut old = Vec::<u8>::new();
+            old.stream(s);
+            if s.is_loading() {
+                self.prg_ram.load(&old);
+            }
+        }
+        stream!(
+            s,
+            self.prg_mode,
+            self.chr_mode,
+            self.prg_ram_protect,
+            self.exram_mode,
+            self.nametables,
+            self.fill_tile,
+            self.fill_attr,
+            self.prg_banks,
+            self.chr_banks,
+            self.chr_upper,
+            self.last_chr_bg,
+            self.split_control,
+            self.split_scroll,
+            self.split_bank,
+            self.irq_scanline,
+            self.irq_enabled,
+            self.irq_pending,
+            self.multiplier,
+            self.exram,
+            self.sprites_8x16,
+            self.in_frame,
+            self.scanline,
+            self.last_nt_addr,
+            self.nt_matches,
+            self.idle_cycles,
+            self.tile,
+            self.fetch,
+            self.in_split,
+            self.ext_attr,
+        );
+        if self.chr_ram {
+            self.chr.stream(s);
+        }
+    }
+}
+
+impl Mapper for Mmc5 {
+    fn cpu_peek(&self, addr: u16) -> u8 {
+        match addr {
+            0x5204 => ((self.irq_pending as u8) << 7) | ((self.in_frame as u8) << 6),
+            0x5205 => (self.multiplier[0] as u16 * self.multiplier[1] as u16) as u8,
+            0x5206 => ((self.multiplier[0] as u16 * self.multiplier[1] as u16) >> 8) as u8,
+            0x5C00..=0x5FFF if self.exram_mode >= 2 => self.exram[addr as usize - 0x5c00],
+            0x6000..=0x7FFF => {
+                let bank = (self.prg_banks[0] & 7) as usize;
+                let offset = bank * 0x2000 + (addr as usize & 0x1fff);
+                self.prg_ram.read(offset).unwrap_or(0)
+            }
+            0x8000..=0xFFFF => {
+                let (rom, page) = self.prg_page(addr);
+                let offset = addr as usize & 0x1fff;
+                if rom {
+                    let pages = self.prg_rom.len() / 0x2000;
+                    self.prg_rom[(page % pages) * 0x2000 + offset]
+                } else {
+                    self.prg_ram.read((page & 7) * 0x2000 + offset).unwrap_or(0)
+                }
+            }
+            _ => 0,
+        }
+    }
+    fn prg_rom_offset(&self, addr: u16) -> Option<usize> {
+        if addr < 0x8000 {
+            return None;
+        }
+        let (rom, page) = self.prg_page(addr);
+        let pages = self.prg_rom.len() / 0x2000;
+        rom.then(|| (page % pages) * 0x2000 + (addr as usize & 0x1fff))
+    }
+    fn cpu_read(&mut self, addr: u16) -> u8 {
+        let val = self.cpu_peek(addr);
+        if addr == 0x5204 {
+            self.irq_pending = false;
+        }
+        val
+    }
+    fn unmapped(&self, addr: u16) -> bool {
+        addr < 0x5000 || ((0x6000..0x8000).contains(&addr) && self.prg_ram.is_empty())
+    }
+    fn cpu_write(&mut self, addr: u16, val: u8) {
+        match addr {
+            0x5100 => self.prg_mode = val & 3,
+            0x5101 => self.chr_mode = val & 3,
+            0x5102 => self.prg_ram_protect[0] = val & 3,
+            0x5103 => self.prg_ram_protect[1] = val & 3,
+            0x5104 => self.exram_mode = val & 3,
+            0x5105 => self.nametables = val,
+            0x5106 => self.fill_tile = val,
+            0x5107 => self.fill_attr = val & 3,
+            0x5113..=0x5117 => self.prg_banks[addr as usize - 0x5113] = val,
+            0x5120..=0x512B => {
+                let reg = addr as usize - 0x5120;
+                self.chr_banks[reg] = ((self.chr_upper as u16 & 3) << 8) | val as u16;
+                self.last_chr_bg = reg >= 8;
+            }
+            0x5130 => self.chr_upper = val & 3,
+            0x5200 => self.split_control = val,
+            0x5201 => self.split_scroll = val,
+            0x5202 => self.split_bank = val,
+            0x5203 => self.irq_scanline = val,
+            0x5204 => self.irq_enabled = val & 0x80 != 0,
+            0x5205 => self.multiplier[0] = val,
+            0x5206 => self.multiplier[1] = val,
+            0x5C00..=0x5FFF if self.exram_mode != 3 => self.exram[addr as usize - 0x5c00] = val,
+            0x6000..=0x7FFF if self.prg_ram_writable() => {
+                let bank = (self.prg_banks[0] & 7) as usize;
+                self.prg_ram
+                    .write(bank * 0x2000 + (addr as usize & 0x1fff), val);
+            }
+            0x8000..=0xDFFF if self.prg_ram_writable() => {
+                let (rom, page) = self.prg_page(addr);
+                if !rom {
+                    self.prg_ram
+                        .write((page & 7) * 0x2000 + (addr as usize & 0x1fff), val);
+                }
+            }
+            _ => {}
+        }
+    }
+    fn ppu_register_write(&mut self, addr: u16, val: u8) {
+        match addr & 0x2007 {
+            0x2000 => self.sprites_8x16 = val & 0x20 != 0,
+            0x2001 if val & 0x18 == 0 => self.in_frame = false,
+            _ => {}
+        }
+    }
+    fn ppu_fetch(&mut self, addr: u16, kind: PpuFetch) {
+        self.idle_cycles = 0;
+        self.detect_scanline(addr);
+        self.fetch = kind;
+        match kind {
+            PpuFetch::Nametable => {
+                self.in_split = self.split_region();
+                if self.exram_mode == 1 {
+                    self.ext_attr = self.exram[addr as usize & 0x3ff];
+                }
+            }
+            PpuFetch::BgPattern | PpuFetch::Attribute => {}
+            PpuFetch::SpritePattern => {
+                self.tile = 0;
+                self.in_split = false;
+            }
+            PpuFetch::Unused => self.in_split = false,
+        }
+    }
+    fn ppu_target(&self, addr: u16) -> PpuTarget {
+        let addr = addr & 0x3fff;
+        if addr < 0x2000 || self.in_split {
+            return PpuTarget::Cartridge;
+        }
+        if self.exram_mode == 1 && self.fetch == PpuFetch::Attribute {
+            return PpuTarget::Cartridge;
+        }
+        match self.nametable_mode(addr) {
+            n @ 0..=1 => PpuTarget::Ciram(n as usize * 0x400 + (addr as usize & 0x3ff)),
+            _ => PpuTarget::Cartridge,
+        }
+    }
+    fn ppu_read(&mut self, addr: u16) -> u8 {
+        let addr = addr & 0x3fff;
+        if addr < 0x2000 {
+            let val = if self.in_split && self.fetch == PpuFetch::BgPattern {
+                // Fine Y comes from the split scroll instead of the PPU
+                let addr = (addr & !0x7) | (self.split_y() as u16 & 7);
+                self.chr[self.chr_addr(addr)]
+            } else {
+                self.chr[self.chr_addr(addr)]
+            };
+            if self.fetch == PpuFetch::BgPattern && addr & 0x8 != 0 {
+                self.tile += 1;
+            }
+            return val;
+        }
+        let offset = addr as usize & 0x3ff;
+        if self.in_split {
+            let y = self.split_y() as usize;
+            let x = self.tile as usize & 0x1f;
+            return match self.fetch {
+                PpuFetch::Attribute => {
+                    let attr = self.exram[0x3c0 + (y / 32) * 8 + x / 4];
+                    let shift = ((y / 16) & 1) * 4 + ((x / 2) & 1) * 2;
+                    ((attr >> shift) & 3) * 0x55
+                }
+                _ => self.exram[(y / 8) * 32 + x],
+            };
+        }
+        if self.exram_mode == 1 && self.fetch == PpuFetch::Attribute {
+            return (self.ext_attr >> 6) * 0x55;
+        }
+        match self.nametable_mode(addr) {
+            2 if self.exram_mode < 2 => self.exram[offset],
+            2 => 0,
+            _ if offset >= 0x3c0 => self.fill_attr * 0x55,
+            _ => self.fill_tile,
+        }
+    }
+    fn ppu_write(&mut self, addr: u16, val: u8) {
+        let addr = addr & 0x3fff;
+        if addr < 0x2000 {
+            if self.chr_ram {
+                let addr = self.chr_addr(addr);
+                self.chr[addr] = val;
+            }
+        } else if self.nametable_mode(addr) == 2 && self.exram_mode < 2 {
+            self.exram[addr as usize & 0x3ff] = val;
+        }
+    }
+    fn mirroring(&self) -> Mirroring {
+        Mirroring::Vertical
+    }
+    fn cpu_tick(&mut self) {
+        self.idle_cycles = self.idle_cycles.saturating_add(1);
+        if self.idle_cycles >= IDLE_CYCLES {
+            self.in_frame = false;
+            self.last_nt_addr = 0;
+            self.fetch = PpuFetch::Unused;
+            self.in_split = false;
+        }
+    }
+    fn irq(&self) -> bool {
+        self.irq_pending && self.irq_enabled
+    }
+    fn save_data(&self) -> Option<Vec<u8>> {
+        self.prg_ram.save_data()
+    }
+    fn load_save_data(&mut self, data: &[u8]) {
+        self.prg_ram.load_save_data(data);
+    }
+}
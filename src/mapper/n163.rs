@@ -0,0 +1,264 @@
+use super::{Mapper, PpuTarget, PrgRam, chr_mem};
+use crate::apu::ExpansionChip;
+use crate::rom::{Mirroring, Rom};
+use crate::savestate::{StateStream, Stateful, stream};
+
+/// CPU cycles between two channel updates
+const CHANNEL_UPDATE_CYCLES: u8 = 15;
+/// Bank numbers from here on select CIRAM instead of CHR ROM
+const CIRAM_BANKS: u8 = 0xe0;
+
+/// Mapper 19, with the N163 wavetable sound chip
+/// See https://www.nesdev.org/wiki/INES_Mapper_019
+/// and https://www.nesdev.org/wiki/Namco_163_audio
+pub struct N163 {
+    prg_rom: Vec<u8>,
+    prg_ram: PrgRam,
+    chr: Vec<u8>,
+    chr_ram: bool,
+    mirroring: Mirroring,
+    /// $8000-$B800, 1K pages for the pattern tables
+    chr_banks: [u8; 8],
+    /// $C000-$D800, 1K pages for the nametables
+    nt_banks: [u8; 4],
+    /// $E000-$F000, 8K pages for $8000-$DFFF
+    prg_banks: [u8; 3],
+    /// $E800 bits 6 and 7, stop the low and high pattern tables from using CIRAM
+    ciram_disabled: [bool; 2],
+    /// 15 bit up counter, bit 15 enables it
+    irq_counter: u16,
+    irq_pending: bool,
+    sound_disabled: bool,
+    /// Wavetables and channel registers
+    sound_ram: [u8; 0x80],
+    /// $F800, bit 7 enables auto increment
+    sound_addr: u8,
+    sound_cycle: u8,
+    channel: u8,
+    channel_output: [i16; 8],
+}
+
+impl N163 {
+    pub fn new(rom: Rom) -> Self {
+        let (chr, chr_ram) = chr_mem(&rom);
+        let prg_ram = PrgRam::new(&rom);
+        N163 {
+            prg_rom: rom.prg_rom,
+            prg_ram,
+            chr,
+            chr_ram,
+            mirroring: rom.mirroring,
+            chr_banks: [0; 8],
+            nt_banks: [0; 4],
+            prg_banks: [0; 3],
+            ciram_disabled: [false; 2],
+            irq_counter: 0,
+            irq_pending: false,
+            sound_disabled: false,
+            sound_ram: [0; 0x80],
+            sound_addr: 0,
+            sound_cycle: 0,
+            channel: 7,
+            channel_output: [0; 8],
+        }
+    }
+
+    fn prg_addr(&self, addr: u16) -> usize {
+        let bank_count = self.prg_rom.len() / 0x2000;
+        let bank = match (addr - 0x8000) / 0x2000 {
+            n @ 0..=2 => (self.prg_banks[n as usize] & 0x3f) as usize,
+            _ => bank_count - 1,
+        };
+        (bank % bank_count) * 0x2000 + (addr as usize & 0x1fff)
+    }
+
+    fn bank(&self, addr: u16) -> u8 {
+        match addr & 0x3fff {
+            0x0000..=0x1fff => self.chr_banks[(addr as usize & 0x1fff) / 0x400],
+            _ => self.nt_banks[(addr as usize >> 10) & 3],
+        }
+    }
+
+    fn chr_addr(&self, addr: u16) -> usize {
+        let bank_count = self.chr.len() / 0x400;
+        (self.bank(addr) as usize % bank_count) * 0x400 + (addr as usize & 0x3ff)
+    }
+
+    /// Number of channels being updated, always the highest ones
+    fn channel_count(&self) -> u8 {
+        ((self.sound_ram[0x7f] >> 4) & 7) + 1
+    }
+
+    fn update_channel(&mut self, channel: u8) {
+        let regs = 0x40 + channel as usize * 8;
+        let reg = |i: usize| self.sound_ram[regs + i] as u32;
+        let freq = reg(0) | (reg(2) << 8) | ((reg(4) & 3) << 16);
+        let phase = reg(1) | (reg(3) << 8) | (reg(5) << 16);
+        let length = 256 - (reg(4) & 0xfc);
+        let phase = (phase + freq) % (length << 16);
+        let sample = ((phase >> 16) + reg(6)) as usize & 0xff;
+        let nibble = (self.sound_ram[sample >> 1] >> ((sample & 1) * 4)) & 0xf;
+        self.channel_output[channel as usize] = (nibble as i16 - 8) * (reg(7) & 0xf) as i16;
+        self.sound_ram[regs + 1] = phase as u8;
+        self.sound_ram[regs + 3] = (phase >> 8) as u8;
+        self.sound_ram[regs + 5] = (phase >> 16) as u8;
+    }
+}
+
+impl Stateful for N163 {
+    fn stream(&mut self, s: &mut StateStream) {
+        stream!(
+            s,
+            self.prg_ram,
+            self.chr_banks,
+            self.nt_banks,
+            self.prg_banks,
+            self.ciram_disabled,
+            self.irq_counter,
+            self.irq_pending,
+            self.sound_disabled,
+            self.sound_ram,
+            self.sound_addr,
+            self.sound_cycle,
+            self.channel,
+            self.channel_output,
+        );
+        if self.chr_ram {
+            self.chr.stream(s);
+        }
+    }
+}
+
+impl Mapper for N163 {
+    fn cpu_peek(&self, addr: u16) -> u8 {
+        match addr {
+            0x4800..=0x4FFF => self.sound_ram[(self.sound_addr & 0x7f) as usize],
+            0x5000..=0x57FF => self.irq_counter as u8,
+            0x5800..=0x5FFF => (self.irq_counter >> 8) as u8,
+            0x6000..=0x7FFF => self.prg_ram.read(addr as usize - 0x6000).unwrap_or(0),
+            0x8000..=0xFFFF => self.prg_rom[self.prg_addr(addr)],
+            _ => 0,
+        }
+    }
+    fn cpu_read(&mut self, addr: u16) -> u8 {
+        let val = self.cpu_peek(addr);
+        if let 0x4800..=0x4FFF = addr
+            && self.sound_addr & 0x80 != 0
+        {
+            self.sound_addr = 0x80 | self.sound_addr.wrapping_add(1) & 0x7f;
+        }
+        val
+    }
+    fn prg_rom_offset(&self, addr: u16) -> Option<usize> {
+        (addr >= 0x8000).then(|| self.prg_addr(addr))
+    }
+    fn unmapped(&self, addr: u16) -> bool {
+        addr < 0x4800 || ((0x6000..0x8000).contains(&addr) && self.prg_ram.is_empty())
+    }
+    fn cpu_write(&mut self, addr: u16, val: u8) {
+        match addr {
+            0x4800..=0x4FFF => {
+                self.sound_ram[(self.sound_addr & 0x7f) as usize] = val;
+                if self.sound_addr & 0x80 != 0 {
+                    self.sound_addr = 0x80 | self.sound_addr.wrapping_add(1) & 0x7f;
+                }
+            }
+            0x5000..=0x57FF => {
+                self.irq_counter = (self.irq_counter & 0xff00) | val as u16;
+                self.irq_pending = false;
+            }
+            0x5800..=0x5FFF => {
+                self.irq_counter = (self.irq_counter & 0x00ff) | ((val as u16) << 8);
+                self.irq_pending = false;
+            }
+            0x6000..=0x7FFF => self.prg_ram.write(addr as usize - 0x6000, val),
+            0x8000..=0xBFFF => self.chr_banks[(addr as usize - 0x8000) / 0x800] = val,
+            0xC000..=0xDFFF => self.nt_banks[(addr as usize - 0xC000) / 0x800] = val,
+            0xE000..=0xE7FF => {
+                self.prg_banks[0] = val;
+                self.sound_disabled = val & 0x40 != 0;
+            }
+            0xE800..=0xEFFF => {
+                self.prg_banks[1] = val;
+                self.ciram_disabled = [val & 0x40 != 0, val & 0x80 != 0];
+            }
+            0xF000..=0xF7FF => self.prg_banks[2] = val,
+            0xF800..=0xFFFF => self.sound_addr = val,
+            _ => {}
+        }
+    }
+    fn ppu_target(&self, addr: u16) -> PpuTarget {
+        let bank = self.bank(addr);
+        let ciram = match addr & 0x3fff {
+            0x0000..=0x1fff => {
+                bank >= CIRAM_BANKS && !self.ciram_disabled[(addr >> 12) as usize & 1]
+            }
+            _ => bank >= CIRAM_BANKS,
+        };
+        if ciram {
+            PpuTarget::Ciram((bank as usize & 1) * 0x400 + (addr as usize & 0x3ff))
+        } else {
+            PpuTarget::Cartridge
+        }
+    }
+    fn chr_offset(&self, addr: u16) -> Option<usize> {
+        Some(self.chr_addr(addr))
+    }
+    fn ppu_read(&mut self, addr: u16) -> u8 {
+        self.chr[self.chr_addr(addr)]
+    }
+    fn ppu_write(&mut self, addr: u16, val: u8) {
+        if self.chr_ram {
+            let addr = self.chr_addr(addr);
+            self.chr[addr] = val;
+        }
+    }
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+    fn cpu_tick(&mut self) {
+        let enabled = self.irq_counter & 0x8000 != 0;
+        if enabled && self.irq_counter & 0x7fff != 0x7fff {
+            self.irq_counter += 1;
+            if self.irq_counter & 0x7fff == 0x7fff {
+                self.irq_pending = true;
+            }
+        }
+
+        if self.sound_disabled {
+            return;
+        }
+        self.sound_cycle += 1;
+        if self.sound_cycle == CHANNEL_UPDATE_CYCLES {
+            self.sound_cycle = 0;
+            self.update_channel(self.channel);
+            self.channel = if self.channel <= 8 - self.channel_count() {
+                7
+            } else {
+                self.channel - 1
+            };
+        }
+    }
+    fn irq(&self) -> bool {
+        self.irq_pending
+    }
+    fn save_data(&self) -> Option<Vec<u8>> {
+        self.prg_ram.save_data()
+    }
+    fn load_save_data(&mut self, data: &[u8]) {
+        self.prg_ram.load_save_data(data);
+    }
+    fn expansion_audio(&self) -> Option<(ExpansionChip, f32)> {
+        if self.sound_disabled {
+            return None;
+        }
+        // The chip cycles through its channels, which averages them out
+        let count = self.channel_count();
+        let sum: i16 = self.channel_output[8 - count as usize..].iter().sum();
+        Some((ExpansionChip::N163, sum as f32 / count as f32 / 120.0))
+    }
+    fn audio_register(&self, addr: u16) -> bool {
+        // Sound RAM, the sound disable bit and the sound RAM address
+        matches!(addr, 0x4800..=0x4FFF | 0xE000..=0xE7FF | 0xF800..=0xFFFF)
+    }
+}
@@ -0,0 +1,91 @@
+use log::warn;
+
+use super::{CartVram, Mapper, chr_mem};
+use crate::rom::{Mirroring, Rom};
+use crate::savestate::{StateStream, Stateful};
+
+/// Mapper 0, no bank switching
+pub struct Nrom {
+    prg_rom: Vec<u8>,
+    chr: Vec<u8>,
+    chr_ram: bool,
+    vram: CartVram,
+    mirroring: Mirroring,
+    /// Writes to PRG ROM are logged once, some games make them every frame
+    warned_rom_write: bool,
+}
+
+impl Nrom {
+    pub fn new(rom: Rom) -> Self {
+        let (chr, chr_ram) = chr_mem(&rom);
+        let vram = CartVram::new(&rom);
+        Nrom {
+            prg_rom: rom.prg_rom,
+            chr,
+            chr_ram,
+            vram,
+            mirroring: rom.mirroring,
+            warned_rom_write: false,
+        }
+    }
+}
+
+impl Stateful for Nrom {
+    fn stream(&mut self, s: &mut StateStream) {
+        if self.chr_ram {
+            self.chr.stream(s);
+        }
+        self.vram.stream(s);
+    }
+}
+
+impl Mapper for Nrom {
+    fn cpu_peek(&self, addr: u16) -> u8 {
+        self.prg_rom_offset(addr)
+            .map_or(0, |offset| self.prg_rom[offset])
+    }
+    fn prg_rom_offset(&self, addr: u16) -> Option<usize> {
+        match addr {
+            // 16K roms are mirrored into $C000-$FFFF
+            0x8000..=0xFFFF => Some((addr - 0x8000) as usize % self.prg_rom.len()),
+            _ => None,
+        }
+    }
+    fn cpu_write(&mut self, addr: u16, val: u8) {
+        match addr {
+            // Nothing listens to writes to PRG ROM
+            0x8000..=0xFFFF if !self.warned_rom_write => {
+                warn!(
+                    "Ignoring write of 0x{val:02X} to ROM at 0x{addr:04X}, NROM has no registers"
+                );
+                self.warned_rom_write = true;
+            }
+            _ => {}
+        }
+    }
+    fn ignores_write(&self, _addr: u16) -> bool {
+        true
+    }
+    fn unmapped(&self, addr: u16) -> bool {
+        addr < 0x8000
+    }
+    fn chr_offset(&self, addr: u16) -> Option<usize> {
+        Some(addr as usize & 0x1fff)
+    }
+    fn ppu_read(&mut self, addr: u16) -> u8 {
+        if CartVram::contains(addr) {
+            return self.vram.read(addr);
+        }
+        self.chr[addr as usize & 0x1fff]
+    }
+    fn ppu_write(&mut self, addr: u16, val: u8) {
+        if CartVram::contains(addr) {
+            self.vram.write(addr, val);
+        } else if self.chr_ram {
+            self.chr[addr as usize & 0x1fff] = val;
+        }
+    }
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+}
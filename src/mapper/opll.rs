@@ -0,0 +1,404 @@
+use std::f32::consts::TAU;
+
+use crate::savestate::{StateStream, Stateful, stream};
+
+/// CPU cycles per OPLL sample, the VRC7 runs its sound at 3.58MHz / 72
+pub const SAMPLE_CYCLES: u8 = 36;
+const SAMPLE_RATE: f32 = 1_789_773.0 / SAMPLE_CYCLES as f32;
+/// Envelope attenuation at which an operator is considered silent
+const SILENT_DB: f32 = 48.0;
+/// Release rate used instead of the patch's when the channel's sustain bit is set
+const SUSTAIN_RELEASE_RATE: u8 = 5;
+const TREMOLO_HZ: f32 = 3.7;
+const TREMOLO_DB: f32 = 4.8;
+const VIBRATO_HZ: f32 = 6.4;
+const VIBRATO_CENTS: f32 = 14.0;
+
+/// Built-in instruments 1-15 of the VRC7, instrument 0 is the custom patch
+/// See https://www.nesdev.org/wiki/VRC7_audio
+const PATCHES: [[u8; 8]; 15] = [
+    [0x03, 0x21, 0x05, 0x06, 0xe8, 0x81, 0x42, 0x27],
+    [0x13, 0x41, 0x14, 0x0d, 0xd8, 0xf6, 0x23, 0x12],
+    [0x11, 0x11, 0x08, 0x08, 0xfa, 0xb2, 0x20, 0x12],
+    [0x31, 0x61, 0x0c, 0x07, 0xa8, 0x64, 0x61, 0x27],
+    [0x32, 0x21, 0x1e, 0x06, 0xe1, 0x76, 0x01, 0x28],
+    [0x02, 0x01, 0x06, 0x00, 0xa3, 0xe2, 0xf4, 0xf4],
+    [0x21, 0x61, 0x1d, 0x07, 0x82, 0x81, 0x11, 0x07],
+    [0x23, 0x21, 0x22, 0x17, 0xa2, 0x72, 0x01, 0x17],
+    [0x35, 0x11, 0x25, 0x00, 0x40, 0x73, 0x72, 0x01],
+    [0xb5, 0x01, 0x0f, 0x0f, 0xa8, 0xa5, 0x51, 0x02],
+    [0x17, 0xc1, 0x24, 0x07, 0xf8, 0xf8, 0x22, 0x12],
+    [0x71, 0x23, 0x11, 0x06, 0x65, 0x74, 0x18, 0x16],
+    [0x01, 0x02, 0xd3, 0x05, 0xc9, 0x95, 0x03, 0x02],
+    [0x61, 0x63, 0x0c, 0x00, 0x94, 0xc0, 0x33, 0xf6],
+    [0x21, 0x72, 0x0d, 0x00, 0xc1, 0xd5, 0x56, 0x06],
+];
+const MULTIPLIERS: [f32; 16] = [
+    0.5, 1.0, 2.0, 3.0, 4.0, 5.0, 6.0, 7.0, 8.0, 9.0, 10.0, 10.0, 12.0, 12.0, 15.0, 15.0,
+];
+/// Key scale attenuation at octave 7 by the top 4 F-number bits, 6dB per octave
+const KSL_DB: [f32; 16] = [
+    0.0, 9.0, 12.0, 13.875, 15.0, 16.125, 16.875, 17.625, 18.0, 18.75, 19.125, 19.5, 19.875, 20.25,
+    20.625, 21.0,
+];
+const KSL_SCALE: [f32; 4] = [0.0, 0.25, 0.5, 1.0];
+
+#[derive(Clone, Copy, Default, PartialEq, Eq)]
+enum Stage {
+    Attack,
+    Decay,
+    Sustain,
+    Release,
+    #[default]
+    Idle,
+}
+
+impl Stateful for Stage {
+    fn stream(&mut self, s: &mut StateStream) {
+        let mut tag = *self as u8;
+        tag.stream(s);
+        *self = match tag {
+            0 => Stage::Attack,
+            1 => Stage::Decay,
+            2 => Stage::Sustain,
+            3 => Stage::Release,
+            4 => Stage::Idle,
+            _ => {
+                s.fail("invalid OPLL envelope stage");
+                Stage::Idle
+            }
+        };
+    }
+}
+
+/// One of the two operators of a patch, decoded from its register bytes
+struct Params {
+    tremolo: bool,
+    vibrato: bool,
+    /// Hold the sustain level until key off, otherwise keep decaying
+    sustained: bool,
+    ksr: bool,
+    multiplier: f32,
+    ksl: usize,
+    rectified: bool,
+    attack: u8,
+    decay: u8,
+    sustain_db: f32,
+    release: u8,
+}
+
+impl Params {
+    /// `op` is 0 for the modulator and 1 for the carrier
+    fn new(patch: &[u8; 8], op: usize) -> Self {
+        Params {
+            tremolo: patch[op] & 0x80 != 0,
+            vibrato: patch[op] & 0x40 != 0,
+            sustained: patch[op] & 0x20 != 0,
+            ksr: patch[op] & 0x10 != 0,
+            multiplier: MULTIPLIERS[(patch[op] & 0xf) as usize],
+            ksl: (patch[2 + op] >> 6) as usize,
+            rectified: patch[3] & (0x08 << op) != 0,
+            attack: patch[4 + op] >> 4,
+            decay: patch[4 + op] & 0xf,
+            sustain_db: (patch[6 + op] >> 4) as f32 * 3.0,
+            release: patch[6 + op] & 0xf,
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+struct Operator {
+    /// In cycles
+    phase: f32,
+    stage: Stage,
+    envelope_db: f32,
+}
+
+impl Default for Operator {
+    fn default() -> Self {
+        Operator {
+            phase: 0.0,
+            stage: Stage::Idle,
+            envelope_db: SILENT_DB,
+        }
+    }
+}
+
+impl Stateful for Operator {
+    fn stream(&mut self, s: &mut StateStream) {
+        stream!(s, self.phase, self.stage, self.envelope_db);
+    }
+}
+
+impl Operator {
+    fn key_on(&mut self) {
+        self.phase = 0.0;
+        self.stage = Stage::Attack;
+    }
+
+    fn key_off(&mut self) {
+        if self.stage != Stage::Idle {
+            self.stage = Stage::Release;
+        }
+    }
+
+    fn step_envelope(&mut self, params: &Params, release: u8, rks: u8) {
+        match self.stage {
+            Stage::Attack => {
+                self.envelope_db -= envelope_step(params.attack, rks, true);
+                if self.envelope_db <= 0.0 {
+                    self.envelope_db = 0.0;
+                    self.stage = Stage::Decay;
+                }
+            }
+            Stage::Decay => {
+                self.envelope_db += envelope_step(params.decay, rks, false);
+                if self.envelope_db >= params.sustain_db {
+                    self.envelope_db = params.sustain_db;
+                    self.stage = Stage::Sustain;
+                }
+            }
+            Stage::Sustain if params.sustained => {}
+            Stage::Sustain | Stage::Release => {
+                self.envelope_db += envelope_step(release, rks, false);
+            }
+            Stage::Idle => {}
+        }
+        if self.envelope_db >= SILENT_DB {
+            self.envelope_db = SILENT_DB;
+            if self.stage != Stage::Attack {
+                self.stage = Stage::Idle;
+            }
+        }
+    }
+
+    /// Advance the phase and produce the output for `modulation` (in cycles)
+    fn output(&mut self, params: &Params, step: f32, modulation: f32, attenuation_db: f32) -> f32 {
+        self.phase = (self.phase + step * params.multiplier).fract();
+        let db = self.envelope_db + attenuation_db;
+        if self.envelope_db >= SILENT_DB {
+            return 0.0;
+        }
+        let wave = (TAU * (self.phase + modulation)).sin();
+        let wave = if params.rectified {
+            wave.max(0.0)
+        } else {
+            wave
+        };
+        wave * 10f32.powf(-db / 20.0)
+    }
+}
+
+/// Attenuation change per sample for an envelope rate
+fn envelope_step(rate: u8, rks: u8, attack: bool) -> f32 {
+    if rate == 0 {
+        return 0.0;
+    }
+    let rate = (rate * 4 + rks).min(63);
+    if attack && rate >= 60 {
+        return f32::INFINITY;
+    }
+    // Time to cover 96dB at rate 4, halving every 4 rates
+    let ms = if attack { 2826.0 } else { 39280.0 } / 2f32.powf((rate - 4) as f32 / 4.0);
+    96.0 / (ms / 1000.0 * SAMPLE_RATE)
+}
+
+#[derive(Clone, Copy, Default)]
+struct Channel {
+    fnum: u16,
+    block: u8,
+    key: bool,
+    sustain: bool,
+    instrument: u8,
+    /// Attenuation in 3dB steps
+    volume: u8,
+    modulator: Operator,
+    carrier: Operator,
+    /// Last two modulator outputs, for feedback
+    feedback: [f32; 2],
+}
+
+impl Stateful for Channel {
+    fn stream(&mut self, s: &mut StateStream) {
+        stream!(
+            s,
+            self.fnum,
+            self.block,
+            self.key,
+            self.sustain,
+            self.instrument,
+            self.volume,
+            self.modulator,
+            self.carrier,
+            self.feedback,
+        );
+    }
+}
+
+impl Channel {
+    fn ksl_db(&self, ksl: usize) -> f32 {
+        let base = KSL_DB[(self.fnum >> 5) as usize] - 6.0 * (7 - self.block) as f32;
+        base.max(0.0) * KSL_SCALE[ksl]
+    }
+
+    /// Key scale rate offset
+    fn rks(&self, ksr: bool) -> u8 {
+        let rks = (self.block << 1) | (self.fnum >> 8) as u8;
+        if ksr { rks } else { rks >> 2 }
+    }
+}
+
+/// Yamaha OPLL (YM2413) derived FM synthesizer with 6 two-operator channels.
+/// This is a floating point approximation of the chip, not a bit exact one.
+pub struct Opll {
+    custom: [u8; 8],
+    addr: u8,
+    channels: [Channel; 6],
+    /// In cycles
+    tremolo_phase: f32,
+    vibrato_phase: f32,
+    output: f32,
+}
+
+impl Stateful for Opll {
+    fn stream(&mut self, s: &mut StateStream) {
+        stream!(
+            s,
+            self.custom,
+            self.addr,
+            self.channels,
+            self.tremolo_phase,
+            self.vibrato_phase,
+            self.output,
+        );
+    }
+}
+
+impl Opll {
+    pub fn new() -> Self {
+        Opll {
+            custom: [0; 8],
+            addr: 0,
+            channels: [Channel::default(); 6],
+            tremolo_phase: 0.0,
+            vibrato_phase: 0.0,
+            output: 0.0,
+        }
+    }
+
+    /// $9010
+    pub fn write_addr(&mut self, val: u8) {
+        self.addr = val;
+    }
+
+    /// $9030
+    pub fn write_data(&mut self, val: u8) {
+        let addr = self.addr as usize;
+        if addr < 0x08 {
+            self.custom[addr] = val;
+            return;
+        }
+        let Some(ch) = self.channels.get_mut(addr & 0xf) else {
+            return;
+        };
+        match addr {
+            0x10..=0x15 => ch.fnum = (ch.fnum & 0x100) | val as u16,
+            0x20..=0x25 => {
+                ch.fnum = (ch.fnum & 0xff) | ((val as u16 & 1) << 8);
+                ch.block = (val >> 1) & 7;
+                ch.sustain = val & 0x20 != 0;
+                let key = val & 0x10 != 0;
+                if key && !ch.key {
+                    ch.modulator.key_on();
+                    ch.carrier.key_on();
+                } else if !key && ch.key {
+                    ch.modulator.key_off();
+                    ch.carrier.key_off();
+                }
+                ch.key = key;
+            }
+            0x30..=0x35 => {
+                ch.instrument = val >> 4;
+                ch.volume = val & 0xf;
+            }
+            _ => {}
+        }
+    }
+
+    fn patch(&self, instrument: u8) -> &[u8; 8] {
+        match instrument {
+            0 => &self.custom,
+            n => &PATCHES[n as usize - 1],
+        }
+    }
+
+    /// Produce the next sample, every [`SAMPLE_CYCLES`] CPU cycles
+    pub fn clock(&mut self) {
+        self.tremolo_phase = (self.tremolo_phase + TREMOLO_HZ / SAMPLE_RATE).fract();
+        self.vibrato_phase = (self.vibrato_phase + VIBRATO_HZ / SAMPLE_RATE).fract();
+        let tremolo_db = TREMOLO_DB * (1.0 - (TAU * self.tremolo_phase).cos()) / 2.0;
+        let vibrato = 2f32.powf(VIBRATO_CENTS / 1200.0 * (TAU * self.vibrato_phase).sin());
+
+        let mut output = 0.0;
+        for i in 0..self.channels.len() {
+            let mut ch = self.channels[i];
+            let patch = *self.patch(ch.instrument);
+            let params = [Params::new(&patch, 0), Params::new(&patch, 1)];
+            // Phase step in cycles per sample before the multiplier
+            let step = (ch.fnum as f32 * (1 << ch.block) as f32) / (1 << 19) as f32;
+
+            let release = |p: &Params| {
+                if ch.sustain {
+                    SUSTAIN_RELEASE_RATE
+                } else {
+                    p.release
+                }
+            };
+            let rks = [ch.rks(params[0].ksr), ch.rks(params[1].ksr)];
+            ch.modulator
+                .step_envelope(&params[0], release(&params[0]), rks[0]);
+            ch.carrier
+                .step_envelope(&params[1], release(&params[1]), rks[1]);
+
+            let extra_db = params
+                .each_ref()
+                .map(|p| ch.ksl_db(p.ksl) + if p.tremolo { tremolo_db } else { 0.0 });
+            let step_for = |p: &Params| if p.vibrato { step * vibrato } else { step };
+
+            let fb = patch[3] & 7;
+            let feedback = if fb == 0 {
+                0.0
+            } else {
+                (ch.feedback[0] + ch.feedback[1]) * 2f32.powi(fb as i32 - 7)
+            };
+            let tl_db = (patch[2] & 0x3f) as f32 * 0.75;
+            let modulator = ch.modulator.output(
+                &params[0],
+                step_for(&params[0]),
+                feedback,
+                tl_db + extra_db[0],
+            );
+            ch.feedback = [ch.feedback[1], modulator];
+            let volume_db = ch.volume as f32 * 3.0;
+            output += ch.carrier.output(
+                &params[1],
+                step_for(&params[1]),
+                modulator * 2.0,
+                volume_db + extra_db[1],
+            );
+            self.channels[i] = ch;
+        }
+        self.output = output;
+    }
+
+    /// Sum of the channels, each in -1.0 to 1.0
+    pub fn output(&self) -> f32 {
+        self.output
+    }
+}
+
+impl Default for Opll {
+    fn default() -> Self {
+        Self::new()
+    }
+}
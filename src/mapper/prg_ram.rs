@@ -0,0 +1,81 @@
+use crate::rom::Rom;
+use crate::savestate::{StateStream, Stateful};
+
+/// Cartridge RAM, sized from the header. Boards map it themselves, usually
+/// to $6000-$7FFF, and decide when it can be read and written.
+/// The battery backed part (PRG NVRAM in NES 2.0 headers) comes first, and
+/// is all that's kept between sessions.
+pub struct PrgRam {
+    data: Vec<u8>,
+    /// Bytes at the start kept by the battery
+    nvram_len: usize,
+}
+
+impl PrgRam {
+    pub fn new(rom: &Rom) -> Self {
+        Self::with_len(rom.prg_ram_size, rom.prg_nvram_size)
+    }
+
+    /// For boards that don't go by the header
+    pub fn with_len(len: usize, nvram_len: usize) -> Self {
+        PrgRam {
+            data: vec![0; len],
+            nvram_len: nvram_len.min(len),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.data.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    /// The byte `offset` into the RAM, which repeats when it is smaller than
+    /// where it is mapped. `None` on boards without RAM.
+    pub fn read(&self, offset: usize) -> Option<u8> {
+        (!self.is_empty()).then(|| self.data[offset % self.len()])
+    }
+
+    pub fn write(&mut self, offset: usize, val: u8) {
+        if !self.is_empty() {
+            let len = self.len();
+            self.data[offset % len] = val;
+        }
+    }
+
+    /// Replace the start of the RAM with `data`, for states saved before the
+    /// RAM was sized from the header
+    pub fn load(&mut self, data: &[u8]) {
+        let len = data.len().min(self.len());
+        self.data[..len].copy_from_slice(&data[..len]);
+    }
+
+    /// The battery backed part, see [`super::Mapper::save_data`]
+    pub fn save_data(&self) -> Option<Vec<u8>> {
+        (self.nvram_len > 0).then(|| self.data[..self.nvram_len].to_vec())
+    }
+
+    pub fn load_save_data(&mut self, data: &[u8]) {
+        let len = data.len().min(self.nvram_len);
+        self.data[..len].copy_from_slice(&data[..len]);
+    }
+}
+
+impl Stateful for PrgRam {
+    fn stream(&mut self, s: &mut StateStream) {
+        if s.version() >= 5 {
+            for byte in &mut self.data {
+                byte.stream(s);
+            }
+            return;
+        }
+        // Before version 5 every board had 8K
+        let mut old = [0; 0x2000];
+        old.stream(s);
+        if s.is_loading() {
+            self.load(&old);
+        }
+    }
+}
@@ -0,0 +1,156 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::rom::Region;
+use crate::savestate::{StateStream, Stateful, stream};
+
+/// The timekeeping half of a DS1307, its first 7 registers. The chip's I2C
+/// side is an [`super::eeprom::Eeprom`], the protocol is the same.
+/// See https://www.analog.com/media/en/technical-documentation/data-sheets/ds1307.pdf
+pub struct Rtc {
+    /// Seconds (bit 7 stops the clock), minutes, hours (bit 6 picks 12 hour
+    /// mode, then bit 5 is PM), day of the week, date, month and year, in BCD
+    regs: [u8; 7],
+    /// CPU cycles in a second
+    rate: u32,
+    /// CPU cycles into the current second
+    cycles: u32,
+}
+
+impl Stateful for Rtc {
+    fn stream(&mut self, s: &mut StateStream) {
+        stream!(s, self.regs, self.cycles);
+    }
+}
+
+fn bcd(val: u32) -> u8 {
+    (val / 10 * 16 + val % 10) as u8
+}
+
+fn binary(val: u8) -> u32 {
+    (val >> 4) as u32 * 10 + (val & 0xf) as u32
+}
+
+/// Every 4th year is a leap year on the DS1307, right until 2100
+fn days_in_year(year: u32) -> u32 {
+    if year.is_multiple_of(4) { 366 } else { 365 }
+}
+
+fn days_in_month(month: u32, year: u32) -> u32 {
+    match month {
+        2 if days_in_year(year) == 366 => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+impl Rtc {
+    /// Stopped at 00:00:00 on Saturday January 1st 2000, until [`Rtc::set`]
+    pub fn new(region: Region) -> Self {
+        Rtc {
+            regs: [0x80, 0, 0, 7, 1, 1, 0],
+            rate: region.cpu_clock_rate() as u32,
+            cycles: 0,
+        }
+    }
+
+    pub fn read(&self, reg: u8) -> u8 {
+        self.regs[reg as usize]
+    }
+
+    /// Writing the seconds restarts the current second
+    pub fn write(&mut self, reg: u8, val: u8) {
+        self.regs[reg as usize] = val;
+        if reg == 0 {
+            self.cycles = 0;
+        }
+    }
+
+    /// Set the clock to `now` in UTC and start it, in 24 hour mode. Sunday
+    /// is day 1 of the week.
+    pub fn set(&mut self, now: SystemTime) {
+        let secs = now.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+        let (days, time) = ((secs / 86_400) as u32, (secs % 86_400) as u32);
+        // January 1st 1970 was a Thursday
+        let weekday = (days + 4) % 7 + 1;
+        let (mut year, mut month, mut date) = (1970, 1, days + 1);
+        while date > days_in_year(year) {
+            date -= days_in_year(year);
+            year += 1;
+        }
+        while date > days_in_month(month, year) {
+            date -= days_in_month(month, year);
+            month += 1;
+        }
+        self.regs = [
+            bcd(time % 60),
+            bcd(time / 60 % 60),
+            bcd(time / 3600),
+            weekday as u8,
+            bcd(date),
+            bcd(month),
+            bcd(year % 100),
+        ];
+        self.cycles = 0;
+    }
+
+    pub fn tick(&mut self) {
+        if self.regs[0] & 0x80 != 0 {
+            return;
+        }
+        self.cycles += 1;
+        if self.cycles >= self.rate {
+            self.cycles = 0;
+            self.next_second();
+        }
+    }
+
+    fn next_second(&mut self) {
+        let [secs, mins, hours, weekday, date, month, year] = self.regs;
+        let twelve_hour = hours & 0x40 != 0;
+        let hour = match twelve_hour {
+            true => binary(hours & 0x1f) % 12 + if hours & 0x20 != 0 { 12 } else { 0 },
+            false => binary(hours & 0x3f),
+        };
+        let (mut secs, mut mins, mut hour) = (binary(secs) + 1, binary(mins), hour);
+        let (mut weekday, mut date) = (weekday as u32, binary(date));
+        let (mut month, mut year) = (binary(month), binary(year));
+        if secs >= 60 {
+            secs = 0;
+            mins += 1;
+        }
+        if mins >= 60 {
+            mins = 0;
+            hour += 1;
+        }
+        if hour >= 24 {
+            hour = 0;
+            weekday = weekday % 7 + 1;
+            date += 1;
+        }
+        if date > days_in_month(month, year) {
+            date = 1;
+            month += 1;
+        }
+        if month > 12 {
+            month = 1;
+            year = (year + 1) % 100;
+        }
+        let hours = match twelve_hour {
+            true => {
+                let pm = if hour >= 12 { 0x20 } else { 0 };
+                0x40 | pm | bcd((hour + 11) % 12 + 1)
+            }
+            false => bcd(hour),
+        };
+        self.regs = [
+            bcd(secs),
+            bcd(mins),
+            hours,
+            weekday as u8,
+            bcd(date),
+            bcd(month),
+            bcd(year),
+        ];
+    }
+}
@@ -0,0 +1,88 @@
+use super::{CartVram, Mapper, bus_conflicts, chr_mem};
+use crate::rom::{Mirroring, Rom};
+use crate::savestate::{StateStream, Stateful, stream};
+
+/// Mapper 2, 16K PRG switching with the last bank fixed at $C000
+/// See https://www.nesdev.org/wiki/UxROM
+pub struct Uxrom {
+    prg_rom: Vec<u8>,
+    chr: Vec<u8>,
+    chr_ram: bool,
+    vram: CartVram,
+    mirroring: Mirroring,
+    bus_conflicts: bool,
+    bank: u8,
+}
+
+impl Uxrom {
+    pub fn new(rom: Rom) -> Self {
+        let (chr, chr_ram) = chr_mem(&rom);
+        let vram = CartVram::new(&rom);
+        Uxrom {
+            bus_conflicts: bus_conflicts(&rom, true),
+            prg_rom: rom.prg_rom,
+            chr,
+            chr_ram,
+            vram,
+            mirroring: rom.mirroring,
+            bank: 0,
+        }
+    }
+}
+
+impl Stateful for Uxrom {
+    fn stream(&mut self, s: &mut StateStream) {
+        stream!(s, self.bank);
+        if self.chr_ram {
+            self.chr.stream(s);
+        }
+        self.vram.stream(s);
+    }
+}
+
+impl Mapper for Uxrom {
+    fn cpu_peek(&self, addr: u16) -> u8 {
+        self.prg_rom_offset(addr)
+            .map_or(0, |offset| self.prg_rom[offset])
+    }
+    fn prg_rom_offset(&self, addr: u16) -> Option<usize> {
+        let bank_count = self.prg_rom.len() / 0x4000;
+        let bank = match addr {
+            0x8000..=0xBFFF => self.bank as usize % bank_count,
+            0xC000..=0xFFFF => bank_count - 1,
+            _ => return None,
+        };
+        Some(bank * 0x4000 + (addr as usize & 0x3fff))
+    }
+    fn unmapped(&self, addr: u16) -> bool {
+        addr < 0x8000
+    }
+    fn cpu_write(&mut self, addr: u16, val: u8) {
+        if addr >= 0x8000 {
+            self.bank = if self.bus_conflicts {
+                val & self.cpu_peek(addr)
+            } else {
+                val
+            };
+        }
+    }
+    fn chr_offset(&self, addr: u16) -> Option<usize> {
+        Some(addr as usize & 0x1fff)
+    }
+    fn ppu_read(&mut self, addr: u16) -> u8 {
+        if CartVram::contains(addr) {
+            return self.vram.read(addr);
+        }
+        self.chr[addr as usize & 0x1fff]
+    }
+    fn ppu_write(&mut self, addr: u16, val: u8) {
+        if CartVram::contains(addr) {
+            self.vram.write(addr, val);
+        } else if self.chr_ram {
+            self.chr[addr as usize & 0x1fff] = val;
+        }
+    }
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+}
@@ -0,0 +1,190 @@
+use super::opll::{self, Opll};
+use super::vrc_irq::VrcIrq;
+use super::{Mapper, PpuTarget, PrgRam, chr_mem, switchable_mirroring};
+use crate::apu::ExpansionChip;
+use crate::rom::{Mirroring, Rom};
+use crate::savestate::{StateStream, Stateful, stream};
+
+/// Each OPLL channel at full volume is about as loud as an APU square channel
+const CHANNEL_LEVEL: f32 = 0.13;
+
+/// Mapper 85, with the OPLL derived FM sound chip
+/// See https://www.nesdev.org/wiki/VRC7
+pub struct Vrc7 {
+    prg_rom: Vec<u8>,
+    prg_ram: PrgRam,
+    /// $E000 bit 6
+    prg_ram_enabled: bool,
+    chr: Vec<u8>,
+    chr_ram: bool,
+    /// $E000 bits 0-1: vertical, horizontal, single screen A, single screen B
+    mirroring: u8,
+    /// 8K pages for $8000-$DFFF
+    prg_banks: [u8; 3],
+    /// 1K pages for the pattern tables
+    chr_banks: [u8; 8],
+    irq: VrcIrq,
+    opll: Opll,
+    /// $E000 bit 7, holds the sound chip in reset
+    sound_reset: bool,
+    sound_cycle: u8,
+}
+
+impl Vrc7 {
+    pub fn new(rom: Rom) -> Self {
+        let (chr, chr_ram) = chr_mem(&rom);
+        let prg_ram = PrgRam::new(&rom);
+        Vrc7 {
+            prg_rom: rom.prg_rom,
+            prg_ram,
+            prg_ram_enabled: false,
+            chr,
+            chr_ram,
+            mirroring: 0,
+            prg_banks: [0; 3],
+            chr_banks: [0; 8],
+            irq: VrcIrq::default(),
+            opll: Opll::new(),
+            sound_reset: false,
+            sound_cycle: 0,
+        }
+    }
+
+    fn prg_addr(&self, addr: u16) -> usize {
+        let bank_count = self.prg_rom.len() / 0x2000;
+        let bank = match (addr - 0x8000) / 0x2000 {
+            n @ 0..=2 => (self.prg_banks[n as usize] & 0x3f) as usize,
+            _ => bank_count - 1,
+        };
+        (bank % bank_count) * 0x2000 + (addr as usize & 0x1fff)
+    }
+
+    fn chr_addr(&self, addr: u16) -> usize {
+        let bank = self.chr_banks[(addr as usize & 0x1fff) / 0x400] as usize;
+        let bank_count = self.chr.len() / 0x400;
+        (bank % bank_count) * 0x400 + (addr as usize & 0x3ff)
+    }
+}
+
+impl Stateful for Vrc7 {
+    fn stream(&mut self, s: &mut StateStream) {
+        stream!(
+            s,
+            self.prg_ram,
+            self.prg_ram_enabled,
+            self.mirroring,
+            self.prg_banks,
+            self.chr_banks,
+            self.irq,
+            self.opll,
+            self.sound_reset,
+            self.sound_cycle,
+        );
+        if self.chr_ram {
+            self.chr.stream(s);
+        }
+    }
+}
+
+impl Mapper for Vrc7 {
+    fn cpu_peek(&self, addr: u16) -> u8 {
+        match addr {
+            0x6000..=0x7FFF if self.prg_ram_enabled => {
+                self.prg_ram.read(addr as usize - 0x6000).unwrap_or(0)
+            }
+            0x8000..=0xFFFF => self.prg_rom[self.prg_addr(addr)],
+            _ => 0,
+        }
+    }
+    fn prg_rom_offset(&self, addr: u16) -> Option<usize> {
+        (addr >= 0x8000).then(|| self.prg_addr(addr))
+    }
+    fn unmapped(&self, addr: u16) -> bool {
+        addr < 0x6000 || (addr < 0x8000 && self.prg_ram.is_empty())
+    }
+    fn cpu_write(&mut self, addr: u16, val: u8) {
+        if let 0x6000..=0x7FFF = addr {
+            if self.prg_ram_enabled {
+                self.prg_ram.write(addr as usize - 0x6000, val);
+            }
+            return;
+        }
+        // VRC7a (Lagrange Point) uses A4 and VRC7b uses A3 for the second register
+        let high = addr & 0x18 != 0;
+        match (addr & 0xf000, high) {
+            (0x8000, false) => self.prg_banks[0] = val,
+            (0x8000, true) => self.prg_banks[1] = val,
+            (0x9000, false) => self.prg_banks[2] = val,
+            (0x9000, true) if addr & 0x20 != 0 => self.opll.write_data(val),
+            (0x9000, true) => self.opll.write_addr(val),
+            (0xA000..=0xD000, _) => {
+                let reg = ((addr - 0xA000) >> 12) * 2 + high as u16;
+                self.chr_banks[reg as usize] = val;
+            }
+            (0xE000, false) => {
+                self.mirroring = val & 3;
+                self.prg_ram_enabled = val & 0x40 != 0;
+                self.sound_reset = val & 0x80 != 0;
+                if self.sound_reset {
+                    self.opll = Opll::new();
+                }
+            }
+            (0xE000, true) => self.irq.write_latch(val),
+            (0xF000, false) => self.irq.write_control(val),
+            (0xF000, true) => self.irq.ack(),
+            _ => {}
+        }
+    }
+    fn ppu_target(&self, addr: u16) -> PpuTarget {
+        switchable_mirroring(addr, self.mirroring)
+    }
+    fn chr_offset(&self, addr: u16) -> Option<usize> {
+        Some(self.chr_addr(addr))
+    }
+    fn ppu_read(&mut self, addr: u16) -> u8 {
+        self.chr[self.chr_addr(addr)]
+    }
+    fn ppu_write(&mut self, addr: u16, val: u8) {
+        if self.chr_ram {
+            let addr = self.chr_addr(addr);
+            self.chr[addr] = val;
+        }
+    }
+    fn mirroring(&self) -> Mirroring {
+        match self.mirroring {
+            1 => Mirroring::Horizontal,
+            _ => Mirroring::Vertical,
+        }
+    }
+    fn cpu_tick(&mut self) {
+        self.irq.tick();
+        if self.sound_reset {
+            return;
+        }
+        self.sound_cycle += 1;
+        if self.sound_cycle == opll::SAMPLE_CYCLES {
+            self.sound_cycle = 0;
+            self.opll.clock();
+        }
+    }
+    fn irq(&self) -> bool {
+        self.irq.pending
+    }
+    fn save_data(&self) -> Option<Vec<u8>> {
+        self.prg_ram.save_data()
+    }
+    fn load_save_data(&mut self, data: &[u8]) {
+        self.prg_ram.load_save_data(data);
+    }
+    fn expansion_audio(&self) -> Option<(ExpansionChip, f32)> {
+        Some((ExpansionChip::Vrc7, self.opll.output() * CHANNEL_LEVEL))
+    }
+    fn audio_register(&self, addr: u16) -> bool {
+        // The OPLL's address and data, and the sound reset in $E000
+        match addr & 0xf000 {
+            0x9000 => addr & 0x18 != 0,
+            0xE000 => addr & 0x18 == 0,
+            _ => false,
+        }
+    }
+}
@@ -0,0 +1,80 @@
+use crate::savestate::{StateStream, Stateful, stream};
+
+/// CPU cycles per scanline, scaled by 3 so it stays an integer
+const PRESCALER_PERIOD: i16 = 341;
+
+/// IRQ counter shared by the Konami VRC4, VRC6 and VRC7
+/// See https://www.nesdev.org/wiki/VRC_IRQ
+#[derive(Default)]
+pub struct VrcIrq {
+    pub pending: bool,
+    latch: u8,
+    counter: u8,
+    prescaler: i16,
+    enabled: bool,
+    enable_after_ack: bool,
+    /// Count CPU cycles instead of scanlines
+    cycle_mode: bool,
+}
+
+impl Stateful for VrcIrq {
+    fn stream(&mut self, s: &mut StateStream) {
+        stream!(
+            s,
+            self.pending,
+            self.latch,
+            self.counter,
+            self.prescaler,
+            self.enabled,
+            self.enable_after_ack,
+            self.cycle_mode,
+        );
+    }
+}
+
+impl VrcIrq {
+    pub fn write_latch(&mut self, val: u8) {
+        self.latch = val;
+    }
+
+    pub fn write_control(&mut self, val: u8) {
+        self.enable_after_ack = val & 1 != 0;
+        self.enabled = val & 2 != 0;
+        self.cycle_mode = val & 4 != 0;
+        self.pending = false;
+        if self.enabled {
+            self.counter = self.latch;
+            self.prescaler = PRESCALER_PERIOD;
+        }
+    }
+
+    pub fn ack(&mut self) {
+        self.pending = false;
+        self.enabled = self.enable_after_ack;
+    }
+
+    /// Advance by a single CPU cycle
+    pub fn tick(&mut self) {
+        if !self.enabled {
+            return;
+        }
+        if self.cycle_mode {
+            self.clock();
+            return;
+        }
+        self.prescaler -= 3;
+        if self.prescaler <= 0 {
+            self.prescaler += PRESCALER_PERIOD;
+            self.clock();
+        }
+    }
+
+    fn clock(&mut self) {
+        if self.counter == 0xff {
+            self.counter = self.latch;
+            self.pending = true;
+        } else {
+            self.counter += 1;
+        }
+    }
+}
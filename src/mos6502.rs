@@ -0,0 +1,833 @@
+//! The 6502 on its own, for anything built around one and not just the NES.
+//! [`Cpu`] runs the official instruction set on any [`Bus`], which decides
+//! what's at each address and what passing cycles do. [`Config`] picks the
+//! interrupt vectors and the [`CpuVariant`], which decides whether the D flag
+//! switches ADC and SBC to decimal. The NES's 2A03 left decimal mode out.
+//!
+//! The NES layers its specifics on top through the hooks on [`Bus`], which
+//! do nothing by default: interrupts from the PPU and APU, breakpoints,
+//! diagnostics and the labels the debugger shows. [`crate::Cpu`] is this CPU
+//! on the NES's [`crate::Bus`].
+
+use crate::diagnostics::Call;
+use crate::error::CpuError;
+use crate::labels::AutoLabelKind;
+use crate::savestate::{StateStream, Stateful, stateful_bitflags, stream};
+
+pub use crate::fetch_decode::{AddrMode, InstructionInfo, Opcode, decode};
+
+mod cycles;
+
+bitflags::bitflags! {
+    #[derive(Clone)]
+    pub struct Flags: u8 {
+        const NEGATIVE = 0b_1000_0000;
+        const OVERFLOW = 0b_0100_0000;
+        const BREAK2 = 0b_0010_0000;
+        const BREAK = 0b_0001_0000;
+        const DECIMAL = 0b_0000_1000;
+        const INTERRUPTDISABLE = 0b_0000_0100;
+        const ZERO = 0b_0000_0010;
+        const CARRY = 0b_0000_0001;
+    }
+}
+
+impl Default for Flags {
+    fn default() -> Self {
+        Self::from_bits_retain(0)
+    }
+}
+
+stateful_bitflags!(Flags);
+
+/// Which chip the core behaves like
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CpuVariant {
+    /// The original NMOS 6502, whose ADC and SBC work in binary coded
+    /// decimal while the D flag is set
+    Nmos6502,
+    /// The NES's CPU, a 6502 with decimal mode cut out. SED and CLD still
+    /// change the D flag, but arithmetic is always binary.
+    Ricoh2A03,
+}
+
+impl CpuVariant {
+    /// Whether the D flag does anything
+    pub fn has_decimal_mode(self) -> bool {
+        self == CpuVariant::Nmos6502
+    }
+}
+
+/// How a particular 6502 is wired up
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Config {
+    pub variant: CpuVariant,
+    pub reset_vector: u16,
+    pub nmi_vector: u16,
+    pub irq_vector: u16,
+}
+
+impl Config {
+    /// An NMOS 6502 like the Apple II's or the C64's 6510
+    pub const MOS6502: Config = Config {
+        variant: CpuVariant::Nmos6502,
+        reset_vector: 0xfffc,
+        nmi_vector: 0xfffa,
+        irq_vector: 0xfffe,
+    };
+    /// The NES's CPU, which ignores the D flag
+    pub const RICOH_2A03: Config = Config {
+        variant: CpuVariant::Ricoh2A03,
+        ..Config::MOS6502
+    };
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config::MOS6502
+    }
+}
+
+/// Everything the CPU is connected to. Only memory access is required, the
+/// rest does nothing unless a system needs it.
+pub trait Bus: Sized {
+    /// What [`Cpu::new`] uses, [`Cpu::with_config`] picks another
+    const CONFIG: Config = Config::MOS6502;
+
+    fn read(&mut self, addr: u16) -> u8;
+    fn write(&mut self, addr: u16, val: u8);
+    /// Read without triggering any side effects of the read, for working out
+    /// addresses and for debuggers
+    fn peek(&self, addr: u16) -> u8;
+
+    fn read_u16(&mut self, addr: u16) -> u16 {
+        let low = self.read(addr) as u16;
+        let high = self.read(addr.wrapping_add(1)) as u16;
+        (high << 8) | low
+    }
+    fn peek_u16(&self, addr: u16) -> u16 {
+        let low = self.peek(addr) as u16;
+        let high = self.peek(addr.wrapping_add(1)) as u16;
+        (high << 8) | low
+    }
+    fn write_u16(&mut self, addr: u16, val: u16) {
+        self.write(addr, val as u8);
+        self.write(addr.wrapping_add(1), (val >> 8) as u8);
+    }
+
+    /// `cycles` CPU cycles passed running an instruction or interrupt
+    fn tick(&mut self, _cycles: u16) {}
+    /// Whether an NMI is pending, which taking clears
+    fn take_nmi(&mut self) -> bool {
+        false
+    }
+    /// Whether the IRQ line is low. Only asked while interrupts are enabled.
+    fn irq(&mut self) -> bool {
+        false
+    }
+
+    /// Before the instruction at PC is read, returns whether to stop before
+    /// it with [`CpuError::DebugBreak`]. `resuming` is set for the
+    /// instruction the CPU last stopped on, which runs this time.
+    fn before_instruction(_cpu: &mut Cpu<Self>, _resuming: bool) -> bool {
+        false
+    }
+    /// After the opcode at PC is read, before the instruction runs. The
+    /// operands are only peeked, see [`Cpu::get_addr_mode_dest`].
+    fn decoded(_cpu: &mut Cpu<Self>, _opcode: Opcode, _addr_mode: AddrMode, _size: u16) {}
+    /// After an instruction ran and its cycles passed, returns whether to stop
+    fn after_instruction(&mut self) -> bool {
+        false
+    }
+    /// After an interrupt jumped to its handler and its cycles passed,
+    /// returns whether to stop
+    fn after_interrupt(&mut self, _nmi: bool) -> bool {
+        false
+    }
+    /// A JSR or interrupt, once the return address is pushed
+    fn call(&mut self, _call: Call) {}
+    /// Code starts at `addr`: the reset vector, an interrupt handler, a
+    /// subroutine or a branch target, whether the branch was taken or not
+    fn code_at(&mut self, _addr: u16, _kind: AutoLabelKind) {}
+    /// A push with the stack pointer at $00 (`overflow`) or a pull at $FF
+    fn stack_wrapped(&mut self, _overflow: bool) {}
+}
+
+pub struct Cpu<B> {
+    pub reg_a: u8,
+    pub reg_x: u8,
+    pub reg_y: u8,
+    pub stack_ptr: u8,
+    pub pc: u16,
+    pub status: Flags,
+    pub memory: B,
+    pub brk: bool,
+    pub config: Config,
+    /// Run instructions a bus cycle at a time, with `tick(1)` after every
+    /// read and write including the dummy ones, instead of ticking all of an
+    /// instruction's cycles once it ran. Slower, for the interactions with
+    /// the rest of the system that happen partway through an instruction.
+    pub cycle_stepped: bool,
+    /// Where [`Cpu::reset`] starts instead of the address in the reset
+    /// vector, for test harnesses like nestest's automation mode at $C000
+    pub reset_pc: Option<u16>,
+    /// From [`Cpu::trigger_nmi`], taken before the next instruction
+    nmi_pending: bool,
+    /// From [`Cpu::trigger_irq`], taken once interrupts are enabled
+    irq_pending: bool,
+    /// The instruction the CPU stopped before for the debugger, which runs
+    /// next without being checked again
+    break_pc: Option<u16>,
+}
+
+const STACK_RESET: u8 = 0xfd;
+/// The stack is page 1, from $01FF down
+pub const STACK_START: u16 = 0x100;
+
+impl<B: Bus> Cpu<B> {
+    pub fn new(bus: B) -> Self {
+        Self::with_config(bus, B::CONFIG)
+    }
+    pub fn with_config(bus: B, config: Config) -> Self {
+        let mut me = Self {
+            reg_a: 0,
+            reg_x: 0,
+            reg_y: 0,
+            stack_ptr: 0,
+            pc: 0,
+            status: Flags::empty(),
+            memory: bus,
+            brk: false,
+            config,
+            cycle_stepped: false,
+            reset_pc: None,
+            nmi_pending: false,
+            irq_pending: false,
+            break_pc: None,
+        };
+        me.reset();
+        me
+    }
+    pub fn reset(&mut self) {
+        self.status = Flags::INTERRUPTDISABLE | Flags::BREAK2;
+        self.reg_a = 0;
+        self.reg_x = 0;
+        self.reg_y = 0;
+        self.stack_ptr = STACK_RESET;
+        self.nmi_pending = false;
+        self.irq_pending = false;
+        let pc = match self.reset_pc {
+            Some(pc) => pc,
+            None => self.memory.read_u16(self.config.reset_vector),
+        };
+        self.pc = pc;
+        self.memory.code_at(pc, AutoLabelKind::Reset);
+    }
+    /// Carry on from `pc`, like a jump. A stop for the debugger before the
+    /// old PC is forgotten.
+    pub fn set_pc(&mut self, pc: u16) {
+        self.pc = pc;
+        self.break_pc = None;
+    }
+    /// Take an NMI before the next instruction, as if the bus asked for one
+    pub fn trigger_nmi(&mut self) {
+        self.nmi_pending = true;
+    }
+    /// Take an IRQ before the next instruction run with interrupts enabled.
+    /// It stays pending until then, and only one is taken however many
+    /// are triggered.
+    pub fn trigger_irq(&mut self) {
+        self.irq_pending = true;
+    }
+    pub fn run_with_callback<F: FnMut(&mut Self)>(&mut self, mut f: F) {
+        while !self.brk {
+            f(self);
+            self.step();
+        }
+    }
+    pub fn run(&mut self) {
+        self.run_with_callback(|_| {})
+    }
+    fn update_zero_negative(&mut self, value: u8) {
+        self.status.set(Flags::ZERO, value == 0);
+        self.status.set(Flags::NEGATIVE, value & 0x80 != 0);
+    }
+}
+
+impl<B: Stateful> Stateful for Cpu<B> {
+    fn stream(&mut self, s: &mut StateStream) {
+        stream!(
+            s,
+            self.reg_a,
+            self.reg_x,
+            self.reg_y,
+            self.stack_ptr,
+            self.pc,
+            self.status,
+            self.brk,
+            self.memory,
+        );
+        // Interrupts triggered by hand came in version 8
+        if s.version() >= 8 {
+            stream!(s, self.nmi_pending, self.irq_pending);
+        } else if s.is_loading() {
+            self.nmi_pending = false;
+            self.irq_pending = false;
+        }
+    }
+}
+
+impl<B: Bus> Cpu<B> {
+    /// Get the value at the correct addressing mode
+    /// Assumes the `pc` is still set at the instruction beginning
+    pub fn get_addr_mode_dest(&self, addr_mode: AddrMode) -> u16 {
+        self.get_addr_mode_dest_ext(addr_mode, self.pc)
+    }
+    /// Like [`Cpu::get_addr_mode_dest`] for the instruction at `base`
+    pub fn get_addr_mode_dest_ext(&self, addr_mode: AddrMode, base: u16) -> u16 {
+        match addr_mode {
+            AddrMode::Implicit | AddrMode::Accumulator => {
+                unreachable!("{addr_mode:?} instructions don't address memory")
+            }
+            AddrMode::Immediate => base.wrapping_add(1),
+            AddrMode::ZeroPage => self.memory.peek(base.wrapping_add(1)) as u16,
+            AddrMode::ZeroPageX => self
+                .memory
+                .peek(base.wrapping_add(1))
+                .wrapping_add(self.reg_x) as u16,
+            AddrMode::ZeroPageY => self
+                .memory
+                .peek(base.wrapping_add(1))
+                .wrapping_add(self.reg_y) as u16,
+            AddrMode::Relative => base.wrapping_add(1),
+            AddrMode::Absolute => self.memory.peek_u16(base.wrapping_add(1)),
+            AddrMode::AbsoluteX => {
+                self.memory
+                    .peek_u16(base.wrapping_add(1))
+                    .wrapping_add(self.reg_x as u16)
+                // + self.status.contains(Flags::CARRY) as u16
+            }
+            AddrMode::AbsoluteY => {
+                self.memory
+                    .peek_u16(base.wrapping_add(1))
+                    .wrapping_add(self.reg_y as u16)
+                // + self.status.contains(Flags::CARRY) as u16
+            }
+            AddrMode::Indirect => {
+                let indirect_addr = self.memory.peek_u16(self.pc.wrapping_add(1));
+                if indirect_addr as u8 == 0xff {
+                    let low = self.memory.peek(indirect_addr) as u16;
+                    let high = self.memory.peek(indirect_addr & 0xff00) as u16;
+                    (high << 8) | low
+                } else {
+                    self.memory.peek_u16(indirect_addr)
+                }
+                // let imm = self.memory.read_u16(base.wrapping_add(1));
+                // self.memory.read_u16(imm)
+            }
+            AddrMode::IndexedIndirect => {
+                let addr = self
+                    .memory
+                    .peek(base.wrapping_add(1))
+                    .wrapping_add(self.reg_x);
+                let low = self.memory.peek(addr as u16);
+                let high = self.memory.peek(addr.wrapping_add(1) as u16);
+                ((high as u16) << 8) | (low as u16)
+            }
+            AddrMode::IndirectIndexed => {
+                let base_loc = self.memory.peek(base.wrapping_add(1));
+                let low = self.memory.peek(base_loc as u16);
+                let high = self.memory.peek(base_loc.wrapping_add(1) as u16);
+                let base = ((high as u16) << 8) | (low as u16);
+                base.wrapping_add(self.reg_y as u16)
+            }
+        }
+    }
+    /// Run a single instruction (or interrupt)
+    pub fn step(&mut self) {
+        let _ = self.try_step();
+    }
+
+    /// Like [`Cpu::step`], but reports an opcode the CPU can't run. The CPU
+    /// stays on it and 2 cycles pass, so the rest of the system carries on.
+    pub fn try_step(&mut self) -> Result<(), CpuError> {
+        if std::mem::take(&mut self.nmi_pending) || self.memory.take_nmi() {
+            return self.interrupt(true);
+        }
+        if !self.status.contains(Flags::INTERRUPTDISABLE) && (self.irq_pending || self.memory.irq())
+        {
+            self.irq_pending = false;
+            return self.interrupt(false);
+        }
+        // Carrying on from a stop, the instruction was already checked
+        let resuming = self.break_pc.take_if(|pc| *pc == self.pc).is_some();
+        if B::before_instruction(self, resuming) {
+            self.break_pc = Some(self.pc);
+            return Err(CpuError::DebugBreak { pc: self.pc });
+        }
+        let instruction_byte = self.memory.read(self.pc);
+        let Some((opcode, addr_mode, inst_info)) = decode(instruction_byte) else {
+            self.memory.tick(2);
+            return Err(CpuError::InvalidOpcode {
+                opcode: instruction_byte,
+                pc: self.pc,
+            });
+        };
+        B::decoded(self, opcode, addr_mode, inst_info.size);
+        if self.cycle_stepped {
+            self.memory.tick(1);
+            self.execute_cycles(opcode, addr_mode);
+        } else {
+            let cycles = self.execute(opcode, addr_mode, inst_info);
+            self.memory.tick(cycles);
+        }
+        if self.memory.after_instruction() {
+            return Err(CpuError::DebugBreak { pc: self.pc });
+        }
+        Ok(())
+    }
+
+    /// Push PC and status, then jump through the NMI or IRQ vector. Takes 7 cycles.
+    fn interrupt(&mut self, nmi: bool) -> Result<(), CpuError> {
+        let from = self.pc;
+        let (vector, kind) = match nmi {
+            true => (self.config.nmi_vector, AutoLabelKind::Nmi),
+            false => (self.config.irq_vector, AutoLabelKind::Irq),
+        };
+        if self.cycle_stepped {
+            self.interrupt_cycles(vector);
+        } else {
+            self.push_stack_u16(self.pc);
+            let mut status = self.status.clone();
+            status.remove(Flags::BREAK);
+            status.insert(Flags::BREAK2);
+            self.push_stack(status.bits());
+            self.status.insert(Flags::INTERRUPTDISABLE);
+            self.pc = self.memory.read_u16(vector);
+        }
+        self.memory.code_at(self.pc, kind);
+        self.memory.call(Call {
+            from,
+            to: self.pc,
+            return_addr: from,
+            sp: self.stack_ptr,
+            interrupt: true,
+        });
+        if !self.cycle_stepped {
+            self.memory.tick(7);
+        }
+        if self.memory.after_interrupt(nmi) {
+            return Err(CpuError::DebugBreak { pc: self.pc });
+        }
+        Ok(())
+    }
+
+    /// Execute a decoded instruction, returning how many cycles it took
+    fn execute(&mut self, opcode: Opcode, addr_mode: AddrMode, inst_info: InstructionInfo) -> u16 {
+        let mut cycles = inst_info.cycles;
+        if self.page_crossed(addr_mode) {
+            cycles += inst_info.cycles_extra;
+        }
+        match (opcode, addr_mode) {
+            (
+                Opcode::ADC
+                | Opcode::AND
+                | Opcode::BIT
+                | Opcode::CMP
+                | Opcode::CPX
+                | Opcode::CPY
+                | Opcode::EOR
+                | Opcode::LDA
+                | Opcode::LDX
+                | Opcode::LDY
+                | Opcode::ORA
+                | Opcode::SBC,
+                addr_mode,
+            ) => {
+                let addr = self.get_addr_mode_dest(addr_mode);
+                let val = self.memory.read(addr);
+                self.read_op(opcode, val);
+            }
+            (Opcode::ASL | Opcode::LSR | Opcode::ROL | Opcode::ROR, AddrMode::Accumulator) => {
+                self.reg_a = self.modify_op(opcode, self.reg_a);
+            }
+            (
+                Opcode::ASL | Opcode::LSR | Opcode::ROL | Opcode::ROR | Opcode::INC | Opcode::DEC,
+                addr_mode,
+            ) => {
+                let addr = self.get_addr_mode_dest(addr_mode);
+                let val = self.memory.read(addr);
+                let new_val = self.modify_op(opcode, val);
+                self.memory.write(addr, new_val);
+            }
+            (Opcode::STA | Opcode::STX | Opcode::STY, addr_mode) => {
+                let addr = self.get_addr_mode_dest(addr_mode);
+                self.memory.write(addr, self.store_value(opcode));
+            }
+            (Opcode::BCC, addr_mode) => {
+                cycles += self.branch_impl(addr_mode, Flags::CARRY, false, inst_info);
+            }
+            (Opcode::BCS, addr_mode) => {
+                cycles += self.branch_impl(addr_mode, Flags::CARRY, true, inst_info);
+            }
+            (Opcode::BEQ, addr_mode) => {
+                cycles += self.branch_impl(addr_mode, Flags::ZERO, true, inst_info);
+            }
+            (Opcode::BMI, addr_mode) => {
+                cycles += self.branch_impl(addr_mode, Flags::NEGATIVE, true, inst_info);
+            }
+            (Opcode::BNE, addr_mode) => {
+                cycles += self.branch_impl(addr_mode, Flags::ZERO, false, inst_info);
+            }
+            (Opcode::BPL, addr_mode) => {
+                cycles += self.branch_impl(addr_mode, Flags::NEGATIVE, false, inst_info);
+            }
+            (Opcode::BRK, _addr_mode) => {
+                self.status.insert(Flags::BREAK);
+                self.brk = true;
+                return cycles;
+                // self.push_stack_u16(self.pc);
+                // self.push_stack(self.status.bits());
+                // self.pc = self.memory.read_u16(0xfffe);
+            }
+            (Opcode::BVC, addr_mode) => {
+                cycles += self.branch_impl(addr_mode, Flags::OVERFLOW, false, inst_info);
+            }
+            (Opcode::BVS, addr_mode) => {
+                cycles += self.branch_impl(addr_mode, Flags::OVERFLOW, true, inst_info);
+            }
+            (Opcode::JMP, addr_mode) => match addr_mode {
+                AddrMode::Absolute | AddrMode::Indirect => {
+                    self.pc = self.get_addr_mode_dest(addr_mode);
+                    return cycles;
+                }
+                _ => unreachable!(),
+            },
+            (Opcode::JSR, addr_mode) => {
+                match addr_mode {
+                    AddrMode::Absolute => (),
+                    _ => unreachable!("JSR is only absolute"),
+                }
+                let return_loc = self.pc.wrapping_add(3); // jsr is 3 bytes
+                let fn_addr = self.memory.read_u16(self.pc.wrapping_add(1)); // absolute
+                self.push_stack_u16(return_loc.wrapping_sub(1)); // rti is 1 byte so it'll be incremented
+                self.jumped_to_subroutine(fn_addr);
+                self.pc = fn_addr;
+                return cycles;
+            }
+            (Opcode::PHA, _addr_mode) => {
+                self.push_stack(self.reg_a);
+            }
+            (Opcode::PHP, _addr_mode) => {
+                self.push_stack(self.pushed_status());
+            }
+            (Opcode::PLA, _addr_mode) => {
+                self.reg_a = self.pop_stack();
+                self.update_zero_negative(self.reg_a);
+            }
+            (Opcode::PLP, _addr_mode) => {
+                let status = self.pop_stack();
+                self.pull_status(status);
+            }
+            (Opcode::RTI, _addr_mode) => {
+                let status = self.pop_stack();
+                self.pull_status(status);
+                self.pc = self.pop_stack_u16().wrapping_sub(1);
+            }
+            (Opcode::RTS, _addr_mode) => {
+                self.pc = self.pop_stack_u16();
+            }
+            (_, _addr_mode) => self.register_op(opcode),
+        }
+        self.pc = self.pc.wrapping_add(inst_info.size);
+        cycles
+    }
+
+    /// The part of an instruction that reads memory (or an immediate operand)
+    /// after the value is read
+    fn read_op(&mut self, opcode: Opcode, val: u8) {
+        match opcode {
+            Opcode::ADC => self.add_a(val),
+            Opcode::AND => {
+                self.reg_a &= val;
+                self.update_zero_negative(self.reg_a);
+            }
+            Opcode::BIT => {
+                self.status.set(Flags::OVERFLOW, val & 0b0100_0000 != 0);
+                self.status.set(Flags::NEGATIVE, val & 0b1000_0000 != 0);
+                self.status.set(Flags::ZERO, self.reg_a & val == 0);
+            }
+            Opcode::CMP => self.compare(self.reg_a, val),
+            Opcode::CPX => self.compare(self.reg_x, val),
+            Opcode::CPY => self.compare(self.reg_y, val),
+            Opcode::EOR => {
+                self.reg_a ^= val;
+                self.update_zero_negative(self.reg_a);
+            }
+            Opcode::LDA => {
+                self.reg_a = val;
+                self.update_zero_negative(val);
+            }
+            Opcode::LDX => {
+                self.reg_x = val;
+                self.update_zero_negative(val);
+            }
+            Opcode::LDY => {
+                self.reg_y = val;
+                self.update_zero_negative(val);
+            }
+            Opcode::ORA => {
+                self.reg_a |= val;
+                self.update_zero_negative(self.reg_a);
+            }
+            Opcode::SBC => self.sub_a(val),
+            _ => unreachable!("{opcode:?} doesn't read memory"),
+        }
+    }
+
+    fn compare(&mut self, reg: u8, m: u8) {
+        self.status.set(Flags::CARRY, reg >= m);
+        self.update_zero_negative(reg.wrapping_sub(m));
+    }
+
+    /// The new value a read-modify-write instruction (or a shift of A) makes
+    fn modify_op(&mut self, opcode: Opcode, val: u8) -> u8 {
+        let carry_in = self.status.contains(Flags::CARRY) as u8;
+        let (new_val, carry) = match opcode {
+            Opcode::ASL => (val << 1, Some(val & 0x80 != 0)),
+            Opcode::LSR => (val >> 1, Some(val & 0x1 != 0)),
+            Opcode::ROL => ((val << 1) | carry_in, Some(val & 0x80 != 0)),
+            Opcode::ROR => ((val >> 1) | (carry_in << 7), Some(val & 0x1 != 0)),
+            Opcode::INC => (val.wrapping_add(1), None),
+            Opcode::DEC => (val.wrapping_sub(1), None),
+            _ => unreachable!("{opcode:?} doesn't modify memory"),
+        };
+        if let Some(carry) = carry {
+            self.status.set(Flags::CARRY, carry);
+        }
+        self.update_zero_negative(new_val);
+        new_val
+    }
+
+    /// What STA, STX or STY writes
+    fn store_value(&self, opcode: Opcode) -> u8 {
+        match opcode {
+            Opcode::STA => self.reg_a,
+            Opcode::STX => self.reg_x,
+            Opcode::STY => self.reg_y,
+            _ => unreachable!("{opcode:?} doesn't store a register"),
+        }
+    }
+
+    /// Instructions that only work on registers and flags
+    fn register_op(&mut self, opcode: Opcode) {
+        match opcode {
+            Opcode::CLC => self.status.remove(Flags::CARRY),
+            Opcode::CLD => self.status.remove(Flags::DECIMAL),
+            Opcode::CLI => self.status.remove(Flags::INTERRUPTDISABLE),
+            Opcode::CLV => self.status.remove(Flags::OVERFLOW),
+            Opcode::DEX => {
+                self.reg_x = self.reg_x.wrapping_sub(1);
+                self.update_zero_negative(self.reg_x);
+            }
+            Opcode::DEY => {
+                self.reg_y = self.reg_y.wrapping_sub(1);
+                self.update_zero_negative(self.reg_y);
+            }
+            Opcode::INX => {
+                self.reg_x = self.reg_x.wrapping_add(1);
+                self.update_zero_negative(self.reg_x);
+            }
+            Opcode::INY => {
+                self.reg_y = self.reg_y.wrapping_add(1);
+                self.update_zero_negative(self.reg_y);
+            }
+            Opcode::NOP => {}
+            Opcode::SEC => self.status.insert(Flags::CARRY),
+            Opcode::SED => self.status.insert(Flags::DECIMAL),
+            Opcode::SEI => self.status.insert(Flags::INTERRUPTDISABLE),
+            Opcode::TAX => {
+                self.reg_x = self.reg_a;
+                self.update_zero_negative(self.reg_x);
+            }
+            Opcode::TAY => {
+                self.reg_y = self.reg_a;
+                self.update_zero_negative(self.reg_y);
+            }
+            Opcode::TSX => {
+                self.reg_x = self.stack_ptr;
+                self.update_zero_negative(self.reg_x);
+            }
+            Opcode::TXA => {
+                self.reg_a = self.reg_x;
+                self.update_zero_negative(self.reg_a);
+            }
+            Opcode::TXS => self.stack_ptr = self.reg_x,
+            Opcode::TYA => {
+                self.reg_a = self.reg_y;
+                self.update_zero_negative(self.reg_a);
+            }
+            _ => unreachable!("{opcode:?} isn't a register instruction"),
+        }
+    }
+
+    /// Status as PHP pushes it, with both break bits set
+    fn pushed_status(&self) -> u8 {
+        (self.status.clone() | Flags::BREAK | Flags::BREAK2).bits()
+    }
+
+    /// Status pulled by PLP or RTI, which has no break flag
+    fn pull_status(&mut self, bits: u8) {
+        self.status = Flags::from_bits_retain(bits);
+        self.status.remove(Flags::BREAK);
+        self.status.insert(Flags::BREAK2);
+    }
+
+    /// Tell the bus about a JSR at PC, once it pushed its return address
+    fn jumped_to_subroutine(&mut self, fn_addr: u16) {
+        self.memory.call(Call {
+            from: self.pc,
+            to: fn_addr,
+            return_addr: self.pc.wrapping_add(3),
+            sp: self.stack_ptr,
+            interrupt: false,
+        });
+        self.memory.code_at(fn_addr, AutoLabelKind::Subroutine);
+    }
+
+    /// Whether indexing crosses a page boundary, which costs an extra cycle on reads
+    fn page_crossed(&self, addr_mode: AddrMode) -> bool {
+        let (base, index) = match addr_mode {
+            AddrMode::AbsoluteX => (self.memory.peek_u16(self.pc.wrapping_add(1)), self.reg_x),
+            AddrMode::AbsoluteY => (self.memory.peek_u16(self.pc.wrapping_add(1)), self.reg_y),
+            AddrMode::IndirectIndexed => {
+                let base_loc = self.memory.peek(self.pc.wrapping_add(1));
+                let low = self.memory.peek(base_loc as u16);
+                let high = self.memory.peek(base_loc.wrapping_add(1) as u16);
+                (((high as u16) << 8) | (low as u16), self.reg_y)
+            }
+            _ => return false,
+        };
+        base & 0xff00 != base.wrapping_add(index as u16) & 0xff00
+    }
+
+    /// Returns the extra cycles taken by the branch
+    fn branch_impl(
+        &mut self,
+        addr_mode: AddrMode,
+        flag: Flags,
+        set: bool,
+        inst_info: InstructionInfo,
+    ) -> u16 {
+        let addr = self.get_addr_mode_dest(addr_mode);
+        let val = self.memory.read(addr) as i8 as i16;
+        let next = self.pc.wrapping_add(inst_info.size);
+        self.memory
+            .code_at(next.wrapping_add_signed(val), AutoLabelKind::Branch);
+        // contains, set => true
+        // !contains, set => false
+        // contains, !set => false
+        // !contains, !set => true
+        // xor truth table
+        if !self.status.contains(flag) ^ set {
+            self.pc = self.pc.wrapping_add_signed(val);
+            if next & 0xff00 != next.wrapping_add_signed(val) & 0xff00 {
+                return inst_info.cycles_extra + inst_info.cycles_extra2;
+            }
+            return inst_info.cycles_extra;
+        }
+        0
+    }
+
+    fn add_a(&mut self, val: u8) {
+        if self.decimal() {
+            return self.add_a_decimal(val);
+        }
+        self.add_binary(val);
+    }
+
+    fn add_binary(&mut self, val: u8) {
+        let sum = self.reg_a as u16 + val as u16 + self.status.contains(Flags::CARRY) as u16;
+        self.status.set(Flags::CARRY, sum > 0xFF);
+        let res = sum as u8;
+        // https://www.righto.com/2012/12/the-6502-overflow-flag-explained.html
+        let overflow = (val ^ res) & (res ^ self.reg_a) & 0x80 != 0;
+        self.status.set(Flags::OVERFLOW, overflow);
+        self.reg_a = res;
+        self.update_zero_negative(res);
+    }
+
+    fn sub_a(&mut self, val: u8) {
+        let a = self.reg_a;
+        let borrow = !self.status.contains(Flags::CARRY) as i16;
+        // On an NMOS 6502 the flags come out the same as in binary
+        self.add_binary(!val);
+        if self.decimal() {
+            let mut low = (a & 0x0f) as i16 - (val & 0x0f) as i16 - borrow;
+            let mut high = (a >> 4) as i16 - (val >> 4) as i16;
+            if low < 0 {
+                low -= 6;
+                high -= 1;
+            }
+            if high < 0 {
+                high -= 6;
+            }
+            self.reg_a = ((high << 4) | (low & 0x0f)) as u8;
+        }
+    }
+
+    /// Whether ADC and SBC work in binary coded decimal
+    fn decimal(&self) -> bool {
+        self.config.variant.has_decimal_mode() && self.status.contains(Flags::DECIMAL)
+    }
+
+    /// ADC in decimal mode, with the flags an NMOS 6502 sets. Z comes from
+    /// the binary sum, N and V from the sum before the high digit is adjusted.
+    /// See http://www.6502.org/tutorials/decimal_mode.html
+    fn add_a_decimal(&mut self, val: u8) {
+        let (a, carry) = (self.reg_a as u16, self.status.contains(Flags::CARRY) as u16);
+        let val16 = val as u16;
+        let mut low = (a & 0x0f) + (val16 & 0x0f) + carry;
+        if low > 9 {
+            low += 6;
+        }
+        let mut high = (a >> 4) + (val16 >> 4) + (low > 0x0f) as u16;
+        let unadjusted = ((high << 4) | (low & 0x0f)) as u8;
+        let binary = self.reg_a.wrapping_add(val).wrapping_add(carry as u8);
+        self.status.set(Flags::ZERO, binary == 0);
+        self.status.set(Flags::NEGATIVE, unadjusted & 0x80 != 0);
+        let overflow = (val ^ unadjusted) & (unadjusted ^ self.reg_a) & 0x80 != 0;
+        self.status.set(Flags::OVERFLOW, overflow);
+        if high > 9 {
+            high += 6;
+        }
+        self.status.set(Flags::CARRY, high > 0x0f);
+        self.reg_a = ((high << 4) | (low & 0x0f)) as u8;
+    }
+
+    fn push_stack(&mut self, val: u8) {
+        if self.stack_ptr == 0x00 {
+            self.memory.stack_wrapped(true);
+        }
+        self.memory.write(STACK_START + self.stack_ptr as u16, val);
+        self.stack_ptr = self.stack_ptr.wrapping_sub(1);
+    }
+    fn pop_stack(&mut self) -> u8 {
+        if self.stack_ptr == 0xff {
+            self.memory.stack_wrapped(false);
+        }
+        self.stack_ptr = self.stack_ptr.wrapping_add(1);
+        self.memory.read(STACK_START + self.stack_ptr as u16)
+    }
+    fn push_stack_u16(&mut self, val: u16) {
+        let low = val as u8;
+        let high = (val >> 8) as u8;
+        self.push_stack(high);
+        self.push_stack(low);
+    }
+    fn pop_stack_u16(&mut self) -> u16 {
+        let low = self.pop_stack();
+        let high = self.pop_stack();
+        ((high as u16) << 8) | (low as u16)
+    }
+}
@@ -0,0 +1,276 @@
+//! Running instructions a bus cycle at a time, see [`Cpu::cycle_stepped`].
+//! Each cycle is one read or write, dummy ones included, followed by
+//! `tick(1)`. The cycles are those of the NMOS 6502, see
+//! https://www.nesdev.org/6502_cpu.txt
+
+use super::{AddrMode, Bus, Cpu, Flags, Opcode, STACK_START};
+use crate::labels::AutoLabelKind;
+
+/// What an instruction does at the address it works out
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Access {
+    Read,
+    Write,
+    Modify,
+}
+
+impl<B: Bus> Cpu<B> {
+    fn read_cycle(&mut self, addr: u16) -> u8 {
+        let val = self.memory.read(addr);
+        self.memory.tick(1);
+        val
+    }
+    fn write_cycle(&mut self, addr: u16, val: u8) {
+        self.memory.write(addr, val);
+        self.memory.tick(1);
+    }
+    /// Read the byte at PC and move past it
+    fn fetch(&mut self) -> u8 {
+        let val = self.read_cycle(self.pc);
+        self.pc = self.pc.wrapping_add(1);
+        val
+    }
+    fn push_cycle(&mut self, val: u8) {
+        if self.stack_ptr == 0x00 {
+            self.memory.stack_wrapped(true);
+        }
+        self.write_cycle(STACK_START + self.stack_ptr as u16, val);
+        self.stack_ptr = self.stack_ptr.wrapping_sub(1);
+    }
+    fn pull_cycle(&mut self) -> u8 {
+        if self.stack_ptr == 0xff {
+            self.memory.stack_wrapped(false);
+        }
+        self.stack_ptr = self.stack_ptr.wrapping_add(1);
+        self.read_cycle(STACK_START + self.stack_ptr as u16)
+    }
+    /// The read before a pull, of the top of the stack
+    fn stack_dummy_read(&mut self) {
+        self.read_cycle(STACK_START + self.stack_ptr as u16);
+    }
+
+    /// Run the instruction at PC after its opcode fetch, which already took
+    /// its cycle
+    pub(super) fn execute_cycles(&mut self, opcode: Opcode, addr_mode: AddrMode) {
+        let start = self.pc;
+        self.pc = self.pc.wrapping_add(1);
+        match opcode {
+            Opcode::BCC => self.branch_cycles(Flags::CARRY, false),
+            Opcode::BCS => self.branch_cycles(Flags::CARRY, true),
+            Opcode::BEQ => self.branch_cycles(Flags::ZERO, true),
+            Opcode::BMI => self.branch_cycles(Flags::NEGATIVE, true),
+            Opcode::BNE => self.branch_cycles(Flags::ZERO, false),
+            Opcode::BPL => self.branch_cycles(Flags::NEGATIVE, false),
+            Opcode::BVC => self.branch_cycles(Flags::OVERFLOW, false),
+            Opcode::BVS => self.branch_cycles(Flags::OVERFLOW, true),
+            Opcode::BRK => {
+                // Like whole instructions, BRK stops the CPU where it is
+                self.read_cycle(self.pc);
+                self.pc = start;
+                self.memory.tick(5);
+                self.status.insert(Flags::BREAK);
+                self.brk = true;
+            }
+            Opcode::JMP => {
+                let low = self.fetch();
+                let high = self.read_cycle(self.pc);
+                let addr = u16::from_le_bytes([low, high]);
+                self.pc = match addr_mode {
+                    AddrMode::Absolute => addr,
+                    // The high byte doesn't carry into the next page
+                    _ => {
+                        let low = self.read_cycle(addr);
+                        let high =
+                            self.read_cycle((addr & 0xff00) | (addr as u8).wrapping_add(1) as u16);
+                        u16::from_le_bytes([low, high])
+                    }
+                };
+            }
+            Opcode::JSR => {
+                let low = self.fetch();
+                self.stack_dummy_read();
+                let [pc_low, pc_high] = self.pc.to_le_bytes();
+                self.push_cycle(pc_high);
+                self.push_cycle(pc_low);
+                let high = self.read_cycle(self.pc);
+                let fn_addr = u16::from_le_bytes([low, high]);
+                self.pc = start;
+                self.jumped_to_subroutine(fn_addr);
+                self.pc = fn_addr;
+            }
+            Opcode::RTS => {
+                self.read_cycle(self.pc);
+                self.stack_dummy_read();
+                let low = self.pull_cycle();
+                let high = self.pull_cycle();
+                self.pc = u16::from_le_bytes([low, high]);
+                self.fetch();
+            }
+            Opcode::RTI => {
+                self.read_cycle(self.pc);
+                self.stack_dummy_read();
+                let status = self.pull_cycle();
+                self.pull_status(status);
+                let low = self.pull_cycle();
+                let high = self.pull_cycle();
+                self.pc = u16::from_le_bytes([low, high]);
+            }
+            Opcode::PHA | Opcode::PHP => {
+                self.read_cycle(self.pc);
+                let val = match opcode {
+                    Opcode::PHA => self.reg_a,
+                    _ => self.pushed_status(),
+                };
+                self.push_cycle(val);
+            }
+            Opcode::PLA | Opcode::PLP => {
+                self.read_cycle(self.pc);
+                self.stack_dummy_read();
+                let val = self.pull_cycle();
+                match opcode {
+                    Opcode::PLA => {
+                        self.reg_a = val;
+                        self.update_zero_negative(val);
+                    }
+                    _ => self.pull_status(val),
+                }
+            }
+            _ => match addr_mode {
+                AddrMode::Implicit => {
+                    self.read_cycle(self.pc);
+                    self.register_op(opcode);
+                }
+                AddrMode::Accumulator => {
+                    self.read_cycle(self.pc);
+                    self.reg_a = self.modify_op(opcode, self.reg_a);
+                }
+                AddrMode::Immediate => {
+                    let val = self.fetch();
+                    self.read_op(opcode, val);
+                }
+                addr_mode => {
+                    let access = match opcode {
+                        Opcode::STA | Opcode::STX | Opcode::STY => Access::Write,
+                        Opcode::ASL
+                        | Opcode::LSR
+                        | Opcode::ROL
+                        | Opcode::ROR
+                        | Opcode::INC
+                        | Opcode::DEC => Access::Modify,
+                        _ => Access::Read,
+                    };
+                    let addr = self.address_cycles(addr_mode, access);
+                    match access {
+                        Access::Read => {
+                            let val = self.read_cycle(addr);
+                            self.read_op(opcode, val);
+                        }
+                        Access::Write => self.write_cycle(addr, self.store_value(opcode)),
+                        Access::Modify => {
+                            // The old value is written back while the new one is worked out
+                            let val = self.read_cycle(addr);
+                            self.write_cycle(addr, val);
+                            let new_val = self.modify_op(opcode, val);
+                            self.write_cycle(addr, new_val);
+                        }
+                    }
+                }
+            },
+        }
+    }
+
+    /// Fetch the operands and pointers of a memory instruction, returning
+    /// the address it works on
+    fn address_cycles(&mut self, addr_mode: AddrMode, access: Access) -> u16 {
+        match addr_mode {
+            AddrMode::ZeroPage => self.fetch() as u16,
+            AddrMode::ZeroPageX | AddrMode::ZeroPageY => {
+                let base = self.fetch();
+                self.read_cycle(base as u16);
+                let index = match addr_mode {
+                    AddrMode::ZeroPageX => self.reg_x,
+                    _ => self.reg_y,
+                };
+                base.wrapping_add(index) as u16
+            }
+            AddrMode::Absolute => {
+                let low = self.fetch();
+                let high = self.fetch();
+                u16::from_le_bytes([low, high])
+            }
+            AddrMode::AbsoluteX | AddrMode::AbsoluteY => {
+                let low = self.fetch();
+                let high = self.fetch();
+                let index = match addr_mode {
+                    AddrMode::AbsoluteX => self.reg_x,
+                    _ => self.reg_y,
+                };
+                self.indexed_cycles(u16::from_le_bytes([low, high]), index, access)
+            }
+            AddrMode::IndexedIndirect => {
+                let pointer = self.fetch();
+                self.read_cycle(pointer as u16);
+                let pointer = pointer.wrapping_add(self.reg_x);
+                let low = self.read_cycle(pointer as u16);
+                let high = self.read_cycle(pointer.wrapping_add(1) as u16);
+                u16::from_le_bytes([low, high])
+            }
+            AddrMode::IndirectIndexed => {
+                let pointer = self.fetch();
+                let low = self.read_cycle(pointer as u16);
+                let high = self.read_cycle(pointer.wrapping_add(1) as u16);
+                self.indexed_cycles(u16::from_le_bytes([low, high]), self.reg_y, access)
+            }
+            AddrMode::Implicit
+            | AddrMode::Accumulator
+            | AddrMode::Immediate
+            | AddrMode::Relative
+            | AddrMode::Indirect => {
+                unreachable!("{addr_mode:?} isn't a memory operand")
+            }
+        }
+    }
+
+    /// `base` plus `index`. The CPU first reads with the low byte added but
+    /// not the carry, and reads again once the high byte is fixed. Reads
+    /// that didn't cross a page skip that, writes never do.
+    fn indexed_cycles(&mut self, base: u16, index: u8, access: Access) -> u16 {
+        let addr = base.wrapping_add(index as u16);
+        let uncorrected = (base & 0xff00) | (addr & 0x00ff);
+        if access != Access::Read || uncorrected != addr {
+            self.read_cycle(uncorrected);
+        }
+        addr
+    }
+
+    fn branch_cycles(&mut self, flag: Flags, set: bool) {
+        let offset = self.fetch() as i8 as i16;
+        let next = self.pc;
+        let target = next.wrapping_add_signed(offset);
+        self.memory.code_at(target, AutoLabelKind::Branch);
+        if self.status.contains(flag) == set {
+            self.read_cycle(next);
+            if next & 0xff00 != target & 0xff00 {
+                self.read_cycle((next & 0xff00) | (target & 0x00ff));
+            }
+            self.pc = target;
+        }
+    }
+
+    /// Push PC and status and read the `vector`, 7 cycles like a BRK
+    pub(super) fn interrupt_cycles(&mut self, vector: u16) {
+        self.read_cycle(self.pc);
+        self.read_cycle(self.pc);
+        let [low, high] = self.pc.to_le_bytes();
+        self.push_cycle(high);
+        self.push_cycle(low);
+        let mut status = self.status.clone();
+        status.remove(Flags::BREAK);
+        status.insert(Flags::BREAK2);
+        self.push_cycle(status.bits());
+        self.status.insert(Flags::INTERRUPTDISABLE);
+        let low = self.read_cycle(vector);
+        let high = self.read_cycle(vector.wrapping_add(1));
+        self.pc = u16::from_le_bytes([low, high]);
+    }
+}
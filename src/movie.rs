@@ -0,0 +1,400 @@
+//! Input movies, the controller input for every frame from a starting state.
+//!
+//! Movies can be edited anywhere, the way a piano roll works: frames can be
+//! inserted, deleted or changed, and the greenzone of states taken while
+//! playing lets [`Movie::seek`] jump back to any frame before the first
+//! edit instead of replaying from the start. The greenzone keeps a state
+//! every few frames within a memory budget, see [`Greenzone`].
+//!
+//! Movies also keep the console's state hash after each frame, so
+//! [`Movie::verify`] can replay one and find the first frame that comes out
+//! differently, e.g. to check a TAS still syncs after an emulator change.
+
+use std::collections::BTreeMap;
+
+use crate::console::Console;
+use crate::joypad::Buttons;
+use crate::savestate::{SaveState, StateStream, stream};
+
+const MAGIC: [u8; 4] = *b"NESM";
+/// Bumped whenever the file layout changes. Version 1 had no state hashes.
+const VERSION: u8 = 2;
+
+/// Controller 1 and 2 for one frame
+pub type FrameInput = [Buttons; 2];
+
+/// Which states a [`Greenzone`] drops when it goes over budget
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Eviction {
+    /// Earliest frames first, for when only recent frames get edited
+    Oldest,
+    /// Every other state where they are closest together, keeping them spread
+    /// evenly so seeking anywhere takes about as long
+    Thin,
+}
+
+/// States at the start of frames played since their inputs last changed
+pub struct Greenzone {
+    states: BTreeMap<usize, SaveState>,
+    /// Frames up to here played with the current input
+    end: usize,
+    /// Bytes held by `states`
+    bytes: usize,
+    /// Keep a state every `interval` frames, at least 1
+    pub interval: usize,
+    /// Bytes of states to keep at most, the newest state is kept regardless
+    pub budget: usize,
+    pub eviction: Eviction,
+}
+
+impl Default for Greenzone {
+    fn default() -> Self {
+        Greenzone {
+            states: BTreeMap::new(),
+            end: 0,
+            bytes: 0,
+            interval: 1,
+            budget: 64 << 20,
+            eviction: Eviction::Thin,
+        }
+    }
+}
+
+impl Greenzone {
+    /// Number of states kept
+    pub fn len(&self) -> usize {
+        self.states.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.states.is_empty()
+    }
+
+    /// Memory used by the kept states
+    pub fn bytes(&self) -> usize {
+        self.bytes
+    }
+
+    /// Frames with a state, oldest first
+    pub fn frames(&self) -> impl Iterator<Item = usize> + '_ {
+        self.states.keys().copied()
+    }
+
+    /// The latest state at or before `frame`
+    fn closest(&self, frame: usize) -> Option<(usize, &SaveState)> {
+        let (&from, state) = self.states.range(..=frame).next_back()?;
+        Some((from, state))
+    }
+
+    /// The console just played up to `frame` from a state in the greenzone
+    fn played(&mut self, frame: usize, state: impl FnOnce() -> SaveState) {
+        self.end = self.end.max(frame);
+        if frame.is_multiple_of(self.interval.max(1)) {
+            let state = state();
+            self.bytes += state.data.len();
+            if let Some(old) = self.states.insert(frame, state) {
+                self.bytes -= old.data.len();
+            }
+            self.evict();
+        }
+    }
+
+    fn evict(&mut self) {
+        while self.bytes > self.budget && self.states.len() > 1 {
+            let frame = match self.eviction {
+                Eviction::Oldest => *self.states.keys().next().unwrap(),
+                Eviction::Thin => self.densest(),
+            };
+            let state = self.states.remove(&frame).unwrap();
+            self.bytes -= state.data.len();
+        }
+    }
+
+    /// The state with the least frames between its neighbours, the oldest on
+    /// ties. Never the newest, frame 0 counts as a neighbour.
+    fn densest(&self) -> usize {
+        let frames: Vec<usize> = self.states.keys().copied().collect();
+        let mut best = (usize::MAX, frames[0]);
+        for (i, &frame) in frames[..frames.len() - 1].iter().enumerate() {
+            let prev = if i == 0 { 0 } else { frames[i - 1] };
+            let gap = frames[i + 1] - prev;
+            if gap < best.0 {
+                best = (gap, frame);
+            }
+        }
+        best.1
+    }
+
+    /// States after `frame` were played with the old input
+    fn invalidate(&mut self, frame: usize) {
+        for (_, state) in self.states.split_off(&(frame + 1)) {
+            self.bytes -= state.data.len();
+        }
+        self.end = self.end.min(frame);
+    }
+}
+
+/// What [`Movie::verify`] found
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Verification {
+    /// Frames played
+    pub frames: usize,
+    /// [`Console::state_hash`] after the last frame
+    pub hash: u32,
+    /// The first frame whose state hash differs from the recorded one
+    pub desync: Option<usize>,
+}
+
+pub struct Movie {
+    /// Where frame 0 starts from, usually power on
+    start: SaveState,
+    inputs: Vec<FrameInput>,
+    /// State hash after each frame, up to the first frame not played since
+    /// its input changed
+    hashes: Vec<u32>,
+    greenzone: Greenzone,
+    /// The next frame the console will play
+    position: usize,
+}
+
+impl Movie {
+    /// An empty movie starting from the console's current state
+    pub fn new(console: &mut Console) -> Self {
+        Movie {
+            start: console.save_state(None),
+            inputs: Vec::new(),
+            hashes: Vec::new(),
+            greenzone: Greenzone::default(),
+            position: 0,
+        }
+    }
+
+    /// [`crate::rom::Rom::hash`] of the game the movie was recorded on
+    pub fn rom_hash(&self) -> u32 {
+        self.start.rom_hash
+    }
+
+    pub fn len(&self) -> usize {
+        self.inputs.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.inputs.is_empty()
+    }
+
+    pub fn inputs(&self) -> &[FrameInput] {
+        &self.inputs
+    }
+
+    pub fn input(&self, frame: usize) -> Option<FrameInput> {
+        self.inputs.get(frame).copied()
+    }
+
+    /// The console's state hash recorded after `frame`, if it was played
+    /// since its input last changed
+    pub fn state_hash(&self, frame: usize) -> Option<u32> {
+        self.hashes.get(frame).copied()
+    }
+
+    /// The next frame [`Movie::play`] or [`Movie::record`] runs. Edits before
+    /// it don't change the console, [`Movie::seek`] back to see them.
+    pub fn position(&self) -> usize {
+        self.position
+    }
+
+    /// Whether `frame` was played since its input or any before it changed
+    pub fn is_green(&self, frame: usize) -> bool {
+        frame <= self.greenzone.end
+    }
+
+    pub fn greenzone(&self) -> &Greenzone {
+        &self.greenzone
+    }
+
+    /// For changing the greenzone's interval, budget and eviction
+    pub fn greenzone_mut(&mut self) -> &mut Greenzone {
+        &mut self.greenzone
+    }
+
+    /// Set what `player` holds on `frame`, ignored if the movie has no such
+    /// frame or player
+    pub fn set_input(&mut self, frame: usize, player: usize, buttons: Buttons) {
+        if let Some(held) = self.held_mut(frame, player) {
+            *held = buttons;
+            self.invalidate(frame);
+        }
+    }
+
+    /// Flip `buttons` for `player` on `frame`
+    pub fn toggle(&mut self, frame: usize, player: usize, buttons: Buttons) {
+        if let Some(held) = self.held_mut(frame, player) {
+            held.toggle(buttons);
+            self.invalidate(frame);
+        }
+    }
+
+    fn held_mut(&mut self, frame: usize, player: usize) -> Option<&mut Buttons> {
+        self.inputs.get_mut(frame)?.get_mut(player)
+    }
+
+    /// Insert `count` frames with nothing pressed before `frame`, at the end
+    /// if `frame` is past it
+    pub fn insert_frames(&mut self, frame: usize, count: usize) {
+        let frame = frame.min(self.inputs.len());
+        self.inputs.splice(
+            frame..frame,
+            std::iter::repeat_n(FrameInput::default(), count),
+        );
+        self.invalidate(frame);
+    }
+
+    /// Delete up to `count` frames from `frame` on, stopping at the end
+    pub fn delete_frames(&mut self, frame: usize, count: usize) {
+        let end = frame.saturating_add(count).min(self.inputs.len());
+        if frame < end {
+            self.inputs.drain(frame..end);
+            self.invalidate(frame);
+        }
+    }
+
+    /// Drop every frame from `len` on
+    pub fn truncate(&mut self, len: usize) {
+        self.inputs.truncate(len);
+        self.invalidate(len);
+    }
+
+    fn invalidate(&mut self, frame: usize) {
+        self.greenzone.invalidate(frame);
+        self.hashes.truncate(frame);
+    }
+
+    /// Run the frame at the current position, `false` at the end of the movie
+    pub fn play(&mut self, console: &mut Console) -> bool {
+        let Some(input) = self.input(self.position) else {
+            return false;
+        };
+        self.run(console, input);
+        true
+    }
+
+    /// Run a frame with `input`, replacing whatever the movie had from here on
+    pub fn record(&mut self, console: &mut Console, input: FrameInput) {
+        self.truncate(self.position);
+        self.inputs.push(input);
+        self.run(console, input);
+    }
+
+    fn run(&mut self, console: &mut Console, input: FrameInput) {
+        run_frame(console, input);
+        let frame = self.position;
+        self.position += 1;
+        if frame < self.hashes.len() {
+            self.hashes[frame] = console.state_hash();
+        } else if frame == self.hashes.len() {
+            self.hashes.push(console.state_hash());
+        }
+        // Past an edit the console is stale until it seeks back
+        if self.position - 1 <= self.greenzone.end {
+            self.greenzone
+                .played(self.position, || console.save_state(None));
+        }
+    }
+
+    /// Put the console at the start of `frame`, replaying from the closest
+    /// greenzone state before it. Fails for frames past the end of the
+    /// movie, or if the console is running another game.
+    pub fn seek(&mut self, console: &mut Console, frame: usize) -> Result<(), String> {
+        if frame > self.len() {
+            return Err(format!("frame {frame} is past the end of the movie"));
+        }
+        let (from, state) = self.greenzone.closest(frame).unwrap_or((0, &self.start));
+        console.load_state(state)?;
+        self.position = from;
+        while self.position < frame {
+            self.play(console);
+        }
+        Ok(())
+    }
+
+    /// Go back to `frame` and drop everything after it, so recording carries on from there
+    pub fn truncate_and_resume(
+        &mut self,
+        console: &mut Console,
+        frame: usize,
+    ) -> Result<(), String> {
+        self.seek(console, frame)?;
+        self.truncate(frame);
+        Ok(())
+    }
+
+    /// Play the whole movie from its start, comparing the console's state
+    /// hash after each frame with the recorded one. Leaves the movie's
+    /// position and greenzone alone. Fails if the console is running another
+    /// game.
+    pub fn verify(&self, console: &mut Console) -> Result<Verification, String> {
+        console.load_state(&self.start)?;
+        let mut verification = Verification {
+            frames: 0,
+            hash: console.state_hash(),
+            desync: None,
+        };
+        for (frame, &input) in self.inputs.iter().enumerate() {
+            run_frame(console, input);
+            verification.frames += 1;
+            verification.hash = console.state_hash();
+            if verification.desync.is_none()
+                && self
+                    .state_hash(frame)
+                    .is_some_and(|hash| hash != verification.hash)
+            {
+                verification.desync = Some(frame);
+            }
+        }
+        Ok(verification)
+    }
+
+    /// The movie file, without the greenzone
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut s = StateStream::saving();
+        s.bytes(&mut MAGIC.clone());
+        s.bytes(&mut [VERSION]);
+        let (mut start, mut inputs) = (self.start.clone(), self.inputs.clone());
+        let mut hashes = self.hashes.clone();
+        stream!(&mut s, start, inputs, hashes);
+        s.finish().expect("saving can't fail")
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, String> {
+        let mut s = StateStream::loading(bytes.to_vec());
+        let mut header = [0; 5];
+        s.bytes(&mut header);
+        if header[..4] != MAGIC {
+            return Err(String::from("not a movie"));
+        }
+        if !(1..=VERSION).contains(&header[4]) {
+            return Err(format!("movie version {} isn't supported", header[4]));
+        }
+        let mut start = SaveState::default();
+        let mut inputs = Vec::new();
+        let mut hashes = Vec::new();
+        stream!(&mut s, start, inputs);
+        if header[4] >= 2 {
+            stream!(&mut s, hashes);
+        }
+        s.finish()?;
+        Ok(Movie {
+            start,
+            inputs,
+            hashes,
+            greenzone: Greenzone::default(),
+            position: 0,
+        })
+    }
+}
+
+fn run_frame(console: &mut Console, input: FrameInput) {
+    for (joypad, buttons) in console.bus_mut().joypads.iter_mut().zip(input) {
+        joypad.buttons = buttons;
+    }
+    console.run_frame();
+}
@@ -0,0 +1,391 @@
+//! Two player netplay. Both sides run the same game and only exchange
+//! controller inputs, so the consoles stay identical as long as they agree
+//! on every frame's input.
+//!
+//! [`Mode::Lockstep`] waits for the other side's input before running a frame.
+//! [`Mode::Rollback`] runs ahead with a guess (the last input received) and when
+//! the real input turns out different, loads the savestate from before that
+//! frame and runs the frames again, like GGPO.
+//!
+//! Every `hash_interval` frames both sides send a hash of the state, and a
+//! mismatch stops the session with an error instead of carrying on desynced.
+
+use std::collections::btree_map::Entry;
+use std::collections::{BTreeMap, VecDeque};
+use std::io::{ErrorKind, Read, Write};
+use std::net::{TcpListener, TcpStream, ToSocketAddrs};
+
+use crate::console::Console;
+use crate::error::NetplayError;
+use crate::joypad::Buttons;
+use crate::savestate::SaveState;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Message {
+    /// Buttons the sender's player holds during `frame`
+    Input { frame: u64, buttons: Buttons },
+    /// Hash of the sender's state at the start of `frame`
+    Hash { frame: u64, hash: u32 },
+}
+
+const INPUT_TAG: u8 = 0;
+const HASH_TAG: u8 = 1;
+
+impl Message {
+    pub fn encode(&self) -> Vec<u8> {
+        let (tag, frame, payload) = match *self {
+            Message::Input { frame, buttons } => (INPUT_TAG, frame, vec![buttons.bits()]),
+            Message::Hash { frame, hash } => (HASH_TAG, frame, hash.to_le_bytes().to_vec()),
+        };
+        let mut bytes = vec![tag];
+        bytes.extend_from_slice(&frame.to_le_bytes());
+        bytes.extend_from_slice(&payload);
+        bytes
+    }
+
+    /// Decode the message at the start of `bytes`, returns it and its length.
+    /// `Ok(None)` if more bytes are needed.
+    pub fn decode(bytes: &[u8]) -> Result<Option<(Message, usize)>, NetplayError> {
+        let Some(&tag) = bytes.first() else {
+            return Ok(None);
+        };
+        let len = match tag {
+            INPUT_TAG => 10,
+            HASH_TAG => 13,
+            _ => return Err(NetplayError::UnknownMessage(tag)),
+        };
+        if bytes.len() < len {
+            return Ok(None);
+        }
+        let frame = u64::from_le_bytes(bytes[1..9].try_into().unwrap());
+        let message = match tag {
+            INPUT_TAG => Message::Input {
+                frame,
+                buttons: Buttons::from_bits_retain(bytes[9]),
+            },
+            _ => Message::Hash {
+                frame,
+                hash: u32::from_le_bytes(bytes[9..13].try_into().unwrap()),
+            },
+        };
+        Ok(Some((message, len)))
+    }
+}
+
+/// Delivers messages to the other side, in order and without losing any
+pub trait Transport {
+    fn send(&mut self, message: Message) -> Result<(), NetplayError>;
+    /// The next message received, without blocking
+    fn recv(&mut self) -> Result<Option<Message>, NetplayError>;
+}
+
+pub struct TcpTransport {
+    stream: TcpStream,
+    received: Vec<u8>,
+}
+
+impl TcpTransport {
+    /// Wait for the other side to connect
+    pub fn host(addr: impl ToSocketAddrs) -> Result<Self, NetplayError> {
+        let listener = TcpListener::bind(addr)?;
+        let (stream, _) = listener.accept()?;
+        Self::new(stream)
+    }
+
+    pub fn connect(addr: impl ToSocketAddrs) -> Result<Self, NetplayError> {
+        Self::new(TcpStream::connect(addr)?)
+    }
+
+    fn new(stream: TcpStream) -> Result<Self, NetplayError> {
+        // Inputs are tiny and late ones stall the game, don't batch them
+        stream.set_nodelay(true)?;
+        stream.set_nonblocking(true)?;
+        Ok(TcpTransport {
+            stream,
+            received: Vec::new(),
+        })
+    }
+}
+
+impl Transport for TcpTransport {
+    fn send(&mut self, message: Message) -> Result<(), NetplayError> {
+        let bytes = message.encode();
+        let mut written = 0;
+        while written < bytes.len() {
+            match self.stream.write(&bytes[written..]) {
+                Ok(0) => return Err(NetplayError::Closed),
+                Ok(n) => written += n,
+                Err(e) if e.kind() == ErrorKind::WouldBlock => std::thread::yield_now(),
+                Err(e) => return Err(e.into()),
+            }
+        }
+        Ok(())
+    }
+
+    fn recv(&mut self) -> Result<Option<Message>, NetplayError> {
+        let mut buf = [0; 256];
+        loop {
+            match self.stream.read(&mut buf) {
+                Ok(0) => return Err(NetplayError::Closed),
+                Ok(n) => self.received.extend_from_slice(&buf[..n]),
+                Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+                Err(e) => return Err(e.into()),
+            }
+        }
+        let decoded = Message::decode(&self.received)?;
+        if let Some((_, len)) = decoded {
+            self.received.drain(..len);
+        }
+        Ok(decoded.map(|(message, _)| message))
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Mode {
+    /// Local inputs are applied `delay` frames after they are made, which
+    /// hides up to that much latency before the game has to wait
+    Lockstep { delay: u64 },
+    /// Predict the other side's input, rolling back at most `max_frames`
+    Rollback { delay: u64, max_frames: u64 },
+}
+
+/// One side of a netplay session
+pub struct Session<T: Transport> {
+    transport: T,
+    mode: Mode,
+    /// Controller port of this side's player, the other side has the other one
+    local_player: usize,
+    /// Frames between state hash checks, 0 to never check
+    pub hash_interval: u64,
+    /// Next frame to run
+    frame: u64,
+    local_inputs: BTreeMap<u64, Buttons>,
+    remote_inputs: BTreeMap<u64, Buttons>,
+    /// Remote inputs guessed for frames that ran before the real one arrived
+    predicted: BTreeMap<u64, Buttons>,
+    /// First frame without the remote input
+    confirmed: u64,
+    /// States at the start of every frame since `confirmed`, when rolling back
+    snapshots: VecDeque<(u64, SaveState)>,
+    rollback_frames: u64,
+    local_hashes: BTreeMap<u64, u32>,
+    remote_hashes: BTreeMap<u64, u32>,
+}
+
+impl<T: Transport> Session<T> {
+    pub fn new(transport: T, mode: Mode, local_player: usize) -> Self {
+        assert!(local_player < 2, "netplay is for players 0 and 1");
+        Session {
+            transport,
+            mode,
+            local_player,
+            hash_interval: 60,
+            frame: 0,
+            local_inputs: BTreeMap::new(),
+            remote_inputs: BTreeMap::new(),
+            predicted: BTreeMap::new(),
+            confirmed: 0,
+            snapshots: VecDeque::new(),
+            rollback_frames: 0,
+            local_hashes: BTreeMap::new(),
+            remote_hashes: BTreeMap::new(),
+        }
+    }
+
+    /// Next frame to run, counted from the start of the session
+    pub fn frame(&self) -> u64 {
+        self.frame
+    }
+
+    fn delay(&self) -> u64 {
+        match self.mode {
+            Mode::Lockstep { delay } | Mode::Rollback { delay, .. } => delay,
+        }
+    }
+
+    /// Frames run again because of rollbacks so far
+    pub fn rollback_frames(&self) -> u64 {
+        self.rollback_frames
+    }
+
+    /// Run the next frame with `local` held by this side's player, unless
+    /// the other side is too far behind. Call once per frame, with the same
+    /// input again if it returns `Ok(false)` because it had to wait.
+    /// Fails if the connection drops or the consoles desync.
+    pub fn advance(&mut self, console: &mut Console, local: Buttons) -> Result<bool, NetplayError> {
+        let input_frame = self.frame + self.delay();
+        if let Entry::Vacant(entry) = self.local_inputs.entry(input_frame) {
+            entry.insert(local);
+            self.transport.send(Message::Input {
+                frame: input_frame,
+                buttons: local,
+            })?;
+        }
+        self.sync(console)?;
+        let ran = match self.mode {
+            Mode::Lockstep { .. } => self.frame < self.confirmed,
+            Mode::Rollback { max_frames, .. } => {
+                self.frame.saturating_sub(self.confirmed) < max_frames
+            }
+        };
+        if !ran {
+            return Ok(false);
+        }
+        self.run_frame(console);
+        match self.mode {
+            Mode::Lockstep { .. } => {
+                if self.hash_due(self.frame) {
+                    self.record_hash(self.frame, console.state_hash());
+                }
+            }
+            Mode::Rollback { .. } => {
+                self.snapshots
+                    .push_back((self.frame, console.save_state(None)));
+            }
+        }
+        Ok(true)
+    }
+
+    /// Handle what the other side sent without running a new frame,
+    /// which can still roll back and correct frames that already ran
+    pub fn sync(&mut self, console: &mut Console) -> Result<(), NetplayError> {
+        let mispredicted = self.receive()?;
+        if let Mode::Rollback { .. } = self.mode {
+            if self.snapshots.is_empty() {
+                self.snapshots
+                    .push_back((self.frame, console.save_state(None)));
+            }
+            if let Some(from) = mispredicted {
+                self.roll_back(console, from)?;
+            }
+            self.prune_snapshots();
+        }
+        // Inputs from before the last frame that can be rolled back to aren't
+        // needed, except the last remote one to base guesses on
+        let keep = self.confirmed.min(self.frame).saturating_sub(1);
+        self.local_inputs = self.local_inputs.split_off(&keep);
+        self.remote_inputs = self.remote_inputs.split_off(&keep);
+        self.check_hashes()
+    }
+
+    /// Take in everything received, returns the first frame that ran with a wrong guess
+    fn receive(&mut self) -> Result<Option<u64>, NetplayError> {
+        let mut mispredicted: Option<u64> = None;
+        while let Some(message) = self.transport.recv()? {
+            match message {
+                Message::Input { frame, buttons } => {
+                    if self
+                        .predicted
+                        .remove(&frame)
+                        .is_some_and(|guess| guess != buttons)
+                    {
+                        mispredicted = Some(mispredicted.map_or(frame, |first| first.min(frame)));
+                    }
+                    self.remote_inputs.insert(frame, buttons);
+                }
+                Message::Hash { frame, hash } => {
+                    self.remote_hashes.insert(frame, hash);
+                }
+            }
+        }
+        // Nobody has input for the frames before the delay kicks in
+        while self.confirmed < self.delay() || self.remote_inputs.contains_key(&self.confirmed) {
+            self.confirmed += 1;
+        }
+        Ok(mispredicted)
+    }
+
+    fn inputs(&mut self, frame: u64) -> [Buttons; 2] {
+        let local = self.local_inputs.get(&frame).copied().unwrap_or_default();
+        let remote = match self.remote_inputs.get(&frame) {
+            Some(&buttons) => buttons,
+            None if frame < self.delay() => Buttons::empty(),
+            None => {
+                // Guess that the other player is still holding what they last held
+                let guess = self
+                    .remote_inputs
+                    .range(..frame)
+                    .next_back()
+                    .map(|(_, &buttons)| buttons)
+                    .unwrap_or_default();
+                self.predicted.insert(frame, guess);
+                guess
+            }
+        };
+        let mut inputs = [remote; 2];
+        inputs[self.local_player] = local;
+        inputs
+    }
+
+    fn run_frame(&mut self, console: &mut Console) {
+        let inputs = self.inputs(self.frame);
+        for (joypad, buttons) in console.bus_mut().joypads.iter_mut().zip(inputs) {
+            joypad.buttons = buttons;
+        }
+        console.run_frame();
+        self.frame += 1;
+    }
+
+    /// Load the state from before `from` and run the frames since again
+    fn roll_back(&mut self, console: &mut Console, from: u64) -> Result<(), NetplayError> {
+        let index = self
+            .snapshots
+            .iter()
+            .position(|(frame, _)| *frame == from)
+            .ok_or(NetplayError::NoSnapshot { frame: from })?;
+        console.load_state(&self.snapshots[index].1)?;
+        self.snapshots.truncate(index + 1);
+        let now = self.frame;
+        self.frame = from;
+        self.rollback_frames += now - from;
+        while self.frame < now {
+            self.predicted.remove(&self.frame);
+            self.run_frame(console);
+            self.snapshots
+                .push_back((self.frame, console.save_state(None)));
+        }
+        Ok(())
+    }
+
+    /// Drop states that can't be rolled back to anymore, hashing them first.
+    /// Mispredictions can only happen from `confirmed` on.
+    fn prune_snapshots(&mut self) {
+        let base = self.confirmed.min(self.frame);
+        while self
+            .snapshots
+            .front()
+            .is_some_and(|(frame, _)| *frame < base)
+        {
+            let (frame, state) = self.snapshots.pop_front().unwrap();
+            if self.hash_due(frame) {
+                self.record_hash(frame, state.state_hash());
+            }
+        }
+    }
+
+    fn hash_due(&self, frame: u64) -> bool {
+        self.hash_interval > 0 && frame.is_multiple_of(self.hash_interval)
+    }
+
+    /// Hash of the confirmed state at the start of `frame`
+    fn record_hash(&mut self, frame: u64, hash: u32) {
+        self.local_hashes.insert(frame, hash);
+        // Sending can only fail if the connection is gone, which receiving reports
+        let _ = self.transport.send(Message::Hash { frame, hash });
+    }
+
+    fn check_hashes(&mut self) -> Result<(), NetplayError> {
+        let both: Vec<u64> = self
+            .local_hashes
+            .keys()
+            .filter(|frame| self.remote_hashes.contains_key(frame))
+            .copied()
+            .collect();
+        for frame in both {
+            if self.local_hashes.remove(&frame) != self.remote_hashes.remove(&frame) {
+                return Err(NetplayError::Desync { frame });
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,154 @@
+//! On-screen display: short messages and the frame rate, drawn over the
+//! picture in a small built-in font. F10 toggles the frame rate.
+
+use std::time::{Duration, Instant};
+
+/// How long a message stays up
+const MESSAGE_TIME: Duration = Duration::from_secs(2);
+/// Font pixels are drawn this many screen pixels wide
+const SCALE: usize = 2;
+const MARGIN: usize = 4;
+const LINE_HEIGHT: usize = (5 + 2) * SCALE;
+
+pub(crate) struct Osd {
+    /// Newest last
+    messages: Vec<(String, Instant)>,
+    pub(crate) show_fps: bool,
+    fps: f64,
+    frames: u32,
+    counted_since: Instant,
+}
+
+impl Osd {
+    pub(crate) fn new() -> Self {
+        Osd {
+            messages: Vec::new(),
+            show_fps: false,
+            fps: 0.0,
+            frames: 0,
+            counted_since: Instant::now(),
+        }
+    }
+
+    /// Show `text` for a couple of seconds
+    pub(crate) fn show(&mut self, text: impl Into<String>) {
+        self.messages.push((text.into(), Instant::now()));
+    }
+
+    /// Count a presented frame, the rate is updated every second
+    pub(crate) fn frame(&mut self) {
+        self.frames += 1;
+        let elapsed = self.counted_since.elapsed();
+        if elapsed >= Duration::from_secs(1) {
+            self.fps = self.frames as f64 / elapsed.as_secs_f64();
+            self.frames = 0;
+            self.counted_since = Instant::now();
+        }
+    }
+
+    /// Messages from the bottom of a frame `stride` pixels wide, the frame
+    /// rate in the top right corner
+    pub(crate) fn draw(&mut self, buffer: &mut [u32], stride: usize) {
+        self.messages
+            .retain(|(_, shown)| shown.elapsed() < MESSAGE_TIME);
+        let height = buffer.len() / stride;
+        for (i, (text, _)) in self.messages.iter().rev().enumerate() {
+            let Some(y) = height.checked_sub(MARGIN + (i + 1) * LINE_HEIGHT) else {
+                break;
+            };
+            draw_text(buffer, stride, MARGIN, y, text);
+        }
+        if self.show_fps {
+            let text = format!("{:.0} FPS", self.fps);
+            let x = stride.saturating_sub(MARGIN + text_width(&text));
+            draw_text(buffer, stride, x, MARGIN, &text);
+        }
+    }
+}
+
+fn text_width(text: &str) -> usize {
+    text.chars().count() * 4 * SCALE
+}
+
+/// White text with a dark backing so it reads on any picture
+fn draw_text(buffer: &mut [u32], stride: usize, left: usize, top: usize, text: &str) {
+    let mut put = |x: usize, y: usize, pixel: u32| {
+        if x < stride
+            && let Some(target) = buffer.get_mut(y * stride + x)
+        {
+            *target = pixel;
+        }
+    };
+    for y in top..top + LINE_HEIGHT {
+        for x in left..left + text_width(text) + SCALE {
+            put(x, y, 0x000000);
+        }
+    }
+    for (i, c) in text.chars().enumerate() {
+        for (row, bits) in glyph(c).iter().enumerate() {
+            for column in 0..3 {
+                if bits & (0b100 >> column) == 0 {
+                    continue;
+                }
+                for d in 0..SCALE * SCALE {
+                    let x = left + SCALE + (i * 4 + column) * SCALE + d % SCALE;
+                    let y = top + SCALE + row * SCALE + d / SCALE;
+                    put(x, y, 0xffffff);
+                }
+            }
+        }
+    }
+}
+
+/// 3 by 5 pixels, a row per byte, letters all uppercase
+fn glyph(c: char) -> [u8; 5] {
+    match c.to_ascii_uppercase() {
+        ' ' => [0b000, 0b000, 0b000, 0b000, 0b000],
+        '0' => [0b111, 0b101, 0b101, 0b101, 0b111],
+        '1' => [0b010, 0b110, 0b010, 0b010, 0b111],
+        '2' => [0b111, 0b001, 0b111, 0b100, 0b111],
+        '3' => [0b111, 0b001, 0b111, 0b001, 0b111],
+        '4' => [0b101, 0b101, 0b111, 0b001, 0b001],
+        '5' => [0b111, 0b100, 0b111, 0b001, 0b111],
+        '6' => [0b111, 0b100, 0b111, 0b101, 0b111],
+        '7' => [0b111, 0b001, 0b001, 0b010, 0b010],
+        '8' => [0b111, 0b101, 0b111, 0b101, 0b111],
+        '9' => [0b111, 0b101, 0b111, 0b001, 0b111],
+        'A' => [0b010, 0b101, 0b111, 0b101, 0b101],
+        'B' => [0b110, 0b101, 0b110, 0b101, 0b110],
+        'C' => [0b011, 0b100, 0b100, 0b100, 0b011],
+        'D' => [0b110, 0b101, 0b101, 0b101, 0b110],
+        'E' => [0b111, 0b100, 0b110, 0b100, 0b111],
+        'F' => [0b111, 0b100, 0b110, 0b100, 0b100],
+        'G' => [0b011, 0b100, 0b101, 0b101, 0b011],
+        'H' => [0b101, 0b101, 0b111, 0b101, 0b101],
+        'I' => [0b111, 0b010, 0b010, 0b010, 0b111],
+        'J' => [0b001, 0b001, 0b001, 0b101, 0b010],
+        'K' => [0b101, 0b101, 0b110, 0b101, 0b101],
+        'L' => [0b100, 0b100, 0b100, 0b100, 0b111],
+        'M' => [0b101, 0b111, 0b111, 0b101, 0b101],
+        'N' => [0b110, 0b101, 0b101, 0b101, 0b101],
+        'O' => [0b010, 0b101, 0b101, 0b101, 0b010],
+        'P' => [0b110, 0b101, 0b110, 0b100, 0b100],
+        'Q' => [0b010, 0b101, 0b101, 0b110, 0b011],
+        'R' => [0b110, 0b101, 0b110, 0b101, 0b101],
+        'S' => [0b011, 0b100, 0b010, 0b001, 0b110],
+        'T' => [0b111, 0b010, 0b010, 0b010, 0b010],
+        'U' => [0b101, 0b101, 0b101, 0b101, 0b111],
+        'V' => [0b101, 0b101, 0b101, 0b101, 0b010],
+        'W' => [0b101, 0b101, 0b111, 0b111, 0b101],
+        'X' => [0b101, 0b101, 0b010, 0b101, 0b101],
+        'Y' => [0b101, 0b101, 0b010, 0b010, 0b010],
+        'Z' => [0b111, 0b001, 0b010, 0b100, 0b111],
+        '.' => [0b000, 0b000, 0b000, 0b000, 0b010],
+        ',' => [0b000, 0b000, 0b000, 0b010, 0b100],
+        ':' => [0b000, 0b010, 0b000, 0b010, 0b000],
+        '-' => [0b000, 0b000, 0b111, 0b000, 0b000],
+        '(' => [0b001, 0b010, 0b010, 0b010, 0b001],
+        ')' => [0b100, 0b010, 0b010, 0b010, 0b100],
+        '/' => [0b001, 0b001, 0b010, 0b100, 0b100],
+        '\'' => [0b010, 0b010, 0b000, 0b000, 0b000],
+        '%' => [0b101, 0b001, 0b010, 0b100, 0b101],
+        _ => [0b111, 0b001, 0b010, 0b000, 0b010],
+    }
+}
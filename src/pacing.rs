@@ -0,0 +1,56 @@
+//! Running games at their own frame rate on displays that refresh at
+//! another, like PAL games at 50 Hz on a 60 Hz monitor. Frontends that wait
+//! for vsync ask a [`FramePacer`] how many frames to run on each refresh
+//! instead of running one, which would play PAL games 20% too fast. Audio
+//! needs nothing extra, the console resamples from its own clock rate, see
+//! [`crate::Bus::set_sample_rate`].
+
+use std::time::Duration;
+
+/// Frames run at most on one refresh. Anything more owed is dropped, like
+/// after the window was dragged and no refreshes came for a while.
+pub const MAX_CATCH_UP: u32 = 4;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct FramePacer {
+    /// Frames per second the console runs at
+    rate: f64,
+    /// Frames owed that weren't run yet
+    owed: f64,
+}
+
+impl FramePacer {
+    /// For a console running at `rate` frames per second, see
+    /// [`crate::console::Console::refresh_rate`]
+    pub fn new(rate: f64) -> Self {
+        FramePacer {
+            rate,
+            // Half a frame ahead, so refreshes a little early or late
+            // still run a frame each when the rates are about the same
+            owed: 0.5,
+        }
+    }
+
+    pub fn rate(&self) -> f64 {
+        self.rate
+    }
+
+    /// Follow a console that changed region
+    pub fn set_rate(&mut self, rate: f64) {
+        self.rate = rate;
+    }
+
+    /// How many frames to run on a refresh `elapsed` after the last one
+    pub fn frames_due(&mut self, elapsed: Duration) -> u32 {
+        self.owed += elapsed.as_secs_f64() * self.rate;
+        let due = self.owed.floor();
+        self.owed -= due;
+        (due as u32).min(MAX_CATCH_UP)
+    }
+
+    /// How long until another frame is due, for frontends that sleep rather
+    /// than wait for vsync
+    pub fn until_next(&self) -> Duration {
+        Duration::from_secs_f64((1.0 - self.owed).max(0.0) / self.rate)
+    }
+}
@@ -0,0 +1,173 @@
+//! IPS and BPS patches, applied to a whole iNES file (header included)
+
+/// See https://zerosoft.zophar.net/ips.php
+pub fn apply_ips(data: &[u8], patch: &[u8]) -> Result<Vec<u8>, String> {
+    let mut patch = patch
+        .strip_prefix(b"PATCH")
+        .ok_or_else(|| String::from("Expected IPS magic number"))?;
+    let mut out = data.to_vec();
+    let truncated = String::from("IPS patch is truncated");
+    loop {
+        let Some((offset, rest)) = patch.split_first_chunk::<3>() else {
+            return Err(truncated);
+        };
+        patch = rest;
+        if offset == b"EOF" {
+            break;
+        }
+        let offset = u32::from_be_bytes([0, offset[0], offset[1], offset[2]]) as usize;
+        let Some((size, rest)) = patch.split_first_chunk::<2>() else {
+            return Err(truncated);
+        };
+        patch = rest;
+        let size = u16::from_be_bytes(*size) as usize;
+        let bytes = if size == 0 {
+            // Run length encoded record
+            let Some((&[count_hi, count_lo, val], rest)) = patch.split_first_chunk::<3>() else {
+                return Err(truncated);
+            };
+            patch = rest;
+            vec![val; u16::from_be_bytes([count_hi, count_lo]) as usize]
+        } else {
+            let Some((bytes, rest)) = patch.split_at_checked(size) else {
+                return Err(truncated);
+            };
+            patch = rest;
+            bytes.to_vec()
+        };
+        if out.len() < offset + bytes.len() {
+            out.resize(offset + bytes.len(), 0);
+        }
+        out[offset..offset + bytes.len()].copy_from_slice(&bytes);
+    }
+    // Optional truncation extension
+    if let Some(len) = patch.first_chunk::<3>() {
+        out.truncate(u32::from_be_bytes([0, len[0], len[1], len[2]]) as usize);
+    }
+    Ok(out)
+}
+
+/// See https://github.com/blakesmith/rombp/blob/master/docs/bps_spec.md
+pub fn apply_bps(data: &[u8], patch: &[u8]) -> Result<Vec<u8>, String> {
+    if patch.len() < 4 + 12 || !patch.starts_with(b"BPS1") {
+        return Err(String::from("Expected BPS magic number"));
+    }
+    let (body, footer) = patch.split_at(patch.len() - 12);
+    let crc = |i: usize| u32::from_le_bytes(footer[i..i + 4].try_into().unwrap());
+    // Covers everything but itself
+    if crc32fast::hash(&patch[..patch.len() - 4]) != crc(8) {
+        return Err(String::from("BPS patch checksum mismatch"));
+    }
+    if crc32fast::hash(data) != crc(0) {
+        return Err(String::from("BPS patch is for a different ROM"));
+    }
+
+    let mut reader = BpsReader { data: body, pos: 4 };
+    let source_size = reader.number()?;
+    let target_size = reader.number()?;
+    let metadata_size = reader.number()?;
+    reader.pos = reader
+        .pos
+        .checked_add(metadata_size)
+        .filter(|&end| end <= body.len())
+        .ok_or("BPS patch is truncated")?;
+    if source_size != data.len() {
+        return Err(String::from("BPS patch is for a different ROM"));
+    }
+
+    // Not sized up front, a corrupt size would ask for any amount of memory
+    let mut out = Vec::new();
+    let mut source_rel = 0isize;
+    let mut target_rel = 0isize;
+    while reader.pos < body.len() {
+        let action = reader.number()?;
+        let len = (action >> 2) + 1;
+        if len > target_size - out.len() {
+            return Err(String::from("BPS patch writes past the end of the ROM"));
+        }
+        match action & 3 {
+            // SourceRead
+            0 => {
+                let start = out.len();
+                let bytes = data
+                    .get(start..start.saturating_add(len))
+                    .ok_or("BPS read out of bounds")?;
+                out.extend_from_slice(bytes);
+            }
+            // TargetRead
+            1 => {
+                let bytes = body
+                    .get(reader.pos..reader.pos.saturating_add(len))
+                    .ok_or("BPS patch is truncated")?;
+                out.extend_from_slice(bytes);
+                reader.pos += len;
+            }
+            // SourceCopy
+            2 => {
+                source_rel = source_rel
+                    .checked_add(reader.signed()?)
+                    .ok_or("BPS copy out of bounds")?;
+                let start = usize::try_from(source_rel).map_err(|_| "BPS copy out of bounds")?;
+                let bytes = data
+                    .get(start..start.saturating_add(len))
+                    .ok_or("BPS copy out of bounds")?;
+                out.extend_from_slice(bytes);
+                source_rel += len as isize;
+            }
+            // TargetCopy, can overlap with what it's writing
+            _ => {
+                target_rel = target_rel
+                    .checked_add(reader.signed()?)
+                    .ok_or("BPS copy out of bounds")?;
+                for _ in 0..len {
+                    let i = usize::try_from(target_rel).map_err(|_| "BPS copy out of bounds")?;
+                    let val = *out.get(i).ok_or("BPS copy out of bounds")?;
+                    out.push(val);
+                    target_rel += 1;
+                }
+            }
+        }
+    }
+    if out.len() != target_size || crc32fast::hash(&out) != crc(4) {
+        return Err(String::from("BPS patch produced a corrupt ROM"));
+    }
+    Ok(out)
+}
+
+const OVERFLOW: &str = "BPS number overflow";
+
+struct BpsReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl BpsReader<'_> {
+    /// Variable length number, 7 bits per byte with the high bit ending it
+    fn number(&mut self) -> Result<usize, String> {
+        let mut val = 0usize;
+        let mut shift = 1usize;
+        loop {
+            let byte = *self
+                .data
+                .get(self.pos)
+                .ok_or_else(|| String::from("BPS patch is truncated"))?;
+            self.pos += 1;
+            val = (byte as usize & 0x7f)
+                .checked_mul(shift)
+                .and_then(|bits| val.checked_add(bits))
+                .ok_or(OVERFLOW)?;
+            if byte & 0x80 != 0 {
+                return Ok(val);
+            }
+            shift = shift.checked_mul(0x80).ok_or(OVERFLOW)?;
+            val = val.checked_add(shift).ok_or(OVERFLOW)?;
+        }
+    }
+
+    /// Number with the sign in the lowest bit
+    fn signed(&mut self) -> Result<isize, String> {
+        let val = self.number()?;
+        let magnitude = (val >> 1) as isize;
+        Ok(if val & 1 != 0 { -magnitude } else { magnitude })
+    }
+}
@@ -0,0 +1,956 @@
+mod render;
+mod tile_cache;
+
+pub use render::{PICTURE_HEIGHT, PICTURE_WIDTH, RenderMode};
+pub use tile_cache::{TileCache, TilePixels};
+
+use crate::mapper::{Mapper, PpuFetch, PpuTarget};
+use crate::rom::{Mirroring, Region};
+use crate::savestate::{StateStream, Stateful, stateful_bitflags, stream};
+use render::{LineSprite, LineStart};
+
+bitflags::bitflags! {
+    /// $2000 PPUCTRL
+    #[derive(Clone, Copy, Debug)]
+    pub struct PpuCtrl: u8 {
+        const NMI_ENABLE = 0b_1000_0000;
+        const MASTER_SLAVE = 0b_0100_0000;
+        const SPRITE_SIZE = 0b_0010_0000;
+        const BG_TABLE = 0b_0001_0000;
+        const SPRITE_TABLE = 0b_0000_1000;
+        const VRAM_INCREMENT = 0b_0000_0100;
+        const NAMETABLE_HIGH = 0b_0000_0010;
+        const NAMETABLE_LOW = 0b_0000_0001;
+    }
+}
+
+bitflags::bitflags! {
+    /// $2001 PPUMASK
+    #[derive(Clone, Copy, Debug)]
+    pub struct PpuMask: u8 {
+        const EMPHASIZE_BLUE = 0b_1000_0000;
+        const EMPHASIZE_GREEN = 0b_0100_0000;
+        const EMPHASIZE_RED = 0b_0010_0000;
+        const SHOW_SPRITES = 0b_0001_0000;
+        const SHOW_BG = 0b_0000_1000;
+        const SHOW_SPRITES_LEFT = 0b_0000_0100;
+        const SHOW_BG_LEFT = 0b_0000_0010;
+        const GREYSCALE = 0b_0000_0001;
+    }
+}
+
+bitflags::bitflags! {
+    /// $2002 PPUSTATUS
+    #[derive(Clone, Copy, Debug)]
+    pub struct PpuStatus: u8 {
+        const VBLANK = 0b_1000_0000;
+        const SPRITE_ZERO_HIT = 0b_0100_0000;
+        const SPRITE_OVERFLOW = 0b_0010_0000;
+    }
+}
+
+stateful_bitflags!(PpuCtrl, PpuMask, PpuStatus);
+
+pub const DOTS_PER_SCANLINE: u16 = 341;
+pub const SCANLINES_PER_FRAME: u16 = 262;
+pub const VBLANK_SCANLINE: u16 = 241;
+pub const PRE_RENDER_SCANLINE: u16 = 261;
+pub const PAL_SCANLINES_PER_FRAME: u16 = 312;
+/// The Dendy idles for 51 scanlines after the picture rather than 1, so
+/// vblank is as short as on NTSC
+pub const DENDY_VBLANK_SCANLINE: u16 = 291;
+/// How long PPU A12 has to stay low before a rise is reported to the mapper.
+/// The MMC3 ignores rises shortly after a fall (about 3 CPU cycles), which
+/// filters out the A12 toggling between the individual sprite pattern fetches.
+pub const A12_FILTER_DOTS: u16 = 10;
+
+/// $3F10, $3F14, $3F18 and $3F1C share their entry with the background
+fn palette_index(addr: u16) -> usize {
+    let index = addr as usize & 0x1f;
+    if index & 0x13 == 0x10 {
+        index & 0x0f
+    } else {
+        index
+    }
+}
+
+/// Where the sprite evaluation state machine is within dots 65-256.
+/// See https://www.nesdev.org/wiki/PPU_sprite_evaluation
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum SpriteEval {
+    /// Reading the Y coordinate of sprite `n` and checking if it is in range
+    CheckY,
+    /// Copying the remaining bytes of an in-range sprite into secondary OAM
+    CopySprite,
+    /// Secondary OAM is full, searching for a 9th sprite.
+    /// Hardware increments both `n` **and** `m` here, which is the overflow bug.
+    Overflow,
+    /// A 9th sprite was found, the PPU still reads its remaining bytes
+    OverflowCopy(u8),
+    /// All 64 sprites were checked, `n` keeps incrementing with failed copies
+    Done,
+}
+
+impl Stateful for SpriteEval {
+    fn stream(&mut self, s: &mut StateStream) {
+        let (mut tag, mut copied) = match *self {
+            SpriteEval::CheckY => (0u8, 0),
+            SpriteEval::CopySprite => (1, 0),
+            SpriteEval::Overflow => (2, 0),
+            SpriteEval::OverflowCopy(copied) => (3, copied),
+            SpriteEval::Done => (4, 0),
+        };
+        stream!(s, tag, copied);
+        *self = match tag {
+            0 => SpriteEval::CheckY,
+            1 => SpriteEval::CopySprite,
+            2 => SpriteEval::Overflow,
+            3 => SpriteEval::OverflowCopy(copied),
+            4 => SpriteEval::Done,
+            _ => {
+                s.fail("invalid sprite evaluation state");
+                SpriteEval::Done
+            }
+        };
+    }
+}
+
+/// Extra idle scanlines inserted after vblank, before the pre-render line.
+/// The CPU gets more time per frame while the APU is paused, so games that
+/// lag run faster without their music slowing down. The Dendy has PAL's
+/// frame, so it gets the `pal` scanlines.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Overclock {
+    pub ntsc: u16,
+    pub pal: u16,
+}
+
+impl Overclock {
+    pub fn scanlines(&self, region: Region) -> u16 {
+        match region {
+            Region::Ntsc => self.ntsc,
+            Region::Pal | Region::Dendy => self.pal,
+        }
+    }
+}
+
+/// Snapshot of where the PPU is within the frame
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PpuTiming {
+    pub scanline: u16,
+    pub dot: u16,
+    pub frame: u64,
+    pub rendering_enabled: bool,
+}
+
+pub struct Ppu {
+    pub region: Region,
+    pub overclock: Overclock,
+    pub ctrl: PpuCtrl,
+    pub mask: PpuMask,
+    pub status: PpuStatus,
+    /// OAMADDR, which is also the `n`/`m` cursor used during sprite evaluation
+    pub oam_addr: u8,
+    pub oam: [u8; 0x100],
+    pub secondary_oam: [u8; 0x20],
+    pub scanline: u16,
+    pub dot: u16,
+    pub frame: u64,
+    /// Number of sprites found for the next scanline
+    pub sprite_count: u8,
+    /// Honour the 8 sprites per scanline limit. When disabled, the sprites the
+    /// hardware would have dropped are collected into `extra_oam` as well and
+    /// drawn with the rest, while evaluation itself (and so the overflow
+    /// flag) is left untouched.
+    pub sprite_limit: bool,
+    /// How long the I/O latch holds a bit that isn't refreshed, in milliseconds.
+    /// `None` keeps it forever, like emulators that don't model the decay.
+    pub open_bus_decay: Option<u32>,
+    pub render_mode: RenderMode,
+    /// What the PPU shows as NES colors, `PICTURE_WIDTH` by `PICTURE_HEIGHT`,
+    /// drawn following `render_mode`
+    pub picture: Vec<u8>,
+    /// Lines [`RenderMode::Scanline`] finished a dot at a time because of a
+    /// write in the middle
+    pub split_lines: u64,
+    /// Tiles [`RenderMode::Scanline`] decoded already
+    pub tile_cache: TileCache,
+    line: LineStart,
+    /// OAM entries of the sprites on the line being drawn, frontmost first
+    line_oam: Vec<[u8; 4]>,
+    /// Their pixels, kept to be refilled each line
+    line_sprites: Vec<LineSprite>,
+    /// Sprites past the first 8 on the next scanline, when the limit is disabled
+    pub extra_oam: [u8; 0xe0],
+    pub extra_sprite_count: u8,
+    /// Whether sprite 0 is part of the sprites found for the next scanline
+    pub sprite_zero_next: bool,
+    /// Internal nametable RAM
+    pub vram: [u8; 0x800],
+    /// Palette RAM at $3F00-$3F1F, 6 bits per entry
+    pub palette: [u8; 0x20],
+    /// Last value read through $2007, returned by the next read
+    pub read_buffer: u8,
+    /// Current VRAM address (loopy `v`)
+    pub vram_addr: u16,
+    /// Temporary VRAM address (loopy `t`)
+    pub temp_addr: u16,
+    /// Fine X scroll (loopy `x`)
+    pub fine_x: u8,
+    /// First/second write toggle for $2005 and $2006 (loopy `w`)
+    pub write_toggle: bool,
+    /// Last address put on the PPU address bus
+    pub bus_addr: u16,
+    /// I/O latch between the CPU and the PPU, what reads of write-only
+    /// registers and unused bits return. See [`Ppu::open_bus`].
+    pub io_latch: u8,
+    /// Frame each bit of `io_latch` was last driven on
+    io_latch_refreshed: [u64; 8],
+    a12_low_dots: u16,
+    /// NMI output went high and the CPU hasn't serviced it yet
+    nmi_pending: bool,
+    /// Nametable byte for the background tile being fetched
+    bg_tile: u8,
+    eval: SpriteEval,
+    /// Value read from OAM on the odd dot, written to secondary OAM on the even dot
+    oam_latch: u8,
+    secondary_addr: u8,
+    sprites_found: u8,
+}
+
+/// Region, overclocking, the sprite limit, the open bus decay and the render
+/// mode are settings and aren't saved, nor is the picture. A state loaded
+/// in the middle of a line draws the rest of it as if it started there.
+/// Loading empties the tile cache, as CHR RAM is loaded with the mapper.
+impl Stateful for Ppu {
+    fn stream(&mut self, s: &mut StateStream) {
+        if s.is_loading() {
+            self.tile_cache.clear();
+        }
+        stream!(
+            s,
+            self.ctrl,
+            self.mask,
+            self.status,
+            self.oam_addr,
+            self.oam,
+            self.secondary_oam,
+            self.scanline,
+            self.dot,
+            self.frame,
+            self.sprite_count,
+            self.extra_oam,
+            self.extra_sprite_count,
+            self.sprite_zero_next,
+            self.vram,
+        );
+        // Palette RAM and the read buffer came in version 2, the I/O latch
+        // in 3. Older states start with them cleared, as at power on.
+        if s.version() >= 2 {
+            stream!(s, self.palette, self.read_buffer);
+        } else if s.is_loading() {
+            self.palette = [0; 0x20];
+            self.read_buffer = 0;
+        }
+        stream!(
+            s,
+            self.vram_addr,
+            self.temp_addr,
+            self.fine_x,
+            self.write_toggle,
+            self.bus_addr,
+        );
+        if s.version() >= 3 {
+            stream!(s, self.io_latch, self.io_latch_refreshed);
+        } else if s.is_loading() {
+            self.io_latch = 0;
+            self.io_latch_refreshed = [0; 8];
+        }
+        stream!(
+            s,
+            self.a12_low_dots,
+            self.nmi_pending,
+            self.bg_tile,
+            self.eval,
+            self.oam_latch,
+            self.secondary_addr,
+            self.sprites_found,
+        );
+    }
+}
+
+impl Default for Ppu {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Ppu {
+    pub fn new() -> Self {
+        Ppu {
+            region: Region::Ntsc,
+            overclock: Overclock::default(),
+            ctrl: PpuCtrl::empty(),
+            mask: PpuMask::empty(),
+            status: PpuStatus::empty(),
+            oam_addr: 0,
+            oam: [0; 0x100],
+            secondary_oam: [0xff; 0x20],
+            scanline: 0,
+            dot: 0,
+            frame: 0,
+            sprite_count: 0,
+            sprite_limit: true,
+            open_bus_decay: Some(600),
+            render_mode: RenderMode::Off,
+            picture: vec![0; PICTURE_WIDTH * PICTURE_HEIGHT],
+            split_lines: 0,
+            tile_cache: TileCache::default(),
+            line: LineStart::default(),
+            line_oam: Vec::with_capacity(64),
+            line_sprites: Vec::with_capacity(64),
+            extra_oam: [0xff; 0xe0],
+            extra_sprite_count: 0,
+            sprite_zero_next: false,
+            eval: SpriteEval::Done,
+            oam_latch: 0xff,
+            secondary_addr: 0,
+            sprites_found: 0,
+            vram: [0; 0x800],
+            palette: [0; 0x20],
+            read_buffer: 0,
+            vram_addr: 0,
+            temp_addr: 0,
+            fine_x: 0,
+            write_toggle: false,
+            bus_addr: 0,
+            io_latch: 0,
+            io_latch_refreshed: [0; 8],
+            a12_low_dots: 0,
+            nmi_pending: false,
+            bg_tile: 0,
+        }
+    }
+
+    pub fn rendering_enabled(&self) -> bool {
+        self.mask
+            .intersects(PpuMask::SHOW_BG | PpuMask::SHOW_SPRITES)
+    }
+
+    pub fn timing(&self) -> PpuTiming {
+        PpuTiming {
+            scanline: self.scanline,
+            dot: self.dot,
+            frame: self.frame,
+            rendering_enabled: self.rendering_enabled(),
+        }
+    }
+
+    /// Level of the /NMI output (inverted)
+    pub fn nmi_output(&self) -> bool {
+        self.status.contains(PpuStatus::VBLANK) && self.ctrl.contains(PpuCtrl::NMI_ENABLE)
+    }
+
+    /// Returns whether an NMI edge happened since the last call
+    pub fn take_nmi(&mut self) -> bool {
+        std::mem::take(&mut self.nmi_pending)
+    }
+
+    /// Scanlines per frame for the region, including overclock scanlines
+    pub fn scanlines_per_frame(&self) -> u16 {
+        self.pre_render_scanline() + 1
+    }
+
+    pub fn pre_render_scanline(&self) -> u16 {
+        let base = match self.region {
+            Region::Ntsc => PRE_RENDER_SCANLINE,
+            Region::Pal | Region::Dendy => PAL_SCANLINES_PER_FRAME - 1,
+        };
+        base + self.overclock.scanlines(self.region)
+    }
+
+    /// The scanline vblank starts on
+    pub fn vblank_scanline(&self) -> u16 {
+        match self.region {
+            Region::Ntsc | Region::Pal => VBLANK_SCANLINE,
+            Region::Dendy => DENDY_VBLANK_SCANLINE,
+        }
+    }
+
+    /// Whether the PPU is in the extra scanlines inserted by overclocking
+    pub fn overclocking(&self) -> bool {
+        let extra = self.overclock.scanlines(self.region);
+        let pre_render = self.pre_render_scanline();
+        extra > 0 && (pre_render - extra..pre_render).contains(&self.scanline)
+    }
+
+    /// Whether the PPU is currently fetching for a visible or the pre-render scanline
+    pub fn rendering_active(&self) -> bool {
+        self.rendering_enabled()
+            && (self.scanline < 240 || self.scanline == self.pre_render_scanline())
+    }
+
+    pub fn sprite_height(&self) -> u16 {
+        if self.ctrl.contains(PpuCtrl::SPRITE_SIZE) {
+            16
+        } else {
+            8
+        }
+    }
+
+    /// The I/O latch as reads see it. Bits decay to 0 one by one when they
+    /// haven't been driven for `open_bus_decay` (about 600ms on hardware).
+    /// See https://www.nesdev.org/wiki/Open_bus_behavior#PPU_open_bus
+    pub fn open_bus(&self) -> u8 {
+        let Some(decay) = self.open_bus_decay else {
+            return self.io_latch;
+        };
+        let fps = match self.region {
+            Region::Ntsc => 60,
+            Region::Pal | Region::Dendy => 50,
+        };
+        let frames = decay as u64 * fps / 1000;
+        (0..8)
+            .filter(|&bit| self.frame.saturating_sub(self.io_latch_refreshed[bit]) < frames)
+            .fold(0, |val, bit| val | (self.io_latch & (1 << bit)))
+    }
+
+    /// Drive the bits of the I/O latch in `mask` with `val`
+    fn refresh_io_latch(&mut self, val: u8, mask: u8) {
+        self.io_latch = (self.io_latch & !mask) | (val & mask);
+        for bit in 0..8 {
+            if mask & (1 << bit) != 0 {
+                self.io_latch_refreshed[bit] = self.frame;
+            }
+        }
+    }
+
+    /// Read a register without any side effects (for tracing/debugging)
+    pub fn peek_register(&self, reg: u16) -> u8 {
+        let open_bus = self.open_bus();
+        match reg & 0x7 {
+            2 => self.status.bits() | (open_bus & 0x1f),
+            4 => self.oam_data(),
+            7 if self.vram_addr & 0x3fff >= 0x3f00 => {
+                self.read_palette(self.vram_addr) | (open_bus & 0xc0)
+            }
+            7 => self.read_buffer,
+            _ => open_bus,
+        }
+    }
+
+    pub fn read_register(&mut self, reg: u16, cart: &mut dyn Mapper) -> u8 {
+        let open_bus = self.open_bus();
+        match reg & 0x7 {
+            2 => {
+                let status = self.status.bits();
+                self.status.remove(PpuStatus::VBLANK);
+                self.write_toggle = false;
+                // Only the flags are driven, the rest is left on the latch
+                self.refresh_io_latch(status, 0xe0);
+                status | (open_bus & 0x1f)
+            }
+            4 => {
+                let val = self.oam_data();
+                self.refresh_io_latch(val, 0xff);
+                val
+            }
+            7 => {
+                let addr = self.vram_addr & 0x3fff;
+                let val = if addr >= 0x3f00 {
+                    // Palette reads skip the buffer, which gets the
+                    // nametable byte underneath instead. Palette entries
+                    // are 6 bits, the top 2 come from the latch.
+                    self.read_buffer = self.read_vram(addr - 0x1000, cart);
+                    let val = self.read_palette(addr);
+                    self.refresh_io_latch(val, 0x3f);
+                    val | (open_bus & 0xc0)
+                } else {
+                    let val = self.read_vram(addr, cart);
+                    let val = std::mem::replace(&mut self.read_buffer, val);
+                    self.refresh_io_latch(val, 0xff);
+                    val
+                };
+                self.increment_vram_addr();
+                val
+            }
+            _ => open_bus,
+        }
+    }
+
+    pub fn write_register(&mut self, reg: u16, val: u8, cart: &mut dyn Mapper) {
+        self.refresh_io_latch(val, 0xff);
+        self.split_line(reg, cart);
+        self.apply_register_write(reg, val, cart);
+    }
+
+    fn apply_register_write(&mut self, reg: u16, val: u8, cart: &mut dyn Mapper) {
+        match reg & 0x7 {
+            0 => {
+                let nmi_was_enabled = self.ctrl.contains(PpuCtrl::NMI_ENABLE);
+                self.ctrl = PpuCtrl::from_bits_retain(val);
+                // Enabling NMI during vblank immediately triggers one
+                if !nmi_was_enabled && self.nmi_output() {
+                    self.nmi_pending = true;
+                }
+                self.temp_addr = (self.temp_addr & !0x0c00) | ((val as u16 & 0x3) << 10);
+            }
+            1 => self.mask = PpuMask::from_bits_retain(val),
+            2 => {}
+            3 => self.oam_addr = val,
+            4 => {
+                if self.rendering_active() {
+                    // Writes are ignored during rendering, but they bump the high
+                    // 6 bits of OAMADDR like the sprite evaluation `n` increment
+                    self.oam_addr = self.oam_addr.wrapping_add(4);
+                    return;
+                }
+                self.write_oam(val);
+            }
+            5 => {
+                if !self.write_toggle {
+                    self.temp_addr = (self.temp_addr & !0x001f) | (val as u16 >> 3);
+                    self.fine_x = val & 0x7;
+                } else {
+                    self.temp_addr = (self.temp_addr & !0x73e0)
+                        | ((val as u16 & 0x7) << 12)
+                        | ((val as u16 & 0xf8) << 2);
+                }
+                self.write_toggle = !self.write_toggle;
+            }
+            6 => {
+                if !self.write_toggle {
+                    self.temp_addr = (self.temp_addr & 0x00ff) | ((val as u16 & 0x3f) << 8);
+                } else {
+                    self.temp_addr = (self.temp_addr & 0xff00) | val as u16;
+                    self.vram_addr = self.temp_addr;
+                    self.bus_addr = self.vram_addr;
+                }
+                self.write_toggle = !self.write_toggle;
+            }
+            _ => {
+                let addr = self.vram_addr & 0x3fff;
+                if addr >= 0x3f00 {
+                    self.palette[palette_index(addr)] = val & 0x3f;
+                } else {
+                    match cart.ppu_target(addr) {
+                        PpuTarget::Cartridge => {
+                            if addr < 0x2000
+                                && let Some(offset) = cart.chr_offset(addr)
+                            {
+                                self.tile_cache.invalidate(offset);
+                            }
+                            cart.ppu_write(addr, val)
+                        }
+                        PpuTarget::Ciram(index) => self.vram[index] = val,
+                    }
+                }
+                self.increment_vram_addr();
+            }
+        }
+    }
+
+    /// Nametables and pattern tables as $2007 sees them
+    fn read_vram(&mut self, addr: u16, cart: &mut dyn Mapper) -> u8 {
+        match cart.ppu_target(addr) {
+            PpuTarget::Cartridge => cart.ppu_read(addr),
+            PpuTarget::Ciram(index) => self.vram[index],
+        }
+    }
+
+    fn read_palette(&self, addr: u16) -> u8 {
+        let val = self.palette[palette_index(addr)];
+        if self.mask.contains(PpuMask::GREYSCALE) {
+            val & 0x30
+        } else {
+            val
+        }
+    }
+
+    /// After a $2007 access, by 1 or 32 following PPUCTRL. While rendering
+    /// the address is busy scrolling, and gets both scroll increments instead.
+    fn increment_vram_addr(&mut self) {
+        if self.rendering_active() {
+            self.increment_coarse_x();
+            self.increment_y();
+        } else if self.ctrl.contains(PpuCtrl::VRAM_INCREMENT) {
+            self.vram_addr = self.vram_addr.wrapping_add(32) & 0x7fff;
+        } else {
+            self.vram_addr = self.vram_addr.wrapping_add(1) & 0x7fff;
+        }
+        self.bus_addr = self.vram_addr;
+    }
+
+    /// Write to OAM at OAMADDR and increment it
+    pub fn write_oam(&mut self, val: u8) {
+        // Bits 2-4 of the attribute byte don't exist in OAM and read back as 0
+        let val = if self.oam_addr & 0x3 == 2 {
+            val & 0xe3
+        } else {
+            val
+        };
+        self.oam[self.oam_addr as usize] = val;
+        self.oam_addr = self.oam_addr.wrapping_add(1);
+    }
+
+    fn oam_data(&self) -> u8 {
+        if self.scanline < 240 && self.rendering_enabled() && (1..=256).contains(&self.dot) {
+            // $2004 exposes the internal OAM bus while sprite evaluation is running
+            self.oam_latch
+        } else {
+            self.oam[self.oam_addr as usize]
+        }
+    }
+
+    /// Advance the PPU by a single dot
+    pub fn tick(&mut self, cart: &mut dyn Mapper) {
+        let visible = self.scanline < 240;
+        let pre_render = self.scanline == self.pre_render_scanline();
+
+        if visible && self.render_mode != RenderMode::Off {
+            match self.dot {
+                1 => self.start_line(),
+                257 => self.finish_line(cart),
+                _ => {}
+            }
+            if (1..=256).contains(&self.dot) {
+                self.draw_dot(cart);
+            }
+        }
+        if self.rendering_active() {
+            self.fetch_tick(cart);
+        }
+        self.update_a12(cart);
+
+        if self.rendering_enabled() {
+            if visible {
+                self.sprite_eval_tick();
+            }
+            if pre_render && self.dot == 1 {
+                self.corrupt_oam_row();
+            }
+            if (visible || pre_render) && (257..=320).contains(&self.dot) {
+                self.oam_addr = 0;
+            }
+        }
+
+        if self.dot == 1 {
+            if self.scanline == self.vblank_scanline() {
+                self.status.insert(PpuStatus::VBLANK);
+                self.nmi_pending |= self.nmi_output();
+            } else if pre_render {
+                self.status.remove(
+                    PpuStatus::VBLANK | PpuStatus::SPRITE_ZERO_HIT | PpuStatus::SPRITE_OVERFLOW,
+                );
+            }
+        }
+
+        self.dot += 1;
+        // The pre-render line is one dot shorter on odd NTSC frames while rendering
+        let skip = pre_render
+            && self.region == Region::Ntsc
+            && self.frame % 2 == 1
+            && self.rendering_enabled();
+        if self.dot == DOTS_PER_SCANLINE || (skip && self.dot == DOTS_PER_SCANLINE - 1) {
+            self.dot = 0;
+            self.scanline += 1;
+            if self.scanline == self.scanlines_per_frame() {
+                self.scanline = 0;
+                self.frame += 1;
+            }
+        }
+    }
+
+    /// Read from the PPU address space while rendering
+    fn fetch(&mut self, addr: u16, kind: PpuFetch, cart: &mut dyn Mapper) -> u8 {
+        self.bus_addr = addr;
+        cart.ppu_fetch(addr, kind);
+        match cart.ppu_target(addr) {
+            PpuTarget::Cartridge => cart.ppu_read(addr),
+            PpuTarget::Ciram(index) => self.vram[index],
+        }
+    }
+
+    /// Memory fetches done by the rendering pipeline.
+    /// See https://www.nesdev.org/wiki/PPU_rendering
+    fn fetch_tick(&mut self, cart: &mut dyn Mapper) {
+        let v = self.vram_addr;
+        match self.dot {
+            1..=256 | 321..=336 => {
+                match self.dot % 8 {
+                    1 => {
+                        self.bg_tile = self.fetch(0x2000 | (v & 0x0fff), PpuFetch::Nametable, cart)
+                    }
+                    3 => {
+                        self.fetch(
+                            0x23c0 | (v & 0x0c00) | ((v >> 4) & 0x38) | ((v >> 2) & 0x07),
+                            PpuFetch::Attribute,
+                            cart,
+                        );
+                    }
+                    5 => {
+                        self.fetch(self.bg_pattern_addr(), PpuFetch::BgPattern, cart);
+                    }
+                    7 => {
+                        self.fetch(self.bg_pattern_addr() | 0x8, PpuFetch::BgPattern, cart);
+                    }
+                    0 => self.increment_coarse_x(),
+                    _ => {}
+                }
+                if self.dot == 256 {
+                    self.increment_y();
+                }
+            }
+            257..=320 => {
+                if self.dot == 257 {
+                    // Copy horizontal position from t to v
+                    self.vram_addr = (v & !0x041f) | (self.temp_addr & 0x041f);
+                }
+                if self.scanline == self.pre_render_scanline() && (280..=304).contains(&self.dot) {
+                    // Copy vertical position from t to v
+                    self.vram_addr = (v & !0x7be0) | (self.temp_addr & 0x7be0);
+                }
+                let slot = ((self.dot - 257) / 8) as usize;
+                match (self.dot - 257) % 8 {
+                    // Garbage nametable fetches
+                    0 | 2 => {
+                        self.fetch(0x2000 | (self.vram_addr & 0x0fff), PpuFetch::Unused, cart);
+                    }
+                    4 => {
+                        self.fetch(
+                            self.sprite_pattern_addr(slot),
+                            PpuFetch::SpritePattern,
+                            cart,
+                        );
+                    }
+                    6 => {
+                        self.fetch(
+                            self.sprite_pattern_addr(slot) | 0x8,
+                            PpuFetch::SpritePattern,
+                            cart,
+                        );
+                    }
+                    _ => {}
+                }
+            }
+            // Unused nametable fetches
+            337 | 339 => {
+                self.fetch(0x2000 | (v & 0x0fff), PpuFetch::Unused, cart);
+            }
+            _ => {}
+        }
+    }
+
+    fn bg_pattern_addr(&self) -> u16 {
+        let table = if self.ctrl.contains(PpuCtrl::BG_TABLE) {
+            0x1000
+        } else {
+            0
+        };
+        let fine_y = self.vram_addr >> 12;
+        table | ((self.bg_tile as u16) << 4) | fine_y
+    }
+
+    fn sprite_pattern_addr(&self, slot: usize) -> u16 {
+        let [y, tile, attr, _] = self.secondary_oam[slot * 4..slot * 4 + 4] else {
+            unreachable!()
+        };
+        self.sprite_row_addr(tile, attr, self.scanline.wrapping_sub(y as u16))
+    }
+
+    /// Address of `row` of a sprite, counted from its top before flipping
+    fn sprite_row_addr(&self, tile: u8, attr: u8, row: u16) -> u16 {
+        let height = self.sprite_height();
+        let mut row = row & (height - 1);
+        if attr & 0x80 != 0 {
+            row = height - 1 - row;
+        }
+        let (table, tile) = if height == 16 {
+            ((tile as u16 & 0x1) << 12, (tile & 0xfe) as u16 + (row >> 3))
+        } else if self.ctrl.contains(PpuCtrl::SPRITE_TABLE) {
+            (0x1000, tile as u16)
+        } else {
+            (0, tile as u16)
+        };
+        table | (tile << 4) | (row & 0x7)
+    }
+
+    fn increment_coarse_x(&mut self) {
+        if self.vram_addr & 0x001f == 31 {
+            self.vram_addr &= !0x001f;
+            self.vram_addr ^= 0x0400;
+        } else {
+            self.vram_addr += 1;
+        }
+    }
+
+    fn increment_y(&mut self) {
+        if self.vram_addr & 0x7000 != 0x7000 {
+            self.vram_addr += 0x1000;
+            return;
+        }
+        self.vram_addr &= !0x7000;
+        let coarse_y = match (self.vram_addr & 0x03e0) >> 5 {
+            29 => {
+                self.vram_addr ^= 0x0800;
+                0
+            }
+            31 => 0,
+            y => y + 1,
+        };
+        self.vram_addr = (self.vram_addr & !0x03e0) | (coarse_y << 5);
+    }
+
+    /// Watch A12 of the PPU address bus and report filtered rises to the mapper
+    fn update_a12(&mut self, cart: &mut dyn Mapper) {
+        if self.bus_addr & 0x1000 != 0 {
+            if self.a12_low_dots >= A12_FILTER_DOTS {
+                cart.a12_rise();
+            }
+            self.a12_low_dots = 0;
+        } else {
+            self.a12_low_dots = self.a12_low_dots.saturating_add(1);
+        }
+    }
+
+    /// If rendering starts with OAMADDR at 8 or above, the OAM row it points to
+    /// gets copied over the first row (sprites 0 and 1).
+    /// See https://www.nesdev.org/wiki/PPU_registers#OAMADDR
+    fn corrupt_oam_row(&mut self) {
+        if self.oam_addr >= 8 {
+            let row = (self.oam_addr & 0xf8) as usize;
+            self.oam.copy_within(row..row + 8, 0);
+        }
+    }
+
+    /// Secondary OAM clear (dots 1-64), evaluation (dots 65-256),
+    /// and latching the result for the sprite fetches (dot 257).
+    /// Odd dots read from primary OAM, even dots write to secondary OAM.
+    fn sprite_eval_tick(&mut self) {
+        match self.dot {
+            1..=64 => {
+                self.oam_latch = 0xff;
+                if self.dot.is_multiple_of(2) {
+                    self.secondary_oam[(self.dot / 2 - 1) as usize] = 0xff;
+                }
+            }
+            65..=256 => {
+                if self.dot == 65 {
+                    self.eval = SpriteEval::CheckY;
+                    self.secondary_addr = 0;
+                    self.sprites_found = 0;
+                    self.sprite_zero_next = false;
+                }
+                if self.dot % 2 == 1 {
+                    self.oam_latch = self.oam[self.oam_addr as usize];
+                } else {
+                    self.sprite_eval_step(self.dot == 66);
+                }
+            }
+            257 => {
+                self.sprite_count = self.sprites_found;
+                self.extra_sprite_count = 0;
+                if !self.sprite_limit && self.sprites_found == 8 {
+                    self.collect_extra_sprites();
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Copy every in-range sprite after the 8th into `extra_oam`
+    fn collect_extra_sprites(&mut self) {
+        let mut found = 0;
+        for sprite in self.oam.chunks_exact(4) {
+            if !self.sprite_in_range(sprite[0]) {
+                continue;
+            }
+            found += 1;
+            if found > 8 {
+                let addr = self.extra_sprite_count as usize * 4;
+                self.extra_oam[addr..addr + 4].copy_from_slice(sprite);
+                self.extra_sprite_count += 1;
+            }
+        }
+    }
+
+    fn sprite_in_range(&self, y: u8) -> bool {
+        let diff = self.scanline.wrapping_sub(y as u16);
+        diff < self.sprite_height()
+    }
+
+    /// Move `n` to the next sprite, returns true if all 64 sprites were checked
+    fn next_sprite(&mut self) -> bool {
+        let (addr, wrapped) = (self.oam_addr & 0xfc).overflowing_add(4);
+        self.oam_addr = addr;
+        wrapped
+    }
+
+    fn sprite_eval_step(&mut self, first: bool) {
+        let val = self.oam_latch;
+        match self.eval {
+            SpriteEval::CheckY => {
+                self.secondary_oam[self.secondary_addr as usize] = val;
+                if self.sprite_in_range(val) {
+                    self.sprite_zero_next |= first;
+                    self.secondary_addr += 1;
+                    self.oam_addr = self.oam_addr.wrapping_add(1);
+                    self.eval = SpriteEval::CopySprite;
+                } else if self.next_sprite() {
+                    self.eval = SpriteEval::Done;
+                }
+            }
+            SpriteEval::CopySprite => {
+                self.secondary_oam[self.secondary_addr as usize] = val;
+                self.secondary_addr += 1;
+                let (addr, wrapped) = self.oam_addr.overflowing_add(1);
+                self.oam_addr = addr;
+                if self.secondary_addr.is_multiple_of(4) {
+                    self.sprites_found += 1;
+                    self.eval = if wrapped {
+                        SpriteEval::Done
+                    } else if self.sprites_found == 8 {
+                        SpriteEval::Overflow
+                    } else {
+                        SpriteEval::CheckY
+                    };
+                }
+            }
+            SpriteEval::Overflow => {
+                if self.sprite_in_range(val) {
+                    self.status.insert(PpuStatus::SPRITE_OVERFLOW);
+                    self.oam_addr = self.oam_addr.wrapping_add(1);
+                    self.eval = SpriteEval::OverflowCopy(3);
+                } else {
+                    // The hardware bug: `m` is incremented along with `n` (without carry)
+                    let m = self.oam_addr.wrapping_add(1) & 0x3;
+                    let wrapped = self.next_sprite();
+                    self.oam_addr |= m;
+                    if wrapped {
+                        self.eval = SpriteEval::Done;
+                    }
+                }
+            }
+            SpriteEval::OverflowCopy(remaining) => {
+                self.oam_addr = self.oam_addr.wrapping_add(1);
+                self.eval = match remaining {
+                    1 => SpriteEval::Done,
+                    _ => SpriteEval::OverflowCopy(remaining - 1),
+                };
+            }
+            SpriteEval::Done => {
+                self.next_sprite();
+            }
+        }
+    }
+}
+
+/// Index into the 2K of nametable RAM for an address in $2000-$2FFF
+pub(crate) fn nametable_index(addr: u16, mirroring: Mirroring) -> usize {
+    let addr = addr as usize & 0x0fff;
+    let table = match mirroring {
+        Mirroring::Vertical | Mirroring::FourScreen => (addr >> 10) & 1,
+        Mirroring::Horizontal => (addr >> 11) & 1,
+    };
+    table * 0x400 + (addr & 0x3ff)
+}
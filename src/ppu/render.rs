@@ -0,0 +1,251 @@
+//! Drawing [`Ppu::picture`] a scanline or a dot at a time, see
+//! [`RenderMode`]. Memory is read without telling the board, so boards that
+//! switch CHR banks by what the PPU fetches (MMC5's 8x16 sprites) may draw
+//! the wrong tiles, and so may boards the CPU switches mid-line unless
+//! drawing goes a dot at a time. Pattern tiles come from
+//! [`Ppu::tile_cache`] where the board says where they are in CHR memory.
+
+use super::tile_cache::decode_row;
+use super::{Ppu, PpuCtrl, PpuMask, PpuStatus};
+use crate::mapper::{Mapper, PpuTarget};
+
+pub const PICTURE_WIDTH: usize = 256;
+pub const PICTURE_HEIGHT: usize = 240;
+
+/// How [`Ppu::picture`] is drawn
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum RenderMode {
+    /// Nothing is drawn, for frontends that don't show the picture
+    #[default]
+    Off,
+    /// Each line at once at the end of it, from the scroll position at its
+    /// start, which is much cheaper than following the fetches dot by dot.
+    /// Writes to PPUCTRL, PPUMASK, PPUSCROLL or PPUADDR in the middle of a
+    /// line switch the rest of it to [`RenderMode::Dot`], so split screens
+    /// and raster effects still show up where they were written. Sprite 0
+    /// hits are found once the line is drawn, at its end.
+    Scanline,
+    /// Each pixel at its dot, from the registers and CHR banks as they are
+    /// then. The slowest, for games whose boards switch banks in the middle
+    /// of lines.
+    Dot,
+}
+
+/// Where the part of the line not drawn yet starts
+#[derive(Clone, Copy, Debug, Default)]
+pub(super) struct LineStart {
+    /// First pixel not drawn
+    x: u16,
+    /// `v` as it would be at the start of the line to scroll like now
+    v: u16,
+    fine_x: u8,
+    /// Drawn a pixel each dot, in [`RenderMode::Dot`] or since a write in
+    /// the middle of the line
+    dots: bool,
+    /// The first of `Ppu::line_oam` is sprite 0
+    sprite_zero: bool,
+}
+
+/// A sprite's pixels on the line being drawn
+pub(super) struct LineSprite {
+    x: u8,
+    /// 2-bit pixels, left to right, 0 is transparent
+    pixels: [u8; 8],
+    attr: u8,
+    zero: bool,
+}
+
+/// `v` with coarse X moved by `tiles`, into the next nametable like the
+/// coarse X increment does
+fn move_tiles(v: u16, tiles: i32) -> u16 {
+    let x = (v & 0x1f) as i32 | ((v >> 5) & 0x20) as i32;
+    let x = (x + tiles).rem_euclid(64) as u16;
+    (v & !0x041f) | (x & 0x1f) | ((x & 0x20) << 5)
+}
+
+impl Ppu {
+    fn drawing(&self) -> bool {
+        self.render_mode != RenderMode::Off && self.scanline < 240
+    }
+
+    /// At dot 1 of a visible line, before secondary OAM is cleared for the
+    /// next one
+    pub(super) fn start_line(&mut self) {
+        self.line = LineStart {
+            dots: self.render_mode == RenderMode::Dot,
+            ..LineStart::default()
+        };
+        self.latch_line(0);
+        // The sprites evaluated on the line before, so none on the first
+        self.line_oam.clear();
+        if self.scanline > 0 {
+            let found = self.secondary_oam[..self.sprite_count as usize * 4].chunks_exact(4);
+            let extra = self.extra_oam[..self.extra_sprite_count as usize * 4].chunks_exact(4);
+            for entry in found.chain(extra) {
+                self.line_oam
+                    .push(entry.try_into().expect("4 bytes a sprite"));
+            }
+            self.line.sprite_zero = self.sprite_zero_next;
+        }
+    }
+
+    /// Keep drawing from pixel `x` with the scroll as it is now. `v` is
+    /// ahead of the pixel shown by the two tiles fetched at the end of the
+    /// last line and a tile every 8 dots since.
+    fn latch_line(&mut self, x: u16) {
+        self.line.x = x;
+        self.line.v = move_tiles(self.vram_addr, -2 - (x / 8) as i32);
+        self.line.fine_x = self.fine_x;
+    }
+
+    /// Before a CPU write to `reg` that changes how the rest of the line is
+    /// drawn, draw up to the pixel the PPU is on and draw the rest a dot at
+    /// a time
+    pub(super) fn split_line(&mut self, reg: u16, cart: &mut dyn Mapper) {
+        if !self.drawing() || !matches!(reg & 0x7, 0 | 1 | 5 | 6) {
+            return;
+        }
+        if !(2..=257).contains(&self.dot) {
+            return;
+        }
+        self.draw_pixels(self.dot - 1, cart);
+        if !self.line.dots {
+            self.line.dots = true;
+            self.split_lines += 1;
+        }
+    }
+
+    /// At dots 1 to 256 of a visible line, the pixel before the dot
+    pub(super) fn draw_dot(&mut self, cart: &mut dyn Mapper) {
+        if self.line.dots {
+            self.latch_line(self.line.x);
+            self.draw_pixels(self.dot, cart);
+        }
+    }
+
+    /// At dot 257, the end of a visible line
+    pub(super) fn finish_line(&mut self, cart: &mut dyn Mapper) {
+        self.draw_pixels(PICTURE_WIDTH as u16, cart);
+    }
+
+    fn draw_pixels(&mut self, end: u16, cart: &mut dyn Mapper) {
+        let start = self.line.x;
+        if start >= end {
+            return;
+        }
+        self.line.x = end;
+        let row = self.scanline as usize * PICTURE_WIDTH;
+        let (start, end) = (start as usize, end as usize);
+        if !self.rendering_enabled() {
+            // The backdrop, or the palette entry `v` points at
+            let addr = match self.vram_addr & 0x3f00 {
+                0x3f00 => self.vram_addr,
+                _ => 0x3f00,
+            };
+            let color = self.read_palette(addr);
+            self.picture[row + start..row + end].fill(color);
+            return;
+        }
+        // Taken so drawing can borrow `self`, and put back to keep its memory
+        let mut sprites = std::mem::take(&mut self.line_sprites);
+        sprites.clear();
+        if self.mask.contains(PpuMask::SHOW_SPRITES) {
+            self.find_line_sprites(&mut sprites, cart);
+        }
+        let show_bg = self.mask.contains(PpuMask::SHOW_BG);
+        let mut tile: Option<(u16, [u8; 8], u8)> = None;
+        for x in start..end {
+            let bg = if show_bg && (x >= 8 || self.mask.contains(PpuMask::SHOW_BG_LEFT)) {
+                let scrolled = x + self.line.fine_x as usize;
+                let v = move_tiles(self.line.v, (scrolled / 8) as i32);
+                if tile.is_none_or(|(fetched, ..)| fetched != v) {
+                    tile = Some(self.bg_tile_row(v, cart));
+                }
+                let (_, pixels, palette) = tile.expect("fetched above");
+                (pixels[scrolled % 8], palette)
+            } else {
+                (0, 0)
+            };
+            let sprite = (x >= 8 || self.mask.contains(PpuMask::SHOW_SPRITES_LEFT))
+                .then(|| {
+                    sprites.iter().find_map(|sprite| {
+                        let offset = x.checked_sub(sprite.x as usize)?;
+                        let pixel = *sprite.pixels.get(offset)?;
+                        (pixel != 0).then_some((pixel, sprite))
+                    })
+                })
+                .flatten();
+            let index = match (bg, sprite) {
+                ((0, _), None) => 0,
+                ((pixel, palette), None) => palette << 2 | pixel,
+                ((bg_pixel, palette), Some((pixel, sprite))) => {
+                    if sprite.zero && bg_pixel != 0 && x != 255 {
+                        self.status.insert(PpuStatus::SPRITE_ZERO_HIT);
+                    }
+                    if bg_pixel != 0 && sprite.attr & 0x20 != 0 {
+                        palette << 2 | bg_pixel
+                    } else {
+                        0x10 | (sprite.attr & 0x3) << 2 | pixel
+                    }
+                }
+            };
+            self.picture[row + x] = self.read_palette(0x3f00 | index as u16);
+        }
+        self.line_sprites = sprites;
+    }
+
+    /// The pixels and palette of the background tile at `v`
+    fn bg_tile_row(&mut self, v: u16, cart: &mut dyn Mapper) -> (u16, [u8; 8], u8) {
+        let tile = self.read_vram(0x2000 | (v & 0x0fff), cart);
+        let attr_addr = 0x23c0 | (v & 0x0c00) | ((v >> 4) & 0x38) | ((v >> 2) & 0x07);
+        let attr = self.read_vram(attr_addr, cart);
+        let shift = ((v >> 4) & 0x4) | (v & 0x2);
+        let table = match self.ctrl.contains(PpuCtrl::BG_TABLE) {
+            true => 0x1000,
+            false => 0,
+        };
+        let addr = table | (tile as u16) << 4 | v >> 12;
+        (v, self.pattern_row(addr, cart), attr >> shift & 0x3)
+    }
+
+    /// The pixels of the tile row whose low plane is at `addr`
+    fn pattern_row(&mut self, addr: u16, cart: &mut dyn Mapper) -> [u8; 8] {
+        let offset = match cart.ppu_target(addr) {
+            PpuTarget::Cartridge => cart.chr_offset(addr),
+            PpuTarget::Ciram(_) => None,
+        };
+        match offset {
+            Some(offset) => {
+                let tile = addr & !0xf;
+                let read = |i: usize| cart.ppu_read(tile + i as u16);
+                self.tile_cache.row(offset, (addr & 0x7) as usize, read)
+            }
+            None => decode_row([self.read_vram(addr, cart), self.read_vram(addr | 0x8, cart)]),
+        }
+    }
+
+    /// Add the sprites evaluation found for this line to `sprites`,
+    /// frontmost first. Like on hardware they are found a line early, so a
+    /// sprite at Y shows from line Y + 1.
+    fn find_line_sprites(&mut self, sprites: &mut Vec<LineSprite>, cart: &mut dyn Mapper) {
+        let line = self.scanline.wrapping_sub(1);
+        for n in 0..self.line_oam.len() {
+            let [y, tile, attr, x] = self.line_oam[n];
+            let row = line.wrapping_sub(y as u16);
+            if row >= self.sprite_height() {
+                continue;
+            }
+            let addr = self.sprite_row_addr(tile, attr, row);
+            let mut pixels = self.pattern_row(addr, cart);
+            if attr & 0x40 != 0 {
+                pixels.reverse();
+            }
+            sprites.push(LineSprite {
+                x,
+                pixels,
+                attr,
+                zero: n == 0 && self.line.sprite_zero,
+            });
+        }
+    }
+}
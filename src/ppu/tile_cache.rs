@@ -0,0 +1,86 @@
+//! Pattern tiles decoded once for [`super::RenderMode::Scanline`] instead of
+//! every time a line shows them. Tiles are found by where they are in CHR
+//! memory, see [`crate::mapper::Mapper::chr_offset`], so switching banks
+//! just picks other tiles and only writes to CHR RAM need to drop any.
+
+/// A tile's 2-bit pixels, row by row, left to right
+pub type TilePixels = [u8; 64];
+
+/// Decoded tiles and how often they were there when needed
+#[derive(Clone, Debug, Default)]
+pub struct TileCache {
+    /// By CHR offset / 16, `None` until decoded or after a write
+    tiles: Vec<Option<TilePixels>>,
+    /// Tile rows drawn from a tile decoded before
+    pub hits: u64,
+    /// Tile rows that had to decode their tile first
+    pub misses: u64,
+}
+
+/// The pixels of a tile row from its two bit planes
+pub(super) fn decode_row(planes: [u8; 2]) -> [u8; 8] {
+    std::array::from_fn(|i| (planes[0] >> (7 - i) & 1) | (planes[1] >> (7 - i) & 1) << 1)
+}
+
+impl TileCache {
+    /// How many tile rows were drawn without decoding, between 0 and 1.
+    /// `None` until something was drawn from the cache.
+    pub fn hit_rate(&self) -> Option<f64> {
+        let total = self.hits + self.misses;
+        (total > 0).then(|| self.hits as f64 / total as f64)
+    }
+
+    /// Tiles decoded now
+    pub fn len(&self) -> usize {
+        self.tiles.iter().flatten().count()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Forget every tile, like after CHR memory changed all at once. The
+    /// statistics are kept.
+    pub fn clear(&mut self) {
+        self.tiles.clear();
+    }
+
+    /// CHR memory at `offset` was written
+    pub(super) fn invalidate(&mut self, offset: usize) {
+        if let Some(tile) = self.tiles.get_mut(offset / 16) {
+            *tile = None;
+        }
+    }
+
+    /// Row `row` of the tile at CHR `offset`, decoded from its 16 bytes read
+    /// with `read` if it isn't there yet
+    pub(super) fn row(
+        &mut self,
+        offset: usize,
+        row: usize,
+        read: impl FnMut(usize) -> u8,
+    ) -> [u8; 8] {
+        let index = offset / 16;
+        if index >= self.tiles.len() {
+            self.tiles.resize(index + 1, None);
+        }
+        let tile = match &mut self.tiles[index] {
+            Some(tile) => {
+                self.hits += 1;
+                tile
+            }
+            empty => {
+                self.misses += 1;
+                let bytes: [u8; 16] = std::array::from_fn(read);
+                let mut tile = [0; 64];
+                for (y, pixels) in tile.chunks_exact_mut(8).enumerate() {
+                    pixels.copy_from_slice(&decode_row([bytes[y], bytes[y + 8]]));
+                }
+                empty.insert(tile)
+            }
+        };
+        tile[row * 8..row * 8 + 8]
+            .try_into()
+            .expect("8 pixels a row")
+    }
+}
@@ -0,0 +1,117 @@
+//! Sound register writes with the CPU cycle of each, like a VGM log. Turns
+//! gameplay audio into register dumps for players, and lets the APU be
+//! compared write for write with other emulators.
+
+use crate::rom::Region;
+
+/// VGM files count time in samples at this rate
+const VGM_SAMPLE_RATE: u64 = 44100;
+/// The VGM 1.61 header, which has the 2A03 clock at $84
+const VGM_HEADER_LEN: usize = 0x100;
+
+/// A write to an APU register or the cartridge's sound chip
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RegisterWrite {
+    /// CPU cycles since the log started
+    pub cycle: u64,
+    pub addr: u16,
+    pub val: u8,
+}
+
+/// Writes to $4000-$4013, $4015 and $4017, and to the registers of the
+/// cartridge's sound chip (see [`crate::mapper::Mapper::audio_register`]).
+/// Off until started, since it costs a check on every write.
+#[derive(Default)]
+pub struct RegisterLog {
+    enabled: bool,
+    /// CPU cycle the log started on
+    start: u64,
+    /// CPU cycles the log ran for, once stopped
+    length: u64,
+    writes: Vec<RegisterWrite>,
+}
+
+impl RegisterLog {
+    /// Clear the log and record from CPU cycle `cycle` on
+    pub fn start(&mut self, cycle: u64) {
+        self.enabled = true;
+        self.start = cycle;
+        self.length = 0;
+        self.writes.clear();
+    }
+
+    /// Stop recording at CPU cycle `cycle`, keeping what was logged
+    pub fn stop(&mut self, cycle: u64) {
+        if self.enabled {
+            self.enabled = false;
+            self.length = cycle - self.start;
+        }
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    pub fn record(&mut self, cycle: u64, addr: u16, val: u8) {
+        if self.enabled {
+            self.writes.push(RegisterWrite {
+                cycle: cycle - self.start,
+                addr,
+                val,
+            });
+        }
+    }
+
+    pub fn writes(&self) -> &[RegisterWrite] {
+        &self.writes
+    }
+
+    /// A write per line, with the cycle, address and value, like `1234 $4000 $BF`
+    pub fn to_text(&self) -> String {
+        self.writes
+            .iter()
+            .map(|write| format!("{} ${:04X} ${:02X}\n", write.cycle, write.addr, write.val))
+            .collect()
+    }
+
+    /// A VGM 1.61 file of the APU writes, with the 2A03 clocked for `region`.
+    /// VGM has no place for the sound chips of NES cartridges, so their
+    /// writes are left out. See https://vgmrips.net/wiki/VGM_Specification
+    pub fn to_vgm(&self, region: Region) -> Vec<u8> {
+        let clock = region.cpu_clock_rate() as u64;
+        let samples = |cycle: u64| cycle * VGM_SAMPLE_RATE / clock;
+        let mut data = Vec::new();
+        let mut waited = 0;
+        let mut wait_until = |data: &mut Vec<u8>, sample: u64| {
+            while waited < sample {
+                let wait = (sample - waited).min(u16::MAX as u64);
+                data.push(0x61);
+                data.extend_from_slice(&(wait as u16).to_le_bytes());
+                waited += wait;
+            }
+        };
+        for write in &self.writes {
+            if let 0x4000..=0x4017 = write.addr {
+                wait_until(&mut data, samples(write.cycle));
+                data.extend_from_slice(&[0xb4, (write.addr - 0x4000) as u8, write.val]);
+            }
+        }
+        let last = self.writes.last().map_or(0, |write| write.cycle);
+        wait_until(&mut data, samples(self.length.max(last)));
+        data.push(0x66);
+
+        let mut vgm = vec![0; VGM_HEADER_LEN];
+        let mut put = |offset: usize, val: u32| {
+            vgm[offset..offset + 4].copy_from_slice(&val.to_le_bytes());
+        };
+        put(0x04, (VGM_HEADER_LEN + data.len() - 4) as u32);
+        put(0x08, 0x161);
+        put(0x18, waited as u32);
+        put(0x24, region.refresh_rate().round() as u32);
+        put(0x34, (VGM_HEADER_LEN - 0x34) as u32);
+        put(0x84, clock as u32);
+        vgm[..4].copy_from_slice(b"Vgm ");
+        vgm.extend(data);
+        vgm
+    }
+}
@@ -1,15 +1,205 @@
+use std::fs;
+use std::path::Path;
+
+use crate::compat::CompatReport;
+use crate::error::RomError;
+use crate::patch;
+use crate::savestate::{StateStream, Stateful};
+
 #[derive(Clone, Debug)]
 pub struct Rom {
     pub prg_rom: Vec<u8>,
     pub chr_rom: Vec<u8>,
     pub mapper: u8,
+    /// NES 2.0 submapper, 0 for iNES 1.0 headers
+    pub submapper: u8,
     pub mirroring: Mirroring,
+    pub region: Region,
+    /// PRG RAM size in bytes, battery backed or not
+    pub prg_ram_size: usize,
+    /// How much of the PRG RAM is battery backed, it comes first
+    pub prg_nvram_size: usize,
+    /// Whether the cartridge keeps its RAM with a battery
+    pub battery: bool,
+    /// Sides of a Famicom Disk System image, [`FDS_SIDE_LEN`] bytes each,
+    /// empty for cartridges. PRG ROM holds the disk system's BIOS.
+    pub disk_sides: Vec<Vec<u8>>,
+    /// Header fields corrected by the ROM database
+    #[cfg(feature = "romdb")]
+    pub overrides: Vec<crate::romdb::HeaderOverride>,
+    /// The file this was parsed from, which patches apply to
+    ines: Vec<u8>,
 }
 
 impl Rom {
-    pub fn new(data: &[u8]) -> Result<Rom, String> {
+    /// Parse an iNES image, correcting its header from the embedded ROM
+    /// database when the `romdb` feature is enabled
+    pub fn new(data: &[u8]) -> Result<Rom, RomError> {
+        #[allow(unused_mut)]
+        let mut rom = parse_ines(data)?;
+        #[cfg(feature = "romdb")]
+        crate::romdb::RomDb::embedded().apply(&mut rom);
+        Ok(rom)
+    }
+
+    /// Parse an iNES image, trusting its header
+    pub fn from_ines(data: &[u8]) -> Result<Rom, RomError> {
         parse_ines(data)
     }
+
+    /// Parse a Famicom Disk System image in the `.fds` layout, with or
+    /// without its 16 byte header, to run on the disk system's `bios`
+    pub fn from_fds(image: &[u8], bios: &[u8]) -> Result<Rom, RomError> {
+        if bios.len() != 0x2000 {
+            return Err(RomError::Disk(format!(
+                "the BIOS is {} bytes, not 8K",
+                bios.len()
+            )));
+        }
+        let sides = match image.get(0..4) {
+            Some(magic) if magic == FDS_MAGIC => &image[16.min(image.len())..],
+            _ => image,
+        };
+        if sides.is_empty() || sides.len() % FDS_SIDE_LEN != 0 {
+            return Err(RomError::Disk(format!(
+                "{} bytes isn't a whole number of {FDS_SIDE_LEN} byte sides",
+                sides.len()
+            )));
+        }
+        let disk_sides: Vec<Vec<u8>> = sides.chunks(FDS_SIDE_LEN).map(Vec::from).collect();
+        if let Some(side) = disk_sides
+            .iter()
+            .position(|side| !side.starts_with(FDS_SIDE_MAGIC))
+        {
+            return Err(RomError::Disk(format!("side {side} has no disk header")));
+        }
+        Ok(Rom {
+            prg_rom: bios.to_vec(),
+            chr_rom: Vec::new(),
+            mapper: 20,
+            submapper: 0,
+            mirroring: Mirroring::Horizontal,
+            region: Region::Ntsc,
+            prg_ram_size: 0x8000,
+            prg_nvram_size: 0,
+            battery: false,
+            disk_sides,
+            #[cfg(feature = "romdb")]
+            overrides: Vec::new(),
+            ines: image.to_vec(),
+        })
+    }
+
+    /// Load a Famicom Disk System image and the BIOS at `bios`, usually
+    /// called `disksys.rom`. Patches apply like for [`Rom::load`].
+    pub fn load_fds(path: &Path, bios: &Path, auto_patch: bool) -> Result<Rom, RomError> {
+        let mut rom = Rom::from_fds(&read(path)?, &read(bios)?)?;
+        if auto_patch {
+            rom.auto_patch(path)?;
+        }
+        Ok(rom)
+    }
+
+    /// Load an iNES file. With `auto_patch`, a patch with the same name
+    /// next to it (`game.ips` or `game.bps` for `game.nes`) is applied.
+    pub fn load(path: &Path, auto_patch: bool) -> Result<Rom, RomError> {
+        let mut rom = Rom::new(&read(path)?)?;
+        if auto_patch {
+            rom.auto_patch(path)?;
+        }
+        Ok(rom)
+    }
+
+    /// Apply the patch next to `path`, if there is one
+    fn auto_patch(&mut self, path: &Path) -> Result<(), RomError> {
+        let ips = path.with_extension("ips");
+        let bps = path.with_extension("bps");
+        if ips.exists() {
+            self.apply_ips(&read(&ips)?)?;
+        } else if bps.exists() {
+            self.apply_bps(&read(&bps)?)?;
+        }
+        Ok(())
+    }
+
+    /// CRC-32 of the PRG and CHR ROM, and disk sides, identifies the game
+    /// regardless of its header
+    pub fn hash(&self) -> u32 {
+        let mut hasher = crc32fast::Hasher::new();
+        hasher.update(&self.prg_rom);
+        hasher.update(&self.chr_rom);
+        for side in &self.disk_sides {
+            hasher.update(side);
+        }
+        hasher.finalize()
+    }
+
+    /// What the game needs that isn't emulated, worth telling the player
+    /// before it goes wrong
+    pub fn compat(&self) -> CompatReport {
+        CompatReport::new(self)
+    }
+
+    /// The iNES file, header included, or the disk image
+    pub(crate) fn file(&self) -> &[u8] {
+        &self.ines
+    }
+
+    /// Whether the header is NES 2.0 rather than iNES 1.0
+    pub fn is_nes2(&self) -> bool {
+        self.disk_sides.is_empty() && self.ines[7] & 0x0c == 0x08
+    }
+
+    /// Apply an IPS patch to the original file and reload it
+    pub fn apply_ips(&mut self, patch: &[u8]) -> Result<(), RomError> {
+        *self = self.reload(&patch::apply_ips(&self.ines, patch).map_err(RomError::Patch)?)?;
+        Ok(())
+    }
+
+    /// Apply a BPS patch to the original file and reload it.
+    /// Fails if the checksums in the patch don't match.
+    pub fn apply_bps(&mut self, patch: &[u8]) -> Result<(), RomError> {
+        *self = self.reload(&patch::apply_bps(&self.ines, patch).map_err(RomError::Patch)?)?;
+        Ok(())
+    }
+
+    /// Parse a patched copy of the file, disk images keep their BIOS
+    fn reload(&self, file: &[u8]) -> Result<Rom, RomError> {
+        if self.disk_sides.is_empty() {
+            Rom::new(file)
+        } else {
+            Rom::from_fds(file, &self.prg_rom)
+        }
+    }
+}
+
+/// TV system the cartridge was made for, which decides the console timing
+#[derive(Debug, Default, PartialEq, Eq, Copy, Clone, Hash)]
+pub enum Region {
+    #[default]
+    Ntsc,
+    Pal,
+    /// The Dendy and other Famiclones built on the UMC UA6527P: PAL's 312
+    /// scanlines at 50 Hz, but 3 dots per CPU cycle like NTSC, NTSC's APU
+    /// rates and vblank starting 51 scanlines after the picture
+    Dendy,
+}
+
+impl Region {
+    /// CPU (and APU) clock in Hz
+    pub fn cpu_clock_rate(self) -> f64 {
+        match self {
+            Region::Ntsc => 1_789_773.0,
+            Region::Pal => 1_662_607.0,
+            Region::Dendy => 1_773_448.0,
+        }
+    }
+
+    /// Frames per second, about 60.1 on NTSC and 50.007 on PAL and Dendy
+    pub fn refresh_rate(self) -> f64 {
+        let (num, den) = crate::dump::frame_rate(self);
+        num as f64 / den as f64
+    }
 }
 
 #[derive(Debug, PartialEq, Eq, Copy, Clone, Hash)]
@@ -19,14 +209,57 @@ pub enum Mirroring {
     FourScreen,
 }
 
+impl Stateful for Mirroring {
+    fn stream(&mut self, s: &mut StateStream) {
+        let mut tag = *self as u8;
+        tag.stream(s);
+        *self = match tag {
+            0 => Mirroring::Vertical,
+            1 => Mirroring::Horizontal,
+            2 => Mirroring::FourScreen,
+            _ => {
+                s.fail("invalid mirroring");
+                Mirroring::Vertical
+            }
+        };
+    }
+}
+
+fn read(path: &Path) -> Result<Vec<u8>, RomError> {
+    fs::read(path).map_err(|error| RomError::Io {
+        path: path.to_path_buf(),
+        error,
+    })
+}
+
 const NES_MAGIC: [u8; 4] = *b"NES\x1A";
-fn parse_ines(data: &[u8]) -> Result<Rom, String> {
-    if data[0..4] != NES_MAGIC {
-        return Err(String::from("Expected NES magic number"));
+const FDS_MAGIC: [u8; 4] = *b"FDS\x1A";
+/// Every disk side starts with its disk info block
+const FDS_SIDE_MAGIC: &[u8] = b"\x01*NINTENDO-HVC*";
+/// Bytes per disk side in `.fds` images, the blocks without their gaps and CRCs
+pub const FDS_SIDE_LEN: usize = 65500;
+fn parse_ines(data: &[u8]) -> Result<Rom, RomError> {
+    if data.get(0..4) != Some(&NES_MAGIC[..]) {
+        return Err(RomError::NotInes);
     }
 
-    let [prg_rom_size, chr_rom_size, flags6, flags7, _prg_ram_len, ..] = data[4..] else {
-        return Err(String::from("too short"));
+    let [
+        prg_rom_size,
+        chr_rom_size,
+        flags6,
+        flags7,
+        flags8,
+        flags9,
+        flags10,
+        _,
+        flags12,
+        ..,
+    ] = data[4..]
+    else {
+        return Err(RomError::Truncated {
+            expected: 16,
+            found: data.len(),
+        });
     };
 
     let prg_rom_size = prg_rom_size as usize * 0x4000;
@@ -35,16 +268,43 @@ fn parse_ines(data: &[u8]) -> Result<Rom, String> {
     let rom_mapper_lower = flags6 >> 4;
     let four_screen = (flags6 >> 3) & 0x1 != 0;
     let trainer = (flags6 >> 2) & 0x1 != 0;
-    // let battery_ram = (flags6 >> 1) & 0x1 != 0;
+    let battery = (flags6 >> 1) & 0x1 != 0;
     let vert_horiz = flags6 & 0x1 != 0;
 
     let rom_mapper_upper = flags7 >> 4;
     let ines_fmt_bits = (flags7 >> 2) & 0b11;
 
-    if ines_fmt_bits != 0 {
-        return Err(String::from("Only NES1.0 supported"));
+    let nes2 = ines_fmt_bits == 2;
+
+    if ines_fmt_bits != 0 && !nes2 {
+        return Err(RomError::UnsupportedFormat);
     }
 
+    // NES 2.0 headers reuse the iNES 1.0 PRG RAM size byte
+    let submapper = if nes2 { flags8 >> 4 } else { 0 };
+    // Multi-region carts run fine on NTSC
+    let region = match (nes2, flags12 & 3, flags9 & 1) {
+        (true, 1, _) | (false, _, 1) => Region::Pal,
+        (true, 3, _) => Region::Dendy,
+        _ => Region::Ntsc,
+    };
+    let shifted = |shift: u8| match shift {
+        0 => 0,
+        shift => 64usize << shift,
+    };
+    let prg_ram_size = match (nes2, flags8) {
+        (true, _) => shifted(flags10 & 0xf) + shifted(flags10 >> 4),
+        // 0 means 8K for compatibility
+        (false, 0) => 0x2000,
+        (false, units) => units as usize * 0x2000,
+    };
+    // iNES 1.0 headers only say whether there's a battery
+    let prg_nvram_size = match nes2 {
+        true => shifted(flags10 >> 4),
+        false if battery => prg_ram_size,
+        false => 0,
+    };
+
     let mirroring = match (four_screen, vert_horiz) {
         (true, _) => Mirroring::FourScreen,
         (false, true) => Mirroring::Vertical,
@@ -53,14 +313,30 @@ fn parse_ines(data: &[u8]) -> Result<Rom, String> {
 
     let trainer_offset = 512 & (-(trainer as isize) as usize);
 
-    let mapper = rom_mapper_lower & (rom_mapper_upper << 4);
+    let mapper = rom_mapper_lower | (rom_mapper_upper << 4);
     let prg_rom_start = 16 + trainer_offset;
     let chr_rom_start = prg_rom_start + prg_rom_size;
+    let end = chr_rom_start + chr_rom_size;
+    if data.len() < end {
+        return Err(RomError::Truncated {
+            expected: end,
+            found: data.len(),
+        });
+    }
 
     Ok(Rom {
         prg_rom: Vec::from(&data[prg_rom_start..prg_rom_start + prg_rom_size]),
         chr_rom: Vec::from(&data[chr_rom_start..chr_rom_start + chr_rom_size]),
         mapper,
+        submapper,
         mirroring,
+        region,
+        prg_ram_size,
+        prg_nvram_size,
+        battery,
+        disk_sides: Vec::new(),
+        #[cfg(feature = "romdb")]
+        overrides: Vec::new(),
+        ines: data.to_vec(),
     })
 }
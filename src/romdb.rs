@@ -0,0 +1,139 @@
+use std::collections::HashMap;
+use std::sync::OnceLock;
+
+use crate::rom::{Mirroring, Rom};
+
+const EMBEDDED: &str = include_str!("../data/romdb.csv");
+
+/// What a database entry says about a cartridge
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DbEntry {
+    pub mapper: u8,
+    /// `None` where the database doesn't tell submappers apart
+    pub submapper: Option<u8>,
+    /// `None` for boards that switch mirroring themselves
+    pub mirroring: Option<Mirroring>,
+    pub prg_ram_size: usize,
+    pub name: String,
+}
+
+/// A header field the database disagreed with, replaced at load time
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HeaderOverride {
+    Mapper { header: u8, db: u8 },
+    Submapper { header: u8, db: u8 },
+    Mirroring { header: Mirroring, db: Mirroring },
+    PrgRamSize { header: usize, db: usize },
+}
+
+/// Cartridges keyed by the CRC32 of their PRG and CHR ROM
+#[derive(Default)]
+pub struct RomDb {
+    entries: HashMap<u32, DbEntry>,
+}
+
+impl RomDb {
+    /// One entry per line: `crc32,mapper,submapper,mirroring,prg_ram_kb,name`,
+    /// with mirroring one of `H`, `V` or `4`. The submapper and mirroring
+    /// can be `-` to keep what the header says. Lines starting with `#` are
+    /// ignored. `cargo run --example romdb` makes them from NesCartDB's XML.
+    pub fn parse(data: &str) -> Result<RomDb, String> {
+        let mut entries = HashMap::new();
+        for (i, line) in data.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let err = || format!("Invalid ROM database entry on line {}", i + 1);
+            let [crc, mapper, submapper, mirroring, prg_ram_kb, name] =
+                line.splitn(6, ',').collect::<Vec<_>>()[..]
+            else {
+                return Err(err());
+            };
+            let mirroring = match mirroring {
+                "H" => Some(Mirroring::Horizontal),
+                "V" => Some(Mirroring::Vertical),
+                "4" => Some(Mirroring::FourScreen),
+                "-" => None,
+                _ => return Err(err()),
+            };
+            let submapper = match submapper {
+                "-" => None,
+                submapper => Some(submapper.parse().map_err(|_| err())?),
+            };
+            let entry = DbEntry {
+                mapper: mapper.parse().map_err(|_| err())?,
+                submapper,
+                mirroring,
+                prg_ram_size: prg_ram_kb.parse::<usize>().map_err(|_| err())? * 1024,
+                name: name.to_string(),
+            };
+            entries.insert(u32::from_str_radix(crc, 16).map_err(|_| err())?, entry);
+        }
+        Ok(RomDb { entries })
+    }
+
+    /// The database built into the binary
+    pub fn embedded() -> &'static RomDb {
+        static DB: OnceLock<RomDb> = OnceLock::new();
+        DB.get_or_init(|| RomDb::parse(EMBEDDED).expect("embedded ROM database is valid"))
+    }
+
+    pub fn get(&self, crc: u32) -> Option<&DbEntry> {
+        self.entries.get(&crc)
+    }
+
+    /// Replace the header fields of `rom` the database knows better,
+    /// recording each change in [`Rom::overrides`]
+    pub fn apply(&self, rom: &mut Rom) {
+        let Some(entry) = self.get(rom_crc(rom)) else {
+            return;
+        };
+        if rom.mapper != entry.mapper {
+            rom.overrides.push(HeaderOverride::Mapper {
+                header: rom.mapper,
+                db: entry.mapper,
+            });
+            rom.mapper = entry.mapper;
+        }
+        if let Some(submapper) = entry.submapper
+            && rom.submapper != submapper
+        {
+            rom.overrides.push(HeaderOverride::Submapper {
+                header: rom.submapper,
+                db: submapper,
+            });
+            rom.submapper = submapper;
+        }
+        if let Some(mirroring) = entry.mirroring
+            && rom.mirroring != mirroring
+        {
+            rom.overrides.push(HeaderOverride::Mirroring {
+                header: rom.mirroring,
+                db: mirroring,
+            });
+            rom.mirroring = mirroring;
+        }
+        if rom.prg_ram_size != entry.prg_ram_size {
+            rom.overrides.push(HeaderOverride::PrgRamSize {
+                header: rom.prg_ram_size,
+                db: entry.prg_ram_size,
+            });
+            // The database doesn't say, a battery keeps all of it
+            let all_battery = rom.battery && rom.prg_nvram_size == rom.prg_ram_size;
+            rom.prg_ram_size = entry.prg_ram_size;
+            rom.prg_nvram_size = match all_battery {
+                true => rom.prg_ram_size,
+                false => rom.prg_nvram_size.min(rom.prg_ram_size),
+            };
+        }
+    }
+}
+
+/// CRC32 of PRG ROM followed by CHR ROM, as used by NesCartDB and No-Intro
+pub fn rom_crc(rom: &Rom) -> u32 {
+    let mut hasher = crc32fast::Hasher::new();
+    hasher.update(&rom.prg_rom);
+    hasher.update(&rom.chr_rom);
+    hasher.finalize()
+}
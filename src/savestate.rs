@@ -0,0 +1,530 @@
+//! Savestates, snapshots of everything that changes while the console runs.
+//!
+//! Components implement [`Stateful`] by streaming their fields in a fixed
+//! order. The same code saves and loads, so the two can't get out of step.
+//! Settings (region, overclocking, audio mixing) and ROM contents aren't
+//! part of a state, they come from the loaded game.
+//!
+//! States remember the [`VERSION`] they were saved with. Components that
+//! changed what they stream check [`StateStream::version`] and read the
+//! older layout, filling in what it didn't have, so states back to
+//! [`OLDEST_VERSION`] still load. Anything else is a [`StateVersionError`].
+
+use std::fmt;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+
+use log::warn;
+
+use crate::console::Console;
+use crate::error::StateError;
+
+const MAGIC: [u8; 4] = *b"NESS";
+/// Bumped whenever a component changes what it streams, with a state of the
+/// version before saved to tests/states so loading it stays tested
+pub const VERSION: u8 = 9;
+/// The first version states still load from
+pub const OLDEST_VERSION: u8 = 1;
+/// Slots per game in [`SaveSlots`]
+pub const SLOT_COUNT: usize = 10;
+
+/// Something with state to save
+pub trait Stateful {
+    fn stream(&mut self, s: &mut StateStream);
+}
+
+/// Stream each field in order, `stream!(s, self.a, self.b)`
+macro_rules! stream {
+    ($s:expr, $($field:expr),+ $(,)?) => {
+        $($crate::savestate::Stateful::stream(&mut $field, $s);)+
+    };
+}
+pub(crate) use stream;
+
+/// Implement [`Stateful`] for bitflags types through their raw bits
+macro_rules! stateful_bitflags {
+    ($($ty:ty),+) => {
+        $(impl $crate::savestate::Stateful for $ty {
+            fn stream(&mut self, s: &mut $crate::savestate::StateStream) {
+                let mut bits = self.bits();
+                $crate::savestate::Stateful::stream(&mut bits, s);
+                *self = <$ty>::from_bits_retain(bits);
+            }
+        })+
+    };
+}
+pub(crate) use stateful_bitflags;
+
+/// Appends fields when saving, overwrites them when loading
+pub struct StateStream {
+    data: Vec<u8>,
+    pos: usize,
+    loading: bool,
+    /// Hash the saved bytes instead of keeping them
+    hasher: Option<crc32fast::Hasher>,
+    error: Option<StateError>,
+    /// The version of the data, [`VERSION`] unless loading an older state
+    version: u8,
+}
+
+impl StateStream {
+    pub fn saving() -> Self {
+        StateStream {
+            data: Vec::new(),
+            pos: 0,
+            loading: false,
+            hasher: None,
+            error: None,
+            version: VERSION,
+        }
+    }
+
+    /// Save into a CRC-32, see [`StateStream::finish_hash`]
+    pub fn hashing() -> Self {
+        StateStream {
+            hasher: Some(crc32fast::Hasher::new()),
+            ..Self::saving()
+        }
+    }
+
+    pub fn loading(data: Vec<u8>) -> Self {
+        Self::loading_version(data, VERSION)
+    }
+
+    /// Load data saved by an older `version`
+    pub fn loading_version(data: Vec<u8>, version: u8) -> Self {
+        StateStream {
+            data,
+            pos: 0,
+            loading: true,
+            hasher: None,
+            error: None,
+            version,
+        }
+    }
+
+    pub fn is_loading(&self) -> bool {
+        self.loading
+    }
+
+    /// The version of the data, for components to read layouts they no
+    /// longer save
+    pub fn version(&self) -> u8 {
+        self.version
+    }
+
+    /// Save `buf`, or fill it in when loading
+    pub fn bytes(&mut self, buf: &mut [u8]) {
+        if let Some(hasher) = &mut self.hasher {
+            hasher.update(buf);
+            return;
+        }
+        if !self.loading {
+            self.data.extend_from_slice(buf);
+            return;
+        }
+        match self.data.get(self.pos..self.pos + buf.len()) {
+            Some(saved) => {
+                buf.copy_from_slice(saved);
+                self.pos += buf.len();
+            }
+            None => {
+                self.truncated();
+            }
+        }
+    }
+
+    /// Mark the data being loaded as invalid, only the first error is kept
+    pub fn fail(&mut self, err: &str) {
+        self.error
+            .get_or_insert_with(|| StateError::Invalid(err.to_string()));
+    }
+
+    fn truncated(&mut self) {
+        self.error.get_or_insert(StateError::Truncated);
+        self.pos = self.data.len();
+    }
+
+    fn remaining(&self) -> usize {
+        self.data.len() - self.pos
+    }
+
+    /// CRC-32 of everything saved into a `hashing` stream, the same as
+    /// hashing the data of a regular save
+    pub fn finish_hash(self) -> u32 {
+        self.hasher.expect("not a hashing stream").finalize()
+    }
+
+    /// The saved data, or an error if what was loaded didn't line up
+    pub fn finish(self) -> Result<Vec<u8>, StateError> {
+        if let Some(err) = self.error {
+            return Err(err);
+        }
+        if self.loading && self.remaining() != 0 {
+            return Err(StateError::TrailingData);
+        }
+        Ok(self.data)
+    }
+}
+
+macro_rules! stateful_le_bytes {
+    ($($ty:ty),+) => {
+        $(impl Stateful for $ty {
+            fn stream(&mut self, s: &mut StateStream) {
+                let mut bytes = self.to_le_bytes();
+                s.bytes(&mut bytes);
+                *self = <$ty>::from_le_bytes(bytes);
+            }
+        })+
+    };
+}
+stateful_le_bytes!(u8, u16, u32, u64, i16, i32, f32);
+
+impl Stateful for bool {
+    fn stream(&mut self, s: &mut StateStream) {
+        let mut byte = *self as u8;
+        byte.stream(s);
+        *self = byte != 0;
+    }
+}
+
+impl<T: Stateful, const N: usize> Stateful for [T; N] {
+    fn stream(&mut self, s: &mut StateStream) {
+        for item in self {
+            item.stream(s);
+        }
+    }
+}
+
+impl<T: Stateful + Default> Stateful for Vec<T> {
+    fn stream(&mut self, s: &mut StateStream) {
+        let mut len = self.len() as u32;
+        len.stream(s);
+        if s.is_loading() {
+            // Every item takes at least a byte, don't allocate for garbage lengths
+            if len as usize > s.remaining() {
+                s.truncated();
+                return;
+            }
+            self.clear();
+            self.resize_with(len as usize, T::default);
+        }
+        for item in self {
+            item.stream(s);
+        }
+    }
+}
+
+impl<T: Stateful + Default> Stateful for Option<T> {
+    fn stream(&mut self, s: &mut StateStream) {
+        let mut some = self.is_some();
+        some.stream(s);
+        if !some {
+            *self = None;
+            return;
+        }
+        self.get_or_insert_with(T::default).stream(s);
+    }
+}
+
+impl<A: Stateful, B: Stateful> Stateful for (A, B) {
+    fn stream(&mut self, s: &mut StateStream) {
+        stream!(s, self.0, self.1);
+    }
+}
+
+/// A small screenshot shown next to a state
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Thumbnail {
+    pub width: u16,
+    pub height: u16,
+    /// 0RGB, row by row
+    pub pixels: Vec<u32>,
+}
+
+impl Stateful for Thumbnail {
+    fn stream(&mut self, s: &mut StateStream) {
+        stream!(s, self.width, self.height, self.pixels);
+        if self.pixels.len() != self.width as usize * self.height as usize {
+            s.fail("thumbnail size doesn't match its pixels");
+        }
+    }
+}
+
+/// A state saved with a version this build can't load
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct StateVersionError {
+    pub found: u8,
+}
+
+impl fmt::Display for StateVersionError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let made_by = if self.found > VERSION {
+            "a newer"
+        } else {
+            "an older"
+        };
+        write!(
+            f,
+            "savestate version {} is from {made_by} emulator, versions {OLDEST_VERSION} to {VERSION} are supported",
+            self.found
+        )
+    }
+}
+
+impl std::error::Error for StateVersionError {}
+
+impl From<StateVersionError> for String {
+    fn from(err: StateVersionError) -> Self {
+        err.to_string()
+    }
+}
+
+/// A console state and what it was saved from.
+/// See [`crate::console::Console::save_state`].
+#[derive(Clone, Debug)]
+pub struct SaveState {
+    /// Seconds since the Unix epoch
+    pub timestamp: u64,
+    /// [`crate::rom::Rom::hash`] of the game, states only load into the same game
+    pub rom_hash: u32,
+    /// PPU frame counter when saved
+    pub frame: u64,
+    pub thumbnail: Option<Thumbnail>,
+    /// [`VERSION`] of `data`
+    pub(crate) version: u8,
+    /// The streamed console
+    pub(crate) data: Vec<u8>,
+}
+
+impl Default for SaveState {
+    fn default() -> Self {
+        SaveState {
+            timestamp: 0,
+            rom_hash: 0,
+            frame: 0,
+            thumbnail: None,
+            version: VERSION,
+            data: Vec::new(),
+        }
+    }
+}
+
+impl Stateful for SaveState {
+    fn stream(&mut self, s: &mut StateStream) {
+        stream!(
+            s,
+            self.timestamp,
+            self.rom_hash,
+            self.frame,
+            self.thumbnail,
+            self.data
+        );
+        // States inside movies and netplay messages share their version
+        self.version = s.version();
+    }
+}
+
+impl SaveState {
+    /// The [`crate::console::Console::state_hash`] of the console when it was saved
+    pub fn state_hash(&self) -> u32 {
+        crc32fast::hash(&self.data)
+    }
+
+    /// The [`VERSION`] it was saved with, older states are migrated as they load
+    pub fn version(&self) -> u8 {
+        self.version
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut s = StateStream::saving();
+        s.bytes(&mut MAGIC.clone());
+        s.bytes(&mut [self.version]);
+        self.clone().stream(&mut s);
+        s.data
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, StateError> {
+        let mut s = StateStream::loading(bytes.to_vec());
+        let mut header = [0; 5];
+        s.bytes(&mut header);
+        if header[..4] != MAGIC {
+            return Err(StateError::NotAState);
+        }
+        let version = header[4];
+        if !(OLDEST_VERSION..=VERSION).contains(&version) {
+            return Err(StateVersionError { found: version }.into());
+        }
+        s.version = version;
+        let mut state = SaveState::default();
+        state.stream(&mut s);
+        s.finish()?;
+        Ok(state)
+    }
+}
+
+/// Numbered savestate files for a game, `game.state0` to `game.state9` next to `game.nes`
+pub struct SaveSlots {
+    rom_path: PathBuf,
+}
+
+impl SaveSlots {
+    pub fn new(rom_path: &Path) -> Self {
+        SaveSlots {
+            rom_path: rom_path.to_path_buf(),
+        }
+    }
+
+    pub fn path(&self, slot: usize) -> PathBuf {
+        assert!(slot < SLOT_COUNT, "no savestate slot {slot}");
+        self.rom_path.with_extension(format!("state{slot}"))
+    }
+
+    pub fn save(&self, slot: usize, state: &SaveState) -> Result<(), String> {
+        write_state(&self.path(slot), state)
+    }
+
+    /// The state in `slot`, `None` if it is empty
+    pub fn load(&self, slot: usize) -> Result<Option<SaveState>, String> {
+        read_state(&self.path(slot))
+    }
+
+    pub fn delete(&self, slot: usize) -> Result<(), String> {
+        let path = self.path(slot);
+        match fs::remove_file(&path) {
+            Err(e) if e.kind() != ErrorKind::NotFound => Err(format!("{}: {e}", path.display())),
+            _ => Ok(()),
+        }
+    }
+
+    /// Every slot in order, with `None` for empty slots.
+    /// Unreadable states are logged and listed as empty.
+    pub fn list(&self) -> Vec<Option<SaveState>> {
+        (0..SLOT_COUNT)
+            .map(|slot| {
+                self.load(slot).unwrap_or_else(|err| {
+                    warn!("Skipping savestate: {err}");
+                    None
+                })
+            })
+            .collect()
+    }
+}
+
+/// Write through a temporary file, so a crash while writing keeps the old state
+fn write_state(path: &Path, state: &SaveState) -> Result<(), String> {
+    let tmp = path.with_extension("tmp");
+    fs::write(&tmp, state.to_bytes())
+        .and_then(|()| fs::rename(&tmp, path))
+        .map_err(|e| format!("{}: {e}", path.display()))
+}
+
+fn read_state(path: &Path) -> Result<Option<SaveState>, String> {
+    match fs::read(path) {
+        Ok(bytes) => SaveState::from_bytes(&bytes)
+            .map(Some)
+            .map_err(|e| format!("{}: {e}", path.display())),
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(format!("{}: {e}", path.display())),
+    }
+}
+
+/// Periodic state in `game.autosave`, so progress survives a crash
+pub struct Autosave {
+    path: PathBuf,
+    /// Frames between saves, 0 only saves through [`Autosave::save_now`]
+    pub interval: u64,
+    /// Frame of the last save, or of the first tick
+    last_frame: Option<u64>,
+}
+
+impl Autosave {
+    pub fn new(rom_path: &Path, interval: u64) -> Self {
+        Autosave {
+            path: rom_path.with_extension("autosave"),
+            interval,
+            last_frame: None,
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Call once per frame, saves when `interval` frames have passed
+    pub fn tick(&mut self, console: &mut Console) -> Result<(), String> {
+        let frame = console.bus().ppu.frame;
+        // Count from the first tick, which may be after resuming from a state.
+        // Loading a state can also take the frame counter backwards.
+        let last = match self.last_frame {
+            Some(last) if last <= frame => last,
+            _ => {
+                self.last_frame = Some(frame);
+                return Ok(());
+            }
+        };
+        if self.interval > 0 && frame - last >= self.interval {
+            self.save_now(console)?;
+        }
+        Ok(())
+    }
+
+    /// Save right away, for when the emulator exits or panics
+    pub fn save_now(&mut self, console: &mut Console) -> Result<(), String> {
+        self.last_frame = Some(console.bus().ppu.frame);
+        write_state(&self.path, &console.save_state(None))
+    }
+
+    /// The last autosave, `None` if there is none
+    pub fn load(&self) -> Result<Option<SaveState>, String> {
+        read_state(&self.path)
+    }
+}
+
+/// Battery backed cartridge RAM in `game.sav`, kept between sessions like
+/// the cartridge would. Unlike savestates these work across emulators.
+/// Famicom Disk System games keep what they wrote to the disk in
+/// `game.fds.sav` instead, leaving the image as it was.
+pub struct BatterySave {
+    path: PathBuf,
+}
+
+impl BatterySave {
+    pub fn new(rom_path: &Path) -> Self {
+        let disk = rom_path
+            .extension()
+            .is_some_and(|ext| ext.eq_ignore_ascii_case("fds"));
+        BatterySave {
+            path: rom_path.with_extension(if disk { "fds.sav" } else { "sav" }),
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Put the save back into the cartridge, `false` if there is none yet
+    pub fn load(&self, console: &mut Console) -> Result<bool, String> {
+        match fs::read(&self.path) {
+            Ok(data) => {
+                console.bus_mut().mapper.load_save_data(&data);
+                Ok(true)
+            }
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(false),
+            Err(e) => Err(format!("{}: {e}", self.path.display())),
+        }
+    }
+
+    /// Write the cartridge's battery backed memory, `false` if it has none
+    pub fn save(&self, console: &Console) -> Result<bool, String> {
+        let Some(data) = console.bus().mapper.save_data() else {
+            return Ok(false);
+        };
+        let tmp = self.path.with_extension("tmp");
+        fs::write(&tmp, data)
+            .and_then(|()| fs::rename(&tmp, &self.path))
+            .map_err(|e| format!("{}: {e}", self.path.display()))?;
+        Ok(true)
+    }
+}
@@ -0,0 +1,89 @@
+//! Events due at known times, so a component compares one deadline per tick
+//! instead of working out each of its conditions every time.
+//!
+//! A component keeps a [`Scheduler`] of its own events against its own
+//! clock, e.g. the APU frame counter's steps against the APU's CPU cycle
+//! count, and handles whatever has come due each time it ticks. Events are
+//! part of the component's state, so they go into savestates with it.
+//!
+//! Only the frame counter uses one. The PPU raises NMI on the dot it sets
+//! the vblank flag, which it steps to anyway, so there is nothing to poll.
+//! Mapper IRQ counters run on [`crate::mapper::Mapper::cpu_tick`], which
+//! boards need every cycle for their sound, disk drive or prescaler either
+//! way, and some games read or reload the counters mid-count. A deadline
+//! would have to be recomputed on each of those writes and would save no
+//! calls.
+
+use crate::savestate::{StateStream, Stateful};
+
+/// Events in the order they are due. Events due on the same tick come out
+/// in the order of `E`'s `Ord`, so the type decides which goes first.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Scheduler<E> {
+    /// Latest first, so the next event comes off the end
+    events: Vec<(u64, E)>,
+}
+
+impl<E> Default for Scheduler<E> {
+    fn default() -> Self {
+        Scheduler { events: Vec::new() }
+    }
+}
+
+impl<E: Ord> Scheduler<E> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add `event`, due once the clock reaches `at`
+    pub fn schedule(&mut self, at: u64, event: E) {
+        let index = self
+            .events
+            .partition_point(|(due, queued)| (*due, queued) > (at, &event));
+        self.events.insert(index, (at, event));
+    }
+
+    /// When the next event is due
+    pub fn next_at(&self) -> Option<u64> {
+        self.events.last().map(|&(at, _)| at)
+    }
+
+    /// Take the next event if it is due by `now`
+    pub fn pop_due(&mut self, now: u64) -> Option<E> {
+        if self.next_at()? > now {
+            return None;
+        }
+        self.events.pop().map(|(_, event)| event)
+    }
+
+    /// Drop every event `f` matches
+    pub fn cancel(&mut self, mut f: impl FnMut(&E) -> bool) {
+        self.events.retain(|(_, event)| !f(event));
+    }
+
+    pub fn clear(&mut self) {
+        self.events.clear();
+    }
+
+    pub fn len(&self) -> usize {
+        self.events.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.events.is_empty()
+    }
+
+    /// Pending events, soonest first
+    pub fn iter(&self) -> impl Iterator<Item = (u64, &E)> {
+        self.events.iter().rev().map(|(at, event)| (*at, event))
+    }
+}
+
+impl<E: Ord + Stateful + Default> Stateful for Scheduler<E> {
+    fn stream(&mut self, s: &mut StateStream) {
+        self.events.stream(s);
+        if !self.events.is_sorted_by(|a, b| a >= b) {
+            s.fail("scheduled events are out of order");
+        }
+    }
+}
@@ -0,0 +1,166 @@
+//! Headless end-to-end tests for game ROMs.
+//!
+//! A script is one command per line, each starting with the frame it applies to:
+//!
+//! ```text
+//! # Hold start for a few frames, then let go
+//! 30 input start
+//! 35 input none
+//! 120 memory $0770 $01      # byte at $0770 is $01 at the start of frame 120
+//! 120 expect [$07FE] >= 3   # any expression from `expr` is non-zero
+//! 200 pc $8E5C              # PC was $8E5C at some point before frame 200
+//! 240 hash $1A2B3C4D        # `Console::frame_hash` at the start of frame 240
+//! 240 state $5E6F7A8B       # `Console::state_hash` at the start of frame 240
+//! ```
+//!
+//! Input lines set the buttons held on controller 1 from that frame on.
+//! The script runs until the frame of its last command. The console draws
+//! with [`RenderMode::Scanline`], so a hash to compare against has to come
+//! from a console drawing the same way.
+
+use crate::clock::EmulatedClock;
+use crate::console::Console;
+use crate::expr::Expr;
+use crate::joypad::Buttons;
+use crate::ppu::RenderMode;
+use crate::rom::Rom;
+
+#[derive(Clone, Debug)]
+pub enum Assertion {
+    MemoryEquals {
+        addr: u16,
+        val: u8,
+    },
+    /// The expression is non-zero
+    Expect(Expr, String),
+    FrameHash(u32),
+    StateHash(u32),
+    /// PC is at `pc` at an instruction boundary before the frame
+    ReachesPc(u16),
+}
+
+#[derive(Clone, Debug)]
+enum Command {
+    Input(Buttons),
+    Assert(Assertion),
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct TestScript {
+    /// Frame and command, sorted by frame
+    commands: Vec<(u64, Command)>,
+}
+
+fn parse_number(word: &str) -> Result<u32, String> {
+    let parsed = match word.strip_prefix('$') {
+        Some(hex) => u32::from_str_radix(hex, 16),
+        None => word.parse(),
+    };
+    parsed.map_err(|_| format!("invalid number {word:?}"))
+}
+
+impl TestScript {
+    pub fn parse(src: &str) -> Result<Self, String> {
+        let mut commands = vec![];
+        for (i, line) in src.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or_default().trim();
+            if line.is_empty() {
+                continue;
+            }
+            let parse = || -> Result<(u64, Command), String> {
+                let mut words = line.split_whitespace();
+                let frame = parse_number(words.next().unwrap_or_default())? as u64;
+                let command = words.next().ok_or("missing command")?;
+                let args: Vec<&str> = words.collect();
+                let command = match (command, args.as_slice()) {
+                    ("input", ["none"]) => Command::Input(Buttons::empty()),
+                    ("input", names) => {
+                        let mut buttons = Buttons::empty();
+                        for name in names {
+                            buttons |= Buttons::from_name(&name.to_ascii_uppercase())
+                                .ok_or(format!("unknown button {name:?}"))?;
+                        }
+                        Command::Input(buttons)
+                    }
+                    ("memory", [addr, val]) => Command::Assert(Assertion::MemoryEquals {
+                        addr: parse_number(addr)? as u16,
+                        val: u8::try_from(parse_number(val)?)
+                            .map_err(|_| format!("{val} doesn't fit in a byte"))?,
+                    }),
+                    ("expect", _) => {
+                        let src = args.join(" ");
+                        Command::Assert(Assertion::Expect(Expr::parse(&src)?, src))
+                    }
+                    ("hash", [hash]) => Command::Assert(Assertion::FrameHash(parse_number(hash)?)),
+                    ("state", [hash]) => Command::Assert(Assertion::StateHash(parse_number(hash)?)),
+                    ("pc", [pc]) => Command::Assert(Assertion::ReachesPc(parse_number(pc)? as u16)),
+                    _ => return Err(format!("unknown command {command:?}")),
+                };
+                Ok((frame, command))
+            };
+            commands.push(parse().map_err(|err| format!("line {}: {err}", i + 1))?);
+        }
+        commands.sort_by_key(|(frame, _)| *frame);
+        Ok(TestScript { commands })
+    }
+
+    /// Run `rom` through the script, returning every failed assertion
+    pub fn run(&self, rom: Rom) -> Result<(), Vec<String>> {
+        let mut console = Console::new(rom);
+        console.set_time_source(EmulatedClock::default());
+        // So frame hashes cover the picture
+        console.bus_mut().ppu.render_mode = RenderMode::Scanline;
+        let mut failures = vec![];
+        let mut pcs_pending: Vec<u16> = self
+            .commands
+            .iter()
+            .filter_map(|(_, command)| match command {
+                Command::Assert(Assertion::ReachesPc(pc)) => Some(*pc),
+                _ => None,
+            })
+            .collect();
+        let mut pcs_reached = vec![];
+
+        for (frame, command) in &self.commands {
+            while console.bus().ppu.frame < *frame {
+                console.step();
+                let pc = console.cpu.pc;
+                if let Some(i) = pcs_pending.iter().position(|&pending| pending == pc) {
+                    pcs_reached.push(pcs_pending.swap_remove(i));
+                }
+            }
+            let failure = match command {
+                Command::Input(buttons) => {
+                    console.bus_mut().joypads[0].buttons = *buttons;
+                    None
+                }
+                Command::Assert(Assertion::MemoryEquals { addr, val }) => {
+                    let found = console.bus().peek(*addr);
+                    (found != *val).then(|| format!("${addr:04X} is ${found:02X}, not ${val:02X}"))
+                }
+                Command::Assert(Assertion::Expect(expr, src)) => {
+                    (expr.eval(&console.cpu) == 0).then(|| format!("{src} is false"))
+                }
+                Command::Assert(Assertion::FrameHash(hash)) => {
+                    let found = console.frame_hash();
+                    (found != *hash).then(|| format!("frame hash is ${found:08X}, not ${hash:08X}"))
+                }
+                Command::Assert(Assertion::StateHash(hash)) => {
+                    let found = console.state_hash();
+                    (found != *hash).then(|| format!("state hash is ${found:08X}, not ${hash:08X}"))
+                }
+                Command::Assert(Assertion::ReachesPc(pc)) => {
+                    (!pcs_reached.contains(pc)).then(|| format!("PC never reached ${pc:04X}"))
+                }
+            };
+            if let Some(failure) = failure {
+                failures.push(format!("frame {frame}: {failure}"));
+            }
+        }
+        if failures.is_empty() {
+            Ok(())
+        } else {
+            Err(failures)
+        }
+    }
+}
@@ -1,13 +1,15 @@
 use crate::{
-    Cpu,
+    Cpu, disasm,
     fetch_decode::{AddrMode, Opcode, decode},
 };
 
 pub fn trace(cpu: &Cpu) -> String {
-    let code = cpu.memory.read(cpu.pc);
-    let (opcode, addrmode, info) = decode(code);
-
+    let code = cpu.memory.peek(cpu.pc);
     let pc = cpu.pc;
+    let Some((opcode, addrmode, info)) = decode(code) else {
+        return with_registers(cpu, &format!("{pc:04x}  {code:02x}        ???"));
+    };
+
     let mut hex_dump = vec![];
     hex_dump.push(code);
 
@@ -17,7 +19,7 @@ pub fn trace(cpu: &Cpu) -> String {
         }
         _ => {
             let addr = cpu.get_addr_mode_dest_ext(addrmode, pc);
-            (addr, cpu.memory.read(addr))
+            (addr, cpu.memory.peek(addr))
         }
     };
 
@@ -27,7 +29,7 @@ pub fn trace(cpu: &Cpu) -> String {
             _ => String::from(""),
         },
         2 => {
-            let address: u8 = cpu.memory.read(pc + 1);
+            let address: u8 = cpu.memory.peek(pc + 1);
             // let value = cpu.mem_read(address));
             hex_dump.push(address);
 
@@ -69,23 +71,23 @@ pub fn trace(cpu: &Cpu) -> String {
             }
         }
         3 => {
-            let address_lo = cpu.memory.read(pc + 1);
-            let address_hi = cpu.memory.read(pc + 2);
+            let address_lo = cpu.memory.peek(pc + 1);
+            let address_hi = cpu.memory.peek(pc + 2);
             hex_dump.push(address_lo);
             hex_dump.push(address_hi);
 
-            let address = cpu.memory.read_u16(pc + 1);
+            let address = cpu.memory.peek_u16(pc + 1);
 
             match addrmode {
                 AddrMode::Relative | AddrMode::Indirect => {
                     if code == 0x6c {
                         //jmp indirect
                         let jmp_addr = if address & 0x00FF == 0x00FF {
-                            let lo = cpu.memory.read(address);
-                            let hi = cpu.memory.read(address & 0xFF00);
+                            let lo = cpu.memory.peek(address);
+                            let hi = cpu.memory.peek(address & 0xFF00);
                             ((hi as u16) << 8) | (lo as u16)
                         } else {
-                            cpu.memory.read_u16(address)
+                            cpu.memory.peek_u16(address)
                         };
 
                         dbg!("ran");
@@ -132,10 +134,27 @@ pub fn trace(cpu: &Cpu) -> String {
         .map(|z| format!("{:02x}", z))
         .collect::<Vec<String>>()
         .join(" ");
-    let asm_str = format!("{:04x}  {:8}  {: >4?} {}", pc, hex_str, opcode, tmp)
-        .trim()
-        .to_string();
+    let asm_str = format!("{:04x}  {:8}  {: >4?} {}", pc, hex_str, opcode, tmp);
+    with_registers(cpu, asm_str.trim())
+}
+
+/// [`trace`] followed by the instruction with its labels, see
+/// [`crate::Bus::label`], when the PC or the operand has one
+pub fn trace_labeled(cpu: &Cpu) -> String {
+    let line = trace(cpu);
+    let Some((text, _)) = disasm::instruction_at(&cpu.memory, cpu.pc) else {
+        return line;
+    };
+    let operand_named = disasm::operand_addr(&cpu.memory, cpu.pc)
+        .is_some_and(|addr| cpu.memory.label(addr).is_some());
+    match cpu.memory.label(cpu.pc) {
+        Some(name) => format!("{line}  ; {name}: {text}"),
+        None if operand_named => format!("{line}  ; {text}"),
+        None => line,
+    }
+}
 
+fn with_registers(cpu: &Cpu, asm_str: &str) -> String {
     format!(
         "{:48} A:{:02x} X:{:02x} Y:{:02x} P:{:02x} SP:{:02x}",
         asm_str, cpu.reg_a, cpu.reg_x, cpu.reg_y, cpu.status, cpu.stack_ptr,
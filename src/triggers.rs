@@ -0,0 +1,252 @@
+//! Input pressed at set frames, for reproducing bugs and simple automation.
+//!
+//! Triggers can be added in code or loaded from text, one per line:
+//!
+//! ```text
+//! # Through the title screen
+//! 600 press start
+//! 1000-1200 hold right
+//! 1100-1110 hold a b   # jump along the way
+//! 1300 press 2 select  # on controller 2
+//! ```
+//!
+//! `press` holds the buttons for a single frame and `hold` for every frame
+//! from the first through the last. A number before the buttons picks the
+//! controller, 1 by default. Triggers add to whatever else holds buttons,
+//! like a player.
+//!
+//! A [`Macro`] is a recorded run of buttons that can be pressed again from
+//! any frame, like a frame-perfect jump.
+
+use std::ops::RangeInclusive;
+
+use crate::Bus;
+use crate::joypad::Buttons;
+
+/// Buttons held on a controller for a range of frames
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Trigger {
+    pub frames: RangeInclusive<u64>,
+    /// 0 for controller 1
+    pub port: usize,
+    pub buttons: Buttons,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Triggers {
+    triggers: Vec<Trigger>,
+    /// Buttons the last [`Triggers::apply`] held on each controller
+    applied: [Buttons; 2],
+}
+
+impl Triggers {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut triggers = Self::new();
+        for (number, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or_default().trim();
+            if line.is_empty() {
+                continue;
+            }
+            let trigger =
+                parse_trigger(line).map_err(|err| format!("line {}: {err}", number + 1))?;
+            triggers.add(trigger);
+        }
+        Ok(triggers)
+    }
+
+    pub fn add(&mut self, trigger: Trigger) {
+        self.triggers.push(trigger);
+    }
+
+    /// Hold `buttons` on controller 1 for the single frame `frame`
+    pub fn press(&mut self, frame: u64, buttons: Buttons) {
+        self.hold(frame..=frame, buttons);
+    }
+
+    /// Hold `buttons` on controller 1 for every frame in `frames`
+    pub fn hold(&mut self, frames: RangeInclusive<u64>, buttons: Buttons) {
+        self.add(Trigger {
+            frames,
+            port: 0,
+            buttons,
+        });
+    }
+
+    pub fn triggers(&self) -> &[Trigger] {
+        &self.triggers
+    }
+
+    /// Buttons the triggers hold on controller `port` during `frame`
+    pub fn buttons(&self, port: usize, frame: u64) -> Buttons {
+        self.triggers
+            .iter()
+            .filter(|trigger| trigger.port == port && trigger.frames.contains(&frame))
+            .fold(Buttons::empty(), |held, trigger| held | trigger.buttons)
+    }
+
+    /// Set the buttons for the frame the PPU is on. Call at the start of
+    /// every frame, buttons held by the last call are let go first.
+    pub fn apply(&mut self, bus: &mut Bus) {
+        let frame = bus.ppu.frame;
+        for port in 0..2 {
+            let buttons = self.buttons(port, frame);
+            let joypad = &mut bus.joypads[port];
+            joypad.buttons.remove(self.applied[port] & !buttons);
+            joypad.buttons.insert(buttons);
+            self.applied[port] = buttons;
+        }
+    }
+
+    /// The last frame any trigger holds buttons on
+    pub fn last_frame(&self) -> Option<u64> {
+        self.triggers
+            .iter()
+            .map(|trigger| *trigger.frames.end())
+            .max()
+    }
+}
+
+/// Buttons held for a run of frames, recorded with [`Macro::record`] and
+/// replayed with [`Macro::schedule`]. As text it's the buttons of each frame
+/// joined by `+`, or `-` for none, with `*N` for `N` frames in a row:
+///
+/// ```text
+/// right*12 right+a*3 -*2 a
+/// ```
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Macro {
+    /// Oldest first
+    pub frames: Vec<Buttons>,
+}
+
+impl Macro {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut frames = Vec::new();
+        for word in text.split_whitespace() {
+            let (names, count) = match word.split_once('*') {
+                Some((names, count)) => match count.parse::<usize>() {
+                    Ok(count) if count > 0 => (names, count),
+                    _ => return Err(format!("invalid count in {word:?}")),
+                },
+                None => (word, 1),
+            };
+            let mut buttons = Buttons::empty();
+            if names != "-" {
+                for name in names.split('+') {
+                    buttons |= Buttons::from_name(&name.to_ascii_uppercase())
+                        .ok_or(format!("unknown button {name:?}"))?;
+                }
+            }
+            frames.extend(std::iter::repeat_n(buttons, count));
+        }
+        Ok(Macro { frames })
+    }
+
+    /// Hold `buttons` from `frame`, counted from the start of the recording.
+    /// The frames skipped since the last call keep the buttons it held, and
+    /// any after `frame` (after a rewind) are dropped.
+    pub fn record(&mut self, frame: usize, buttons: Buttons) {
+        let last = self.frames.last().copied().unwrap_or_default();
+        self.frames.resize(frame, last);
+        self.frames.push(buttons);
+    }
+
+    /// Drop the frames after the last one holding anything, which waiting
+    /// to stop recording leaves
+    pub fn trim(&mut self) {
+        let len = self.frames.iter().rposition(|buttons| !buttons.is_empty());
+        self.frames.truncate(len.map_or(0, |last| last + 1));
+    }
+
+    /// Hold the macro's buttons on controller `port` from frame `start` on
+    pub fn schedule(&self, triggers: &mut Triggers, port: usize, start: u64) {
+        for (first, last, buttons) in self.runs() {
+            if !buttons.is_empty() {
+                triggers.add(Trigger {
+                    frames: start + first as u64..=start + last as u64,
+                    port,
+                    buttons,
+                });
+            }
+        }
+    }
+
+    /// The first and last frame of each run of the same buttons
+    fn runs(&self) -> impl Iterator<Item = (usize, usize, Buttons)> + '_ {
+        self.frames.chunk_by(|a, b| a == b).scan(0, |first, run| {
+            let start = *first;
+            *first += run.len();
+            Some((start, *first - 1, run[0]))
+        })
+    }
+
+    pub fn to_text(&self) -> String {
+        let words: Vec<String> = self
+            .runs()
+            .map(|(first, last, buttons)| {
+                let names: Vec<String> = buttons
+                    .iter_names()
+                    .map(|(name, _)| name.to_ascii_lowercase())
+                    .collect();
+                let names = match names.is_empty() {
+                    true => "-".to_string(),
+                    false => names.join("+"),
+                };
+                match last - first + 1 {
+                    1 => names,
+                    count => format!("{names}*{count}"),
+                }
+            })
+            .collect();
+        words.join(" ")
+    }
+}
+
+fn parse_trigger(line: &str) -> Result<Trigger, String> {
+    let mut words = line.split_whitespace();
+    let frames = words.next().unwrap_or_default();
+    let frame = |word: &str| {
+        word.parse::<u64>()
+            .map_err(|_| format!("invalid frame {word:?}"))
+    };
+    let verb = words.next().ok_or("missing press or hold")?;
+    let frames = match (frames.split_once('-'), verb) {
+        (Some(_), "press") => return Err("press takes a single frame".to_string()),
+        (Some((first, last)), "hold") => frame(first)?..=frame(last)?,
+        (None, "press" | "hold") => frame(frames)?..=frame(frames)?,
+        (_, verb) => return Err(format!("unknown command {verb:?}")),
+    };
+    if frames.is_empty() {
+        return Err("frames end before they start".to_string());
+    }
+    let mut words = words.peekable();
+    let port = match words.peek().and_then(|word| word.parse::<usize>().ok()) {
+        Some(controller @ 1..=2) => {
+            words.next();
+            controller - 1
+        }
+        Some(controller) => return Err(format!("no controller {controller}")),
+        None => 0,
+    };
+    let mut buttons = Buttons::empty();
+    for name in words {
+        buttons |= Buttons::from_name(&name.to_ascii_uppercase())
+            .ok_or(format!("unknown button {name:?}"))?;
+    }
+    if buttons.is_empty() {
+        return Err("missing buttons".to_string());
+    }
+    Ok(Trigger {
+        frames,
+        port,
+        buttons,
+    })
+}
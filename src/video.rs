@@ -0,0 +1,519 @@
+//! Frames as the frontend shows them, and what happens to them on the way.
+//!
+//! Pixels are 0RGB like the rest of the crate. The PPU's picture is
+//! [`FRAME_WIDTH`] by [`FRAME_HEIGHT`], but TVs hid a border of it and many
+//! games leave garbage there, so it is usually cropped before scaling.
+//!
+//! Frames go through a [`FilterChain`] of [`VideoFilter`]s before they are
+//! presented, e.g. cropping, then blending with the last frame, then scaling.
+//! Smoother upscalers than [`Scale`] are in `upscale` with the `upscale`
+//! feature.
+//!
+//! Frontends that emulate on one thread and present on another can pass
+//! frames through a [`FramePool`], which recycles their buffers.
+
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+
+use crate::rom::Region;
+
+#[cfg(feature = "upscale")]
+pub mod upscale;
+
+/// Size of the picture the PPU outputs
+pub const FRAME_WIDTH: usize = 256;
+pub const FRAME_HEIGHT: usize = 240;
+
+/// The colors of the NTSC PPU, the 2C02, in 0RGB, by NES color index.
+/// See https://www.nesdev.org/wiki/PPU_palettes
+pub const NES_PALETTE: [u32; 64] = [
+    // $00-$07
+    0x666666, 0x002a88, 0x1412a7, 0x3b00a4, 0x5c007e, 0x6e0040, 0x6c0600, 0x561d00,
+    // $08-$0F
+    0x333500, 0x0b4800, 0x005200, 0x004f08, 0x00404d, 0x000000, 0x000000, 0x000000,
+    // $10-$17
+    0xadadad, 0x155fd9, 0x4240ff, 0x7527fe, 0xa01acc, 0xb71e7b, 0xb53120, 0x994e00,
+    // $18-$1F
+    0x6b6d00, 0x388700, 0x0c9300, 0x008f32, 0x007c8d, 0x000000, 0x000000, 0x000000,
+    // $20-$27
+    0xfffeff, 0x64b0ff, 0x9290ff, 0xc676ff, 0xf36aff, 0xfe6ecc, 0xfe8170, 0xea9e22,
+    // $28-$2F
+    0xbcbe00, 0x88d800, 0x5ce430, 0x45e082, 0x48cdde, 0x4f4f4f, 0x000000, 0x000000,
+    // $30-$37
+    0xfffeff, 0xc0dfff, 0xd3d2ff, 0xe8c8ff, 0xfbc2ff, 0xfec4ea, 0xfeccc5, 0xf7d8a5,
+    // $38-$3F
+    0xe4e594, 0xcfef96, 0xbdf4ab, 0xb3f3cc, 0xb5ebf2, 0xb8b8b8, 0x000000, 0x000000,
+];
+
+/// A picture, row by row
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Frame {
+    pub pixels: Vec<u32>,
+    pub width: usize,
+    pub height: usize,
+}
+
+impl Frame {
+    /// Black frame of `width` by `height`
+    pub fn new(width: usize, height: usize) -> Self {
+        Frame {
+            pixels: vec![0; width * height],
+            width,
+            height,
+        }
+    }
+
+    /// The PPU's picture, see [`crate::ppu::Ppu::picture`], in the colors
+    /// of [`NES_PALETTE`]
+    pub fn from_picture(picture: &[u8]) -> Self {
+        let mut frame = Frame::default();
+        frame.draw_picture(picture);
+        frame
+    }
+
+    /// [`Frame::from_picture`] into this frame's buffer
+    pub fn draw_picture(&mut self, picture: &[u8]) {
+        self.pixels.clear();
+        self.pixels.extend(
+            picture
+                .iter()
+                .map(|&color| NES_PALETTE[color as usize & 0x3f]),
+        );
+        self.width = FRAME_WIDTH;
+        self.height = picture.len() / FRAME_WIDTH;
+    }
+
+    /// Wrap `pixels`, `None` if there aren't `width * height` of them
+    pub fn from_pixels(pixels: Vec<u32>, width: usize, height: usize) -> Option<Self> {
+        (pixels.len() == width * height).then_some(Frame {
+            pixels,
+            width,
+            height,
+        })
+    }
+
+    pub fn row(&self, y: usize) -> &[u32] {
+        &self.pixels[y * self.width..(y + 1) * self.width]
+    }
+
+    /// Make this a copy of `other`, in the buffer it already has
+    pub fn copy_from(&mut self, other: &Frame) {
+        self.pixels.clear();
+        self.pixels.extend_from_slice(&other.pixels);
+        self.width = other.width;
+        self.height = other.height;
+    }
+
+    /// Every pixel repeated `factor` times in both directions
+    pub fn scale(&self, factor: usize) -> Frame {
+        let mut scaled = Frame::default();
+        self.scale_into(factor, &mut scaled);
+        scaled
+    }
+
+    /// [`Frame::scale`] into `out`, reusing its buffer
+    pub fn scale_into(&self, factor: usize, out: &mut Frame) {
+        out.pixels.clear();
+        out.width = self.width * factor;
+        out.height = self.height * factor;
+        for y in 0..self.height {
+            let start = out.pixels.len();
+            let row = self.row(y);
+            out.pixels.extend((0..out.width).map(|x| row[x / factor]));
+            for _ in 1..factor {
+                out.pixels.extend_from_within(start..start + out.width);
+            }
+        }
+    }
+
+    /// Shrunk to `width` by `height`, each pixel the average of the block
+    /// of pixels it covers. Sizes bigger than the frame are clamped to it.
+    pub fn downscale(&self, width: usize, height: usize) -> Frame {
+        let width = width.clamp(1, self.width.max(1));
+        let height = height.clamp(1, self.height.max(1));
+        if self.pixels.is_empty() {
+            return Frame::new(0, 0);
+        }
+        let mut pixels = Vec::with_capacity(width * height);
+        for y in 0..height {
+            let rows = y * self.height / height..(y + 1) * self.height / height;
+            for x in 0..width {
+                let columns = x * self.width / width..(x + 1) * self.width / width;
+                let mut sums = [0; 3];
+                for row in rows.clone() {
+                    for &pixel in &self.row(row)[columns.clone()] {
+                        let [_, r, g, b] = pixel.to_be_bytes();
+                        sums[0] += r as usize;
+                        sums[1] += g as usize;
+                        sums[2] += b as usize;
+                    }
+                }
+                let count = rows.len() * columns.len();
+                let [r, g, b] = sums.map(|sum| (sum / count) as u8);
+                pixels.push(u32::from_be_bytes([0, r, g, b]));
+            }
+        }
+        Frame {
+            pixels,
+            width,
+            height,
+        }
+    }
+
+    /// Binary PPM image, which most image viewers and converters read
+    pub fn to_ppm(&self) -> Vec<u8> {
+        let mut ppm = format!("P6\n{} {}\n255\n", self.width, self.height).into_bytes();
+        for pixel in &self.pixels {
+            ppm.extend_from_slice(&pixel.to_be_bytes()[1..]);
+        }
+        ppm
+    }
+
+    /// The largest whole factor the frame can be scaled by and still fit in
+    /// `width` by `height`, at least 1. Whole factors keep every pixel the
+    /// same size, so the picture stays sharp.
+    pub fn fit_scale(&self, width: usize, height: usize) -> usize {
+        (width / self.width.max(1))
+            .min(height / self.height.max(1))
+            .max(1)
+    }
+
+    /// Copy into `target` with rows of `stride` pixels, clipped to fit
+    pub fn blit(&self, target: &mut [u32], stride: usize) {
+        self.blit_at(target, stride, 0, 0, 1);
+    }
+
+    /// [`Frame::blit`] into the middle of `target`
+    pub fn blit_centered(&self, target: &mut [u32], stride: usize) {
+        self.blit_scaled_centered(target, stride, 1);
+    }
+
+    /// [`Frame::scale`] and [`Frame::blit_centered`] at once, without
+    /// making the scaled frame
+    pub fn blit_scaled_centered(&self, target: &mut [u32], stride: usize, factor: usize) {
+        let height = target.len() / stride.max(1);
+        let left = stride.saturating_sub(self.width * factor) / 2;
+        let top = height.saturating_sub(self.height * factor) / 2;
+        self.blit_at(target, stride, left, top, factor);
+    }
+
+    fn blit_at(&self, target: &mut [u32], stride: usize, left: usize, top: usize, factor: usize) {
+        let Some(target) = target.get_mut(top * stride..) else {
+            return;
+        };
+        let rows = target.chunks_mut(stride).take(self.height * factor);
+        for (y, dest) in rows.enumerate() {
+            let dest = dest.get_mut(left..).unwrap_or_default();
+            let row = self.row(y / factor);
+            let width = (self.width * factor).min(dest.len());
+            for (x, pixel) in dest[..width].iter_mut().enumerate() {
+                *pixel = row[x / factor];
+            }
+        }
+    }
+}
+
+/// A step of post-processing. Filters may keep state between frames, so
+/// each frame should go through once, in order.
+pub trait VideoFilter {
+    fn apply(&mut self, frame: Frame) -> Frame;
+    /// Like [`VideoFilter::apply`], into `out`. Filters that override this
+    /// reuse its buffer, so a [`FilterChain`] of them allocates nothing per
+    /// frame.
+    fn apply_into(&mut self, frame: &Frame, out: &mut Frame) {
+        *out = self.apply(frame.clone());
+    }
+    /// Forget earlier frames, after a jump like loading a state
+    fn reset(&mut self) {}
+}
+
+/// Filters run one after the other
+#[derive(Default)]
+pub struct FilterChain {
+    pub filters: Vec<Box<dyn VideoFilter>>,
+    /// The output of [`FilterChain::filter`] and a spare for the filters
+    buffers: [Frame; 2],
+}
+
+impl FilterChain {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push(&mut self, filter: impl VideoFilter + 'static) {
+        self.filters.push(Box::new(filter));
+    }
+
+    pub fn apply(&mut self, frame: Frame) -> Frame {
+        self.filters
+            .iter_mut()
+            .fold(frame, |frame, filter| filter.apply(frame))
+    }
+
+    /// Like [`FilterChain::apply`], through buffers the chain keeps, for
+    /// frontends presenting every frame
+    pub fn filter(&mut self, frame: &Frame) -> &Frame {
+        let [output, spare] = &mut self.buffers;
+        output.copy_from(frame);
+        for filter in &mut self.filters {
+            filter.apply_into(output, spare);
+            std::mem::swap(output, spare);
+        }
+        output
+    }
+
+    pub fn reset(&mut self) {
+        for filter in &mut self.filters {
+            filter.reset();
+        }
+    }
+}
+
+/// Nearest neighbour scaling by a whole factor, see [`Frame::scale`]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Scale(pub usize);
+
+impl VideoFilter for Scale {
+    fn apply(&mut self, frame: Frame) -> Frame {
+        frame.scale(self.0)
+    }
+    fn apply_into(&mut self, frame: &Frame, out: &mut Frame) {
+        frame.scale_into(self.0, out);
+    }
+}
+
+/// Pixels cut from each edge of the frame
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Overscan {
+    pub top: usize,
+    pub bottom: usize,
+    pub left: usize,
+    pub right: usize,
+}
+
+/// A frame before and after cropping, e.g. to show one and screenshot the other
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CroppedFrame {
+    pub full: Frame,
+    pub cropped: Frame,
+}
+
+impl Overscan {
+    /// What TVs of the region usually hid: 8 lines top and bottom on NTSC.
+    /// PAL TVs showed the whole picture, and the PPU blanks its top line
+    /// itself, Dendy ones too.
+    pub fn for_region(region: Region) -> Self {
+        match region {
+            Region::Ntsc => Overscan {
+                top: 8,
+                bottom: 8,
+                ..Overscan::default()
+            },
+            Region::Pal | Region::Dendy => Overscan::default(),
+        }
+    }
+
+    /// Parse `top,bottom,left,right`, or a single number for all edges
+    pub fn parse(s: &str) -> Result<Self, String> {
+        let edges = s
+            .split(',')
+            .map(|edge| edge.trim().parse::<usize>())
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| format!("{s}: {e}"))?;
+        match edges[..] {
+            [all] => Ok(Overscan {
+                top: all,
+                bottom: all,
+                left: all,
+                right: all,
+            }),
+            [top, bottom, left, right] => Ok(Overscan {
+                top,
+                bottom,
+                left,
+                right,
+            }),
+            _ => Err(format!("{s}: expected 1 or 4 edges")),
+        }
+    }
+
+    /// `frame` without the overscan. Cropping more than the whole frame
+    /// leaves an empty one.
+    pub fn crop(&self, frame: &Frame) -> Frame {
+        let mut cropped = Frame::default();
+        self.crop_into(frame, &mut cropped);
+        cropped
+    }
+
+    /// [`Overscan::crop`] into `out`, reusing its buffer
+    pub fn crop_into(&self, frame: &Frame, out: &mut Frame) {
+        out.width = frame.width.saturating_sub(self.left + self.right);
+        out.height = frame.height.saturating_sub(self.top + self.bottom);
+        out.pixels.clear();
+        if out.width == 0 || out.height == 0 {
+            return;
+        }
+        for y in self.top..self.top + out.height {
+            out.pixels
+                .extend_from_slice(&frame.row(y)[self.left..self.left + out.width]);
+        }
+    }
+
+    /// Crop `frame`, keeping the full one as well
+    pub fn apply(&self, frame: Frame) -> CroppedFrame {
+        CroppedFrame {
+            cropped: self.crop(&frame),
+            full: frame,
+        }
+    }
+}
+
+impl VideoFilter for Overscan {
+    fn apply(&mut self, frame: Frame) -> Frame {
+        self.crop(&frame)
+    }
+    fn apply_into(&mut self, frame: &Frame, out: &mut Frame) {
+        self.crop_into(frame, out);
+    }
+}
+
+/// How [`FrameBlend`] mixes in earlier frames
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BlendMode {
+    /// Half of this frame and half of the last one. Sprites that games
+    /// flicker every other frame show steadily at half brightness.
+    Mix,
+    /// Like a CRT's phosphors, each pixel fades from its brightest by
+    /// `persistence` (0 to 1) per frame, and is only ever lit up, not darkened
+    Phosphor { persistence: f32 },
+}
+
+/// Blends each frame with earlier ones to hide sprite flicker
+pub struct FrameBlend {
+    pub mode: BlendMode,
+    /// Last input frame for `Mix`, last output for `Phosphor`
+    previous: Option<Frame>,
+}
+
+impl FrameBlend {
+    pub fn new(mode: BlendMode) -> Self {
+        FrameBlend {
+            mode,
+            previous: None,
+        }
+    }
+}
+
+impl BlendMode {
+    /// A channel of a pixel now, blended with the one before
+    fn blend(self, now: u8, before: u8) -> u8 {
+        match self {
+            BlendMode::Mix => ((now as u16 + before as u16) / 2) as u8,
+            BlendMode::Phosphor { persistence } => now.max((before as f32 * persistence) as u8),
+        }
+    }
+}
+
+impl VideoFilter for FrameBlend {
+    fn apply(&mut self, frame: Frame) -> Frame {
+        let mut blended = Frame::default();
+        self.apply_into(&frame, &mut blended);
+        blended
+    }
+
+    fn apply_into(&mut self, frame: &Frame, out: &mut Frame) {
+        out.copy_from(frame);
+        let mode = self.mode;
+        match &mut self.previous {
+            Some(previous) if (previous.width, previous.height) == (frame.width, frame.height) => {
+                for (pixel, &before) in out.pixels.iter_mut().zip(&previous.pixels) {
+                    let now = pixel.to_be_bytes();
+                    let before = before.to_be_bytes();
+                    *pixel =
+                        u32::from_be_bytes([0, 1, 2, 3].map(|i| mode.blend(now[i], before[i])));
+                }
+                previous.copy_from(match mode {
+                    BlendMode::Mix => frame,
+                    BlendMode::Phosphor { .. } => out,
+                });
+            }
+            _ => self.previous = Some(frame.clone()),
+        }
+    }
+
+    fn reset(&mut self) {
+        self.previous = None;
+    }
+}
+
+/// A swap chain of frames from the thread drawing them to the thread
+/// presenting them. Neither waits for the other: a frame finished before the
+/// last one was taken replaces it, so the presenter always gets the newest.
+/// Buffers go back to the pool once presented, so nothing is allocated per
+/// frame once there are enough of them, 3 with one being drawn, one waiting
+/// and one on screen. Clones share the pool.
+#[derive(Clone, Debug)]
+pub struct FramePool {
+    shared: Arc<Mutex<Pool>>,
+}
+
+#[derive(Debug)]
+struct Pool {
+    width: usize,
+    height: usize,
+    free: Vec<Frame>,
+    /// Finished and not taken yet
+    ready: Option<Frame>,
+}
+
+impl FramePool {
+    /// For frames of `width` by `height`, starting with `buffers` of them
+    pub fn new(width: usize, height: usize, buffers: usize) -> Self {
+        FramePool {
+            shared: Arc::new(Mutex::new(Pool {
+                width,
+                height,
+                free: (0..buffers).map(|_| Frame::new(width, height)).collect(),
+                ready: None,
+            })),
+        }
+    }
+
+    fn pool(&self) -> MutexGuard<'_, Pool> {
+        // Nothing is left half done while locked, so a panic elsewhere
+        // doesn't spoil the pool
+        self.shared.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// A frame to draw into, holding whatever was drawn into it before.
+    /// Only allocates if every buffer is in use, e.g. when frames that were
+    /// taken aren't recycled.
+    pub fn acquire(&self) -> Frame {
+        let mut pool = self.pool();
+        match pool.free.pop() {
+            Some(frame) => frame,
+            None => Frame::new(pool.width, pool.height),
+        }
+    }
+
+    /// Hand over a finished frame, dropping one that's still waiting
+    pub fn submit(&self, frame: Frame) {
+        let mut pool = self.pool();
+        if let Some(dropped) = pool.ready.replace(frame) {
+            pool.free.push(dropped);
+        }
+    }
+
+    /// The newest finished frame, if there's one since the last call
+    pub fn take(&self) -> Option<Frame> {
+        self.pool().ready.take()
+    }
+
+    /// Give back a frame once it's presented. Frames of another size are
+    /// dropped.
+    pub fn recycle(&self, frame: Frame) {
+        let mut pool = self.pool();
+        if (frame.width, frame.height) == (pool.width, pool.height) {
+            pool.free.push(frame);
+        }
+    }
+}
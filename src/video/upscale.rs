@@ -0,0 +1,198 @@
+//! Pixel art upscalers, which round off the staircase edges nearest
+//! neighbour scaling leaves. Both look at each pixel's neighbourhood one
+//! corner at a time, the other corners are the same rules rotated.
+
+use super::{Frame, VideoFilter};
+
+/// HQ2x, blends each quarter of a pixel with the neighbours towards its
+/// corner depending on which of them are similar to it. These are hq2x's
+/// rules for the usual cases, not its full 256 entry table.
+/// See https://en.wikipedia.org/wiki/Hqx
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Hq2x;
+
+impl VideoFilter for Hq2x {
+    fn apply(&mut self, frame: Frame) -> Frame {
+        let mut out = Frame::default();
+        self.apply_into(&frame, &mut out);
+        out
+    }
+    fn apply_into(&mut self, frame: &Frame, out: &mut Frame) {
+        upscale(frame, 2, out, |out, frame, x, y| {
+            for quarter in 0..4 {
+                // Towards the top left corner, rotated
+                let at = |dx, dy| neighbour(frame, x, y, rotate((dx, dy), quarter));
+                let (e, a, b, d) = (at(0, 0), at(-1, -1), at(0, -1), at(-1, 0));
+                let pixel = if similar(b, d) && !similar(b, e) && !similar(d, e) {
+                    // An edge cuts across the corner
+                    mix(&[(e, 2), (b, 1), (d, 1)])
+                } else if !similar(a, e) {
+                    mix(&[(e, 3), (a, 1)])
+                } else {
+                    e
+                };
+                let (sx, sy) = rotate_subpixel((0, 0), 2, quarter);
+                out[sy * 2 + sx] = pixel;
+            }
+        })
+    }
+}
+
+/// xBR at 2x or 3x: a corner is blended into the colour across an edge when
+/// the edge runs through it more clearly than the other diagonal. This is
+/// xBR's corner rule as xBRZ uses it, without xBRZ's handling of shallow
+/// and steep lines.
+/// See https://forums.libretro.com/t/xbr-algorithm-tutorial/123
+#[derive(Clone, Copy, Debug)]
+pub struct Xbr {
+    scale: usize,
+}
+
+impl Xbr {
+    /// Panics unless `scale` is 2 or 3
+    pub fn new(scale: usize) -> Self {
+        assert!(matches!(scale, 2 | 3), "xBR scales by 2 or 3");
+        Xbr { scale }
+    }
+
+    pub fn scale(&self) -> usize {
+        self.scale
+    }
+}
+
+impl VideoFilter for Xbr {
+    fn apply(&mut self, frame: Frame) -> Frame {
+        let mut out = Frame::default();
+        self.apply_into(&frame, &mut out);
+        out
+    }
+    fn apply_into(&mut self, frame: &Frame, out: &mut Frame) {
+        let n = self.scale;
+        upscale(frame, n, out, |out, frame, x, y| {
+            for quarter in 0..4 {
+                // Towards the bottom right corner, rotated
+                let at = |dx, dy| neighbour(frame, x, y, rotate((dx, dy), quarter));
+                let (e, f, h, i) = (at(0, 0), at(1, 0), at(0, 1), at(1, 1));
+                if e == f || e == h {
+                    continue;
+                }
+                let across = distance(e, at(1, -1))
+                    + distance(e, at(-1, 1))
+                    + distance(i, at(2, 1))
+                    + distance(i, at(1, 2))
+                    + 4 * distance(h, f);
+                let along = distance(h, at(-1, 1))
+                    + distance(h, at(0, 2))
+                    + distance(f, at(1, -1))
+                    + distance(f, at(2, 0))
+                    + 4 * distance(e, i);
+                if across >= along {
+                    continue;
+                }
+                let edge = if distance(e, f) <= distance(e, h) {
+                    f
+                } else {
+                    h
+                };
+                let mut put = |(sx, sy), weight| {
+                    let (sx, sy) = rotate_subpixel((sx, sy), n, quarter);
+                    out[sy * n + sx] = mix(&[(out[sy * n + sx], 4 - weight), (edge, weight)]);
+                };
+                if n == 2 {
+                    put((1, 1), 2);
+                } else {
+                    put((2, 2), 3);
+                    put((1, 2), 1);
+                    put((2, 1), 1);
+                }
+            }
+        })
+    }
+}
+
+/// Make `out` a frame `scale` times the size, `block` fills in the `scale` by
+/// `scale` pixels for each source pixel, starting out as copies of it
+fn upscale(
+    frame: &Frame,
+    scale: usize,
+    out: &mut Frame,
+    block: impl Fn(&mut [u32], &Frame, usize, usize),
+) {
+    out.width = frame.width * scale;
+    out.height = frame.height * scale;
+    out.pixels.resize(out.width * out.height, 0);
+    let mut block_pixels = [0; 9];
+    let pixels = &mut block_pixels[..scale * scale];
+    for y in 0..frame.height {
+        for x in 0..frame.width {
+            pixels.fill(frame.row(y)[x]);
+            block(pixels, frame, x, y);
+            for (sy, row) in pixels.chunks(scale).enumerate() {
+                let start = (y * scale + sy) * out.width + x * scale;
+                out.pixels[start..start + scale].copy_from_slice(row);
+            }
+        }
+    }
+}
+
+/// Pixel at an offset, repeating the edges of the frame
+fn neighbour(frame: &Frame, x: usize, y: usize, (dx, dy): (i32, i32)) -> u32 {
+    let x = (x as i32 + dx).clamp(0, frame.width as i32 - 1) as usize;
+    let y = (y as i32 + dy).clamp(0, frame.height as i32 - 1) as usize;
+    frame.row(y)[x]
+}
+
+/// An offset turned by `quarter` quarter turns
+fn rotate((dx, dy): (i32, i32), quarter: u8) -> (i32, i32) {
+    match quarter {
+        0 => (dx, dy),
+        1 => (-dy, dx),
+        2 => (-dx, -dy),
+        _ => (dy, -dx),
+    }
+}
+
+/// A position within an `n` by `n` block turned like [`rotate`]
+fn rotate_subpixel((sx, sy): (usize, usize), n: usize, quarter: u8) -> (usize, usize) {
+    // Doubled so the middle of the block is a whole number
+    let centered = |s: usize| 2 * s as i32 - (n as i32 - 1);
+    let (dx, dy) = rotate((centered(sx), centered(sy)), quarter);
+    let back = |d: i32| ((d + n as i32 - 1) / 2) as usize;
+    (back(dx), back(dy))
+}
+
+fn yuv(pixel: u32) -> [i32; 3] {
+    let [_, r, g, b] = pixel.to_be_bytes().map(i32::from);
+    [
+        (299 * r + 587 * g + 114 * b) / 1000,
+        (-169 * r - 331 * g + 500 * b) / 1000,
+        (500 * r - 419 * g - 81 * b) / 1000,
+    ]
+}
+
+/// hqx's test for pixels that look alike
+fn similar(a: u32, b: u32) -> bool {
+    let [ya, ua, va] = yuv(a);
+    let [yb, ub, vb] = yuv(b);
+    (ya - yb).abs() <= 0x30 && (ua - ub).abs() <= 7 && (va - vb).abs() <= 6
+}
+
+/// xBR's colour distance, brightness counting the most
+fn distance(a: u32, b: u32) -> i32 {
+    let [ya, ua, va] = yuv(a);
+    let [yb, ub, vb] = yuv(b);
+    48 * (ya - yb).abs() + 7 * (ua - ub).abs() + 6 * (va - vb).abs()
+}
+
+/// Weighted average of 0RGB pixels
+fn mix(pixels: &[(u32, u32)]) -> u32 {
+    let total: u32 = pixels.iter().map(|&(_, weight)| weight).sum();
+    let channel = |shift: u32| {
+        let sum: u32 = pixels
+            .iter()
+            .map(|&(pixel, weight)| (pixel >> shift & 0xff) * weight)
+            .sum();
+        (sum / total) << shift
+    };
+    channel(16) | channel(8) | channel(0)
+}
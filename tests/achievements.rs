@@ -0,0 +1,79 @@
+use std::sync::{Arc, Mutex};
+
+use nes::Bus;
+use nes::achievements::{MEMORY_REGIONS, MEMORY_SIZE, RegionKind, prg_ram, read_memory, rom_hash};
+use nes::console::Console;
+use nes::rom::Rom;
+
+mod common;
+
+/// NROM whose NMI handler counts frames in $10
+fn counter() -> Rom {
+    common::nrom(&[
+        // LDA #$80; STA $2000; JMP *
+        (0xc000, &[0xa9, 0x80, 0x8d, 0x00, 0x20, 0x4c, 0x05, 0xc0]),
+        // INC $10; RTI
+        (0xd000, &[0xe6, 0x10, 0x40]),
+        (0xfffa, &[0x00, 0xd0, 0x00, 0xc0, 0x00, 0xc0]),
+    ])
+}
+
+#[test]
+fn memory_map() {
+    let mut next = 0;
+    for region in MEMORY_REGIONS {
+        assert_eq!(region.start, next);
+        next = region.end + 1;
+    }
+    assert_eq!(next, MEMORY_SIZE);
+    assert_eq!(MEMORY_REGIONS[0].kind, RegionKind::SystemRam);
+
+    let mut bus = Bus::new(counter());
+    bus.cpu_ram[0x7ff] = 0x12;
+    bus.cpu_ram[0] = 0x34;
+    let mut buf = [0; 2];
+    assert_eq!(read_memory(&bus, 0x7ff, &mut buf), 2);
+    assert_eq!(buf, [0x12, 0x34]);
+    // Mirrors read the same RAM
+    assert_eq!(read_memory(&bus, 0x1fff, &mut buf[..1]), 1);
+    assert_eq!(buf[0], 0x12);
+    // Reset vector, and nothing past the end of the map
+    let mut buf = [0xaa; 4];
+    assert_eq!(read_memory(&bus, 0xfffc, &mut buf), 4);
+    assert_eq!(buf, [0x00, 0xc0, 0x00, 0xc0]);
+    assert_eq!(read_memory(&bus, 0xfffe, &mut buf), 2);
+    assert_eq!(read_memory(&bus, MEMORY_SIZE, &mut buf), 0);
+}
+
+#[test]
+fn cartridge_ram() {
+    let mut ines = vec![0; 16 + 0x8000 + 0x2000];
+    ines[..8].copy_from_slice(b"NES\x1a\x02\x01\x40\x00");
+    let mut bus = Bus::new(Rom::new(&ines).unwrap());
+    bus.write(0x6000, 0x56);
+    bus.write(0x7fff, 0x78);
+    let ram = prg_ram(&bus);
+    assert_eq!(ram.len(), 0x2000);
+    assert_eq!((ram[0], ram[0x1fff]), (0x56, 0x78));
+}
+
+#[test]
+fn frame_callbacks() {
+    let mut console = Console::new(counter());
+    let seen = Arc::new(Mutex::new(vec![]));
+    let log = Arc::clone(&seen);
+    console.on_frame(move |bus| log.lock().unwrap().push(bus.cpu_ram[0x10]));
+    for _ in 0..4 {
+        console.run_frame();
+    }
+    // Called after each frame's vblank NMI
+    assert_eq!(*seen.lock().unwrap(), [1, 2, 3, 4]);
+}
+
+#[test]
+fn hash() {
+    // MD5 of the file without its header
+    let rom = common::nrom(&[]);
+    assert_eq!(rom_hash(&rom), "c249729d83de8c4ea516f5676fefe5c8");
+    assert_ne!(rom_hash(&counter()), rom_hash(&rom));
+}
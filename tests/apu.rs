@@ -0,0 +1,126 @@
+use nes::apu::Apu;
+
+fn tick(apu: &mut Apu, cycles: u32) {
+    for _ in 0..cycles {
+        apu.tick();
+    }
+}
+
+/// Run until the frame IRQ flag gets set, returning the CPU cycles it took
+fn cycles_to_irq(apu: &mut Apu) -> u32 {
+    let mut cycles = 0;
+    while apu.peek_status() & 0x40 == 0 {
+        apu.tick();
+        cycles += 1;
+    }
+    cycles
+}
+
+#[test]
+fn length_counter_status() {
+    let mut apu = Apu::new();
+    // Loads are ignored while a channel is disabled
+    apu.write_register(0x4003, 0x18);
+    apu.tick();
+    assert_eq!(apu.read_status() & 0x0f, 0);
+
+    apu.write_register(0x4015, 0x0f);
+    for addr in [0x4003, 0x4007, 0x400b, 0x400f] {
+        apu.write_register(addr, 0x18);
+    }
+    apu.tick();
+    assert_eq!(apu.read_status() & 0x0f, 0x0f);
+    assert_eq!(apu.pulse1.length.counter, 2);
+
+    // Disabling clears the counter right away
+    apu.write_register(0x4015, 0x0d);
+    assert_eq!(apu.read_status() & 0x0f, 0x0d);
+
+    // 2 half frames later everything is silent
+    tick(&mut apu, 29830);
+    assert_eq!(apu.read_status() & 0x0f, 0);
+}
+
+#[test]
+fn length_counter_halt() {
+    let mut apu = Apu::new();
+    apu.write_register(0x4015, 0x01);
+    apu.write_register(0x4000, 0x20);
+    apu.write_register(0x4003, 0x18);
+    tick(&mut apu, 29830 * 2);
+    assert_eq!(apu.pulse1.length.counter, 2);
+    apu.write_register(0x4000, 0x00);
+    tick(&mut apu, 29830);
+    assert_eq!(apu.pulse1.length.counter, 0);
+}
+
+#[test]
+fn frame_irq_flag() {
+    let mut apu = Apu::new();
+    assert_eq!(cycles_to_irq(&mut apu), 29828);
+    // Cleared by reading, unless it gets set again on the next two cycles
+    assert_eq!(apu.read_status() & 0x40, 0x40);
+    apu.tick();
+    assert_eq!(apu.read_status() & 0x40, 0x40);
+    apu.tick();
+    assert_eq!(apu.read_status() & 0x40, 0x40);
+    apu.tick();
+    assert_eq!(apu.read_status() & 0x40, 0);
+    assert_eq!(cycles_to_irq(&mut apu), 29830 - 3);
+
+    // Setting the inhibit flag clears it immediately
+    apu.write_register(0x4017, 0x40);
+    assert_eq!(apu.peek_status() & 0x40, 0);
+    tick(&mut apu, 29830 * 2);
+    assert_eq!(apu.peek_status() & 0x40, 0);
+
+    // No IRQ in the 5-step sequence
+    apu.write_register(0x4017, 0x80);
+    // The old sequence is still running until the write lands
+    tick(&mut apu, 4);
+    apu.read_status();
+    tick(&mut apu, 37282 * 2);
+    assert_eq!(apu.peek_status() & 0x40, 0);
+}
+
+#[test]
+fn frame_counter_write_delay() {
+    let mut delays = vec![];
+    for align in 0..2 {
+        let mut apu = Apu::new();
+        tick(&mut apu, align);
+        apu.write_register(0x4015, 0x01);
+        apu.write_register(0x4003, 0x18);
+        // The 5-step mode clocks the length counters once the write lands
+        apu.write_register(0x4017, 0x80);
+        let mut delay = 0;
+        while apu.pulse1.length.counter == 0 || apu.pulse1.length.counter == 2 {
+            apu.tick();
+            delay += 1;
+        }
+        delays.push(delay);
+        // And the sequence restarts from there
+        tick(&mut apu, 14913 - 1);
+        assert_eq!(apu.pulse1.length.counter, 1);
+        apu.tick();
+        assert_eq!(apu.pulse1.length.counter, 0);
+    }
+    assert_eq!(delays, [4, 3]);
+}
+
+#[test]
+fn length_reload_during_clock() {
+    // A reload on the same cycle as a half frame clock is ignored while the
+    // counter is non-zero, and goes through when it was already 0
+    for (loaded, expected) in [(true, 1), (false, 2)] {
+        let mut apu = Apu::new();
+        apu.write_register(0x4015, 0x01);
+        if loaded {
+            apu.write_register(0x4003, 0x18);
+        }
+        tick(&mut apu, 14913 - 1);
+        apu.write_register(0x4003, 0x18);
+        apu.tick();
+        assert_eq!(apu.pulse1.length.counter, expected);
+    }
+}
@@ -0,0 +1,201 @@
+use nes::Bus;
+use nes::apu::{
+    AudioConfig, AudioOutput, AudioRouting, AudioStats, BlipBuffer, EqBand, ExpansionChip,
+    FilterChain,
+};
+
+mod common;
+
+fn bus() -> Bus {
+    Bus::new(common::nrom(&[]))
+}
+
+#[test]
+fn expansion_routing() {
+    let mut config = AudioConfig::default();
+    let vrc6 = Some((ExpansionChip::Vrc6, 0.25));
+    assert_eq!(config.mix(0.5, vrc6), 0.75);
+
+    config.set_expansion_volume(ExpansionChip::Vrc6, 2.0);
+    config.set_expansion_volume(ExpansionChip::N163, 0.0);
+    assert_eq!(config.mix(0.5, vrc6), 1.0);
+    assert_eq!(config.mix(0.5, Some((ExpansionChip::N163, 0.25))), 0.5);
+
+    config.master_volume = 0.5;
+    assert_eq!(config.mix(0.5, vrc6), 0.5);
+
+    // Expansion audio doesn't reach the output on an NES
+    config.routing = AudioRouting::Nes;
+    assert_eq!(config.mix(0.5, vrc6), 0.25);
+}
+
+#[test]
+fn dmc_output_level() {
+    let mut bus = bus();
+    assert_eq!(bus.audio_sample(), 0.0);
+    bus.write(0x4011, 0x7f);
+    let loud = bus.audio_sample();
+    bus.write(0x4011, 0x20);
+    let quiet = bus.audio_sample();
+    assert!(loud > quiet && quiet > 0.0);
+    // The DAC is non-linear
+    assert!(quiet > loud * 0x20 as f32 / 0x7f as f32);
+
+    bus.audio.apu_volume = 0.0;
+    assert_eq!(bus.audio_sample(), 0.0);
+}
+
+#[test]
+fn resampled_output() {
+    let mut bus = bus();
+    bus.audio.hardware_filters = false;
+    bus.write(0x4011, 0x40);
+    let level = bus.audio_sample();
+    // One NTSC frame
+    bus.tick(29780);
+    let mut out = [0.0; 1024];
+    let count = bus.read_audio(&mut out);
+    assert!((733..=734).contains(&count), "{count}");
+    // The step rings briefly and settles on the new level
+    assert!((out[count - 1] - level).abs() < 1e-4);
+    assert!(out[..count].iter().all(|&s| s < level * 1.2));
+
+    bus.set_sample_rate(48000.0);
+    bus.tick(29780);
+    let count = bus.read_audio(&mut out);
+    assert!((798..=799).contains(&count), "{count}");
+}
+
+#[test]
+fn band_limited_steps_dont_alias() {
+    // A 30 kHz square is entirely above the 22 kHz Nyquist frequency,
+    // so a band-limited resampler outputs nothing but its average
+    let clock_rate = 1_789_773.0;
+    let half_period = 30;
+    let mut blip = BlipBuffer::new(clock_rate, 44100.0);
+    let mut naive = vec![];
+    let mut level = 0.0;
+    let mut next_sample = 0.0;
+    for clock in 0..clock_rate as u32 / 10 {
+        if clock % half_period == 0 {
+            let new = if level == 0.0 { 1.0 } else { 0.0 };
+            blip.add_delta(clock, new - level);
+            level = new;
+        }
+        if clock as f64 >= next_sample {
+            naive.push(level);
+            next_sample += clock_rate / 44100.0;
+        }
+    }
+    blip.end_frame(clock_rate as u32 / 10);
+    let mut out = vec![0.0; blip.samples_avail()];
+    blip.read_samples(&mut out);
+
+    let spread = |samples: &[f32]| {
+        let samples = &samples[100..samples.len() - 100];
+        let mean = samples.iter().sum::<f32>() / samples.len() as f32;
+        samples.iter().map(|s| (s - mean).abs()).fold(0.0, f32::max)
+    };
+    assert!(spread(&out) < 0.02, "{}", spread(&out));
+    assert!(spread(&naive) > 0.4);
+}
+
+#[test]
+fn hardware_filters() {
+    // DC is removed by the high-passes
+    let mut bus = bus();
+    bus.write(0x4011, 0x40);
+    for _ in 0..10 {
+        bus.tick(29780);
+    }
+    let mut out = [0.0; 8192];
+    let count = bus.read_audio(&mut out);
+    assert!(out[..100].iter().any(|&s| s > 0.1));
+    assert!(out[count - 1].abs() < 1e-3);
+
+    // The low-pass leaves a 1kHz tone alone and takes a good bite out of 20kHz
+    let amplitude = |chain: &mut FilterChain, freq: f32| {
+        let samples: Vec<f32> = (0..44100)
+            .map(|i| chain.process((i as f32 * freq / 44100.0 * std::f32::consts::TAU).sin()))
+            .collect();
+        samples[22050..]
+            .iter()
+            .fold(0.0f32, |max, s| max.max(s.abs()))
+    };
+    let lp = |freq| amplitude(&mut FilterChain::new(true, &[], 44100.0), freq);
+    assert!(lp(1000.0) > 0.85);
+    assert!(lp(20000.0) < 0.7);
+    assert!(lp(50.0) < 0.2);
+
+    // An EQ band boosts around its frequency only
+    let boost = [EqBand {
+        freq: 1000.0,
+        gain_db: 6.0,
+        q: 1.0,
+    }];
+    let eq = |freq| amplitude(&mut FilterChain::new(false, &boost, 44100.0), freq);
+    assert!((eq(1000.0) - 2.0).abs() < 0.05);
+    assert!((eq(100.0) - 1.0).abs() < 0.05);
+}
+
+#[test]
+fn channel_panning() {
+    let mut bus = bus();
+    bus.audio.hardware_filters = false;
+    bus.audio.pan.dmc = -1.0;
+    bus.write(0x4011, 0x40);
+    let level = bus.audio_sample();
+    bus.tick(29780);
+    let mut out = [[0.0; 2]; 1024];
+    let count = bus.read_audio_stereo(&mut out);
+    let [left, right] = out[count - 1];
+    assert!((left - level).abs() < 1e-4);
+    assert!(right.abs() < 1e-4);
+
+    // Centered channels are at full volume on both sides
+    let config = AudioConfig::default();
+    let [left, right] = config.mix_stereo([0.1, 0.0, 0.0, 0.0, 0.2], None);
+    assert!((left - 0.3).abs() < 1e-6 && (right - 0.3).abs() < 1e-6);
+}
+
+#[test]
+fn audio_stats() {
+    let mut stats = AudioStats::new(1000.0);
+    assert_eq!(stats.drift_ppm(), None);
+    // 100 samples a report, the device plays 98 of them in between
+    stats.record(0, 0, 100);
+    stats.record(2, 0, 100);
+    assert_eq!(stats.underruns(), 0);
+    stats.record(4, 0, 100);
+    assert_eq!(stats.latency().as_millis(), 104);
+    assert_eq!(stats.fill_history().collect::<Vec<_>>(), [100, 102, 104]);
+    let drift = stats.drift_ppm().unwrap();
+    assert!((drift - 20408.0).abs() < 1.0, "{drift}");
+
+    // Ran dry, then too far ahead
+    stats.record(0, 0, 100);
+    assert_eq!(stats.underruns(), 1);
+    stats.record(90, 90, 100);
+    assert_eq!((stats.overruns(), stats.latency().as_millis()), (1, 100));
+    stats.reset();
+    assert_eq!((stats.underruns(), stats.fill_history().len()), (0, 0));
+}
+
+#[test]
+fn unread_audio_is_bounded() {
+    let config = AudioConfig {
+        hardware_filters: false,
+        ..AudioConfig::default()
+    };
+    let mut output = AudioOutput::new(100_000.0, 10_000.0);
+    output.tick([0.5; 2]);
+    // Three seconds of it with nobody reading
+    for _ in 0..300_000 {
+        output.tick([0.5; 2]);
+    }
+    let mut out = vec![[0.0; 2]; 20_000];
+    let count = output.read(&mut out, &config);
+    assert!((9990..=10_000).contains(&count), "{count}");
+    // The samples dropped still got to the level
+    assert!((out[0][0] - 0.5).abs() < 1e-4);
+}
@@ -0,0 +1,286 @@
+use std::time::{Duration, UNIX_EPOCH};
+
+use nes::Bus;
+use nes::clock::EmulatedClock;
+use nes::console::Console;
+use nes::rom::Rom;
+
+fn rom(mapper: u8, submapper: u8) -> Rom {
+    // NES 2.0, 8 16K PRG banks each filled with its bank number, 8K CHR
+    let mut ines = vec![0; 16];
+    ines[..9].copy_from_slice(&[
+        b'N',
+        b'E',
+        b'S',
+        0x1a,
+        0x08,
+        0x01,
+        mapper << 4,
+        (mapper & 0xf0) | 0x08,
+        submapper << 4,
+    ]);
+    ines.extend((0..8u8).flat_map(|bank| [bank; 0x4000]));
+    ines.extend([0; 0x2000]);
+    Rom::new(&ines).unwrap()
+}
+
+fn bandai(mapper: u8, submapper: u8) -> Bus {
+    Bus::new(rom(mapper, submapper))
+}
+
+/// Bit-bang the EEPROM lines through $800D
+struct I2c<'a>(&'a mut Bus);
+
+impl I2c<'_> {
+    fn set(&mut self, scl: bool, sda: bool) {
+        self.0
+            .write(0x800D, 0x80 | ((scl as u8) << 5) | ((sda as u8) << 6));
+    }
+
+    fn sda(&mut self) -> bool {
+        self.0.read(0x6000) & 0x10 != 0
+    }
+
+    fn start(&mut self) {
+        self.set(true, true);
+        self.set(true, false);
+        self.set(false, false);
+    }
+
+    fn stop(&mut self) {
+        self.set(false, false);
+        self.set(true, false);
+        self.set(true, true);
+    }
+
+    /// Send a byte, returns whether the EEPROM acknowledged it
+    fn send(&mut self, byte: u8) -> bool {
+        for i in (0..8).rev() {
+            let bit = byte >> i & 1 != 0;
+            self.set(false, bit);
+            self.set(true, bit);
+            self.set(false, bit);
+        }
+        self.set(false, true);
+        self.set(true, true);
+        let ack = !self.sda();
+        self.set(false, true);
+        ack
+    }
+
+    fn recv(&mut self, ack: bool) -> u8 {
+        let mut byte = 0;
+        for _ in 0..8 {
+            self.set(false, true);
+            self.set(true, true);
+            byte = (byte << 1) | self.sda() as u8;
+        }
+        self.set(false, !ack);
+        self.set(true, !ack);
+        self.set(false, !ack);
+        byte
+    }
+}
+
+#[test]
+fn prg_banking() {
+    let mut bus = bandai(16, 0);
+    bus.write(0x8008, 3);
+    assert_eq!(bus.read(0x8000), 3);
+    assert_eq!(bus.read(0xC000), 7);
+    // FCG registers at $6000
+    bus.write(0x6008, 5);
+    assert_eq!(bus.read(0x8000), 5);
+
+    // LZ93D50 only
+    let mut bus = bandai(16, 5);
+    bus.write(0x6008, 5);
+    assert_eq!(bus.read(0x8000), 0);
+}
+
+#[test]
+fn irq_counter() {
+    // The LZ93D50 copies the latch into the counter when enabling
+    let mut bus = bandai(16, 5);
+    bus.write(0x800B, 10);
+    bus.write(0x800C, 0);
+    bus.write(0x800A, 1);
+    bus.tick(9);
+    assert!(!bus.mapper.irq());
+    bus.tick(1);
+    assert!(bus.mapper.irq());
+    bus.write(0x800A, 0);
+    assert!(!bus.mapper.irq());
+
+    // The FCG writes the counter directly
+    let mut bus = bandai(16, 4);
+    bus.write(0x600A, 1);
+    bus.write(0x600B, 10);
+    bus.tick(9);
+    assert!(!bus.mapper.irq());
+    bus.tick(1);
+    assert!(bus.mapper.irq());
+}
+
+#[test]
+fn eeprom_24c02() {
+    let mut bus = bandai(16, 5);
+    let mut i2c = I2c(&mut bus);
+    i2c.start();
+    assert!(i2c.send(0xa0));
+    assert!(i2c.send(0x10));
+    assert!(i2c.send(0x12));
+    assert!(i2c.send(0x34));
+    i2c.stop();
+
+    // Random read
+    i2c.start();
+    assert!(i2c.send(0xa0));
+    assert!(i2c.send(0x10));
+    i2c.start();
+    assert!(i2c.send(0xa1));
+    assert_eq!(i2c.recv(true), 0x12);
+    assert_eq!(i2c.recv(false), 0x34);
+    i2c.stop();
+
+    // Other devices on the bus aren't acknowledged
+    i2c.start();
+    assert!(!i2c.send(0x50));
+    i2c.stop();
+
+    let save = bus.mapper.save_data().unwrap();
+    assert_eq!(save.len(), 0x100);
+    assert_eq!(save[0x10..0x12], [0x12, 0x34]);
+    let mut bus = bandai(16, 5);
+    bus.mapper.load_save_data(&save);
+    let mut i2c = I2c(&mut bus);
+    i2c.start();
+    assert!(i2c.send(0xa0));
+    assert!(i2c.send(0x11));
+    i2c.start();
+    assert!(i2c.send(0xa1));
+    assert_eq!(i2c.recv(false), 0x34);
+}
+
+#[test]
+fn eeprom_24c01() {
+    let mut bus = bandai(159, 0);
+    let mut i2c = I2c(&mut bus);
+    i2c.start();
+    assert!(i2c.send(0x05 << 1));
+    assert!(i2c.send(0xab));
+    i2c.stop();
+
+    i2c.start();
+    assert!(i2c.send((0x05 << 1) | 1));
+    assert_eq!(i2c.recv(false), 0xab);
+    i2c.stop();
+    assert_eq!(bus.mapper.save_data().unwrap().len(), 0x80);
+}
+
+#[test]
+fn prg_ram() {
+    let mut bus = bandai(153, 0);
+    bus.write(0x800D, 0x20);
+    bus.write(0x6123, 0x45);
+    assert_eq!(bus.read(0x6123), 0x45);
+    bus.write(0x800D, 0x00);
+    assert_eq!(bus.read(0x6123), 0);
+    assert_eq!(bus.mapper.save_data().unwrap()[0x123], 0x45);
+}
+
+#[test]
+fn datach_barcode() {
+    let mut bus = bandai(157, 0);
+    assert_eq!(bus.read(0x6000) & 0x08, 0);
+    let reader = bus.mapper.barcode_reader().unwrap();
+    assert!(reader.scan("490123456789").is_err());
+    assert!(reader.scan("490123456789x").is_err());
+    reader.scan("4901234567894").unwrap();
+
+    // A bit per 1000 cycles, low for bars
+    let mut bars = Vec::new();
+    while bus.mapper.barcode_reader().unwrap().scanning() {
+        bars.push(bus.read(0x6000) & 0x08 == 0);
+        for _ in 0..1000 {
+            bus.mapper.cpu_tick();
+        }
+    }
+    assert_eq!(bars.len(), 33 + 3 + 42 + 5 + 42 + 3 + 33);
+    let bits = |bars: &[bool]| bars.iter().fold(0, |acc, &bar| acc << 1 | bar as u8);
+    assert!(bars[..33].iter().all(|&bar| !bar));
+    assert_eq!(bits(&bars[33..36]), 0b101);
+    // 9 with odd parity, then 0 with even parity since the code starts with 4
+    assert_eq!(bits(&bars[36..43]), 0b0001011);
+    assert_eq!(bits(&bars[43..50]), 0b0100111);
+    assert_eq!(bits(&bars[78..83]), 0b01010);
+    // Check digit 4 on the right
+    assert_eq!(bits(&bars[118..125]), 0b1011100);
+    assert_eq!(bus.read(0x6000) & 0x08, 0);
+
+    // EAN-8
+    bus.mapper
+        .barcode_reader()
+        .unwrap()
+        .scan("96385074")
+        .unwrap();
+    assert!(bandai(16, 0).mapper.barcode_reader().is_none());
+}
+
+#[test]
+fn datach_rtc() {
+    let mut console = Console::new(rom(157, 0));
+    // 23:59:59 on Thursday February 29th 2024
+    let start = UNIX_EPOCH + Duration::from_secs(1_709_251_199);
+    console.set_time_source(EmulatedClock { start });
+    let read_time = |bus: &mut Bus| {
+        let mut i2c = I2c(bus);
+        i2c.start();
+        assert!(i2c.send(0xd0));
+        assert!(i2c.send(0x00));
+        i2c.start();
+        assert!(i2c.send(0xd1));
+        let time: Vec<u8> = (0..7).map(|i| i2c.recv(i < 6)).collect();
+        i2c.stop();
+        time
+    };
+    let bus = console.bus_mut();
+    assert_eq!(read_time(bus), [0x59, 0x59, 0x23, 5, 0x29, 0x02, 0x24]);
+    for _ in 0..1_789_773 {
+        bus.mapper.cpu_tick();
+    }
+    assert_eq!(read_time(bus), [0x00, 0x00, 0x00, 6, 0x01, 0x03, 0x24]);
+
+    // 11:59:59 PM in 12 hour mode, then halted
+    let mut i2c = I2c(bus);
+    i2c.start();
+    assert!(i2c.send(0xd0));
+    for byte in [0x00, 0x59, 0x59, 0x71] {
+        assert!(i2c.send(byte));
+    }
+    i2c.stop();
+    for _ in 0..1_789_773 {
+        bus.mapper.cpu_tick();
+    }
+    assert_eq!(read_time(bus)[..3], [0x00, 0x00, 0x52]);
+    let mut i2c = I2c(bus);
+    i2c.start();
+    assert!(i2c.send(0xd0));
+    assert!(i2c.send(0x00));
+    assert!(i2c.send(0x80));
+    i2c.stop();
+    for _ in 0..1_789_773 {
+        bus.mapper.cpu_tick();
+    }
+    assert_eq!(read_time(bus)[..3], [0x80, 0x00, 0x52]);
+
+    // The EEPROM is still there, and nothing else has a clock
+    let mut i2c = I2c(bus);
+    i2c.start();
+    assert!(i2c.send(0xa0));
+    i2c.stop();
+    let mut bus = bandai(16, 0);
+    let mut i2c = I2c(&mut bus);
+    i2c.start();
+    assert!(!i2c.send(0xd0));
+}
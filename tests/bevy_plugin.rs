@@ -0,0 +1,126 @@
+#![cfg(feature = "bevy")]
+
+use bevy::asset::AssetPlugin;
+use bevy::image::{Image, ImagePlugin};
+use bevy::prelude::*;
+use bevy::time::TimeUpdateStrategy;
+use nes::bevy_plugin::{NesConsole, NesPlugin, NesScreen};
+use nes::console::Console;
+use nes::joypad::Buttons;
+use nes::rom::Rom;
+use nes::video::{Frame, NES_PALETTE};
+use std::time::Duration;
+
+mod common;
+
+/// Sets the backdrop color and spins, the screen shows the frame count
+fn app() -> App {
+    build_app(false, |plugin| {
+        plugin.with_render(|console| {
+            let frame = console.bus().ppu.frame as u32;
+            Frame::from_pixels(vec![frame, 0, 0, 0xffffff], 2, 2).unwrap()
+        })
+    })
+}
+
+fn build_app(pal: bool, plugin: fn(NesPlugin) -> NesPlugin) -> App {
+    let mut ines = common::ines(&[(
+        0xc000,
+        &[
+            // Wait out the PPU's warm-up, two vblanks; BIT $2002; BPL; BIT $2002; BPL
+            0x2c, 0x02, 0x20, 0x10, 0xfb, 0x2c, 0x02, 0x20, 0x10, 0xfb,
+            // Backdrop color $16: LDA #$3F; STA $2006; LDA #0; STA $2006; LDA #$16; STA $2007
+            0xa9, 0x3f, 0x8d, 0x06, 0x20, 0xa9, 0x00, 0x8d, 0x06, 0x20, 0xa9, 0x16, 0x8d, 0x07,
+            0x20,
+            // Leave the PPU address out of the palette; LDA #0; STA $2006; STA $2006; JMP *
+            0xa9, 0x00, 0x8d, 0x06, 0x20, 0x8d, 0x06, 0x20, 0x4c, 0x21, 0xc0,
+        ],
+    )]);
+    ines[9] = pal as u8;
+    let mut app = App::new();
+    app.add_plugins((
+        MinimalPlugins,
+        AssetPlugin::default(),
+        ImagePlugin::default(),
+        bevy::input::InputPlugin,
+    ))
+    .add_plugins(plugin(NesPlugin::new(move || {
+        Console::new(Rom::new(&ines).unwrap())
+    })));
+    app.finish();
+    app.cleanup();
+    app
+}
+
+#[test]
+fn screen_follows_console() {
+    let mut app = app();
+    app.update();
+    app.update();
+    let frame = app
+        .world()
+        .non_send_resource::<NesConsole>()
+        .console
+        .bus()
+        .ppu
+        .frame;
+    assert_eq!(frame, 2);
+    let screen = app.world().resource::<NesScreen>();
+    let images = app.world().resource::<Assets<Image>>();
+    let image = images.get(&screen.image).unwrap();
+    assert_eq!((image.width(), image.height()), (2, 2));
+    let data = image.data.as_ref().unwrap();
+    assert_eq!(&data[..4], &[0, 0, 2, 0xff]);
+    assert_eq!(&data[12..], &[0xff; 4]);
+}
+
+#[test]
+fn screen_shows_picture() {
+    let mut app = build_app(false, |plugin| plugin);
+    for _ in 0..4 {
+        app.update();
+    }
+    let screen = app.world().resource::<NesScreen>();
+    let images = app.world().resource::<Assets<Image>>();
+    let image = images.get(&screen.image).unwrap();
+    assert_eq!((image.width(), image.height()), (256, 240));
+    let [_, r, g, b] = NES_PALETTE[0x16].to_be_bytes();
+    let data = image.data.as_ref().unwrap();
+    assert_eq!(&data[..4], &[r, g, b, 0xff]);
+    assert_eq!(&data[data.len() - 4..], &[r, g, b, 0xff]);
+}
+
+#[test]
+fn keys_press_buttons() {
+    let mut app = app();
+    let mut keys = app.world_mut().resource_mut::<ButtonInput<KeyCode>>();
+    keys.press(KeyCode::ArrowLeft);
+    keys.press(KeyCode::KeyX);
+    app.update();
+    let nes = app.world().non_send_resource::<NesConsole>();
+    assert_eq!(
+        nes.console.bus().joypads[0].buttons,
+        Buttons::LEFT | Buttons::A
+    );
+}
+
+/// Frames run in the first `updates` updates of a 60 Hz display
+fn paced_frames(pal: bool, updates: usize) -> u64 {
+    let mut app = build_app(pal, NesPlugin::with_pacing);
+    app.insert_resource(TimeUpdateStrategy::ManualDuration(Duration::from_secs_f64(
+        1.0 / 60.0,
+    )));
+    for _ in 0..updates {
+        app.update();
+    }
+    let nes = app.world().non_send_resource::<NesConsole>();
+    nes.console.bus().ppu.frame
+}
+
+#[test]
+fn pacing_follows_region() {
+    // The first update has no time since the last one
+    assert_eq!(paced_frames(false, 7), 6);
+    assert_eq!(paced_frames(true, 7), 5);
+    assert_eq!(paced_frames(true, 61), 50);
+}
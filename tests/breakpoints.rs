@@ -0,0 +1,195 @@
+use nes::UnmappedAccess;
+use nes::breakpoints::{Breakpoint, PpuAccess};
+use nes::console::Console;
+use nes::error::CpuError;
+use nes::rom::Rom;
+
+mod common;
+
+/// UxROM with 4 banks. Switches bank 1 in, calls the RTS at its start and
+/// stops on a BRK. The NMI handler just returns.
+fn console() -> Console {
+    let mut ines = vec![0; 16 + 0x10000];
+    ines[..7].copy_from_slice(b"NES\x1a\x04\x00\x20");
+    let code: &[(usize, &[u8])] = &[
+        // LDA #$80; STA $2000; LDA #$01; STA $C006; JSR $8000; BRK
+        // The STA goes to the $01 in the LDA since UxROM has bus conflicts
+        (
+            0xc000,
+            &[
+                0xa9, 0x80, 0x8d, 0x00, 0x20, 0xa9, 0x01, 0x8d, 0x06, 0xc0, 0x20, 0x00, 0x80, 0x00,
+            ],
+        ),
+        // RTI
+        (0xd000, &[0x40]),
+        // RTS at the start of bank 1
+        (0x4000, &[0x60]),
+        (0xfffa, &[0x00, 0xd0, 0x00, 0xc0, 0x00, 0xc0]),
+    ];
+    for (offset, bytes) in code {
+        let start = 16 + offset;
+        ines[start..start + bytes.len()].copy_from_slice(bytes);
+    }
+    Console::new(Rom::new(&ines).unwrap())
+}
+
+fn stops_at(console: &mut Console, pc: u16, breakpoint: Breakpoint) {
+    assert_eq!(console.try_step(), Err(CpuError::DebugBreak { pc }));
+    assert_eq!(console.cpu.pc, pc);
+    assert_eq!(console.bus().breakpoints.hit(), Some(breakpoint));
+}
+
+#[test]
+fn breakpoints() {
+    let mut console = console();
+    let bank = Breakpoint::PrgBank {
+        bank: 1,
+        size: 0x4000,
+    };
+    let breakpoints = &mut console.bus_mut().breakpoints;
+    for breakpoint in [
+        Breakpoint::Pc(0xc005),
+        Breakpoint::BankSwitch,
+        bank,
+        Breakpoint::Brk,
+        Breakpoint::Nmi,
+    ] {
+        breakpoints.add(breakpoint);
+    }
+    console.try_step().unwrap();
+    console.try_step().unwrap();
+
+    // Before the LDA, then stepping runs it
+    stops_at(&mut console, 0xc005, Breakpoint::Pc(0xc005));
+    console.try_step().unwrap();
+    assert_eq!(console.cpu.reg_a, 1);
+
+    // After the STA that switched banks
+    stops_at(&mut console, 0xc00a, Breakpoint::BankSwitch);
+    console.try_step().unwrap();
+
+    // Before the RTS in bank 1
+    stops_at(&mut console, 0x8000, bank);
+    console.try_step().unwrap();
+
+    stops_at(&mut console, 0xc00d, Breakpoint::Brk);
+
+    let breakpoints = &mut console.bus_mut().breakpoints;
+    assert!(breakpoints.remove(Breakpoint::Brk));
+    assert!(!breakpoints.remove(Breakpoint::Brk));
+    assert_eq!(breakpoints.list().len(), 4);
+    // Spins on the BRK until vblank
+    let stop = loop {
+        if let Err(err) = console.try_step() {
+            break err;
+        }
+    };
+    assert_eq!(stop, CpuError::DebugBreak { pc: 0xd000 });
+    assert_eq!(console.bus().breakpoints.hit(), Some(Breakpoint::Nmi));
+    assert_eq!(console.bus().ppu.timing().scanline, 241);
+}
+
+#[test]
+fn no_breakpoints() {
+    let mut console = console();
+    for _ in 0..100 {
+        console.try_step().unwrap();
+    }
+    assert_eq!(console.bus().breakpoints.hit(), None);
+}
+
+/// NROM writing $21 to the first palette entry, then reading the first byte
+/// of tile 1
+fn ppu_console() -> Console {
+    let code = [
+        // LDA #$3F; STA $2006; LDA #$00; STA $2006; LDA #$21; STA $2007
+        0xa9, 0x3f, 0x8d, 0x06, 0x20, 0xa9, 0x00, 0x8d, 0x06, 0x20, 0xa9, 0x21, 0x8d, 0x07, 0x20,
+        // LDA #$00; STA $2006; LDA #$10; STA $2006; LDA $2007; JMP *
+        0xa9, 0x00, 0x8d, 0x06, 0x20, 0xa9, 0x10, 0x8d, 0x06, 0x20, 0xad, 0x07, 0x20, 0x4c, 0x1c,
+        0xc0,
+    ];
+    let mut ines = common::ines(&[(0xc000, &code)]);
+    ines[16 + 0x4000 + 0x10] = 0x5a;
+    Console::new(Rom::new(&ines).unwrap())
+}
+
+#[test]
+fn ppu_breakpoints() {
+    let mut console = ppu_console();
+    let breakpoints = &mut console.bus_mut().breakpoints;
+    breakpoints.add(Breakpoint::nametable_writes());
+    breakpoints.add(Breakpoint::palette_writes());
+    breakpoints.add(Breakpoint::tile_reads(1));
+    breakpoints.add(Breakpoint::tile_reads(2));
+    for _ in 0..5 {
+        console.try_step().unwrap();
+    }
+    stops_at(&mut console, 0xc00f, Breakpoint::palette_writes());
+    assert_eq!(
+        console.bus().breakpoints.ppu_access(),
+        Some(PpuAccess {
+            addr: 0x3f00,
+            val: 0x21,
+            write: true,
+            pc: 0xc00c,
+        })
+    );
+    for _ in 0..4 {
+        console.try_step().unwrap();
+    }
+    stops_at(&mut console, 0xc01c, Breakpoint::tile_reads(1));
+    assert_eq!(
+        console.bus().breakpoints.ppu_access(),
+        Some(PpuAccess {
+            addr: 0x0010,
+            val: 0x5a,
+            write: false,
+            pc: 0xc019,
+        })
+    );
+    // The JMP doesn't touch the PPU
+    for _ in 0..10 {
+        console.try_step().unwrap();
+    }
+}
+
+#[test]
+fn unmapped_access() {
+    // NROM: LDA $6000; NOP; STA $5000; NOP, with nothing at $4020-$7FFF
+    let code = [0xad, 0x00, 0x60, 0xea, 0x8d, 0x00, 0x50, 0xea];
+    let mut console = Console::new(common::nrom(&[(0xc000, &code)]));
+    console.cpu.reset();
+    console.bus_mut().unmapped_access = UnmappedAccess::Break;
+    stops_at(&mut console, 0xc003, Breakpoint::Unmapped);
+    console.try_step().unwrap();
+    stops_at(&mut console, 0xc007, Breakpoint::Unmapped);
+
+    // Only stops with the policy set
+    console.cpu.reset();
+    console.bus_mut().unmapped_access = UnmappedAccess::WarnOnce;
+    for _ in 0..4 {
+        console.try_step().unwrap();
+    }
+}
+
+#[test]
+fn unmapped_by_board() {
+    // 64K of PRG ROM and 8K of CHR, from the 7 header bytes after "NES\x1a"
+    let board = |header: &[u8]| {
+        let mut ines = vec![0; 16 + 0x10000 + 0x2000];
+        ines[..4].copy_from_slice(b"NES\x1a");
+        ines[4..4 + header.len()].copy_from_slice(header);
+        nes::mapper::new(Rom::new(&ines).unwrap())
+    };
+    let uxrom = board(&[0x04, 0x01, 0x20]);
+    assert!(uxrom.unmapped(0x4020) && uxrom.unmapped(0x6000));
+    assert!(!uxrom.unmapped(0x8000));
+    // MMC1 has PRG RAM unless an NES 2.0 header says otherwise
+    let mmc1 = board(&[0x04, 0x01, 0x10]);
+    assert!(mmc1.unmapped(0x5fff) && !mmc1.unmapped(0x6000));
+    let mmc1 = board(&[0x04, 0x01, 0x10, 0x08]);
+    assert!(mmc1.unmapped(0x6000));
+    // N163's registers start at $4800
+    let n163 = board(&[0x04, 0x01, 0x30, 0x10]);
+    assert!(n163.unmapped(0x4700) && !n163.unmapped(0x4800) && !n163.unmapped(0x5800));
+}
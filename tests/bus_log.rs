@@ -0,0 +1,85 @@
+use nes::bus_log::BusAccess;
+use nes::console::Console;
+
+mod common;
+
+/// NROM running LDA #$05; STA $0200; JMP $C005 from reset
+fn test_console() -> Console {
+    let code = [0xa9, 0x05, 0x8d, 0x00, 0x02, 0x4c, 0x05, 0xc0];
+    let mut console = Console::new(common::nrom(&[(0xc000, &code)]));
+    console.cpu.reset();
+    console
+}
+
+fn accesses(console: &Console) -> Vec<(u64, u16, u8, bool)> {
+    let log = &console.bus().bus_log;
+    log.accesses()
+        .map(
+            |&BusAccess {
+                 cycle,
+                 addr,
+                 val,
+                 write,
+             }| (cycle, addr, val, write),
+        )
+        .collect()
+}
+
+#[test]
+fn cycle_stepped() {
+    let mut console = test_console();
+    console.cpu.cycle_stepped = true;
+    let bus = console.bus_mut();
+    bus.bus_log.start(bus.cycles, 100);
+    console.step();
+    console.step();
+    assert_eq!(
+        accesses(&console),
+        [
+            (0, 0xc000, 0xa9, false),
+            (1, 0xc001, 0x05, false),
+            (2, 0xc002, 0x8d, false),
+            (3, 0xc003, 0x00, false),
+            (4, 0xc004, 0x02, false),
+            (5, 0x0200, 0x05, true),
+        ]
+    );
+    assert!(
+        console
+            .bus()
+            .bus_log
+            .to_csv()
+            .starts_with("cycle,address,value,access\n0,C000,A9,R\n1,C001,05,R\n")
+    );
+    assert!(console.bus().bus_log.to_csv().ends_with("5,0200,05,W\n"));
+}
+
+#[test]
+fn window() {
+    let mut console = test_console();
+    let bus = console.bus_mut();
+    bus.bus_log.start(bus.cycles, 2);
+    console.step();
+    console.step();
+    // Only the last two are kept. Whole instructions peek their operands
+    // and take their cycles at the end, so STA shows its opcode and write
+    // on the cycle LDA ended.
+    assert_eq!(
+        accesses(&console),
+        [(2, 0xc002, 0x8d, false), (2, 0x0200, 0x05, true)]
+    );
+
+    console.bus_mut().bus_log.stop();
+    console.step();
+    assert_eq!(accesses(&console).len(), 2);
+}
+
+#[test]
+fn status_reads_and_going_back() {
+    let mut console = test_console();
+    let bus = console.bus_mut();
+    // Like a state from before the log started being loaded
+    bus.bus_log.start(bus.cycles + 50, 10);
+    bus.read(0x4015);
+    assert_eq!(accesses(&console), [(0, 0x4015, 0x00, false)]);
+}
@@ -0,0 +1,47 @@
+//! Cartridges shared by the tests that declare `mod common;`
+
+// Each test file is its own crate and uses only some of these
+#![allow(dead_code)]
+
+use nes::rom::Rom;
+
+/// iNES image of a 16K NROM cartridge with 8K of CHR ROM, with each of
+/// `code`'s bytes at its address in $C000-$FFFF and reset at $C000 unless
+/// `code` says otherwise
+pub fn ines(code: &[(u16, &[u8])]) -> Vec<u8> {
+    let mut ines = vec![0; 16 + 0x4000 + 0x2000];
+    ines[..6].copy_from_slice(b"NES\x1a\x01\x01");
+    ines[16 + 0x3ffc..16 + 0x3ffe].copy_from_slice(&[0x00, 0xc0]);
+    for (addr, bytes) in code {
+        let start = 16 + (*addr as usize - 0xc000);
+        ines[start..start + bytes.len()].copy_from_slice(bytes);
+    }
+    ines
+}
+
+/// [`ines`], parsed
+pub fn nrom(code: &[(u16, &[u8])]) -> Rom {
+    Rom::new(&ines(code)).unwrap()
+}
+
+/// Reads both controllers in the NMI and folds them into $11 and $13
+pub fn two_players() -> Rom {
+    nrom(&[
+        // LDA #$80; STA $2000; JMP *
+        (0xc000, &[0xa9, 0x80, 0x8d, 0x00, 0x20, 0x4c, 0x05, 0xc0]),
+        (
+            0xd000,
+            &[
+                // LDA #1; STA $4016; LDA #0; STA $4016; LDX #8
+                0xa9, 0x01, 0x8d, 0x16, 0x40, 0xa9, 0x00, 0x8d, 0x16, 0x40, 0xa2, 0x08,
+                // LDA $4016; LSR A; ROL $10; LDA $4017; LSR A; ROL $12; DEX; BNE loop
+                0xad, 0x16, 0x40, 0x4a, 0x26, 0x10, 0xad, 0x17, 0x40, 0x4a, 0x26, 0x12, 0xca, 0xd0,
+                0xf1,
+                // LDA $11; ASL A; ADC $10; STA $11; LDA $13; ASL A; ADC $12; STA $13; RTI
+                0xa5, 0x11, 0x0a, 0x65, 0x10, 0x85, 0x11, 0xa5, 0x13, 0x0a, 0x65, 0x12, 0x85, 0x13,
+                0x40,
+            ],
+        ),
+        (0xfffa, &[0x00, 0xd0, 0x00, 0xc0, 0x00, 0xc0]),
+    ])
+}
@@ -0,0 +1,66 @@
+use nes::apu::ExpansionChip;
+use nes::compat::{CompatIssue, Nes2Field};
+use nes::rom::Rom;
+
+mod common;
+
+/// A 16K PRG, 8K CHR game with header bytes 6 on set to `flags`
+fn rom(flags: &[u8]) -> Rom {
+    let mut ines = common::ines(&[]);
+    ines[6..6 + flags.len()].copy_from_slice(flags);
+    if flags.first().is_some_and(|flags6| flags6 & 0x04 != 0) {
+        // The trainer goes between the header and PRG ROM
+        ines.splice(16..16, [0; 512]);
+    }
+    Rom::from_ines(&ines).unwrap()
+}
+
+#[test]
+fn supported_game() {
+    let report = rom(&[]).compat();
+    assert!(report.is_empty());
+    assert!(!report.may_break());
+}
+
+#[test]
+fn mappers() {
+    // VRC6
+    let report = rom(&[0x80, 0x10]).compat();
+    assert_eq!(report.issues, [CompatIssue::UnsupportedMapper(0x18)]);
+    assert!(report.may_break());
+
+    // MMC5 is emulated, its sound isn't
+    let report = rom(&[0x50]).compat();
+    assert_eq!(
+        report.issues,
+        [CompatIssue::ExpansionAudio(ExpansionChip::Mmc5)]
+    );
+    assert!(!report.may_break());
+}
+
+#[test]
+fn header() {
+    let report = rom(&[0x04]).compat();
+    assert_eq!(report.issues, [CompatIssue::Trainer]);
+
+    let report = rom(&[0, 0, 0, 1]).compat();
+    assert_eq!(report.issues, [CompatIssue::Pal]);
+    assert!(!report.may_break());
+
+    // NES 2.0 with 8K of battery backed PRG RAM, 8K of CHR RAM, Dendy
+    // timing, a miscellaneous ROM and a Zapper
+    let report = rom(&[0, 0x08, 0, 0, 0x70, 0x07, 0x03, 0, 0x01, 0x08]).compat();
+    assert_eq!(
+        report.issues,
+        [
+            CompatIssue::Nes2Field(Nes2Field::MiscRoms),
+            CompatIssue::Nes2Field(Nes2Field::DefaultExpansionDevice(8)),
+            CompatIssue::Dendy,
+        ]
+    );
+    assert!(report.may_break());
+    assert_eq!(
+        report.issues[0].to_string(),
+        "NES 2.0 miscellaneous ROM isn't supported"
+    );
+}
@@ -0,0 +1,158 @@
+use std::path::{Path, PathBuf};
+
+use nes::config::{
+    Action, Dirs, GameSettings, HotkeyMap, InputBindings, InputDevice, RECENT_COUNT, RecentRoms,
+};
+use nes::console::Console;
+use nes::joypad::Buttons;
+use nes::rom::{Region, Rom};
+use nes::savestate::SaveSlots;
+
+fn temp_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("nes-{name}-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    dir
+}
+
+fn rom(fill: u8) -> Rom {
+    let mut ines = vec![fill; 16 + 0x4000 + 0x2000];
+    ines[..16].copy_from_slice(b"NES\x1a\x01\x01\0\0\0\0\0\0\0\0\0\0");
+    Rom::new(&ines).unwrap()
+}
+
+#[test]
+fn recent_roms() {
+    let dir = temp_dir("recent");
+    let mut recent = RecentRoms::load(&dir).unwrap();
+    assert!(recent.roms.is_empty());
+    for i in 0..RECENT_COUNT + 2 {
+        recent.add(Path::new(&format!("/games/{i}.nes")));
+    }
+    // Opening one again moves it to the top
+    recent.add(Path::new("/games/5.nes"));
+    recent.save().unwrap();
+
+    let recent = RecentRoms::load(&dir).unwrap();
+    assert_eq!(recent.roms.len(), RECENT_COUNT);
+    assert_eq!(recent.roms[0], Path::new("/games/5.nes"));
+    assert_eq!(recent.roms[1], Path::new("/games/11.nes"));
+    assert!(!recent.roms.contains(&PathBuf::from("/games/1.nes")));
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn game_settings() {
+    let dir = temp_dir("settings");
+    let mut settings = GameSettings::load(&dir, &rom(0)).unwrap();
+    settings.set("overclock", "20");
+    settings.set("sprite-limit", "off");
+    settings.set("region", "dendy");
+    settings.set("dip-switches", "4");
+    settings.set("zapper", "crosshair");
+    settings.save().unwrap();
+
+    // Another game has its own
+    assert_eq!(
+        GameSettings::load(&dir, &rom(0xea)).unwrap().iter().count(),
+        0
+    );
+
+    let settings = GameSettings::load(&dir, &rom(0)).unwrap();
+    assert_eq!(settings.get("zapper"), Some("crosshair"));
+    let mut console = Console::new(rom(0));
+    settings.apply(&mut console).unwrap();
+    let ppu = &console.bus().ppu;
+    assert_eq!((ppu.overclock.ntsc, ppu.overclock.pal), (20, 20));
+    assert!(!ppu.sprite_limit);
+    assert_eq!(ppu.region, Region::Dendy);
+
+    let mut settings = settings;
+    settings.set("open-bus-decay", "soon");
+    assert!(settings.apply(&mut console).is_err());
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn portable_dirs() {
+    let dirs = Dirs::in_dir(Path::new("/opt/nes"));
+    assert_eq!(dirs.config, Path::new("/opt/nes"));
+    assert_eq!(dirs.saves, Path::new("/opt/nes/saves"));
+    let state_base = Dirs::file(&dirs.states, Path::new("/games/zelda.nes"));
+    assert_eq!(state_base, Path::new("/opt/nes/states/zelda.nes"));
+    assert_eq!(
+        SaveSlots::new(&state_base).path(1),
+        Path::new("/opt/nes/states/zelda.state1")
+    );
+}
+
+#[test]
+fn input_bindings() {
+    let dir = temp_dir("input");
+    let mut bindings = InputBindings::load(&dir).unwrap();
+    let (key, pad) = (InputDevice::Keyboard, InputDevice::Gamepad);
+    assert_eq!(bindings.get(key, Action::A), Some("X"));
+    assert_eq!(bindings.action(pad, "South"), Some(Action::A));
+    assert_eq!(
+        bindings.buttons(key, ["ArrowUp", "Z", "F5"]),
+        Buttons::UP | Buttons::B
+    );
+
+    // Taking a key from another action leaves that one unbound
+    bindings.bind(key, Action::A, "Z");
+    assert_eq!(bindings.get(key, Action::B), None);
+    bindings.bind(pad, Action::FlipDisk, "RightTrigger");
+    bindings.unbind(key, Action::Screenshot);
+    bindings.save().unwrap();
+
+    let mut bindings = InputBindings::load(&dir).unwrap();
+    assert_eq!(bindings.action(key, "Z"), Some(Action::A));
+    assert_eq!(bindings.get(key, Action::B), None);
+    assert_eq!(bindings.get(key, Action::Screenshot), None);
+    assert_eq!(bindings.get(pad, Action::FlipDisk), Some("RightTrigger"));
+    assert_eq!(bindings.get(key, Action::Start), Some("Enter"));
+
+    // Hotkeys follow the file, controller buttons aren't hotkeys
+    let hotkeys = HotkeyMap::load(&dir).unwrap();
+    assert_eq!(hotkeys.get(pad, "RightTrigger"), Some(Action::FlipDisk));
+    assert_eq!(hotkeys.get(key, "Backspace"), Some(Action::Rewind));
+    assert_eq!(hotkeys.get(key, "U"), Some(Action::Undo));
+    assert_eq!(hotkeys.get(key, "T"), Some(Action::PlayMacro));
+    assert_eq!(hotkeys.get(key, "F12"), None);
+    assert_eq!(hotkeys.get(key, "Z"), None);
+
+    bindings.reset();
+    assert_eq!(bindings.get(key, Action::B), Some("Z"));
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn unreadable_files() {
+    let dir = temp_dir("unreadable");
+    let rom = rom(0);
+    let settings = GameSettings::empty(&dir, &rom);
+    std::fs::create_dir_all(settings.path().parent().unwrap()).unwrap();
+    std::fs::write(settings.path(), b"\xff").unwrap();
+    assert!(GameSettings::load(&dir, &rom).is_err());
+    assert_eq!(settings.iter().count(), 0);
+
+    let bindings = InputBindings::defaults(&dir);
+    std::fs::write(bindings.path(), b"\xff").unwrap();
+    assert!(InputBindings::load(&dir).is_err());
+    assert_eq!(bindings.get(InputDevice::Keyboard, Action::B), Some("Z"));
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn game_controls() {
+    let dir = temp_dir("game-controls");
+    let mut settings = GameSettings::load(&dir, &rom(0)).unwrap();
+    settings.set("key.a", "Space");
+    settings.set("pad.select", "");
+    settings.set("overclock", "20");
+    let mut bindings = InputBindings::load(&dir).unwrap();
+    bindings.override_with(&settings);
+    let (key, pad) = (InputDevice::Keyboard, InputDevice::Gamepad);
+    assert_eq!(bindings.get(key, Action::A), Some("Space"));
+    assert_eq!(bindings.get(pad, Action::Select), None);
+    assert_eq!(bindings.get(key, Action::B), Some("Z"));
+}
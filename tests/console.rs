@@ -0,0 +1,202 @@
+use std::time::{Duration, UNIX_EPOCH};
+
+use nes::clock::EmulatedClock;
+use nes::console::Console;
+use nes::error::StateError;
+use nes::ppu::{PpuMask, RenderMode};
+use nes::rom::Rom;
+
+mod common;
+
+/// Spins on `JMP $C000`
+fn console() -> Console {
+    Console::new(common::nrom(&[(0xc000, &[0x4c, 0x00, 0xc0])]))
+}
+
+#[test]
+fn run_to_scanline() {
+    let mut console = console();
+    console.run_to_scanline(241);
+    let timing = console.bus().ppu.timing();
+    assert_eq!(timing.scanline, 241);
+    assert_eq!(timing.frame, 0);
+    assert!(!timing.rendering_enabled);
+    // JMP takes 3 cycles, so at most 9 dots late
+    assert!(timing.dot < 9);
+
+    // The next occurrence is in the next frame
+    console.run_to_scanline(10);
+    assert_eq!(console.bus().ppu.timing().frame, 1);
+    assert_eq!(console.bus().ppu.timing().scanline, 10);
+}
+
+#[test]
+fn run_to_dot() {
+    let mut console = console();
+    console.bus_mut().ppu.mask = PpuMask::SHOW_BG;
+    for (scanline, dot) in [(20, 100), (20, 340), (261, 339), (0, 0)] {
+        console.run_to_dot(scanline, dot);
+        let timing = console.bus().ppu.timing();
+        assert!(timing.rendering_enabled);
+        let pos = |scanline: u16, dot: u16| scanline as u32 * 341 + dot as u32;
+        let late =
+            (pos(timing.scanline, timing.dot) + 262 * 341 - pos(scanline, dot)) % (262 * 341);
+        assert!(late < 9, "{scanline} {dot}: {timing:?}");
+    }
+    // A PAL scanline on NTSC stops at the end of the frame
+    console.run_to_dot(300, 400);
+    let timing = console.bus().ppu.timing();
+    assert!(
+        (timing.scanline, timing.dot) == (261, 340) || (timing.scanline, timing.dot) < (0, 9),
+        "{timing:?}"
+    );
+    // Already there
+    let timing = console.bus().ppu.timing();
+    console.run_to_dot(timing.scanline, timing.dot);
+    assert_eq!(console.bus().ppu.timing(), timing);
+}
+
+#[test]
+fn state_hash() {
+    let mut a = console();
+    let mut b = console();
+    for _ in 0..3 {
+        a.run_frame();
+        b.run_frame();
+    }
+    let hash = a.state_hash();
+    assert_eq!(hash, b.state_hash());
+    // Hashing doesn't change anything
+    assert_eq!(a.state_hash(), hash);
+    assert_eq!(a.save_state(None).state_hash(), hash);
+
+    b.bus_mut().cpu_ram[0x42] = 1;
+    assert_ne!(b.state_hash(), hash);
+    a.run_frame();
+    assert_ne!(a.state_hash(), hash);
+}
+
+#[test]
+fn frame_hash() {
+    let mut a = console();
+    let mut b = console();
+    for console in [&mut a, &mut b] {
+        console.bus_mut().ppu.render_mode = RenderMode::Scanline;
+        console.run_frame();
+    }
+    let hash = a.frame_hash();
+    assert_eq!(hash, b.frame_hash());
+
+    // A different backdrop color, drawn on the next frame
+    b.bus_mut().ppu.palette[0] = 0x21;
+    assert_ne!(b.frame_hash(), hash);
+    b.run_frame();
+    assert_ne!(b.frame_hash(), hash);
+    b.bus_mut().ppu.palette[0] = a.bus().ppu.palette[0];
+    assert_ne!(b.frame_hash(), hash);
+}
+
+#[test]
+fn open_bus() {
+    // LDA $4016; STA $00; LDA $4018; STA $01; JMP *
+    let code = [
+        0xad, 0x16, 0x40, 0x85, 0x00, 0xad, 0x18, 0x40, 0x85, 0x01, 0x4c, 0x0a, 0xc0,
+    ];
+    let mut console = Console::new(common::nrom(&[(0xc000, &code)]));
+    console.run_frame();
+    // The high byte of the operand is still on the bus
+    assert_eq!(console.bus().cpu_ram[..2], [0x40, 0x40]);
+
+    let bus = console.bus_mut();
+    bus.write(0x0000, 0x12);
+    assert_eq!(bus.read(0x4000), 0x12);
+    bus.write(0x0000, 0xff);
+    assert_eq!(bus.read(0x4015) & 0x20, 0x20);
+    // Reading $4015 doesn't drive the bus
+    assert_eq!(bus.read(0x4016), 0xe0);
+    assert_eq!(bus.read(0x401f), 0xe0);
+}
+
+/// MMC3 spinning on `JMP $E000`, with `version` at the start of PRG ROM bank 1
+fn mmc3(version: u8) -> Vec<u8> {
+    let mut ines = vec![0; 16 + 0x8000 + 0x2000];
+    ines[..7].copy_from_slice(b"NES\x1a\x02\x01\x40");
+    ines[16 + 0x2000] = version;
+    ines[16 + 0x6000..16 + 0x6003].copy_from_slice(&[0x4c, 0x00, 0xe0]);
+    ines[16 + 0x7ffc..16 + 0x7ffe].copy_from_slice(&[0x00, 0xe0]);
+    ines
+}
+
+#[test]
+fn reload_rom() {
+    let mut console = Console::new(Rom::new(&mmc3(1)).unwrap());
+    console.cpu.reset();
+    console.run_frame();
+    let bus = console.bus_mut();
+    // Bank 1 at $8000
+    bus.write(0x8000, 6);
+    bus.write(0x8001, 1);
+    bus.write(0x6000, 0x55);
+    bus.write(0x0010, 7);
+    assert_eq!(bus.read(0x8000), 1);
+    console.cpu.reg_x = 9;
+    let hash = console.rom_hash();
+
+    // The new code shows up in the same bank, RAM and the CPU carry on
+    assert!(console.reload_rom(Rom::new(&mmc3(2)).unwrap(), true));
+    assert_ne!(console.rom_hash(), hash);
+    assert_eq!(console.cpu.reg_x, 9);
+    let bus = console.bus_mut();
+    assert_eq!(bus.read(0x8000), 2);
+    assert_eq!(bus.read(0x6000), 0x55);
+    assert_eq!(bus.read(0x0010), 7);
+    console.run_frame();
+
+    // Another board starts over, RAM is still kept
+    let mut nrom = mmc3(3);
+    nrom[6] = 0;
+    assert!(!console.reload_rom(Rom::new(&nrom).unwrap(), false));
+    assert_eq!(console.cpu.reg_x, 0);
+    assert_eq!(console.cpu.pc, 0xe000);
+    let bus = console.bus_mut();
+    assert_eq!(bus.read(0x8000), 0);
+    assert_eq!(bus.read(0x0010), 7);
+}
+
+#[test]
+fn load_state_any_rom() {
+    let mut console = Console::new(Rom::new(&mmc3(1)).unwrap());
+    console.bus_mut().write(0x0010, 7);
+    let state = console.save_state(None);
+
+    let mut rebuilt = Console::new(Rom::new(&mmc3(2)).unwrap());
+    assert!(matches!(
+        rebuilt.load_state(&state),
+        Err(StateError::WrongRom { .. })
+    ));
+    rebuilt.load_state_any_rom(&state).unwrap();
+    assert_eq!(rebuilt.bus().cpu_ram[0x10], 7);
+
+    // Not on another board
+    let mut nrom = mmc3(2);
+    nrom[6] = 0;
+    let mut other = Console::new(Rom::new(&nrom).unwrap());
+    assert!(other.load_state_any_rom(&state).is_err());
+    assert_eq!(other.bus().cpu_ram[0x10], 0);
+}
+
+#[test]
+fn time_source() {
+    let mut console = console();
+    let clock = EmulatedClock::default();
+    console.set_time_source(clock);
+    assert_eq!(console.now(), clock.start);
+    // Runs with the console, not the host
+    for _ in 0..120 {
+        console.run_frame();
+    }
+    let elapsed = console.now().duration_since(clock.start).unwrap();
+    assert!(elapsed.abs_diff(Duration::from_secs(2)) < Duration::from_millis(20));
+    let start = clock.start.duration_since(UNIX_EPOCH).unwrap().as_secs();
+    assert_eq!(console.save_state(None).timestamp, start + 1);
+}
@@ -0,0 +1,73 @@
+#![cfg(feature = "egui")]
+
+use egui::{Event, Key, Modifiers, RawInput};
+use nes::config::{Action, InputBindings, InputDevice};
+use nes::debug_ui::{BindingCapture, input_bindings};
+
+/// A frame of the bindings panel with `key` pressed, returning whether the
+/// bindings changed
+fn frame(bindings: &mut InputBindings, capture: &mut BindingCapture, key: Option<Key>) -> bool {
+    let events = key
+        .map(|key| Event::Key {
+            key,
+            physical_key: None,
+            pressed: true,
+            repeat: false,
+            modifiers: Modifiers::NONE,
+        })
+        .into_iter()
+        .collect();
+    let mut changed = false;
+    let _ = egui::Context::default().run(
+        RawInput {
+            events,
+            ..Default::default()
+        },
+        |ctx| {
+            // Laying out the grid takes egui a second pass
+            egui::CentralPanel::default().show(ctx, |ui| {
+                changed |= input_bindings(ui, bindings, capture);
+            });
+        },
+    );
+    changed
+}
+
+#[test]
+fn rebind() {
+    let dir = std::env::temp_dir().join(format!("nes-rebind-{}", std::process::id()));
+    let mut bindings = InputBindings::load(&dir).unwrap();
+    let mut capture = BindingCapture::default();
+    assert!(!frame(&mut bindings, &mut capture, Some(Key::S)));
+
+    capture.start(InputDevice::Keyboard, Action::Start);
+    assert!(!frame(&mut bindings, &mut capture, None));
+    assert!(capture.waiting());
+    assert!(frame(&mut bindings, &mut capture, Some(Key::S)));
+    assert!(!capture.waiting());
+    assert_eq!(
+        bindings.get(InputDevice::Keyboard, Action::Start),
+        Some("S")
+    );
+
+    // Escape cancels
+    capture.start(InputDevice::Keyboard, Action::Select);
+    assert!(!frame(&mut bindings, &mut capture, Some(Key::Escape)));
+    assert!(!capture.waiting());
+    assert_eq!(
+        bindings.get(InputDevice::Keyboard, Action::Select),
+        Some("Space")
+    );
+
+    // Gamepads are passed on by the frontend, and only count while waiting
+    capture.gamepad_pressed("North");
+    capture.start(InputDevice::Gamepad, Action::B);
+    capture.gamepad_pressed("East");
+    assert!(frame(&mut bindings, &mut capture, None));
+    assert_eq!(bindings.get(InputDevice::Gamepad, Action::B), Some("East"));
+
+    // Saved as they change
+    let saved = InputBindings::load(&dir).unwrap();
+    assert_eq!(saved.get(InputDevice::Keyboard, Action::Start), Some("S"));
+    std::fs::remove_dir_all(&dir).unwrap();
+}
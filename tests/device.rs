@@ -0,0 +1,62 @@
+use nes::console::Console;
+use nes::device::RandomDevice;
+
+mod common;
+
+/// Stores 4 numbers read from $FE at $10-$13, then loops
+fn console(seed: u64) -> Console {
+    let mut console = Console::new(common::nrom(&[(
+        0xc000,
+        &[
+            // LDA $FE; STA $10; LDA $FE; STA $11; LDA $FE; STA $12; LDA $FE; STA $13
+            0xa5, 0xfe, 0x85, 0x10, 0xa5, 0xfe, 0x85, 0x11, 0xa5, 0xfe, 0x85, 0x12, 0xa5, 0xfe,
+            0x85, 0x13, // JMP *
+            0x4c, 0x10, 0xc0,
+        ],
+    )]));
+    let device = Box::new(RandomDevice::new(0xfe, seed));
+    console.bus_mut().devices.push(device);
+    console.cpu.reset();
+    console
+}
+
+fn numbers(console: &Console) -> [u8; 4] {
+    std::array::from_fn(|i| console.bus().peek(0x10 + i as u16))
+}
+
+#[test]
+fn random_device() {
+    let mut first = console(7);
+    // Peeking doesn't use up a number
+    let next = first.bus().peek(0xfe);
+    assert_eq!(first.bus().peek(0xfe), next);
+    first.run_frame();
+    let seen = numbers(&first);
+    assert_eq!(seen[0], next);
+    assert_ne!(seen, [seen[0]; 4]);
+
+    let mut again = console(7);
+    again.run_frame();
+    assert_eq!(numbers(&again), seen);
+    let mut other = console(8);
+    other.run_frame();
+    assert_ne!(numbers(&other), seen);
+
+    // The device answers in front of RAM, writes included
+    first.bus_mut().write(0xfe, 0x55);
+    assert_eq!(first.bus().cpu_ram[0xfe], 0);
+    assert!(first.bus_mut().device_mut::<RandomDevice>().is_some());
+}
+
+#[test]
+fn random_device_state() {
+    let mut console = console(7);
+    let state = console.save_state(None);
+    console.run_frame();
+    let seen = numbers(&console);
+
+    console.load_state(&state).unwrap();
+    console.bus_mut().cpu_ram[0x10..0x14].fill(0);
+    console.run_frame();
+    assert_eq!(numbers(&console), seen);
+}
@@ -0,0 +1,181 @@
+use nes::console::Console;
+use nes::diagnostics::{Call, DiagnosticKind};
+use nes::error::CpuError;
+
+mod common;
+
+/// NROM running `code` from $C000
+fn console(code: &[u8]) -> Console {
+    Console::new(common::nrom(&[(0xc000, code)]))
+}
+
+const CODE: &[u8] = &[
+    0xa2, 0x00, // LDX #$00
+    0x9a, // TXS
+    0x48, // PHA
+    0x68, // PLA
+    0xa5, 0x10, // LDA $10
+    0x85, 0x11, // STA $11
+    0xa5, 0x11, // LDA $11
+    0x8d, 0x00, 0x80, // STA $8000
+    0x4c, 0x20, 0xc0, // JMP $C020
+];
+
+fn run(console: &mut Console) {
+    for _ in 0..20 {
+        console.step();
+    }
+}
+
+#[test]
+fn reports_problems() {
+    let mut code = CODE.to_vec();
+    code.resize(0x20, 0xea);
+    // JMP $C020, which the log has as data
+    code.extend([0x4c, 0x20, 0xc0]);
+    let mut cdl = vec![0x01; code.len()];
+    cdl[0x20] = 0x02;
+
+    let mut console = console(&code);
+    let diagnostics = &mut console.bus_mut().diagnostics;
+    diagnostics.enabled = true;
+    diagnostics.set_cdl(cdl);
+    run(&mut console);
+
+    let reports = console.bus_mut().diagnostics.take_reports();
+    let found: Vec<_> = reports
+        .iter()
+        .map(|report| (report.kind, report.pc))
+        .collect();
+    assert_eq!(
+        found,
+        [
+            (DiagnosticKind::StackOverflow, 0xc003),
+            (DiagnosticKind::StackUnderflow, 0xc004),
+            (DiagnosticKind::UninitializedRead { addr: 0x10 }, 0xc005),
+            (
+                DiagnosticKind::RomWrite {
+                    addr: 0x8000,
+                    val: 0
+                },
+                0xc00b
+            ),
+            // Only once, though it runs again every instruction
+            (DiagnosticKind::DataExecuted { offset: 0x20 }, 0xc020),
+        ]
+    );
+    let overflow = &reports[0];
+    assert_eq!(overflow.trace.len(), 3);
+    assert!(overflow.trace[2].starts_with("C003  48        PHA"));
+    assert!(
+        overflow
+            .to_string()
+            .starts_with("stack overflow, wrapped from $0100 to $01FF at $C003\n    C000")
+    );
+    assert!(console.bus().diagnostics.reports().is_empty());
+}
+
+#[test]
+fn off_by_default() {
+    let mut console = console(CODE);
+    run(&mut console);
+    assert!(console.bus().diagnostics.reports().is_empty());
+}
+
+#[test]
+fn savestates_initialize_ram() {
+    let mut console = console(CODE);
+    let state = console.save_state(None);
+    console.bus_mut().diagnostics.enabled = true;
+    console.load_state(&state).unwrap();
+    run(&mut console);
+    let kinds: Vec<_> = console
+        .bus()
+        .diagnostics
+        .reports()
+        .iter()
+        .map(|report| report.kind)
+        .collect();
+    assert!(!kinds.contains(&DiagnosticKind::UninitializedRead { addr: 0x10 }));
+    assert!(kinds.contains(&DiagnosticKind::StackOverflow));
+}
+
+#[test]
+fn return_mismatch() {
+    let mut code = vec![0xea; 0x22];
+    code[..9].copy_from_slice(&[
+        0x20, 0x10, 0xc0, // JSR $C010
+        0x20, 0x20, 0xc0, // JSR $C020
+        0x4c, 0x06, 0xc0, // JMP $C006
+    ]);
+    code[0x10] = 0x60; // RTS
+    code[0x20..].copy_from_slice(&[
+        0x48, // PHA
+        0x60, // RTS
+    ]);
+    let mut console = console(&code);
+    let diagnostics = &mut console.bus_mut().diagnostics;
+    diagnostics.enabled = true;
+    diagnostics.break_on_return_mismatch = true;
+    for _ in 0..4 {
+        console.try_step().unwrap();
+    }
+    let calls: Vec<_> = console.bus().diagnostics.call_stack().copied().collect();
+    assert_eq!(
+        calls,
+        [Call {
+            from: 0xc003,
+            to: 0xc020,
+            return_addr: 0xc006,
+            sp: 0xfb,
+            interrupt: false,
+        }]
+    );
+
+    // The RTS pulls the pushed A and half the return address
+    assert_eq!(console.try_step(), Err(CpuError::DebugBreak { pc: 0xc021 }));
+    assert_eq!(console.cpu.pc, 0xc021);
+    let reports = console.bus().diagnostics.reports();
+    assert_eq!(reports.len(), 1);
+    assert_eq!(
+        reports[0].kind,
+        DiagnosticKind::ReturnMismatch {
+            expected: 0xc006,
+            found: 0x0501,
+        }
+    );
+    assert_eq!(
+        reports[0].to_string().lines().next(),
+        Some("returning to $0501 instead of $C006 at $C021")
+    );
+    console.try_step().unwrap();
+    assert_eq!(console.cpu.pc, 0x0501);
+}
+
+#[test]
+fn interrupts_return_with_rti() {
+    let mut code = vec![0xea; 0x3ffc];
+    code[..10].copy_from_slice(&[
+        0xa9, 0x80, // LDA #$80
+        0x8d, 0x00, 0x20, // STA $2000, NMI on
+        0x85, 0x10, // STA $10
+        0x4c, 0x07, 0xc0, // JMP $C007
+    ]);
+    code[0x30..0x36].copy_from_slice(&[
+        0xe6, 0x10, // INC $10
+        0x20, 0x40, 0xc0, // JSR $C040
+        0x40, // RTI
+    ]);
+    code[0x40] = 0x60; // RTS
+    code[0x3ffa..].copy_from_slice(&[0x30, 0xc0]);
+    let mut console = console(&code);
+    let diagnostics = &mut console.bus_mut().diagnostics;
+    diagnostics.enabled = true;
+    diagnostics.break_on_return_mismatch = true;
+    for _ in 0..3 {
+        console.try_run_frame().unwrap();
+    }
+    assert!(console.bus().peek(0x10) >= 0x82);
+    assert!(console.bus().diagnostics.reports().is_empty());
+    assert_eq!(console.bus().diagnostics.call_stack().count(), 0);
+}
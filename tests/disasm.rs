@@ -0,0 +1,77 @@
+use nes::disasm::disassemble;
+use nes::labels::Labels;
+use nes::rom::Rom;
+
+mod common;
+
+const CODE: &[u8] = &[
+    0xad, 0x02, 0x20, // LDA $2002
+    0x10, 0xfb, // BPL $C000
+    0xad, 0x10, 0x00, // LDA $0010
+    0xbd, 0x0e, 0xc0, // LDA $C00E,X
+    0x4c, 0x0b, 0xc0, // JMP $C00B
+    0x01, 0x02, 0x03, // table
+];
+
+/// NROM with `CODE` at $C000, which reset and NMI point to
+fn rom() -> Rom {
+    common::nrom(&[
+        (0xc000, CODE),
+        (0xfffa, &[0x00, 0xc0, 0x00, 0xc0, 0x0e, 0xc0]),
+    ])
+}
+
+/// The instructions and data of `source` without their comments
+fn lines(source: &str) -> Vec<&str> {
+    source
+        .lines()
+        .map(|line| line.split(';').next().unwrap().trim())
+        .filter(|line| !line.is_empty())
+        .collect()
+}
+
+#[test]
+fn disassembles_with_labels() {
+    let mut cdl = vec![0x01; 0x0e];
+    cdl.extend([0x02; 3]);
+    let mut labels = Labels::registers();
+    labels
+        .load_mlb("P:0000:Start:Waits for vblank\nR:0010-0011:counter\n")
+        .unwrap();
+    let source = disassemble(&rom(), Some(&cdl), &labels);
+    let lines = lines(&source);
+
+    let start = lines.iter().position(|&line| line == "Start:").unwrap();
+    assert_eq!(
+        lines[start..start + 10],
+        [
+            "Start:",
+            "lda PPUSTATUS",
+            "bpl Start",
+            "lda a:counter",
+            "lda LC00E,x",
+            "LC00B:",
+            "jmp LC00B",
+            "LC00E:",
+            ".byte $01, $02, $03",
+            ".byte $00, $00, $00, $00, $00, $00, $00, $00",
+        ]
+    );
+    assert_eq!(lines.last(), Some(&".word Start, Start, LC00E"));
+    assert!(lines.contains(&"counter = $0010"));
+    assert!(lines.contains(&".org $C000"));
+    assert!(source.contains("; Waits for vblank\nStart:"));
+    assert!(source.contains("; C00E  data\n"));
+    assert!(source.contains("; C011  not logged\n"));
+}
+
+#[test]
+fn bad_labels() {
+    let mut labels = Labels::new();
+    assert_eq!(
+        labels.load_mlb("P:0000:Start\nR:zz:counter"),
+        Err("line 2: bad address".to_string())
+    );
+    assert!(labels.load_mlb("P:0000:1st").is_err());
+    assert!(labels.load_mlb("R:0010-000F:counter").is_err());
+}
@@ -0,0 +1,245 @@
+use nes::Bus;
+use nes::mapper::{Gtrom, Mapper, PpuTarget};
+use nes::rom::Rom;
+
+mod common;
+
+/// NES 2.0 image with 16K PRG banks filled with $F0 | bank number,
+/// except for a $03 at the start of each, and 8K CHR banks filled with their number
+fn image(mapper: u8, submapper: u8, prg_banks: u8, chr_banks: u8) -> Rom {
+    let mut ines = vec![0; 16];
+    ines[..9].copy_from_slice(&[
+        b'N',
+        b'E',
+        b'S',
+        0x1a,
+        prg_banks,
+        chr_banks,
+        mapper << 4,
+        0x08 | (mapper & 0xf0),
+        submapper << 4,
+    ]);
+    for bank in 0..prg_banks {
+        let mut data = [0xf0 | bank; 0x4000];
+        data[0] = 0x03;
+        ines.extend(data);
+    }
+    ines.extend((0..chr_banks).flat_map(|bank| [bank; 0x2000]));
+    Rom::new(&ines).unwrap()
+}
+
+fn rom(mapper: u8, submapper: u8, prg_banks: u8, chr_banks: u8) -> Bus {
+    Bus::new(image(mapper, submapper, prg_banks, chr_banks))
+}
+
+#[test]
+fn nrom_ignores_writes() {
+    let mut bus = rom(0, 0, 2, 1);
+    for addr in [0x8000, 0x8001, 0xC001, 0xFFFF] {
+        bus.write(addr, 0x55);
+    }
+    assert_eq!((bus.read(0x8000), bus.read(0x8001)), (0x03, 0xf0));
+    assert_eq!((bus.read(0xC001), bus.read(0xFFFF)), (0xf1, 0xf1));
+}
+
+#[test]
+fn uxrom() {
+    let mut bus = rom(2, 1, 8, 0);
+    bus.write(0x8000, 5);
+    assert_eq!(bus.read(0x8001), 0xf5);
+    assert_eq!(bus.read(0xC001), 0xf7);
+
+    // The ROM drives $03 at $C000, which wins over the written bits
+    let mut bus = rom(2, 2, 8, 0);
+    bus.write(0xC000, 5);
+    assert_eq!(bus.read(0x8001), 0xf1);
+    // No conflict where the ROM has all bits set in the written value
+    bus.write(0xC001, 6);
+    assert_eq!(bus.read(0x8001), 0xf6);
+
+    // Assumed for UxROM without a submapper
+    let mut bus = rom(2, 0, 8, 0);
+    bus.write(0xC000, 5);
+    assert_eq!(bus.read(0x8001), 0xf1);
+}
+
+#[test]
+fn cnrom() {
+    let mut bus = rom(3, 1, 2, 4);
+    bus.write(0x8000, 2);
+    assert_eq!(bus.mapper.ppu_read(0x0000), 2);
+
+    let mut bus = rom(3, 2, 2, 4);
+    // $06 & $03
+    bus.write(0x8000, 0x06);
+    assert_eq!(bus.mapper.ppu_read(0x0000), 2);
+    // $13 & $F0
+    bus.write(0x8001, 0x13);
+    assert_eq!(bus.mapper.ppu_read(0x1fff), 0);
+}
+
+#[test]
+fn axrom() {
+    // No conflicts without a submapper
+    let mut bus = rom(7, 0, 8, 0);
+    bus.write(0x8000, 0x13);
+    assert_eq!(bus.read(0x8001), 0xf6);
+    assert_eq!(bus.read(0xC001), 0xf7);
+    assert_eq!(bus.mapper.ppu_target(0x2005), PpuTarget::Ciram(0x405));
+    assert_eq!(bus.mapper.ppu_target(0x2805), PpuTarget::Ciram(0x405));
+    bus.write(0x8000, 0x03);
+    assert_eq!(bus.mapper.ppu_target(0x2c05), PpuTarget::Ciram(0x005));
+
+    // AMROM
+    let mut bus = rom(7, 2, 8, 0);
+    bus.write(0x8000, 0x13);
+    assert_eq!(bus.read(0x8001), 0xf6);
+    assert_eq!(bus.mapper.ppu_target(0x2005), PpuTarget::Ciram(0x005));
+}
+
+#[test]
+fn color_dreams() {
+    let mut bus = rom(11, 0, 8, 4);
+    bus.write(0x8001, 0x21);
+    assert_eq!((bus.read(0x8001), bus.read(0xC001)), (0xf2, 0xf3));
+    assert_eq!(bus.mapper.ppu_read(0x0000), 2);
+
+    // Mapper 144 takes D0 from the ROM alone
+    let mut bus = rom(144, 0, 8, 4);
+    bus.write(0x8001, 0x21);
+    assert_eq!(bus.read(0x8001), 0xf0);
+    assert_eq!(bus.mapper.ppu_read(0x0000), 2);
+    bus.write(0x8000, 0x20);
+    assert_eq!(bus.read(0x8001), 0xf2);
+}
+
+#[test]
+fn camerica() {
+    let mut bus = rom(71, 0, 8, 0);
+    bus.write(0xC000, 5);
+    assert_eq!((bus.read(0x8001), bus.read(0xC001)), (0xf5, 0xf7));
+    // The header's mirroring until the BF9097 register is written
+    assert_eq!(bus.mapper.ppu_target(0x2805), PpuTarget::Ciram(0x405));
+    bus.write(0x8000, 0x10);
+    assert_eq!(bus.mapper.ppu_target(0x2805), PpuTarget::Ciram(0x405));
+    bus.write(0x9000, 0x10);
+    assert_eq!(bus.mapper.ppu_target(0x2005), PpuTarget::Ciram(0x405));
+    bus.write(0x8000, 0x00);
+    assert_eq!(bus.mapper.ppu_target(0x2c05), PpuTarget::Ciram(0x005));
+
+    // Fire Hawk's board starts on single screen A
+    let bus = rom(71, 1, 8, 0);
+    assert_eq!(bus.mapper.ppu_target(0x2805), PpuTarget::Ciram(0x005));
+
+    // Quattro
+    let mut bus = rom(232, 0, 16, 0);
+    bus.write(0x8000, 0x18);
+    bus.write(0xC000, 1);
+    assert_eq!((bus.read(0x8001), bus.read(0xC001)), (0xfd, 0xff));
+}
+
+#[test]
+fn action_53() {
+    let mut bus = rom(28, 0, 16, 0);
+    // Starts in the last 32K, where the menu is
+    assert_eq!((bus.read(0x8001), bus.read(0xC001)), (0xfe, 0xff));
+
+    // A 128K UxROM game in the second 32K onwards
+    bus.write(0x5000, 0x81);
+    bus.write(0x8000, 1);
+    bus.write(0x5000, 0x80);
+    bus.write(0x8000, 0x2e);
+    bus.write(0x5000, 0x01);
+    bus.write(0x8000, 5);
+    assert_eq!((bus.read(0x8001), bus.read(0xC001)), (0xf5, 0xf3));
+    assert_eq!(bus.mapper.ppu_target(0x2805), PpuTarget::Ciram(0x005));
+
+    // AxROM games pick the screen with the bank registers
+    bus.write(0x5000, 0x80);
+    bus.write(0x8000, 0x00);
+    bus.write(0x5000, 0x01);
+    bus.write(0x8000, 0x10);
+    assert_eq!(bus.mapper.ppu_target(0x2005), PpuTarget::Ciram(0x405));
+
+    // 32K of CHR RAM
+    bus.write(0x5000, 0x00);
+    bus.write(0x8000, 3);
+    bus.mapper.ppu_write(0x0010, 0x55);
+    bus.write(0x8000, 0);
+    assert_eq!(bus.mapper.ppu_read(0x0010), 0);
+    bus.write(0x8000, 3);
+    assert_eq!(bus.mapper.ppu_read(0x0010), 0x55);
+}
+
+#[test]
+fn gtrom() {
+    let mut bus = rom(111, 0, 32, 0);
+    bus.write(0x5000, 2);
+    assert_eq!((bus.read(0x8001), bus.read(0xC001)), (0xf4, 0xf5));
+
+    // CHR RAM banks and nametable pages
+    bus.mapper.ppu_write(0x0010, 1);
+    bus.mapper.ppu_write(0x2c10, 2);
+    assert_eq!(bus.mapper.ppu_target(0x2c10), PpuTarget::Cartridge);
+    bus.write(0x7000, 0x30);
+    assert_eq!(bus.mapper.ppu_read(0x0010), 0);
+    assert_eq!(bus.mapper.ppu_read(0x2c10), 0);
+    bus.write(0x7000, 0x00);
+    assert_eq!(bus.mapper.ppu_read(0x0010), 1);
+    assert_eq!(bus.mapper.ppu_read(0x2c10), 2);
+
+    // Programming a byte of flash, nothing happens without unlocking it
+    bus.write(0x8002, 0x00);
+    assert_eq!(bus.read(0x8002), 0xf0);
+    for (addr, val) in [
+        (0xD555, 0xaa),
+        (0xAAAA, 0x55),
+        (0xD555, 0xa0),
+        (0x8002, 0x42),
+    ] {
+        bus.write(addr, val);
+    }
+    assert_eq!(bus.read(0x8002), 0x42);
+
+    // Erasing its 4K sector
+    for (addr, val) in [
+        (0xD555, 0xaa),
+        (0xAAAA, 0x55),
+        (0xD555, 0x80),
+        (0xD555, 0xaa),
+        (0xAAAA, 0x55),
+        (0x8000, 0x30),
+    ] {
+        bus.write(addr, val);
+    }
+    assert_eq!((bus.read(0x8002), bus.read(0x9002)), (0xff, 0xf0));
+
+    let mut gtrom = Gtrom::new(image(111, 0, 32, 0));
+    gtrom.cpu_write(0x5000, 0x40);
+    assert_eq!(gtrom.leds(), (false, true));
+}
+
+#[test]
+fn four_screen() {
+    let mut ines = common::ines(&[]);
+    ines[6] = 0x08;
+    let mut bus = Bus::new(Rom::new(&ines).unwrap());
+    assert_eq!(bus.mapper.ppu_target(0x2405), PpuTarget::Ciram(0x405));
+    assert_eq!(bus.mapper.ppu_target(0x2805), PpuTarget::Cartridge);
+    assert_eq!(bus.mapper.ppu_target(0x3c05), PpuTarget::Cartridge);
+
+    // A different byte in each nametable
+    for (i, table) in [0x20, 0x24, 0x28, 0x2c].into_iter().enumerate() {
+        bus.write(0x2006, table);
+        bus.write(0x2006, 0x00);
+        bus.write(0x2007, i as u8 + 1);
+    }
+    for (i, table) in [0x20, 0x24, 0x28, 0x2c].into_iter().enumerate() {
+        bus.write(0x2006, table);
+        bus.write(0x2006, 0x00);
+        // The first read fills the read buffer
+        bus.read(0x2007);
+        assert_eq!(bus.read(0x2007), i as u8 + 1);
+    }
+    assert_eq!((bus.ppu.vram[0], bus.ppu.vram[0x400]), (1, 2));
+}
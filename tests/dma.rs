@@ -0,0 +1,113 @@
+use nes::Bus;
+use nes::joypad::Buttons;
+
+mod common;
+
+fn bus() -> Bus {
+    Bus::new(common::nrom(&[]))
+}
+
+/// Write `val` to `addr` like a 4 cycle `STA abs` would
+fn sta(bus: &mut Bus, addr: u16, val: u8) -> u64 {
+    let start = bus.cycles;
+    bus.write(addr, val);
+    bus.tick(4);
+    bus.cycles - start - 4
+}
+
+#[test]
+fn oam_dma() {
+    for align in 0..2 {
+        let mut bus = bus();
+        bus.tick(align);
+        for i in 0..0x100 {
+            bus.write(0x0200 + i, i as u8);
+        }
+        let stall = sta(&mut bus, 0x4014, 0x02);
+        assert!(stall == 513 || stall == 514, "{stall}");
+        assert_eq!(bus.ppu.oam[0x41], 0x41);
+        assert_eq!(bus.ppu.oam[0xff], 0xff);
+        // Attribute bytes lose bits 2-4
+        assert_eq!(bus.ppu.oam[0x42], 0x42 & 0xe3);
+    }
+}
+
+#[test]
+fn dmc_dma() {
+    let mut stalls = vec![];
+    for align in 0..2 {
+        let mut bus = bus();
+        bus.tick(align);
+        bus.write(0x4010, 0x0f);
+        bus.write(0x4013, 0x01);
+        stalls.push(sta(&mut bus, 0x4015, 0x10));
+        assert_eq!(bus.apu.dmc.bytes_remaining, 16);
+    }
+    stalls.sort();
+    assert_eq!(stalls, [3, 4]);
+}
+
+#[test]
+fn dmc_dma_during_oam_dma() {
+    let mut bus = bus();
+    // Fastest rate, fetches a byte every 8 * 54 cycles
+    bus.write(0x4010, 0x0f);
+    bus.write(0x4013, 0xff);
+    bus.write(0x4015, 0x10);
+    // Wait for the output unit to start consuming samples
+    for _ in 0..1000 {
+        bus.tick(4);
+    }
+    let remaining = bus.apu.dmc.bytes_remaining;
+    let stall = sta(&mut bus, 0x4014, 0x02);
+    let fetched = (remaining - bus.apu.dmc.bytes_remaining) as u64;
+    assert!(fetched > 0);
+    // Each DMC fetch steals a get cycle and needs an extra alignment cycle
+    assert!(
+        stall == 513 + 2 * fetched || stall == 514 + 2 * fetched,
+        "{stall}"
+    );
+}
+
+#[test]
+fn dmc_irq() {
+    let mut bus = bus();
+    bus.write(0x4010, 0x8f);
+    bus.write(0x4013, 0x00);
+    sta(&mut bus, 0x4015, 0x10);
+    assert_eq!(bus.apu.dmc.bytes_remaining, 0);
+    assert_eq!(bus.read(0x4015) & 0x80, 0x80);
+    // Writing $4015 acknowledges the interrupt
+    bus.write(0x4015, 0x00);
+    assert!(bus.irq_sources().is_empty());
+}
+
+/// Count how many of `polls` controller reads came back corrupted with a
+/// DMC sample playing at the fastest rate
+fn corrupted_polls(glitch: bool, polls: usize) -> usize {
+    let mut bus = bus();
+    bus.dmc_read_glitch = glitch;
+    bus.joypads[0].buttons = Buttons::A | Buttons::START;
+    bus.write(0x4010, 0x4f);
+    bus.write(0x4013, 0xff);
+    bus.write(0x4015, 0x10);
+    (0..polls)
+        .filter(|_| {
+            sta(&mut bus, 0x4016, 1);
+            sta(&mut bus, 0x4016, 0);
+            let mut state = 0;
+            for i in 0..8 {
+                // LDA $4016, LSR A, ROL $00, DEX, BNE
+                state |= (bus.read(0x4016) & 1) << i;
+                bus.tick(4 + 2 + 5 + 2 + 3);
+            }
+            state != (Buttons::A | Buttons::START).bits()
+        })
+        .count()
+}
+
+#[test]
+fn dmc_dma_controller_glitch() {
+    assert!(corrupted_polls(true, 2000) > 0);
+    assert_eq!(corrupted_polls(false, 2000), 0);
+}
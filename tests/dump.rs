@@ -0,0 +1,87 @@
+use nes::console::Console;
+use nes::dump::{AvDump, DUMP_SAMPLE_RATE, StemDump, frame_rate, stem_names};
+use nes::rom::Region;
+
+mod common;
+
+/// Plays a square wave from reset
+fn test_console() -> Console {
+    // LDA #$01; STA $4015; LDA #$bf; STA $4000; LDA #$80; STA $4002; STA $4003; JMP *
+    let code = [
+        0xa9, 0x01, 0x8d, 0x15, 0x40, 0xa9, 0xbf, 0x8d, 0x00, 0x40, 0xa9, 0x80, 0x8d, 0x02, 0x40,
+        0x8d, 0x03, 0x40, 0x4c, 0x12, 0xc0,
+    ];
+    Console::new(common::nrom(&[(0xc000, &code)]))
+}
+
+#[test]
+fn frames_and_audio_line_up() {
+    let dir = std::env::temp_dir().join(format!("nes-dump-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("movie");
+
+    let mut console = test_console();
+    console.run_frame();
+    let mut dump = AvDump::create(&path, &mut console, 4, 2).unwrap();
+    for frame in 0..7 {
+        console.run_frame();
+        dump.frame(&mut console, &[0xffffff * (frame % 2); 8])
+            .unwrap();
+    }
+    assert!(dump.frame(&mut console, &[0; 3]).is_err());
+    let (num, den) = frame_rate(Region::Ntsc);
+    let samples = 7 * DUMP_SAMPLE_RATE as u64 * den / num;
+    assert_eq!(dump.samples(), samples);
+    dump.finish().unwrap();
+
+    let video = std::fs::read(path.with_extension("y4m")).unwrap();
+    let header = b"YUV4MPEG2 W4 H2 F39375000:655171 Ip A1:1 C444\n";
+    assert!(video.starts_with(header));
+    let frame_len = 6 + 3 * 8;
+    assert_eq!(video.len(), header.len() + 7 * frame_len);
+    // Black, then white
+    let luma = |frame: usize| video[header.len() + frame * frame_len + 6];
+    assert_eq!((luma(0), luma(1)), (16, 235));
+
+    let audio = std::fs::read(path.with_extension("wav")).unwrap();
+    assert_eq!(&audio[..4], b"RIFF");
+    assert_eq!(audio.len() as u64, 44 + samples * 4);
+    let data_len = u32::from_le_bytes(audio[40..44].try_into().unwrap());
+    assert_eq!(data_len as u64, samples * 4);
+    assert!(audio[44..].iter().any(|&byte| byte != 0));
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn stems() {
+    let dir = std::env::temp_dir().join(format!("nes-stems-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("song");
+
+    let mut console = test_console();
+    console.run_frame();
+    let names = stem_names(&console);
+    assert_eq!(names, ["pulse1", "pulse2", "triangle", "noise", "dmc"]);
+    let mut dump = StemDump::create(&path, &mut console).unwrap();
+    for _ in 0..5 {
+        console.run_frame();
+        dump.frame(&mut console).unwrap();
+    }
+    let (num, den) = frame_rate(Region::Ntsc);
+    let samples = 5 * DUMP_SAMPLE_RATE as u64 * den / num;
+    assert_eq!(dump.samples(), samples);
+    dump.finish(&mut console).unwrap();
+    assert_eq!(console.bus_mut().read_stems(&mut [[0.0; 6]; 16]), 0);
+
+    for name in &names {
+        let audio = std::fs::read(path.with_extension(format!("{name}.wav"))).unwrap();
+        assert_eq!(audio.len() as u64, 44 + samples * 2, "{name}");
+        // Mono
+        assert_eq!(u16::from_le_bytes([audio[22], audio[23]]), 1);
+        // Only the square wave makes a sound
+        let silent = audio[44..].iter().all(|&byte| byte == 0);
+        assert_eq!(silent, name != "pulse1", "{name}");
+    }
+    assert!(!path.with_extension("vrc6.wav").exists());
+    std::fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,141 @@
+use std::error::Error;
+use std::path::Path;
+
+use nes::console::Console;
+use nes::error::{CpuError, MapperError, NesError, NetplayError, RomError, StateError};
+use nes::netplay::Message;
+use nes::rom::Rom;
+use nes::savestate::SaveState;
+
+mod common;
+
+/// NROM running `code` from $C000
+fn ines(mapper: u8, code: &[u8]) -> Vec<u8> {
+    let mut ines = common::ines(&[(0xc000, code)]);
+    ines[6] = mapper << 4;
+    ines
+}
+
+#[test]
+fn rom_errors() {
+    assert!(matches!(Rom::new(b"NE"), Err(RomError::NotInes)));
+    assert!(matches!(
+        Rom::new(b"NES\x1a\x01"),
+        Err(RomError::Truncated {
+            expected: 16,
+            found: 5
+        })
+    ));
+    let ines = ines(0, &[]);
+    assert!(matches!(
+        Rom::new(&ines[..0x1000]),
+        Err(RomError::Truncated {
+            expected: 0x6010,
+            found: 0x1000
+        })
+    ));
+    let mut nes3 = ines.clone();
+    nes3[7] = 0x04;
+    assert!(matches!(Rom::new(&nes3), Err(RomError::UnsupportedFormat)));
+
+    let err = Rom::load(Path::new("missing.nes"), false).unwrap_err();
+    assert!(matches!(&err, RomError::Io { path, .. } if path == Path::new("missing.nes")));
+    assert!(err.source().is_some());
+    assert!(err.to_string().starts_with("missing.nes: "));
+}
+
+#[test]
+fn unsupported_mapper() {
+    let rom = Rom::from_ines(&ines(9, &[])).unwrap();
+    assert!(matches!(
+        Console::try_new(rom.clone()),
+        Err(MapperError::Unsupported(9))
+    ));
+    // Still runs as NROM
+    Console::new(rom).run_frame();
+}
+
+#[test]
+fn invalid_opcode() {
+    // INC $10; STA $8000; .byte $02
+    let rom = Rom::new(&ines(0, &[0xe6, 0x10, 0x8d, 0x00, 0x80, 0x02])).unwrap();
+    let mut console = Console::new(rom);
+    console.try_step().unwrap();
+    console.try_step().unwrap();
+    let cycles = console.bus().cycles;
+    let jammed = CpuError::InvalidOpcode {
+        opcode: 0x02,
+        pc: 0xc005,
+    };
+    assert_eq!(console.try_step(), Err(jammed));
+    assert_eq!(console.cpu.pc, 0xc005);
+    assert_eq!(console.bus().cycles, cycles + 2);
+    assert_eq!(console.try_run_frame(), Err(jammed));
+
+    // The rest of the console carries on
+    let frame = console.bus().ppu.frame;
+    console.run_frame();
+    assert_eq!(console.bus().ppu.frame, frame + 1);
+    assert_eq!(console.cpu.pc, 0xc005);
+}
+
+#[test]
+fn state_errors() {
+    let mut console = Console::new(Rom::new(&ines(0, &[0x4c, 0x00, 0xc0])).unwrap());
+    let bytes = console.save_state(None).to_bytes();
+    assert_eq!(
+        SaveState::from_bytes(b"not a state").unwrap_err(),
+        StateError::NotAState
+    );
+    assert_eq!(
+        SaveState::from_bytes(&bytes[..bytes.len() - 1]).unwrap_err(),
+        StateError::Truncated
+    );
+    let mut trailing = bytes.clone();
+    trailing.push(0);
+    assert_eq!(
+        SaveState::from_bytes(&trailing).unwrap_err(),
+        StateError::TrailingData
+    );
+
+    let mut other = Console::new(Rom::new(&ines(0, &[0xea])).unwrap());
+    let state = SaveState::from_bytes(&bytes).unwrap();
+    assert_eq!(
+        other.load_state(&state),
+        Err(StateError::WrongRom {
+            state: console.rom_hash(),
+            rom: other.rom_hash(),
+        })
+    );
+}
+
+#[test]
+fn netplay_errors() {
+    assert!(matches!(
+        Message::decode(&[9, 0]),
+        Err(NetplayError::UnknownMessage(9))
+    ));
+    let err = NesError::from(NetplayError::Desync { frame: 60 });
+    assert!(matches!(err, NesError::Netplay(_)));
+    assert_eq!(String::from(err), "desynced at frame 60");
+}
+
+#[test]
+fn into_nes_error() {
+    fn run(ines: &[u8]) -> Result<u32, NesError> {
+        let mut console = Console::try_new(Rom::new(ines)?)?;
+        console.try_run_frame()?;
+        let state = SaveState::from_bytes(&console.save_state(None).to_bytes())?;
+        console.load_state(&state)?;
+        Ok(console.state_hash())
+    }
+    assert!(run(&ines(0, &[0x4c, 0x00, 0xc0])).is_ok());
+    assert!(matches!(run(b"NES"), Err(NesError::Rom(_))));
+    assert!(matches!(
+        run(&ines(9, &[])),
+        Err(NesError::Mapper(MapperError::Unsupported(9)))
+    ));
+    let err = run(&ines(0, &[0x02])).unwrap_err();
+    assert!(matches!(err, NesError::Cpu(_)));
+    assert_eq!(String::from(err), "Invalid Opcode: `02` at $C000");
+}
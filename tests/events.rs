@@ -0,0 +1,66 @@
+use nes::console::Console;
+use nes::events::BusEventKind;
+use nes::rom::Rom;
+
+mod common;
+
+fn console() -> Console {
+    let mut ines = common::ines(&[
+        // LDA #$80; STA $2000; JMP *
+        (0xc000, &[0xa9, 0x80, 0x8d, 0x00, 0x20, 0x4c, 0x05, 0xc0]),
+        // NMI: STA $8000; RTI
+        (0xd000, &[0x8d, 0x00, 0x80, 0x40]),
+        (0xfffa, &[0x00, 0xd0, 0x00, 0xc0, 0x00, 0xc0]),
+    ]);
+    // UxROM, so the NMI handler has a mapper register to write
+    ines[6] = 0x20;
+    let mut console = Console::new(Rom::new(&ines).unwrap());
+    console.bus_mut().events.enabled = true;
+    console
+}
+
+#[test]
+fn events_per_frame() {
+    let mut console = console();
+    console.run_to_scanline(10);
+    let events = console.bus().events.current_events();
+    assert_eq!(
+        events[0].kind,
+        BusEventKind::PpuRegisterWrite {
+            addr: 0x2000,
+            val: 0x80
+        }
+    );
+    assert_eq!(events[0].scanline, 0);
+
+    // Frame 0 is complete once frame 1 started
+    console.run_to_scanline(0);
+    console.run_to_scanline(10);
+    let events = console.bus().events.frame_events();
+    let kinds: Vec<_> = events.iter().map(|event| event.kind).collect();
+    assert_eq!(
+        kinds,
+        [
+            BusEventKind::PpuRegisterWrite {
+                addr: 0x2000,
+                val: 0x80
+            },
+            BusEventKind::Nmi,
+            BusEventKind::MapperWrite {
+                addr: 0x8000,
+                val: 0x80
+            },
+        ]
+    );
+    assert_eq!(events[1].scanline, 241);
+    assert!(events[1].dot < 10);
+    assert!(events[2].dot > events[1].dot);
+}
+
+#[test]
+fn disabled_by_default() {
+    let mut console = console();
+    console.bus_mut().events.enabled = false;
+    console.run_to_scanline(250);
+    assert!(console.bus().events.current_events().is_empty());
+}
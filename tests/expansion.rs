@@ -0,0 +1,60 @@
+use nes::Bus;
+use nes::expansion::{FamilyKeyboard, Microphone};
+use nes::joypad::Buttons;
+
+mod common;
+
+fn bus() -> Bus {
+    Bus::new(common::nrom(&[]))
+}
+
+/// Scan the whole keyboard the way Family BASIC does, 4 bits per column
+fn scan(bus: &mut Bus) -> Vec<u8> {
+    let mut keys = vec![];
+    bus.write(0x4016, 0b101);
+    for _ in 0..9 {
+        bus.write(0x4016, 0b100);
+        keys.push(!bus.read(0x4017) >> 1 & 0xf);
+        bus.write(0x4016, 0b110);
+        keys.push(!bus.read(0x4017) >> 1 & 0xf);
+    }
+    keys
+}
+
+#[test]
+fn keyboard() {
+    let mut bus = bus();
+    bus.expansion = Some(Box::new(FamilyKeyboard::new()));
+    assert!(bus.expansion_mut::<Microphone>().is_none());
+    let keyboard = bus.expansion_mut::<FamilyKeyboard>().unwrap();
+    assert!(keyboard.set_key("RETURN", true));
+    assert!(keyboard.set_key("X", true));
+    assert!(keyboard.set_key("SPACE", true));
+    assert!(!keyboard.set_key("F9", true));
+
+    let keys = scan(&mut bus);
+    let mut expected = vec![0; 18];
+    expected[0] = 0b0100;
+    expected[13] = 0b1000;
+    expected[17] = 0b0100;
+    assert_eq!(keys, expected);
+
+    bus.expansion_mut::<FamilyKeyboard>()
+        .unwrap()
+        .set_key("X", false);
+    assert_eq!(scan(&mut bus)[13], 0);
+    // Disabled, and controllers still read through it
+    bus.joypads[1].buttons = Buttons::A;
+    bus.write(0x4016, 1);
+    assert_eq!(bus.read(0x4017), 1);
+}
+
+#[test]
+fn microphone() {
+    let mut bus = bus();
+    bus.expansion = Some(Box::new(Microphone::new()));
+    assert_eq!(bus.read(0x4016) & 0b100, 0);
+    bus.expansion_mut::<Microphone>().unwrap().loud = true;
+    assert_eq!(bus.read(0x4016) & 0b100, 0b100);
+    assert_eq!(bus.read(0x4017) & 0b100, 0);
+}
@@ -0,0 +1,79 @@
+use nes::console::Console;
+use nes::expr::Expr;
+
+mod common;
+
+/// Spins on `INC $10; JMP $C000`
+fn console() -> Console {
+    Console::new(common::nrom(&[(0xc000, &[0xe6, 0x10, 0x4c, 0x00, 0xc0])]))
+}
+
+fn eval(console: &Console, src: &str) -> i32 {
+    Expr::parse(src).unwrap().eval(&console.cpu)
+}
+
+#[test]
+fn evaluation() {
+    let mut console = console();
+    console.cpu.memory.cpu_ram[0x7fe] = 0x34;
+    console.cpu.memory.cpu_ram[0x7ff] = 0x12;
+    console.cpu.reg_x = 2;
+    assert_eq!(eval(&console, "1 + 2 * 3"), 7);
+    assert_eq!(eval(&console, "(1 + 2) * 3"), 9);
+    assert_eq!(eval(&console, "$10 | %101 << 1"), 0x1a);
+    assert_eq!(eval(&console, "10 % 4"), 2);
+    assert_eq!(eval(&console, "[$07FE]"), 0x34);
+    assert_eq!(eval(&console, "{$07FE}"), 0x1234);
+    assert_eq!(eval(&console, "[$07FC + X]"), 0x34);
+    assert_eq!(eval(&console, "pc == $C000 && !(a != 0)"), 1);
+    assert_eq!(eval(&console, "-1 < 0 || 1 / 0"), 1);
+    assert_eq!(eval(&console, "7 / 0"), 0);
+    assert_eq!(eval(&console, "~0 & $FF"), 0xff);
+
+    for bad in ["", "1 +", "(1", "[1", "foo", "$", "1 2", "%2"] {
+        assert!(Expr::parse(bad).is_err(), "{bad:?}");
+    }
+}
+
+#[test]
+fn watches_sampled_per_frame() {
+    let mut console = console();
+    let hp = console.add_watch("[$10]").unwrap();
+    let frame = console.add_watch("frame").unwrap();
+    assert!(console.add_watch("[$10").is_err());
+    for _ in 0..3 {
+        console.run_frame();
+    }
+    let watches = console.watches();
+    assert_eq!(watches[frame].history, [1, 2, 3]);
+    assert_eq!(watches[hp].history.len(), 3);
+    // INC/JMP is 8 cycles, about 3723 loops a frame
+    let values: Vec<i32> = watches[hp].history.iter().copied().collect();
+    assert!(values[0] != values[1] && values[1] != values[2]);
+    assert_eq!(console.remove_watch(hp).source, "[$10]");
+}
+
+#[test]
+fn conditions_met_when_they_become_true() {
+    let mut console = console();
+    // Already true, so not met until it turns false and back
+    let always = console.add_condition("1").unwrap();
+    let even = console.add_condition("frame % 2 == 0").unwrap();
+    assert!(console.add_condition("[$10").is_err());
+    console.run_frame();
+    assert!(console.take_met_conditions().is_empty());
+    console.run_frame();
+    assert!(console.conditions()[even].holds);
+    let state = console.save_state(None);
+    console.run_frame();
+    console.run_frame();
+    // Met at frames 2 and 4, taken together
+    assert_eq!(console.take_met_conditions(), [even, even]);
+
+    // Loading a state where it holds doesn't meet it again
+    console.run_frame();
+    console.load_state(&state).unwrap();
+    assert!(console.take_met_conditions().is_empty());
+    assert_eq!(console.remove_condition(always).source, "1");
+    assert_eq!(console.conditions()[0].source, "frame % 2 == 0");
+}
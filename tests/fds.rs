@@ -0,0 +1,186 @@
+use std::path::Path;
+
+use nes::console::Console;
+use nes::error::RomError;
+use nes::mapper::{Fds, Mapper};
+use nes::rom::{FDS_SIDE_LEN, Rom};
+use nes::savestate::BatterySave;
+
+mod common;
+
+/// Spins on `JMP $E000`
+fn bios() -> Vec<u8> {
+    let mut bios = vec![0; 0x2000];
+    bios[..3].copy_from_slice(&[0x4c, 0x00, 0xe0]);
+    bios[0x1ffc..0x1ffe].copy_from_slice(&[0x00, 0xe0]);
+    bios
+}
+
+/// `.fds` image with a header and `sides` sides, each with one 4 byte file
+fn image(sides: u8) -> Vec<u8> {
+    let mut image = b"FDS\x1a".to_vec();
+    image.push(sides);
+    image.resize(16, 0);
+    for side in 0..sides {
+        let mut disk = b"\x01*NINTENDO-HVC*".to_vec();
+        disk.resize(56, side);
+        disk.extend([2, 1]);
+        let mut header = vec![3, 0, 0, b'F', b'I', b'L', b'E', b' ', b' ', b' ', 0, 0];
+        header.extend([0, 4, 0, 0]);
+        disk.extend(header);
+        disk.extend([4, 0xde, 0xad, 0xbe, 0xef]);
+        disk.resize(FDS_SIDE_LEN, 0);
+        image.extend(disk);
+    }
+    image
+}
+
+fn rom(sides: u8) -> Rom {
+    Rom::from_fds(&image(sides), &bios()).unwrap()
+}
+
+/// CPU cycles until the drive has a byte for the CPU, and the byte
+fn next_byte(fds: &mut Fds) -> (u32, u8) {
+    for cycles in 1..1_000_000 {
+        fds.cpu_tick();
+        if fds.cpu_read(0x4030) & 0x02 != 0 {
+            return (cycles, fds.cpu_read(0x4031));
+        }
+    }
+    panic!("the drive didn't read anything");
+}
+
+#[test]
+fn from_fds() {
+    let rom = rom(2);
+    assert_eq!((rom.mapper, rom.disk_sides.len()), (20, 2));
+    assert_eq!(rom.prg_rom, bios());
+    // Same game without the header
+    let headerless = Rom::from_fds(&image(2)[16..], &bios()).unwrap();
+    assert_eq!(headerless.hash(), rom.hash());
+
+    assert!(matches!(
+        Rom::from_fds(&image(1), &[0; 0x1000]),
+        Err(RomError::Disk(_))
+    ));
+    assert!(matches!(
+        Rom::from_fds(&image(1)[..1000], &bios()),
+        Err(RomError::Disk(_))
+    ));
+    let mut image = image(2);
+    image[16 + FDS_SIDE_LEN + 1] = b'-';
+    assert!(matches!(
+        Rom::from_fds(&image, &bios()),
+        Err(RomError::Disk(_))
+    ));
+}
+
+#[test]
+fn read_disk() {
+    let mut fds = Fds::new(rom(1));
+    fds.cpu_write(0x4023, 1);
+    // Motor on, reading, waiting for a block
+    fds.cpu_write(0x4025, 0x45);
+    assert_eq!(fds.cpu_read(0x4032) & 0x01, 0);
+    let (cycles, mark) = next_byte(&mut fds);
+    // The head rewinds and passes the lead-in first
+    assert!(cycles > 50000, "{cycles}");
+    assert_eq!(mark, 0x80);
+    let (cycles, byte) = next_byte(&mut fds);
+    assert_eq!((cycles, byte), (151, 0x01));
+    assert_eq!(next_byte(&mut fds).1, b'*');
+
+    let mut fast = Fds::new(rom(1));
+    fast.set_fast_load(true);
+    fast.cpu_write(0x4023, 1);
+    fast.cpu_write(0x4025, 0x45);
+    let (cycles, mark) = next_byte(&mut fast);
+    assert!(cycles < 10, "{cycles}");
+    assert_eq!(mark, 0x80);
+    assert_eq!(next_byte(&mut fast).1, 0x01);
+}
+
+#[test]
+fn timer_irq() {
+    let mut fds = Fds::new(rom(1));
+    // Nothing without disk I/O enabled
+    fds.cpu_write(0x4020, 2);
+    fds.cpu_write(0x4022, 0x02);
+    fds.cpu_tick();
+    fds.cpu_write(0x4023, 1);
+    fds.cpu_write(0x4020, 2);
+    fds.cpu_write(0x4021, 0);
+    fds.cpu_write(0x4022, 0x02);
+    for _ in 0..3 {
+        assert!(!fds.irq());
+        fds.cpu_tick();
+    }
+    assert!(fds.irq());
+    assert_eq!(fds.cpu_read(0x4030) & 0x01, 0x01);
+    assert!(!fds.irq());
+}
+
+#[test]
+fn swap_disks() {
+    let mut console = Console::new(rom(3));
+    assert_eq!((console.disk_sides(), console.disk_side()), (3, Some(0)));
+    let empty = |console: &Console| console.bus().peek(0x4032) & 0x01 != 0;
+    assert!(!empty(&console));
+
+    // The drive is empty for a while before the next side goes in
+    assert_eq!(console.flip_disk(), Some(1));
+    assert_eq!(console.disk_side(), Some(1));
+    assert!(empty(&console));
+    for _ in 0..70 {
+        console.run_frame();
+    }
+    assert!(!empty(&console));
+
+    console.insert_disk(2);
+    assert_eq!(console.flip_disk(), Some(0));
+    console.eject_disk();
+    assert_eq!(console.disk_side(), None);
+    assert!(empty(&console));
+    // Straight in when the drive is empty
+    assert_eq!(console.flip_disk(), Some(0));
+    assert!(!empty(&console));
+
+    // Cartridges have no disks
+    let mut cartridge = Console::new(common::nrom(&[]));
+    assert_eq!(cartridge.flip_disk(), None);
+}
+
+#[test]
+fn save_overlay() {
+    let mut console = Console::new(rom(2));
+    assert_eq!(console.bus().mapper.save_data(), None);
+    let bus = console.bus_mut();
+    bus.mapper.set_fast_load(true);
+    bus.write(0x4023, 1);
+    bus.write(0x4024, 0x5a);
+    // Motor on, writing
+    bus.write(0x4025, 0x41);
+    console.run_frame();
+    let overlay = console.bus().mapper.save_data().unwrap();
+    // One record of the bytes written, not the whole disk
+    assert!(overlay.len() < 1000, "{}", overlay.len());
+    assert_eq!(overlay[0], 0);
+
+    let mut loaded = Console::new(rom(2));
+    loaded.bus_mut().mapper.load_save_data(&overlay);
+    assert_eq!(loaded.bus().mapper.save_data(), Some(overlay));
+
+    // Savestates carry the written disk
+    let state = console.save_state(None);
+    let mut restored = Console::new(rom(2));
+    restored.load_state(&state).unwrap();
+    assert_eq!(restored.state_hash(), console.state_hash());
+}
+
+#[test]
+fn save_path() {
+    let save = BatterySave::new(Path::new("roms/game.fds"));
+    assert_eq!(save.path(), Path::new("roms/game.fds.sav"));
+    let save = BatterySave::new(Path::new("roms/game.nes"));
+    assert_eq!(save.path(), Path::new("roms/game.sav"));
+}
@@ -0,0 +1,51 @@
+use nes::Bus;
+use nes::expansion::Multitap;
+use nes::joypad::{Buttons, FourScore};
+
+mod common;
+
+fn bus() -> Bus {
+    Bus::new(common::nrom(&[]))
+}
+
+/// Strobe and read `bits` bits from bit `bit` of `addr`, first bit lowest
+fn read_bits(bus: &mut Bus, addr: u16, bit: u8, bits: usize) -> u32 {
+    bus.write(0x4016, 1);
+    bus.write(0x4016, 0);
+    (0..bits).fold(0, |report, i| {
+        report | ((bus.read(addr) >> bit & 1) as u32) << i
+    })
+}
+
+#[test]
+fn four_score() {
+    let mut bus = bus();
+    bus.ports = [0, 1].map(|port| Some(Box::new(FourScore::new(port)) as _));
+    bus.port_mut::<FourScore>(0).unwrap().buttons = [Buttons::A, Buttons::START];
+    bus.port_mut::<FourScore>(1).unwrap().buttons = [Buttons::B, Buttons::RIGHT];
+    // Player 1, player 3, then the signature read as 00010000
+    assert_eq!(read_bits(&mut bus, 0x4016, 0, 24), 0x08_08_01);
+    // Player 2, player 4, then 00100000
+    assert_eq!(read_bits(&mut bus, 0x4017, 0, 24), 0x04_80_02);
+    // Then 1s
+    assert_eq!(bus.read(0x4016) & 1, 1);
+}
+
+#[test]
+fn multitap() {
+    let mut bus = bus();
+    bus.expansion = Some(Box::new(Multitap::new()));
+    bus.joypads[0].buttons = Buttons::A;
+    let multitap = bus.expansion_mut::<Multitap>().unwrap();
+    multitap.joypads[0].buttons = Buttons::UP;
+    multitap.joypads[1].buttons = Buttons::SELECT;
+    // Controllers 3 and 4 on bit 1, next to controllers 1 and 2 on bit 0
+    bus.write(0x4016, 1);
+    bus.write(0x4016, 0);
+    let reads: Vec<u8> = (0..8).map(|_| bus.read(0x4016) & 3).collect();
+    assert_eq!(reads, [1, 0, 0, 0, 2, 0, 0, 0]);
+    assert_eq!(
+        read_bits(&mut bus, 0x4017, 1, 8),
+        Buttons::SELECT.bits() as u32
+    );
+}
@@ -0,0 +1,33 @@
+#![cfg(feature = "async")]
+
+use std::pin::Pin;
+use std::task::{Context, Poll, Waker};
+
+use futures_core::Stream;
+use nes::console::Console;
+
+mod common;
+
+/// Spins on `JMP $C000`
+fn console() -> Console {
+    Console::new(common::nrom(&[(0xc000, &[0x4c, 0x00, 0xc0])]))
+}
+
+#[test]
+fn yields_every_scanline() {
+    let mut console = console();
+    let mut cx = Context::from_waker(Waker::noop());
+    let mut stream = console.run_frames_stream(|console| console.bus().ppu.frame);
+    let mut pending = 0;
+    let mut frames = vec![];
+    while frames.len() < 3 {
+        match Pin::new(&mut stream).poll_next(&mut cx) {
+            Poll::Ready(frame) => frames.push(frame.unwrap()),
+            Poll::Pending => pending += 1,
+        }
+    }
+    assert_eq!(frames, [1, 2, 3]);
+    // 262 scanlines a frame, the last one ends with the frame instead
+    assert_eq!(pending, 3 * 261);
+    assert_eq!(console.bus().ppu.scanline, 0);
+}
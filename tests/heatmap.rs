@@ -0,0 +1,83 @@
+use nes::console::Console;
+use nes::heatmap::HeatmapMemory;
+use nes::rom::Rom;
+
+/// 48K UxROM running `code` from $C000 in the last bank
+fn console(code: &[u8]) -> Console {
+    let mut ines = vec![0; 16 + 0xc000 + 0x2000];
+    ines[..7].copy_from_slice(b"NES\x1a\x03\x01\x20");
+    ines[16 + 0x8000..16 + 0x8000 + code.len()].copy_from_slice(code);
+    ines[16 + 0xbffc..16 + 0xbffe].copy_from_slice(&[0x00, 0xc0]);
+    Console::new(Rom::new(&ines).unwrap())
+}
+
+const CODE: &[u8] = &[
+    0xa9, 0x01, // LDA #$01
+    0x8d, 0x00, 0xc0, // STA $C000, switch to bank 1
+    0xad, 0x00, 0x80, // LDA $8000
+    0x8d, 0x10, 0x08, // STA $0810
+    0xe6, 0x10, // INC $10
+    0xb1, 0x20, // LDA ($20),Y
+    0x4c, 0x0f, 0xc0, // JMP $C00F
+];
+
+#[test]
+fn counts_accesses() {
+    let mut console = console(CODE);
+    console.bus_mut().heatmap.enabled = true;
+    for _ in 0..6 {
+        console.step();
+    }
+    let heatmap = &console.bus().heatmap;
+    let ram = heatmap.counts(HeatmapMemory::Ram);
+    assert_eq!((ram.reads[0x10], ram.writes[0x10]), (1, 2));
+    // The pointer at $20 and the $0000 it points to
+    assert_eq!(ram.touched().collect::<Vec<_>>(), [0x00, 0x10, 0x20, 0x21]);
+
+    // Counted by where they are in the ROM, not their CPU address, up to
+    // the JMP that hasn't run yet
+    let prg = heatmap.counts(HeatmapMemory::PrgRom);
+    assert_eq!(prg.reads[0x4000], 1);
+    assert_eq!(
+        prg.sum(0x8000..0x8000 + CODE.len()),
+        (CODE.len() as u64 - 3, 0)
+    );
+    // The write to $C000 picked the bank rather than writing ROM
+    assert!(prg.writes.iter().all(|&n| n == 0));
+
+    console.bus_mut().heatmap.clear();
+    let heatmap = &console.bus().heatmap;
+    assert_eq!(heatmap.counts(HeatmapMemory::Ram).touched().count(), 0);
+    assert_eq!(heatmap.counts(HeatmapMemory::PrgRom).touched().count(), 0);
+}
+
+#[test]
+fn disabled_by_default() {
+    let mut console = console(CODE);
+    console.run_frame();
+    let heatmap = &console.bus().heatmap;
+    assert_eq!(heatmap.counts(HeatmapMemory::Ram).touched().count(), 0);
+    assert_eq!(heatmap.counts(HeatmapMemory::PrgRom).touched().count(), 0);
+}
+
+#[test]
+fn sampling() {
+    let total = |interval| {
+        let mut console = console(CODE);
+        console.bus_mut().heatmap.enabled = true;
+        console.bus_mut().heatmap.interval = interval;
+        console.run_frame();
+        let heatmap = &console.bus().heatmap;
+        let (ram, prg) = (
+            heatmap.counts(HeatmapMemory::Ram),
+            heatmap.counts(HeatmapMemory::PrgRom),
+        );
+        let (ram_reads, ram_writes) = ram.sum(0..ram.len());
+        let (prg_reads, _) = prg.sum(0..prg.len());
+        ram_reads + ram_writes + prg_reads
+    };
+    let every = total(1);
+    assert!(every > 20_000);
+    // Every 7th access counts as 7
+    assert_eq!(total(7), every - every % 7);
+}
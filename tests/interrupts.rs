@@ -0,0 +1,44 @@
+use nes::{Bus, Cpu, IrqSource};
+
+mod common;
+
+fn program_cpu(code: &[(u16, &[u8])]) -> Cpu {
+    Cpu::new(Bus::new(common::nrom(code)))
+}
+
+#[test]
+fn frame_irq_and_nmi() {
+    let mut cpu = program_cpu(&[
+        // CLI; LDA #$80; STA $2000; JMP *
+        (
+            0xc000,
+            &[0x58, 0xa9, 0x80, 0x8d, 0x00, 0x20, 0x4c, 0x06, 0xc0],
+        ),
+        // IRQ: INC $10; LDA $4015; RTI
+        (0xd000, &[0xe6, 0x10, 0xad, 0x15, 0x40, 0x40]),
+        // NMI: INC $11; RTI
+        (0xd100, &[0xe6, 0x11, 0x40]),
+        (0xfffa, &[0x00, 0xd1, 0x00, 0xc0, 0x00, 0xd0]),
+    ]);
+    while cpu.memory.cycles < 29830 * 2 + 100 {
+        cpu.step();
+    }
+    assert_eq!(cpu.memory.cpu_ram[0x10], 2);
+    assert_eq!(cpu.memory.cpu_ram[0x11], 2);
+    assert_eq!(cpu.pc, 0xc006);
+    assert!(cpu.memory.irq_sources().is_empty());
+}
+
+#[test]
+fn irq_masked_until_cli() {
+    let mut cpu = program_cpu(&[
+        // JMP *
+        (0xc000, &[0x4c, 0x00, 0xc0]),
+        (0xfffa, &[0x00, 0xd1, 0x00, 0xc0, 0x00, 0xd0]),
+    ]);
+    while cpu.memory.cycles < 29830 + 100 {
+        cpu.step();
+    }
+    assert_eq!(cpu.memory.irq_sources(), IrqSource::FRAME_COUNTER);
+    assert_eq!(cpu.pc, 0xc000);
+}
@@ -0,0 +1,73 @@
+use nes::console::Console;
+use nes::disasm::instruction_at;
+use nes::trace::trace_labeled;
+
+mod common;
+
+const CODE: &[u8] = &[
+    0x20, 0x10, 0xc0, // JSR $C010
+    0x4c, 0x03, 0xc0, // JMP $C003
+];
+
+const SUBROUTINE: &[u8] = &[
+    0xa2, 0x03, // LDX #$03
+    0xca, // DEX
+    0xd0, 0xfd, // BNE $C012
+    0x60, // RTS
+];
+
+/// NROM running `CODE` from $C000, with `SUBROUTINE` at $C010
+fn console() -> Console {
+    let mut console = Console::new(common::nrom(&[(0xc000, CODE), (0xc010, SUBROUTINE)]));
+    console.bus_mut().auto_labels.enabled = true;
+    console.cpu.reset();
+    console
+}
+
+#[test]
+fn labels_code_as_it_runs() {
+    let mut console = console();
+    for _ in 0..10 {
+        console.step();
+    }
+    let bus = console.bus();
+    assert_eq!(bus.label(0xc000).as_deref(), Some("reset_C000"));
+    assert_eq!(bus.label(0xc010).as_deref(), Some("sub_C010"));
+    assert_eq!(bus.label(0xc012).as_deref(), Some("loc_C012"));
+    assert_eq!(bus.label(0xc003), None);
+    assert_eq!(
+        instruction_at(bus, 0xc013),
+        Some(("bne loc_C012".to_string(), 2))
+    );
+}
+
+#[test]
+fn user_labels_come_first() {
+    let mut console = console();
+    console.bus_mut().labels.insert_prg_rom(0x10, "init", "", 1);
+    console.step();
+    assert_eq!(console.cpu.pc, 0xc010);
+    assert!(trace_labeled(&console.cpu).ends_with("  ; init: ldx #$03"));
+    // Round the loop once so the branch has been seen
+    for _ in 0..4 {
+        console.step();
+    }
+    assert_eq!(console.cpu.pc, 0xc013);
+    assert!(trace_labeled(&console.cpu).ends_with("  ; bne loc_C012"));
+
+    let bus = console.bus_mut();
+    assert_eq!(bus.label(0xc010).as_deref(), Some("init"));
+    let mut labels = bus.labels.clone();
+    labels.merge(&bus.auto_labels);
+    assert_eq!(labels.prg_rom_name(0x00).as_deref(), Some("reset_C000"));
+    assert_eq!(labels.prg_rom_name(0x10).as_deref(), Some("init"));
+    assert_eq!(labels.prg_rom_name(0x12).as_deref(), Some("loc_C012"));
+}
+
+#[test]
+fn off_by_default() {
+    let mut console = Console::new(common::nrom(&[(0xc000, CODE)]));
+    console.step();
+    assert!(console.bus().auto_labels.is_empty());
+    assert_eq!(console.bus().label(0xc010), None);
+}
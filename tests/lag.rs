@@ -0,0 +1,32 @@
+use nes::console::Console;
+
+mod common;
+
+/// Reads the controller in the NMI of every other frame
+fn console() -> Console {
+    Console::new(common::nrom(&[
+        // LDA #$80; STA $2000; JMP *
+        (0xc000, &[0xa9, 0x80, 0x8d, 0x00, 0x20, 0x4c, 0x05, 0xc0]),
+        // INC $11; LDA $11; AND #1; BNE +3; LDA $4016; RTI
+        (
+            0xd000,
+            &[
+                0xe6, 0x11, 0xa5, 0x11, 0x29, 0x01, 0xd0, 0x03, 0xad, 0x16, 0x40, 0x40,
+            ],
+        ),
+        (0xfffa, &[0x00, 0xd0, 0x00, 0xc0, 0x00, 0xc0]),
+    ]))
+}
+
+#[test]
+fn lag_frames() {
+    let mut console = console();
+    assert_eq!(console.lag_count(), 0);
+    let mut lagged = vec![];
+    for _ in 0..6 {
+        console.run_frame();
+        lagged.push(console.is_lag_frame());
+    }
+    assert_eq!(lagged, [true, false, true, false, true, false]);
+    assert_eq!(console.lag_count(), 3);
+}
@@ -0,0 +1,179 @@
+use nes::Bus;
+use nes::mapper::{Mapper, Mmc1, Mmc1Board};
+use nes::rom::{Mirroring, Rom};
+
+/// NES 2.0, mapper 1 with 8K of CHR RAM. Each 16K PRG bank starts with its
+/// number, `ram` is header byte 10.
+fn mmc1_rom(prg_banks: usize, submapper: u8, ram: u8) -> Rom {
+    let mut ines = vec![0; 16 + prg_banks * 0x4000];
+    ines[..11].copy_from_slice(&[
+        b'N',
+        b'E',
+        b'S',
+        0x1a,
+        prg_banks as u8,
+        0x00,
+        0x10,
+        0x08,
+        submapper << 4,
+        0x00,
+        ram,
+    ]);
+    for bank in 0..prg_banks {
+        ines[16 + bank * 0x4000] = bank as u8;
+    }
+    Rom::new(&ines).unwrap()
+}
+
+fn mmc1(prg_banks: usize, submapper: u8, ram: u8) -> Bus {
+    Bus::new(mmc1_rom(prg_banks, submapper, ram))
+}
+
+/// Write the 5 bits of `val` to the register at `addr`
+fn serial(bus: &mut Bus, addr: u16, val: u8) {
+    for bit in 0..5 {
+        bus.write(addr, val >> bit);
+    }
+}
+
+#[test]
+fn prg_banking() {
+    let mut bus = mmc1(8, 0, 0x07);
+    // The last bank is fixed at $C000 from power on
+    assert_eq!(bus.read(0xc000), 7);
+    serial(&mut bus, 0xe000, 3);
+    assert_eq!(bus.read(0x8000), 3);
+
+    // Fixed first bank at $8000
+    serial(&mut bus, 0x8000, 0x08);
+    assert_eq!(bus.read(0x8000), 0);
+    assert_eq!(bus.read(0xc000), 3);
+
+    // 32K at a time, ignoring the low bit
+    serial(&mut bus, 0x8000, 0x00);
+    assert_eq!((bus.read(0x8000), bus.read(0xc000)), (2, 3));
+
+    // A write with bit 7 set starts over and fixes the last bank again
+    bus.write(0x8000, 1);
+    bus.write(0x8000, 0x80);
+    serial(&mut bus, 0xe000, 5);
+    assert_eq!((bus.read(0x8000), bus.read(0xc000)), (5, 7));
+}
+
+#[test]
+fn mirroring() {
+    let mut bus = mmc1(2, 0, 0x07);
+    serial(&mut bus, 0x8000, 0x0f);
+    assert_eq!(bus.mapper.mirroring(), Mirroring::Horizontal);
+    serial(&mut bus, 0x8000, 0x0e);
+    assert_eq!(bus.mapper.mirroring(), Mirroring::Vertical);
+}
+
+#[test]
+fn detect_boards() {
+    for (prg_banks, submapper, ram, expected) in [
+        (16, 0, 0x07, Mmc1Board::Standard),
+        (32, 0, 0x07, Mmc1Board::Surom),
+        (16, 0, 0x77, Mmc1Board::Sorom),
+        (32, 0, 0x09, Mmc1Board::Sxrom),
+        (16, 1, 0x07, Mmc1Board::Surom),
+        (16, 2, 0x07, Mmc1Board::Sorom),
+        (16, 4, 0x07, Mmc1Board::Sxrom),
+    ] {
+        let rom = mmc1_rom(prg_banks, submapper, ram);
+        assert_eq!(Mmc1Board::detect(&rom), expected);
+        assert_eq!(Mmc1::new(rom).board, expected);
+    }
+}
+
+#[test]
+fn surom() {
+    let mut bus = mmc1(32, 0, 0x07);
+    assert_eq!((bus.read(0x8000), bus.read(0xc000)), (0, 15));
+    // CHR bit 4 picks the second 256K
+    serial(&mut bus, 0xa000, 0x10);
+    serial(&mut bus, 0xe000, 3);
+    assert_eq!((bus.read(0x8000), bus.read(0xc000)), (19, 31));
+    serial(&mut bus, 0xa000, 0x00);
+    assert_eq!((bus.read(0x8000), bus.read(0xc000)), (3, 15));
+}
+
+#[test]
+fn sorom() {
+    // 8K of RAM and 8K battery backed
+    let mut bus = mmc1(16, 0, 0x77);
+    bus.write(0x6000, 0x11);
+    serial(&mut bus, 0xa000, 0x08);
+    assert_eq!(bus.read(0x6000), 0);
+    bus.write(0x6000, 0x22);
+    serial(&mut bus, 0xa000, 0x00);
+    assert_eq!(bus.read(0x6000), 0x11);
+
+    // Only the second bank is kept
+    let save = bus.mapper.save_data().unwrap();
+    assert_eq!((save.len(), save[0]), (0x2000, 0x22));
+}
+
+#[test]
+fn sxrom() {
+    let mut bus = mmc1(32, 4, 0x09);
+    for bank in 0..4 {
+        serial(&mut bus, 0xa000, bank << 2);
+        bus.write(0x6000, bank + 1);
+    }
+    for bank in 0..4 {
+        serial(&mut bus, 0xa000, bank << 2);
+        assert_eq!(bus.read(0x6000), bank + 1);
+    }
+    // Bit 4 still picks the PRG ROM half
+    serial(&mut bus, 0xa000, 0x10);
+    assert_eq!(bus.read(0xc000), 31);
+    assert_eq!(bus.read(0x6000), 1);
+
+    // PRG bank bit 4 disables the RAM
+    serial(&mut bus, 0xe000, 0x10);
+    assert_eq!(bus.read(0x6000), 0);
+}
+
+#[test]
+fn nes_event() {
+    // Mapper 105, 256K of PRG ROM
+    let mut ines = vec![0; 16 + 0x40000];
+    ines[..8].copy_from_slice(b"NES\x1a\x10\x00\x90\x60");
+    for bank in 0..16 {
+        ines[16 + bank * 0x4000] = bank as u8;
+    }
+    let rom = Rom::new(&ines).unwrap();
+    assert_eq!(Mmc1Board::detect(&rom), Mmc1Board::Event);
+    let mut bus = Bus::new(rom);
+    serial(&mut bus, 0xa000, 0x04);
+    // The first 32K until the timer bit is cleared and set
+    assert_eq!((bus.read(0x8000), bus.read(0xc000)), (0, 1));
+    serial(&mut bus, 0xa000, 0x14);
+    assert_eq!((bus.read(0x8000), bus.read(0xc000)), (4, 5));
+
+    // MMC1 banking in the second 128K
+    serial(&mut bus, 0xa000, 0x18);
+    assert_eq!((bus.read(0x8000), bus.read(0xc000)), (8, 15));
+    serial(&mut bus, 0xe000, 2);
+    assert_eq!((bus.read(0x8000), bus.read(0xc000)), (10, 15));
+
+    // The timer only runs with bit 4 clear
+    serial(&mut bus, 0xa000, 0x08);
+    for _ in 0..1000 {
+        bus.mapper.cpu_tick();
+    }
+    assert!(!bus.mapper.irq());
+}
+
+#[test]
+fn nes_event_dip_switches() {
+    let mut ines = vec![0; 16 + 0x40000];
+    ines[..8].copy_from_slice(b"NES\x1a\x10\x00\x90\x60");
+    let mut mmc1 = Mmc1::new(Rom::new(&ines).unwrap());
+    // About 5:00 up to 9:41
+    assert_eq!(mmc1.event_time(), 16 << 25);
+    mmc1.set_dip_switches(0x0f);
+    assert_eq!(mmc1.event_time(), 31 << 25);
+    assert_eq!(mmc1.event_time() / 1_789_773, 581);
+}
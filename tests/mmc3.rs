@@ -0,0 +1,211 @@
+use nes::Bus;
+use nes::mapper::PpuTarget;
+use nes::rom::{Mirroring, Rom};
+
+fn mmc3(submapper: u8) -> Bus {
+    let mut ines = vec![0; 16 + 0x8000 + 0x2000];
+    // NES 2.0 header, mapper 4
+    ines[..9].copy_from_slice(&[
+        b'N',
+        b'E',
+        b'S',
+        0x1a,
+        0x02,
+        0x01,
+        0x40,
+        0x08,
+        submapper << 4,
+    ]);
+    let mut bus = Bus::new(Rom::new(&ines).unwrap());
+    // Background from $0000, sprites from $1000
+    bus.write(0x2000, 0x08);
+    bus.write(0x2001, 0x18);
+    bus
+}
+
+fn run_to(bus: &mut Bus, scanline: u16, dot: u16) {
+    while bus.ppu.scanline != scanline || bus.ppu.dot != dot {
+        bus.ppu.tick(bus.mapper.as_mut());
+    }
+}
+
+#[test]
+fn scanline_counter() {
+    let mut bus = mmc3(0);
+    bus.write(0xC000, 3);
+    bus.write(0xC001, 0);
+    bus.write(0xE001, 0);
+
+    // Reloaded on scanline 0, then clocked once per scanline
+    // even though A12 toggles for each sprite fetch
+    run_to(&mut bus, 3, 261);
+    assert!(!bus.mapper.irq());
+    run_to(&mut bus, 3, 262);
+    assert!(bus.mapper.irq());
+
+    bus.write(0xE000, 0);
+    assert!(!bus.mapper.irq());
+}
+
+#[test]
+fn revision_reload_behavior() {
+    for (submapper, fires_again) in [(0, true), (4, false)] {
+        let mut bus = mmc3(submapper);
+        bus.write(0xC000, 0);
+        bus.write(0xC001, 0);
+        bus.write(0xE001, 0);
+
+        run_to(&mut bus, 0, 262);
+        assert!(bus.mapper.irq());
+        bus.write(0xE000, 0);
+        bus.write(0xE001, 0);
+
+        // Only MMC3B/C keep firing with a latch of 0
+        run_to(&mut bus, 1, 262);
+        assert_eq!(bus.mapper.irq(), fires_again);
+    }
+}
+
+/// NES 2.0 MMC3 with a battery and header byte 10 set to `prg_ram`, the
+/// PRG RAM and PRG NVRAM sizes as shift counts
+fn mmc3_with_ram(prg_ram: u8) -> Rom {
+    let mut ines = vec![0; 16 + 0x8000 + 0x2000];
+    ines[..8].copy_from_slice(b"NES\x1a\x02\x01\x42\x08");
+    ines[10] = prg_ram;
+    Rom::new(&ines).unwrap()
+}
+
+#[test]
+fn prg_ram_protect() {
+    // 8K
+    let mut bus = Bus::new(mmc3_with_ram(0x07));
+    bus.write(0x6000, 1);
+    assert_eq!(bus.read(0x6000), 1);
+
+    // Enabled and protected
+    bus.write(0xA001, 0xC0);
+    bus.write(0x6000, 2);
+    assert_eq!(bus.read(0x6000), 1);
+
+    // Disabled, nothing answers
+    bus.write(0xA001, 0x00);
+    assert_eq!(bus.read(0x6000), 0);
+
+    bus.write(0xA001, 0x80);
+    bus.write(0x6000, 3);
+    assert_eq!(bus.read(0x6000), 3);
+}
+
+#[test]
+fn prg_ram_sizes() {
+    // 2K of NVRAM, repeating through $6000-$7FFF
+    let mut bus = Bus::new(mmc3_with_ram(0x50));
+    bus.write(0x6000, 0x12);
+    assert_eq!(bus.read(0x6800), 0x12);
+    assert_eq!(bus.read(0x7800), 0x12);
+    assert_eq!(bus.mapper.save_data().map(|data| data.len()), Some(0x800));
+
+    // 2K of NVRAM followed by 8K of RAM, only the NVRAM is saved
+    let rom = mmc3_with_ram(0x57);
+    assert_eq!((rom.prg_ram_size, rom.prg_nvram_size), (0x2800, 0x800));
+    let mut bus = Bus::new(rom);
+    bus.write(0x6000, 0x12);
+    bus.write(0x6900, 0x34);
+    let save = bus.mapper.save_data().unwrap();
+    assert_eq!(save.len(), 0x800);
+    assert_eq!(save[0], 0x12);
+
+    // None at all
+    let mut bus = Bus::new(mmc3_with_ram(0x00));
+    bus.write(0x6000, 0x12);
+    assert_eq!(bus.read(0x6000), 0);
+    assert_eq!(bus.mapper.save_data(), None);
+}
+
+#[test]
+fn four_screen() {
+    // TVROM, like Rad Racer II
+    let mut ines = vec![0; 16 + 0x8000 + 0x2000];
+    ines[..7].copy_from_slice(b"NES\x1a\x02\x01\x48");
+    let mut bus = Bus::new(Rom::new(&ines).unwrap());
+    bus.mapper.ppu_write(0x2c10, 0x55);
+    assert_eq!(bus.mapper.ppu_read(0x2c10), 0x55);
+    assert_eq!(bus.mapper.ppu_read(0x2810), 0x00);
+    assert_eq!(bus.mapper.ppu_read(0x0010), 0x00);
+    // The mirroring register doesn't change it
+    bus.write(0xA000, 1);
+    assert_eq!(bus.mapper.ppu_target(0x2c10), PpuTarget::Cartridge);
+    // Nor PRG RAM's enable and protection
+    bus.write(0xA000, 0x00);
+    bus.write(0x6000, 0x12);
+    assert_eq!(bus.read(0x6000), 0x12);
+}
+
+/// iNES mapper `mapper` with 128K of PRG and CHR ROM, each 8K PRG bank and
+/// 1K CHR bank starting with its number
+fn namco(mapper: u8) -> Bus {
+    let mut ines = vec![0; 16 + 0x20000 + 0x20000];
+    ines[..8].copy_from_slice(&[
+        b'N',
+        b'E',
+        b'S',
+        0x1a,
+        0x08,
+        0x10,
+        mapper << 4,
+        mapper & 0xf0,
+    ]);
+    for bank in 0..16 {
+        ines[16 + bank * 0x2000] = bank as u8;
+    }
+    for bank in 0..128 {
+        ines[16 + 0x20000 + bank * 0x400] = bank as u8;
+    }
+    Bus::new(Rom::new(&ines).unwrap())
+}
+
+#[test]
+fn namco_108() {
+    let mut bus = namco(206);
+    // No PRG mode, CHR inversion or PRG RAM
+    bus.write(0x8000, 0xc6);
+    bus.write(0x8001, 3);
+    bus.write(0x8000, 0x80);
+    bus.write(0x8001, 8);
+    assert_eq!(bus.read(0x8000), 3);
+    assert_eq!(bus.read(0xc000), 14);
+    assert_eq!(bus.mapper.ppu_read(0x0000), 8);
+    bus.write(0x6000, 1);
+    assert_eq!(bus.read(0x6000), 0);
+
+    // No mirroring control or IRQ
+    bus.write(0xa000, 1);
+    assert_eq!(bus.mapper.mirroring(), Mirroring::Horizontal);
+    bus.write(0xc000, 0);
+    bus.write(0xc001, 0);
+    bus.write(0xe001, 0);
+    bus.mapper.a12_rise();
+    assert!(!bus.mapper.irq());
+}
+
+#[test]
+fn namco_3443_chr() {
+    let mut bus = namco(88);
+    bus.write(0x8000, 0);
+    bus.write(0x8001, 0x44);
+    bus.write(0x8000, 2);
+    bus.write(0x8001, 0x05);
+    // The left pattern table from the lower 64K and the right from the upper
+    assert_eq!(bus.mapper.ppu_read(0x0000), 0x04);
+    assert_eq!(bus.mapper.ppu_read(0x1000), 0x45);
+    assert_eq!(bus.mapper.ppu_target(0x2400), PpuTarget::Ciram(0x000));
+}
+
+#[test]
+fn namco_3453_mirroring() {
+    let mut bus = namco(154);
+    bus.write(0xe000, 0x40);
+    assert_eq!(bus.mapper.ppu_target(0x2000), PpuTarget::Ciram(0x400));
+    bus.write(0x8000, 0x00);
+    assert_eq!(bus.mapper.ppu_target(0x2c00), PpuTarget::Ciram(0x000));
+}
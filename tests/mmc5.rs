@@ -0,0 +1,160 @@
+use nes::Bus;
+use nes::mapper::{Mapper, PpuFetch, PpuTarget};
+use nes::rom::Rom;
+
+fn mmc5() -> Bus {
+    // 16 8K PRG pages and 64 4K CHR pages, each filled with its page number
+    let mut ines = vec![0; 16];
+    ines[..8].copy_from_slice(b"NES\x1a\x08\x20\x50\x00");
+    ines.extend((0..16u8).flat_map(|page| [page; 0x2000]));
+    ines.extend((0..64u8).flat_map(|page| [page; 0x1000]));
+    Bus::new(Rom::new(&ines).unwrap())
+}
+
+/// Make a rendering fetch the way the PPU does, with an empty CIRAM
+fn fetch(cart: &mut dyn Mapper, addr: u16, kind: PpuFetch) -> u8 {
+    cart.ppu_fetch(addr, kind);
+    match cart.ppu_target(addr) {
+        PpuTarget::Cartridge => cart.ppu_read(addr),
+        PpuTarget::Ciram(_) => 0,
+    }
+}
+
+/// Fetch a background tile, returning the nametable, attribute and pattern bytes
+fn fetch_tile(cart: &mut dyn Mapper, nt_addr: u16, fine_y: u16) -> [u8; 3] {
+    let tile = fetch(cart, nt_addr, PpuFetch::Nametable);
+    let attr = fetch(
+        cart,
+        0x23c0 | ((nt_addr >> 4) & 0x38) | ((nt_addr >> 2) & 0x07),
+        PpuFetch::Attribute,
+    );
+    let pattern = fetch(cart, ((tile as u16) << 4) | fine_y, PpuFetch::BgPattern);
+    fetch(cart, ((tile as u16) << 4) | fine_y | 8, PpuFetch::BgPattern);
+    [tile, attr, pattern]
+}
+
+fn start_scanline(cart: &mut dyn Mapper) {
+    for _ in 0..8 {
+        fetch(cart, 0x1000, PpuFetch::SpritePattern);
+    }
+}
+
+#[test]
+fn prg_banking() {
+    let mut bus = mmc5();
+    // Mode 3 at power on, $E000 starts at the last page
+    assert_eq!(bus.read(0xE000), 15);
+    bus.write(0x5114, 0x83);
+    bus.write(0x5115, 0x84);
+    bus.write(0x5116, 0x85);
+    assert_eq!(bus.read(0x8000), 3);
+    assert_eq!(bus.read(0xA000), 4);
+    assert_eq!(bus.read(0xC000), 5);
+
+    // 16K pages ignore the low bit
+    bus.write(0x5100, 1);
+    assert_eq!(bus.read(0x8000), 4);
+    assert_eq!(bus.read(0xA000), 5);
+    assert_eq!(bus.read(0xC000), 14);
+
+    // PRG RAM in the ROM area, writable once unprotected
+    bus.write(0x5100, 3);
+    bus.write(0x5114, 0x01);
+    bus.write(0x8000, 0x42);
+    assert_eq!(bus.read(0x8000), 0);
+    bus.write(0x5102, 2);
+    bus.write(0x5103, 1);
+    bus.write(0x8000, 0x42);
+    assert_eq!(bus.read(0x8000), 0x42);
+    bus.write(0x5113, 1);
+    assert_eq!(bus.read(0x6000), 0x42);
+}
+
+#[test]
+fn multiplier() {
+    let mut bus = mmc5();
+    bus.write(0x5205, 200);
+    bus.write(0x5206, 100);
+    assert_eq!(bus.read(0x5205), (20000 & 0xff) as u8);
+    assert_eq!(bus.read(0x5206), (20000 >> 8) as u8);
+}
+
+#[test]
+fn scanline_irq() {
+    let mut bus = mmc5();
+    bus.write(0x5203, 10);
+    bus.write(0x5204, 0x80);
+    bus.write(0x2001, 0x18);
+    // Scanlines are counted from the pre-render line's fetches
+    while bus.ppu.frame == 0 {
+        bus.tick(1);
+    }
+    bus.read(0x5204);
+    while !bus.mapper.irq() {
+        bus.tick(1);
+    }
+    // Detected at the start of scanline 10
+    assert_eq!(bus.ppu.scanline, 10);
+    assert!(bus.ppu.dot < 8, "{}", bus.ppu.dot);
+    assert_eq!(bus.read(0x5204) & 0xc0, 0xc0);
+    assert!(!bus.mapper.irq());
+
+    // In-frame is cleared once the PPU stops fetching
+    bus.write(0x2001, 0x00);
+    bus.tick(3);
+    assert_eq!(bus.read(0x5204) & 0x40, 0);
+}
+
+#[test]
+fn extended_attributes() {
+    let mut bus = mmc5();
+    bus.write(0x5104, 1);
+    bus.write(0x5130, 1);
+    // Palette 2 and 4K page $45 for the tile at row 1, column 3
+    bus.write(0x5c00 + 32 + 3, 0x80 | 0x05);
+    let cart = bus.mapper.as_mut();
+    let [_, attr, pattern] = fetch_tile(cart, 0x2000 + 32 + 3, 0);
+    assert_eq!(attr, 0xaa);
+    assert_eq!(pattern, 0x45 % 64);
+    // Sprites aren't affected
+    assert_eq!(fetch(cart, 0x1000, PpuFetch::SpritePattern), 0x01);
+}
+
+#[test]
+fn fill_mode() {
+    let mut bus = mmc5();
+    bus.write(0x5105, 0xff);
+    bus.write(0x5106, 0x12);
+    bus.write(0x5107, 0x03);
+    let [tile, attr, _] = fetch_tile(bus.mapper.as_mut(), 0x2c00, 0);
+    assert_eq!([tile, attr], [0x12, 0xff]);
+}
+
+#[test]
+fn vertical_split() {
+    let mut bus = mmc5();
+    // ExRAM nametable for the split, tile number = column
+    for col in 0..32 {
+        bus.write(0x5c00 + 2 * 32 + col, col as u8);
+    }
+    bus.write(0x5c00 + 0x3c0, 0b_0100_0000);
+    // Left 4 tiles, scrolled to row 2, pattern page 7
+    bus.write(0x5200, 0x80 | 4);
+    bus.write(0x5201, 16 + 3);
+    bus.write(0x5202, 7);
+    let cart = bus.mapper.as_mut();
+
+    start_scanline(cart);
+    for col in 0..6u8 {
+        let [tile, attr, pattern] = fetch_tile(cart, 0x2000 + 20, 0);
+        if col < 4 {
+            assert_eq!(tile, col);
+            // Columns 2 and 3 of row 2 are in the bottom right quadrant
+            assert_eq!(attr, if col < 2 { 0x00 } else { 0x55 });
+            assert_eq!(pattern, 7);
+        } else {
+            assert_eq!(tile, 0);
+            assert_eq!(pattern, 0);
+        }
+    }
+}
@@ -0,0 +1,425 @@
+use nes::Flags;
+use nes::mos6502::{Bus, Config, Cpu, CpuVariant};
+use nes::rom::Rom;
+
+mod common;
+
+/// 64K of RAM and nothing else
+struct Ram(Vec<u8>);
+
+impl Ram {
+    /// `program` at $0200 and every vector pointing at it
+    fn with_program(program: &[u8]) -> Ram {
+        let mut ram = vec![0; 0x10000];
+        ram[0x200..0x200 + program.len()].copy_from_slice(program);
+        ram[0xfffa..].copy_from_slice(&[0x00, 0x02, 0x00, 0x02, 0x00, 0x02]);
+        Ram(ram)
+    }
+}
+
+impl Bus for Ram {
+    fn read(&mut self, addr: u16) -> u8 {
+        self.0[addr as usize]
+    }
+    fn write(&mut self, addr: u16, val: u8) {
+        self.0[addr as usize] = val;
+    }
+    fn peek(&self, addr: u16) -> u8 {
+        self.0[addr as usize]
+    }
+}
+
+/// Run `program` up to its BRK, returning A and the flags
+fn run(config: Config, program: &[u8]) -> (u8, Flags) {
+    let mut cpu = Cpu::with_config(Ram::with_program(program), config);
+    cpu.run();
+    (cpu.reg_a, cpu.status)
+}
+
+#[test]
+fn variants() {
+    assert_eq!(Cpu::new(Ram::with_program(&[])).config, Config::MOS6502);
+    assert!(CpuVariant::Nmos6502.has_decimal_mode());
+    // The NES's CPU, on an empty NROM cartridge
+    let cpu = nes::Cpu::new(nes::Bus::new(common::nrom(&[])));
+    assert_eq!(cpu.config.variant, CpuVariant::Ricoh2A03);
+    assert!(!CpuVariant::Ricoh2A03.has_decimal_mode());
+}
+
+#[test]
+fn decimal_mode() {
+    // SED; CLC; LDA #$15; ADC #$27; BRK
+    let add = [0xf8, 0x18, 0xa9, 0x15, 0x69, 0x27, 0x00];
+    let (a, status) = run(Config::MOS6502, &add);
+    assert_eq!(a, 0x42);
+    assert!(!status.contains(Flags::CARRY));
+    // The 2A03 adds in binary whatever the D flag says
+    assert_eq!(run(Config::RICOH_2A03, &add).0, 0x3c);
+
+    // SED; CLC; LDA #$99; ADC #$01; BRK. Z comes from the binary sum ($9A)
+    // and N from the sum before the high digit is adjusted ($A0).
+    let (a, status) = run(Config::MOS6502, &[0xf8, 0x18, 0xa9, 0x99, 0x69, 0x01, 0x00]);
+    assert_eq!(a, 0x00);
+    assert!(status.contains(Flags::CARRY | Flags::NEGATIVE));
+    assert!(!status.contains(Flags::ZERO));
+
+    // SED; CLC; LDA #$79; ADC #$10; BRK, overflows like $79 + $10 in binary
+    let (a, status) = run(Config::MOS6502, &[0xf8, 0x18, 0xa9, 0x79, 0x69, 0x10, 0x00]);
+    assert_eq!(a, 0x89);
+    assert!(status.contains(Flags::OVERFLOW | Flags::NEGATIVE));
+    assert!(!status.contains(Flags::CARRY));
+
+    // SED; SEC; LDA #$42; SBC #$15; BRK
+    let (a, status) = run(Config::MOS6502, &[0xf8, 0x38, 0xa9, 0x42, 0xe9, 0x15, 0x00]);
+    assert_eq!(a, 0x27);
+    assert!(status.contains(Flags::CARRY));
+
+    // SED; SEC; LDA #$00; SBC #$01; BRK
+    let (a, status) = run(Config::MOS6502, &[0xf8, 0x38, 0xa9, 0x00, 0xe9, 0x01, 0x00]);
+    assert_eq!(a, 0x99);
+    // Like $00 - $01 in binary
+    assert!(status.contains(Flags::NEGATIVE));
+    assert!(!status.contains(Flags::CARRY | Flags::ZERO));
+
+    // CLD; CLC; LDA #$15; ADC #$27; BRK, the D flag is clear
+    assert_eq!(
+        run(Config::MOS6502, &[0xd8, 0x18, 0xa9, 0x15, 0x69, 0x27, 0x00]).0,
+        0x3c
+    );
+}
+
+/// RAM that raises one IRQ
+struct IrqOnce {
+    ram: Ram,
+    irq: bool,
+}
+
+impl Bus for IrqOnce {
+    fn read(&mut self, addr: u16) -> u8 {
+        self.ram.read(addr)
+    }
+    fn write(&mut self, addr: u16, val: u8) {
+        self.ram.write(addr, val)
+    }
+    fn peek(&self, addr: u16) -> u8 {
+        self.ram.peek(addr)
+    }
+    fn irq(&mut self) -> bool {
+        std::mem::take(&mut self.irq)
+    }
+}
+
+#[test]
+fn vectors() {
+    let config = Config {
+        reset_vector: 0x1000,
+        irq_vector: 0x1002,
+        ..Config::MOS6502
+    };
+    let mut ram = Ram::with_program(&[]);
+    // Reset to $0300, IRQs to $0400
+    ram.0[0x1000..0x1004].copy_from_slice(&[0x00, 0x03, 0x00, 0x04]);
+    // CLI; NOP
+    ram.0[0x300..0x302].copy_from_slice(&[0x58, 0xea]);
+    // LDA #$07; RTI
+    ram.0[0x400..0x403].copy_from_slice(&[0xa9, 0x07, 0x40]);
+    let mut cpu = Cpu::with_config(IrqOnce { ram, irq: true }, config);
+    assert_eq!(cpu.pc, 0x300);
+
+    // The IRQ waits for CLI
+    cpu.step();
+    assert_eq!(cpu.pc, 0x301);
+    cpu.step();
+    assert_eq!(cpu.pc, 0x400);
+    cpu.step();
+    cpu.step();
+    assert_eq!((cpu.reg_a, cpu.pc), (0x07, 0x301));
+}
+
+#[test]
+fn wraps_at_the_top_of_memory() {
+    let mut ram = Ram::with_program(&[]);
+    // LDA #$42 at $FFFF, with its operand at $0000
+    ram.0[0xffff] = 0xa9;
+    ram.0[0x0000] = 0x42;
+    let mut cpu = Cpu::new(ram);
+    cpu.pc = 0xffff;
+    cpu.step();
+    assert_eq!((cpu.reg_a, cpu.pc), (0x42, 0x0001));
+
+    // JSR $0300 at $FFFE returns to $0001
+    cpu.memory.0[0xfffe..].copy_from_slice(&[0x20, 0x00]);
+    cpu.memory.0[0x0000] = 0x03;
+    cpu.memory.0[0x0300] = 0x60;
+    cpu.pc = 0xfffe;
+    cpu.step();
+    assert_eq!(cpu.pc, 0x0300);
+    cpu.step();
+    assert_eq!(cpu.pc, 0x0001);
+    assert_eq!(cpu.memory.read_u16(0xffff), 0x0300);
+}
+
+/// RAM that records every bus cycle, as (address, value, write)
+#[derive(Default)]
+struct Recorder {
+    ram: Vec<u8>,
+    cycles: Vec<(u16, u8, bool)>,
+    /// Cycles ticked, which should be one per access
+    ticks: usize,
+}
+
+impl Bus for Recorder {
+    fn read(&mut self, addr: u16) -> u8 {
+        let val = self.ram[addr as usize];
+        self.cycles.push((addr, val, false));
+        val
+    }
+    fn write(&mut self, addr: u16, val: u8) {
+        self.ram[addr as usize] = val;
+        self.cycles.push((addr, val, true));
+    }
+    fn peek(&self, addr: u16) -> u8 {
+        self.ram[addr as usize]
+    }
+    fn tick(&mut self, cycles: u16) {
+        self.ticks += cycles as usize;
+    }
+}
+
+/// The bus cycles of the instruction at $0200 with X = $10 and the stack
+/// pointer at $FD
+fn bus_cycles(instruction: &[u8], ram: &[(u16, u8)]) -> Vec<(u16, u8, bool)> {
+    let mut bus = Recorder {
+        ram: Ram::with_program(instruction).0,
+        ..Recorder::default()
+    };
+    for &(addr, val) in ram {
+        bus.ram[addr as usize] = val;
+    }
+    let mut cpu = Cpu::new(bus);
+    cpu.cycle_stepped = true;
+    cpu.reg_x = 0x10;
+    cpu.memory.cycles.clear();
+    cpu.step();
+    assert_eq!(cpu.memory.ticks, cpu.memory.cycles.len());
+    std::mem::take(&mut cpu.memory.cycles)
+}
+
+#[test]
+fn cycle_stepped() {
+    // LDA $02F8,X crosses a page, so reads $0208 before $0308
+    assert_eq!(
+        bus_cycles(&[0xbd, 0xf8, 0x02], &[(0x308, 0x55)]),
+        [
+            (0x200, 0xbd, false),
+            (0x201, 0xf8, false),
+            (0x202, 0x02, false),
+            (0x208, 0x00, false),
+            (0x308, 0x55, false),
+        ]
+    );
+    // STA $0300,X always reads before writing
+    assert_eq!(
+        bus_cycles(&[0x9d, 0x00, 0x03], &[]),
+        [
+            (0x200, 0x9d, false),
+            (0x201, 0x00, false),
+            (0x202, 0x03, false),
+            (0x310, 0x00, false),
+            (0x310, 0x00, true),
+        ]
+    );
+    // INC $40 writes the old value back first
+    assert_eq!(
+        bus_cycles(&[0xe6, 0x40], &[(0x40, 0x07)]),
+        [
+            (0x200, 0xe6, false),
+            (0x201, 0x40, false),
+            (0x040, 0x07, false),
+            (0x040, 0x07, true),
+            (0x040, 0x08, true),
+        ]
+    );
+    // JSR $1234 pushes the address of its last byte
+    assert_eq!(
+        bus_cycles(&[0x20, 0x34, 0x12], &[]),
+        [
+            (0x200, 0x20, false),
+            (0x201, 0x34, false),
+            (0x1fd, 0x00, false),
+            (0x1fd, 0x02, true),
+            (0x1fc, 0x02, true),
+            (0x202, 0x12, false),
+        ]
+    );
+}
+
+#[test]
+fn cycle_stepped_nestest() {
+    const CODE: &[u8] = include_bytes!("../nestest.nes");
+    let new_cpu = |cycle_stepped| {
+        let mut cpu = nes::Cpu::new(nes::Bus::new(Rom::new(CODE).unwrap()));
+        cpu.cycle_stepped = cycle_stepped;
+        // Automation mode, up to the unofficial opcodes
+        cpu.set_pc(0xc000);
+        cpu
+    };
+    let (mut whole, mut cycles) = (new_cpu(false), new_cpu(true));
+    let registers = |cpu: &nes::Cpu| {
+        let status = cpu.status.bits();
+        let (a, x, y, sp) = (cpu.reg_a, cpu.reg_x, cpu.reg_y, cpu.stack_ptr);
+        (cpu.pc, a, x, y, sp, status, cpu.memory.cycles)
+    };
+    let mut steps = 0;
+    while whole.try_step().is_ok() {
+        assert!(cycles.try_step().is_ok());
+        assert_eq!(registers(&whole), registers(&cycles), "after {steps} steps");
+        steps += 1;
+    }
+    assert!(steps > 5000);
+}
+
+/// SingleStepTests' 6502 vectors, https://github.com/SingleStepTests/65x02,
+/// through the cycle-stepped core: registers, RAM and every bus cycle after
+/// one instruction. The suite is too big to keep here, so this runs when
+/// `SINGLE_STEP_TESTS` is the directory with its `6502/v1` JSON files and
+/// passes without it. Opcodes the core doesn't know are skipped.
+#[test]
+fn single_step_tests() {
+    let Some(dir) = std::env::var_os("SINGLE_STEP_TESTS") else {
+        return;
+    };
+    let mut paths: Vec<_> = std::fs::read_dir(&dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "json"))
+        .collect();
+    paths.sort();
+    let (mut passed, mut skipped, mut failures) = (0, 0, vec![]);
+    for path in paths {
+        let tests: serde_json::Value =
+            serde_json::from_slice(&std::fs::read(&path).unwrap()).unwrap();
+        for test in tests.as_array().unwrap() {
+            match single_step(test) {
+                Some(Ok(())) => passed += 1,
+                Some(Err(err)) => failures.push(format!("{}: {err}", test["name"])),
+                None => skipped += 1,
+            }
+        }
+    }
+    eprintln!(
+        "{passed} passed, {} failed, {skipped} skipped",
+        failures.len()
+    );
+    assert!(
+        failures.is_empty(),
+        "{}",
+        failures[..failures.len().min(20)].join("\n")
+    );
+}
+
+/// Run one SingleStepTests case, `None` if the core doesn't know its opcode
+fn single_step(test: &serde_json::Value) -> Option<Result<(), String>> {
+    let number = |value: &serde_json::Value| value.as_u64().unwrap();
+    let mut bus = Recorder {
+        ram: vec![0; 0x10000],
+        ..Recorder::default()
+    };
+    let initial = &test["initial"];
+    for pair in initial["ram"].as_array().unwrap() {
+        bus.ram[number(&pair[0]) as usize] = number(&pair[1]) as u8;
+    }
+    let mut cpu = Cpu::with_config(bus, Config::MOS6502);
+    cpu.cycle_stepped = true;
+    cpu.pc = number(&initial["pc"]) as u16;
+    cpu.stack_ptr = number(&initial["s"]) as u8;
+    cpu.reg_a = number(&initial["a"]) as u8;
+    cpu.reg_x = number(&initial["x"]) as u8;
+    cpu.reg_y = number(&initial["y"]) as u8;
+    cpu.status = Flags::from_bits_retain(number(&initial["p"]) as u8);
+    cpu.memory.cycles.clear();
+    cpu.try_step().ok()?;
+
+    let last = &test["final"];
+    // B and bit 5 aren't stored in the register
+    let status = |p: u8| p & 0xcf;
+    let registers = |cpu: &Cpu<Recorder>| {
+        let p = status(cpu.status.bits());
+        (cpu.pc, cpu.stack_ptr, cpu.reg_a, cpu.reg_x, cpu.reg_y, p)
+    };
+    let expected = (
+        number(&last["pc"]) as u16,
+        number(&last["s"]) as u8,
+        number(&last["a"]) as u8,
+        number(&last["x"]) as u8,
+        number(&last["y"]) as u8,
+        status(number(&last["p"]) as u8),
+    );
+    if registers(&cpu) != expected {
+        return Some(Err(format!(
+            "registers {:x?}, expected {expected:x?}",
+            registers(&cpu)
+        )));
+    }
+    for pair in last["ram"].as_array().unwrap() {
+        let (addr, val) = (number(&pair[0]) as usize, number(&pair[1]) as u8);
+        if cpu.memory.ram[addr] != val {
+            let found = cpu.memory.ram[addr];
+            return Some(Err(format!(
+                "${addr:04X} = ${found:02X}, expected ${val:02X}"
+            )));
+        }
+    }
+    let cycles: Vec<(u16, u8, bool)> = test["cycles"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|cycle| {
+            let write = cycle[2] == "write";
+            (number(&cycle[0]) as u16, number(&cycle[1]) as u8, write)
+        })
+        .collect();
+    if cpu.memory.cycles != cycles {
+        return Some(Err(format!(
+            "cycles {:x?}, expected {cycles:x?}",
+            cpu.memory.cycles
+        )));
+    }
+    Some(Ok(()))
+}
+
+#[test]
+fn test_harness_controls() {
+    let mut ram = Ram::with_program(&[]);
+    // NMIs to $0400, IRQs to $0500, both RTI
+    ram.0[0xfffa..].copy_from_slice(&[0x00, 0x04, 0x00, 0x02, 0x00, 0x05]);
+    ram.0[0x400] = 0x40;
+    ram.0[0x500] = 0x40;
+    // CLI; NOP at $0300
+    ram.0[0x300..0x302].copy_from_slice(&[0x58, 0xea]);
+    let mut cpu = Cpu::new(ram);
+    assert_eq!(cpu.pc, 0x200);
+    cpu.reset_pc = Some(0x300);
+    cpu.reset();
+    assert_eq!(cpu.pc, 0x300);
+
+    cpu.trigger_nmi();
+    cpu.step();
+    assert_eq!(cpu.pc, 0x400);
+    cpu.step();
+    assert_eq!(cpu.pc, 0x300);
+
+    // The IRQ waits for CLI, and two make one
+    cpu.trigger_irq();
+    cpu.trigger_irq();
+    cpu.step();
+    assert_eq!(cpu.pc, 0x301);
+    cpu.step();
+    assert_eq!(cpu.pc, 0x500);
+    cpu.step();
+    cpu.step();
+    assert_eq!(cpu.pc, 0x302);
+
+    cpu.set_pc(0x300);
+    assert_eq!(cpu.pc, 0x300);
+}
@@ -0,0 +1,186 @@
+use nes::console::Console;
+use nes::joypad::Buttons;
+use nes::movie::{Eviction, FrameInput, Movie, Verification};
+
+mod common;
+
+fn test_console() -> Console {
+    Console::new(common::two_players())
+}
+
+fn input(frame: usize) -> FrameInput {
+    let buttons = |n: usize| Buttons::from_bits_retain((n * 37) as u8);
+    [buttons(frame / 3), buttons(frame / 4 + 1)]
+}
+
+/// A fresh console run through `inputs`
+fn replay(inputs: &[FrameInput]) -> [u8; 0x800] {
+    let mut console = test_console();
+    for input in inputs {
+        for (joypad, buttons) in console.bus_mut().joypads.iter_mut().zip(input) {
+            joypad.buttons = *buttons;
+        }
+        console.run_frame();
+    }
+    console.bus().cpu_ram
+}
+
+fn recorded(frames: usize) -> (Console, Movie) {
+    let mut console = test_console();
+    let mut movie = Movie::new(&mut console);
+    for frame in 0..frames {
+        movie.record(&mut console, input(frame));
+    }
+    (console, movie)
+}
+
+#[test]
+fn seek_uses_greenzone() {
+    let (mut console, mut movie) = recorded(12);
+    assert_eq!(movie.len(), 12);
+    assert_eq!(movie.position(), 12);
+    let end = console.bus().cpu_ram;
+    assert_eq!(end, replay(movie.inputs()));
+
+    movie.seek(&mut console, 5).unwrap();
+    assert_eq!(movie.position(), 5);
+    assert_eq!(console.bus().cpu_ram, replay(&movie.inputs()[..5]));
+    while movie.play(&mut console) {}
+    assert_eq!(console.bus().cpu_ram, end);
+    assert!(movie.seek(&mut console, 13).is_err());
+}
+
+#[test]
+fn edits() {
+    let (mut console, mut movie) = recorded(12);
+    assert!((0..=12).all(|frame| movie.is_green(frame)));
+
+    movie.toggle(6, 1, Buttons::A | Buttons::UP);
+    assert!(movie.is_green(6) && !movie.is_green(7));
+    let mut expected = input(6);
+    expected[1].toggle(Buttons::A | Buttons::UP);
+    assert_eq!(movie.input(6), Some(expected));
+
+    movie.insert_frames(2, 3);
+    assert_eq!(movie.len(), 15);
+    assert_eq!(movie.input(2), Some(FrameInput::default()));
+    assert_eq!(movie.input(5), Some(input(2)));
+    assert!(!movie.is_green(3));
+
+    movie.delete_frames(0, 2);
+    movie.set_input(0, 0, Buttons::START);
+    assert_eq!(movie.len(), 13);
+    assert_eq!(movie.input(3), Some(input(2)));
+
+    // Past the end or the players
+    let inputs = movie.inputs().to_vec();
+    movie.set_input(13, 0, Buttons::A);
+    movie.toggle(0, 2, Buttons::A);
+    movie.delete_frames(13, 1);
+    assert_eq!(movie.inputs(), inputs);
+    movie.delete_frames(12, usize::MAX);
+    assert_eq!(movie.len(), 12);
+    movie.insert_frames(20, 1);
+    assert_eq!(movie.len(), 13);
+    assert_eq!(movie.input(12), Some(FrameInput::default()));
+
+    movie.seek(&mut console, movie.len()).unwrap();
+    assert_eq!(console.bus().cpu_ram, replay(movie.inputs()));
+}
+
+#[test]
+fn truncate_and_resume() {
+    let (mut console, mut movie) = recorded(10);
+    movie.truncate_and_resume(&mut console, 4).unwrap();
+    assert_eq!((movie.len(), movie.position()), (4, 4));
+    for frame in 20..23 {
+        movie.record(&mut console, input(frame));
+    }
+    assert_eq!(movie.len(), 7);
+    assert_eq!(console.bus().cpu_ram, replay(movie.inputs()));
+}
+
+#[test]
+fn file() {
+    let (_, movie) = recorded(6);
+    let loaded = Movie::from_bytes(&movie.to_bytes()).unwrap();
+    assert_eq!(loaded.inputs(), movie.inputs());
+    assert_eq!(loaded.rom_hash(), movie.rom_hash());
+
+    let mut loaded = loaded;
+    let mut console = test_console();
+    console.run_frame();
+    loaded.seek(&mut console, 6).unwrap();
+    assert_eq!(console.bus().cpu_ram, replay(movie.inputs()));
+
+    assert!(Movie::from_bytes(b"not a movie").is_err());
+}
+
+#[test]
+fn verify() {
+    let (mut console, mut movie) = recorded(12);
+    let end = console.state_hash();
+    let bytes = movie.to_bytes();
+    let verification = Movie::from_bytes(&bytes)
+        .unwrap()
+        .verify(&mut test_console())
+        .unwrap();
+    assert_eq!(
+        verification,
+        Verification {
+            frames: 12,
+            hash: end,
+            desync: None,
+        }
+    );
+
+    // The hashes are the last thing in the file, corrupt the one after frame 7
+    let mut bytes = bytes;
+    let at = bytes.len() - 4 * (12 - 7);
+    bytes[at] ^= 1;
+    let verification = Movie::from_bytes(&bytes)
+        .unwrap()
+        .verify(&mut test_console())
+        .unwrap();
+    assert_eq!((verification.hash, verification.desync), (end, Some(7)));
+
+    // Edits drop the hashes they change until the frames are played again
+    movie.set_input(8, 1, Buttons::A);
+    assert!(movie.state_hash(7).is_some());
+    assert!(movie.state_hash(8).is_none());
+    movie.seek(&mut console, 12).unwrap();
+    assert!(movie.state_hash(11).is_some());
+    assert_eq!(movie.verify(&mut console).unwrap().desync, None);
+}
+
+#[test]
+fn greenzone_budget() {
+    let mut console = test_console();
+    let mut movie = Movie::new(&mut console);
+    movie.greenzone_mut().interval = 2;
+    movie.record(&mut console, input(0));
+    movie.record(&mut console, input(1));
+    let state_size = movie.greenzone().bytes();
+    assert_eq!(movie.greenzone().frames().collect::<Vec<_>>(), [2]);
+
+    movie.greenzone_mut().budget = 4 * state_size;
+    for frame in 2..16 {
+        movie.record(&mut console, input(frame));
+    }
+    let greenzone = movie.greenzone();
+    assert_eq!(greenzone.bytes(), 4 * state_size);
+    assert_eq!(greenzone.frames().collect::<Vec<_>>(), [4, 8, 12, 16]);
+    assert!(movie.is_green(16));
+
+    movie.seek(&mut console, 11).unwrap();
+    assert_eq!(console.bus().cpu_ram, replay(&movie.inputs()[..11]));
+
+    movie.greenzone_mut().eviction = Eviction::Oldest;
+    movie.seek(&mut console, 16).unwrap();
+    movie.record(&mut console, input(16));
+    movie.record(&mut console, input(17));
+    assert_eq!(
+        movie.greenzone().frames().collect::<Vec<_>>(),
+        [8, 12, 16, 18]
+    );
+}
@@ -0,0 +1,100 @@
+use nes::Bus;
+use nes::apu::ExpansionChip;
+use nes::mapper::PpuTarget;
+use nes::rom::Rom;
+
+fn n163() -> Bus {
+    // 4 8K PRG banks, 16 1K CHR banks, each filled with its bank number
+    let mut ines = vec![0; 16];
+    ines[..8].copy_from_slice(b"NES\x1a\x02\x02\x30\x10");
+    ines.extend((0..4u8).flat_map(|bank| [bank; 0x2000]));
+    ines.extend((0..16u8).flat_map(|bank| [bank; 0x400]));
+    Bus::new(Rom::new(&ines).unwrap())
+}
+
+#[test]
+fn prg_banking() {
+    let mut bus = n163();
+    bus.write(0xE000, 2);
+    bus.write(0xE800, 1);
+    bus.write(0xF000, 0);
+    assert_eq!(bus.read(0x8000), 2);
+    assert_eq!(bus.read(0xA000), 1);
+    assert_eq!(bus.read(0xC000), 0);
+    assert_eq!(bus.read(0xE000), 3);
+}
+
+#[test]
+fn chr_and_nametable_banking() {
+    let mut bus = n163();
+    // Pattern table page from CHR ROM, nametable page from CIRAM
+    bus.write(0x8800, 5);
+    bus.write(0xC000, 0xe1);
+    assert_eq!(bus.mapper.ppu_target(0x0400), PpuTarget::Cartridge);
+    assert_eq!(bus.mapper.ppu_read(0x0400), 5);
+    assert_eq!(bus.mapper.ppu_target(0x2010), PpuTarget::Ciram(0x410));
+
+    // Nametable page from CHR ROM
+    bus.write(0xC800, 9);
+    assert_eq!(bus.mapper.ppu_target(0x2400), PpuTarget::Cartridge);
+    assert_eq!(bus.mapper.ppu_read(0x2400), 9);
+
+    // Pattern tables can use CIRAM unless disabled through $E800
+    bus.write(0xB800, 0xe0);
+    assert_eq!(bus.mapper.ppu_target(0x1c00), PpuTarget::Ciram(0));
+    bus.write(0xE800, 0x80);
+    assert_eq!(bus.mapper.ppu_target(0x1c00), PpuTarget::Cartridge);
+}
+
+#[test]
+fn irq_counter() {
+    let mut bus = n163();
+    bus.write(0x5000, 0xf0);
+    bus.write(0x5800, 0xff);
+    bus.tick(14);
+    assert!(!bus.mapper.irq());
+    bus.tick(1);
+    assert!(bus.mapper.irq());
+    // The counter stops at $7FFF
+    bus.tick(10);
+    assert_eq!(bus.read(0x5000), 0xff);
+    assert_eq!(bus.read(0x5800), 0xff);
+    bus.write(0x5800, 0xff);
+    assert!(!bus.mapper.irq());
+}
+
+#[test]
+fn wavetable_channel() {
+    let mut bus = n163();
+    // Square wave: 4 samples at 15, 4 samples at 0
+    bus.write(0xF800, 0x80);
+    for val in [0xff, 0xff, 0x00, 0x00] {
+        bus.write(0x4800, val);
+    }
+    // Channel 7: length 8 at address 0, full volume, advancing a sample
+    // every update, with only one channel enabled
+    bus.write(0xF800, 0x80 | 0x78);
+    for val in [0x00, 0x00, 0x00, 0x00, 0xf9, 0x00, 0x00, 0x0f] {
+        bus.write(0x4800, val);
+    }
+    // Auto increment wraps around
+    assert_eq!(bus.peek(0x4800), 0xff);
+
+    let mut levels = vec![];
+    for _ in 0..8 {
+        bus.tick(15);
+        let Some((ExpansionChip::N163, level)) = bus.mapper.expansion_audio() else {
+            panic!("no N163 output");
+        };
+        levels.push(level);
+    }
+    // The phase is advanced before the sample is played, so this starts at sample 1
+    assert_eq!(levels[..3], [levels[0]; 3]);
+    assert_eq!(levels[3..7], [levels[3]; 4]);
+    assert_eq!(levels[7], levels[0]);
+    assert!(levels[0] > 0.0 && levels[3] < 0.0);
+
+    // Sound disable
+    bus.write(0xE000, 0x40);
+    assert_eq!(bus.mapper.expansion_audio(), None);
+}
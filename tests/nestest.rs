@@ -1,6 +1,7 @@
 use std::io::BufWriter;
 use std::io::Write;
 
+use nes::console::Console;
 use nes::{Bus, Cpu, rom::Rom, trace::trace};
 
 #[test]
@@ -13,12 +14,54 @@ fn main() {
     cpu.reset();
     // cpu.pc = 0xc000;
 
-    let mut file = BufWriter::new(std::fs::File::create("my_log.txt").unwrap());
+    let log = std::path::Path::new(env!("CARGO_TARGET_TMPDIR")).join("my_log.txt");
+    let mut file = BufWriter::new(std::fs::File::create(log).unwrap());
 
+    // From reset the ROM sits in its menu forever, trace as much as the reference log
+    let mut lines = CORRECT_LOG.split(|&b| b == b'\n').count();
     cpu.run_with_callback(|cpu| {
+        lines -= 1;
+        if lines == 0 {
+            cpu.brk = true;
+        }
         let line = trace(cpu);
         writeln!(file, "{}", line).unwrap();
         file.flush().unwrap();
         println!("{}", line);
     });
 }
+
+/// Trace nestest from reset for as many instructions as the reference log,
+/// returning the trace and the hash of the state it ends in
+fn trace_nestest(mut console: Console) -> (Vec<String>, u32) {
+    const CORRECT_LOG: &[u8] = include_bytes!("../nestest.txt");
+    console.cpu.reset();
+    let lines = CORRECT_LOG.split(|&b| b == b'\n').count();
+    let trace = (0..lines)
+        .map(|_| {
+            let line = trace(&console.cpu);
+            console.step();
+            line
+        })
+        .collect();
+    (trace, console.state_hash())
+}
+
+#[test]
+fn parallel_instances() {
+    const CODE: &[u8] = include_bytes!("../nestest.nes");
+    let new_console = || Console::new(Rom::new(CODE).unwrap());
+    let expected = trace_nestest(new_console());
+    // Half built here and moved over, half built on their own thread
+    let consoles: Vec<Console> = (0..4).map(|_| new_console()).collect();
+    std::thread::scope(|scope| {
+        let moved = consoles
+            .into_iter()
+            .map(|console| scope.spawn(move || trace_nestest(console)));
+        let built = (0..4).map(|_| scope.spawn(move || trace_nestest(new_console())));
+        let threads: Vec<_> = moved.chain(built).collect();
+        for thread in threads {
+            assert!(thread.join().unwrap() == expected);
+        }
+    });
+}
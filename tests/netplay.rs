@@ -0,0 +1,190 @@
+use std::cell::{Cell, RefCell};
+use std::collections::VecDeque;
+use std::rc::Rc;
+use std::time::Duration;
+
+use nes::console::Console;
+use nes::error::NetplayError;
+use nes::joypad::Buttons;
+use nes::netplay::{Message, Mode, Session, TcpTransport, Transport};
+
+mod common;
+
+fn console() -> Console {
+    Console::new(common::two_players())
+}
+
+/// In-memory connection delivering messages `latency` ticks after they're sent
+struct Pipe {
+    clock: Rc<Cell<u64>>,
+    latency: u64,
+    outgoing: Rc<RefCell<VecDeque<(u64, Message)>>>,
+    incoming: Rc<RefCell<VecDeque<(u64, Message)>>>,
+}
+
+impl Transport for Pipe {
+    fn send(&mut self, message: Message) -> Result<(), NetplayError> {
+        let arrival = self.clock.get() + self.latency;
+        self.outgoing.borrow_mut().push_back((arrival, message));
+        Ok(())
+    }
+
+    fn recv(&mut self) -> Result<Option<Message>, NetplayError> {
+        let mut incoming = self.incoming.borrow_mut();
+        match incoming.front() {
+            Some((arrival, _)) if *arrival <= self.clock.get() => {
+                Ok(incoming.pop_front().map(|(_, message)| message))
+            }
+            _ => Ok(None),
+        }
+    }
+}
+
+fn pipes(clock: &Rc<Cell<u64>>, latency: u64) -> (Pipe, Pipe) {
+    let a = Rc::new(RefCell::new(VecDeque::new()));
+    let b = Rc::new(RefCell::new(VecDeque::new()));
+    let pipe = |outgoing: &Rc<_>, incoming: &Rc<_>| Pipe {
+        clock: clock.clone(),
+        latency,
+        outgoing: Rc::clone(outgoing),
+        incoming: Rc::clone(incoming),
+    };
+    (pipe(&a, &b), pipe(&b, &a))
+}
+
+/// Player inputs that change every few frames, differently for each player
+fn input(player: usize, frame: u64) -> Buttons {
+    let period = [7, 5][player];
+    Buttons::from_bits_retain((frame / period * 37 + player as u64 * 11) as u8)
+}
+
+/// What both consoles should end up as after `frames` frames
+fn reference(frames: u64, delay: u64) -> [u8; 0x800] {
+    let mut console = console();
+    for frame in 0..frames {
+        for (player, joypad) in console.bus_mut().joypads.iter_mut().enumerate() {
+            joypad.buttons = match frame.checked_sub(delay) {
+                Some(made) => input(player, made),
+                None => Buttons::empty(),
+            };
+        }
+        console.run_frame();
+    }
+    console.bus().cpu_ram
+}
+
+/// Run both sides until they have run `frames` frames and heard everything
+fn play(mode: Mode, latency: u64, frames: u64) -> [(Console, Session<Pipe>); 2] {
+    let clock = Rc::new(Cell::new(0));
+    let (a, b) = pipes(&clock, latency);
+    let mut sides = [
+        (console(), Session::new(a, mode, 0)),
+        (console(), Session::new(b, mode, 1)),
+    ];
+    for side in &mut sides {
+        side.1.hash_interval = 10;
+    }
+    let mut settled = 0;
+    while settled < latency + 2 {
+        clock.set(clock.get() + 1);
+        for (player, (console, session)) in sides.iter_mut().enumerate() {
+            if session.frame() < frames {
+                let buttons = input(player, session.frame());
+                session.advance(console, buttons).unwrap();
+            } else {
+                session.sync(console).unwrap();
+            }
+        }
+        if sides.iter().all(|(_, session)| session.frame() == frames) {
+            settled += 1;
+        }
+        assert!(clock.get() < 10 * frames, "netplay stalled");
+    }
+    sides
+}
+
+#[test]
+fn lockstep() {
+    let sides = play(Mode::Lockstep { delay: 3 }, 2, 30);
+    let expected = reference(30, 3);
+    for (console, _) in &sides {
+        assert_eq!(console.bus().cpu_ram, expected);
+    }
+}
+
+#[test]
+fn rollback() {
+    let mode = Mode::Rollback {
+        delay: 1,
+        max_frames: 8,
+    };
+    let sides = play(mode, 4, 30);
+    let expected = reference(30, 1);
+    for (console, session) in &sides {
+        assert!(session.rollback_frames() > 0);
+        assert_eq!(console.bus().cpu_ram, expected);
+    }
+}
+
+#[test]
+fn desync_detected() {
+    let clock = Rc::new(Cell::new(0));
+    let (a, b) = pipes(&clock, 1);
+    let mode = Mode::Lockstep { delay: 2 };
+    let mut sides = [
+        (console(), Session::new(a, mode, 0)),
+        (console(), Session::new(b, mode, 1)),
+    ];
+    for side in &mut sides {
+        side.1.hash_interval = 10;
+    }
+    let mut error = None;
+    while error.is_none() && clock.get() < 200 {
+        clock.set(clock.get() + 1);
+        if clock.get() == 25 {
+            sides[1].0.bus_mut().cpu_ram[0x100] = 0x55;
+        }
+        for (console, session) in &mut sides {
+            if let Err(err) = session.advance(console, Buttons::empty()) {
+                error = Some(err);
+            }
+        }
+    }
+    let error = error.expect("desync went unnoticed");
+    assert!(matches!(error, NetplayError::Desync { .. }), "{error}");
+}
+
+#[test]
+fn tcp_transport() {
+    let addr = "127.0.0.1:47651";
+    let host = std::thread::spawn(move || TcpTransport::host(addr).unwrap());
+    // Refused until the host is listening
+    let mut joined = loop {
+        match TcpTransport::connect(addr) {
+            Ok(transport) => break transport,
+            Err(NetplayError::Io(_)) => std::thread::sleep(Duration::from_millis(10)),
+            Err(err) => panic!("{err}"),
+        }
+    };
+    let mut host = host.join().unwrap();
+    let message = Message::Input {
+        frame: 3,
+        buttons: Buttons::A | Buttons::LEFT,
+    };
+    joined.send(message).unwrap();
+    let received = loop {
+        if let Some(message) = host.recv().unwrap() {
+            break message;
+        }
+    };
+    assert_eq!(received, message);
+
+    drop(joined);
+    let closed = loop {
+        match host.recv() {
+            Ok(None) => std::thread::yield_now(),
+            other => break other,
+        }
+    };
+    assert!(matches!(closed, Err(NetplayError::Closed)), "{closed:?}");
+}
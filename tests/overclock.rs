@@ -0,0 +1,108 @@
+use nes::Bus;
+use nes::ppu::{DOTS_PER_SCANLINE, PpuStatus};
+use nes::rom::{Region, Rom};
+
+mod common;
+
+fn bus(pal: bool) -> Bus {
+    let mut ines = common::ines(&[]);
+    ines[9] = pal as u8;
+    Bus::new(Rom::new(&ines).unwrap())
+}
+
+/// Run until the PPU reaches `scanline`, returning the CPU cycles it took
+fn run_to_scanline(bus: &mut Bus, scanline: u16) -> u64 {
+    let start = bus.cycles;
+    while bus.ppu.scanline != scanline {
+        bus.tick(1);
+    }
+    bus.cycles - start
+}
+
+/// NES 2.0 header with Dendy timing
+fn dendy() -> Bus {
+    let mut ines = common::ines(&[]);
+    ines[7] = 0x08;
+    ines[12] = 3;
+    Bus::new(Rom::new(&ines).unwrap())
+}
+
+#[test]
+fn region_from_header() {
+    assert_eq!(bus(false).ppu.region, Region::Ntsc);
+    assert_eq!(bus(true).ppu.region, Region::Pal);
+    assert_eq!(dendy().ppu.region, Region::Dendy);
+}
+
+#[test]
+fn dendy_timing() {
+    let mut bus = dendy();
+    assert_eq!(bus.ppu.scanlines_per_frame(), 312);
+    // 3 dots per CPU cycle, and vblank starts 50 scanlines later than on
+    // NTSC and PAL
+    let cycles = run_to_scanline(&mut bus, 291);
+    assert_eq!(cycles, 291 * DOTS_PER_SCANLINE as u64 / 3);
+    assert!(!bus.ppu.status.contains(PpuStatus::VBLANK));
+    bus.tick(1);
+    assert!(bus.ppu.status.contains(PpuStatus::VBLANK));
+    run_to_scanline(&mut bus, 310);
+    assert!(bus.ppu.status.contains(PpuStatus::VBLANK));
+    run_to_scanline(&mut bus, 0);
+    assert!(!bus.ppu.status.contains(PpuStatus::VBLANK));
+    assert_eq!(bus.cycles, 312 * DOTS_PER_SCANLINE as u64 / 3);
+
+    // Other dumps can be switched to it
+    let mut bus = self::bus(false);
+    bus.set_region(Region::Dendy);
+    assert_eq!(bus.ppu.scanlines_per_frame(), 312);
+    assert_eq!(bus.ppu.vblank_scanline(), 291);
+}
+
+#[test]
+fn extra_scanlines_extend_vblank() {
+    let mut bus = bus(false);
+    bus.ppu.overclock.ntsc = 50;
+    assert_eq!(bus.ppu.scanlines_per_frame(), 262 + 50);
+    run_to_scanline(&mut bus, 262);
+    assert!(bus.ppu.overclocking());
+    assert!(bus.ppu.status.contains(PpuStatus::VBLANK));
+    run_to_scanline(&mut bus, 261 + 50);
+    assert!(!bus.ppu.overclocking());
+    run_to_scanline(&mut bus, 0);
+    assert_eq!(bus.ppu.frame, 1);
+    assert!(!bus.ppu.status.contains(PpuStatus::VBLANK));
+}
+
+#[test]
+fn apu_paused_while_overclocking() {
+    let mut bus = bus(false);
+    bus.ppu.overclock.ntsc = 100;
+    // Fastest rate, fetches a byte every 8 * 54 cycles
+    bus.write(0x4010, 0x0f);
+    bus.write(0x4013, 0xff);
+    bus.write(0x4015, 0x10);
+    run_to_scanline(&mut bus, 262);
+    let remaining = bus.apu.dmc.bytes_remaining;
+    // No DMA stalls either, the CPU gets exactly the extra lines
+    let cycles = run_to_scanline(&mut bus, 262 + 99);
+    assert!(
+        cycles.abs_diff(99 * DOTS_PER_SCANLINE as u64 / 3) <= 1,
+        "{cycles}"
+    );
+    assert_eq!(bus.apu.dmc.bytes_remaining, remaining);
+    bus.tick(8 * 54 + 4);
+    assert!(bus.apu.dmc.bytes_remaining < remaining);
+}
+
+#[test]
+fn pal_frame_timing() {
+    let mut bus = bus(true);
+    assert_eq!(bus.ppu.scanlines_per_frame(), 312);
+    run_to_scanline(&mut bus, 0);
+    let cycles = run_to_scanline(&mut bus, 1) + run_to_scanline(&mut bus, 0);
+    // 341 * 312 dots at 3.2 dots per CPU cycle
+    assert!(cycles.abs_diff(33247) <= 1, "{cycles}");
+    bus.ppu.overclock.pal = 10;
+    assert_eq!(bus.ppu.scanlines_per_frame(), 322);
+    assert_eq!(bus.ppu.overclock.scanlines(Region::Ntsc), 0);
+}
@@ -0,0 +1,41 @@
+use std::time::Duration;
+
+use nes::pacing::{FramePacer, MAX_CATCH_UP};
+use nes::rom::Region;
+
+const REFRESH: Duration = Duration::from_micros(16_667);
+
+#[test]
+fn refresh_rates() {
+    assert!((Region::Ntsc.refresh_rate() - 60.0988).abs() < 0.0001);
+    assert!((Region::Pal.refresh_rate() - 50.007).abs() < 0.0001);
+    assert_eq!(Region::Dendy.refresh_rate(), Region::Pal.refresh_rate());
+}
+
+#[test]
+fn pal_on_60hz() {
+    let mut pacer = FramePacer::new(Region::Pal.refresh_rate());
+    let frames: Vec<u32> = (0..6).map(|_| pacer.frames_due(REFRESH)).collect();
+    assert_eq!(frames, [1, 1, 1, 0, 1, 1]);
+    let frames: u32 = (0..594).map(|_| pacer.frames_due(REFRESH)).sum();
+    assert_eq!(frames, 495);
+}
+
+#[test]
+fn ntsc_on_60hz() {
+    let mut pacer = FramePacer::new(Region::Ntsc.refresh_rate());
+    // A little jitter doesn't skip or double frames
+    for elapsed in [15_900, 17_400, 16_000, 17_300, 16_667] {
+        assert_eq!(pacer.frames_due(Duration::from_micros(elapsed)), 1);
+    }
+}
+
+#[test]
+fn catch_up() {
+    let mut pacer = FramePacer::new(50.0);
+    assert_eq!(pacer.frames_due(Duration::from_millis(40)), 2);
+    assert_eq!(pacer.until_next(), Duration::from_millis(10));
+    assert_eq!(pacer.frames_due(Duration::from_secs(1)), MAX_CATCH_UP);
+    pacer.set_rate(60.0);
+    assert_eq!(pacer.rate(), 60.0);
+}
@@ -0,0 +1,133 @@
+use nes::error::RomError;
+use nes::rom::Rom;
+
+mod common;
+
+fn ips() -> Vec<u8> {
+    let mut patch = b"PATCH".to_vec();
+    // 2 bytes at the start of PRG ROM
+    patch.extend([0x00, 0x00, 0x10, 0x00, 0x02, 0xab, 0xcd]);
+    // Run of 4 $EE at the start of CHR ROM
+    patch.extend([0x00, 0x40, 0x10, 0x00, 0x00, 0x00, 0x04, 0xee]);
+    // Switch to vertical mirroring in the header
+    patch.extend([0x00, 0x00, 0x06, 0x00, 0x01, 0x01]);
+    patch.extend(b"EOF");
+    patch
+}
+
+fn bps_number(out: &mut Vec<u8>, mut val: usize) {
+    loop {
+        let byte = val as u8 & 0x7f;
+        val >>= 7;
+        if val == 0 {
+            out.push(0x80 | byte);
+            return;
+        }
+        out.push(byte);
+        val -= 1;
+    }
+}
+
+/// Replaces the first byte of PRG ROM with $42
+fn bps(source: &[u8]) -> Vec<u8> {
+    let mut target = source.to_vec();
+    target[16] = 0x42;
+    let mut patch = b"BPS1".to_vec();
+    bps_number(&mut patch, source.len());
+    bps_number(&mut patch, target.len());
+    bps_number(&mut patch, 0);
+    // SourceRead the header, TargetRead a byte, SourceRead the rest
+    bps_number(&mut patch, (16 - 1) << 2);
+    bps_number(&mut patch, 1);
+    patch.push(0x42);
+    bps_number(&mut patch, (source.len() - 17 - 1) << 2);
+    checksums(patch, source, &target)
+}
+
+/// `patch` with the checksums that end a BPS patch
+fn checksums(mut patch: Vec<u8>, source: &[u8], target: &[u8]) -> Vec<u8> {
+    patch.extend(crc32fast::hash(source).to_le_bytes());
+    patch.extend(crc32fast::hash(target).to_le_bytes());
+    patch.extend(crc32fast::hash(&patch).to_le_bytes());
+    patch
+}
+
+#[test]
+fn ips_patch() {
+    let mut rom = Rom::new(&common::ines(&[])).unwrap();
+    rom.apply_ips(&ips()).unwrap();
+    assert_eq!(rom.prg_rom[..3], [0xab, 0xcd, 0x00]);
+    assert_eq!(rom.chr_rom[..5], [0xee, 0xee, 0xee, 0xee, 0x00]);
+    assert_eq!(rom.mirroring, nes::rom::Mirroring::Vertical);
+
+    assert!(rom.apply_ips(b"PATCH\x00\x00").is_err());
+    assert!(rom.apply_ips(b"NOPE").is_err());
+}
+
+#[test]
+fn bps_patch() {
+    let ines = common::ines(&[]);
+    let mut rom = Rom::new(&ines).unwrap();
+    let patch = bps(&ines);
+    rom.apply_bps(&patch).unwrap();
+    assert_eq!(rom.prg_rom[..2], [0x42, 0x00]);
+
+    // Made for the unpatched ROM
+    assert!(rom.apply_bps(&patch).is_err());
+    // Corrupt patch
+    let mut rom = Rom::new(&ines).unwrap();
+    let mut corrupt = patch.clone();
+    corrupt[8] ^= 1;
+    assert!(rom.apply_bps(&corrupt).is_err());
+}
+
+#[test]
+fn malformed_bps() {
+    let ines = common::ines(&[]);
+    let apply = |patch: Vec<u8>| match Rom::new(&ines).unwrap().apply_bps(&patch) {
+        Err(RomError::Patch(err)) => err,
+        other => panic!("{other:?}"),
+    };
+
+    // Sizes too big for a usize
+    let mut patch = b"BPS1".to_vec();
+    patch.extend([0x7f; 10]);
+    patch.push(0x80);
+    assert_eq!(apply(checksums(patch, &ines, &ines)), "BPS number overflow");
+
+    // Metadata running past the end
+    let mut patch = b"BPS1".to_vec();
+    bps_number(&mut patch, ines.len());
+    bps_number(&mut patch, ines.len());
+    bps_number(&mut patch, 1 << 40);
+    assert_eq!(
+        apply(checksums(patch, &ines, &ines)),
+        "BPS patch is truncated"
+    );
+
+    // A read longer than the ROM it makes
+    let mut patch = b"BPS1".to_vec();
+    bps_number(&mut patch, ines.len());
+    bps_number(&mut patch, ines.len());
+    bps_number(&mut patch, 0);
+    bps_number(&mut patch, usize::MAX >> 2 << 2);
+    assert_eq!(
+        apply(checksums(patch, &ines, &ines)),
+        "BPS patch writes past the end of the ROM"
+    );
+}
+
+#[test]
+fn auto_patch() {
+    let dir = std::env::temp_dir().join(format!("nes-patch-test-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("game.nes");
+    std::fs::write(&path, common::ines(&[])).unwrap();
+    std::fs::write(dir.join("game.ips"), ips()).unwrap();
+
+    let rom = Rom::load(&path, true).unwrap();
+    assert_eq!(rom.prg_rom[0], 0xab);
+    let rom = Rom::load(&path, false).unwrap();
+    assert_eq!(rom.prg_rom[0], 0x00);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
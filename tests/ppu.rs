@@ -0,0 +1,414 @@
+use nes::mapper::{self, Mapper};
+use nes::ppu::{PICTURE_WIDTH, Ppu, PpuMask, PpuStatus, RenderMode};
+use nes::rom::Rom;
+
+mod common;
+
+fn run_to(ppu: &mut Ppu, scanline: u16, dot: u16) {
+    let mut cart = nrom();
+    while ppu.scanline != scanline || ppu.dot != dot {
+        ppu.tick(cart.as_mut());
+    }
+}
+
+fn nrom() -> Box<dyn Mapper> {
+    mapper::new(common::nrom(&[]))
+}
+
+fn sprite_ppu(sprites: &[[u8; 4]]) -> Ppu {
+    let mut ppu = Ppu::new();
+    ppu.mask = PpuMask::SHOW_SPRITES;
+    // Move every sprite offscreen
+    ppu.oam = [0xff; 0x100];
+    for (i, sprite) in sprites.iter().enumerate() {
+        ppu.oam[i * 4..i * 4 + 4].copy_from_slice(sprite);
+    }
+    ppu
+}
+
+#[test]
+fn sprite_evaluation() {
+    let mut sprites = vec![[0x20, 0x01, 0x00, 0x10]; 8];
+    sprites[3] = [0x60, 0x02, 0x00, 0x20];
+    let mut ppu = sprite_ppu(&sprites);
+    run_to(&mut ppu, 0x24, 258);
+    assert_eq!(ppu.sprite_count, 7);
+    assert!(ppu.sprite_zero_next);
+    assert_eq!(ppu.secondary_oam[..4], [0x20, 0x01, 0x00, 0x10]);
+    assert_eq!(ppu.secondary_oam[28..], [0xff; 4]);
+    assert!(!ppu.status.contains(PpuStatus::SPRITE_OVERFLOW));
+}
+
+#[test]
+fn sprite_overflow() {
+    let mut ppu = sprite_ppu(&[[0x20, 0x01, 0x00, 0x10]; 9]);
+    run_to(&mut ppu, 0x20, 258);
+    assert_eq!(ppu.sprite_count, 8);
+    assert!(ppu.status.contains(PpuStatus::SPRITE_OVERFLOW));
+
+    // Cleared on the pre-render line
+    run_to(&mut ppu, 261, 2);
+    assert!(!ppu.status.contains(PpuStatus::SPRITE_OVERFLOW));
+}
+
+#[test]
+fn sprite_limit_disabled() {
+    let mut sprites = vec![[0x20, 0x01, 0x00, 0x10]; 10];
+    sprites[8] = [0x1c, 0x02, 0x00, 0x28];
+    sprites[9] = [0x1c, 0x02, 0x00, 0x30];
+    let mut ppu = sprite_ppu(&sprites);
+    ppu.sprite_limit = false;
+    // Nothing extra on lines with 8 sprites or less
+    run_to(&mut ppu, 0x1d, 258);
+    assert_eq!(ppu.sprite_count, 2);
+    assert_eq!(ppu.extra_sprite_count, 0);
+
+    run_to(&mut ppu, 0x20, 258);
+    assert_eq!(ppu.sprite_count, 8);
+    assert_eq!(ppu.extra_sprite_count, 2);
+    assert_eq!(ppu.extra_oam[4..8], [0x1c, 0x02, 0x00, 0x30]);
+    // Overflow is still reported like on hardware
+    assert!(ppu.status.contains(PpuStatus::SPRITE_OVERFLOW));
+}
+
+#[test]
+fn sprite_overflow_hardware_bug() {
+    // After 8 sprites are found, `m` is incremented along with `n`,
+    // so the tile number of sprite 10 is treated as a Y coordinate
+    let mut sprites = vec![[0x20, 0x01, 0x00, 0x10]; 8];
+    sprites.push([0xf0, 0xf0, 0x00, 0x10]);
+    sprites.push([0xf0, 0x20, 0x00, 0x10]);
+    let mut ppu = sprite_ppu(&sprites);
+    run_to(&mut ppu, 0x20, 258);
+    assert_eq!(ppu.sprite_count, 8);
+    assert!(ppu.status.contains(PpuStatus::SPRITE_OVERFLOW));
+
+    // And a real 10th sprite can be missed because `m` points to its X coordinate
+    let mut sprites = vec![[0x20, 0x01, 0x00, 0x10]; 8];
+    sprites.push([0xf0, 0xf0, 0x00, 0x10]);
+    sprites.push([0x20, 0x01, 0x00, 0xf0]);
+    let mut ppu = sprite_ppu(&sprites);
+    run_to(&mut ppu, 0x20, 258);
+    assert_eq!(ppu.sprite_count, 8);
+    assert!(!ppu.status.contains(PpuStatus::SPRITE_OVERFLOW));
+}
+
+#[test]
+fn oam_data_quirks() {
+    let mut ppu = Ppu::new();
+    let mut cart = nrom();
+    ppu.write_register(0x2003, 0x00, cart.as_mut());
+    for val in [0x12, 0x34, 0xff, 0x56] {
+        ppu.write_register(0x2004, val, cart.as_mut());
+    }
+    ppu.write_register(0x2003, 0x02, cart.as_mut());
+    // Bits 2-4 of the attribute byte read back as 0
+    assert_eq!(ppu.read_register(0x2004, cart.as_mut()), 0xe3);
+
+    // Writes during rendering don't touch OAM but bump OAMADDR by a sprite
+    ppu.mask = PpuMask::SHOW_BG;
+    run_to(&mut ppu, 10, 300);
+    ppu.write_register(0x2003, 0x05, cart.as_mut());
+    ppu.write_register(0x2004, 0xaa, cart.as_mut());
+    assert_eq!(ppu.oam_addr, 0x09);
+    assert_eq!(ppu.oam[0x05], 0x00);
+}
+
+#[test]
+fn oam_row_corruption() {
+    let mut ppu = Ppu::new();
+    let mut cart = nrom();
+    for (i, byte) in ppu.oam.iter_mut().enumerate() {
+        *byte = i as u8;
+    }
+    run_to(&mut ppu, 250, 0);
+    ppu.write_register(0x2003, 0x2b, cart.as_mut());
+    ppu.mask = PpuMask::SHOW_BG;
+    run_to(&mut ppu, 261, 2);
+    assert_eq!(
+        ppu.oam[..8],
+        [0x28, 0x29, 0x2a, 0x2b, 0x2c, 0x2d, 0x2e, 0x2f]
+    );
+    assert_eq!(ppu.oam[8], 0x08);
+}
+
+fn set_vram_addr(ppu: &mut Ppu, cart: &mut dyn Mapper, addr: u16) {
+    ppu.write_register(0x2006, (addr >> 8) as u8, cart);
+    ppu.write_register(0x2006, addr as u8, cart);
+}
+
+#[test]
+fn ppu_data_read_buffer() {
+    let mut ppu = Ppu::new();
+    let mut cart = nrom();
+    set_vram_addr(&mut ppu, cart.as_mut(), 0x2000);
+    for val in [0x11, 0x22, 0x33] {
+        ppu.write_register(0x2007, val, cart.as_mut());
+    }
+    // Each read returns the byte fetched by the previous one
+    set_vram_addr(&mut ppu, cart.as_mut(), 0x2000);
+    let reads: Vec<u8> = (0..4)
+        .map(|_| ppu.read_register(0x2007, cart.as_mut()))
+        .collect();
+    assert_eq!(reads, [0x00, 0x11, 0x22, 0x33]);
+
+    // Increments by 32 going down a column
+    ppu.write_register(0x2000, 0x04, cart.as_mut());
+    set_vram_addr(&mut ppu, cart.as_mut(), 0x2001);
+    ppu.write_register(0x2007, 0x44, cart.as_mut());
+    ppu.write_register(0x2007, 0x55, cart.as_mut());
+    assert_eq!(ppu.vram_addr, 0x2041);
+    assert_eq!(ppu.vram[0x21], 0x55);
+}
+
+#[test]
+fn ppu_data_palette_reads() {
+    let mut ppu = Ppu::new();
+    let mut cart = nrom();
+    set_vram_addr(&mut ppu, cart.as_mut(), 0x2f00);
+    ppu.write_register(0x2007, 0x99, cart.as_mut());
+    set_vram_addr(&mut ppu, cart.as_mut(), 0x3f10);
+    ppu.write_register(0x2007, 0xe7, cart.as_mut());
+
+    // Sprite palette 0's backdrop mirrors the background's, and palette
+    // reads come straight back while the buffer gets the nametable below
+    set_vram_addr(&mut ppu, cart.as_mut(), 0x3f00);
+    assert_eq!(ppu.peek_register(0x2007), 0x27);
+    assert_eq!(ppu.read_register(0x2007, cart.as_mut()), 0x27);
+    assert_eq!(ppu.read_buffer, 0x99);
+
+    ppu.mask = PpuMask::GREYSCALE;
+    set_vram_addr(&mut ppu, cart.as_mut(), 0x3f10);
+    assert_eq!(ppu.read_register(0x2007, cart.as_mut()), 0x20);
+}
+
+#[test]
+fn open_bus_decay() {
+    let mut ppu = Ppu::new();
+    let mut cart = nrom();
+    ppu.write_register(0x2003, 0xff, cart.as_mut());
+    assert_eq!(ppu.read_register(0x2000, cart.as_mut()), 0xff);
+
+    // $2002 drives only its 3 flags, the rest keeps decaying from the write
+    ppu.frame = 30;
+    assert_eq!(ppu.read_register(0x2002, cart.as_mut()), 0x1f);
+    ppu.frame = 35;
+    assert_eq!(ppu.read_register(0x2005, cart.as_mut()), 0x1f);
+    ppu.frame = 36;
+    assert_eq!(ppu.read_register(0x2005, cart.as_mut()), 0x00);
+
+    ppu.open_bus_decay = None;
+    assert_eq!(ppu.peek_register(0x2006), 0x1f);
+}
+
+/// NROM with tile 1 all color 1 and tile 2 all color 3
+fn tile_cart() -> Box<dyn Mapper> {
+    let mut ines = common::ines(&[]);
+    let chr = 16 + 0x4000;
+    ines[chr + 0x10..chr + 0x18].fill(0xff);
+    ines[chr + 0x20..chr + 0x30].fill(0xff);
+    mapper::new(Rom::new(&ines).unwrap())
+}
+
+fn step_to(ppu: &mut Ppu, cart: &mut dyn Mapper, scanline: u16, dot: u16) {
+    while ppu.scanline != scanline || ppu.dot != dot {
+        ppu.tick(cart);
+    }
+}
+
+#[test]
+fn scanline_rendering() {
+    let mut cart = tile_cart();
+    let mut ppu = Ppu::new();
+    ppu.render_mode = RenderMode::Scanline;
+    ppu.mask = PpuMask::SHOW_BG
+        | PpuMask::SHOW_BG_LEFT
+        | PpuMask::SHOW_SPRITES
+        | PpuMask::SHOW_SPRITES_LEFT;
+    ppu.palette[0x00] = 0x0f;
+    ppu.palette[0x01] = 0x30;
+    ppu.palette[0x13] = 0x27;
+    // Tile 1 in the first and third columns, and across the second row
+    ppu.vram[0] = 1;
+    ppu.vram[2] = 1;
+    ppu.vram[32..64].fill(1);
+    // Sprite 0 over the third column, on lines 1-8
+    ppu.oam = [0xff; 0x100];
+    ppu.oam[..4].copy_from_slice(&[0x00, 0x02, 0x00, 0x10]);
+
+    // Through a pre-render line, which sets up the scroll
+    step_to(&mut ppu, cart.as_mut(), 261, 0);
+    step_to(&mut ppu, cart.as_mut(), 1, 258);
+    let line = &ppu.picture[PICTURE_WIDTH..PICTURE_WIDTH * 2];
+    assert_eq!(line[..8], [0x30; 8]);
+    assert_eq!(line[8..16], [0x0f; 8]);
+    assert_eq!(line[16..24], [0x27; 8]);
+    assert_eq!(line[24], 0x0f);
+    assert!(ppu.status.contains(PpuStatus::SPRITE_ZERO_HIT));
+    assert_eq!(ppu.split_lines, 0);
+
+    // Turning rendering off halfway splits the line there
+    step_to(&mut ppu, cart.as_mut(), 10, 100);
+    ppu.write_register(0x2001, 0x00, cart.as_mut());
+    step_to(&mut ppu, cart.as_mut(), 11, 258);
+    let line = &ppu.picture[PICTURE_WIDTH * 10..PICTURE_WIDTH * 11];
+    assert_eq!(line[..99], [0x30; 99]);
+    assert_eq!(line[99..], [0x0f; 157]);
+    assert_eq!(ppu.picture[PICTURE_WIDTH * 11 + 5], 0x0f);
+    assert_eq!(ppu.split_lines, 1);
+
+    // Scrolled right by 12 pixels, the third column starts at 4
+    ppu.mask = PpuMask::SHOW_BG | PpuMask::SHOW_BG_LEFT;
+    ppu.write_register(0x2005, 12, cart.as_mut());
+    step_to(&mut ppu, cart.as_mut(), 1, 258);
+    let line = &ppu.picture[PICTURE_WIDTH..PICTURE_WIDTH * 2];
+    assert_eq!(line[..4], [0x0f; 4]);
+    assert_eq!(line[4..12], [0x30; 8]);
+    assert_eq!(line[12], 0x0f);
+
+    // Nothing is drawn without a render mode
+    let mut ppu = Ppu::new();
+    ppu.palette[0] = 0x0f;
+    step_to(&mut ppu, cart.as_mut(), 1, 258);
+    assert!(ppu.picture.iter().all(|&color| color == 0));
+}
+
+#[test]
+fn dot_rendering() {
+    let mut cart = tile_cart();
+    for mode in [RenderMode::Scanline, RenderMode::Dot] {
+        let mut ppu = Ppu::new();
+        ppu.render_mode = mode;
+        ppu.mask = PpuMask::SHOW_BG | PpuMask::SHOW_BG_LEFT;
+        ppu.palette[0x00] = 0x0f;
+        ppu.palette[0x01] = 0x30;
+        step_to(&mut ppu, cart.as_mut(), 261, 0);
+
+        // The nametable changing under a line only shows from there a dot
+        // at a time, drawing a line at once sees the change everywhere
+        step_to(&mut ppu, cart.as_mut(), 30, 100);
+        ppu.vram[96..128].fill(1);
+        step_to(&mut ppu, cart.as_mut(), 31, 0);
+        let line = &ppu.picture[PICTURE_WIDTH * 30..PICTURE_WIDTH * 31];
+        match mode {
+            RenderMode::Dot => assert_eq!(line[..99], [0x0f; 99]),
+            _ => assert_eq!(line[..99], [0x30; 99]),
+        }
+        assert_eq!(line[99..], [0x30; 157]);
+
+        // After a write in the middle, the scanline renderer goes on a dot
+        // at a time too, and the line counts as split
+        step_to(&mut ppu, cart.as_mut(), 40, 100);
+        ppu.write_register(0x2001, 0x0a, cart.as_mut());
+        ppu.vram[160..192].fill(1);
+        step_to(&mut ppu, cart.as_mut(), 41, 0);
+        let line = &ppu.picture[PICTURE_WIDTH * 40..PICTURE_WIDTH * 41];
+        assert_eq!(line[..99], [0x0f; 99]);
+        assert_eq!(line[99..], [0x30; 157]);
+        let split = (mode == RenderMode::Scanline) as u64;
+        assert_eq!(ppu.split_lines, split);
+    }
+}
+
+#[test]
+fn unlimited_sprites_are_drawn() {
+    let mut cart = tile_cart();
+    let mut ppu = Ppu::new();
+    ppu.render_mode = RenderMode::Scanline;
+    ppu.mask = PpuMask::SHOW_SPRITES | PpuMask::SHOW_SPRITES_LEFT;
+    ppu.palette[0x00] = 0x0f;
+    ppu.palette[0x13] = 0x27;
+    // 10 sprites side by side on lines 21-28
+    ppu.oam = [0xff; 0x100];
+    for n in 0..10 {
+        ppu.oam[n * 4..n * 4 + 4].copy_from_slice(&[20, 0x02, 0x00, n as u8 * 8]);
+    }
+    step_to(&mut ppu, cart.as_mut(), 22, 0);
+    let line = &ppu.picture[PICTURE_WIDTH * 21..PICTURE_WIDTH * 22];
+    assert_eq!(line[..64], [0x27; 64]);
+    assert_eq!(line[64..80], [0x0f; 16]);
+
+    // From the line after the next evaluation
+    ppu.sprite_limit = false;
+    step_to(&mut ppu, cart.as_mut(), 24, 0);
+    let line = &ppu.picture[PICTURE_WIDTH * 23..PICTURE_WIDTH * 24];
+    assert_eq!(line[..80], [0x27; 80]);
+    assert_eq!(line[80], 0x0f);
+}
+
+/// The colors of line 0 in the next frame
+fn next_frame_line(ppu: &mut Ppu, cart: &mut dyn Mapper) -> Vec<u8> {
+    step_to(ppu, cart, 261, 0);
+    step_to(ppu, cart, 1, 0);
+    ppu.picture[..PICTURE_WIDTH].to_vec()
+}
+
+#[test]
+fn tile_cache() {
+    // CHR RAM, with every tile of the screen tile 0
+    let mut ines = vec![0; 16 + 0x4000];
+    ines[..6].copy_from_slice(b"NES\x1a\x01\x00");
+    let mut cart = mapper::new(Rom::new(&ines).unwrap());
+    let mut ppu = Ppu::new();
+    ppu.render_mode = RenderMode::Scanline;
+    ppu.palette[..4].copy_from_slice(&[0x0f, 0x30, 0x00, 0x16]);
+    set_vram_addr(&mut ppu, cart.as_mut(), 0x0000);
+    for _ in 0..8 {
+        ppu.write_register(0x2007, 0xff, cart.as_mut());
+    }
+    set_vram_addr(&mut ppu, cart.as_mut(), 0x0000);
+    ppu.mask = PpuMask::SHOW_BG | PpuMask::SHOW_BG_LEFT;
+    assert_eq!(ppu.tile_cache.hit_rate(), None);
+
+    // The tile is decoded once for the whole screen
+    assert_eq!(
+        next_frame_line(&mut ppu, cart.as_mut()),
+        [0x30; PICTURE_WIDTH]
+    );
+    assert_eq!(ppu.tile_cache.misses, 1);
+    assert!(ppu.tile_cache.hits > 240 * 32);
+    assert_eq!(ppu.tile_cache.len(), 1);
+
+    // Writing CHR RAM decodes it again
+    ppu.write_register(0x2001, 0x00, cart.as_mut());
+    set_vram_addr(&mut ppu, cart.as_mut(), 0x0008);
+    for _ in 0..8 {
+        ppu.write_register(0x2007, 0xff, cart.as_mut());
+    }
+    set_vram_addr(&mut ppu, cart.as_mut(), 0x0000);
+    assert!(ppu.tile_cache.is_empty());
+    ppu.mask = PpuMask::SHOW_BG | PpuMask::SHOW_BG_LEFT;
+    assert_eq!(
+        next_frame_line(&mut ppu, cart.as_mut()),
+        [0x16; PICTURE_WIDTH]
+    );
+    assert_eq!(ppu.tile_cache.misses, 2);
+
+    // CNROM with tile 0 color 1 in the first bank and color 3 in the second
+    let mut ines = vec![0; 16 + 0x4000 + 0x4000];
+    ines[..7].copy_from_slice(b"NES\x1a\x01\x02\x30");
+    ines[16..16 + 0x4000].fill(0xff);
+    let chr = 16 + 0x4000;
+    ines[chr..chr + 0x8].fill(0xff);
+    ines[chr + 0x2000..chr + 0x2010].fill(0xff);
+    let mut cart = mapper::new(Rom::new(&ines).unwrap());
+    ppu.tile_cache = Default::default();
+    assert_eq!(
+        next_frame_line(&mut ppu, cart.as_mut()),
+        [0x30; PICTURE_WIDTH]
+    );
+    // Switching banks picks other tiles, and the ones switched out stay
+    cart.cpu_write(0x8000, 1);
+    assert_eq!(
+        next_frame_line(&mut ppu, cart.as_mut()),
+        [0x16; PICTURE_WIDTH]
+    );
+    cart.cpu_write(0x8000, 0);
+    assert_eq!(
+        next_frame_line(&mut ppu, cart.as_mut()),
+        [0x30; PICTURE_WIDTH]
+    );
+    assert_eq!(ppu.tile_cache.misses, 2);
+    assert_eq!(ppu.tile_cache.len(), 2);
+    assert!(ppu.tile_cache.hit_rate().unwrap() > 0.99);
+}
@@ -0,0 +1,88 @@
+use nes::Bus;
+use nes::console::Console;
+use nes::register_log::RegisterWrite;
+use nes::rom::{Region, Rom};
+
+mod common;
+
+/// Plays a square wave from reset
+fn test_console() -> Console {
+    // LDA #$01; STA $4015; LDA #$bf; STA $4000; LDA #$80; STA $4002; STA $4003; JMP *
+    let code = [
+        0xa9, 0x01, 0x8d, 0x15, 0x40, 0xa9, 0xbf, 0x8d, 0x00, 0x40, 0xa9, 0x80, 0x8d, 0x02, 0x40,
+        0x8d, 0x03, 0x40, 0x4c, 0x12, 0xc0,
+    ];
+    Console::new(common::nrom(&[(0xc000, &code)]))
+}
+
+#[test]
+fn apu_writes() {
+    let mut console = test_console();
+    let bus = console.bus_mut();
+    // Nothing is logged until started
+    bus.write(0x4008, 0xff);
+    let start = bus.cycles;
+    bus.register_log.start(start);
+    console.run_frame();
+    let bus = console.bus_mut();
+    bus.register_log.stop(bus.cycles);
+    let frame = bus.cycles - start;
+    // Not a sound register
+    bus.write(0x4016, 1);
+
+    let log = &bus.register_log;
+    let writes: Vec<(u16, u8)> = log.writes().iter().map(|w| (w.addr, w.val)).collect();
+    assert_eq!(
+        writes,
+        [
+            (0x4015, 0x01),
+            (0x4000, 0xbf),
+            (0x4002, 0x80),
+            (0x4003, 0x80)
+        ]
+    );
+    // LDA #$bf and STA $4000 after STA $4015
+    let cycles: Vec<u64> = log.writes().iter().map(|w| w.cycle).collect();
+    assert_eq!(cycles[1] - cycles[0], 6);
+    assert!(
+        log.to_text()
+            .starts_with(&format!("{} $4015 $01\n", cycles[0]))
+    );
+
+    let vgm = log.to_vgm(Region::Ntsc);
+    assert_eq!(&vgm[..4], b"Vgm ");
+    let header_u32 =
+        |offset: usize| u32::from_le_bytes(vgm[offset..offset + 4].try_into().unwrap());
+    assert_eq!(header_u32(0x04) as usize, vgm.len() - 4);
+    assert_eq!(header_u32(0x84), 1_789_773);
+    assert_eq!(header_u32(0x18) as u64, frame * 44100 / 1_789_773);
+    let data = &vgm[0x34 + header_u32(0x34) as usize..];
+    assert_eq!(&data[..6], [0xb4, 0x15, 0x01, 0xb4, 0x00, 0xbf]);
+    assert_eq!(data.last(), Some(&0x66));
+}
+
+#[test]
+fn sound_chip_writes() {
+    // N163
+    let mut ines = vec![0; 16];
+    ines[..8].copy_from_slice(b"NES\x1a\x02\x02\x30\x10");
+    ines.resize(16 + 0x8000 + 0x4000, 0);
+    let mut bus = Bus::new(Rom::new(&ines).unwrap());
+    bus.register_log.start(bus.cycles);
+    bus.write(0xF800, 0x80);
+    bus.write(0x8000, 1);
+    bus.write(0x4800, 0x42);
+    bus.register_log.stop(bus.cycles);
+    let at_start = |addr, val| RegisterWrite {
+        cycle: 0,
+        addr,
+        val,
+    };
+    assert_eq!(
+        bus.register_log.writes(),
+        [at_start(0xF800, 0x80), at_start(0x4800, 0x42)]
+    );
+    // VGM has nowhere to put them
+    let vgm = bus.register_log.to_vgm(Region::Ntsc);
+    assert_eq!(&vgm[0x100..], [0x66]);
+}
@@ -0,0 +1,93 @@
+#![cfg(feature = "romdb")]
+
+use nes::rom::{Mirroring, Rom};
+use nes::romdb::{HeaderOverride, RomDb, rom_crc};
+
+mod common;
+
+fn ines() -> Vec<u8> {
+    // Mapper 0, horizontal mirroring, no PRG RAM size given
+    common::ines(&[(0xc000, &[0x42])])
+}
+
+#[test]
+fn header_overrides() {
+    let mut rom = Rom::from_ines(&ines()).unwrap();
+    assert_eq!(rom.prg_ram_size, 0x2000);
+    let db = RomDb::parse(&format!(
+        "# comment\n{:08X},2,0,V,0,Test Cart\n",
+        rom_crc(&rom)
+    ))
+    .unwrap();
+    assert_eq!(db.get(rom_crc(&rom)).unwrap().name, "Test Cart");
+
+    db.apply(&mut rom);
+    assert_eq!(rom.mapper, 2);
+    assert_eq!(rom.mirroring, Mirroring::Vertical);
+    assert_eq!(rom.prg_ram_size, 0);
+    assert_eq!(
+        rom.overrides,
+        [
+            HeaderOverride::Mapper { header: 0, db: 2 },
+            HeaderOverride::Mirroring {
+                header: Mirroring::Horizontal,
+                db: Mirroring::Vertical
+            },
+            HeaderOverride::PrgRamSize {
+                header: 0x2000,
+                db: 0
+            },
+        ]
+    );
+}
+
+#[test]
+fn unknown_rom() {
+    // Not in the embedded database, the header is kept
+    let rom = Rom::new(&ines()).unwrap();
+    assert!(rom.overrides.is_empty());
+    assert_eq!(rom.mirroring, Mirroring::Horizontal);
+    assert!(RomDb::parse("1234,x,0,V,0,Bad").is_err());
+}
+
+#[test]
+fn unknown_fields_keep_the_header() {
+    let mut rom = Rom::from_ines(&ines()).unwrap();
+    rom.submapper = 2;
+    let db = RomDb::parse(&format!("{:08X},0,-,-,8,Test Cart\n", rom_crc(&rom))).unwrap();
+    db.apply(&mut rom);
+    assert_eq!(rom.submapper, 2);
+    assert_eq!(rom.mirroring, Mirroring::Horizontal);
+    assert!(rom.overrides.is_empty());
+}
+
+/// Stands in for a dump of Super Mario Bros. whose header says MMC1 with
+/// horizontal mirroring, as the game itself can't be checked in. It's all
+/// zeros but for the last 4 bytes, chosen to give it the game's CRC.
+fn bad_header_smb() -> Vec<u8> {
+    let mut ines = vec![0; 16 + 0x8000 + 0x2000];
+    ines[..7].copy_from_slice(b"NES\x1a\x02\x01\x10");
+    let end = ines.len();
+    ines[end - 4..].copy_from_slice(&[0xc0, 0xdb, 0x28, 0xbd]);
+    ines
+}
+
+#[test]
+fn embedded_database_fixes_bad_header() {
+    let rom = Rom::new(&bad_header_smb()).unwrap();
+    assert_eq!(rom_crc(&rom), 0x3337ec46);
+    let entry = RomDb::embedded().get(0x3337ec46).unwrap();
+    assert_eq!(entry.name, "Super Mario Bros.");
+    assert_eq!(rom.mapper, 0);
+    assert_eq!(rom.mirroring, Mirroring::Vertical);
+    assert_eq!(
+        rom.overrides[..2],
+        [
+            HeaderOverride::Mapper { header: 1, db: 0 },
+            HeaderOverride::Mirroring {
+                header: Mirroring::Horizontal,
+                db: Mirroring::Vertical
+            },
+        ]
+    );
+}
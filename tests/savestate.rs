@@ -0,0 +1,330 @@
+use nes::console::Console;
+use nes::error::StateError;
+use nes::rom::Rom;
+use nes::savestate::{
+    Autosave, BatterySave, SLOT_COUNT, SaveSlots, SaveState, StateVersionError, Thumbnail, VERSION,
+};
+
+mod common;
+
+/// UxROM with CHR RAM. The main loop counts in $12, the NMI handler counts
+/// frames in $11 and plays a note whose period follows the frame count.
+fn test_console(seed: u8) -> Console {
+    let mut ines = common::ines(&[
+        // LDA #$80; STA $2000; LDA #$01; STA $4015; INC $12; JMP *-2
+        (
+            0xc000,
+            &[
+                0xa9, 0x80, 0x8d, 0x00, 0x20, 0xa9, 0x01, 0x8d, 0x15, 0x40, 0xe6, 0x12, 0x4c, 0x0a,
+                0xc0,
+            ],
+        ),
+        // INC $11; LDA $11; STA $4002; LDA #$bf; STA $4000; STA $4003; RTI
+        (
+            0xd000,
+            &[
+                0xe6, 0x11, 0xa5, 0x11, 0x8d, 0x02, 0x40, 0xa9, 0xbf, 0x8d, 0x00, 0x40, 0x8d, 0x03,
+                0x40, 0x40,
+            ],
+        ),
+        (0xe000, &[seed]),
+        (0xfffa, &[0x00, 0xd0, 0x00, 0xc0, 0x00, 0xc0]),
+    ]);
+    ines[5..7].copy_from_slice(&[0x00, 0x20]);
+    ines.truncate(16 + 0x4000);
+    Console::new(Rom::new(&ines).unwrap())
+}
+
+/// Everything observable that should come back after loading
+fn snapshot(console: &Console) -> (u16, u64, u64, u16, [u8; 0x800], u8) {
+    let bus = console.bus();
+    (
+        console.cpu.pc,
+        bus.cycles,
+        bus.ppu.frame,
+        bus.ppu.dot,
+        bus.cpu_ram,
+        bus.apu.peek_status(),
+    )
+}
+
+#[test]
+fn round_trip() {
+    let mut console = test_console(0);
+    for _ in 0..5 {
+        console.run_frame();
+    }
+    console.run_to_scanline(100);
+    let state = console.save_state(None);
+    assert_eq!(state.frame, 5);
+    assert_eq!(state.rom_hash, console.rom_hash());
+
+    let mut expected = vec![];
+    for _ in 0..10 {
+        console.run_frame();
+        expected.push(snapshot(&console));
+    }
+
+    console.load_state(&state).unwrap();
+    assert_eq!(console.bus().ppu.frame, 5);
+    let mut replayed = vec![];
+    for _ in 0..10 {
+        console.run_frame();
+        replayed.push(snapshot(&console));
+    }
+    assert_eq!(replayed, expected);
+
+    // Also through the file format
+    let state = SaveState::from_bytes(&state.to_bytes()).unwrap();
+    console.load_state(&state).unwrap();
+    console.run_frame();
+    assert_eq!(snapshot(&console), expected[0]);
+}
+
+#[test]
+fn triggered_interrupts() {
+    let mut console = test_console(0);
+    console.run_frame();
+    console.cpu.trigger_nmi();
+    let state = console.save_state(None);
+    console.step();
+    assert_eq!(console.cpu.pc, 0xd000);
+
+    let mut loaded = test_console(0);
+    loaded.load_state(&state).unwrap();
+    loaded.step();
+    assert_eq!(loaded.cpu.pc, 0xd000);
+}
+
+#[test]
+fn refuses_other_games_and_corrupt_states() {
+    let mut console = test_console(0);
+    console.run_frame();
+    let state = console.save_state(None);
+
+    let mut other = test_console(1);
+    assert_ne!(other.rom_hash(), console.rom_hash());
+    assert!(other.load_state(&state).is_err());
+
+    let bytes = state.to_bytes();
+    assert!(SaveState::from_bytes(&bytes[..bytes.len() - 1]).is_err());
+    assert!(SaveState::from_bytes(b"not a state").is_err());
+}
+
+#[test]
+fn slots() {
+    let dir = std::env::temp_dir().join(format!("nes-savestate-slots-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let slots = SaveSlots::new(&dir.join("game.nes"));
+
+    let mut console = test_console(0);
+    for _ in 0..3 {
+        console.run_frame();
+    }
+    let thumbnail = Thumbnail {
+        width: 2,
+        height: 1,
+        pixels: vec![0xff0000, 0x00ff00],
+    };
+    slots
+        .save(3, &console.save_state(Some(thumbnail.clone())))
+        .unwrap();
+    assert_eq!(slots.path(3), dir.join("game.state3"));
+
+    let list = slots.list();
+    assert_eq!(list.len(), SLOT_COUNT);
+    for (slot, state) in list.iter().enumerate() {
+        assert_eq!(state.is_some(), slot == 3);
+    }
+    let state = list[3].as_ref().unwrap();
+    assert_eq!(state.frame, 3);
+    assert_eq!(state.thumbnail.as_ref(), Some(&thumbnail));
+    assert!(state.timestamp > 0);
+
+    console.run_frame();
+    console
+        .load_state(&slots.load(3).unwrap().unwrap())
+        .unwrap();
+    assert_eq!(console.bus().ppu.frame, 3);
+
+    slots.delete(3).unwrap();
+    assert!(slots.load(3).unwrap().is_none());
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn autosave() {
+    let dir = std::env::temp_dir().join(format!("nes-savestate-autosave-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let mut autosave = Autosave::new(&dir.join("game.nes"), 10);
+    assert!(autosave.load().unwrap().is_none());
+
+    let mut console = test_console(0);
+    for _ in 0..25 {
+        console.run_frame();
+        autosave.tick(&mut console).unwrap();
+    }
+    assert_eq!(autosave.path(), dir.join("game.autosave"));
+    // Counted from the first tick at frame 1
+    assert_eq!(autosave.load().unwrap().unwrap().frame, 21);
+
+    console.run_frame();
+    autosave.save_now(&mut console).unwrap();
+    let state = autosave.load().unwrap().unwrap();
+    assert_eq!(state.frame, 26);
+
+    let mut resumed = test_console(0);
+    resumed.load_state(&state).unwrap();
+    assert_eq!(resumed.bus().cpu_ram, console.bus().cpu_ram);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn battery_save() {
+    let dir = std::env::temp_dir().join(format!("nes-savestate-battery-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let battery = BatterySave::new(&dir.join("game.nes"));
+    assert_eq!(battery.path(), dir.join("game.sav"));
+
+    // MMC3 with a battery
+    let mmc3 = |battery: bool| {
+        let mut ines = vec![0; 16 + 0x8000 + 0x2000];
+        ines[..7].copy_from_slice(b"NES\x1a\x02\x01\x40");
+        ines[6] |= (battery as u8) << 1;
+        Console::new(Rom::new(&ines).unwrap())
+    };
+    let mut console = mmc3(true);
+    assert!(!battery.load(&mut console).unwrap());
+    console.bus_mut().write(0x6123, 0x45);
+    assert!(battery.save(&console).unwrap());
+
+    let mut console = mmc3(true);
+    assert!(battery.load(&mut console).unwrap());
+    assert_eq!(console.bus().peek(0x6123), 0x45);
+
+    // Nothing to keep without one
+    assert!(!battery.save(&mmc3(false)).unwrap());
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn mapper_registers() {
+    // MMC3 with each 8K PRG bank filled with its number, looping at $E000
+    let mut ines = vec![0; 16];
+    ines[..7].copy_from_slice(b"NES\x1a\x04\x00\x42");
+    for bank in 0..8 {
+        ines.extend([bank; 0x2000]);
+    }
+    let end = ines.len();
+    ines[end - 0x2000..end - 0x1ffd].copy_from_slice(&[0x4c, 0x00, 0xe0]);
+    ines[end - 4..end - 2].copy_from_slice(&[0x00, 0xe0]);
+    let mut console = Console::new(Rom::new(&ines).unwrap());
+    let bus = console.bus_mut();
+    for (reg, bank) in [(6, 3), (7, 5)] {
+        bus.write(0x8000, reg);
+        bus.write(0x8001, bank);
+    }
+    bus.write(0x6000, 0x42);
+    bus.write(0xC000, 2);
+    bus.write(0xC001, 0);
+    bus.write(0xE001, 0);
+    bus.mapper.a12_rise();
+    let state = console.save_state(None);
+
+    let bus = console.bus_mut();
+    bus.write(0x8000, 6);
+    bus.write(0x8001, 1);
+    bus.write(0x6000, 0);
+    bus.write(0xE000, 0);
+    console.load_state(&state).unwrap();
+
+    let bus = console.bus_mut();
+    assert_eq!((bus.peek(0x8000), bus.peek(0xA000)), (3, 5));
+    assert_eq!(bus.peek(0x6000), 0x42);
+    // Reloaded with 2 before saving, fires 2 scanlines later
+    bus.mapper.a12_rise();
+    assert!(!bus.mapper.irq());
+    bus.mapper.a12_rise();
+    assert!(bus.mapper.irq());
+}
+
+/// NROM that sets pulse 1 playing, then counts in $10. It leaves the PPU
+/// alone, so nothing added to the PPU's state since version 1 changes.
+fn apu_console() -> Console {
+    // LDA #$0f; STA $4015; LDA #$bf; STA $4000; LDA #$40; STA $4002
+    // LDA #$09; STA $4003; LDA #$00; STA $4017; INC $10; JMP *-2
+    let code = [
+        0xa9, 0x0f, 0x8d, 0x15, 0x40, 0xa9, 0xbf, 0x8d, 0x00, 0x40, 0xa9, 0x40, 0x8d, 0x02, 0x40,
+        0xa9, 0x09, 0x8d, 0x03, 0x40, 0xa9, 0x00, 0x8d, 0x17, 0x40, 0xe6, 0x10, 0x4c, 0x19, 0xc0,
+    ];
+    Console::new(common::nrom(&[(0xc000, &code)]))
+}
+
+/// Where the states in tests/states were saved: partway through frame 3,
+/// right after switching the frame counter to 5 steps
+fn run_to_saved_point(console: &mut Console) {
+    for _ in 0..3 {
+        console.run_frame();
+    }
+    console.run_to_scanline(100);
+    console.bus_mut().write(0x4017, 0x80);
+}
+
+#[test]
+fn migrates_old_versions() {
+    let mut current = apu_console();
+    run_to_saved_point(&mut current);
+    let saved_hash = current.state_hash();
+    let mut expected = vec![];
+    for _ in 0..5 {
+        current.run_frame();
+        expected.push((current.state_hash(), current.bus().apu.peek_status()));
+    }
+
+    for (version, bytes) in [
+        (1, &include_bytes!("states/v1.state")[..]),
+        (2, include_bytes!("states/v2.state")),
+        (3, include_bytes!("states/v3.state")),
+        (4, include_bytes!("states/v4.state")),
+        (5, include_bytes!("states/v5.state")),
+        (6, include_bytes!("states/v6.state")),
+        (7, include_bytes!("states/v7.state")),
+        (8, include_bytes!("states/v8.state")),
+    ] {
+        let state = SaveState::from_bytes(bytes).unwrap();
+        assert_eq!(state.version(), version);
+        let mut console = apu_console();
+        console.load_state(&state).unwrap();
+        assert_eq!(console.state_hash(), saved_hash, "version {version}");
+        let mut replayed = vec![];
+        for _ in 0..5 {
+            console.run_frame();
+            replayed.push((console.state_hash(), console.bus().apu.peek_status()));
+        }
+        assert_eq!(replayed, expected, "version {version}");
+
+        // Saving again keeps the old layout the data is in
+        let resaved = SaveState::from_bytes(&state.to_bytes()).unwrap();
+        assert_eq!(resaved.version(), version);
+    }
+}
+
+#[test]
+fn refuses_unsupported_versions() {
+    let mut console = apu_console();
+    let mut bytes = console.save_state(None).to_bytes();
+    for version in [0, VERSION + 1] {
+        bytes[4] = version;
+        let err = SaveState::from_bytes(&bytes).unwrap_err();
+        assert_eq!(
+            err,
+            StateError::Version(StateVersionError { found: version })
+        );
+    }
+    assert!(
+        SaveState::from_bytes(&bytes)
+            .unwrap_err()
+            .to_string()
+            .contains("newer emulator")
+    );
+}
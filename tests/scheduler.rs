@@ -0,0 +1,46 @@
+use nes::savestate::{StateStream, Stateful};
+use nes::scheduler::Scheduler;
+
+#[test]
+fn events_come_due_in_order() {
+    let mut scheduler = Scheduler::new();
+    scheduler.schedule(20, 'c');
+    scheduler.schedule(10, 'b');
+    scheduler.schedule(10, 'a');
+    scheduler.schedule(30, 'd');
+    assert_eq!(scheduler.next_at(), Some(10));
+    assert_eq!(scheduler.pop_due(9), None);
+    // Same cycle, in the events' order
+    assert_eq!(scheduler.pop_due(10), Some('a'));
+    assert_eq!(scheduler.pop_due(10), Some('b'));
+    assert_eq!(scheduler.pop_due(10), None);
+    // Late events still come out
+    scheduler.cancel(|&event| event == 'd');
+    assert_eq!(scheduler.pop_due(50), Some('c'));
+    assert!(scheduler.is_empty());
+}
+
+#[test]
+fn streams_with_state() {
+    let mut scheduler: Scheduler<u8> = Scheduler::new();
+    scheduler.schedule(5, 1);
+    scheduler.schedule(3, 2);
+    let mut s = StateStream::saving();
+    scheduler.stream(&mut s);
+    let data = s.finish().unwrap();
+
+    let mut loaded = Scheduler::new();
+    let mut s = StateStream::loading(data.clone());
+    loaded.stream(&mut s);
+    s.finish().unwrap();
+    assert_eq!(loaded, scheduler);
+    assert_eq!(loaded.iter().collect::<Vec<_>>(), [(3, &2), (5, &1)]);
+
+    // Events out of order can only come from a corrupt state
+    let mut swapped = data[..4].to_vec();
+    swapped.extend_from_slice(&data[13..]);
+    swapped.extend_from_slice(&data[4..13]);
+    let mut s = StateStream::loading(swapped);
+    Scheduler::<u8>::new().stream(&mut s);
+    assert!(s.finish().is_err());
+}
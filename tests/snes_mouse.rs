@@ -0,0 +1,53 @@
+use nes::Bus;
+use nes::joypad::SnesMouse;
+
+mod common;
+
+fn bus() -> Bus {
+    let mut bus = Bus::new(common::nrom(&[]));
+    bus.ports[0] = Some(Box::new(SnesMouse::new()));
+    bus
+}
+
+/// Strobe and read the 32 bit report from $4016
+fn report(bus: &mut Bus) -> u32 {
+    bus.write(0x4016, 1);
+    bus.write(0x4016, 0);
+    (0..32).fold(0, |report, _| report << 1 | (bus.read(0x4016) & 1) as u32)
+}
+
+#[test]
+fn report_format() {
+    let mut bus = bus();
+    assert_eq!(report(&mut bus), 0x0001_0000);
+
+    let mouse = bus.port_mut::<SnesMouse>(0).unwrap();
+    mouse.dx = -5;
+    mouse.dy = 300;
+    mouse.left = true;
+    // Buttons and signature, down 127 (clamped), left 5
+    assert_eq!(report(&mut bus), 0x0041_7f85);
+    // Movement is counted from the last strobe
+    assert_eq!(report(&mut bus), 0x0041_0000);
+    // Then 1s, like a controller
+    assert_eq!(bus.read(0x4016) & 1, 1);
+    // The other port still has a controller
+    assert_eq!(bus.read(0x4017) & 1, 0);
+}
+
+#[test]
+fn sensitivity() {
+    let mut bus = bus();
+    for expected in [1, 2, 0] {
+        bus.write(0x4016, 1);
+        bus.read(0x4016);
+        bus.write(0x4016, 0);
+        assert_eq!(
+            bus.port_mut::<SnesMouse>(0).unwrap().sensitivity(),
+            expected
+        );
+    }
+    bus.write(0x4016, 1);
+    bus.read(0x4016);
+    assert_eq!(report(&mut bus) >> 20 & 0b11, 1);
+}
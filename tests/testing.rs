@@ -0,0 +1,79 @@
+use nes::console::Console;
+use nes::ppu::RenderMode;
+use nes::rom::Rom;
+use nes::testing::TestScript;
+
+mod common;
+
+/// Reads controller 1 into $10 and counts frames in $11 on every NMI
+fn rom() -> Rom {
+    common::nrom(&[
+        // LDA #$80; STA $2000; JMP *
+        (0xc000, &[0xa9, 0x80, 0x8d, 0x00, 0x20, 0x4c, 0x05, 0xc0]),
+        (
+            0xd000,
+            &[
+                // Strobe: LDA #1; STA $4016; LDA #0; STA $4016; LDX #8
+                0xa9, 0x01, 0x8d, 0x16, 0x40, 0xa9, 0x00, 0x8d, 0x16, 0x40, 0xa2, 0x08,
+                // LDA $4016; LSR A; ROL $10; DEX; BNE -9
+                0xad, 0x16, 0x40, 0x4a, 0x26, 0x10, 0xca, 0xd0, 0xf7, // INC $11; RTI
+                0xe6, 0x11, 0x40,
+            ],
+        ),
+        (0xfffa, &[0x00, 0xd0, 0x00, 0xc0, 0x00, 0xc0]),
+    ])
+}
+
+#[test]
+fn passing_script() {
+    let mut console = Console::new(rom());
+    console.bus_mut().ppu.render_mode = RenderMode::Scanline;
+    for _ in 0..20 {
+        console.run_frame();
+    }
+    let hash = console.frame_hash();
+
+    let script = TestScript::parse(&format!(
+        "
+        # Start is the 4th bit shifted in, so it ends up in bit 4
+        5 input start
+        8 memory $10 $10
+        8 input a right
+        10 expect [$10] == $81 && [$11] >= 8
+        12 input none
+        15 pc $D013
+        20 hash ${hash:08X}
+        "
+    ))
+    .unwrap();
+    script.run(rom()).unwrap();
+}
+
+#[test]
+fn failing_script() {
+    let script = TestScript::parse(
+        "
+        10 memory $11 $00
+        10 expect [$10] != 0
+        10 pc $1234
+        10 hash $0
+        10 state $0
+        ",
+    )
+    .unwrap();
+    let failures = script.run(rom()).unwrap_err();
+    assert_eq!(failures.len(), 5);
+    assert_eq!(failures[0], "frame 10: $0011 is $0A, not $00");
+    assert_eq!(failures[2], "frame 10: PC never reached $1234");
+
+    for bad in [
+        "input start",
+        "5 press a",
+        "5 input turbo",
+        "5 memory $10",
+        "5 memory $10 $100",
+        "x pc 0",
+    ] {
+        assert!(TestScript::parse(bad).is_err(), "{bad:?}");
+    }
+}
@@ -0,0 +1,108 @@
+use nes::Bus;
+use nes::joypad::Buttons;
+use nes::triggers::{Macro, Trigger, Triggers};
+
+mod common;
+
+const SCRIPT: &str = "
+# Through the title screen
+600 press start
+1000-1200 hold right
+1100-1110 hold a b   # jump along the way
+1300 press 2 select
+";
+
+#[test]
+fn parse() {
+    let triggers = Triggers::parse(SCRIPT).unwrap();
+    assert_eq!(triggers.triggers().len(), 4);
+    assert_eq!(
+        triggers.triggers()[3],
+        Trigger {
+            frames: 1300..=1300,
+            port: 1,
+            buttons: Buttons::SELECT,
+        }
+    );
+    assert_eq!(triggers.buttons(0, 599), Buttons::empty());
+    assert_eq!(triggers.buttons(0, 600), Buttons::START);
+    assert_eq!(triggers.buttons(0, 601), Buttons::empty());
+    assert_eq!(
+        triggers.buttons(0, 1105),
+        Buttons::RIGHT | Buttons::A | Buttons::B
+    );
+    assert_eq!(triggers.buttons(0, 1200), Buttons::RIGHT);
+    assert_eq!(triggers.buttons(0, 1300), Buttons::empty());
+    assert_eq!(triggers.buttons(1, 1300), Buttons::SELECT);
+    assert_eq!(triggers.last_frame(), Some(1300));
+}
+
+#[test]
+fn parse_errors() {
+    for (line, err) in [
+        ("600", "line 1: missing press or hold"),
+        ("600 tap a", "line 1: unknown command \"tap\""),
+        ("600-700 press a", "line 1: press takes a single frame"),
+        ("700-600 hold a", "line 1: frames end before they start"),
+        ("x press a", "line 1: invalid frame \"x\""),
+        ("600 press 3 a", "line 1: no controller 3"),
+        ("600 press", "line 1: missing buttons"),
+        ("600 press turbo", "line 1: unknown button \"turbo\""),
+    ] {
+        assert_eq!(Triggers::parse(line).unwrap_err(), err, "{line}");
+    }
+}
+
+#[test]
+fn apply() {
+    let mut bus = Bus::new(common::nrom(&[]));
+    let mut triggers = Triggers::new();
+    triggers.press(2, Buttons::START);
+    triggers.hold(2..=3, Buttons::A);
+
+    // Buttons held some other way stay held
+    bus.joypads[0].buttons = Buttons::B;
+    bus.ppu.frame = 2;
+    triggers.apply(&mut bus);
+    assert_eq!(
+        bus.joypads[0].buttons,
+        Buttons::A | Buttons::B | Buttons::START
+    );
+    bus.ppu.frame = 3;
+    triggers.apply(&mut bus);
+    assert_eq!(bus.joypads[0].buttons, Buttons::A | Buttons::B);
+    bus.ppu.frame = 4;
+    triggers.apply(&mut bus);
+    assert_eq!(bus.joypads[0].buttons, Buttons::B);
+}
+
+#[test]
+fn macros() {
+    let mut recorded = Macro::new();
+    recorded.record(0, Buttons::RIGHT);
+    // Frames between calls keep the last buttons
+    recorded.record(3, Buttons::RIGHT | Buttons::A);
+    recorded.record(4, Buttons::empty());
+    recorded.record(5, Buttons::A);
+    recorded.record(6, Buttons::empty());
+    recorded.record(7, Buttons::empty());
+    recorded.trim();
+    assert_eq!(recorded.frames.len(), 6);
+    let text = recorded.to_text();
+    assert_eq!(text, "right*3 a+right - a");
+    assert_eq!(Macro::parse(&text).unwrap(), recorded);
+
+    let mut triggers = Triggers::new();
+    recorded.schedule(&mut triggers, 1, 100);
+    assert_eq!(triggers.buttons(1, 99), Buttons::empty());
+    assert_eq!(triggers.buttons(1, 102), Buttons::RIGHT);
+    assert_eq!(triggers.buttons(1, 103), Buttons::RIGHT | Buttons::A);
+    assert_eq!(triggers.buttons(1, 104), Buttons::empty());
+    assert_eq!(triggers.buttons(1, 105), Buttons::A);
+    assert_eq!(triggers.buttons(0, 105), Buttons::empty());
+    assert_eq!(triggers.last_frame(), Some(105));
+
+    for bad in ["right*0", "right*x", "jump"] {
+        assert!(Macro::parse(bad).is_err(), "{bad}");
+    }
+}
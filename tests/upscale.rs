@@ -0,0 +1,37 @@
+#![cfg(feature = "upscale")]
+
+use nes::video::upscale::{Hq2x, Xbr};
+use nes::video::{Frame, VideoFilter};
+
+/// White above the diagonal, black on and below it
+fn staircase() -> Frame {
+    let pixels = (0..4)
+        .flat_map(|y| (0..4).map(move |x| if x > y { 0xffffff } else { 0 }))
+        .collect();
+    Frame::from_pixels(pixels, 4, 4).unwrap()
+}
+
+#[test]
+fn flat_frames_stay_flat() {
+    let flat = Frame::from_pixels(vec![0x336699; 9], 3, 3).unwrap();
+    assert_eq!(Hq2x.apply(flat.clone()), flat.scale(2));
+    assert_eq!(Xbr::new(2).apply(flat.clone()), flat.scale(2));
+    assert_eq!(Xbr::new(3).apply(flat.clone()), flat.scale(3));
+}
+
+#[test]
+fn diagonal_edges_are_smoothed() {
+    // The top right quarter of the black pixel at (1, 1) is on the edge
+    let hq2x = Hq2x.apply(staircase());
+    assert_eq!((hq2x.width, hq2x.height), (8, 8));
+    assert_eq!(hq2x.row(2)[3], 0x7f7f7f);
+    assert_eq!(hq2x.row(3)[2], 0);
+
+    let xbr = Xbr::new(3).apply(staircase());
+    assert_eq!((xbr.width, xbr.height), (12, 12));
+    let corner = xbr.row(3)[5];
+    assert!(corner != 0 && corner != 0xffffff, "{corner:06x}");
+    // Away from the edge nothing changes
+    assert_eq!(xbr.row(10)[1], 0);
+    assert_eq!(xbr.row(1)[10], 0xffffff);
+}
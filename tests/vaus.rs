@@ -0,0 +1,48 @@
+use nes::Bus;
+use nes::joypad::{Buttons, Vaus};
+
+mod common;
+
+fn bus() -> Bus {
+    Bus::new(common::nrom(&[]))
+}
+
+/// Strobe, then read the dial from `bit` of $4017
+fn read_dial(bus: &mut Bus, bit: u8) -> u8 {
+    bus.write(0x4016, 1);
+    bus.write(0x4016, 0);
+    (0..8).fold(0, |dial, _| (dial << 1) | (bus.read(0x4017) >> bit & 1))
+}
+
+#[test]
+fn nes_port_2() {
+    let mut bus = bus();
+    bus.ports[1] = Some(Box::new(Vaus::new(false)));
+    bus.port_mut::<Vaus>(1).unwrap().position = 0xa5;
+    assert_eq!(read_dial(&mut bus, 4), !0xa5);
+    assert_eq!(bus.read(0x4017) & 0x08, 0);
+
+    bus.port_mut::<Vaus>(1).unwrap().fire = true;
+    assert_eq!(bus.peek(0x4017) & 0x08, 0x08);
+    // Controller 1 still works, controller 2 is unplugged
+    bus.joypads[0].buttons = Buttons::A;
+    bus.joypads[1].buttons = Buttons::A;
+    bus.write(0x4016, 1);
+    assert_eq!(bus.read(0x4016), 1);
+    assert_eq!(bus.read(0x4017) & 1, 0);
+}
+
+#[test]
+fn famicom_expansion_port() {
+    let mut bus = bus();
+    bus.expansion = Some(Box::new(Vaus::new(true)));
+    bus.expansion_mut::<Vaus>().unwrap().position = Vaus::MAX;
+    bus.joypads[1].buttons = Buttons::A;
+    assert_eq!(read_dial(&mut bus, 1), !Vaus::MAX);
+
+    bus.expansion_mut::<Vaus>().unwrap().fire = true;
+    bus.write(0x4016, 1);
+    assert_eq!(bus.read(0x4016), 0b10);
+    // Alongside both controllers
+    assert_eq!(bus.read(0x4017) & 1, 1);
+}
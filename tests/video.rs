@@ -0,0 +1,207 @@
+use nes::rom::Region;
+use nes::video::{
+    BlendMode, FRAME_HEIGHT, FRAME_WIDTH, FilterChain, Frame, FrameBlend, FramePool, NES_PALETTE,
+    Overscan, Scale, VideoFilter,
+};
+
+/// Every pixel is its own coordinates, `y << 8 | x`
+fn numbered_frame() -> Frame {
+    let pixels = (0..FRAME_HEIGHT)
+        .flat_map(|y| (0..FRAME_WIDTH).map(move |x| (y << 8 | x) as u32))
+        .collect();
+    Frame::from_pixels(pixels, FRAME_WIDTH, FRAME_HEIGHT).unwrap()
+}
+
+#[test]
+fn picture_colors() {
+    let mut picture = vec![0x0f; FRAME_WIDTH * FRAME_HEIGHT];
+    picture[1] = 0x30;
+    picture[FRAME_WIDTH] = 0x16;
+    let frame = Frame::from_picture(&picture);
+    assert_eq!((frame.width, frame.height), (FRAME_WIDTH, FRAME_HEIGHT));
+    assert_eq!(frame.row(0)[..3], [0x000000, 0xfffeff, 0x000000]);
+    assert_eq!(frame.row(1)[0], NES_PALETTE[0x16]);
+
+    // Into a frame that had another size
+    let mut reused = Frame::new(32, 32);
+    reused.draw_picture(&picture);
+    assert_eq!(reused, frame);
+}
+
+#[test]
+fn overscan_cropping() {
+    let ntsc = Overscan::for_region(Region::Ntsc);
+    let output = ntsc.apply(numbered_frame());
+    assert_eq!(output.full, numbered_frame());
+    assert_eq!((output.cropped.width, output.cropped.height), (256, 224));
+    assert_eq!(output.cropped.pixels[0], 8 << 8);
+    assert_eq!(*output.cropped.pixels.last().unwrap(), 231 << 8 | 255);
+
+    let sides = Overscan::parse("0,0,8,16").unwrap();
+    let cropped = sides.crop(&numbered_frame());
+    assert_eq!((cropped.width, cropped.height), (232, 240));
+    assert_eq!(cropped.row(1)[0], 1 << 8 | 8);
+    assert_eq!(Overscan::parse("4").unwrap().left, 4);
+    assert!(Overscan::parse("1,2").is_err());
+
+    // Cropping it all away is fine
+    let everything = Overscan::parse("200,200,0,0").unwrap();
+    assert!(everything.crop(&numbered_frame()).pixels.is_empty());
+}
+
+#[test]
+fn scale_and_blit() {
+    let frame = Frame::from_pixels(vec![1, 2, 3, 4], 2, 2).unwrap();
+    let scaled = frame.scale(2);
+    assert_eq!(
+        scaled.pixels,
+        [1, 1, 2, 2, 1, 1, 2, 2, 3, 3, 4, 4, 3, 3, 4, 4]
+    );
+
+    // Clipped to the target
+    let mut target = [0; 3 * 3];
+    scaled.blit(&mut target, 3);
+    assert_eq!(target, [1, 1, 2, 1, 1, 2, 3, 3, 4]);
+
+    // The most whole pixels that fit, in the middle
+    assert_eq!(frame.fit_scale(7, 5), 2);
+    assert_eq!(frame.fit_scale(1, 1), 1);
+    let mut target = [0; 6 * 4];
+    frame
+        .scale(frame.fit_scale(6, 4))
+        .blit_centered(&mut target, 6);
+    let rows: Vec<&[u32]> = target.chunks(6).collect();
+    assert_eq!(
+        rows,
+        [
+            [0, 1, 1, 2, 2, 0],
+            [0, 1, 1, 2, 2, 0],
+            [0, 3, 3, 4, 4, 0],
+            [0, 3, 3, 4, 4, 0]
+        ]
+    );
+    let mut tall = [0; 2 * 4];
+    frame.blit_centered(&mut tall, 2);
+    assert_eq!(tall, [0, 0, 1, 2, 3, 4, 0, 0]);
+
+    // Scaling while blitting draws the same
+    let mut direct = [0; 6 * 4];
+    frame.blit_scaled_centered(&mut direct, 6, 2);
+    assert_eq!(direct, target);
+}
+
+#[test]
+fn frame_blending() {
+    let white = Frame::from_pixels(vec![0xffffff; 4], 2, 2).unwrap();
+    let black = Frame::new(2, 2);
+
+    let mut mix = FrameBlend::new(BlendMode::Mix);
+    assert_eq!(mix.apply(white.clone()), white);
+    assert_eq!(mix.apply(black.clone()).pixels[0], 0x7f7f7f);
+    // Mixed with the last input, not the last output
+    assert_eq!(mix.apply(black.clone()), black);
+
+    let mut chain = FilterChain::new();
+    chain.push(FrameBlend::new(BlendMode::Phosphor { persistence: 0.5 }));
+    chain.push(Scale(2));
+    chain.apply(white.clone());
+    let faded = chain.apply(black.clone());
+    assert_eq!((faded.width, faded.pixels[0]), (4, 0x7f7f7f));
+    assert_eq!(chain.apply(black.clone()).pixels[0], 0x3f3f3f);
+    chain.reset();
+    assert_eq!(chain.apply(black.clone()).pixels[0], 0);
+}
+
+#[test]
+fn filter_chain_buffers() {
+    let frame = numbered_frame();
+    let mut chain = FilterChain::new();
+    chain.push(Overscan::for_region(Region::Ntsc));
+    chain.push(FrameBlend::new(BlendMode::Mix));
+    chain.push(Scale(2));
+    let mut applied = FilterChain::new();
+    applied.push(Overscan::for_region(Region::Ntsc));
+    applied.push(FrameBlend::new(BlendMode::Mix));
+    applied.push(Scale(2));
+    for _ in 0..2 {
+        assert_eq!(*chain.filter(&frame), applied.apply(frame.clone()));
+    }
+    // Once the buffers are big enough the same two take turns
+    let buffers = [0; 2].map(|_| chain.filter(&frame).pixels.as_ptr());
+    for _ in 0..2 {
+        assert_eq!(
+            [0; 2].map(|_| chain.filter(&frame).pixels.as_ptr()),
+            buffers
+        );
+    }
+}
+
+#[test]
+fn ppm() {
+    let frame = Frame::from_pixels(vec![0x123456, 0xabcdef], 2, 1).unwrap();
+    assert_eq!(
+        frame.to_ppm(),
+        b"P6\n2 1\n255\n\x12\x34\x56\xab\xcd\xef".to_vec()
+    );
+}
+
+#[test]
+fn downscale() {
+    let frame = Frame::from_pixels(
+        vec![
+            0x000000, 0x202020, 0xff0000, 0xff0000, //
+            0x404040, 0x606060, 0x00ff00, 0x00ff00,
+        ],
+        4,
+        2,
+    )
+    .unwrap();
+    let small = frame.downscale(2, 1);
+    assert_eq!((small.width, small.height), (2, 1));
+    assert_eq!(small.pixels, vec![0x303030, 0x7f7f00]);
+    // Never bigger than it was
+    assert_eq!(frame.downscale(8, 8), frame);
+}
+
+#[test]
+fn frame_pool() {
+    let pool = FramePool::new(2, 1, 3);
+    let frames: Vec<Frame> = (0..3).map(|_| pool.acquire()).collect();
+    let buffers: Vec<*const u32> = frames.iter().map(|frame| frame.pixels.as_ptr()).collect();
+    for frame in frames {
+        pool.recycle(frame);
+    }
+    assert_eq!(pool.take(), None);
+
+    // Only the newest is presented
+    let mut frame = pool.acquire();
+    frame.pixels.copy_from_slice(&[1, 2]);
+    pool.submit(frame);
+    let mut frame = pool.acquire();
+    frame.pixels.copy_from_slice(&[3, 4]);
+    pool.submit(frame);
+    assert_eq!(pool.take().unwrap().pixels, [3, 4]);
+    assert_eq!(pool.take(), None);
+
+    // Presented on another thread, the same buffers go round
+    let presenter = pool.clone();
+    let presenting = std::thread::spawn(move || {
+        let mut presented = 0;
+        while presented < 10 {
+            if let Some(frame) = presenter.take() {
+                presenter.recycle(frame);
+                presented += 1;
+            }
+        }
+    });
+    while !presenting.is_finished() {
+        let frame = pool.acquire();
+        assert!(buffers.contains(&frame.pixels.as_ptr()));
+        pool.submit(frame);
+    }
+    presenting.join().unwrap();
+
+    // Frames of another size aren't kept
+    pool.recycle(Frame::new(4, 4));
+    assert_eq!(pool.acquire().width, 2);
+}
@@ -0,0 +1,114 @@
+use nes::Bus;
+use nes::apu::ExpansionChip;
+use nes::mapper::PpuTarget;
+use nes::rom::Rom;
+
+fn vrc7() -> Bus {
+    // 8 8K PRG banks each filled with its bank number, CHR RAM
+    let mut ines = vec![0; 16];
+    ines[..8].copy_from_slice(b"NES\x1a\x04\x00\x50\x50");
+    ines.extend((0..8u8).flat_map(|bank| [bank; 0x2000]));
+    Bus::new(Rom::new(&ines).unwrap())
+}
+
+fn level(bus: &Bus) -> f32 {
+    let Some((ExpansionChip::Vrc7, level)) = bus.mapper.expansion_audio() else {
+        panic!("no VRC7 output");
+    };
+    level
+}
+
+fn opll_write(bus: &mut Bus, reg: u8, val: u8) {
+    bus.write(0x9010, reg);
+    bus.write(0x9030, val);
+}
+
+#[test]
+fn prg_banking() {
+    let mut bus = vrc7();
+    bus.write(0x8000, 3);
+    // VRC7a and VRC7b select the second register with different address lines
+    bus.write(0x8010, 4);
+    bus.write(0x9000, 5);
+    assert_eq!(bus.read(0x8000), 3);
+    assert_eq!(bus.read(0xA000), 4);
+    assert_eq!(bus.read(0xC000), 5);
+    assert_eq!(bus.read(0xE000), 7);
+    bus.write(0x8008, 6);
+    assert_eq!(bus.read(0xA000), 6);
+}
+
+#[test]
+fn mirroring() {
+    let mut bus = vrc7();
+    bus.write(0xE000, 0);
+    assert_eq!(bus.mapper.ppu_target(0x2c05), PpuTarget::Ciram(0x405));
+    bus.write(0xE000, 1);
+    assert_eq!(bus.mapper.ppu_target(0x2c05), PpuTarget::Ciram(0x405));
+    assert_eq!(bus.mapper.ppu_target(0x2405), PpuTarget::Ciram(0x005));
+    bus.write(0xE000, 2);
+    assert_eq!(bus.mapper.ppu_target(0x2c05), PpuTarget::Ciram(0x005));
+    bus.write(0xE000, 3);
+    assert_eq!(bus.mapper.ppu_target(0x2005), PpuTarget::Ciram(0x405));
+}
+
+#[test]
+fn irq_cycle_mode() {
+    let mut bus = vrc7();
+    bus.write(0xE010, 0xf0);
+    bus.write(0xF000, 0x06);
+    bus.tick(15);
+    assert!(!bus.mapper.irq());
+    bus.tick(1);
+    assert!(bus.mapper.irq());
+    bus.write(0xF010, 0);
+    assert!(!bus.mapper.irq());
+    // Acknowledging copies the E bit, which was clear
+    bus.tick(0x200);
+    assert!(!bus.mapper.irq());
+}
+
+#[test]
+fn fm_note() {
+    let mut bus = vrc7();
+    assert_eq!(level(&bus), 0.0);
+    // Custom patch: a plain sine with instant attack and release
+    for (reg, val) in [0x20, 0x21, 0x3f, 0x00, 0xf0, 0xf0, 0x0f, 0x0f]
+        .into_iter()
+        .enumerate()
+    {
+        opll_write(&mut bus, reg as u8, val);
+    }
+    // Custom patch at full volume, A4
+    opll_write(&mut bus, 0x30, 0x00);
+    opll_write(&mut bus, 0x10, 0x20);
+    opll_write(&mut bus, 0x20, 0x10 | (4 << 1) | 1);
+
+    let mut peak = 0f32;
+    let mut crossings = 0;
+    let mut last = 0.0;
+    for _ in 0..1000 {
+        bus.tick(36);
+        let level = level(&bus);
+        peak = peak.max(level.abs());
+        if last < 0.0 && level >= 0.0 {
+            crossings += 1;
+        }
+        last = level;
+    }
+    assert!(peak > 0.1, "{peak}");
+    // About 20ms of a ~440Hz wave
+    assert!((5..15).contains(&crossings), "{crossings}");
+
+    // Key off, the note dies out
+    opll_write(&mut bus, 0x20, (4 << 1) | 1);
+    bus.tick(36 * 1000);
+    assert_eq!(level(&bus), 0.0);
+
+    // Sound reset
+    opll_write(&mut bus, 0x20, 0x10 | (4 << 1) | 1);
+    bus.tick(36 * 100);
+    bus.write(0xE000, 0x80);
+    bus.tick(36 * 100);
+    assert_eq!(level(&bus), 0.0);
+}
@@ -0,0 +1,65 @@
+use nes::Bus;
+use nes::joypad::{PortDevice, Zapper};
+
+mod common;
+
+/// A 16x16 black frame with a white 4x4 box at (8, 8)
+fn frame() -> Vec<u32> {
+    let mut frame = vec![0; 16 * 16];
+    for y in 8..12 {
+        frame[y * 16 + 8..y * 16 + 12].fill(0xffffff);
+    }
+    frame
+}
+
+#[test]
+fn light_and_trigger() {
+    let mut bus = Bus::new(common::nrom(&[]));
+    bus.ports[1] = Some(Box::new(Zapper::new()));
+
+    let zapper = bus.port_mut::<Zapper>(1).unwrap();
+    zapper.aim = Some((2, 2));
+    zapper.sense(&frame(), 16);
+    assert_eq!(bus.read(0x4017) & 0x18, 0x08);
+
+    let zapper = bus.port_mut::<Zapper>(1).unwrap();
+    zapper.aim = Some((9, 10));
+    zapper.trigger = true;
+    zapper.sense(&frame(), 16);
+    assert_eq!(bus.read(0x4017) & 0x18, 0x10);
+
+    // Off screen sees nothing
+    let zapper = bus.port_mut::<Zapper>(1).unwrap();
+    zapper.aim = None;
+    zapper.sense(&frame(), 16);
+    assert_eq!(bus.peek(0x4017) & 0x08, 0x08);
+}
+
+#[test]
+fn calibration() {
+    let mut zapper = Zapper::new();
+    zapper.aim = Some((3, 2));
+    zapper.calibrate((10, 10));
+    assert_eq!(zapper.offset, (7, 8));
+    assert_eq!(zapper.target(), Some((10, 10)));
+
+    // Pointing at the dark corner now hits the box
+    zapper.aim = Some((0, 0));
+    zapper.sense(&frame(), 16);
+    assert_eq!(zapper.peek() & 0x08, 0);
+}
+
+#[test]
+fn crosshair() {
+    let mut zapper = Zapper::new();
+    zapper.aim = Some((1, 1));
+    let mut frame = vec![0; 16 * 16];
+    zapper.draw_crosshair(&mut frame, 16);
+    assert!(frame.iter().all(|&pixel| pixel == 0));
+
+    zapper.crosshair = true;
+    zapper.draw_crosshair(&mut frame, 16);
+    // Both arms, clipped at the edges, the middle inverted once
+    assert_eq!(frame.iter().filter(|&&pixel| pixel != 0).count(), 6 + 5);
+    assert_eq!(frame[16 + 1], 0xffffff);
+}